@@ -0,0 +1,48 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+/// Embeds build metadata for `src/build_info.rs`. Builds outside a git checkout, or without git
+/// installed, get an empty hash rather than failing.
+fn main() {
+    let git_hash = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_default();
+    let git_dirty = git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(
+        |status| !status.is_empty()
+    );
+    let built_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    // Cargo exposes enabled features as CARGO_FEATURE_<NAME> variables
+    let mut features: Vec<String> = env
+        ::vars()
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_").map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=BUILD_GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=BUILD_GIT_DIRTY={}", git_dirty);
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", built_at);
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features.join(","));
+
+    // Rebuild when the checked-out commit or the index changes; without a checkout, only when
+    // this script does
+    println!("cargo:rerun-if-changed=build.rs");
+    for path in [".git/HEAD", ".git/index"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
+
+/// Runs a git command, returning its trimmed output if it succeeded.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|stdout| stdout.trim().to_string())
+}
@@ -4,6 +4,14 @@ CREATE TABLE websites (
     description TEXT NOT NULL,
     url TEXT UNIQUE NOT NULL,
     word_count INT NOT NULL,
+    last_crawled TIMESTAMPTZ,
+    -- Two-letter ISO 639-1 code of the language the crawler detected, if it could tell
+    language TEXT,
+    -- Set by the crawler when a page disappears, instead of deleting the row
+    deleted_at TIMESTAMPTZ,
+    host TEXT GENERATED ALWAYS AS (
+        lower(substring(url FROM '^[A-Za-z][A-Za-z0-9+.-]*://(?:[^/?#@]*@)?([^/?#:]+)'))
+    ) STORED,
     CONSTRAINT unique_url UNIQUE (url) 
 );
 
@@ -19,6 +27,9 @@ CREATE TABLE website_keywords (
     keyword_id INT NOT NULL REFERENCES keywords(id),
     website_id INT NOT NULL REFERENCES websites(id),
     keyword_occurrences INT NOT NULL,
+    -- The forms the keyword took in the page's text, with their case, for `case_sensitive`.
+    -- Optional: without it, only titles and descriptions are checked.
+    original_forms TEXT[],
     CONSTRAINT unique_keyword_website UNIQUE (keyword_id, website_id, keyword_occurrences) 
 );
 
@@ -26,9 +37,56 @@ CREATE TABLE website_links (
     id SERIAL PRIMARY KEY,
     source_website_id INT NOT NULL REFERENCES websites(id),
     target_website TEXT NOT NULL,
+    -- The link's anchor text, which ranks the target for the words others describe it with.
+    -- Optional: without it, anchor text isn't a ranking signal.
+    link_text TEXT,
     CONSTRAINT unique_source_target UNIQUE (source_website_id, target_website) 
 );
 
+-- Where each keyword occurs in a page's text, counted in words from the start, for `NEAR/n`.
+-- Optional: without it, only titles and descriptions are checked.
+CREATE TABLE website_keyword_positions (
+    website_id INT NOT NULL REFERENCES websites(id),
+    keyword_id INT NOT NULL REFERENCES keywords(id),
+    positions INT[] NOT NULL,
+    PRIMARY KEY (website_id, keyword_id)
+);
+
+-- Each page's PageRank, as of the last computation, so authority is ready straight after a
+-- restart. Optional: without it, scores are recomputed at every startup.
+CREATE TABLE website_pagerank (
+    website_id INT PRIMARY KEY REFERENCES websites(id),
+    score REAL NOT NULL
+);
+
+-- Searches stored with `POST /saved`, as a JSON object of their parameters, with who saved them
+CREATE TABLE saved_searches (
+    name TEXT PRIMARY KEY,
+    parameters TEXT NOT NULL,
+    -- `admin`, or `key:` and the SHA-256 of the API key; NULL for searches saved without one
+    owner TEXT,
+    saved_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Searches logged for `GET /trending` when `QUERY_LOG` is on, with a salted hash of who searched
+CREATE TABLE query_log (
+    query TEXT NOT NULL,
+    ip_hash TEXT NOT NULL,
+    searched_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_query_log_searched_at ON query_log (searched_at);
+
+-- How often each page was shown in the top search results and clicked through to, when
+-- `CLICK_FEEDBACK` is on. Counts decay with age, so they're stored already decayed as of
+-- `updated_at`.
+CREATE TABLE result_clicks (
+    website_id INT PRIMARY KEY REFERENCES websites(id),
+    impressions REAL NOT NULL,
+    clicks REAL NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
 CREATE INDEX idx_keywords_documents ON keywords (documents_containing_word);
 CREATE INDEX idx_website_keywords_keyword_id ON website_keywords (keyword_id);
 CREATE INDEX idx_website_keywords_website_id ON website_keywords (website_id);
@@ -36,4 +94,12 @@ CREATE INDEX idx_website_keywords_occurrences ON website_keywords (keyword_occur
 CREATE INDEX idx_website_links_source ON website_links (source_website_id);
 CREATE INDEX idx_website_links_target ON website_links (target_website);
 CREATE INDEX idx_keywords_word ON keywords (word);
+-- For `prefix*` wildcards, which look words up with LIKE 'prefix%'
+CREATE INDEX idx_keywords_word_prefix ON keywords (word text_pattern_ops);
+-- Reversed so a domain and all of its subdomains form one contiguous index range
+CREATE INDEX idx_websites_host_reversed ON websites ((reverse(host) COLLATE "C"));
 CREATE INDEX idx_website_keywords_keyword_id_website_id ON website_keywords (keyword_id, website_id);
+-- For searches filtered with `lang=`
+CREATE INDEX idx_websites_language ON websites (language);
+-- For the most recently crawled pages, shown for an empty query with `empty=recent`
+CREATE INDEX idx_websites_last_crawled ON websites (last_crawled DESC);
@@ -0,0 +1,164 @@
+use axum::{
+    extract::{ Extension, Request },
+    http::{ header::AUTHORIZATION, HeaderMap, Uri },
+    middleware::Next,
+    response::Response,
+};
+use serde_json::{ json, Map, Value };
+use std::sync::Arc;
+use std::time::Instant;
+use crate::api_keys::API_KEY_HEADER;
+use crate::auth::{ ADMIN_TOKEN_HEADER, TURNSTILE_TOKEN_HEADER };
+use crate::state::AppState;
+
+/// What credentials are replaced with in the access log
+const REDACTED: &str = "[redacted]";
+
+/// Queues each request for the access log, if it's on, once it has been answered. Tokens
+/// and keys are redacted from the URI and headers first.
+pub async fn log_requests(
+    Extension(state): Extension<Arc<AppState>>,
+    request: Request,
+    next: Next
+) -> Response {
+    if !state.config.access_log {
+        return next.run(request).await;
+    }
+
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let uri = sanitise_uri(request.uri());
+    let headers = sanitise_headers(request.headers());
+    let response = next.run(request).await;
+    state.access_log.send(
+        json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "method": method,
+            "uri": uri,
+            "status": response.status().as_u16(),
+            "duration_ms": started.elapsed().as_secs_f64() * 1000.0,
+            "headers": headers,
+        })
+    );
+    response
+}
+
+/// The URI with the value of any `token` query parameter redacted.
+pub fn sanitise_uri(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.to_string();
+    };
+    let pairs: Vec<String> = query
+        .split('&')
+        .map(|pair| {
+            let name = pair.split('=').next().unwrap_or_default();
+            let decoded = url::form_urlencoded::parse(name.as_bytes()).next();
+            if decoded.is_some_and(|(name, _)| name == "token") {
+                format!("{}={}", name, REDACTED)
+            } else {
+                pair.to_string()
+            }
+        })
+        .collect();
+    format!("{}?{}", uri.path(), pairs.join("&"))
+}
+
+/// The headers as a JSON object, with the Turnstile token, admin token and API key redacted.
+/// `Authorization` keeps its scheme, such as `Turnstile` or `Bearer`, but not its credentials.
+/// Repeated headers are joined with commas.
+pub fn sanitise_headers(headers: &HeaderMap) -> Map<String, Value> {
+    let mut sanitised = Map::new();
+    for name in headers.keys() {
+        let values: Vec<String> = headers
+            .get_all(name)
+            .iter()
+            .map(|value| {
+                if
+                    name == TURNSTILE_TOKEN_HEADER ||
+                    name == ADMIN_TOKEN_HEADER ||
+                    name == API_KEY_HEADER
+                {
+                    return REDACTED.to_string();
+                }
+                let value = String::from_utf8_lossy(value.as_bytes());
+                if name == AUTHORIZATION {
+                    return match value.trim().split_once(' ') {
+                        Some((scheme, _)) => format!("{} {}", scheme, REDACTED),
+                        None => REDACTED.to_string(),
+                    };
+                }
+                value.into_owned()
+            })
+            .collect();
+        sanitised.insert(name.to_string(), Value::String(values.join(", ")));
+    }
+    sanitised
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn redacts_the_token_query_parameter() {
+        let uri: Uri = "/?q=rust&token=secret-token&results=5".parse().unwrap();
+        assert_eq!(sanitise_uri(&uri), "/?q=rust&token=[redacted]&results=5");
+
+        // However it's encoded, and however often it's repeated
+        let uri: Uri = "/?to%6Ben=secret&token&token=again".parse().unwrap();
+        assert_eq!(sanitise_uri(&uri), "/?to%6Ben=[redacted]&token=[redacted]&token=[redacted]");
+
+        // Other parameters are left alone, even if they mention tokens
+        let uri: Uri = "/?q=token%3Dsecret&tokens=1".parse().unwrap();
+        assert_eq!(sanitise_uri(&uri), "/?q=token%3Dsecret&tokens=1");
+        let uri: Uri = "/stats".parse().unwrap();
+        assert_eq!(sanitise_uri(&uri), "/stats");
+    }
+
+    #[test]
+    fn redacts_credential_headers() {
+        let sanitised = sanitise_headers(
+            &headers(
+                &[
+                    ("x-turnstile-token", "secret-token"),
+                    ("x-admin-token", "secret-admin"),
+                    ("x-api-key", "secret-key"),
+                    ("user-agent", "curl/8.0"),
+                ]
+            )
+        );
+        assert_eq!(
+            Value::Object(sanitised),
+            json!({
+                "x-turnstile-token": "[redacted]",
+                "x-admin-token": "[redacted]",
+                "x-api-key": "[redacted]",
+                "user-agent": "curl/8.0",
+            })
+        );
+    }
+
+    #[test]
+    fn keeps_only_the_authorization_scheme() {
+        let sanitise = |value| sanitise_headers(&headers(&[("authorization", value)]))
+            ["authorization"].clone();
+        assert_eq!(sanitise("Turnstile secret-token"), "Turnstile [redacted]");
+        assert_eq!(sanitise("  Bearer secret-admin "), "Bearer [redacted]");
+        assert_eq!(sanitise("secret"), "[redacted]");
+
+        // Every value of a repeated header is redacted
+        let sanitised = sanitise_headers(
+            &headers(&[("authorization", "Turnstile one"), ("authorization", "Bearer two")])
+        );
+        assert_eq!(sanitised["authorization"], "Turnstile [redacted], Bearer [redacted]");
+    }
+}
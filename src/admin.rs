@@ -0,0 +1,217 @@
+use axum::{
+    extract::{ Extension, Query },
+    http::StatusCode,
+    response::{ IntoResponse, Json, Response },
+};
+use serde_json::{ Value, json };
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::database::{ self, LinkDirection };
+use crate::experiments::Experiment;
+use crate::params::sanitise_query;
+use crate::search;
+use crate::spam::SpamList;
+use crate::state::AppState;
+
+const DEFAULT_TOP_LINKS_LIMIT: i64 = 50;
+const MAX_TOP_LINKS_LIMIT: i64 = 500;
+
+/// `GET /admin/links/top?by=inbound|outbound&limit=50&offset=0`: the most linked pages.
+pub async fn top_links(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(state): Extension<Arc<AppState>>
+) -> Response {
+    let direction = match params.get("by").map(String::as_str) {
+        None | Some("inbound") => LinkDirection::Inbound,
+        Some("outbound") => LinkDirection::Outbound,
+        Some(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "by must be inbound or outbound" })),
+            ).into_response();
+        }
+    };
+    let limit = params
+        .get("limit")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_TOP_LINKS_LIMIT)
+        .clamp(1, MAX_TOP_LINKS_LIMIT);
+    let offset = params
+        .get("offset")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0_i64)
+        .max(0);
+
+    // Only the first page is cached; it's the one dashboards poll
+    let cache_key = format!("{:?}|{}", direction, limit);
+    if offset == 0 {
+        if let Some(cached) = state.top_links_cache.get(&cache_key) {
+            return Json(cached).into_response();
+        }
+    }
+
+    let fetched = state.database.run(|| {
+        database::fetch_top_linked(&state.pool, direction, limit, offset)
+    }).await;
+    let (total, pages) = match fetched {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error fetching top linked pages: {}", e);
+            return e.response("Failed to fetch link statistics");
+        }
+    };
+
+    let body =
+        json!({
+        "by": if direction == LinkDirection::Inbound { "inbound" } else { "outbound" },
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+        "has_more": offset + (pages.len() as i64) < total,
+        "pages": pages.iter().map(|(url, title, link_count)| {
+            json!({ "url": url, "title": title, "link_count": link_count })
+        }).collect::<Vec<_>>(),
+    });
+
+    if offset == 0 {
+        state.top_links_cache.insert(cache_key, body.clone());
+    }
+    Json(body).into_response()
+}
+
+/// `POST /admin/spam/reload`: re-reads the spam terms file without a restart.
+pub async fn reload_spam_terms(Extension(state): Extension<Arc<AppState>>) -> Response {
+    let filename = match &state.config.spam.terms_file {
+        Some(filename) => filename,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": "No spam terms file is configured" })),
+            ).into_response();
+        }
+    };
+
+    match SpamList::load(filename, &state.config.folding_exceptions) {
+        Ok(spam_list) => {
+            let terms = spam_list.len();
+            state.spam_list.store(Arc::new(spam_list));
+            // Cached rankings were penalised with the old list
+            state.result_cache.clear();
+            println!("Reloaded {} spam terms.", terms);
+            Json(json!({ "terms": terms })).into_response()
+        }
+        Err(e) => {
+            eprintln!("Error reloading spam terms from {}: {}", filename, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": "Failed to reload spam terms" })),
+            ).into_response()
+        }
+    }
+}
+
+/// `GET /admin/cache/results?query=...` or `?key=...`: the cached rankings for a query, under
+/// any options, or for one exact cache key, with the index generation each was ranked against.
+pub async fn cached_results(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(state): Extension<Arc<AppState>>
+) -> Response {
+    let (entries, lookup) = match (params.get("key"), params.get("query")) {
+        (Some(key), _) => {
+            let mut entries = state.result_cache.entries(key);
+            entries.retain(|entry| entry.key == *key);
+            (entries, json!({ "key": key }))
+        }
+        (None, Some(query)) => {
+            let query = sanitise_query(query);
+            let entries = state.result_cache.entries(&search::search_cache_prefix(&query));
+            (entries, json!({ "query": search::normalise_query(&query) }))
+        }
+        (None, None) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "query or key is required" })),
+            ).into_response();
+        }
+    };
+
+    let body =
+        json!({
+        "lookup": lookup,
+        "cached": !entries.is_empty(),
+        "entries": entries.iter().map(|entry| {
+            json!({
+                "key": entry.key,
+                "age_secs": entry.age.as_secs(),
+                "ttl_remaining_secs": entry.remaining.as_secs(),
+                "weight": entry.weight,
+                "index_generation": entry.value.index_generation,
+                "results": entry.value.results.len(),
+                "complete": entry.value.complete,
+            })
+        }).collect::<Vec<_>>(),
+    });
+    Json(body).into_response()
+}
+
+/// `DELETE /admin/cache/results?key=...` or `?all=true`: evicts one cached ranking or all of
+/// them.
+pub async fn evict_results(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(state): Extension<Arc<AppState>>
+) -> Response {
+    let removed = match (params.get("key"), params.get("all").map(String::as_str)) {
+        (Some(key), _) => usize::from(state.result_cache.remove(key)),
+        (None, Some("true")) => {
+            let removed = state.result_cache.clear();
+            // A purge usually means the index changed, so formatted pages may be stale too
+            state.fragment_cache.clear();
+            println!("Purged {} cached search results.", removed);
+            removed
+        }
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "key or all=true is required" })),
+            ).into_response();
+        }
+    };
+    Json(json!({ "removed": removed })).into_response()
+}
+
+/// `GET /admin/metrics`: queue depth and write counts for each background writer, and result
+/// cache hit counts.
+pub async fn metrics(Extension(state): Extension<Arc<AppState>>) -> Json<Value> {
+    Json(
+        json!({
+            "background_writers": {
+                "slow_query_log": state.slow_query_log.stats(),
+                "capture_log": state.capture_log.stats(),
+                "access_log": state.access_log.stats(),
+                "shadow_log": state.shadow_log.stats(),
+                "query_log": state.query_log.stats(),
+                "click_log": state.click_log.stats(),
+            },
+            "outbound": state.http_client.stats(),
+            "database": state.database.stats(),
+            "result_cache": state.result_cache.stats(),
+            "fragment_cache": state.fragment_cache.stats(),
+            "suggest_cache": state.suggest_cache.stats(),
+            "shadow": state.shadow.stats(),
+            "experiment": state.experiment.as_ref().map(Experiment::stats),
+            "pagerank": {
+                "published": state.authority.load().is_published(),
+                "computing": state.pagerank_permits.available_permits() == 0,
+                "stored": state.stored_pagerank,
+                "hits": state.authority.load().has_hits(),
+            },
+            "clicks": {
+                "enabled": state.config.clicks.enabled,
+                "rated_pages": state.click_rates.load().rated_pages(),
+            },
+            "idf_cache": {
+                "words": state.document_frequencies.load().words(),
+            },
+        })
+    )
+}
@@ -0,0 +1,59 @@
+use axum::http::{ HeaderMap, HeaderName };
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ BufRead, BufReader };
+use std::path::Path;
+
+/// Request header carrying an API key
+pub static API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+/// The set of API keys accepted by the service, with optional per-key quotas
+#[derive(Default)]
+pub struct ApiKeys {
+    quotas: HashMap<String, Option<u32>>,
+}
+
+impl ApiKeys {
+    /// Loads API keys from a file.
+    ///
+    /// Each non-empty line holds a key, optionally followed by whitespace and the number of
+    /// requests it may make per rate-limit window. Lines starting with `#` are comments.
+    pub fn load<P: AsRef<Path>>(filename: P) -> Result<Self, std::io::Error> {
+        let file = File::open(filename)?;
+        let reader = BufReader::new(file);
+        let mut quotas = HashMap::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            if let Some(key) = parts.next() {
+                let quota = parts.next().and_then(|quota| quota.parse().ok());
+                quotas.insert(key.to_string(), quota);
+            }
+        }
+        Ok(ApiKeys { quotas })
+    }
+
+    /// Returns the valid API key sent with the request, if any.
+    pub fn find_in_headers<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        headers
+            .get(&API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|key| self.quotas.contains_key(*key))
+    }
+
+    /// Returns the configured quota for a key, if it has one.
+    pub fn quota(&self, key: &str) -> Option<u32> {
+        self.quotas.get(key).copied().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.quotas.len()
+    }
+}
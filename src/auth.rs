@@ -0,0 +1,160 @@
+use axum::{
+    extract::{ ConnectInfo, Extension, Query, Request },
+    http::{ header::AUTHORIZATION, HeaderMap, HeaderName, StatusCode },
+    middleware::Next,
+    response::{ IntoResponse, Json, Response },
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::client_ip::client_identity;
+use crate::state::AppState;
+use crate::timing::{ Phase, RequestTiming };
+use crate::turnstile::validate_turnstile_token;
+
+/// Request header carrying the Turnstile token
+pub static TURNSTILE_TOKEN_HEADER: HeaderName = HeaderName::from_static("x-turnstile-token");
+
+/// Request header carrying the admin token
+pub static ADMIN_TOKEN_HEADER: HeaderName = HeaderName::from_static("x-admin-token");
+
+/// Rejects requests that carry neither a valid API key nor a valid Turnstile token.
+pub async fn require_turnstile(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+    mut request: Request,
+    next: Next
+) -> Response {
+    // Turnstile can be switched off, and the bare root is a public service descriptor
+    let is_descriptor = request.uri().path() == "/" && !params.contains_key("q");
+    if !state.config.turnstile.enabled || is_descriptor {
+        return next.run(request).await;
+    }
+
+    // API key holders don't need to solve a challenge
+    if state.api_keys.find_in_headers(request.headers()).is_some() {
+        return next.run(request).await;
+    }
+
+    let turnstile_start = Instant::now();
+
+    let allow_query_token = state.config.turnstile.allow_query_token;
+    let token = match extract_turnstile_token(request.headers(), &params, allow_query_token) {
+        Some(token) => token,
+        None => {
+            return auth_error("Missing Turnstile token");
+        }
+    };
+
+    let ip = client_identity(addr.ip(), &state.config.client_identity);
+    if !validate_token(&state, &token, &ip).await {
+        return auth_error("Invalid Turnstile token");
+    }
+
+    if let Some(timing) = request.extensions_mut().get_mut::<RequestTiming>() {
+        timing.record(Phase::TurnstileValidation, turnstile_start.elapsed());
+    }
+
+    next.run(request).await
+}
+
+/// Rejects requests that don't carry the configured admin token.
+///
+/// The token is accepted as `Authorization: Bearer <token>` or `X-Admin-Token`. Admin routes
+/// are unavailable altogether when no admin token is configured.
+pub async fn require_admin(
+    Extension(state): Extension<Arc<AppState>>,
+    request: Request,
+    next: Next
+) -> Response {
+    let admin_token = match &state.config.admin_token {
+        Some(admin_token) => admin_token,
+        None => {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    };
+
+    if !is_admin(request.headers(), admin_token) {
+        return auth_error("Invalid admin token");
+    }
+    next.run(request).await
+}
+
+/// Returns whether the request headers carry `admin_token`.
+pub fn is_admin(headers: &HeaderMap, admin_token: &str) -> bool {
+    let bearer_token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().strip_prefix("Bearer "))
+        .map(str::trim);
+    let header_token = headers
+        .get(&ADMIN_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim);
+
+    bearer_token
+        .or(header_token)
+        .map(|token| constant_time_eq(token.as_bytes(), admin_token.as_bytes()))
+        .unwrap_or(false)
+}
+
+/// Compares two byte strings without short-circuiting on the first difference.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b)
+        .fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Finds the Turnstile token in the request.
+///
+/// The `X-Turnstile-Token` header is checked first, then `Authorization: Turnstile <token>`,
+/// and finally the `token` query parameter if `allow_query_token` is set.
+pub fn extract_turnstile_token(
+    headers: &HeaderMap,
+    params: &HashMap<String, String>,
+    allow_query_token: bool
+) -> Option<String> {
+    let header_token = headers
+        .get(&TURNSTILE_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|token| !token.is_empty());
+
+    let authorization_token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().strip_prefix("Turnstile "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty());
+
+    let query_token = params
+        .get("token")
+        .map(String::as_str)
+        .filter(|token| allow_query_token && !token.is_empty());
+
+    header_token.or(authorization_token).or(query_token).map(String::from)
+}
+
+/// Accepts a token this client already validated, and otherwise asks Turnstile. The cache isn't
+/// locked during the Turnstile call, so it never holds up other clients' requests.
+async fn validate_token(state: &AppState, token: &str, ip: &str) -> bool {
+    if state.token_cache.is_valid(token, ip) {
+        return true;
+    }
+    if !validate_turnstile_token(&state.http_client, &state.config.turnstile, token).await {
+        println!("Token validation failed for IP: {}", ip);
+        return false;
+    }
+    state.token_cache.add_token(token.to_string(), ip.to_string());
+    true
+}
+
+fn auth_error(message: &str) -> Response {
+    (StatusCode::UNAUTHORIZED, Json(json!({ "error": message }))).into_response()
+}
@@ -0,0 +1,438 @@
+use futures::FutureExt;
+use serde_json::{ Value, json };
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{ watch, Notify };
+use tokio::task::JoinHandle;
+use crate::config::WriterConfig;
+
+/// Shortest and longest wait before a failed sink is restarted
+const MIN_RESTART_DELAY: Duration = Duration::from_millis(100);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+
+/// Where a `BackgroundWriter` sends its rows
+pub trait Sink<T>: Send + 'static {
+    /// Writes a batch of rows. On failure the batch is kept and retried with a new sink.
+    fn write(&mut self, batch: &[T]) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+/// Rows waiting to be written, shared between the senders and the writer task
+struct Shared<T> {
+    name: &'static str,
+    rows: Mutex<VecDeque<T>>,
+    notify: Notify,
+    capacity: usize,
+    batch_size: usize,
+    flush_interval: Duration,
+    written: AtomicU64,
+    dropped: AtomicU64,
+    sink_failures: AtomicU64,
+}
+
+impl<T> Shared<T> {
+    fn len(&self) -> usize {
+        self.rows.lock().unwrap().len()
+    }
+
+    fn take(&self, count: usize) -> Vec<T> {
+        let mut rows = self.rows.lock().unwrap();
+        let count = count.min(rows.len());
+        rows.drain(..count).collect()
+    }
+
+    /// Puts a batch that failed to write back at the front of the queue, dropping the oldest
+    /// rows if that overflows it.
+    fn requeue(&self, batch: Vec<T>) {
+        let mut rows = self.rows.lock().unwrap();
+        for row in batch.into_iter().rev() {
+            rows.push_front(row);
+        }
+        while rows.len() > self.capacity {
+            rows.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Writes rows to a sink from a background task, so requests never wait on logging.
+///
+/// Rows are batched until `batch_size` are queued or `flush_interval` passes. When the queue is
+/// full, the oldest row is dropped and counted. A sink that fails or panics is replaced after a
+/// backoff. On shutdown, everything still queued is flushed before the task exits.
+pub struct BackgroundWriter<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Send + Sync + 'static> BackgroundWriter<T> {
+    /// Starts the writer task, building a sink with `make_sink` at first and after each failure.
+    /// The returned handle finishes once the writer has flushed after `shutdown` changes.
+    pub fn spawn<S, F>(
+        name: &'static str,
+        config: &WriterConfig,
+        make_sink: F,
+        shutdown: watch::Receiver<bool>
+    ) -> (Self, JoinHandle<()>)
+        where S: Sink<T>, F: Fn() -> S + Send + 'static
+    {
+        let shared = Arc::new(Shared {
+            name,
+            rows: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            capacity: config.capacity.max(1),
+            batch_size: config.batch_size.max(1),
+            flush_interval: config.flush_interval,
+            written: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            sink_failures: AtomicU64::new(0),
+        });
+        let handle = tokio::spawn(supervise(shared.clone(), make_sink, shutdown));
+        (BackgroundWriter { shared }, handle)
+    }
+
+    /// Queues a row to be written, dropping the oldest queued row if the queue is full.
+    pub fn send(&self, row: T) {
+        let mut rows = self.shared.rows.lock().unwrap();
+        if rows.len() >= self.shared.capacity {
+            rows.pop_front();
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        rows.push_back(row);
+        drop(rows);
+        self.shared.notify.notify_one();
+    }
+
+    /// Current queue depth, and rows written and dropped since startup.
+    pub fn stats(&self) -> Value {
+        json!({
+            "queue_depth": self.shared.len(),
+            "written": self.shared.written.load(Ordering::Relaxed),
+            "dropped": self.shared.dropped.load(Ordering::Relaxed),
+            "sink_failures": self.shared.sink_failures.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Runs the writer with a fresh sink until it flushes on shutdown, restarting it whenever the
+/// sink fails or panics.
+async fn supervise<T, S, F>(shared: Arc<Shared<T>>, make_sink: F, mut shutdown: watch::Receiver<bool>)
+    where T: Send + Sync + 'static, S: Sink<T>, F: Fn() -> S + Send + 'static
+{
+    let mut shutting_down = false;
+    let mut restart_delay = MIN_RESTART_DELAY;
+    loop {
+        let mut sink = make_sink();
+        let written_before = shared.written.load(Ordering::Relaxed);
+        let run = AssertUnwindSafe(write_batches(&shared, &mut sink, &mut shutdown, &mut shutting_down));
+        let error = match run.catch_unwind().await {
+            Ok(Ok(())) => {
+                return;
+            }
+            Ok(Err(e)) => e,
+            Err(_) => "sink panicked".to_string(),
+        };
+        shared.sink_failures.fetch_add(1, Ordering::Relaxed);
+        // A sink that worked for a while before failing is retried promptly
+        if shared.written.load(Ordering::Relaxed) > written_before {
+            restart_delay = MIN_RESTART_DELAY;
+        }
+
+        // Give up on what's left rather than hold up shutdown retrying a broken sink
+        if shutting_down {
+            let lost = shared.take(usize::MAX).len();
+            shared.dropped.fetch_add(lost as u64, Ordering::Relaxed);
+            eprintln!("{} writer failed while flushing, dropping {} rows: {}", shared.name, lost, error);
+            return;
+        }
+        eprintln!("{} writer failed, restarting in {:?}: {}", shared.name, restart_delay, error);
+        tokio::select! {
+            _ = tokio::time::sleep(restart_delay) => {}
+            _ = shutdown.changed() => {
+                shutting_down = true;
+            }
+        }
+        restart_delay = (restart_delay * 2).min(MAX_RESTART_DELAY);
+    }
+}
+
+/// Writes queued rows in batches until shutdown, then writes whatever is left.
+async fn write_batches<T, S>(
+    shared: &Shared<T>,
+    sink: &mut S,
+    shutdown: &mut watch::Receiver<bool>,
+    shutting_down: &mut bool
+) -> Result<(), String>
+    where T: Send + Sync, S: Sink<T>
+{
+    loop {
+        if !*shutting_down {
+            // Wait for a full batch or the flush interval, whichever comes first
+            let deadline = tokio::time::sleep(shared.flush_interval);
+            tokio::pin!(deadline);
+            while shared.len() < shared.batch_size {
+                tokio::select! {
+                    _ = &mut deadline => {
+                        break;
+                    }
+                    _ = shared.notify.notified() => {}
+                    _ = shutdown.changed() => {
+                        *shutting_down = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        let batch = shared.take(shared.batch_size);
+        if batch.is_empty() {
+            if *shutting_down {
+                return Ok(());
+            }
+            continue;
+        }
+        if let Err(e) = sink.write(&batch).await {
+            shared.requeue(batch);
+            return Err(e);
+        }
+        shared.written.fetch_add(batch.len() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Where log rows go: appended as lines of JSON to a file, opened on first write, or printed to
+/// standard error after a prefix
+pub enum LogSink {
+    File {
+        path: String,
+        file: Option<tokio::fs::File>,
+    },
+    Stderr {
+        prefix: &'static str,
+    },
+}
+
+impl LogSink {
+    /// Logs to `path` if given, otherwise to standard error.
+    pub fn new(path: Option<&str>, prefix: &'static str) -> Self {
+        match path {
+            Some(path) => LogSink::File { path: path.to_string(), file: None },
+            None => LogSink::Stderr { prefix },
+        }
+    }
+}
+
+impl Sink<Value> for LogSink {
+    async fn write(&mut self, batch: &[Value]) -> Result<(), String> {
+        let (path, file) = match self {
+            LogSink::File { path, file } => (path, file),
+            LogSink::Stderr { prefix } => {
+                for row in batch {
+                    eprintln!("{}{}", prefix, row);
+                }
+                return Ok(());
+            }
+        };
+
+        let mut lines = String::new();
+        for row in batch {
+            lines.push_str(&row.to_string());
+            lines.push('\n');
+        }
+        let file = match file {
+            Some(file) => file,
+            None => {
+                let opened = tokio::fs::OpenOptions
+                    ::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path).await
+                    .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+                file.insert(opened)
+            }
+        };
+        file.write_all(lines.as_bytes()).await.map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        file.flush().await.map_err(|e| format!("Failed to flush {}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Instant;
+
+    /// Records each batch it's given, failing the first `failures` writes
+    #[derive(Clone, Default)]
+    struct MockSink {
+        batches: Arc<Mutex<Vec<Vec<u32>>>>,
+        failures: Arc<AtomicUsize>,
+    }
+
+    impl MockSink {
+        fn failing(failures: usize) -> Self {
+            MockSink { failures: Arc::new(AtomicUsize::new(failures)), ..Default::default() }
+        }
+
+        fn batches(&self) -> Vec<Vec<u32>> {
+            self.batches.lock().unwrap().clone()
+        }
+    }
+
+    impl Sink<u32> for MockSink {
+        async fn write(&mut self, batch: &[u32]) -> Result<(), String> {
+            let failed = self.failures
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(1))
+                .is_ok();
+            if failed {
+                return Err("mock sink failed".to_string());
+            }
+            self.batches.lock().unwrap().push(batch.to_vec());
+            Ok(())
+        }
+    }
+
+    struct Running {
+        writer: BackgroundWriter<u32>,
+        handle: JoinHandle<()>,
+        shutdown: watch::Sender<bool>,
+    }
+
+    impl Running {
+        /// Shuts the writer down and waits for it to finish flushing.
+        async fn shut_down(self) -> Value {
+            self.shutdown.send(true).unwrap();
+            tokio::time::timeout(Duration::from_secs(5), self.handle).await
+                .expect("The writer didn't finish after shutdown")
+                .unwrap();
+            self.writer.stats()
+        }
+    }
+
+    fn start(
+        sink: &MockSink,
+        capacity: usize,
+        batch_size: usize,
+        flush_interval: Duration
+    ) -> Running {
+        let config = WriterConfig { capacity, batch_size, flush_interval };
+        let (shutdown, receiver) = watch::channel(false);
+        let sink = sink.clone();
+        let make_sink = move || sink.clone();
+        let (writer, handle) = BackgroundWriter::spawn("test", &config, make_sink, receiver);
+        Running { writer, handle, shutdown }
+    }
+
+    async fn wait_until(condition: impl Fn() -> bool) {
+        let started = Instant::now();
+        while !condition() {
+            assert!(started.elapsed() < Duration::from_secs(5), "Timed out waiting");
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_full_batches_and_holds_back_a_partial_one() {
+        let sink = MockSink::default();
+        let running = start(&sink, 100, 3, Duration::from_secs(60));
+        for row in 1..=7 {
+            running.writer.send(row);
+        }
+
+        wait_until(|| sink.batches().len() == 2).await;
+        assert_eq!(sink.batches(), [vec![1, 2, 3], vec![4, 5, 6]]);
+        // The seventh waits for its batch to fill or the interval to pass
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(running.writer.stats()["queue_depth"], 1);
+        assert_eq!(sink.batches().len(), 2);
+
+        // One more fills the batch
+        running.writer.send(8);
+        running.writer.send(9);
+        wait_until(|| sink.batches().len() == 3).await;
+        assert_eq!(sink.batches()[2], [7, 8, 9]);
+        assert_eq!(running.shut_down().await["written"], 9);
+    }
+
+    #[tokio::test]
+    async fn writes_a_partial_batch_once_the_interval_passes() {
+        let sink = MockSink::default();
+        let running = start(&sink, 100, 10, Duration::from_millis(100));
+        let sent = Instant::now();
+        running.writer.send(1);
+        running.writer.send(2);
+
+        wait_until(|| !sink.batches().is_empty()).await;
+        assert!(sent.elapsed() >= Duration::from_millis(90), "Written after {:?}", sent.elapsed());
+        assert_eq!(sink.batches(), [vec![1, 2]]);
+        running.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn drops_the_oldest_rows_when_the_queue_is_full() {
+        let sink = MockSink::default();
+        let running = start(&sink, 3, 10, Duration::from_secs(60));
+        for row in 1..=5 {
+            running.writer.send(row);
+        }
+        let stats = running.writer.stats();
+        assert_eq!(stats["queue_depth"], 3);
+        assert_eq!(stats["dropped"], 2);
+
+        let stats = running.shut_down().await;
+        assert_eq!(sink.batches(), [vec![3, 4, 5]]);
+        assert_eq!(
+            stats,
+            json!({ "queue_depth": 0, "written": 3, "dropped": 2, "sink_failures": 0 })
+        );
+    }
+
+    #[tokio::test]
+    async fn flushes_everything_queued_on_shutdown() {
+        let sink = MockSink::default();
+        let running = start(&sink, 100, 4, Duration::from_secs(60));
+        for row in 1..=10 {
+            running.writer.send(row);
+        }
+
+        // Shut down before the last batch fills, and without waiting for the interval
+        let stats = running.shut_down().await;
+        let written: Vec<u32> = sink.batches().concat();
+        assert_eq!(written, (1..=10).collect::<Vec<_>>());
+        assert!(sink.batches().iter().all(|batch| batch.len() <= 4));
+        assert_eq!(stats["written"], 10);
+        assert_eq!(stats["queue_depth"], 0);
+    }
+
+    #[tokio::test]
+    async fn retries_a_failed_batch_with_a_new_sink() {
+        let sink = MockSink::failing(1);
+        let running = start(&sink, 100, 2, Duration::from_secs(60));
+        running.writer.send(1);
+        running.writer.send(2);
+
+        wait_until(|| !sink.batches().is_empty()).await;
+        assert_eq!(sink.batches(), [vec![1, 2]]);
+        let stats = running.shut_down().await;
+        assert_eq!(stats["sink_failures"], 1);
+        assert_eq!(stats["dropped"], 0);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_broken_sink_at_shutdown() {
+        let sink = MockSink::failing(usize::MAX);
+        let running = start(&sink, 100, 2, Duration::from_secs(60));
+        for row in 1..=5 {
+            running.writer.send(row);
+        }
+        wait_until(|| running.writer.stats()["sink_failures"] != 0).await;
+
+        let stats = running.shut_down().await;
+        assert!(sink.batches().is_empty());
+        assert_eq!(stats["written"], 0);
+        assert_eq!(stats["dropped"], 5);
+        assert_eq!(stats["queue_depth"], 0);
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::time::{ Duration, Instant };
+use crate::config::FieldBoosts;
+use crate::database::{ Keyword, Webpage };
+use crate::ranking::{ self, ScoringQuery, TfIdfScorer };
+use crate::replay::parse_arg;
+
+/// Distinct words the synthetic pages are made of
+const VOCABULARY: usize = 20_000;
+
+/// Keywords on each synthetic page, besides any query terms
+const KEYWORDS_PER_PAGE: usize = 40;
+
+/// The synthetic query; every page contains at least one of its terms, as candidates do
+const QUERY: [&str; 3] = ["w1", "w2", "w3"];
+
+/// `bench-scoring [--pages N] [--rounds R] [--seed S]`: scores `N` synthetic candidates, like
+/// those of a broad query, on one thread and across rayon's, and prints how long each took.
+///
+/// The pages are generated from the seed, so runs with the same arguments score the same
+/// candidates, and no database is needed.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut pages = 50_000;
+    let mut rounds = 5;
+    let mut seed = 1;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pages" => {
+                pages = parse_arg(args.next(), "--pages")?;
+            }
+            "--rounds" => {
+                rounds = parse_arg::<usize>(args.next(), "--rounds")?.max(1);
+            }
+            "--seed" => {
+                seed = parse_arg(args.next(), "--seed")?;
+            }
+            _ => {
+                return Err(format!("Unexpected argument {:?}", arg));
+            }
+        }
+    }
+
+    let websites = synthetic_pages(pages, seed);
+    let query: Vec<String> = QUERY.iter().map(|term| term.to_string()).collect();
+    let query = ScoringQuery::new(pages as i64, FieldBoosts::default(), &query, &[]);
+    let score = |parallel| ranking::score_with(&TfIdfScorer, &query, &websites, parallel);
+
+    // One untimed round each, so neither pays for warming allocators or starting rayon's threads
+    let serial = score(false);
+    let parallel = score(true);
+    let same = serial.len() == parallel.len() &&
+        serial
+            .iter()
+            .zip(&parallel)
+            .all(|((a, a_page), (b, b_page))| a == b && a_page.id == b_page.id);
+    if !same {
+        return Err("Serial and parallel scoring gave different results".to_string());
+    }
+
+    let time = |parallel| {
+        let start = Instant::now();
+        for _ in 0..rounds {
+            score(parallel);
+        }
+        start.elapsed() / (rounds as u32)
+    };
+    let serial = time(false);
+    let parallel = time(true);
+
+    println!("Scored {} candidates, {} rounds each.", pages, rounds);
+    println!("  On 1 thread: {:.1?} per round", serial);
+    println!("  On {} threads: {:.1?} per round", rayon::current_num_threads(), parallel);
+    println!("Speedup: {:.2}x", speedup(serial, parallel));
+    Ok(())
+}
+
+fn speedup(serial: Duration, parallel: Duration) -> f64 {
+    serial.as_secs_f64() / parallel.as_secs_f64().max(f64::EPSILON)
+}
+
+/// `count` pages of random words from the vocabulary, each with one to all of the query's terms,
+/// and their document frequencies filled in as `idf::fill` would.
+fn synthetic_pages(count: usize, seed: u64) -> Vec<Webpage> {
+    // Xorshift64, seeded as `replay` seeds its shuffle
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    let mut next = move |bound: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % (bound as u64)) as usize
+    };
+
+    let mut websites: Vec<Webpage> = (0..count)
+        .map(|id| {
+            let mut words: HashMap<String, i32> = HashMap::new();
+            for term in QUERY.iter().take(1 + next(QUERY.len())) {
+                *words.entry(term.to_string()).or_default() += 1 + (next(5) as i32);
+            }
+            for _ in 0..KEYWORDS_PER_PAGE {
+                let word = format!("w{}", next(VOCABULARY));
+                *words.entry(word).or_default() += 1 + (next(10) as i32);
+            }
+            let mut keywords: Vec<(Keyword, i32)> = words
+                .into_iter()
+                .map(|(word, occurrences)| {
+                    let id = word[1..].parse().unwrap_or_default();
+                    (Keyword { id, word, documents_containing_word: 0 }, occurrences)
+                })
+                .collect();
+            keywords.sort_by(|(a, _), (b, _)| a.word.cmp(&b.word));
+            Webpage {
+                id: id as i32,
+                url: format!("https://example.com/{}", id),
+                word_count: keywords
+                    .iter()
+                    .map(|(_, occurrences)| occurrences)
+                    .sum(),
+                keywords,
+                ..Webpage::default()
+            }
+        })
+        .collect();
+
+    let mut frequencies: HashMap<String, i64> = HashMap::new();
+    for website in &websites {
+        for (keyword, _) in &website.keywords {
+            *frequencies.entry(keyword.word.clone()).or_default() += 1;
+        }
+    }
+    for website in websites.iter_mut() {
+        for (keyword, _) in website.keywords.iter_mut() {
+            keyword.documents_containing_word = frequencies[&keyword.word];
+        }
+    }
+    websites
+}
@@ -0,0 +1,288 @@
+use axum::{
+    extract::{ rejection::JsonRejection, Extension },
+    http::{ HeaderMap, StatusCode },
+    response::{ IntoResponse, Json, Response },
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::auth;
+use crate::config::Config;
+use crate::database::{ self, CandidateFilter, Webpage };
+use crate::idf;
+use crate::lemmatise;
+use crate::params::{ check_query_size, clamp_results, sanitise_query };
+use crate::ranking;
+use crate::result_formatter::{ format_result, ResultOptions };
+use crate::search;
+use crate::state::AppState;
+use crate::timing::{ self, Phase, RequestTiming };
+use crate::trace::{ PipelineTrace, SearchVariant };
+use crate::warnings::{ self, Warnings };
+
+/// Most queries a single blend may combine
+const MAX_QUERIES: usize = 5;
+
+/// Body of a `POST /blend` request
+#[derive(Debug, Deserialize)]
+pub struct BlendRequest {
+    pub queries: Vec<WeightedQuery>,
+    pub results: Option<usize>,
+    #[serde(default)]
+    pub links: bool,
+    #[serde(default)]
+    pub explain: bool,
+    #[serde(default)]
+    pub debug: bool,
+}
+
+/// A query and how much it counts towards the blended ranking
+#[derive(Debug, Clone, Deserialize)]
+pub struct WeightedQuery {
+    pub query: String,
+    pub weight: f64,
+}
+
+/// `POST /blend`: a single ranking of the pages relevant to several weighted queries at once.
+pub async fn blend(
+    Extension(state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+    timing: Option<Extension<RequestTiming>>,
+    request: Result<Json<BlendRequest>, JsonRejection>
+) -> Response {
+    let request = match request {
+        Ok(Json(request)) => request,
+        Err(rejection) => {
+            return error_response(StatusCode::BAD_REQUEST, &rejection.body_text());
+        }
+    };
+    let queries = match merge_queries(&request.queries, &state.config) {
+        Ok(queries) => queries,
+        Err((status, message)) => {
+            return error_response(status, &message);
+        }
+    };
+    let mut warnings = Warnings::default();
+    let num_results = clamp_results(request.results, state.config.max_results, &mut warnings);
+    let debug =
+        request.debug ||
+        state.config.admin_token
+            .as_deref()
+            .is_some_and(|admin_token| auth::is_admin(&headers, admin_token));
+
+    let mut timing = timing.map(|Extension(timing)| timing).unwrap_or_default();
+    let search_start = Instant::now();
+    let mut trace = PipelineTrace {
+        variant: SearchVariant::Blended,
+        ..Default::default()
+    };
+    let (results, contributions) = perform_blend(
+        &queries,
+        &state,
+        request.links,
+        num_results,
+        &mut timing,
+        &mut trace,
+        &mut warnings
+    ).await;
+    timing.record(Phase::TotalSearchFunction, search_start.elapsed());
+
+    let total_request_time = timing.elapsed();
+    if total_request_time >= state.config.slow_query_threshold {
+        let logged: Vec<&str> = queries
+            .iter()
+            .map(|query| query.query.as_str())
+            .collect();
+        search::log_slow_query(&state, "/blend", json!(logged), total_request_time, trace);
+    }
+
+    let options = ResultOptions {
+        include_links: request.links,
+        include_keywords: true,
+        snippets: false,
+        explain: request.explain,
+    };
+    let mut response =
+        json!({
+        "queries": queries.iter().map(|query| {
+            json!({ "query": query.query, "weight": query.weight })
+        }).collect::<Vec<_>>(),
+        "matching_webpages": results.len(),
+        "time_taken": timing::format_timing_info(&timing, total_request_time),
+        "website_count": state.index.load().website_count,
+        "results": results.iter().map(|(score, webpage)| {
+            let mut result = format_result(score, webpage, &state.top_domains, &state.fragment_cache, options);
+            // Show how much each query contributed to the blended score
+            if let Some(contributions) = contributions.get(&webpage.id) {
+                if request.explain {
+                    result["explain"]["queries"] = json!(
+                        queries
+                            .iter()
+                            .zip(contributions)
+                            .map(|(query, &contribution)| {
+                                json!({
+                                    "query": query.query,
+                                    "contribution": contribution as f32,
+                                })
+                            })
+                            .collect::<Vec<_>>()
+                    );
+                }
+            }
+            result
+        }).collect::<Vec<_>>(),
+    });
+    if debug {
+        response["debug"] = json!({ "pipeline": trace.to_json() });
+    }
+    warnings.add_to(&mut response);
+    Json(response).into_response()
+}
+
+/// Sanitises and validates the queries of a blend, merging duplicates by adding their weights
+/// and normalising the weights to sum to 1.
+fn merge_queries(
+    queries: &[WeightedQuery],
+    config: &Config
+) -> Result<Vec<WeightedQuery>, (StatusCode, String)> {
+    let invalid = |message: String| (StatusCode::BAD_REQUEST, message);
+    if queries.is_empty() {
+        return Err(invalid("At least one query is required".to_string()));
+    }
+    if queries.len() > MAX_QUERIES {
+        return Err(invalid(format!("At most {} queries can be blended", MAX_QUERIES)));
+    }
+
+    let mut merged: Vec<WeightedQuery> = vec![];
+    for weighted_query in queries {
+        let query = sanitise_query(&weighted_query.query);
+        if query.is_empty() {
+            return Err(invalid("Queries must not be empty".to_string()));
+        }
+        check_query_size(&query, config).map_err(|e| (e.status(), e.message))?;
+        if !weighted_query.weight.is_finite() || weighted_query.weight <= 0.0 {
+            return Err(invalid(format!("Weight for {:?} must be a positive number", query)));
+        }
+
+        let normalised = search::normalise_query(&query);
+        let duplicate = merged
+            .iter_mut()
+            .find(|existing| search::normalise_query(&existing.query) == normalised);
+        match duplicate {
+            Some(existing) => {
+                existing.weight += weighted_query.weight;
+            }
+            None => merged.push(WeightedQuery { query, weight: weighted_query.weight }),
+        }
+    }
+
+    let total_weight: f64 = merged.iter().map(|query| query.weight).sum();
+    for query in &mut merged {
+        query.weight /= total_weight;
+    }
+    Ok(merged)
+}
+
+/// Runs the search pipeline for several weighted queries over one shared set of candidates.
+///
+/// Returns the ranked results and each result's weighted contribution from every query, in
+/// query order, keyed by webpage id.
+async fn perform_blend(
+    queries: &[WeightedQuery],
+    state: &AppState,
+    include_links: bool,
+    num_results: usize,
+    timing: &mut RequestTiming,
+    trace: &mut PipelineTrace,
+    warnings: &mut Warnings
+) -> (Vec<(f64, Webpage)>, HashMap<i32, Vec<f64>>) {
+    // Lemmatise each query, and collect the keywords of all of them to fetch candidates with
+    let lemmatise_time = Instant::now();
+    let weighted_keywords: Vec<(Vec<String>, f64)> = queries
+        .iter()
+        .map(|query| {
+            let keywords = lemmatise::lemmatise_string(&query.query, &state.config.folding_exceptions);
+            (keywords, query.weight)
+        })
+        .collect();
+    let mut keywords: Vec<String> = vec![];
+    for keyword in weighted_keywords.iter().flat_map(|(keywords, _)| keywords) {
+        if !keywords.contains(keyword) {
+            keywords.push(keyword.clone());
+        }
+    }
+    timing.record(Phase::Lemmatisation, lemmatise_time.elapsed());
+    trace.query_terms = keywords.len();
+
+    let website_count = state.index.load().website_count;
+    if website_count == 0 {
+        warnings.push(
+            warnings::EMPTY_INDEX,
+            "The index has no pages yet, so no results can be returned".to_string(),
+            None
+        );
+        return (vec![], HashMap::new());
+    }
+
+    let db_time = Instant::now();
+    let filter = CandidateFilter { tombstones: state.tombstones(false), ..CandidateFilter::default() };
+    let fetched = match database::fetch_webpages(&state.pool, &keywords, &filter).await {
+        Ok(mut webpages) => idf::fill(state, &mut webpages).await.map(|_| webpages),
+        Err(e) => Err(e),
+    }.map_err(|e| e.to_string());
+    let webpages = match fetched {
+        Ok(webpages) => webpages,
+        Err(e) => {
+            eprintln!("Error fetching webpages: {}", e);
+            warnings.push(
+                warnings::CANDIDATES_UNAVAILABLE,
+                "The pages matching the queries couldn't be fetched, so nothing was blended"
+                    .to_string(),
+                None
+            );
+            return (vec![], HashMap::new());
+        }
+    };
+    timing.record(Phase::InitialDatabaseQuery, db_time.elapsed());
+    trace.candidates_fetched = webpages.len();
+    trace.candidates_complete = true;
+
+    // Unlike a single-query search, partial matches are kept: a page relevant to only some of
+    // the queries still belongs in the blend
+    let tfidf_time = Instant::now();
+    let blended = ranking::get_blended_scores(website_count, &weighted_keywords, &webpages);
+    trace.below_threshold = webpages.len() - blended.len();
+    let mut contributions = HashMap::new();
+    let mut ranked_webpages: Vec<(f64, Webpage)> = blended
+        .into_iter()
+        .map(|(score, query_contributions, webpage)| {
+            contributions.insert(webpage.id, query_contributions);
+            (score, webpage)
+        })
+        .collect();
+
+    search::apply_spam_penalties(state, &mut ranked_webpages, trace, warnings).await;
+    trace.truncated = search::sort_by_authority(
+        state,
+        &state.config.ranking,
+        &mut ranked_webpages,
+        num_results,
+        trace
+    );
+    trace.returned = ranked_webpages.len();
+    timing.record(Phase::TfIdfCalculation, tfidf_time.elapsed());
+
+    if include_links {
+        let link_time = Instant::now();
+        search::attach_links(state, &mut ranked_webpages, warnings).await;
+        timing.record(Phase::LinkFetching, link_time.elapsed());
+    }
+
+    (ranked_webpages, contributions)
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
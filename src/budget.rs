@@ -0,0 +1,254 @@
+use serde_json::{ Value, json };
+use crate::result_formatter::snippet;
+
+/// Descriptions are cut to this many characters when shedding them
+const SHED_DESCRIPTION_CHARS: usize = 80;
+
+/// Optional result fields, in the order they're shed when a response is over its size budget
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+    Keywords,
+    LinksFrom,
+    Descriptions,
+}
+
+impl Stage {
+    /// Sheds this stage's payload from a result, returning whether anything was removed.
+    fn apply(self, result: &mut Value) -> bool {
+        let result = match result.as_object_mut() {
+            Some(result) => result,
+            None => {
+                return false;
+            }
+        };
+        match self {
+            Stage::Keywords => result.remove("keywords").is_some(),
+            Stage::LinksFrom => result.remove("links_from").is_some(),
+            Stage::Descriptions => {
+                let description = result.get("description").and_then(Value::as_str).unwrap_or_default();
+                let shortened = snippet(description, SHED_DESCRIPTION_CHARS);
+                let changed = shortened != description;
+                result.insert("description".to_string(), json!(shortened));
+                changed
+            }
+        }
+    }
+}
+
+/// What was left out of a response to fit its size budget
+#[derive(Debug, Default)]
+struct Shed {
+    keywords: bool,
+    links_from: bool,
+    descriptions: bool,
+    results_dropped: usize,
+}
+
+impl Shed {
+    fn record(&mut self, stage: Stage) {
+        match stage {
+            Stage::Keywords => {
+                self.keywords = true;
+            }
+            Stage::LinksFrom => {
+                self.links_from = true;
+            }
+            Stage::Descriptions => {
+                self.descriptions = true;
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.keywords && !self.links_from && !self.descriptions && self.results_dropped == 0
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "keywords": self.keywords,
+            "links_from": self.links_from,
+            "descriptions": self.descriptions,
+            "results_dropped": self.results_dropped,
+        })
+    }
+}
+
+/// Sets `results` on `response`, shedding optional payload until the serialised response fits in
+/// `max_bytes`: first keywords, then linking pages, then most of each description, and finally
+/// trailing results. Anything shed is listed under `truncated`.
+///
+/// Sizes are measured by serialising each result, so the budget holds for the body as sent. A
+/// budget too small for the response without any results leaves it with none.
+pub fn fit_to_budget(mut response: Value, mut results: Vec<Value>, max_bytes: usize) -> Value {
+    let mut shed = Shed::default();
+    let mut sizes: Vec<usize> = results.iter().map(serialised_len).collect();
+
+    for stage in [Stage::Keywords, Stage::LinksFrom, Stage::Descriptions] {
+        if response_len(&response, &sizes, &shed) <= max_bytes {
+            break;
+        }
+        let mut changed = false;
+        for result in &mut results {
+            changed |= stage.apply(result);
+        }
+        if changed {
+            shed.record(stage);
+            sizes = results.iter().map(serialised_len).collect();
+        }
+    }
+
+    while !results.is_empty() && response_len(&response, &sizes, &shed) > max_bytes {
+        results.pop();
+        sizes.pop();
+        shed.results_dropped += 1;
+    }
+
+    response["results"] = Value::Array(results);
+    if !shed.is_empty() {
+        response["truncated"] = shed.to_json();
+    }
+    response
+}
+
+/// The serialised length of `response` carrying results of the given serialised sizes.
+fn response_len(response: &Value, result_sizes: &[usize], shed: &Shed) -> usize {
+    let mut envelope = response.clone();
+    envelope["results"] = json!([]);
+    if !shed.is_empty() {
+        envelope["truncated"] = shed.to_json();
+    }
+    // The results and the commas between them go inside the envelope's empty array
+    serialised_len(&envelope) +
+        result_sizes.iter().sum::<usize>() +
+        result_sizes.len().saturating_sub(1)
+}
+
+fn serialised_len(value: &Value) -> usize {
+    serde_json::to_vec(value).map(|bytes| bytes.len()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(n: usize) -> Value {
+        json!({
+            "url": format!("https://example.com/{}", n),
+            "title": format!("Page {}", n),
+            "description": "word ".repeat(60),
+            "keywords": [["rust", 3], ["async", 2], ["tokio", 1]],
+            "links_from": ["https://a.example.com/", "https://b.example.com/"],
+        })
+    }
+
+    fn results() -> Vec<Value> {
+        (0..4).map(result).collect()
+    }
+
+    fn envelope() -> Value {
+        json!({ "query": "rust", "matching_webpages": 4 })
+    }
+
+    /// The serialised size of the whole response with `results`
+    fn full_len(results: Vec<Value>) -> usize {
+        let mut response = envelope();
+        response["results"] = Value::Array(results);
+        serialised_len(&response)
+    }
+
+    /// Fits the results to `max_bytes`, checking the body is within it and what it kept
+    fn fit(max_bytes: usize) -> Value {
+        let response = fit_to_budget(envelope(), results(), max_bytes);
+        let body = serde_json::to_vec(&response).unwrap();
+        let parsed: Value = serde_json::from_slice(&body).unwrap();
+        assert!(body.len() <= max_bytes || parsed["results"] == json!([]), "{}", parsed);
+        assert_eq!(parsed["query"], "rust");
+        parsed
+    }
+
+    #[test]
+    fn leaves_responses_within_budget_alone() {
+        let size = full_len(results());
+        let response = fit(size);
+        assert_eq!(response["results"], json!(results()));
+        assert_eq!(response.get("truncated"), None);
+        // The measured size is exact, so a byte less starts shedding
+        let response = fit(size - 1);
+        assert_eq!(response["truncated"]["keywords"], true);
+    }
+
+    #[test]
+    fn sheds_keywords_then_links_then_descriptions_then_results() {
+        let mut without_keywords = results();
+        for result in &mut without_keywords {
+            result.as_object_mut().unwrap().remove("keywords");
+        }
+        let mut without_links = without_keywords.clone();
+        for result in &mut without_links {
+            result.as_object_mut().unwrap().remove("links_from");
+        }
+        let truncated_len = serialised_len(&Shed { keywords: true, ..Shed::default() }.to_json());
+
+        // Enough with the keywords shed
+        let response = fit(full_len(without_keywords.clone()) + truncated_len + 32);
+        let shed = json!({
+            "keywords": true,
+            "links_from": false,
+            "descriptions": false,
+            "results_dropped": 0,
+        });
+        assert_eq!(response["truncated"], shed);
+        assert_eq!(response["results"], json!(without_keywords));
+
+        // Enough with the links shed too
+        let response = fit(full_len(without_links.clone()) + truncated_len + 32);
+        assert_eq!(response["truncated"]["links_from"], true);
+        assert_eq!(response["truncated"]["descriptions"], false);
+        assert_eq!(response["results"], json!(without_links));
+
+        // Then descriptions are cut short, and every result is kept
+        let response = fit(full_len(without_links.clone()) - 100);
+        assert_eq!(response["truncated"]["descriptions"], true);
+        assert_eq!(response["truncated"]["results_dropped"], 0);
+        let results = response["results"].as_array().unwrap();
+        assert_eq!(results.len(), 4);
+        for result in results {
+            let description = result["description"].as_str().unwrap();
+            assert!(description.chars().count() <= SHED_DESCRIPTION_CHARS + 1, "{}", description);
+            assert_eq!(result.get("keywords"), None);
+        }
+
+        // Finally trailing results go, best first kept
+        let response = fit(400);
+        let results = response["results"].as_array().unwrap();
+        assert!(results.len() < 4);
+        assert_eq!(response["truncated"]["results_dropped"], 4 - results.len());
+        for (n, result) in results.iter().enumerate() {
+            assert_eq!(result["url"], format!("https://example.com/{}", n));
+        }
+    }
+
+    #[test]
+    fn drops_every_result_from_a_budget_smaller_than_the_envelope() {
+        let response = fit_to_budget(envelope(), results(), 1);
+        assert_eq!(response["results"], json!([]));
+        assert_eq!(response["truncated"]["results_dropped"], 4);
+        assert_eq!(response["query"], "rust");
+    }
+
+    #[test]
+    fn only_records_stages_that_removed_something() {
+        // Nothing to shed but results
+        let bare: Vec<Value> = (0..3)
+            .map(|n| json!({ "url": format!("https://example.com/{}", n), "description": "" }))
+            .collect();
+        let max_bytes = full_len(bare.clone()) - 1;
+        let response = fit_to_budget(envelope(), bare, max_bytes);
+        assert_eq!(response["truncated"]["keywords"], false);
+        assert_eq!(response["truncated"]["links_from"], false);
+        assert_eq!(response["truncated"]["descriptions"], false);
+        assert!(response["truncated"]["results_dropped"].as_u64().unwrap() > 0);
+        assert!(serialised_len(&response) <= max_bytes);
+        assert!(!response["results"].as_array().unwrap().is_empty());
+    }
+}
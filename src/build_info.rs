@@ -0,0 +1,157 @@
+use axum::{
+    http::{ HeaderMap, HeaderName, HeaderValue },
+    response::Response,
+};
+use chrono::{ DateTime, SecondsFormat };
+use once_cell::sync::Lazy;
+use serde_json::{ Value, json };
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+use crate::conditional::ConditionalJson;
+
+/// Header carrying the build version on search responses, so cached responses can be traced to
+/// the build that produced them
+pub static VERSION_HEADER: HeaderName = HeaderName::from_static("x-search-version");
+
+/// How long clients and CDNs may cache `/version`; it only changes on deploy
+const VERSION_MAX_AGE: Duration = Duration::from_secs(3600);
+
+/// What was built, and when, as recorded by `build.rs`
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Short commit hash, empty if the build didn't happen in a git checkout
+    git_hash: &'static str,
+    /// Whether tracked files had uncommitted changes
+    pub git_dirty: bool,
+    /// Seconds since the Unix epoch
+    pub built_at: u64,
+    /// Comma-separated cargo features
+    features: &'static str,
+}
+
+pub static BUILD_INFO: Lazy<BuildInfo> = Lazy::new(|| BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    git_hash: env!("BUILD_GIT_HASH"),
+    git_dirty: env!("BUILD_GIT_DIRTY") == "true",
+    built_at: env!("BUILD_TIMESTAMP").parse().unwrap_or_default(),
+    features: env!("BUILD_FEATURES"),
+});
+
+impl BuildInfo {
+    /// The crate version with the commit appended, e.g. `0.1.0+1a2b3c4` or `0.1.0+1a2b3c4.dirty`.
+    pub fn version_string(&self) -> String {
+        match (self.git_hash, self.git_dirty) {
+            ("", _) => self.version.to_string(),
+            (git_hash, false) => format!("{}+{}", self.version, git_hash),
+            (git_hash, true) => format!("{}+{}.dirty", self.version, git_hash),
+        }
+    }
+
+    /// The short commit hash, if the build happened in a git checkout.
+    pub fn git_hash(&self) -> Option<&'static str> {
+        (!self.git_hash.is_empty()).then_some(self.git_hash)
+    }
+
+    pub fn features(&self) -> Vec<&'static str> {
+        self.features
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect()
+    }
+
+    /// The build time as an RFC 3339 timestamp.
+    pub fn built_at_rfc3339(&self) -> String {
+        DateTime::from_timestamp(self.built_at as i64, 0)
+            .unwrap_or_default()
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    /// The line printed first at startup.
+    pub fn banner(&self) -> String {
+        format!(
+            "{} {} (built {}, features: {})",
+            env!("CARGO_PKG_NAME"),
+            self.version_string(),
+            self.built_at_rfc3339(),
+            self.features().join(", ")
+        )
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "service": env!("CARGO_PKG_NAME"),
+            "version": self.version,
+            "git_hash": self.git_hash(),
+            "git_dirty": self.git_dirty,
+            "built_at": self.built_at_rfc3339(),
+            "features": self.features(),
+        })
+    }
+}
+
+/// `GET /version`: the running build.
+pub async fn version(headers: HeaderMap) -> Response {
+    let built_at = UNIX_EPOCH + Duration::from_secs(BUILD_INFO.built_at);
+    ConditionalJson::new(&BUILD_INFO.to_json(), built_at.min(SystemTime::now()), VERSION_MAX_AGE)
+        .respond(&headers)
+}
+
+/// Adds the `X-Search-Version` header to a response.
+pub async fn add_version_header(mut response: Response) -> Response {
+    if let Ok(value) = HeaderValue::from_str(&BUILD_INFO.version_string()) {
+        response.headers_mut().insert(VERSION_HEADER.clone(), value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(git_hash: &'static str, git_dirty: bool, features: &'static str) -> BuildInfo {
+        BuildInfo { version: "1.2.3", git_hash, git_dirty, built_at: 1_700_000_000, features }
+    }
+
+    #[test]
+    fn versions_name_the_commit_when_there_is_one() {
+        assert_eq!(build("1a2b3c4", false, "").version_string(), "1.2.3+1a2b3c4");
+        assert_eq!(build("1a2b3c4", true, "").version_string(), "1.2.3+1a2b3c4.dirty");
+
+        // Built outside a git checkout
+        let info = build("", true, "");
+        assert_eq!(info.version_string(), "1.2.3");
+        assert_eq!(info.git_hash(), None);
+        assert_eq!(info.to_json()["git_hash"], Value::Null);
+    }
+
+    #[test]
+    fn describes_the_build() {
+        let info = build("1a2b3c4", false, "default,native-tls");
+        assert_eq!(info.features(), ["default", "native-tls"]);
+        assert_eq!(build("", false, "").features(), Vec::<&str>::new());
+        assert_eq!(info.built_at_rfc3339(), "2023-11-14T22:13:20Z");
+        assert_eq!(
+            info.banner(),
+            format!(
+                "{} 1.2.3+1a2b3c4 (built 2023-11-14T22:13:20Z, features: default, native-tls)",
+                env!("CARGO_PKG_NAME")
+            )
+        );
+        assert_eq!(
+            info.to_json(),
+            json!({
+                "service": env!("CARGO_PKG_NAME"),
+                "version": "1.2.3",
+                "git_hash": "1a2b3c4",
+                "git_dirty": false,
+                "built_at": "2023-11-14T22:13:20Z",
+                "features": ["default", "native-tls"],
+            })
+        );
+    }
+
+    #[test]
+    fn the_running_build_is_a_valid_header() {
+        assert!(HeaderValue::from_str(&BUILD_INFO.version_string()).is_ok());
+        assert!(BUILD_INFO.version_string().starts_with(env!("CARGO_PKG_VERSION")));
+    }
+}
@@ -0,0 +1,64 @@
+use axum::{
+    extract::{ Extension, Query },
+    http::StatusCode,
+    response::{ IntoResponse, Json, Response },
+};
+use serde_json::{ Value, json };
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use crate::build_info::BUILD_INFO;
+use crate::database::Webpage;
+use crate::state::AppState;
+
+/// Queues a search for offline replay, if capture is on. Only the effective parameters and the
+/// order of the result URLs are kept, nothing identifying the client.
+pub fn record(state: &AppState, applied: &Value, results: &[(f64, Webpage)]) {
+    if !state.capturing.load(Ordering::Relaxed) {
+        return;
+    }
+    state.capture_log.send(
+        json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "build": BUILD_INFO.version_string(),
+            "website_count": state.index.load().website_count,
+            "params": applied,
+            "results": results.iter().map(|(_, webpage)| &webpage.url).collect::<Vec<_>>(),
+        })
+    );
+}
+
+/// `POST /admin/capture?enabled=true|false`: turns request capture on or off. Without
+/// `enabled`, reports whether it's on.
+pub async fn set_capture(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(state): Extension<Arc<AppState>>
+) -> Response {
+    let file = match &state.config.capture_file {
+        Some(file) => file,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": "No capture file is configured" })),
+            ).into_response();
+        }
+    };
+
+    match params.get("enabled").map(String::as_str) {
+        Some("true") => state.capturing.store(true, Ordering::Relaxed),
+        Some("false") => state.capturing.store(false, Ordering::Relaxed),
+        Some(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "enabled must be true or false" })),
+            ).into_response();
+        }
+        None => {}
+    }
+    Json(
+        json!({
+            "capturing": state.capturing.load(Ordering::Relaxed),
+            "file": file,
+        })
+    ).into_response()
+}
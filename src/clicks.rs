@@ -0,0 +1,181 @@
+use axum::{
+    extract::{ rejection::JsonRejection, Extension },
+    http::StatusCode,
+    response::{ IntoResponse, Json, Response },
+};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use crate::background_writer::Sink;
+use crate::config::ClickConfig;
+use crate::database::{ self, Webpage };
+use crate::state::AppState;
+
+/// A page being shown in the top results of a search, or clicked through to from them
+pub struct Interaction {
+    pub website_id: i32,
+    pub clicked: bool,
+}
+
+/// Adds interactions to the counts in the `result_clicks` table
+pub struct ClickLogSink {
+    pool: PgPool,
+    half_life: Duration,
+}
+
+impl ClickLogSink {
+    pub fn new(pool: PgPool, half_life: Duration) -> Self {
+        ClickLogSink { pool, half_life }
+    }
+}
+
+impl Sink<Interaction> for ClickLogSink {
+    async fn write(&mut self, batch: &[Interaction]) -> Result<(), String> {
+        // Summed per page first, since a page can only be upserted once per statement
+        let mut counts: HashMap<i32, (f32, f32)> = HashMap::new();
+        for interaction in batch {
+            let (impressions, clicks) = counts.entry(interaction.website_id).or_default();
+            if interaction.clicked {
+                *clicks += 1.0;
+            } else {
+                *impressions += 1.0;
+            }
+        }
+        let website_ids: Vec<i32> = counts.keys().copied().collect();
+        let impressions: Vec<f32> = website_ids
+            .iter()
+            .map(|id| counts[id].0)
+            .collect();
+        let clicks: Vec<f32> = website_ids
+            .iter()
+            .map(|id| counts[id].1)
+            .collect();
+        database
+            ::add_result_clicks(
+                &self.pool,
+                &website_ids,
+                &impressions,
+                &clicks,
+                self.half_life.as_secs_f64()
+            ).await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Each clicked page's click-through rate, as of the last reload
+#[derive(Debug, Default)]
+pub struct ClickRates {
+    rates: HashMap<i32, f64>,
+}
+
+impl ClickRates {
+    /// The share of the times a page was shown that it was clicked, from 0 to 1; 0 for pages
+    /// never clicked.
+    pub fn rate(&self, website_id: i32) -> f64 {
+        self.rates.get(&website_id).copied().unwrap_or_default()
+    }
+
+    /// How many pages have a click-through rate.
+    pub fn rated_pages(&self) -> usize {
+        self.rates.len()
+    }
+}
+
+/// A page's click-through rate from its decayed counts. Clicks beyond the page's impressions
+/// aren't counted, so clicks on a page searches don't show can't raise it, and the rate is
+/// smoothed by `CLICK_PRIOR_IMPRESSIONS` unclicked impressions, so a few clicks on a rarely
+/// shown page don't look like a high rate.
+fn click_rate(impressions: f64, clicks: f64, config: &ClickConfig) -> f64 {
+    let rate = clicks.min(impressions) / (impressions + config.prior_impressions);
+    if rate.is_finite() {
+        rate.clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Counts the top results of a search as shown, if click feedback is on.
+pub fn record_impressions(state: &AppState, results: &[(f64, Webpage)]) {
+    let config = &state.config.clicks;
+    if !config.enabled {
+        return;
+    }
+    for (_, webpage) in results.iter().take(config.impression_depth) {
+        state.click_log.send(Interaction { website_id: webpage.id, clicked: false });
+    }
+}
+
+/// Reloads the click-through rates from `result_clicks` every `CLICK_REFRESH_SECS` until
+/// shutdown, so ranking never waits on the table.
+pub async fn run_refresh(state: Arc<AppState>, mut shutdown: watch::Receiver<bool>) {
+    let config = state.config.clicks.clone();
+    if !config.enabled {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(config.refresh);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => {
+                break;
+            }
+        }
+
+        match database::fetch_result_clicks(&state.pool, config.half_life.as_secs_f64()).await {
+            Ok(counts) => {
+                let rates = counts
+                    .into_iter()
+                    .map(|(website_id, impressions, clicks)| {
+                        (website_id, click_rate(impressions, clicks, &config))
+                    })
+                    .filter(|(_, rate)| *rate > 0.0)
+                    .collect();
+                state.click_rates.store(Arc::new(ClickRates { rates }));
+            }
+            Err(e) => eprintln!("Error loading click-through rates: {}", e),
+        }
+    }
+}
+
+/// Body of a `POST /click` request
+#[derive(Debug, Deserialize)]
+pub struct ClickRequest {
+    /// The result's URL, as search returned it
+    pub url: String,
+}
+
+/// `POST /click`: records that a search result was clicked through to, for click feedback.
+pub async fn click(
+    Extension(state): Extension<Arc<AppState>>,
+    request: Result<Json<ClickRequest>, JsonRejection>
+) -> Response {
+    if !state.config.clicks.enabled {
+        return error_response(StatusCode::NOT_FOUND, "Click feedback is off");
+    }
+    let request = match request {
+        Ok(Json(request)) => request,
+        Err(rejection) => {
+            return error_response(StatusCode::BAD_REQUEST, &rejection.body_text());
+        }
+    };
+    match database::find_webpage_by_url(&state.pool, &request.url).await {
+        Ok(Some((website_id, _))) => {
+            state.click_log.send(Interaction { website_id, clicked: true });
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(None) => error_response(StatusCode::NOT_FOUND, "Not an indexed page"),
+        Err(e) => {
+            eprintln!("Error looking up clicked page: {}", e);
+            e.response("Failed to record click")
+        }
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
@@ -0,0 +1,111 @@
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr };
+use crate::config::ClientIdentityConfig;
+
+/// Returns the identity a client address is tracked under by the token cache and rate limiter.
+///
+/// IPv4-mapped IPv6 addresses are treated as the IPv4 address they carry. Addresses are then
+/// truncated to the configured prefix length, so one IPv6 user rotating through their /64 keeps a
+/// single identity, and so does someone cycling through every address they control in that range.
+pub fn client_identity(ip: IpAddr, config: &ClientIdentityConfig) -> String {
+    match canonical_ip(ip) {
+        IpAddr::V4(ip) => {
+            let prefix = config.ipv4_prefix.min(32);
+            let network = Ipv4Addr::from(u32::from(ip) & prefix_mask_u32(prefix));
+            if prefix == 32 {
+                network.to_string()
+            } else {
+                format!("{}/{}", network, prefix)
+            }
+        }
+        IpAddr::V6(ip) => {
+            let prefix = config.ipv6_prefix.min(128);
+            let network = Ipv6Addr::from(u128::from(ip) & prefix_mask_u128(prefix));
+            if prefix == 128 {
+                network.to_string()
+            } else {
+                format!("{}/{}", network, prefix)
+            }
+        }
+    }
+}
+
+/// Unwraps IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) into plain IPv4.
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) =>
+            match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => ip,
+            }
+        IpAddr::V4(_) => ip,
+    }
+}
+
+fn prefix_mask_u32(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - (prefix as u32)).unwrap_or(0)
+}
+
+fn prefix_mask_u128(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - (prefix as u32)).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn identity(ip: &str, ipv4_prefix: u8, ipv6_prefix: u8) -> String {
+        client_identity(ip.parse().unwrap(), &(ClientIdentityConfig { ipv4_prefix, ipv6_prefix }))
+    }
+
+    #[test]
+    fn treats_mapped_addresses_as_ipv4() {
+        assert_eq!(identity("::ffff:203.0.113.7", 32, 64), "203.0.113.7");
+        assert_eq!(identity("::ffff:203.0.113.7", 32, 64), identity("203.0.113.7", 32, 64));
+        assert_eq!(identity("::ffff:203.0.113.7", 24, 64), "203.0.113.0/24");
+        // IPv4-compatible addresses are long deprecated, and stay IPv6
+        assert_eq!(identity("::203.0.113.7", 32, 64), "::/64");
+    }
+
+    #[test]
+    fn keeps_ipv4_exact_by_default() {
+        assert_eq!(identity("203.0.113.7", 32, 64), "203.0.113.7");
+        assert_ne!(identity("203.0.113.7", 32, 64), identity("203.0.113.8", 32, 64));
+        assert_eq!(identity("203.0.113.7", 16, 64), "203.0.0.0/16");
+        assert_eq!(identity("203.0.113.7", 0, 64), "0.0.0.0/0");
+    }
+
+    #[test]
+    fn groups_ipv6_addresses_by_prefix() {
+        let home = identity("2001:db8:aaaa:bbbb:1:2:3:4", 32, 64);
+        assert_eq!(home, "2001:db8:aaaa:bbbb::/64");
+        assert_eq!(identity("2001:db8:aaaa:bbbb:ffff:ffff:ffff:ffff", 32, 64), home);
+        assert_ne!(identity("2001:db8:aaaa:bbbc::1", 32, 64), home);
+
+        assert_eq!(identity("2001:db8:aaaa:bbbb::1", 32, 56), "2001:db8:aaaa:bb00::/56");
+        assert_eq!(identity("2001:db8:aaaa:bbbb::1", 32, 128), "2001:db8:aaaa:bbbb::1");
+        // Prefixes longer than the address are the whole address
+        assert_eq!(identity("2001:db8::1", 32, 200), "2001:db8::1");
+        assert_eq!(identity("203.0.113.7", 40, 64), "203.0.113.7");
+    }
+
+    #[test]
+    fn an_attacker_cycling_through_a_range_stays_one_identity() {
+        let identities = |prefix| -> HashSet<String> {
+            (0..10_000u128)
+                .map(|n| {
+                    let address = (0x2001_0db8_0042_u128 << 80) | (n << 64) | n.wrapping_mul(7919);
+                    client_identity(
+                        IpAddr::V6(Ipv6Addr::from(address)),
+                        &(ClientIdentityConfig { ipv4_prefix: 32, ipv6_prefix: prefix })
+                    )
+                })
+                .collect()
+        };
+        // Every address in a /48, spread across its /64s, is one identity at /48
+        assert_eq!(identities(48).len(), 1);
+        assert_eq!(identities(48).into_iter().next().unwrap(), "2001:db8:42::/48");
+        // At the default /64, each /64 is its own
+        assert_eq!(identities(64).len(), 10_000);
+    }
+}
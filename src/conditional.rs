@@ -0,0 +1,173 @@
+use axum::{
+    http::{ header, HeaderMap, HeaderValue, StatusCode },
+    response::{ IntoResponse, Response },
+};
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use std::time::{ Duration, SystemTime };
+
+/// A JSON response that honours `If-None-Match` and `If-Modified-Since`
+pub struct ConditionalJson {
+    body: String,
+    etag: String,
+    last_modified: SystemTime,
+    max_age: Duration,
+}
+
+impl ConditionalJson {
+    /// Wraps `body`, which was last refreshed at `last_modified`.
+    pub fn new(body: &Value, last_modified: SystemTime, max_age: Duration) -> Self {
+        let body = body.to_string();
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+
+        ConditionalJson {
+            etag: format!("\"{:016x}\"", hasher.finish()),
+            body,
+            last_modified,
+            max_age,
+        }
+    }
+
+    /// Produces a 304 if the client's cached copy is still current, otherwise a 200 with the body.
+    pub fn respond(self, request_headers: &HeaderMap) -> Response {
+        let last_modified = httpdate::fmt_http_date(self.last_modified);
+        let cache_control = format!("public, max-age={}", self.max_age.as_secs());
+
+        let status = if self.is_not_modified(request_headers) {
+            StatusCode::NOT_MODIFIED
+        } else {
+            StatusCode::OK
+        };
+
+        let mut response = if status == StatusCode::NOT_MODIFIED {
+            status.into_response()
+        } else {
+            let mut response = self.body.into_response();
+            response
+                .headers_mut()
+                .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            response
+        };
+
+        let headers = response.headers_mut();
+        for (name, value) in [
+            (header::ETAG, self.etag),
+            (header::LAST_MODIFIED, last_modified),
+            (header::CACHE_CONTROL, cache_control),
+        ] {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(name, value);
+            }
+        }
+        response
+    }
+
+    fn is_not_modified(&self, request_headers: &HeaderMap) -> bool {
+        // If-None-Match takes precedence over If-Modified-Since when both are present
+        if let Some(if_none_match) = request_headers.get(header::IF_NONE_MATCH) {
+            return if_none_match
+                .to_str()
+                .map(|value| {
+                    value.split(',').any(|tag| {
+                        let tag = tag.trim();
+                        tag == "*" || tag.trim_start_matches("W/") == self.etag
+                    })
+                })
+                .unwrap_or(false);
+        }
+
+        request_headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok())
+            .map(|since| truncate_to_seconds(self.last_modified) <= since)
+            .unwrap_or(false)
+    }
+}
+
+/// HTTP dates have one-second resolution, so compare at that granularity.
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    SystemTime::UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const MAX_AGE: Duration = Duration::from_secs(30);
+
+    fn modified_at() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_250)
+    }
+
+    fn respond(body: &Value, request: &[(header::HeaderName, &str)]) -> Response {
+        let mut headers = HeaderMap::new();
+        for (name, value) in request {
+            headers.insert(name, HeaderValue::from_str(value).unwrap());
+        }
+        ConditionalJson::new(body, modified_at(), MAX_AGE).respond(&headers)
+    }
+
+    fn header_of(response: &Response, name: header::HeaderName) -> &str {
+        response.headers()[name].to_str().unwrap()
+    }
+
+    #[test]
+    fn answers_with_validators() {
+        let response = respond(&json!({ "website_count": 7 }), &[]);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header_of(&response, header::CONTENT_TYPE), "application/json");
+        assert_eq!(header_of(&response, header::LAST_MODIFIED), "Tue, 14 Nov 2023 22:13:20 GMT");
+        assert_eq!(header_of(&response, header::CACHE_CONTROL), "public, max-age=30");
+        assert!(header_of(&response, header::ETAG).starts_with('"'));
+    }
+
+    #[test]
+    fn not_modified_while_the_etag_matches() {
+        let body = json!({ "website_count": 7 });
+        let etag = header_of(&respond(&body, &[]), header::ETAG).to_string();
+
+        for if_none_match in [etag.clone(), format!("W/{}", etag), format!("\"other\", {}", etag)] {
+            let response = respond(&body, &[(header::IF_NONE_MATCH, &if_none_match)]);
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{}", if_none_match);
+            assert_eq!(header_of(&response, header::ETAG), etag);
+        }
+        let response = respond(&body, &[(header::IF_NONE_MATCH, "*")]);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // A changed body gets a new ETag, so the cached copy is replaced
+        let response = respond(&json!({ "website_count": 8 }), &[(header::IF_NONE_MATCH, &etag)]);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(header_of(&response, header::ETAG), etag);
+    }
+
+    #[test]
+    fn not_modified_since_the_last_modification_to_the_second() {
+        let body = json!({});
+        for (since, status) in [
+            ("Tue, 14 Nov 2023 22:13:20 GMT", StatusCode::NOT_MODIFIED),
+            ("Tue, 14 Nov 2023 22:13:21 GMT", StatusCode::NOT_MODIFIED),
+            ("Tue, 14 Nov 2023 22:13:19 GMT", StatusCode::OK),
+            ("yesterday", StatusCode::OK),
+        ] {
+            let response = respond(&body, &[(header::IF_MODIFIED_SINCE, since)]);
+            assert_eq!(response.status(), status, "{}", since);
+        }
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence() {
+        let response = respond(
+            &json!({}),
+            &[
+                (header::IF_NONE_MATCH, "\"stale\""),
+                (header::IF_MODIFIED_SINCE, "Tue, 14 Nov 2023 22:13:21 GMT"),
+            ]
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
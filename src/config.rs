@@ -0,0 +1,1043 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+use crate::experiments;
+use crate::locale::LanguageCode;
+use crate::params::{ self, Preset };
+use crate::ranking::{ Decay, Ranker, Recency };
+use crate::units;
+
+/// Runtime configuration loaded from environment variables
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub port: u16,
+    /// Longest a request waits for a database connection from the pool
+    pub db_acquire_timeout: Duration,
+    /// Retrying failed database calls, and not calling a database that keeps failing
+    pub db_retry: DatabaseRetryConfig,
+    pub max_results: usize,
+    /// Most results from one site a search returns without `max_per_domain`; 0 for no cap
+    pub max_per_domain: usize,
+    /// Longest query accepted, in characters after sanitisation
+    pub max_query_chars: usize,
+    /// Most words a query may have, counting those of phrases but not operators
+    pub max_query_terms: usize,
+    /// Deepest a query's parentheses may nest
+    pub max_query_depth: usize,
+    /// Most distinct keywords a search may look up
+    pub keyword_budget: usize,
+    /// The keyword budget for admin and API-key callers
+    pub privileged_keyword_budget: usize,
+    /// Size budget for search responses without a `max_bytes` parameter; 0 for none
+    pub max_response_bytes: usize,
+    /// Searches taking at least this long are logged with their pipeline trace
+    pub slow_query_threshold: Duration,
+    /// File slow queries are appended to as JSON lines; standard error without one
+    pub slow_query_log_file: Option<String>,
+    /// File captured searches are appended to; capture can't be turned on without one
+    pub capture_file: Option<String>,
+    /// Whether every request is logged, with its credentials redacted
+    pub access_log: bool,
+    /// File the access log is appended to as JSON lines; standard error without one
+    pub access_log_file: Option<String>,
+    /// Queueing and batching shared by the background log writers
+    pub log_writer: WriterConfig,
+    pub stats_max_age: Duration,
+    /// How often the index is checked for pages and links the crawler has changed; zero never
+    /// checks, so the index is only read at startup
+    pub index_refresh: Duration,
+    pub links_cache_ttl: Duration,
+    pub top_links_cache_ttl: Duration,
+    pub site_cache_ttl: Duration,
+    pub suggest_cache_ttl: Duration,
+    /// Letters kept as they are when folding diacritics out of queries
+    pub folding_exceptions: Vec<char>,
+    /// Named bundles of search parameter defaults, selected with `preset=`
+    pub presets: BTreeMap<String, Preset>,
+    pub turnstile: TurnstileConfig,
+    pub api_keys_file: Option<String>,
+    /// File of synonym groups searched for in place of each other; no expansion without one
+    pub synonyms_file: Option<String>,
+    /// Language whose stopwords are removed from searches without `lang=`; none if unset or
+    /// not a language code
+    pub stopwords_language: Option<LanguageCode>,
+    /// Guess each query's language, to remove its stopwords and lemmatise it with its lemma map
+    pub detect_query_language: bool,
+    /// Lemma maps for languages other than English, as `(language, file)`
+    pub lemma_files: Vec<(LanguageCode, String)>,
+    /// Match terms no page contains fuzzily, as if they had been written `word~`
+    pub fuzzy_fallback: bool,
+    pub admin_token: Option<String>,
+    pub rate_limit: RateLimitConfig,
+    pub client_identity: ClientIdentityConfig,
+    pub result_cache: ResultCacheConfig,
+    pub fragment_cache: FragmentCacheConfig,
+    pub two_tier: TwoTierConfig,
+    pub warming: WarmingConfig,
+    pub pagerank: PageRankConfig,
+    /// The signal weights of searches without a `profile` parameter
+    pub ranking: RankingConfig,
+    /// Named sets of signal weights, selected with `profile=`
+    pub ranking_profiles: BTreeMap<String, RankingConfig>,
+    pub experiment: ExperimentConfig,
+    pub spam: SpamConfig,
+    pub quality: QualityConfig,
+    pub field_weights: FieldWeights,
+    pub field_boosts: FieldBoosts,
+    /// Added to the relevance of pages with every one of a multi-word query's terms, in
+    /// proportion to the share of them a page has
+    pub coverage_weight: f64,
+    /// Least relevance score a loose TF-IDF search without `min_match` returns pages with,
+    /// unless it asks for another with `min_score`
+    pub min_score: f64,
+    pub proximity: ProximityConfig,
+    pub preferences: PreferenceConfig,
+    pub anchor_text: AnchorTextConfig,
+    /// How searches without a `ranker` parameter score relevance
+    pub ranker: Ranker,
+    pub bm25: Bm25Config,
+    pub recency: RecencyConfig,
+    pub spelling: SpellingConfig,
+    pub related: RelatedConfig,
+    pub link_expansion: LinkExpansionConfig,
+    pub semantic: SemanticConfig,
+    pub duplicates: DuplicateConfig,
+    pub trending: TrendingConfig,
+    pub clicks: ClickConfig,
+    pub idf_cache: IdfCacheConfig,
+    pub shadow: ShadowConfig,
+    pub http_client: HttpClientConfig,
+    pub cors: CorsConfig,
+}
+
+/// Settings for Cloudflare Turnstile validation
+#[derive(Debug, Clone)]
+pub struct TurnstileConfig {
+    pub enabled: bool,
+    pub secret_key: String,
+    pub verify_url: String,
+    pub allow_query_token: bool,
+}
+
+/// Per-client request quotas, counted over a fixed window
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub window: Duration,
+    pub ip_quota: u32,
+    pub token_quota: u32,
+    pub api_key_quota: u32,
+}
+
+/// Prefix lengths client addresses are grouped by for identity purposes
+#[derive(Debug, Clone)]
+pub struct ClientIdentityConfig {
+    pub ipv4_prefix: u8,
+    pub ipv6_prefix: u8,
+}
+
+/// Settings for the ranked search result cache
+#[derive(Debug, Clone)]
+pub struct ResultCacheConfig {
+    pub ttl: Duration,
+    pub capacity: usize,
+}
+
+/// Settings for the cache of formatted result fields shared between searches
+#[derive(Debug, Clone)]
+pub struct FragmentCacheConfig {
+    pub ttl: Duration,
+    /// Most serialised bytes held at once; zero disables the cache
+    pub max_bytes: usize,
+}
+
+/// Settings for retrying database calls that failed in a way that may pass, and for the circuit
+/// breaker that stops calling the database while it keeps failing
+#[derive(Debug, Clone)]
+pub struct DatabaseRetryConfig {
+    /// Most times a call is tried, including the first; 1 never retries
+    pub attempts: usize,
+    /// Wait before the first retry, doubled before each one after it
+    pub backoff: Duration,
+    /// Consecutive failed calls that open the circuit; zero never opens it
+    pub breaker_threshold: u32,
+    /// How long an open circuit rejects calls before letting one through to try the database
+    pub breaker_cooldown: Duration,
+}
+
+/// Settings for two-tier search, where a quick first page is refined in the background
+#[derive(Debug, Clone)]
+pub struct TwoTierConfig {
+    /// How many pre-ranked candidates the quick first tier scores
+    pub candidate_limit: i64,
+    /// How many background refinements may run at once
+    pub max_background: usize,
+}
+
+/// Settings for suggesting spelling corrections
+#[derive(Debug, Clone)]
+pub struct SpellingConfig {
+    /// How many of the most common indexed words corrections are drawn from; zero disables
+    /// suggestions
+    pub vocabulary_size: i64,
+    /// Searches returning fewer results than this get a suggestion
+    pub suggest_below: usize,
+}
+
+/// Settings for the related searches suggested with results
+#[derive(Debug, Clone)]
+pub struct RelatedConfig {
+    /// Most related searches suggested; zero turns them off
+    pub count: usize,
+    /// How many of the top results the words they're made of are drawn from
+    pub from_results: usize,
+}
+
+/// Settings for expanding queries with few matches through the link graph
+#[derive(Debug, Clone)]
+pub struct LinkExpansionConfig {
+    /// Queries matching fewer pages than this are expanded; zero turns expansion off
+    pub below: usize,
+    /// Most words from linking pages a query is expanded with
+    pub terms: usize,
+}
+
+/// Settings for reordering results by embedding similarity with `semantic=true`
+#[derive(Debug, Clone)]
+pub struct SemanticConfig {
+    /// OpenAI-compatible embeddings endpoint, local or external; reranking is off without one
+    pub url: Option<String>,
+    /// Model named in embedding requests, for services serving several
+    pub model: Option<String>,
+    /// Sent as a bearer token, for services that need one
+    pub api_key: Option<String>,
+    /// How many of the best ranked results are reordered
+    pub top_k: usize,
+    /// Longest a search waits for embeddings before keeping its ranked order
+    pub timeout: Duration,
+}
+
+/// Settings for collapsing near-duplicate results with `collapse=true`
+#[derive(Debug, Clone)]
+pub struct DuplicateConfig {
+    /// Most bits two pages' fingerprints may differ in for them to count as near-duplicates
+    pub max_distance: u32,
+}
+
+/// Settings for logging searches and counting the trending ones
+#[derive(Debug, Clone)]
+pub struct TrendingConfig {
+    /// Log searches to the `query_log` table, for `GET /trending`
+    pub enabled: bool,
+    /// How far back each set of trending queries counts searches
+    pub windows: Vec<Duration>,
+    /// How often the trending queries are recounted from the log
+    pub refresh: Duration,
+    /// Most trending queries kept for each window
+    pub count: usize,
+    /// Secret mixed into the hashes of who searched; a random one per process if unset
+    pub salt: Option<String>,
+}
+
+/// Settings for learning from which results users click through to
+#[derive(Debug, Clone)]
+pub struct ClickConfig {
+    /// Count impressions and `POST /click` clicks in the `result_clicks` table, and boost
+    /// results by their click-through rate
+    pub enabled: bool,
+    /// How many of a search's top results count as shown
+    pub impression_depth: usize,
+    /// Age at which impressions and clicks count half as much
+    pub half_life: Duration,
+    /// Unclicked impressions every page's rate is smoothed with
+    pub prior_impressions: f64,
+    /// How often click-through rates are reloaded from the table
+    pub refresh: Duration,
+}
+
+/// Settings for the in-memory table of the most common words' document frequencies
+#[derive(Debug, Clone)]
+pub struct IdfCacheConfig {
+    /// How many of the most common words are held; 0 looks every word up
+    pub words: i64,
+    /// How often the table is reloaded from `keywords`
+    pub refresh: Duration,
+}
+
+/// Settings for periodically re-running popular queries into the result cache
+#[derive(Debug, Clone)]
+pub struct WarmingConfig {
+    pub interval: Duration,
+    /// How many of the most popular queries to warm; zero disables warming
+    pub top_n: usize,
+    pub concurrency: usize,
+    /// Queries that last took longer than this to execute are not warmed
+    pub slow_threshold: Duration,
+}
+
+/// Settings for computing link-graph authority scores in the background
+#[derive(Debug, Clone)]
+pub struct PageRankConfig {
+    /// Off for link graphs too large to hold in memory
+    pub enabled: bool,
+    pub iterations: usize,
+    pub damping: f32,
+    /// How many links are loaded per database round trip
+    pub batch_size: i64,
+    /// Whether HITS hub and authority scores are computed over the same link graph
+    pub hits: bool,
+    pub hits_iterations: usize,
+}
+
+/// How much each signal counts towards a result's score
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RankingConfig {
+    /// Multiplier for the relevance score, from TF-IDF or BM25
+    pub relevance: f64,
+    /// Most a domain's popularity rank in `top-1m.txt` adds to its pages' scores
+    pub domain: f64,
+    /// Most a just-crawled page's score is raised by with `recency=normal`, the default
+    pub freshness: f64,
+    /// How much link authority adds, for the most authoritative page
+    pub links: f64,
+    /// Most a page's score is raised by for its click-through rate, when click feedback is on
+    pub clicks: f64,
+    /// How much a HITS hub score adds, for the best hub, when HITS is on
+    pub hubs: f64,
+    /// How much a HITS authority score adds, for the best authority, when HITS is on
+    pub authorities: f64,
+}
+
+/// Settings for downranking pages that use spam terms
+#[derive(Debug, Clone)]
+pub struct SpamConfig {
+    /// File of spam terms, one per line; spam scoring is off without one
+    pub terms_file: Option<String>,
+    /// Spam term occurrences per word above which a page is penalised
+    pub threshold: f32,
+    /// Fewest spam term occurrences a page must have before it can be penalised
+    pub min_occurrences: i64,
+    /// Multiplier applied to a penalised page's ranking
+    pub penalty: f32,
+    /// Drop penalised pages from results instead of downranking them
+    pub strict: bool,
+}
+
+/// Thresholds below which pages are taken to be low quality and downranked. Each penalty is a
+/// multiplier, so 1 turns its check off.
+#[derive(Debug, Clone, Copy)]
+pub struct QualityConfig {
+    /// Pages with fewer words than this are thin
+    pub thin_words: i32,
+    pub thin_penalty: f64,
+    /// A query term's occurrences per word above which a page is stuffed with it
+    pub max_density: f64,
+    pub stuffing_penalty: f64,
+    /// Descriptions shorter than this, in characters, are near-empty
+    pub min_description_chars: usize,
+    pub description_penalty: f64,
+}
+
+/// Bonuses added to a page's relevance for the share of the query's terms in each of its fields.
+/// All are 0 by default, so only the indexed body text counts.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldWeights {
+    pub title: f64,
+    pub url: f64,
+    pub description: f64,
+}
+
+impl FieldWeights {
+    pub fn any(&self) -> bool {
+        self.title > 0.0 || self.url > 0.0 || self.description > 0.0
+    }
+}
+
+/// Settings for ranking pages with a multi-word query's terms close together higher
+#[derive(Debug, Clone, Copy)]
+pub struct ProximityConfig {
+    /// Added to the relevance of a page with the terms next to each other, and less the further
+    /// apart they are; 0 turns it off
+    pub weight: f64,
+    /// How many of the best scored pages are checked, since each needs its word positions
+    pub candidates: usize,
+    /// Multiplies the score of pages with a multi-word query's words in order, as a phrase,
+    /// whether or not it was quoted; 1 turns it off
+    pub verbatim_multiplier: f64,
+}
+
+/// Settings for ranking pages by the anchor text of the links to them
+#[derive(Debug, Clone, Copy)]
+pub struct AnchorTextConfig {
+    /// Added to the relevance of a page whose inbound anchor text has every query term, and
+    /// that share of it for some; 0 turns it off
+    pub weight: f64,
+    /// How many of the best scored pages are checked, since each needs its inbound links
+    pub candidates: usize,
+}
+
+/// Bonuses added to the relevance of pages matching the preferences a request sends
+#[derive(Debug, Clone, Copy)]
+pub struct PreferenceConfig {
+    /// Added for a page on a favoured domain or one of its subdomains
+    pub domain_weight: f64,
+    /// Added, times the share of favoured topics a page's title, URL or description has
+    pub topic_weight: f64,
+}
+
+/// How many extra occurrences a term counts for in relevance scoring for each time it's in a
+/// page's title or description, so a title match outscores a body-only one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FieldBoosts {
+    pub title: f64,
+    pub description: f64,
+}
+
+impl FieldBoosts {
+    pub fn any(&self) -> bool {
+        self.title > 0.0 || self.description > 0.0
+    }
+}
+
+/// How much newer pages are preferred
+#[derive(Debug, Clone, Copy)]
+pub struct RecencyConfig {
+    /// Most a just-crawled page's score is raised by with `recency=strong`, whatever the ranking
+    /// profile
+    pub strong_weight: f64,
+    /// Age at which a page's freshness has halved
+    pub half_life: Duration,
+    pub decay: Decay,
+}
+
+impl RecencyConfig {
+    /// The freshness weight `recency` asks for, under the weights of `ranking`.
+    pub fn weight(&self, recency: Recency, ranking: &RankingConfig) -> f64 {
+        match recency {
+            Recency::Normal => ranking.freshness,
+            Recency::Strong => self.strong_weight,
+            Recency::Off => 0.0,
+        }
+    }
+}
+
+/// BM25's tuning parameters
+#[derive(Debug, Clone, Copy)]
+pub struct Bm25Config {
+    /// How quickly repeating a term stops raising a page's score
+    pub k1: f64,
+    /// How much a page's length discounts its term counts, from 0 (not at all) to 1
+    pub b: f64,
+}
+
+/// A ranking experiment, splitting live searches between ranking profiles
+#[derive(Debug, Clone, Default)]
+pub struct ExperimentConfig {
+    /// The experiment's name, which responses are tagged with; `None` runs no experiment
+    pub name: Option<String>,
+    /// Each variant's id, `control` or a ranking profile's name, and its weight
+    pub variants: Vec<(String, u32)>,
+}
+
+/// A candidate ranking configuration, tried out on a sample of live searches without affecting
+/// their responses
+#[derive(Debug, Clone)]
+pub struct ShadowConfig {
+    /// Share of searches also ranked in the shadow, from 0 (off) to 100
+    pub sample_percent: f64,
+    /// The shadow ranking's counterpart of `RankingConfig::relevance`
+    pub relevance_weight: f64,
+    /// The shadow ranking's counterpart of `RankingConfig::links`
+    pub authority_weight: f64,
+    /// The shadow ranking's counterpart of `RankingConfig::domain`
+    pub domain_rank_weight: f64,
+    /// The shadow ranking's counterpart of `RankingConfig::hubs`
+    pub hub_weight: f64,
+    /// The shadow ranking's counterpart of `RankingConfig::authorities`
+    pub hits_authority_weight: f64,
+    /// The shadow ranking's counterpart of `SpamConfig::penalty`
+    pub spam_penalty: f64,
+    /// Most comparisons running at once; sampled searches beyond that aren't compared
+    pub max_concurrent: usize,
+    /// File comparisons are appended to as JSON lines; standard error without one
+    pub log_file: Option<String>,
+}
+
+/// Settings for a background writer's queue
+#[derive(Debug, Clone)]
+pub struct WriterConfig {
+    /// Most rows queued before the oldest are dropped
+    pub capacity: usize,
+    pub batch_size: usize,
+    /// Longest a row waits for its batch to fill before being written anyway
+    pub flush_interval: Duration,
+}
+
+/// Settings for the shared outbound HTTP client
+#[derive(Debug, Clone)]
+pub struct HttpClientConfig {
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration,
+    pub pool_idle_timeout: Duration,
+    pub pool_max_idle_per_host: usize,
+    pub https_proxy: Option<String>,
+}
+
+/// Cross-origin settings for the public routes
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub public_methods: Vec<String>,
+    pub max_age: Duration,
+    pub allow_credentials: bool,
+}
+
+impl Config {
+    /// Reads the configuration from the environment, panicking on malformed values.
+    pub fn from_env() -> Self {
+        let max_results = env_or("MAX_RESULTS", 100);
+        let ranking = RankingConfig {
+            relevance: env_or("RELEVANCE_WEIGHT", 1.0),
+            domain: env_or("DOMAIN_RANK_WEIGHT", 0.2),
+            freshness: env_or("RECENCY_WEIGHT", 0.1),
+            links: env_or("PAGERANK_WEIGHT", 0.5),
+            clicks: env_or("CLICK_WEIGHT", 0.5),
+            hubs: env_or("HUB_WEIGHT", 0.0),
+            authorities: env_or("HITS_AUTHORITY_WEIGHT", 0.0),
+        };
+        let ranking_profiles = load_ranking_profiles(
+            env_opt("RANKING_PROFILES_FILE").as_deref(),
+            ranking
+        ).unwrap_or_else(|e| panic!("RANKING_PROFILES_FILE is invalid: {}", e));
+        let experiment = parse_experiment(
+            env_opt("EXPERIMENT_NAME"),
+            env_opt("EXPERIMENT_VARIANTS").as_deref(),
+            &ranking_profiles
+        ).unwrap_or_else(|e| panic!("EXPERIMENT_VARIANTS is invalid: {}", e));
+        let spam_penalty: f64 = env_or("SPAM_PENALTY", 0.2);
+        let presets = params
+            ::load_presets(env_opt("SEARCH_PRESETS_FILE").as_deref(), max_results)
+            .unwrap_or_else(|e| panic!("SEARCH_PRESETS_FILE is invalid: {}", e));
+
+        Config {
+            port: env_or("AXUM_PORT", 3000),
+            db_acquire_timeout: env_duration("DB_ACQUIRE_TIMEOUT", Duration::from_secs(30)),
+            db_retry: DatabaseRetryConfig {
+                attempts: env_or("DB_RETRY_ATTEMPTS", 3).max(1),
+                backoff: env_millis("DB_RETRY_BACKOFF_MS", 50),
+                breaker_threshold: env_or("DB_BREAKER_THRESHOLD", 5),
+                breaker_cooldown: env_secs("DB_BREAKER_COOLDOWN_SECS", 10),
+            },
+            max_results,
+            max_per_domain: env_or("MAX_PER_DOMAIN", 3),
+            max_query_chars: env_or("MAX_QUERY_CHARS", 512),
+            max_query_terms: env_or("MAX_QUERY_TERMS", 64),
+            max_query_depth: env_or("MAX_QUERY_DEPTH", 8),
+            max_response_bytes: env_bytes("MAX_RESPONSE_BYTES", 0),
+            keyword_budget: env_or("KEYWORD_BUDGET", 32),
+            privileged_keyword_budget: env_or("KEYWORD_BUDGET_PRIVILEGED", 128),
+            slow_query_threshold: env_millis("SLOW_QUERY_MS", 1000),
+            slow_query_log_file: env_opt("SLOW_QUERY_LOG_FILE"),
+            capture_file: env_opt("CAPTURE_FILE"),
+            access_log: env_flag("ACCESS_LOG", false),
+            access_log_file: env_opt("ACCESS_LOG_FILE"),
+            log_writer: WriterConfig {
+                capacity: env_or("LOG_QUEUE_CAPACITY", 10_000),
+                batch_size: env_or("LOG_BATCH_SIZE", 100),
+                flush_interval: env_millis("LOG_FLUSH_MS", 1000),
+            },
+            stats_max_age: env_secs("STATS_MAX_AGE_SECS", 30),
+            index_refresh: env_secs("INDEX_REFRESH_SECS", 60),
+            links_cache_ttl: env_secs("LINKS_CACHE_TTL_SECS", 60),
+            top_links_cache_ttl: env_secs("TOP_LINKS_CACHE_TTL_SECS", 300),
+            site_cache_ttl: env_secs("SITE_CACHE_TTL_SECS", 300),
+            suggest_cache_ttl: env_secs("SUGGEST_CACHE_TTL_SECS", 60),
+            folding_exceptions: env_list("DIACRITIC_FOLDING_EXCEPTIONS", &[])
+                .iter()
+                .flat_map(|letters| letters.to_lowercase().chars().collect::<Vec<_>>())
+                .collect(),
+            presets,
+            turnstile: TurnstileConfig::from_env(),
+            api_keys_file: env_opt("API_KEYS_FILE"),
+            synonyms_file: env_opt("SYNONYMS_FILE"),
+            stopwords_language: LanguageCode::parse(&env_or("STOPWORDS_LANGUAGE", "en".to_string())),
+            detect_query_language: env_flag("DETECT_QUERY_LANGUAGE", true),
+            lemma_files: env_list("LEMMA_FILES", &[])
+                .iter()
+                .filter_map(|entry| {
+                    let (language, file) = entry.split_once('=')?;
+                    Some((LanguageCode::parse(language)?, file.trim().to_string()))
+                })
+                .collect(),
+            fuzzy_fallback: env_flag("FUZZY_FALLBACK", false),
+            admin_token: env_opt("ADMIN_TOKEN"),
+            rate_limit: RateLimitConfig {
+                window: env_secs("RATE_LIMIT_WINDOW_SECS", 60),
+                ip_quota: env_or("RATE_LIMIT_IP_QUOTA", 60),
+                token_quota: env_or("RATE_LIMIT_TOKEN_QUOTA", 120),
+                api_key_quota: env_or("RATE_LIMIT_API_KEY_QUOTA", 600),
+            },
+            client_identity: ClientIdentityConfig {
+                ipv4_prefix: env_prefix("IPV4_IDENTITY_PREFIX", 32, 32),
+                ipv6_prefix: env_prefix("IPV6_IDENTITY_PREFIX", 64, 128),
+            },
+            result_cache: ResultCacheConfig {
+                ttl: env_secs("RESULT_CACHE_TTL_SECS", 300),
+                capacity: env_or("RESULT_CACHE_CAPACITY", 1000),
+            },
+            fragment_cache: FragmentCacheConfig {
+                ttl: env_secs("FRAGMENT_CACHE_TTL_SECS", 300),
+                max_bytes: env_bytes("FRAGMENT_CACHE_BYTES", 16 << 20),
+            },
+            two_tier: TwoTierConfig {
+                candidate_limit: env_or("TWO_TIER_CANDIDATES", 200),
+                max_background: env_or("TWO_TIER_MAX_BACKGROUND", 4),
+            },
+            warming: WarmingConfig {
+                interval: env_secs("WARM_INTERVAL_SECS", 240),
+                top_n: env_or("WARM_TOP_QUERIES", 20),
+                concurrency: env_or("WARM_CONCURRENCY", 2),
+                slow_threshold: env_millis("WARM_SLOW_THRESHOLD_MS", 2000),
+            },
+            pagerank: PageRankConfig {
+                enabled: env_flag("PAGERANK_ENABLED", true),
+                iterations: env_or("PAGERANK_ITERATIONS", 20),
+                damping: env_or("PAGERANK_DAMPING", 0.85),
+                batch_size: env_or("PAGERANK_BATCH_SIZE", 100_000),
+                hits: env_flag("HITS_ENABLED", false),
+                hits_iterations: env_or("HITS_ITERATIONS", 20),
+            },
+            ranking,
+            ranking_profiles,
+            experiment,
+            spam: SpamConfig {
+                terms_file: env_opt("SPAM_TERMS_FILE"),
+                threshold: env_or("SPAM_THRESHOLD", 0.02),
+                min_occurrences: env_or("SPAM_MIN_OCCURRENCES", 3),
+                penalty: spam_penalty as f32,
+                strict: env_flag("SPAM_STRICT", false),
+            },
+            quality: QualityConfig {
+                thin_words: env_or("THIN_PAGE_WORDS", 50),
+                thin_penalty: env_or("THIN_PAGE_PENALTY", 0.7),
+                max_density: env_or("KEYWORD_DENSITY_MAX", 0.25),
+                stuffing_penalty: env_or("KEYWORD_STUFFING_PENALTY", 0.5),
+                min_description_chars: env_or("MIN_DESCRIPTION_CHARS", 20),
+                description_penalty: env_or("EMPTY_DESCRIPTION_PENALTY", 0.9),
+            },
+            field_weights: FieldWeights {
+                title: env_or("FIELD_WEIGHT_TITLE", 0.0),
+                url: env_or("FIELD_WEIGHT_URL", 0.0),
+                description: env_or("FIELD_WEIGHT_DESCRIPTION", 0.0),
+            },
+            field_boosts: FieldBoosts {
+                title: env_or("FIELD_BOOST_TITLE", 3.0),
+                description: env_or("FIELD_BOOST_DESCRIPTION", 1.0),
+            },
+            coverage_weight: env_or("COVERAGE_WEIGHT", 0.5),
+            min_score: env_or("MIN_SCORE", 1.0),
+            proximity: ProximityConfig {
+                weight: env_or("PROXIMITY_WEIGHT", 0.3),
+                candidates: env_or("PROXIMITY_CANDIDATES", 200),
+                verbatim_multiplier: env_or("VERBATIM_MULTIPLIER", 1.25),
+            },
+            preferences: PreferenceConfig {
+                domain_weight: env_or("PREFERRED_DOMAIN_WEIGHT", 0.5),
+                topic_weight: env_or("PREFERRED_TOPIC_WEIGHT", 0.25),
+            },
+            anchor_text: AnchorTextConfig {
+                weight: env_or("ANCHOR_TEXT_WEIGHT", 0.5),
+                candidates: env_or("ANCHOR_TEXT_CANDIDATES", 200),
+            },
+            ranker: env_opt("RANKER")
+                .map(|name| {
+                    Ranker::parse(name.trim())
+                        .unwrap_or_else(|| panic!("RANKER must be tfidf or bm25"))
+                })
+                .unwrap_or_default(),
+            bm25: Bm25Config {
+                k1: env_or("BM25_K1", 1.2),
+                b: env_or("BM25_B", 0.75_f64).clamp(0.0, 1.0),
+            },
+            recency: RecencyConfig {
+                strong_weight: env_or("RECENCY_STRONG_WEIGHT", 0.5),
+                half_life: env_duration("RECENCY_HALF_LIFE", Duration::from_secs(30 * 24 * 3600)),
+                decay: env_opt("RECENCY_DECAY")
+                    .map(|name| {
+                        Decay::parse(name.trim())
+                            .unwrap_or_else(|| panic!("RECENCY_DECAY must be exponential or linear"))
+                    })
+                    .unwrap_or(Decay::Exponential),
+            },
+            spelling: SpellingConfig {
+                vocabulary_size: env_or("SPELLING_VOCABULARY", 50_000),
+                suggest_below: env_or("SPELLING_SUGGEST_BELOW", 3),
+            },
+            related: RelatedConfig {
+                count: env_or("RELATED_SEARCHES", 5),
+                from_results: env_or("RELATED_FROM_RESULTS", 10),
+            },
+            link_expansion: LinkExpansionConfig {
+                below: env_or("LINK_EXPANSION_BELOW", 3),
+                terms: env_or("LINK_EXPANSION_TERMS", 3),
+            },
+            semantic: SemanticConfig {
+                url: env_opt("EMBEDDING_URL"),
+                model: env_opt("EMBEDDING_MODEL"),
+                api_key: env_opt("EMBEDDING_API_KEY"),
+                top_k: env_or("SEMANTIC_TOP_K", 20),
+                timeout: env_millis("SEMANTIC_TIMEOUT_MS", 1000),
+            },
+            duplicates: DuplicateConfig {
+                max_distance: env_or("DUPLICATE_MAX_DISTANCE", 3),
+            },
+            trending: TrendingConfig {
+                enabled: env_flag("QUERY_LOG", false),
+                windows: env_list("TRENDING_WINDOWS", &["1h", "24h", "168h"])
+                    .iter()
+                    .map(|window| {
+                        units
+                            ::parse_duration(window, None)
+                            .unwrap_or_else(|e| panic!("TRENDING_WINDOWS must be durations: {}", e))
+                    })
+                    .collect(),
+                refresh: env_secs("TRENDING_REFRESH_SECS", 60),
+                count: env_or("TRENDING_COUNT", 50),
+                salt: env_opt("QUERY_LOG_SALT"),
+            },
+            clicks: ClickConfig {
+                enabled: env_flag("CLICK_FEEDBACK", false),
+                impression_depth: env_or("CLICK_IMPRESSION_DEPTH", 10),
+                half_life: env_duration("CLICK_HALF_LIFE", Duration::from_secs(7 * 24 * 3600)),
+                prior_impressions: env_or("CLICK_PRIOR_IMPRESSIONS", 20.0),
+                refresh: env_secs("CLICK_REFRESH_SECS", 60),
+            },
+            idf_cache: IdfCacheConfig {
+                words: env_or("IDF_CACHE_WORDS", 1_000_000),
+                refresh: env_secs("IDF_REFRESH_SECS", 3600),
+            },
+            shadow: ShadowConfig {
+                sample_percent: env_percent("SHADOW_SAMPLE_PERCENT", 0.0),
+                relevance_weight: env_or("SHADOW_RELEVANCE_WEIGHT", ranking.relevance),
+                authority_weight: env_or("SHADOW_PAGERANK_WEIGHT", ranking.links),
+                domain_rank_weight: env_or("SHADOW_DOMAIN_RANK_WEIGHT", ranking.domain),
+                hub_weight: env_or("SHADOW_HUB_WEIGHT", ranking.hubs),
+                hits_authority_weight: env_or("SHADOW_HITS_AUTHORITY_WEIGHT", ranking.authorities),
+                spam_penalty: env_or("SHADOW_SPAM_PENALTY", spam_penalty),
+                max_concurrent: env_or("SHADOW_MAX_CONCURRENT", 2),
+                log_file: env_opt("SHADOW_LOG_FILE"),
+            },
+            http_client: HttpClientConfig {
+                connect_timeout: env_millis("HTTP_CONNECT_TIMEOUT_MS", 2000),
+                request_timeout: env_millis("HTTP_REQUEST_TIMEOUT_MS", 5000),
+                pool_idle_timeout: env_secs("HTTP_POOL_IDLE_TIMEOUT_SECS", 90),
+                pool_max_idle_per_host: env_or("HTTP_POOL_MAX_IDLE_PER_HOST", 8),
+                // Honour the conventional proxy variables in both spellings
+                https_proxy: env_opt("HTTPS_PROXY").or_else(|| env_opt("https_proxy")),
+            },
+            cors: CorsConfig {
+                allowed_origins: env_list(
+                    "CORS_ALLOWED_ORIGINS",
+                    &[
+                        "http://localhost:3000",
+                        "http://localhost:3001",
+                        "http://search.ylokhmotov.dev",
+                        "https://search.ylokhmotov.dev",
+                    ]
+                ),
+                public_methods: env_list("CORS_PUBLIC_METHODS", &["GET", "POST"]),
+                max_age: env_secs("CORS_MAX_AGE_SECS", 600),
+                allow_credentials: env_flag("CORS_ALLOW_CREDENTIALS", false),
+            },
+        }
+    }
+}
+
+impl Config {
+    /// The signal weights of the ranking profile named `profile`, or the configured ones without
+    /// one. A profile that no longer exists, as in an old capture, gets the configured weights.
+    pub fn ranking(&self, profile: Option<&str>) -> RankingConfig {
+        profile
+            .and_then(|profile| self.ranking_profiles.get(profile))
+            .copied()
+            .unwrap_or(self.ranking)
+    }
+
+    /// The duration and size settings as they were parsed, with their units, to print at
+    /// startup.
+    pub fn units_summary(&self) -> String {
+        let durations = [
+            ("DB_ACQUIRE_TIMEOUT", self.db_acquire_timeout),
+            ("DB_RETRY_BACKOFF_MS", self.db_retry.backoff),
+            ("DB_BREAKER_COOLDOWN_SECS", self.db_retry.breaker_cooldown),
+            ("SLOW_QUERY_MS", self.slow_query_threshold),
+            ("LOG_FLUSH_MS", self.log_writer.flush_interval),
+            ("STATS_MAX_AGE_SECS", self.stats_max_age),
+            ("INDEX_REFRESH_SECS", self.index_refresh),
+            ("LINKS_CACHE_TTL_SECS", self.links_cache_ttl),
+            ("TOP_LINKS_CACHE_TTL_SECS", self.top_links_cache_ttl),
+            ("SITE_CACHE_TTL_SECS", self.site_cache_ttl),
+            ("SUGGEST_CACHE_TTL_SECS", self.suggest_cache_ttl),
+            ("RATE_LIMIT_WINDOW_SECS", self.rate_limit.window),
+            ("RESULT_CACHE_TTL_SECS", self.result_cache.ttl),
+            ("FRAGMENT_CACHE_TTL_SECS", self.fragment_cache.ttl),
+            ("WARM_INTERVAL_SECS", self.warming.interval),
+            ("WARM_SLOW_THRESHOLD_MS", self.warming.slow_threshold),
+            ("RECENCY_HALF_LIFE", self.recency.half_life),
+            ("SEMANTIC_TIMEOUT_MS", self.semantic.timeout),
+            ("TRENDING_REFRESH_SECS", self.trending.refresh),
+            ("CLICK_HALF_LIFE", self.clicks.half_life),
+            ("CLICK_REFRESH_SECS", self.clicks.refresh),
+            ("IDF_REFRESH_SECS", self.idf_cache.refresh),
+            ("HTTP_CONNECT_TIMEOUT_MS", self.http_client.connect_timeout),
+            ("HTTP_REQUEST_TIMEOUT_MS", self.http_client.request_timeout),
+            ("HTTP_POOL_IDLE_TIMEOUT_SECS", self.http_client.pool_idle_timeout),
+            ("CORS_MAX_AGE_SECS", self.cors.max_age),
+        ];
+        let mut settings: Vec<String> = durations
+            .iter()
+            .map(|(name, duration)| format!("{}={}", name, units::format_duration(*duration)))
+            .collect();
+        let windows: Vec<String> = self.trending.windows
+            .iter()
+            .map(|window| units::format_duration(*window))
+            .collect();
+        settings.push(format!("TRENDING_WINDOWS={}", windows.join(",")));
+        for (name, bytes) in [
+            ("MAX_RESPONSE_BYTES", self.max_response_bytes),
+            ("FRAGMENT_CACHE_BYTES", self.fragment_cache.max_bytes),
+        ] {
+            settings.push(format!("{}={}", name, units::format_bytes(bytes as u64)));
+        }
+        settings.join(" ")
+    }
+}
+
+/// Returns the built-in ranking profiles, overlaid with any defined in `file`.
+///
+/// Each line of the file is a profile name followed by `signal=weight` pairs, for the signals
+/// `relevance`, `domain`, `freshness`, `links` and `clicks`; signals a profile doesn't name keep
+/// their configured weights. Blank lines and lines starting with `#` are ignored, and a custom
+/// profile with a built-in name replaces it.
+fn load_ranking_profiles(
+    file: Option<&str>,
+    default: RankingConfig
+) -> Result<BTreeMap<String, RankingConfig>, String> {
+    let mut profiles = BTreeMap::new();
+    profiles.insert("relevance".to_string(), RankingConfig {
+        domain: 0.0,
+        freshness: 0.0,
+        links: 0.0,
+        clicks: 0.0,
+        hubs: 0.0,
+        authorities: 0.0,
+        ..default
+    });
+    profiles.insert("fresh".to_string(), RankingConfig { freshness: 0.5, ..default });
+    profiles.insert("authority".to_string(), RankingConfig { domain: 0.5, links: 1.0, ..default });
+    profiles.insert("directory".to_string(), RankingConfig { hubs: 1.0, ..default });
+
+    let Some(file) = file else {
+        return Ok(profiles);
+    };
+    let contents = std::fs
+        ::read_to_string(file)
+        .map_err(|e| format!("Failed to read {}: {}", file, e))?;
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let name = fields.next().unwrap_or_default().to_lowercase();
+        let mut profile = default;
+        for field in fields {
+            let invalid = || format!("{}:{}: invalid weight {:?}", file, line_number + 1, field);
+            let (signal, weight) = field.split_once('=').ok_or_else(invalid)?;
+            let weight: f64 = weight.parse().map_err(|_| invalid())?;
+            match signal {
+                "relevance" => {
+                    profile.relevance = weight;
+                }
+                "domain" => {
+                    profile.domain = weight;
+                }
+                "freshness" => {
+                    profile.freshness = weight;
+                }
+                "links" => {
+                    profile.links = weight;
+                }
+                "clicks" => {
+                    profile.clicks = weight;
+                }
+                "hubs" => {
+                    profile.hubs = weight;
+                }
+                "authorities" => {
+                    profile.authorities = weight;
+                }
+                _ => {
+                    return Err(invalid());
+                }
+            }
+        }
+        profiles.insert(name, profile);
+    }
+    Ok(profiles)
+}
+
+/// Parses `EXPERIMENT_VARIANTS`, a comma-separated list of `variant=weight`, where each variant
+/// is `control` or the name of a ranking profile; a variant without a weight has weight 1.
+/// Without a name there's no experiment, and without variants every search is in the control.
+fn parse_experiment(
+    name: Option<String>,
+    variants: Option<&str>,
+    profiles: &BTreeMap<String, RankingConfig>
+) -> Result<ExperimentConfig, String> {
+    let Some(name) = name else {
+        return Ok(ExperimentConfig::default());
+    };
+    let mut parsed = vec![];
+    for variant in variants.unwrap_or(experiments::CONTROL).split(',').map(str::trim) {
+        let (id, weight) = variant.split_once('=').unwrap_or((variant, "1"));
+        let id = id.trim().to_lowercase();
+        let weight: u32 = weight
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid weight in {:?}", variant))?;
+        if id != experiments::CONTROL && !profiles.contains_key(&id) {
+            return Err(format!("{:?} is neither control nor a ranking profile", id));
+        }
+        parsed.push((id, weight));
+    }
+    if parsed.iter().all(|(_, weight)| *weight == 0) {
+        return Err("at least one variant needs a weight above 0".to_string());
+    }
+    Ok(ExperimentConfig { name: Some(name), variants: parsed })
+}
+
+impl TurnstileConfig {
+    fn from_env() -> Self {
+        let enabled = env_flag("TURNSTILE_ENABLED", true);
+        let secret_key = env_opt("CLOUDFLARE_TURNSTILE_SECRET_KEY");
+        if enabled && secret_key.is_none() {
+            panic!("CLOUDFLARE_TURNSTILE_SECRET_KEY must be set");
+        }
+
+        TurnstileConfig {
+            enabled,
+            secret_key: secret_key.unwrap_or_default(),
+            verify_url: env_opt("TURNSTILE_VERIFY_URL").unwrap_or_else(||
+                "https://challenges.cloudflare.com/turnstile/v0/siteverify".to_string()
+            ),
+            allow_query_token: env_flag("TURNSTILE_ALLOW_QUERY_TOKEN", true),
+        }
+    }
+}
+
+/// Returns the parsed value of an environment variable, or `default` if it is unset.
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env_opt(name) {
+        Some(value) =>
+            value.parse().unwrap_or_else(|_| panic!("{} must be a valid number", name)),
+        None => default,
+    }
+}
+
+/// Returns a duration from the environment, or `default` if it is unset. The value must carry
+/// its unit, as in `30s`, since the name doesn't say what a bare number would count.
+fn env_duration(name: &str, default: Duration) -> Duration {
+    env_duration_in(name, None, default)
+}
+
+/// Returns a duration from a variable whose name ends in `_MS`, so a bare number is in
+/// milliseconds. A value with a unit, like `2s`, is accepted too.
+fn env_millis(name: &str, default: u64) -> Duration {
+    env_duration_in(name, Some("ms"), Duration::from_millis(default))
+}
+
+/// Returns a duration from a variable whose name ends in `_SECS`, so a bare number is in
+/// seconds. A value with a unit, like `5m`, is accepted too.
+fn env_secs(name: &str, default: u64) -> Duration {
+    env_duration_in(name, Some("s"), Duration::from_secs(default))
+}
+
+fn env_duration_in(name: &str, bare_unit: Option<&str>, default: Duration) -> Duration {
+    match env_opt(name) {
+        Some(value) =>
+            units
+                ::parse_duration(&value, bare_unit)
+                .unwrap_or_else(|e| panic!("{} must be a duration: {}", name, e)),
+        None => default,
+    }
+}
+
+/// Returns a size in bytes from the environment, or `default` if it is unset. A bare number is
+/// in bytes, and values like `256kb` are accepted too.
+fn env_bytes(name: &str, default: usize) -> usize {
+    match env_opt(name) {
+        Some(value) =>
+            units
+                ::parse_bytes(&value)
+                .ok()
+                .and_then(|bytes| usize::try_from(bytes).ok())
+                .unwrap_or_else(|| panic!("{} must be a size such as 256kb or 10mb", name)),
+        None => default,
+    }
+}
+
+/// Returns a network prefix length from the environment, checking it against `max`.
+fn env_prefix(name: &str, default: u8, max: u8) -> u8 {
+    let prefix = env_or(name, default);
+    if prefix > max {
+        panic!("{} must be at most {}", name, max);
+    }
+    prefix
+}
+
+/// Returns a percentage from the environment, checking it is between 0 and 100.
+fn env_percent(name: &str, default: f64) -> f64 {
+    let percent = env_or(name, default);
+    if !(0.0..=100.0).contains(&percent) {
+        panic!("{} must be between 0 and 100", name);
+    }
+    percent
+}
+
+/// Returns the boolean value of an environment variable, or `default` if it is unset.
+fn env_flag(name: &str, default: bool) -> bool {
+    match env_opt(name).map(|value| value.to_lowercase()) {
+        Some(value) =>
+            match value.as_str() {
+                "true" | "1" | "yes" => true,
+                "false" | "0" | "no" => false,
+                _ => panic!("{} must be true or false", name),
+            }
+        None => default,
+    }
+}
+
+/// Returns the comma-separated values of an environment variable, or `default` if it is unset.
+fn env_list(name: &str, default: &[&str]) -> Vec<String> {
+    match env_opt(name) {
+        Some(value) =>
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect(),
+        None =>
+            default
+                .iter()
+                .map(|item| item.to_string())
+                .collect(),
+    }
+}
+
+/// Returns the value of an environment variable, treating empty values as unset.
+fn env_opt(name: &str) -> Option<String> {
+    std::env
+        ::var(name)
+        .ok()
+        .filter(|value| !value.is_empty())
+}
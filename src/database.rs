@@ -1,18 +1,174 @@
+use axum::{
+    http::StatusCode,
+    response::{ IntoResponse, Json, Response },
+};
+use chrono::{ DateTime, Utc };
+use futures::{ Stream, StreamExt };
+use serde_json::json;
 use sqlx::{ PgPool, Row, postgres::PgRow };
 use std::collections::HashMap;
-use std::error::Error;
+use std::fmt;
+use crate::locale::LanguageCode;
+use crate::query_parser::ParsedQuery;
+use crate::ranking::QualityPenalty;
+use crate::diversity::MoreFromSite;
+use crate::duplicates::Duplicate;
+use crate::explain::Explanation;
+use crate::pagerank::HitsScores;
+use crate::spam::SpamMatch;
+use crate::trending::{ LoggedQuery, TrendingQuery };
+
+/// A database failure, classified by what it means for the request. Each variant carries
+/// operator-facing detail (the SQLSTATE and any constraint name) for logs, which is never sent
+/// to clients.
+#[derive(Debug)]
+pub enum AppError {
+    /// The database couldn't be reached, is shutting down, or is out of resources
+    Unavailable(String),
+    /// A statement was cancelled, usually by `statement_timeout`, or waited too long for a lock
+    Timeout(String),
+    /// A table, column, or type the queries rely on doesn't exist in the database
+    SchemaMismatch(String),
+    Internal(String),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::SchemaMismatch(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Whether the same request may succeed if tried again.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, AppError::Unavailable(_) | AppError::Timeout(_))
+    }
+
+    /// An error response with the status for this failure and a client-facing `message`.
+    pub fn response(&self, message: &str) -> Response {
+        (
+            self.status(),
+            Json(json!({ "error": message, "retryable": self.is_retryable() })),
+        ).into_response()
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Unavailable(detail) => write!(f, "database unavailable: {}", detail),
+            AppError::Timeout(detail) => write!(f, "database timeout: {}", detail),
+            AppError::SchemaMismatch(detail) => write!(f, "database schema mismatch: {}", detail),
+            AppError::Internal(detail) => write!(f, "database error: {}", detail),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<sqlx::Error> for AppError {
+    fn from(error: sqlx::Error) -> Self {
+        let detail = error.to_string();
+        match &error {
+            sqlx::Error::Database(database_error) => {
+                let code = database_error.code().map(|code| code.into_owned()).unwrap_or_default();
+                let detail = match database_error.constraint() {
+                    Some(constraint) => format!("SQLSTATE {} ({}): {}", code, constraint, detail),
+                    None => format!("SQLSTATE {}: {}", code, detail),
+                };
+                match code.as_str() {
+                    // Query cancelled, lock not available
+                    "57014" | "55P03" => AppError::Timeout(detail),
+                    // Admin or crash shutdown, cannot connect now, serialisation failure, deadlock
+                    "57P01" | "57P02" | "57P03" | "40001" | "40P01" => AppError::Unavailable(detail),
+                    // Undefined table, column, function, or object; wrong column type
+                    "42P01" | "42703" | "42883" | "42704" | "42804" => {
+                        AppError::SchemaMismatch(detail)
+                    }
+                    // Connection exceptions and insufficient resources
+                    code if code.starts_with("08") || code.starts_with("53") => {
+                        AppError::Unavailable(detail)
+                    }
+                    _ => AppError::Internal(detail),
+                }
+            }
+            sqlx::Error::Io(_) |
+            sqlx::Error::Tls(_) |
+            sqlx::Error::PoolTimedOut |
+            sqlx::Error::PoolClosed |
+            sqlx::Error::WorkerCrashed => AppError::Unavailable(detail),
+            sqlx::Error::ColumnNotFound(_) |
+            sqlx::Error::ColumnDecode { .. } |
+            sqlx::Error::TypeNotFound { .. } => AppError::SchemaMismatch(detail),
+            _ => AppError::Internal(detail),
+        }
+    }
+}
 
 /// Represents a webpage with its associated metadata and keyword information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Webpage {
     pub id: i32,
     pub title: String,
     pub url: String,
     pub description: String,
     pub word_count: i32,
-    pub keywords: HashMap<Keyword, i32>,
+    pub last_crawled: Option<DateTime<Utc>>,
+    /// ISO 639-1 code of the page's language, if the crawler detected one
+    pub language: Option<String>,
+    /// Matched keywords with their occurrences, sorted by word so scoring is deterministic
+    pub keywords: Vec<(Keyword, i32)>,
+    /// How often each of `keywords`, in the same order, occurs in the title and description.
+    /// Empty until counted for field boosts.
+    pub field_occurrences: Vec<FieldOccurrences>,
     pub links_to_count: Option<usize>,
     pub links_from: Option<HashMap<String, i32>>,
+    pub spam: Option<SpamMatch>,
+    /// Why the page was downranked as low quality, if it was
+    pub quality: Option<QualityPenalty>,
+    /// The page's HITS hub and authority scores, once computed
+    pub hits: Option<HitsScores>,
+    /// Whether the title or description contains the query's words in order, if checked
+    pub verbatim: Option<bool>,
+    /// Whether the title or description contains every quoted phrase of the query, if it has any
+    pub phrase_match: Option<bool>,
+    /// Whether every `NEAR/n` pair of the query is close enough together, if it has any
+    pub proximity_match: Option<bool>,
+    /// How close together the page has a multi-word query's terms, from 0 to 1, if checked
+    pub term_closeness: Option<f64>,
+    /// The share of the query's distinct terms in the anchor text of links to the page, if
+    /// checked
+    pub anchor_text: Option<f64>,
+    /// Whether the page's text has the query's words in order, by the word positions the crawler
+    /// recorded, if checked
+    pub verbatim_in_text: Option<bool>,
+    /// Synonyms the page matched query terms through, if the query was expanded
+    pub synonyms: Vec<String>,
+    /// Words spelt like a fuzzy term that the page matched it through
+    pub fuzzy: Vec<String>,
+    /// Words from pages linking to the query's matches that the page was found through, if it
+    /// doesn't match the query itself
+    pub link_expansion: Vec<String>,
+    /// Lower ranked results collapsed into this one as near-duplicates of it
+    pub duplicates: Vec<Duplicate>,
+    /// Lower ranked results from this one's site held back by `max_per_domain`, if any
+    pub more_from_site: Option<MoreFromSite>,
+    /// Distinct query terms the page contains, directly or through a synonym, counted while
+    /// scoring
+    pub matched_terms: usize,
+    /// The ranker's relevance score, before any bonus or weight was applied
+    pub relevance: f64,
+    /// How the page's score and place were arrived at, with `explain=true`
+    pub explanation: Option<Box<Explanation>>,
+}
+
+/// How often a keyword occurs in a page's title and description
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FieldOccurrences {
+    pub title: u32,
+    pub description: u32,
 }
 
 /// Represents a keyword with its associated metadata
@@ -20,21 +176,131 @@ pub struct Webpage {
 pub struct Keyword {
     pub id: i32,
     pub word: String,
+    /// Left at 0 by the page fetches, and filled in by `idf::fill`
     pub documents_containing_word: i64,
 }
 
+/// Which pages may be search candidates, besides matching a keyword
+#[derive(Debug, Clone, Default)]
+pub struct CandidateFilter {
+    /// Only pages on this host or its subdomains
+    pub site: Option<String>,
+    /// No pages containing any of these words
+    pub excluded_terms: Vec<String>,
+    /// Only pages whose lowercased URL contains all of these
+    pub url_terms: Vec<String>,
+    /// Only pages whose lowercased title contains all of these
+    pub title_terms: Vec<String>,
+    /// Only pages containing every one of these words, for strict searches
+    pub required_terms: Vec<String>,
+    /// Only pages last crawled at or after this time
+    pub crawled_after: Option<DateTime<Utc>>,
+    /// Only pages last crawled before this time
+    pub crawled_before: Option<DateTime<Utc>>,
+    /// Only pages in this language
+    pub language: Option<LanguageCode>,
+    /// Only pages whose URL matches this Postgres regex
+    pub url_regex: Option<String>,
+    pub tombstones: Tombstones,
+}
+
+impl CandidateFilter {
+    /// The filter for a parsed query's `site:`, `url:`, `intitle:` and exclusion operators. A boolean
+    /// expression's `NOT` terms can sit under an `OR`, so they aren't excluded here and are left
+    /// to the expression.
+    pub fn for_query(parsed: &ParsedQuery, tombstones: Tombstones) -> Self {
+        CandidateFilter {
+            site: parsed.site_filter.clone(),
+            excluded_terms: if parsed.expression.is_none() {
+                parsed.excluded_terms.clone()
+            } else {
+                vec![]
+            },
+            url_terms: parsed.url_terms.clone(),
+            title_terms: parsed.title_substrings.clone(),
+            tombstones,
+            ..CandidateFilter::default()
+        }
+    }
+
+    /// Whether the filter leaves out any pages besides tombstoned ones, or those lacking a
+    /// required term, which aren't a filter of the query's.
+    pub fn narrows(&self) -> bool {
+        self.site.is_some() ||
+            !self.excluded_terms.is_empty() ||
+            !self.url_terms.is_empty() ||
+            !self.title_terms.is_empty() ||
+            self.crawled_after.is_some() ||
+            self.crawled_before.is_some() ||
+            self.language.is_some() ||
+            self.url_regex.is_some()
+    }
+
+    /// SQL for the filter on `table`, with the site bound as parameter `$n`, the excluded terms
+    /// as `$n + 1`, the URL terms as `$n + 2`, the crawl date range as `$n + 3` and `$n + 4`, the
+    /// language as `$n + 5`, the title terms as `$n + 6`, the required terms as `$n + 7` and the
+    /// URL regex as `$n + 8`.
+    fn condition(&self, table: &str, n: usize) -> String {
+        format!(
+            r#"{}
+            AND NOT EXISTS (
+                SELECT 1
+                FROM website_keywords xwk
+                JOIN keywords xk ON xk.id = xwk.keyword_id
+                WHERE xwk.website_id = {table}.id AND xk.word = ANY(${}::text[])
+            )
+            AND NOT EXISTS (
+                SELECT 1
+                FROM unnest(${}::text[]) url_term
+                WHERE strpos(lower({table}.url), url_term) = 0
+            )
+            AND NOT EXISTS (
+                SELECT 1
+                FROM unnest(${title}::text[]) title_term
+                WHERE strpos(lower({table}.title), title_term) = 0
+            )
+            AND (${after}::timestamptz IS NULL OR {table}.last_crawled >= ${after})
+            AND (${before}::timestamptz IS NULL OR {table}.last_crawled < ${before})
+            AND (${language}::text IS NULL OR {table}.language = ${language})
+            AND (${url_regex}::text IS NULL OR {table}.url ~ ${url_regex})
+            AND NOT EXISTS (
+                SELECT 1
+                FROM unnest(${required}::text[]) required_term
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM website_keywords rwk
+                    JOIN keywords rk ON rk.id = rwk.keyword_id
+                    WHERE rwk.website_id = {table}.id AND rk.word = required_term
+                )
+            )
+            AND {}"#,
+            site_condition(table, n),
+            n + 1,
+            n + 2,
+            self.tombstones.condition(table),
+            after = n + 3,
+            before = n + 4,
+            language = n + 5,
+            title = n + 6,
+            required = n + 7,
+            url_regex = n + 8
+        )
+    }
+}
+
+/// Fetches every webpage matching any of the keywords and passing `filter`.
 pub async fn fetch_webpages(
     pool: &PgPool,
     keywords: &[String],
-    include_links: bool
-) -> Result<Vec<Webpage>, Box<dyn Error>> {
+    filter: &CandidateFilter
+) -> Result<Vec<Webpage>, AppError> {
     // Return early if no keywords are provided
     if keywords.is_empty() {
         return Ok(vec![]);
     }
 
     // Prepare the SQL query to fetch all necessary data in a single round trip
-    let query =
+    let query = format!(
         r#"
         SELECT 
             w.id as website_id, 
@@ -42,8 +308,9 @@ pub async fn fetch_webpages(
             w.url, 
             w.description, 
             w.word_count, 
+            w.last_crawled, 
+            w.language, 
             k.word, 
-            k.documents_containing_word,
             k.id as keyword_id, 
             wk.keyword_occurrences
         FROM 
@@ -54,11 +321,115 @@ pub async fn fetch_webpages(
             keywords k ON wk.keyword_id = k.id
         WHERE 
             k.word = ANY($1::text[])
-    "#;
+            AND {}
+    "#,
+        filter.condition("w", 2)
+    );
 
     // Execute the query and fetch all rows
-    let rows: Vec<PgRow> = sqlx::query(query).bind(keywords).fetch_all(pool).await?;
+    let rows: Vec<PgRow> = sqlx::query(&query)
+        .bind(keywords)
+        .bind(filter.site.as_deref().map(reverse_domain))
+        .bind(&filter.excluded_terms)
+        .bind(&filter.url_terms)
+        .bind(filter.crawled_after)
+        .bind(filter.crawled_before)
+        .bind(filter.language.as_ref().map(LanguageCode::as_str))
+        .bind(&filter.title_terms)
+        .bind(&filter.required_terms)
+        .bind(filter.url_regex.as_deref())
+        .fetch_all(pool).await?;
+
+    Ok(build_webpages(rows))
+}
+
+/// Fetches at most `limit` webpages matching the keywords, choosing the ones with the highest
+/// summed TF-IDF in SQL so only those candidates' rows are transferred and ranked. Only pages
+/// passing `filter` are candidates, so none of the `limit` is spent on pages that would be
+/// dropped.
+///
+/// Also returns whether the candidate set was complete, i.e. fewer than `limit` pages matched.
+pub async fn fetch_top_webpages(
+    pool: &PgPool,
+    keywords: &[String],
+    document_count: i64,
+    limit: i64,
+    filter: &CandidateFilter
+) -> Result<(Vec<Webpage>, bool), AppError> {
+    // Return early if no keywords are provided
+    if keywords.is_empty() {
+        return Ok((vec![], true));
+    }
+
+    let query = format!(
+        r#"
+        WITH candidates AS (
+            SELECT 
+                wk.website_id,
+                SUM(
+                    (wk.keyword_occurrences::float8 / GREATEST(w.word_count, 1)) *
+                    LN(GREATEST($2::float8 / GREATEST(k.documents_containing_word, 1), 1))
+                ) as pre_score
+            FROM 
+                website_keywords wk
+            JOIN 
+                keywords k ON wk.keyword_id = k.id
+            JOIN 
+                websites w ON w.id = wk.website_id
+            WHERE 
+                k.word = ANY($1::text[])
+                AND {}
+            GROUP BY wk.website_id
+            ORDER BY pre_score DESC, wk.website_id
+            LIMIT $3
+        )
+        SELECT 
+            w.id as website_id, 
+            w.title, 
+            w.url, 
+            w.description, 
+            w.word_count, 
+            w.last_crawled, 
+            w.language, 
+            k.word, 
+            k.id as keyword_id, 
+            wk.keyword_occurrences
+        FROM 
+            candidates c
+        JOIN 
+            websites w ON w.id = c.website_id
+        JOIN 
+            website_keywords wk ON w.id = wk.website_id
+        JOIN 
+            keywords k ON wk.keyword_id = k.id
+        WHERE 
+            k.word = ANY($1::text[])
+    "#,
+        filter.condition("w", 4)
+    );
+
+    let rows: Vec<PgRow> = sqlx::query(&query)
+        .bind(keywords)
+        .bind(document_count)
+        .bind(limit)
+        .bind(filter.site.as_deref().map(reverse_domain))
+        .bind(&filter.excluded_terms)
+        .bind(&filter.url_terms)
+        .bind(filter.crawled_after)
+        .bind(filter.crawled_before)
+        .bind(filter.language.as_ref().map(LanguageCode::as_str))
+        .bind(&filter.title_terms)
+        .bind(&filter.required_terms)
+        .bind(filter.url_regex.as_deref())
+        .fetch_all(pool).await?;
 
+    let webpages = build_webpages(rows);
+    let complete = (webpages.len() as i64) < limit;
+    Ok((webpages, complete))
+}
+
+/// Groups keyword rows into one `Webpage` per website id.
+fn build_webpages(rows: Vec<PgRow>) -> Vec<Webpage> {
     // Use a HashMap to efficiently build Webpage structs
     let mut webpages_map: HashMap<i32, Webpage> = HashMap::new();
 
@@ -70,7 +441,7 @@ pub async fn fetch_webpages(
         let keyword = Keyword {
             id: row.get("keyword_id"),
             word: row.get("word"),
-            documents_containing_word: row.get("documents_containing_word"),
+            documents_containing_word: 0,
         };
 
         // Use entry API for efficient map operations
@@ -80,32 +451,48 @@ pub async fn fetch_webpages(
             url: row.get("url"),
             description: row.get("description"),
             word_count: row.get("word_count"),
-            keywords: HashMap::new(),
+            last_crawled: row.get("last_crawled"),
+            language: row.get("language"),
+            keywords: Vec::new(),
             links_to_count: None,
             links_from: None,
+            spam: None,
+            quality: None,
+            hits: None,
+            verbatim: None,
+            phrase_match: None,
+            proximity_match: None,
+            term_closeness: None,
+            anchor_text: None,
+            verbatim_in_text: None,
+            synonyms: Vec::new(),
+            fuzzy: Vec::new(),
+            link_expansion: Vec::new(),
+            duplicates: Vec::new(),
+            more_from_site: None,
+            field_occurrences: Vec::new(),
+            matched_terms: 0,
+            relevance: 0.0,
+            explanation: None,
         });
 
-        webpage_struct.keywords.insert(keyword, keyword_occurrences);
+        webpage_struct.keywords.push((keyword, keyword_occurrences));
     }
 
-    // Fetch and add link information if requested
-    if include_links {
-        let links = fetch_links(pool).await?;
-        for (webpage_id, links_to_count, links_from) in links {
-            if let Some(webpage) = webpages_map.get_mut(&webpage_id) {
-                webpage.links_to_count = Some(links_to_count);
-                webpage.links_from = Some(links_from);
-            }
-        }
+    // Fix the order of pages and keywords once, so scores and ties come out the same every run
+    let mut webpages: Vec<Webpage> = webpages_map.into_values().collect();
+    webpages.sort_unstable_by_key(|webpage| webpage.id);
+    for webpage in &mut webpages {
+        webpage.keywords.sort_by(|a, b| a.0.word.cmp(&b.0.word));
     }
-
-    Ok(webpages_map.into_values().collect())
+    webpages
 }
 
-pub async fn fetch_links(
-    pool: &PgPool
-) -> Result<Vec<(i32, usize, HashMap<String, i32>)>, Box<dyn Error>> {
-    // Prepare the SQL query to fetch link information
+pub async fn fetch_links_for_ids(
+    pool: &PgPool,
+    webpage_ids: &[i32]
+) -> Result<HashMap<i32, (usize, HashMap<String, i32>)>, AppError> {
+    // Prepare the SQL query to fetch link information for specific webpage IDs
     let query =
         r#"
         SELECT 
@@ -120,11 +507,13 @@ pub async fn fetch_links(
             website_links wl ON wl.target_website = w.url
         LEFT JOIN 
             websites ws ON ws.id = wl.source_website_id
+        WHERE
+            w.id = ANY($1::int[])
         GROUP BY w.id, ws.url
     "#;
 
     // Execute the query and fetch all rows
-    let rows: Vec<PgRow> = sqlx::query(query).fetch_all(pool).await?;
+    let rows: Vec<PgRow> = sqlx::query(query).bind(webpage_ids).fetch_all(pool).await?;
 
     // Use a HashMap to efficiently build link information
     let mut links_map: HashMap<i32, (usize, HashMap<String, i32>)> = HashMap::new();
@@ -143,65 +532,1476 @@ pub async fn fetch_links(
         }
     }
 
-    // Convert the HashMap into the desired Vec format
+    Ok(links_map)
+}
+
+/// Returns how often each of `words` occurs on each of the given webpages, for those that
+/// contain any of them.
+pub async fn fetch_keyword_occurrences(
+    pool: &PgPool,
+    webpage_ids: &[i32],
+    words: &[String]
+) -> Result<HashMap<i32, HashMap<String, i32>>, AppError> {
+    let query =
+        r#"
+        SELECT 
+            wk.website_id, 
+            k.word, 
+            wk.keyword_occurrences
+        FROM 
+            website_keywords wk
+        JOIN 
+            keywords k ON wk.keyword_id = k.id
+        WHERE 
+            wk.website_id = ANY($1::int[])
+            AND k.word = ANY($2::text[])
+    "#;
+
+    let rows: Vec<PgRow> = sqlx::query(query).bind(webpage_ids).bind(words).fetch_all(pool).await?;
+
+    let mut occurrences: HashMap<i32, HashMap<String, i32>> = HashMap::new();
+    for row in rows {
+        let keyword_occurrences: i32 = row.get("keyword_occurrences");
+        *occurrences
+            .entry(row.get("website_id"))
+            .or_default()
+            .entry(row.get("word"))
+            .or_insert(0) += keyword_occurrences;
+    }
+
+    Ok(occurrences)
+}
+
+/// Returns where each of `words` occurs in the text of each of the given webpages, as word
+/// offsets in ascending order, for those the crawler recorded positions for.
+pub async fn fetch_term_positions(
+    pool: &PgPool,
+    webpage_ids: &[i32],
+    words: &[String]
+) -> Result<HashMap<i32, HashMap<String, Vec<i32>>>, AppError> {
+    let query =
+        r#"
+        SELECT 
+            p.website_id, 
+            k.word, 
+            p.positions
+        FROM 
+            website_keyword_positions p
+        JOIN 
+            keywords k ON p.keyword_id = k.id
+        WHERE 
+            p.website_id = ANY($1::int[])
+            AND k.word = ANY($2::text[])
+    "#;
+
+    let rows: Vec<PgRow> = sqlx::query(query).bind(webpage_ids).bind(words).fetch_all(pool).await?;
+
+    let mut positions: HashMap<i32, HashMap<String, Vec<i32>>> = HashMap::new();
+    for row in rows {
+        let mut word_positions: Vec<i32> = row.get("positions");
+        word_positions.sort_unstable();
+        positions.entry(row.get("website_id")).or_default().insert(row.get("word"), word_positions);
+    }
+
+    Ok(positions)
+}
+
+/// Returns the anchor text of the links to each of `urls` from other pages, for the links the
+/// crawler recorded it for. A page's links to itself don't count.
+pub async fn fetch_anchor_texts(
+    pool: &PgPool,
+    urls: &[String]
+) -> Result<HashMap<String, Vec<String>>, AppError> {
+    let query =
+        r#"
+        SELECT 
+            l.target_website, 
+            l.link_text
+        FROM 
+            website_links l
+        JOIN 
+            websites s ON l.source_website_id = s.id
+        WHERE 
+            l.target_website = ANY($1::text[])
+            AND l.link_text IS NOT NULL
+            AND l.link_text <> ''
+            AND s.url <> l.target_website
+    "#;
+
+    let rows: Vec<PgRow> = sqlx::query(query).bind(urls).fetch_all(pool).await?;
+
+    let mut texts: HashMap<String, Vec<String>> = HashMap::new();
+    for row in rows {
+        texts.entry(row.get("target_website")).or_default().push(row.get("link_text"));
+    }
+
+    Ok(texts)
+}
+
+/// Returns the forms each of `words` took in the text of each of the given webpages, with
+/// their case, for those the crawler recorded them for.
+pub async fn fetch_original_forms(
+    pool: &PgPool,
+    webpage_ids: &[i32],
+    words: &[String]
+) -> Result<HashMap<i32, Vec<String>>, AppError> {
+    let query =
+        r#"
+        SELECT 
+            wk.website_id, 
+            wk.original_forms
+        FROM 
+            website_keywords wk
+        JOIN 
+            keywords k ON wk.keyword_id = k.id
+        WHERE 
+            wk.website_id = ANY($1::int[])
+            AND k.word = ANY($2::text[])
+            AND wk.original_forms IS NOT NULL
+    "#;
+
+    let rows: Vec<PgRow> = sqlx::query(query).bind(webpage_ids).bind(words).fetch_all(pool).await?;
+
+    let mut forms: HashMap<i32, Vec<String>> = HashMap::new();
+    for row in rows {
+        let original_forms: Vec<String> = row.get("original_forms");
+        forms.entry(row.get("website_id")).or_default().extend(original_forms);
+    }
+
+    Ok(forms)
+}
+
+/// Returns up to `limit` of each given webpage's most frequent words with their occurrences,
+/// for fingerprinting its content.
+pub async fn fetch_top_words(
+    pool: &PgPool,
+    webpage_ids: &[i32],
+    limit: i64
+) -> Result<HashMap<i32, Vec<(String, i32)>>, AppError> {
+    let query =
+        r#"
+        SELECT 
+            website_id, word, keyword_occurrences
+        FROM (
+            SELECT 
+                wk.website_id, 
+                k.word, 
+                wk.keyword_occurrences, 
+                ROW_NUMBER() OVER (
+                    PARTITION BY wk.website_id 
+                    ORDER BY wk.keyword_occurrences DESC, k.word
+                ) AS position
+            FROM 
+                website_keywords wk
+            JOIN 
+                keywords k ON wk.keyword_id = k.id
+            WHERE 
+                wk.website_id = ANY($1::int[])
+        ) ranked
+        WHERE 
+            position <= $2
+    "#;
+
+    let rows: Vec<PgRow> = sqlx::query(query).bind(webpage_ids).bind(limit).fetch_all(pool).await?;
+
+    let mut words: HashMap<i32, Vec<(String, i32)>> = HashMap::new();
+    for row in rows {
+        words
+            .entry(row.get("website_id"))
+            .or_default()
+            .push((row.get("word"), row.get("keyword_occurrences")));
+    }
+
+    Ok(words)
+}
+
+/// Stores a saved search's parameters, as a JSON object, for `owner`. A search already saved
+/// under `name` is only replaced if `owner` saved it, or with `replace_any`, and keeps its owner.
+/// Returns whether the search was stored.
+pub async fn save_search(
+    pool: &PgPool,
+    name: &str,
+    parameters: &str,
+    owner: &str,
+    replace_any: bool
+) -> Result<bool, AppError> {
+    let query =
+        r#"
+        INSERT INTO saved_searches (name, parameters, owner) 
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name) DO UPDATE 
+        SET parameters = EXCLUDED.parameters, saved_at = now()
+        WHERE saved_searches.owner = EXCLUDED.owner OR $4
+    "#;
+    let result = sqlx::query(query)
+        .bind(name)
+        .bind(parameters)
+        .bind(owner)
+        .bind(replace_any)
+        .execute(pool).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns the parameters saved under `name`, if any.
+pub async fn fetch_saved_search(pool: &PgPool, name: &str) -> Result<Option<String>, AppError> {
+    let query = "SELECT parameters FROM saved_searches WHERE name = $1";
+    let parameters: Option<String> = sqlx::query_scalar(query).bind(name).fetch_optional(pool).await?;
+    Ok(parameters)
+}
+
+/// Appends searches to the query log.
+pub async fn insert_logged_queries(pool: &PgPool, rows: &[LoggedQuery]) -> Result<(), AppError> {
+    let queries: Vec<&str> = rows.iter().map(|row| row.query.as_str()).collect();
+    let ip_hashes: Vec<&str> = rows.iter().map(|row| row.ip_hash.as_str()).collect();
+    let searched_at: Vec<DateTime<Utc>> = rows.iter().map(|row| row.searched_at).collect();
+    let query =
+        r#"
+        INSERT INTO query_log (query, ip_hash, searched_at) 
+        SELECT * FROM UNNEST($1::text[], $2::text[], $3::timestamptz[])
+    "#;
+    sqlx::query(query).bind(queries).bind(ip_hashes).bind(searched_at).execute(pool).await?;
+    Ok(())
+}
+
+/// Returns up to `limit` queries logged since `since` by at least `min_searchers` distinct
+/// searchers, those searched for by the most first.
+pub async fn fetch_trending_queries(
+    pool: &PgPool,
+    since: DateTime<Utc>,
+    min_searchers: i64,
+    limit: i64
+) -> Result<Vec<TrendingQuery>, AppError> {
+    let query =
+        r#"
+        SELECT 
+            query, COUNT(*) AS searches, COUNT(DISTINCT ip_hash) AS searchers
+        FROM 
+            query_log
+        WHERE 
+            searched_at >= $1
+        GROUP BY 
+            query
+        HAVING 
+            COUNT(DISTINCT ip_hash) >= $2
+        ORDER BY 
+            searchers DESC, searches DESC, query
+        LIMIT $3
+    "#;
+    let rows: Vec<PgRow> = sqlx::query(query)
+        .bind(since)
+        .bind(min_searchers)
+        .bind(limit)
+        .fetch_all(pool).await?;
+
     Ok(
-        links_map
-            .into_iter()
-            .map(|(id, (to_count, from))| (id, to_count, from))
+        rows
+            .iter()
+            .map(|row| TrendingQuery {
+                query: row.get("query"),
+                searches: row.get("searches"),
+                searchers: row.get("searchers"),
+            })
             .collect()
     )
 }
 
-pub async fn fetch_links_for_ids(
+/// Deletes searches logged before `before`, returning how many were deleted.
+pub async fn delete_logged_queries_before(
     pool: &PgPool,
-    webpage_ids: &[i32]
-) -> Result<HashMap<i32, (usize, HashMap<String, i32>)>, Box<dyn Error>> {
-    // Prepare the SQL query to fetch link information for specific webpage IDs
+    before: DateTime<Utc>
+) -> Result<u64, AppError> {
+    let query = "DELETE FROM query_log WHERE searched_at < $1";
+    let result = sqlx::query(query).bind(before).execute(pool).await?;
+    Ok(result.rows_affected())
+}
+
+/// Adds impressions and clicks to each page's counts in `result_clicks`, first decaying the
+/// counts already there by how long ago they were last updated, halving every
+/// `half_life_secs`. Each page should appear at most once.
+pub async fn add_result_clicks(
+    pool: &PgPool,
+    website_ids: &[i32],
+    impressions: &[f32],
+    clicks: &[f32],
+    half_life_secs: f64
+) -> Result<(), AppError> {
     let query =
         r#"
+        INSERT INTO result_clicks (website_id, impressions, clicks, updated_at) 
         SELECT 
-            w.id as website_id,
-            COUNT(DISTINCT wt.target_website) as links_to_count,
-            ws.url as source_website
+            website_id, impressions, clicks, now() 
         FROM 
-            websites w
-        LEFT JOIN 
-            website_links wt ON w.id = wt.source_website_id
-        LEFT JOIN 
-            website_links wl ON wl.target_website = w.url
-        LEFT JOIN 
-            websites ws ON ws.id = wl.source_website_id
-        WHERE
-            w.id = ANY($1::int[])
-        GROUP BY w.id, ws.url
+            UNNEST($1::int[], $2::real[], $3::real[]) AS batch (website_id, impressions, clicks)
+        ON CONFLICT (website_id) DO UPDATE 
+        SET 
+            impressions = result_clicks.impressions * power(
+                0.5,
+                EXTRACT(EPOCH FROM now() - result_clicks.updated_at)::float8 / $4
+            ) + EXCLUDED.impressions,
+            clicks = result_clicks.clicks * power(
+                0.5,
+                EXTRACT(EPOCH FROM now() - result_clicks.updated_at)::float8 / $4
+            ) + EXCLUDED.clicks,
+            updated_at = now()
     "#;
+    sqlx::query(query)
+        .bind(website_ids)
+        .bind(impressions)
+        .bind(clicks)
+        .bind(half_life_secs)
+        .execute(pool).await?;
+    Ok(())
+}
 
-    // Execute the query and fetch all rows
-    let rows: Vec<PgRow> = sqlx::query(query).bind(webpage_ids).fetch_all(pool).await?;
+/// Returns the impressions and clicks of every clicked page in `result_clicks`, decayed to now
+/// with the given half-life.
+pub async fn fetch_result_clicks(
+    pool: &PgPool,
+    half_life_secs: f64
+) -> Result<Vec<(i32, f64, f64)>, AppError> {
+    let query =
+        r#"
+        SELECT 
+            website_id, 
+            impressions * power(0.5, EXTRACT(EPOCH FROM now() - updated_at)::float8 / $1) 
+                AS impressions, 
+            clicks * power(0.5, EXTRACT(EPOCH FROM now() - updated_at)::float8 / $1) AS clicks
+        FROM 
+            result_clicks
+        WHERE 
+            clicks > 0
+    "#;
+    let rows: Vec<PgRow> = sqlx::query(query).bind(half_life_secs).fetch_all(pool).await?;
+    Ok(
+        rows
+            .iter()
+            .map(|row| (row.get("website_id"), row.get("impressions"), row.get("clicks")))
+            .collect()
+    )
+}
 
-    // Use a HashMap to efficiently build link information
-    let mut links_map: HashMap<i32, (usize, HashMap<String, i32>)> = HashMap::new();
+/// Returns up to `limit` indexed words, other than `excluded`, that the most of the given
+/// webpages contain, then those occurring most often in them.
+pub async fn fetch_cooccurring_words(
+    pool: &PgPool,
+    webpage_ids: &[i32],
+    excluded: &[String],
+    limit: i64
+) -> Result<Vec<String>, AppError> {
+    let query =
+        r#"
+        SELECT 
+            k.word
+        FROM 
+            website_keywords wk
+        JOIN 
+            keywords k ON wk.keyword_id = k.id
+        WHERE 
+            wk.website_id = ANY($1::int[])
+            AND NOT (k.word = ANY($2::text[]))
+        GROUP BY 
+            k.word
+        ORDER BY 
+            COUNT(DISTINCT wk.website_id) DESC, SUM(wk.keyword_occurrences) DESC, k.word
+        LIMIT $3
+    "#;
 
-    // Process each row and populate the links_map
-    for row in rows {
-        let webpage_id: i32 = row.get("website_id");
-        let links_to_count: i64 = row.get("links_to_count");
-        let source_website: Option<String> = row.get("source_website");
+    let words: Vec<String> = sqlx::query_scalar(query)
+        .bind(webpage_ids)
+        .bind(excluded)
+        .bind(limit)
+        .fetch_all(pool).await?;
+    Ok(words)
+}
 
-        let entry = links_map
-            .entry(webpage_id)
-            .or_insert((links_to_count as usize, HashMap::new()));
-        if let Some(source) = source_website {
-            *entry.1.entry(source).or_insert(0) += 1;
+/// Returns up to `limit` indexed words, other than `excluded`, that the most pages linking to
+/// any of `target_urls` contain, then those occurring most often in them.
+pub async fn fetch_linking_words(
+    pool: &PgPool,
+    target_urls: &[String],
+    excluded: &[String],
+    limit: i64,
+    tombstones: Tombstones
+) -> Result<Vec<String>, AppError> {
+    let query = format!(
+        r#"
+        SELECT 
+            k.word
+        FROM 
+            website_links wl
+        JOIN 
+            websites w ON w.id = wl.source_website_id
+        JOIN 
+            website_keywords wk ON wk.website_id = wl.source_website_id
+        JOIN 
+            keywords k ON wk.keyword_id = k.id
+        WHERE 
+            wl.target_website = ANY($1::text[])
+            AND NOT (k.word = ANY($2::text[]))
+            AND {}
+        GROUP BY 
+            k.word
+        ORDER BY 
+            COUNT(DISTINCT wl.source_website_id) DESC, SUM(wk.keyword_occurrences) DESC, k.word
+        LIMIT $3
+    "#,
+        tombstones.condition("w")
+    );
+
+    let words: Vec<String> = sqlx::query_scalar(&query)
+        .bind(target_urls)
+        .bind(excluded)
+        .bind(limit)
+        .fetch_all(pool).await?;
+    Ok(words)
+}
+
+/// Returns how many documents contain each of `words`, for those in the index.
+pub async fn fetch_document_frequencies(
+    pool: &PgPool,
+    words: &[String]
+) -> Result<HashMap<String, i64>, AppError> {
+    let query = "SELECT word, documents_containing_word FROM keywords WHERE word = ANY($1::text[])";
+    let rows: Vec<PgRow> = sqlx::query(query).bind(words).fetch_all(pool).await?;
+
+    Ok(
+        rows
+            .iter()
+            .map(|row| {
+                let documents: Option<i64> = row.get("documents_containing_word");
+                (row.get("word"), documents.unwrap_or(0))
+            })
+            .collect()
+    )
+}
+
+/// Returns how many documents contain each of the `limit` indexed words in the most documents.
+pub async fn fetch_common_document_frequencies(
+    pool: &PgPool,
+    limit: i64
+) -> Result<HashMap<String, i64>, AppError> {
+    let query =
+        r#"
+        SELECT word, documents_containing_word 
+        FROM keywords 
+        WHERE documents_containing_word > 0
+        ORDER BY documents_containing_word DESC, word
+        LIMIT $1
+    "#;
+    let rows: Vec<PgRow> = sqlx::query(query).bind(limit).fetch_all(pool).await?;
+
+    Ok(
+        rows
+            .iter()
+            .map(|row| (row.get("word"), row.get("documents_containing_word")))
+            .collect()
+    )
+}
+
+/// Returns the `limit` indexed words in the most documents, most first.
+pub async fn fetch_vocabulary(pool: &PgPool, limit: i64) -> Result<Vec<String>, AppError> {
+    let query =
+        r#"
+        SELECT word 
+        FROM keywords 
+        WHERE documents_containing_word > 0
+        ORDER BY documents_containing_word DESC, word
+        LIMIT $1
+    "#;
+    Ok(sqlx::query_scalar(query).bind(limit).fetch_all(pool).await?)
+}
+
+/// Returns up to `limit` indexed words starting with `prefix`, those in the most documents
+/// first. The prefix must be letters and digits only, as nothing in it is escaped.
+pub async fn fetch_words_with_prefix(
+    pool: &PgPool,
+    prefix: &str,
+    limit: i64
+) -> Result<Vec<String>, AppError> {
+    let query =
+        r#"
+        SELECT word 
+        FROM keywords 
+        WHERE word LIKE $1 || '%'
+        ORDER BY documents_containing_word DESC NULLS LAST, word
+        LIMIT $2
+    "#;
+    Ok(sqlx::query_scalar(query).bind(prefix).bind(limit).fetch_all(pool).await?)
+}
+
+/// Checks that the tables and columns the queries rely on exist, so a database that doesn't
+/// match `schema.sql` fails at startup rather than on the first search.
+pub async fn check_schema(pool: &PgPool) -> Result<(), AppError> {
+    let probes = [
+        "SELECT id, title, description, url, word_count, last_crawled, language, host FROM websites LIMIT 0",
+        "SELECT id, word, documents_containing_word FROM keywords LIMIT 0",
+        "SELECT keyword_id, website_id, keyword_occurrences FROM website_keywords LIMIT 0",
+        "SELECT source_website_id, target_website FROM website_links LIMIT 0",
+    ];
+    for probe in probes {
+        sqlx::query(probe).execute(pool).await?;
+    }
+    Ok(())
+}
+
+/// Whether `websites` has the `deleted_at` column the crawler tombstones dead pages with.
+/// Older schemas don't, and keep every page live.
+pub async fn has_deleted_at(pool: &PgPool) -> Result<bool, AppError> {
+    let query =
+        r#"
+        SELECT EXISTS (
+            SELECT 1 
+            FROM information_schema.columns 
+            WHERE 
+                table_schema = current_schema()
+                AND table_name = 'websites'
+                AND column_name = 'deleted_at'
+        )
+    "#;
+    let exists: bool = sqlx::query_scalar(query).fetch_one(pool).await?;
+    Ok(exists)
+}
+
+/// Whether the optional `website_keyword_positions` table exists, so `NEAR/n` can check page
+/// text and not just titles and descriptions.
+pub async fn has_term_positions(pool: &PgPool) -> Result<bool, AppError> {
+    let query =
+        r#"
+        SELECT EXISTS (
+            SELECT 1 
+            FROM information_schema.tables 
+            WHERE 
+                table_schema = current_schema()
+                AND table_name = 'website_keyword_positions'
+        )
+    "#;
+    let exists: bool = sqlx::query_scalar(query).fetch_one(pool).await?;
+    Ok(exists)
+}
+
+/// Whether `website_links` has the optional `link_text` column, so the anchor text of links
+/// to a page can rank it.
+pub async fn has_link_text(pool: &PgPool) -> Result<bool, AppError> {
+    let query =
+        r#"
+        SELECT EXISTS (
+            SELECT 1 
+            FROM information_schema.columns 
+            WHERE 
+                table_schema = current_schema()
+                AND table_name = 'website_links'
+                AND column_name = 'link_text'
+        )
+    "#;
+    let exists: bool = sqlx::query_scalar(query).fetch_one(pool).await?;
+    Ok(exists)
+}
+
+/// Whether `website_keywords` has the optional `original_forms` column, so case-sensitive
+/// searches can check page text and not just titles and descriptions.
+pub async fn has_original_forms(pool: &PgPool) -> Result<bool, AppError> {
+    let query =
+        r#"
+        SELECT EXISTS (
+            SELECT 1 
+            FROM information_schema.columns 
+            WHERE 
+                table_schema = current_schema()
+                AND table_name = 'website_keywords'
+                AND column_name = 'original_forms'
+        )
+    "#;
+    let exists: bool = sqlx::query_scalar(query).fetch_one(pool).await?;
+    Ok(exists)
+}
+
+/// Whether a query leaves out pages the crawler has tombstoned with `deleted_at`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tombstones {
+    /// Only live pages are returned
+    #[default]
+    Exclude,
+    /// Tombstoned pages are returned too, on an admin's request or because the schema has no
+    /// `deleted_at` column
+    Include,
+}
+
+impl Tombstones {
+    /// A condition on the websites table aliased `table` that holds for the rows to return.
+    fn condition(self, table: &str) -> String {
+        match self {
+            Tombstones::Exclude => format!("{}.deleted_at IS NULL", table),
+            Tombstones::Include => "TRUE".to_string(),
         }
     }
+}
 
-    Ok(links_map)
+/// Returns the average word count of the pages `tombstones` lets through, for BM25's length
+/// normalisation, or 1 for an empty index.
+pub async fn average_word_count(pool: &PgPool, tombstones: Tombstones) -> Result<f64, AppError> {
+    let query = format!(
+        "SELECT COALESCE(AVG(GREATEST(w.word_count, 1)), 1)::float8 FROM websites w WHERE {}",
+        tombstones.condition("w")
+    );
+    let average: f64 = sqlx::query_scalar(&query).fetch_one(pool).await?;
+    Ok(average)
+}
+
+/// A cheap summary of the index that changes whenever the crawler adds, recrawls or tombstones
+/// pages, or adds or removes links, so the server can tell when the index has changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexFingerprint {
+    /// The pages `tombstones` lets through, leaving out tombstoned ones unless told otherwise so
+    /// IDF reflects the pages that can actually be returned
+    pub live_pages: i64,
+    max_page_id: Option<i32>,
+    last_crawled: Option<DateTime<Utc>>,
+    last_deleted: Option<DateTime<Utc>>,
+    links: i64,
+    max_link_id: Option<i32>,
+}
+
+/// Reads the index's fingerprint, counting the pages `tombstones` lets through as live.
+pub async fn fetch_index_fingerprint(
+    pool: &PgPool,
+    tombstones: Tombstones
+) -> Result<IndexFingerprint, AppError> {
+    let last_deleted = match tombstones {
+        Tombstones::Exclude => "(SELECT MAX(deleted_at) FROM websites)",
+        Tombstones::Include => "NULL::timestamptz",
+    };
+    let query = format!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM websites w WHERE {}) as live_pages,
+            (SELECT MAX(id) FROM websites) as max_page_id,
+            (SELECT MAX(last_crawled) FROM websites) as last_crawled,
+            {} as last_deleted,
+            (SELECT COUNT(*) FROM website_links) as links,
+            (SELECT MAX(id) FROM website_links) as max_link_id
+    "#,
+        tombstones.condition("w"),
+        last_deleted
+    );
+    let row = sqlx::query(&query).fetch_one(pool).await?;
+    Ok(IndexFingerprint {
+        live_pages: row.get("live_pages"),
+        max_page_id: row.get("max_page_id"),
+        last_crawled: row.get("last_crawled"),
+        last_deleted: row.get("last_deleted"),
+        links: row.get("links"),
+        max_link_id: row.get("max_link_id"),
+    })
+}
+
+/// Which side of a link to rank pages by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkDirection {
+    Inbound,
+    Outbound,
+}
+
+/// Returns the total number of pages with links in `direction`, and one page of
+/// `(url, title, link_count)` rows ordered by link count.
+///
+/// Counts are aggregated straight off `website_links` (using the source and target indexes)
+/// before joining `websites`, so only the requested page of rows is ever joined.
+pub async fn fetch_top_linked(
+    pool: &PgPool,
+    direction: LinkDirection,
+    limit: i64,
+    offset: i64
+) -> Result<(i64, Vec<(String, Option<String>, i64)>), AppError> {
+    let (count_query, query) = match direction {
+        LinkDirection::Inbound =>
+            (
+                "SELECT COUNT(DISTINCT target_website) FROM website_links",
+                r#"
+                SELECT 
+                    t.url,
+                    w.title,
+                    t.link_count
+                FROM (
+                    SELECT target_website as url, COUNT(*) as link_count
+                    FROM website_links
+                    GROUP BY target_website
+                    ORDER BY link_count DESC, target_website
+                    LIMIT $1 OFFSET $2
+                ) t
+                LEFT JOIN 
+                    websites w ON w.url = t.url
+                ORDER BY t.link_count DESC, t.url
+            "#,
+            ),
+        LinkDirection::Outbound =>
+            (
+                "SELECT COUNT(DISTINCT source_website_id) FROM website_links",
+                r#"
+                SELECT 
+                    w.url,
+                    w.title,
+                    t.link_count
+                FROM (
+                    SELECT source_website_id, COUNT(*) as link_count
+                    FROM website_links
+                    GROUP BY source_website_id
+                    ORDER BY link_count DESC, source_website_id
+                    LIMIT $1 OFFSET $2
+                ) t
+                JOIN 
+                    websites w ON w.id = t.source_website_id
+                ORDER BY t.link_count DESC, t.source_website_id
+            "#,
+            ),
+    };
+
+    let total: i64 = sqlx::query_scalar(count_query).fetch_one(pool).await?;
+    let rows: Vec<PgRow> = sqlx::query(query).bind(limit).bind(offset).fetch_all(pool).await?;
+
+    let pages = rows
+        .iter()
+        .map(|row| (row.get("url"), row.get("title"), row.get("link_count")))
+        .collect();
+
+    Ok((total, pages))
+}
+
+/// Returns the id and title of the indexed webpage with exactly this URL.
+pub async fn find_webpage_by_url(
+    pool: &PgPool,
+    url: &str
+) -> Result<Option<(i32, String)>, AppError> {
+    let query = "SELECT id, title FROM websites WHERE url = $1";
+    let row: Option<PgRow> = sqlx::query(query).bind(url).fetch_optional(pool).await?;
+    Ok(row.map(|row| (row.get("id"), row.get("title"))))
 }
 
-pub async fn count_websites(pool: &PgPool) -> Result<i64, Box<dyn Error>> {
-    // Execute a simple COUNT query to get the total number of websites
-    let query = "SELECT COUNT(*) FROM websites";
-    let count: i64 = sqlx::query_scalar(query).fetch_one(pool).await?;
+pub async fn count_outbound_links(pool: &PgPool, webpage_id: i32) -> Result<i64, AppError> {
+    let query = "SELECT COUNT(*) FROM website_links WHERE source_website_id = $1";
+    let count: i64 = sqlx::query_scalar(query).bind(webpage_id).fetch_one(pool).await?;
     Ok(count)
 }
+
+/// Returns the number of distinct pages linking to `url`, and one page of those sources with
+/// how many times each links to it.
+pub async fn fetch_inbound_links(
+    pool: &PgPool,
+    url: &str,
+    limit: i64,
+    offset: i64
+) -> Result<(i64, Vec<(String, i64)>), AppError> {
+    let count_query =
+        "SELECT COUNT(DISTINCT source_website_id) FROM website_links WHERE target_website = $1";
+    let total: i64 = sqlx::query_scalar(count_query).bind(url).fetch_one(pool).await?;
+
+    let query =
+        r#"
+        SELECT 
+            ws.url as source_website,
+            COUNT(*) as occurrences
+        FROM 
+            website_links wl
+        JOIN 
+            websites ws ON ws.id = wl.source_website_id
+        WHERE 
+            wl.target_website = $1
+        GROUP BY ws.url
+        ORDER BY occurrences DESC, ws.url
+        LIMIT $2 OFFSET $3
+    "#;
+
+    let rows: Vec<PgRow> = sqlx::query(query)
+        .bind(url)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool).await?;
+
+    let sources = rows
+        .iter()
+        .map(|row| (row.get("source_website"), row.get("occurrences")))
+        .collect();
+
+    Ok((total, sources))
+}
+
+/// Returns which of the given URLs appear anywhere in the link graph, as pages or link targets.
+pub async fn find_known_urls(pool: &PgPool, urls: &[String]) -> Result<Vec<String>, AppError> {
+    let query =
+        r#"
+        SELECT url FROM websites WHERE url = ANY($1::text[])
+        UNION
+        SELECT target_website FROM website_links WHERE target_website = ANY($1::text[])
+    "#;
+    let known: Vec<String> = sqlx::query_scalar(query).bind(urls).fetch_all(pool).await?;
+    Ok(known)
+}
+
+/// An indexed URL as listed by the URL export
+#[derive(Debug, Clone)]
+pub struct IndexedUrl {
+    pub id: i32,
+    pub url: String,
+    pub last_crawled: Option<DateTime<Utc>>,
+    pub word_count: i32,
+}
+
+/// Which indexed URLs to export
+#[derive(Debug, Clone, Default)]
+pub struct UrlExportFilter {
+    /// Only pages crawled at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only pages on this host or its subdomains
+    pub domain: Option<String>,
+    /// Only pages with an id greater than this, for resuming an interrupted export
+    pub after_id: i32,
+    pub tombstones: Tombstones,
+}
+
+/// Builds the query that lists the indexed URLs matching `filter`, for `stream_indexed_urls`.
+/// The caller holds on to it, since the stream borrows it.
+pub fn indexed_urls_query(filter: &UrlExportFilter) -> String {
+    format!(
+        r#"
+        SELECT 
+            id, 
+            url, 
+            last_crawled, 
+            word_count
+        FROM 
+            websites w
+        WHERE 
+            id > $1
+            AND ($2::timestamptz IS NULL OR last_crawled >= $2)
+            AND ($3::text IS NULL OR url ~* $3)
+            AND {}
+        ORDER BY id
+    "#,
+        filter.tombstones.condition("w")
+    )
+}
+
+/// Streams every indexed URL matching `filter` in id order, without holding the result set in
+/// memory. `query` comes from `indexed_urls_query`.
+pub fn stream_indexed_urls<'a>(
+    pool: &'a PgPool,
+    filter: &'a UrlExportFilter,
+    query: &'a str
+) -> impl Stream<Item = Result<IndexedUrl, sqlx::Error>> + 'a {
+    let domain_pattern = filter.domain.as_deref().map(domain_pattern);
+
+    sqlx::query(query)
+        .bind(filter.after_id)
+        .bind(filter.since)
+        .bind(domain_pattern)
+        .fetch(pool)
+        .map(|row| {
+            row.map(|row| IndexedUrl {
+                id: row.get("id"),
+                url: row.get("url"),
+                last_crawled: row.get("last_crawled"),
+                word_count: row.get("word_count"),
+            })
+        })
+}
+
+/// Builds a case-insensitive Postgres regex matching URLs on `domain` or any of its subdomains.
+fn domain_pattern(domain: &str) -> String {
+    format!("^https?://([^/?#]*\\.)?{}([:/?#]|$)", regex::escape(domain))
+}
+
+/// Which pages random sampling may return
+#[derive(Debug, Clone, Default)]
+pub struct SampleFilter {
+    /// Only pages on this host or its subdomains
+    pub domain: Option<String>,
+    pub min_word_count: Option<i32>,
+    pub tombstones: Tombstones,
+}
+
+/// How many times the table sample is widened before falling back to id probing
+const SAMPLE_ATTEMPTS: usize = 3;
+
+/// How many random-id probes are made when sampling comes back short
+const PROBE_ATTEMPTS: usize = 3;
+
+/// Returns up to `count` random webpages matching `filter`, without keyword or link information.
+///
+/// Samples a small fraction of the table's blocks with `TABLESAMPLE SYSTEM`, widening the sample
+/// if the filters are selective, then probes from random ids if that still comes back short. The
+/// number of queries is bounded, so filters that match nothing yield an empty result rather than
+/// endless retries.
+pub async fn sample_webpages(
+    pool: &PgPool,
+    count: i64,
+    website_count: i64,
+    filter: &SampleFilter
+) -> Result<Vec<Webpage>, AppError> {
+    let sample_query = format!(
+        r#"
+        SELECT 
+            id, 
+            title, 
+            url, 
+            description, 
+            word_count, 
+            last_crawled, 
+            language
+        FROM 
+            websites w TABLESAMPLE SYSTEM ($1::real)
+        WHERE 
+            ($2::text IS NULL OR url ~* $2)
+            AND ($3::int IS NULL OR word_count >= $3)
+            AND {}
+        ORDER BY random()
+        LIMIT $4
+    "#,
+        filter.tombstones.condition("w")
+    );
+    let probe_query = format!(
+        r#"
+        SELECT 
+            id, 
+            title, 
+            url, 
+            description, 
+            word_count, 
+            last_crawled, 
+            language
+        FROM 
+            websites w
+        WHERE 
+            id >= (
+                SELECT floor(random() * (MAX(id) - MIN(id) + 1))::int + MIN(id) FROM websites
+            )
+            AND ($1::text IS NULL OR url ~* $1)
+            AND ($2::int IS NULL OR word_count >= $2)
+            AND {}
+        ORDER BY id
+        LIMIT $3
+    "#,
+        filter.tombstones.condition("w")
+    );
+
+    let domain_pattern = filter.domain.as_deref().map(domain_pattern);
+    let wanted = count.max(0) as usize;
+    let mut sampled: HashMap<i32, Webpage> = HashMap::new();
+
+    let mut sampled_whole_table = false;
+    for percent in sample_percents(count, website_count) {
+        let rows: Vec<PgRow> = sqlx::query(&sample_query)
+            .bind(percent as f32)
+            .bind(&domain_pattern)
+            .bind(filter.min_word_count)
+            .bind(count)
+            .fetch_all(pool).await?;
+        collect_sampled(&mut sampled, rows, wanted);
+        sampled_whole_table = percent >= 100.0;
+
+        if sampled.len() >= wanted {
+            break;
+        }
+    }
+
+    // A full-table sample already saw every matching page, so probing can't find any more
+    if !sampled_whole_table {
+        for _ in 0..PROBE_ATTEMPTS {
+            if sampled.len() >= wanted {
+                break;
+            }
+            let rows: Vec<PgRow> = sqlx::query(&probe_query)
+                .bind(&domain_pattern)
+                .bind(filter.min_word_count)
+                .bind(count)
+                .fetch_all(pool).await?;
+            collect_sampled(&mut sampled, rows, wanted);
+        }
+    }
+
+    Ok(sampled.into_values().collect())
+}
+
+/// The percentage of the table each sampling attempt covers, at most `SAMPLE_ATTEMPTS` of them:
+/// enough for a few times `count` rows at first, then ten times more until it's the whole table.
+fn sample_percents(count: i64, website_count: i64) -> Vec<f64> {
+    let mut percent = (((count as f64) * 4.0) / (website_count.max(1) as f64)) * 100.0;
+    percent = percent.clamp(0.01, 100.0);
+    let mut percents = vec![];
+    while percents.len() < SAMPLE_ATTEMPTS {
+        percents.push(percent);
+        if percent >= 100.0 {
+            break;
+        }
+        percent = (percent * 10.0).min(100.0);
+    }
+    percents
+}
+
+/// Adds sampled rows to `sampled`, skipping pages already picked, until it holds `wanted` pages.
+fn collect_sampled(sampled: &mut HashMap<i32, Webpage>, rows: Vec<PgRow>, wanted: usize) {
+    for row in rows {
+        if sampled.len() >= wanted {
+            break;
+        }
+        let id: i32 = row.get("id");
+        sampled.entry(id).or_insert_with(|| metadata_only_webpage(&row));
+    }
+}
+
+/// A webpage from a row of its `websites` columns, without keyword or link information.
+fn metadata_only_webpage(row: &PgRow) -> Webpage {
+    Webpage {
+        id: row.get("id"),
+        title: row.get("title"),
+        url: row.get("url"),
+        description: row.get("description"),
+        word_count: row.get("word_count"),
+        last_crawled: row.get("last_crawled"),
+        language: row.get("language"),
+        keywords: Vec::new(),
+        links_to_count: None,
+        links_from: None,
+        spam: None,
+        quality: None,
+        hits: None,
+        verbatim: None,
+        phrase_match: None,
+        proximity_match: None,
+        term_closeness: None,
+        anchor_text: None,
+        verbatim_in_text: None,
+        synonyms: Vec::new(),
+        fuzzy: Vec::new(),
+        link_expansion: Vec::new(),
+        duplicates: Vec::new(),
+        more_from_site: None,
+        field_occurrences: Vec::new(),
+        matched_terms: 0,
+        relevance: 0.0,
+        explanation: None,
+    }
+}
+
+/// Returns up to `limit` webpages, most recently crawled first, without keyword or link
+/// information. Pages that were never crawled are left out.
+pub async fn fetch_recent_webpages(
+    pool: &PgPool,
+    limit: i64,
+    tombstones: Tombstones
+) -> Result<Vec<Webpage>, AppError> {
+    let query = format!(
+        r#"
+        SELECT 
+            id, 
+            title, 
+            url, 
+            description, 
+            word_count, 
+            last_crawled, 
+            language
+        FROM 
+            websites w
+        WHERE 
+            last_crawled IS NOT NULL
+            AND {}
+        ORDER BY last_crawled DESC, id
+        LIMIT $1
+    "#,
+        tombstones.condition("w")
+    );
+
+    let rows: Vec<PgRow> = sqlx::query(&query).bind(limit).fetch_all(pool).await?;
+    Ok(rows.iter().map(metadata_only_webpage).collect())
+}
+
+/// Whether the schema has the optional `website_pagerank` table, so authority scores outlast a
+/// restart.
+pub async fn has_stored_pagerank(pool: &PgPool) -> Result<bool, AppError> {
+    let query =
+        r#"
+        SELECT EXISTS (
+            SELECT 1 
+            FROM information_schema.tables 
+            WHERE 
+                table_schema = current_schema()
+                AND table_name = 'website_pagerank'
+        )
+    "#;
+    let exists: bool = sqlx::query_scalar(query).fetch_one(pool).await?;
+    Ok(exists)
+}
+
+/// Returns the stored PageRank scores as parallel id and score lists, in ascending id order.
+pub async fn fetch_pagerank_scores(pool: &PgPool) -> Result<(Vec<i32>, Vec<f32>), AppError> {
+    let query = "SELECT website_id, score FROM website_pagerank ORDER BY website_id";
+    let rows: Vec<(i32, f32)> = sqlx::query_as(query).fetch_all(pool).await?;
+    Ok(rows.into_iter().unzip())
+}
+
+/// Replaces the stored PageRank scores with `scores`, the score of each page in `ids`, inserted
+/// `batch_size` at a time in one transaction, so readers never see a partial set.
+pub async fn store_pagerank_scores(
+    pool: &PgPool,
+    ids: &[i32],
+    scores: &[f32],
+    batch_size: usize
+) -> Result<(), AppError> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query("DELETE FROM website_pagerank").execute(&mut *transaction).await?;
+    let query =
+        r#"
+        INSERT INTO website_pagerank (website_id, score) 
+        SELECT * FROM UNNEST($1::int[], $2::real[])
+    "#;
+    for (ids, scores) in ids.chunks(batch_size.max(1)).zip(scores.chunks(batch_size.max(1))) {
+        sqlx::query(query).bind(ids).bind(scores).execute(&mut *transaction).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Returns the ids of every indexed webpage, in ascending order.
+pub async fn fetch_website_ids(pool: &PgPool) -> Result<Vec<i32>, AppError> {
+    let query = "SELECT id FROM websites ORDER BY id";
+    let ids: Vec<i32> = sqlx::query_scalar(query).fetch_all(pool).await?;
+    Ok(ids)
+}
+
+/// Returns the next batch of links after link id `after_id`, as
+/// `(link_id, source_website_id, target_website_id)`. The target id is `None` when the target
+/// URL isn't indexed. An empty batch means every link has been read.
+pub async fn fetch_link_edges(
+    pool: &PgPool,
+    after_id: i32,
+    limit: i64
+) -> Result<Vec<(i32, i32, Option<i32>)>, AppError> {
+    // Limit before joining, so batches advance through website_links even when few targets
+    // are indexed
+    let query =
+        r#"
+        SELECT 
+            b.id, 
+            b.source_website_id, 
+            w.id as target_website_id
+        FROM (
+            SELECT id, source_website_id, target_website
+            FROM website_links
+            WHERE id > $1
+            ORDER BY id
+            LIMIT $2
+        ) b
+        LEFT JOIN 
+            websites w ON w.url = b.target_website
+        ORDER BY b.id
+    "#;
+
+    let rows: Vec<PgRow> = sqlx::query(query).bind(after_id).bind(limit).fetch_all(pool).await?;
+
+    let edges = rows
+        .iter()
+        .map(|row| (row.get("id"), row.get("source_website_id"), row.get("target_website_id")))
+        .collect();
+
+    Ok(edges)
+}
+
+/// SQL matching the pages of `table` on the domain bound as parameter `$n`, reversed with
+/// `reverse_domain`, or on any of its subdomains. A NULL domain matches every page.
+fn site_condition(table: &str, n: usize) -> String {
+    format!(
+        r#"(
+            ${n}::text IS NULL
+            OR reverse({table}.host) COLLATE "C" = ${n}
+            OR (
+                reverse({table}.host) COLLATE "C" >= ${n} || '.'
+                AND reverse({table}.host) COLLATE "C" < ${n} || '/'
+            )
+        )"#
+    )
+}
+
+/// A domain reversed to match the reversed-host index.
+fn reverse_domain(domain: &str) -> String {
+    domain.chars().rev().collect()
+}
+
+/// How to order a site's pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SiteSort {
+    /// Most recently crawled first
+    Recent,
+    /// Most linked-to first
+    Links,
+    /// Longest first
+    Words,
+}
+
+/// An indexed page as listed for its site
+#[derive(Debug, Clone)]
+pub struct SitePage {
+    pub title: String,
+    pub url: String,
+    pub description: String,
+    pub word_count: i32,
+    pub last_crawled: Option<DateTime<Utc>>,
+    pub inbound_links: i64,
+}
+
+/// Returns the number of indexed pages on `domain` or any of its subdomains, and one page of
+/// them in `sort` order.
+///
+/// Hosts are matched through the reversed-host index: `example.com` and `*.example.com`
+/// reverse to `moc.elpmaxe` and the range just after `moc.elpmaxe.`.
+pub async fn fetch_site_pages(
+    pool: &PgPool,
+    domain: &str,
+    sort: SiteSort,
+    limit: i64,
+    offset: i64,
+    tombstones: Tombstones
+) -> Result<(i64, Vec<SitePage>), AppError> {
+    let live = tombstones.condition("w");
+    let count_query = format!(
+        r#"
+        SELECT COUNT(*) 
+        FROM websites w
+        WHERE 
+            (
+                reverse(host) COLLATE "C" = $1
+                OR (reverse(host) COLLATE "C" >= $1 || '.' AND reverse(host) COLLATE "C" < $1 || '/')
+            )
+            AND {}
+    "#,
+        live
+    );
+
+    // Link counts are needed for every page of the site to sort by them, but otherwise only
+    // for the requested page
+    let query = match sort {
+        SiteSort::Links =>
+            format!(
+                r#"
+            SELECT 
+                w.title, w.url, w.description, w.word_count, w.last_crawled, l.inbound_links
+            FROM 
+                websites w
+            CROSS JOIN LATERAL (
+                SELECT COUNT(*) as inbound_links FROM website_links WHERE target_website = w.url
+            ) l
+            WHERE 
+                (
+                    reverse(w.host) COLLATE "C" = $1
+                    OR (reverse(w.host) COLLATE "C" >= $1 || '.' AND reverse(w.host) COLLATE "C" < $1 || '/')
+                )
+                AND {}
+            ORDER BY l.inbound_links DESC, w.id
+            LIMIT $2 OFFSET $3
+        "#,
+                live
+            ),
+        SiteSort::Recent =>
+            format!(
+                r#"
+            SELECT 
+                s.title, s.url, s.description, s.word_count, s.last_crawled, l.inbound_links
+            FROM (
+                SELECT id, title, url, description, word_count, last_crawled
+                FROM websites w
+                WHERE 
+                    (
+                        reverse(host) COLLATE "C" = $1
+                        OR (reverse(host) COLLATE "C" >= $1 || '.' AND reverse(host) COLLATE "C" < $1 || '/')
+                    )
+                    AND {}
+                ORDER BY last_crawled DESC NULLS LAST, id
+                LIMIT $2 OFFSET $3
+            ) s
+            CROSS JOIN LATERAL (
+                SELECT COUNT(*) as inbound_links FROM website_links WHERE target_website = s.url
+            ) l
+            ORDER BY s.last_crawled DESC NULLS LAST, s.id
+        "#,
+                live
+            ),
+        SiteSort::Words =>
+            format!(
+                r#"
+            SELECT 
+                s.title, s.url, s.description, s.word_count, s.last_crawled, l.inbound_links
+            FROM (
+                SELECT id, title, url, description, word_count, last_crawled
+                FROM websites w
+                WHERE 
+                    (
+                        reverse(host) COLLATE "C" = $1
+                        OR (reverse(host) COLLATE "C" >= $1 || '.' AND reverse(host) COLLATE "C" < $1 || '/')
+                    )
+                    AND {}
+                ORDER BY word_count DESC, id
+                LIMIT $2 OFFSET $3
+            ) s
+            CROSS JOIN LATERAL (
+                SELECT COUNT(*) as inbound_links FROM website_links WHERE target_website = s.url
+            ) l
+            ORDER BY s.word_count DESC, s.id
+        "#,
+                live
+            ),
+    };
+
+    let reversed = reverse_domain(domain);
+    let total: i64 = sqlx::query_scalar(&count_query).bind(&reversed).fetch_one(pool).await?;
+    if total == 0 {
+        return Ok((0, vec![]));
+    }
+
+    let rows: Vec<PgRow> = sqlx::query(&query)
+        .bind(&reversed)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool).await?;
+
+    let pages = rows
+        .iter()
+        .map(|row| SitePage {
+            title: row.get("title"),
+            url: row.get("url"),
+            description: row.get("description"),
+            word_count: row.get("word_count"),
+            last_crawled: row.get("last_crawled"),
+            inbound_links: row.get("inbound_links"),
+        })
+        .collect();
+
+    Ok((total, pages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::error::{ DatabaseError, ErrorKind };
+    use std::borrow::Cow;
+
+    /// A Postgres error with a SQLSTATE and maybe a constraint, as sqlx reports them
+    #[derive(Debug)]
+    struct PostgresError {
+        code: &'static str,
+        constraint: Option<&'static str>,
+    }
+
+    impl fmt::Display for PostgresError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "postgres said no")
+        }
+    }
+
+    impl std::error::Error for PostgresError {}
+
+    impl DatabaseError for PostgresError {
+        fn message(&self) -> &str {
+            "postgres said no"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.code))
+        }
+
+        fn constraint(&self) -> Option<&str> {
+            self.constraint
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    fn postgres_error(code: &'static str) -> AppError {
+        AppError::from(sqlx::Error::Database(Box::new(PostgresError { code, constraint: None })))
+    }
+
+    #[test]
+    fn classifies_sqlstates() {
+        for code in ["57014", "55P03"] {
+            assert!(matches!(postgres_error(code), AppError::Timeout(_)), "{}", code);
+        }
+        for code in ["57P01", "57P02", "57P03", "40001", "40P01", "08006", "08001", "53300"] {
+            assert!(matches!(postgres_error(code), AppError::Unavailable(_)), "{}", code);
+        }
+        for code in ["42P01", "42703", "42883", "42704", "42804"] {
+            assert!(matches!(postgres_error(code), AppError::SchemaMismatch(_)), "{}", code);
+        }
+        for code in ["23505", "22P02", "42601"] {
+            assert!(matches!(postgres_error(code), AppError::Internal(_)), "{}", code);
+        }
+    }
+
+    #[test]
+    fn classifies_driver_errors() {
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        assert!(matches!(AppError::from(sqlx::Error::Io(io)), AppError::Unavailable(_)));
+        assert!(matches!(AppError::from(sqlx::Error::PoolTimedOut), AppError::Unavailable(_)));
+        assert!(matches!(AppError::from(sqlx::Error::PoolClosed), AppError::Unavailable(_)));
+        let missing = sqlx::Error::ColumnNotFound("host".to_string());
+        assert!(matches!(AppError::from(missing), AppError::SchemaMismatch(_)));
+        assert!(matches!(AppError::from(sqlx::Error::RowNotFound), AppError::Internal(_)));
+    }
+
+    #[test]
+    fn statuses_and_retryability_follow_the_class() {
+        let cases = [
+            (postgres_error("08006"), StatusCode::SERVICE_UNAVAILABLE, true),
+            (postgres_error("57014"), StatusCode::GATEWAY_TIMEOUT, true),
+            (postgres_error("42703"), StatusCode::INTERNAL_SERVER_ERROR, false),
+            (postgres_error("23505"), StatusCode::INTERNAL_SERVER_ERROR, false),
+        ];
+        for (error, status, retryable) in cases {
+            assert_eq!(error.status(), status, "{}", error);
+            assert_eq!(error.is_retryable(), retryable, "{}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_operator_detail_out_of_responses() {
+        let constraint = Some("websites_url_key");
+        let error = sqlx::Error::Database(Box::new(PostgresError { code: "23505", constraint }));
+        let error = AppError::from(error);
+        // The log line names the SQLSTATE and the constraint
+        assert_eq!(
+            error.to_string(),
+            "database error: SQLSTATE 23505 (websites_url_key): error returned from database: \
+             postgres said no"
+        );
+
+        let response = error.response("Failed to save");
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({ "error": "Failed to save", "retryable": false }));
+    }
+
+    #[test]
+    fn widens_random_samples_a_bounded_number_of_times() {
+        // A big table starts small and grows tenfold each time, but only so often
+        assert_eq!(sample_percents(5, 2_000_000), [0.01, 0.1, 1.0]);
+        assert_eq!(sample_percents(50, 100_000), [0.2, 2.0, 20.0]);
+        // Once the whole table has been sampled there's nothing more to find
+        assert_eq!(sample_percents(5, 1_000), [2.0, 20.0, 100.0]);
+        assert_eq!(sample_percents(5, 100), [20.0, 100.0]);
+        assert_eq!(sample_percents(5, 10), [100.0]);
+        assert_eq!(sample_percents(5, 0), [100.0]);
+        for (count, website_count) in [(1, i64::MAX), (50, 1), (i64::MAX, 1_000)] {
+            assert!(sample_percents(count, website_count).len() <= SAMPLE_ATTEMPTS);
+        }
+    }
+}
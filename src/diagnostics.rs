@@ -0,0 +1,141 @@
+use serde_json::{ Value, json };
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::idf;
+use crate::ranking::MatchMode;
+use crate::search::SearchOptions;
+use crate::state::AppState;
+use crate::trace::{ CacheOutcome, PipelineTrace };
+
+/// Longest the document frequency lookup may hold up an empty response
+const LOOKUP_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Explains why a search returned nothing: whether each query term is in the index at all,
+/// how many candidates each stage of the pipeline eliminated, and what to try next.
+///
+/// Elimination counts are only reported when the pipeline ran for this request, since a cached
+/// result carries no trace.
+pub async fn no_results(
+    state: &AppState,
+    keywords: &[String],
+    trace: &PipelineTrace,
+    options: SearchOptions
+) -> Value {
+    let mut terms: Vec<String> = keywords.to_vec();
+    terms.sort();
+    terms.dedup();
+
+    // Diagnostics are best effort: without frequencies, terms are reported as unknown. No term
+    // is in an empty index, so there's nothing to look up.
+    let website_count = state.index.load().website_count;
+    let frequencies = if website_count == 0 {
+        Some(HashMap::new())
+    } else {
+        tokio::time
+            ::timeout(LOOKUP_TIMEOUT, idf::lookup(state, &terms)).await
+            .ok()
+            .and_then(Result::ok)
+    };
+    let unindexed: Vec<&String> = match &frequencies {
+        Some(frequencies) => terms
+            .iter()
+            .filter(|term| frequencies.get(*term).is_none_or(|&documents| documents == 0))
+            .collect(),
+        None => vec![],
+    };
+
+    let ran = trace.cache == CacheOutcome::Miss;
+    let suggestion = if website_count == 0 {
+        json!({ "action": "wait_for_index", "message": "The index has no pages yet" })
+    } else if terms.is_empty() {
+        json!({ "action": "rephrase", "message": "The query has no searchable words" })
+    } else if !unindexed.is_empty() && options.exact {
+        json!({
+            "action": "disable_verbatim",
+            "terms": unindexed,
+            "message": "These terms aren't indexed as typed; without verbatim, their lemmas are looked up",
+        })
+    } else if !unindexed.is_empty() {
+        json!({
+            "action": "drop_terms",
+            "terms": unindexed,
+            "message": "These terms aren't in the index, so no page can match them",
+        })
+    } else if
+        ran &&
+        (trace.off_site > 0 ||
+            trace.not_in_field > 0 ||
+            (trace.filtered_in_fetch && trace.candidates_fetched == 0))
+    {
+        json!({
+            "action": "drop_filters",
+            "message": "Pages matched the terms, but the query's filters or exclusions removed them",
+        })
+    } else if ran && options.mode == MatchMode::Strict && trace.candidates_fetched == 0 {
+        json!({
+            "action": "loose_mode",
+            "message": "Every term is indexed, but no page contains all of them; mode=loose also considers pages with some",
+        })
+    } else if ran && options.case_sensitive && trace.wrong_case > 0 {
+        json!({
+            "action": "disable_case_sensitive",
+            "message": "Pages matched the terms, but not in the case they were typed in",
+        })
+    } else if ran && options.verbatim_only && trace.not_verbatim > 0 {
+        json!({
+            "action": "disable_verbatim_only",
+            "message": "Pages matched every term, but none contained the query verbatim",
+        })
+    } else if ran && trace.below_threshold > 0 {
+        match (options.min_match, options.min_score) {
+            (_, Some(_)) => {
+                json!({
+                    "action": "lower_min_score",
+                    "message": "Pages matched some terms, but scored below min_score or matched fewer than min_match requires",
+                })
+            }
+            (Some(_), None) => {
+                json!({
+                    "action": "fewer_terms",
+                    "message": "Pages matched some terms, but fewer than min_match requires",
+                })
+            }
+            (None, None) => {
+                json!({
+                    "action": "fewer_terms",
+                    "message": "Pages matched some terms, but results must match every term",
+                })
+            }
+        }
+    } else {
+        Value::Null
+    };
+
+    json!({
+        "terms": terms.iter().map(|term| {
+            let documents = frequencies.as_ref().map(|frequencies| {
+                frequencies.get(term).copied().unwrap_or(0)
+            });
+            json!({
+                "term": term,
+                "indexed": documents.map(|documents| documents > 0),
+                "documents": documents,
+            })
+        }).collect::<Vec<_>>(),
+        "candidates": if ran {
+            json!({
+                "fetched": trace.candidates_fetched,
+                "filtered_in_fetch": trace.filtered_in_fetch,
+                "off_site": trace.off_site,
+                "not_in_field": trace.not_in_field,
+                "wrong_case": trace.wrong_case,
+                "below_threshold": trace.below_threshold,
+                "not_verbatim": trace.not_verbatim,
+                "spam": trace.spam_excluded,
+            })
+        } else {
+            Value::Null
+        },
+        "suggestion": suggestion,
+    })
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use crate::database::Webpage;
+use crate::result_formatter;
+
+/// How many of a site's results past `max_per_domain` are listed under its best one
+const MORE_FROM_SITE_SHOWN: usize = 5;
+
+/// A site's results past `max_per_domain`, grouped under its best ranked result
+#[derive(Debug, Clone)]
+pub struct MoreFromSite {
+    pub domain: String,
+    /// How many results from the site were held back, including those not listed
+    pub count: usize,
+    /// The best ranked of them, as `(score, url, title)`
+    pub results: Vec<(f64, String, String)>,
+    /// The query restricted to the site with `site:`, which finds all of them
+    pub query: String,
+}
+
+/// The site a result counts towards: its host, without a leading `www.`, so `www.example.com`
+/// and `example.com` share a cap.
+fn site(url: &str) -> Option<String> {
+    let host = result_formatter::extract_domain_from_string(url)?;
+    Some(host.strip_prefix("www.").map(String::from).unwrap_or(host))
+}
+
+/// Keeps at most `max_per_domain` of the ranked webpages from each site, in their ranked order,
+/// and groups the rest under their site's best ranked result as `more_from_site`. Returns how
+/// many were held back.
+pub fn cap_per_domain(
+    query: &str,
+    ranked_webpages: &mut Vec<(f64, Webpage)>,
+    max_per_domain: usize
+) -> usize {
+    // Each site's kept results so far, and where its best one is in `kept`
+    let mut sites: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut kept: Vec<(f64, Webpage)> = Vec::with_capacity(ranked_webpages.len());
+    let mut held_back = 0;
+    for (score, webpage) in ranked_webpages.drain(..) {
+        let Some(site) = site(&webpage.url) else {
+            kept.push((score, webpage));
+            continue;
+        };
+        match sites.get_mut(&site) {
+            Some((count, _)) if *count < max_per_domain => {
+                *count += 1;
+                kept.push((score, webpage));
+            }
+            Some((_, best)) => {
+                let more = kept[*best].1.more_from_site.get_or_insert_with(|| MoreFromSite {
+                    query: format!("{} site:{}", query, site),
+                    domain: site,
+                    count: 0,
+                    results: vec![],
+                });
+                more.count += 1;
+                if more.results.len() < MORE_FROM_SITE_SHOWN {
+                    more.results.push((score, webpage.url, webpage.title));
+                }
+                held_back += 1;
+            }
+            None => {
+                sites.insert(site, (1, kept.len()));
+                kept.push((score, webpage));
+            }
+        }
+    }
+    *ranked_webpages = kept;
+    held_back
+}
@@ -0,0 +1,94 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use crate::database::{ self, AppError, Webpage };
+use crate::state::AppState;
+
+/// How many of a page's most frequent words its fingerprint is made of
+const FINGERPRINT_WORDS: i64 = 64;
+
+/// Fewest indexed words a page needs to be fingerprinted; a handful of words can't tell pages
+/// apart, so pages with fewer are never collapsed
+const MIN_FINGERPRINT_WORDS: usize = 8;
+
+/// A result collapsed into a better ranked near-duplicate of it
+#[derive(Debug, Clone)]
+pub struct Duplicate {
+    pub url: String,
+    pub title: String,
+    pub score: f64,
+}
+
+/// The SimHash of a page's words: each bit is set if the words whose hashes have it set
+/// outweigh those whose hashes don't. Words are weighed by the log of their occurrences, so one
+/// very frequent word can't decide every bit. Pages sharing most of their frequent words get
+/// fingerprints differing in few bits.
+pub fn simhash(words: &[(String, i32)]) -> u64 {
+    let mut weights = [0.0_f64; 64];
+    for (word, occurrences) in words {
+        let mut hasher = DefaultHasher::new();
+        word.hash(&mut hasher);
+        let hash = hasher.finish();
+        let word_weight = (*occurrences as f64).max(0.0).ln_1p();
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if (hash >> bit) & 1 == 1 {
+                *weight += word_weight;
+            } else {
+                *weight -= word_weight;
+            }
+        }
+    }
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0.0)
+        .fold(0, |fingerprint, (bit, _)| fingerprint | (1 << bit))
+}
+
+/// Collapses each of the first `scanned` ranked webpages whose fingerprint is within
+/// `DUPLICATE_MAX_DISTANCE` bits of a better ranked one's into it, listing it under that page's
+/// `duplicates`, and returns how many were collapsed. Pages past `scanned` are left as they are.
+pub async fn collapse(
+    state: &AppState,
+    ranked_webpages: &mut Vec<(f64, Webpage)>,
+    scanned: usize
+) -> Result<usize, AppError> {
+    let scanned = scanned.min(ranked_webpages.len());
+    if scanned < 2 {
+        return Ok(0);
+    }
+    let ids: Vec<i32> = ranked_webpages[..scanned]
+        .iter()
+        .map(|(_, webpage)| webpage.id)
+        .collect();
+    let words = database::fetch_top_words(&state.pool, &ids, FINGERPRINT_WORDS).await?;
+    let max_distance = state.config.duplicates.max_distance;
+
+    let rest = ranked_webpages.split_off(scanned);
+    let mut kept: Vec<((f64, Webpage), Option<u64>)> = vec![];
+    let mut collapsed = 0;
+    for (score, webpage) in ranked_webpages.drain(..) {
+        let fingerprint = words
+            .get(&webpage.id)
+            .filter(|words| words.len() >= MIN_FINGERPRINT_WORDS)
+            .map(|words| simhash(words));
+        let primary = fingerprint.and_then(|fingerprint| {
+            kept.iter_mut().find(|(_, primary)| {
+                primary.is_some_and(|primary| (primary ^ fingerprint).count_ones() <= max_distance)
+            })
+        });
+        match primary {
+            Some(((_, primary), _)) => {
+                primary.duplicates.push(Duplicate {
+                    url: webpage.url,
+                    title: webpage.title,
+                    score,
+                });
+                collapsed += 1;
+            }
+            None => kept.push(((score, webpage), fingerprint)),
+        }
+    }
+    ranked_webpages.extend(kept.into_iter().map(|(ranked, _)| ranked));
+    ranked_webpages.extend(rest);
+    Ok(collapsed)
+}
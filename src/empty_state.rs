@@ -0,0 +1,144 @@
+use axum::response::{ IntoResponse, Json, Response };
+use serde_json::{ Value, json };
+use crate::database;
+use crate::params::SearchParams;
+use crate::result_formatter::{ extract_domain_from_string, format_result };
+use crate::state::AppState;
+use crate::warnings::Warnings;
+
+/// How many recently crawled pages are looked at for each result wanted, since only those on
+/// top domains are shown
+const RECENT_CANDIDATES_PER_RESULT: i64 = 20;
+
+/// Fewest searches a query needs to be shown as trending, so one person's query isn't shown to
+/// everyone
+pub const MIN_TRENDING_SEARCHES: u64 = 2;
+
+/// What a search with an empty query returns instead of an error, for a search box's empty state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyQuery {
+    /// The most searched queries recently
+    Trending,
+    /// The most recently crawled pages on top domains
+    Recent,
+    /// No results
+    None,
+}
+
+impl EmptyQuery {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "trending" => Some(EmptyQuery::Trending),
+            "recent" => Some(EmptyQuery::Recent),
+            "none" => Some(EmptyQuery::None),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EmptyQuery::Trending => "trending",
+            EmptyQuery::Recent => "recent",
+            EmptyQuery::None => "none",
+        }
+    }
+}
+
+/// Responds to a search with an empty query with what `mode` asks for, marked with its
+/// `result_source` so clients can render it differently from search results.
+pub async fn respond(
+    state: &AppState,
+    search_params: &SearchParams,
+    mode: EmptyQuery,
+    warnings: Warnings
+) -> Response {
+    let results = match mode {
+        EmptyQuery::Trending => trending(state, search_params.num_results),
+        EmptyQuery::Recent =>
+            match recent(state, search_params).await {
+                Ok(results) => results,
+                Err(e) => {
+                    eprintln!("Error fetching recent pages: {}", e);
+                    return e.response("Failed to fetch recent pages");
+                }
+            }
+        EmptyQuery::None => vec![],
+    };
+
+    let mut response =
+        json!({
+        "query": "",
+        "result_source": mode.name(),
+        "count": results.len(),
+        "results": results,
+        "website_count": state.index.load().website_count,
+    });
+    warnings.add_to(&mut response);
+    Json(response).into_response()
+}
+
+fn trending(state: &AppState, limit: usize) -> Vec<Value> {
+    state.popularity
+        .top(limit)
+        .into_iter()
+        .filter(|(_, stats)| stats.count >= MIN_TRENDING_SEARCHES)
+        .map(|(query, stats)| json!({ "query": query, "searches": stats.count }))
+        .collect()
+}
+
+/// The most recently crawled pages whose domain is a top domain, newest first.
+async fn recent(
+    state: &AppState,
+    search_params: &SearchParams
+) -> Result<Vec<Value>, database::AppError> {
+    let candidates = (search_params.num_results as i64).saturating_mul(RECENT_CANDIDATES_PER_RESULT);
+    let webpages = database::fetch_recent_webpages(
+        &state.pool,
+        candidates,
+        state.tombstones(false)
+    ).await?;
+
+    let options = search_params.result_options();
+    Ok(
+        webpages
+            .iter()
+            .filter(|webpage| {
+                extract_domain_from_string(&webpage.url).is_some_and(|domain| {
+                    state.top_domains.contains_key(&domain)
+                })
+            })
+            .take(search_params.num_results)
+            .map(|webpage| {
+                // Recent pages have no relevance score
+                let mut result = format_result(
+                    &0.0,
+                    webpage,
+                    &state.top_domains,
+                    &state.fragment_cache,
+                    options
+                );
+                if let Some(result) = result.as_object_mut() {
+                    result.remove("score");
+                    result.remove("raw_score");
+                }
+                result
+            })
+            .collect()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_each_mode_as_it_is_parsed() {
+        for mode in [EmptyQuery::Trending, EmptyQuery::Recent, EmptyQuery::None] {
+            assert_eq!(EmptyQuery::parse(mode.name()), Some(mode));
+        }
+        // Callers trim and lowercase the parameter first
+        for value in ["", "Trending", " recent", "popular"] {
+            assert_eq!(EmptyQuery::parse(value), None, "{:?}", value);
+        }
+    }
+}
@@ -0,0 +1,498 @@
+use serde_json::{ Map, Value, json };
+use crate::config::Config;
+
+/// Describes a single query parameter accepted by an endpoint
+pub struct ParamDef {
+    pub name: &'static str,
+    pub kind: &'static str,
+    pub required: bool,
+    pub description: &'static str,
+}
+
+/// Describes a public endpoint; used for both the root descriptor and the OpenAPI document
+pub struct EndpointDef {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub summary: &'static str,
+    pub authenticated: bool,
+    pub params: &'static [ParamDef],
+}
+
+pub const ENDPOINTS: &[EndpointDef] = &[
+    EndpointDef {
+        method: "GET",
+        path: "/",
+        summary: "Search the index. Without `q`, returns this service descriptor.",
+        authenticated: true,
+        params: &[
+            ParamDef {
+                name: "q",
+                kind: "string",
+                required: true,
+                description: "The search query. Supports `\"phrases\"`, `-excluded` words, `site:domain`, `title:`, `desc:`, `url:`, `inurl:` and `intitle:` scoped terms, `NEAR/n`, and `word~` fuzzy terms.",
+            },
+            ParamDef {
+                name: "results",
+                kind: "integer",
+                required: false,
+                description: "Number of results to return, capped at `max_results`.",
+            },
+            ParamDef {
+                name: "links",
+                kind: "boolean",
+                required: false,
+                description: "Include link information for each result.",
+            },
+            ParamDef {
+                name: "keywords",
+                kind: "boolean",
+                required: false,
+                description: "Include each result's matched keywords (default true).",
+            },
+            ParamDef {
+                name: "snippets",
+                kind: "boolean",
+                required: false,
+                description: "Shorten each result's description to a snippet.",
+            },
+            ParamDef {
+                name: "verbatim_only",
+                kind: "boolean",
+                required: false,
+                description: "Only return results whose title or description contains the query verbatim.",
+            },
+            ParamDef {
+                name: "after",
+                kind: "string",
+                required: false,
+                description: "Only return pages last crawled at or after this RFC 3339 time or `YYYY-MM-DD` date (UTC).",
+            },
+            ParamDef {
+                name: "before",
+                kind: "string",
+                required: false,
+                description: "Only return pages last crawled before this RFC 3339 time or `YYYY-MM-DD` date (UTC).",
+            },
+            ParamDef {
+                name: "lang",
+                kind: "string",
+                required: false,
+                description: "Only return pages in this language, as a two-letter ISO 639-1 code such as `en`.",
+            },
+            ParamDef {
+                name: "verbatim",
+                kind: "boolean",
+                required: false,
+                description: "Look query words up as typed, without lemmatising them.",
+            },
+            ParamDef {
+                name: "case_sensitive",
+                kind: "boolean",
+                required: false,
+                description: "Only match query words in the case they were typed in, such as `PgPool` but not `pgpool`.",
+            },
+            ParamDef {
+                name: "expand",
+                kind: "boolean",
+                required: false,
+                description: "Also search for synonyms of the query's terms, and for pages found through links when it matches few, ranking pages that need them lower. Defaults to `true`.",
+            },
+            ParamDef {
+                name: "min_match",
+                kind: "string",
+                required: false,
+                description: "Return pages containing at least this share of the query's terms, such as `75%`, or this many, such as `2`, instead of only full matches.",
+            },
+            ParamDef {
+                name: "min_score",
+                kind: "number",
+                required: false,
+                description: "Only return pages with at least this relevance score, with any ranker or mode. Defaults to the server's `MIN_SCORE` for loose TF-IDF searches without `min_match`.",
+            },
+            ParamDef {
+                name: "mode",
+                kind: "string",
+                required: false,
+                description: "`loose` (default) fetches pages with any term and returns full matches; `strict` only fetches pages with every term and returns them all.",
+            },
+            ParamDef {
+                name: "ranker",
+                kind: "string",
+                required: false,
+                description: "How relevance is scored: `tfidf` or `bm25`. Defaults to the server's `RANKER`.",
+            },
+            ParamDef {
+                name: "recency",
+                kind: "string",
+                required: false,
+                description: "How strongly newer pages are preferred: `normal` (default), `strong` for news-like queries, or `off`.",
+            },
+            ParamDef {
+                name: "profile",
+                kind: "string",
+                required: false,
+                description: "A ranking profile weighting relevance, domain popularity, freshness and link authority: `relevance`, `fresh`, `authority` or one from `RANKING_PROFILES_FILE`. Defaults to the configured weights.",
+            },
+            ParamDef {
+                name: "semantic",
+                kind: "boolean",
+                required: false,
+                description: "Reorder the best `SEMANTIC_TOP_K` results by the similarity of their embeddings to the query's, when `EMBEDDING_URL` is set.",
+            },
+            ParamDef {
+                name: "collapse",
+                kind: "boolean",
+                required: false,
+                description: "Fold near-duplicate pages into the best ranked of them, listing the others under its `duplicates`.",
+            },
+            ParamDef {
+                name: "max_per_domain",
+                kind: "integer",
+                required: false,
+                description: "Most results returned from one site, the rest grouped under its best result as `more_from_site`; 0 for no cap. Defaults to `MAX_PER_DOMAIN`.",
+            },
+            ParamDef {
+                name: "prefer_domains",
+                kind: "string",
+                required: false,
+                description: "Comma-separated domains the caller favours; results on them or their subdomains are boosted. Overrides `domains=` in the `X-Search-Prefs` header.",
+            },
+            ParamDef {
+                name: "prefer_topics",
+                kind: "string",
+                required: false,
+                description: "Comma-separated topics the caller favours; results with a topic's words in their title, URL or description are boosted. Overrides `topics=` in the `X-Search-Prefs` header.",
+            },
+            ParamDef {
+                name: "bucket",
+                kind: "integer",
+                required: false,
+                description: "The experiment bucket, 0 to 99, to rank in instead of the one the client's address falls in, when `EXPERIMENT_NAME` is set.",
+            },
+            ParamDef {
+                name: "stopwords",
+                kind: "string",
+                required: false,
+                description: "`off` keeps common words such as `the` and `to` in the query; by default they're left out in the `lang` language, or the configured one.",
+            },
+            ParamDef {
+                name: "autocorrect",
+                kind: "boolean",
+                required: false,
+                description: "Serve the results of the `did_you_mean` spelling suggestion instead, if it finds more.",
+            },
+            ParamDef {
+                name: "max_bytes",
+                kind: "integer",
+                required: false,
+                description: "Size budget for the response; optional fields and then trailing results are shed to fit.",
+            },
+            ParamDef {
+                name: "preset",
+                kind: "string",
+                required: false,
+                description: "`minimal`, `standard`, `full`, `research`, or a custom preset of parameter defaults.",
+            },
+            ParamDef {
+                name: "explain",
+                kind: "boolean",
+                required: false,
+                description: "Include how each result's score was arrived at: per-term TF and IDF, the cosine's parts, each bonus and weight, the blended signals, any tie-break and spam penalty.",
+            },
+            ParamDef {
+                name: "debug",
+                kind: "boolean",
+                required: false,
+                description: "Include a trace of what the search pipeline did.",
+            },
+            ParamDef {
+                name: "include_deleted",
+                kind: "boolean",
+                required: false,
+                description: "Also return pages the crawler has tombstoned. Admin only.",
+            },
+            ParamDef {
+                name: "url_regex",
+                kind: "string",
+                required: false,
+                description: "Only return pages whose URL matches this Postgres regex, e.g. `/docs/.*/api`. Admin only.",
+            },
+            ParamDef {
+                name: "signals",
+                kind: "boolean",
+                required: false,
+                description: "Include each result's raw ranking features. Requires an API key.",
+            },
+            ParamDef {
+                name: "empty",
+                kind: "string",
+                required: false,
+                description: "For an empty `q`: `trending` queries, `recent` pages, or `none`. Otherwise `400`.",
+            },
+            ParamDef {
+                name: "token",
+                kind: "string",
+                required: false,
+                description: "Turnstile token, if not sent in the `X-Turnstile-Token` header.",
+            },
+        ],
+    },
+    EndpointDef {
+        method: "POST",
+        path: "/blend",
+        summary: "One ranking for up to 5 weighted queries, sent as a JSON body.",
+        authenticated: true,
+        params: &[],
+    },
+    EndpointDef {
+        method: "GET",
+        path: "/links",
+        summary: "Inbound and outbound links for a URL, whether or not it is indexed.",
+        authenticated: true,
+        params: &[
+            ParamDef {
+                name: "url",
+                kind: "string",
+                required: true,
+                description: "The absolute URL to look up.",
+            },
+            ParamDef {
+                name: "limit",
+                kind: "integer",
+                required: false,
+                description: "Number of linking pages to return (default 50, at most 200).",
+            },
+            ParamDef {
+                name: "offset",
+                kind: "integer",
+                required: false,
+                description: "Number of linking pages to skip.",
+            },
+        ],
+    },
+    EndpointDef {
+        method: "GET",
+        path: "/site/{domain}",
+        summary: "Indexed pages on a domain and its subdomains, with pagination.",
+        authenticated: true,
+        params: &[
+            ParamDef {
+                name: "domain",
+                kind: "string",
+                required: true,
+                description: "The domain, e.g. `example.com`.",
+            },
+            ParamDef {
+                name: "page",
+                kind: "integer",
+                required: false,
+                description: "Page number, starting at 1.",
+            },
+            ParamDef {
+                name: "per_page",
+                kind: "integer",
+                required: false,
+                description: "Pages per page of results (default 20, at most 100).",
+            },
+            ParamDef {
+                name: "sort",
+                kind: "string",
+                required: false,
+                description: "`recent` (default), `links`, or `words`.",
+            },
+            ParamDef {
+                name: "ui_lang",
+                kind: "string",
+                required: false,
+                description: "Language for `last_crawled_ago`: `en`, `fr`, or `de`. Defaults to `Accept-Language`, then English.",
+            },
+            ParamDef {
+                name: "include_deleted",
+                kind: "boolean",
+                required: false,
+                description: "Also list pages the crawler has tombstoned. Admin only.",
+            },
+        ],
+    },
+    EndpointDef {
+        method: "POST",
+        path: "/saved",
+        summary: "Stores a named search, its `q` and other `params`, sent as a JSON body. Needs an API key.",
+        authenticated: true,
+        params: &[],
+    },
+    EndpointDef {
+        method: "GET",
+        path: "/saved/{name}/run",
+        summary: "Runs a saved search, answering like `GET /`; parameters sent override the saved ones.",
+        authenticated: true,
+        params: &[
+            ParamDef {
+                name: "name",
+                kind: "string",
+                required: true,
+                description: "The name the search was saved under.",
+            },
+        ],
+    },
+    EndpointDef {
+        method: "GET",
+        path: "/trending",
+        summary: "The queries searched for by the most people over a recent window, when `QUERY_LOG` is on.",
+        authenticated: true,
+        params: &[
+            ParamDef {
+                name: "window",
+                kind: "string",
+                required: false,
+                description: "One of `TRENDING_WINDOWS`, e.g. `24h`. Defaults to the first of them.",
+            },
+            ParamDef {
+                name: "count",
+                kind: "integer",
+                required: false,
+                description: "Queries to return (default 10, at most `TRENDING_COUNT`).",
+            },
+        ],
+    },
+    EndpointDef {
+        method: "GET",
+        path: "/suggest",
+        summary: "Completions for a partly typed query, from searched queries and indexed words.",
+        authenticated: true,
+        params: &[
+            ParamDef {
+                name: "q",
+                kind: "string",
+                required: true,
+                description: "The query typed so far.",
+            },
+            ParamDef {
+                name: "count",
+                kind: "integer",
+                required: false,
+                description: "Number of completions to return (default 8, at most 20).",
+            },
+        ],
+    },
+    EndpointDef {
+        method: "GET",
+        path: "/random",
+        summary: "Random indexed pages, in the search result shape without a score or raw score.",
+        authenticated: true,
+        params: &[
+            ParamDef {
+                name: "count",
+                kind: "integer",
+                required: false,
+                description: "Number of pages to return (default 5, at most 50).",
+            },
+            ParamDef {
+                name: "domain",
+                kind: "string",
+                required: false,
+                description: "Only return pages on this host or its subdomains.",
+            },
+            ParamDef {
+                name: "min_word_count",
+                kind: "integer",
+                required: false,
+                description: "Only return pages with at least this many words.",
+            },
+        ],
+    },
+    EndpointDef {
+        method: "GET",
+        path: "/stats",
+        summary: "Index statistics. Supports conditional requests via ETag and Last-Modified.",
+        authenticated: false,
+        params: &[],
+    },
+    EndpointDef {
+        method: "GET",
+        path: "/version",
+        summary: "The running build: version, git commit, build time, and cargo features.",
+        authenticated: false,
+        params: &[],
+    },
+    EndpointDef {
+        method: "GET",
+        path: "/openapi.json",
+        summary: "OpenAPI description of this service.",
+        authenticated: false,
+        params: &[],
+    },
+];
+
+/// Builds the JSON document returned by `GET /` when no query is supplied.
+pub fn service_descriptor(config: &Config) -> Value {
+    json!({
+        "service": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "max_results": config.max_results,
+        "turnstile_enforced": config.turnstile.enabled,
+        "openapi": "/openapi.json",
+        "endpoints": ENDPOINTS.iter().map(|endpoint| {
+            json!({
+                "method": endpoint.method,
+                "path": endpoint.path,
+                "summary": endpoint.summary,
+                "authenticated": endpoint.authenticated && config.turnstile.enabled,
+                "parameters": endpoint.params.iter().map(|param| {
+                    json!({
+                        "name": param.name,
+                        "type": param.kind,
+                        "required": param.required,
+                        "description": param.description,
+                    })
+                }).collect::<Vec<_>>(),
+            })
+        }).collect::<Vec<_>>(),
+    })
+}
+
+/// Builds an OpenAPI 3 document from the endpoint definitions.
+pub fn openapi_document(config: &Config) -> Value {
+    let mut paths = Map::new();
+
+    for endpoint in ENDPOINTS {
+        let parameters: Vec<Value> = endpoint.params
+            .iter()
+            .map(|param| {
+                let mut schema = json!({ "type": param.kind });
+                if param.name == "results" {
+                    schema["maximum"] = json!(config.max_results);
+                }
+                // Parameters named in the path template are path parameters
+                let location = if endpoint.path.contains(&format!("{{{}}}", param.name)) {
+                    "path"
+                } else {
+                    "query"
+                };
+                json!({
+                    "name": param.name,
+                    "in": location,
+                    "required": param.required,
+                    "description": param.description,
+                    "schema": schema,
+                })
+            })
+            .collect();
+
+        let path_item = paths.entry(endpoint.path).or_insert_with(|| json!({}));
+        path_item[endpoint.method.to_lowercase()] = json!({
+            "summary": endpoint.summary,
+            "parameters": parameters,
+            "responses": { "200": { "description": "Successful response" } },
+        });
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+    })
+}
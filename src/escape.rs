@@ -0,0 +1,111 @@
+/// Characters that make a spreadsheet treat a cell as a formula
+const FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+/// Escapes a user-controlled or index-derived string for a CSV field: quotes it if it contains a
+/// delimiter, quote, or line break, and neutralises values a spreadsheet would run as a formula
+/// by prefixing them with `'`.
+pub fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(FORMULA_PREFIXES) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strings that break out of, or run code in, one output format or another
+    const HOSTILE: &[&str] = &[
+        "=cmd|' /C calc'!A0",
+        "+1+1",
+        "-2+3",
+        "@SUM(A1:A2)",
+        "\t=1",
+        "\r=1",
+        "=HYPERLINK(\"https://evil.example\",\"click\")",
+        "a,b,c",
+        "say \"hi\"",
+        "\"",
+        "line\nbreak",
+        "carriage\rreturn",
+        "crlf\r\n,\"",
+        "<script>alert(1)</script>",
+        "]]><evil/>",
+        "data: injected\n\nevent: fake",
+        "'already quoted",
+        "café ü 東京",
+        "",
+    ];
+
+    /// Reads a single CSV field back the way a spreadsheet would.
+    fn parse_csv_field(field: &str) -> String {
+        match field.strip_prefix('"').and_then(|field| field.strip_suffix('"')) {
+            Some(quoted) => {
+                let unescaped = quoted.replace("\"\"", "");
+                assert!(!unescaped.contains('"'), "Unescaped quote in {:?}", field);
+                quoted.replace("\"\"", "\"")
+            }
+            None => {
+                assert!(!field.contains([',', '"', '\n', '\r']), "Unquoted {:?}", field);
+                field.to_string()
+            }
+        }
+    }
+
+    #[test]
+    fn csv_fields_are_safe_and_round_trip() {
+        for value in HOSTILE {
+            let field = csv_field(value);
+            let parsed = parse_csv_field(&field);
+            // Nothing a spreadsheet reads can start a formula
+            assert!(!parsed.starts_with(FORMULA_PREFIXES), "{:?} became {:?}", value, field);
+            // Everything else comes back as it was
+            let original = match value.starts_with(FORMULA_PREFIXES) {
+                true => parsed.strip_prefix('\'').unwrap(),
+                false => parsed.as_str(),
+            };
+            assert_eq!(original, *value, "{:?}", field);
+        }
+    }
+
+    #[test]
+    fn csv_fields_stay_in_their_row_and_column() {
+        let row: Vec<String> = HOSTILE
+            .iter()
+            .map(|value| csv_field(value))
+            .collect();
+        let line = row.join(",");
+
+        // Split outside quotes only, there are as many fields as values
+        let mut fields = 1;
+        let mut quoted = false;
+        for character in line.chars() {
+            match character {
+                '"' => {
+                    quoted = !quoted;
+                }
+                ',' if !quoted => {
+                    fields += 1;
+                }
+                '\n' | '\r' => assert!(quoted, "A line break outside quotes in {:?}", line),
+                _ => {}
+            }
+        }
+        assert!(!quoted);
+        assert_eq!(fields, HOSTILE.len());
+    }
+
+    #[test]
+    fn leaves_plain_values_unquoted() {
+        assert_eq!(csv_field("https://example.com/a?b=1"), "https://example.com/a?b=1");
+        assert_eq!(csv_field("a-b"), "a-b");
+        assert_eq!(csv_field("=a,b"), "\"'=a,b\"");
+    }
+}
@@ -0,0 +1,115 @@
+use serde_json::{ Value, json };
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use std::net::IpAddr;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use crate::client_ip::client_identity;
+use crate::config::{ ClientIdentityConfig, ExperimentConfig };
+
+/// How many buckets requests are split into; `bucket=` takes one of `0..BUCKETS`
+pub const BUCKETS: usize = 100;
+
+/// The variant ranked with the configured weights, without a profile
+pub const CONTROL: &str = "control";
+
+/// One arm of the experiment: searches bucketed into it are ranked with its profile
+struct Variant {
+    id: String,
+    /// The ranking profile it ranks with; `None` for the control
+    profile: Option<String>,
+    /// Its share of the buckets, relative to the other variants' weights
+    weight: u32,
+    /// Responses it has served since startup
+    served: AtomicU64,
+}
+
+/// The variant a search was bucketed into, to tag its response with
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub experiment: String,
+    pub variant: String,
+    pub profile: Option<String>,
+    pub bucket: usize,
+}
+
+impl Assignment {
+    pub fn to_json(&self) -> Value {
+        json!({ "name": self.experiment, "variant": self.variant, "bucket": self.bucket })
+    }
+}
+
+/// Splits searches between ranking variants by bucket, so a client keeps seeing the same one,
+/// and counts the responses each served
+pub struct Experiment {
+    name: String,
+    variants: Vec<Variant>,
+}
+
+impl Experiment {
+    /// The configured experiment, or `None` if there isn't one.
+    pub fn new(config: &ExperimentConfig) -> Option<Self> {
+        let name = config.name.clone()?;
+        let variants = config.variants
+            .iter()
+            .map(|(id, weight)| Variant {
+                id: id.clone(),
+                profile: (id != CONTROL).then(|| id.clone()),
+                weight: *weight,
+                served: AtomicU64::new(0),
+            })
+            .collect();
+        Some(Experiment { name, variants })
+    }
+
+    /// The bucket a client falls in: a hash of its identity, salted with the experiment's name so
+    /// a new experiment splits clients afresh. The same client is always in the same bucket.
+    pub fn bucket_for(&self, client: IpAddr, identity: &ClientIdentityConfig) -> usize {
+        let mut hasher = DefaultHasher::new();
+        self.name.hash(&mut hasher);
+        client_identity(client, identity).hash(&mut hasher);
+        (hasher.finish() % (BUCKETS as u64)) as usize
+    }
+
+    /// Assigns a bucket to the variant whose share of the buckets it falls in, and counts the
+    /// response as served by it.
+    pub fn assign(&self, bucket: usize) -> Assignment {
+        let total: u64 = self.variants
+            .iter()
+            .map(|variant| u64::from(variant.weight))
+            .sum();
+        // Where the bucket falls along the variants' summed weights
+        let mut point = ((bucket.min(BUCKETS - 1) as u64) * total) / (BUCKETS as u64);
+        let variant = self.variants
+            .iter()
+            .find(|variant| {
+                let within = point < u64::from(variant.weight);
+                point = point.saturating_sub(u64::from(variant.weight));
+                within
+            })
+            .or(self.variants.last())
+            .expect("An experiment has at least one variant");
+        variant.served.fetch_add(1, Ordering::Relaxed);
+        Assignment {
+            experiment: self.name.clone(),
+            variant: variant.id.clone(),
+            profile: variant.profile.clone(),
+            bucket,
+        }
+    }
+
+    pub fn stats(&self) -> Value {
+        json!({
+            "name": self.name,
+            "variants": self.variants
+                .iter()
+                .map(|variant| {
+                    json!({
+                        "id": variant.id,
+                        "weight": variant.weight,
+                        "served": variant.served.load(Ordering::Relaxed),
+                    })
+                })
+                .collect::<Vec<_>>(),
+        })
+    }
+}
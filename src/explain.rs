@@ -0,0 +1,283 @@
+use chrono::{ DateTime, Utc };
+use serde_json::{ Value, json };
+use crate::config::RankingConfig;
+use crate::database::Webpage;
+use crate::lemmatise::Normaliser;
+use crate::preferences::{ self, Preferences };
+use crate::ranking::{ self, Cosine, Ranker, TermScore };
+use crate::search::{ self, RankingParams, ScoreParts };
+use crate::state::AppState;
+
+/// How a result's score and place were arrived at, for `explain=true`
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    pub ranker: Ranker,
+    /// The ranker's score, before any bonus or weight was applied
+    pub relevance: f64,
+    /// Each matched query term's part in `relevance`
+    pub terms: Vec<TermScore>,
+    /// The parts of the cosine similarity, for TF-IDF
+    pub cosine: Option<Cosine>,
+    /// What the bonuses and weights applied after scoring did to it, in the order applied
+    pub adjustments: Vec<Adjustment>,
+    /// The parts of the blended score results are ordered by
+    pub blend: ScoreParts,
+    /// What ordered the result after the one before it, if their blended scores tied
+    pub tie_break: Option<TieBreak>,
+}
+
+/// A bonus or weight applied to a result's score after relevance scoring
+#[derive(Debug, Clone, Copy)]
+pub struct Adjustment {
+    pub signal: &'static str,
+    pub effect: Effect,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Effect {
+    Add(f64),
+    Multiply(f64),
+}
+
+/// Which of `select_ranked`'s tie-breaks ordered two results with the same blended score
+#[derive(Debug, Clone, Copy)]
+pub enum TieBreak {
+    /// The one on the more popular domain came first
+    DomainRank,
+    /// Neither domain outranked the other, so the page indexed first came first
+    Id,
+}
+
+impl Explanation {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "ranker": self.ranker.name(),
+            "relevance": self.relevance,
+            "terms": self.terms
+                .iter()
+                .map(|term| {
+                    json!({
+                        "term": term.term,
+                        "word": term.word,
+                        "occurrences": term.occurrences,
+                        "tf": term.tf,
+                        "idf": term.idf,
+                        "contribution": term.contribution,
+                    })
+                })
+                .collect::<Vec<_>>(),
+            "cosine": self.cosine.map(|cosine| {
+                json!({
+                    "dot_product": cosine.dot_product,
+                    "query_norm": cosine.query_norm,
+                    "document_norm": cosine.document_norm,
+                })
+            }),
+            "adjustments": self.adjustments
+                .iter()
+                .map(|adjustment| match adjustment.effect {
+                    Effect::Add(value) => json!({ "signal": adjustment.signal, "add": value }),
+                    Effect::Multiply(value) => {
+                        json!({ "signal": adjustment.signal, "multiply": value })
+                    }
+                })
+                .collect::<Vec<_>>(),
+            "blend": {
+                "relevance": self.blend.relevance,
+                "links": self.blend.links,
+                "hubs": self.blend.hubs,
+                "authorities": self.blend.authorities,
+                "domain": self.blend.domain,
+                "spam_penalty": self.blend.penalty,
+                "score": self.blend.score(),
+            },
+            "tie_break": self.tie_break.map(TieBreak::name),
+        })
+    }
+}
+
+impl TieBreak {
+    pub fn name(self) -> &'static str {
+        match self {
+            TieBreak::DomainRank => "domain_rank",
+            TieBreak::Id => "id",
+        }
+    }
+}
+
+/// What a search was ranked with, to explain its results by
+pub struct Context<'a> {
+    pub ranker: Ranker,
+    pub keywords: &'a [String],
+    /// Synonyms and similar spellings standing in for query terms, as `(term, word)`
+    pub stand_ins: &'a [(String, String)],
+    /// The words pages found through links were scored against
+    pub link_words: &'a [String],
+    pub ranking: &'a RankingConfig,
+    pub recency_weight: f64,
+    /// When freshness was measured from
+    pub now: DateTime<Utc>,
+    pub normaliser: Normaliser<'a>,
+    pub preferences: &'a Preferences,
+    /// Whether the results are still in blended order, so ties can be told apart
+    pub blended_order: bool,
+}
+
+/// Explains each of the returned `ranked_webpages`, recomputing its score the way the search
+/// pipeline did and recording each step.
+pub fn explain(state: &AppState, context: &Context, ranked_webpages: &mut [(f64, Webpage)]) {
+    let authority = state.authority.load();
+    let params = RankingParams::new(context.ranking);
+    let top_domains = &state.top_domains;
+    let mut previous: Option<(f64, usize)> = None;
+    for (score, webpage) in ranked_webpages.iter_mut() {
+        let blend = params.score_parts(*score, webpage, &authority, top_domains);
+        let domain_rank = search::domain_rank(top_domains, &webpage.url);
+        let tie_break = previous
+            .filter(|(blended, _)| context.blended_order && *blended == blend.score())
+            .map(|(_, previous_rank)| {
+                if previous_rank != domain_rank { TieBreak::DomainRank } else { TieBreak::Id }
+            });
+        previous = Some((blend.score(), domain_rank));
+
+        let (terms, cosine) = explain_relevance(state, context, webpage);
+        webpage.explanation = Some(
+            Box::new(Explanation {
+                ranker: context.ranker,
+                relevance: webpage.relevance,
+                terms,
+                cosine,
+                adjustments: adjustments(state, context, webpage),
+                blend,
+                tie_break,
+            })
+        );
+    }
+}
+
+/// Breaks a page's relevance down by term, against the words it was scored against.
+fn explain_relevance(
+    state: &AppState,
+    context: &Context,
+    webpage: &Webpage
+) -> (Vec<TermScore>, Option<Cosine>) {
+    // Pages found through links were scored against the linking pages' words alone
+    let (keywords, stand_ins) = if webpage.link_expansion.is_empty() {
+        (context.keywords, context.stand_ins)
+    } else {
+        (context.link_words, &[][..])
+    };
+    let boosts = state.config.field_boosts;
+    let index = state.index.load();
+    match context.ranker {
+        Ranker::TfIdf => {
+            let (terms, cosine) = ranking::explain_tf_idf(
+                index.website_count,
+                boosts,
+                keywords,
+                stand_ins,
+                webpage
+            );
+            (terms, Some(cosine))
+        }
+        Ranker::Bm25 => {
+            let mut terms = ranking::explain_bm25(
+                index.website_count,
+                index.average_word_count,
+                state.config.bm25,
+                boosts,
+                keywords,
+                stand_ins,
+                webpage
+            );
+            // Scaled the way the score was, by the best any candidate could have scored
+            let total: f64 = terms
+                .iter()
+                .map(|term| term.contribution)
+                .sum();
+            if total > 0.0 {
+                for term in terms.iter_mut() {
+                    term.contribution *= webpage.relevance / total;
+                }
+            }
+            (terms, None)
+        }
+    }
+}
+
+/// The bonuses and weights `perform_search` applied to a page's relevance, in its order.
+fn adjustments(state: &AppState, context: &Context, webpage: &Webpage) -> Vec<Adjustment> {
+    let mut adjustments = vec![];
+    let mut push = |signal, effect| adjustments.push(Adjustment { signal, effect });
+    if !webpage.synonyms.is_empty() {
+        push("synonyms", Effect::Multiply(ranking::SYNONYM_WEIGHT));
+    }
+    if !webpage.fuzzy.is_empty() {
+        push("fuzzy", Effect::Multiply(ranking::FUZZY_WEIGHT));
+    }
+    let weight = state.config.coverage_weight;
+    match search::distinct_term_count(context.keywords) {
+        // Pages found through links were added after, so gained nothing for the query's terms
+        Some(distinct_terms) if weight > 0.0 && webpage.link_expansion.is_empty() => {
+            let bonus = ranking::coverage_bonus(webpage.matched_terms, distinct_terms, weight);
+            push("coverage", Effect::Add(bonus));
+        }
+        _ => {}
+    }
+    if !webpage.link_expansion.is_empty() {
+        push("link_expansion", Effect::Multiply(ranking::LINK_EXPANSION_WEIGHT));
+    }
+    if webpage.phrase_match == Some(true) {
+        push("phrase_match", Effect::Add(ranking::PHRASE_MATCH_BONUS));
+    }
+    let field_weights = &state.config.field_weights;
+    if field_weights.any() {
+        let bonus = ranking::field_bonus(context.keywords, webpage, field_weights, context.normaliser);
+        push("fields", Effect::Add(bonus));
+    }
+    if context.recency_weight > 0.0 {
+        let recency = &state.config.recency;
+        let freshness = ranking::freshness(
+            webpage.last_crawled,
+            context.now,
+            recency.half_life,
+            recency.decay
+        );
+        push("freshness", Effect::Add(context.recency_weight * freshness));
+    }
+    if state.config.clicks.enabled && context.ranking.clicks > 0.0 {
+        let rate = state.click_rates.load().rate(webpage.id);
+        push("clicks", Effect::Add(context.ranking.clicks * rate));
+    }
+    if !context.preferences.is_empty() {
+        let topics = context.preferences.normalised_topics(context.normaliser);
+        let bonus = preferences::bonus(
+            context.preferences,
+            &topics,
+            webpage,
+            &state.config.preferences,
+            context.normaliser
+        );
+        if bonus > 0.0 {
+            push("preferences", Effect::Add(bonus));
+        }
+    }
+    if let Some(share) = webpage.anchor_text.filter(|share| *share > 0.0) {
+        push("anchor_text", Effect::Add(state.config.anchor_text.weight * share));
+    }
+    if webpage.proximity_match == Some(true) {
+        push("proximity_match", Effect::Add(ranking::PROXIMITY_BONUS));
+    }
+    if let Some(closeness) = webpage.term_closeness {
+        push("term_closeness", Effect::Add(state.config.proximity.weight * closeness));
+    }
+    let verbatim_multiplier = state.config.proximity.verbatim_multiplier;
+    let verbatim = webpage.verbatim == Some(true) || webpage.verbatim_in_text == Some(true);
+    if context.keywords.len() > 1 && verbatim_multiplier != 1.0 && verbatim {
+        push("verbatim", Effect::Multiply(verbatim_multiplier));
+    }
+    if let Some(quality) = &webpage.quality {
+        push("quality", Effect::Multiply(quality.penalty));
+    }
+    adjustments
+}
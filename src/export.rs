@@ -0,0 +1,193 @@
+use axum::{
+    body::{ Body, Bytes },
+    extract::{ Extension, Query },
+    http::{ header, StatusCode },
+    response::{ IntoResponse, Json, Response },
+};
+use chrono::SecondsFormat;
+use futures::StreamExt;
+use serde_json::json;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::pin::pin;
+use std::sync::Arc;
+use tokio::sync::{ mpsc, OwnedSemaphorePermit };
+use crate::database::{ self, IndexedUrl, UrlExportFilter };
+use crate::escape::csv_field;
+use crate::params;
+use crate::result_formatter::canonicalise_domain;
+use crate::state::AppState;
+
+/// Rows are sent to the client in chunks of roughly this many bytes
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Number of chunks buffered between the database and a slow client
+const CHUNK_BUFFER: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+enum ExportFormat {
+    JsonLines,
+    Csv,
+}
+
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::JsonLines => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    fn header(self) -> String {
+        match self {
+            ExportFormat::JsonLines => String::new(),
+            ExportFormat::Csv => "id,url,last_crawled,word_count\n".to_string(),
+        }
+    }
+
+    fn write_row(self, out: &mut String, row: &IndexedUrl) {
+        let last_crawled = row.last_crawled.map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true));
+        match self {
+            ExportFormat::JsonLines => {
+                let line =
+                    json!({
+                    "id": row.id,
+                    "url": row.url,
+                    "last_crawled": last_crawled,
+                    "word_count": row.word_count,
+                });
+                out.push_str(&line.to_string());
+                out.push('\n');
+            }
+            ExportFormat::Csv => {
+                out.push_str(
+                    &format!(
+                        "{},{},{},{}\n",
+                        row.id,
+                        csv_field(&row.url),
+                        last_crawled.unwrap_or_default(),
+                        row.word_count
+                    )
+                );
+            }
+        }
+    }
+}
+
+/// `GET /admin/export/urls?format=jsonl|csv&since=&domain=&after_id=&include_deleted=`: streams
+/// every indexed URL, leaving out tombstoned pages unless `include_deleted=true`.
+///
+/// Rows come out in id order, so an interrupted export can be resumed by passing the last id
+/// received as `after_id`. Only one export runs at a time.
+pub async fn export_urls(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(state): Extension<Arc<AppState>>
+) -> Response {
+    let format = match params.get("format").map(String::as_str) {
+        None | Some("jsonl") => ExportFormat::JsonLines,
+        Some("csv") => ExportFormat::Csv,
+        Some(_) => {
+            return error_response(StatusCode::BAD_REQUEST, "format must be jsonl or csv");
+        }
+    };
+    let since = match params::parse_date(params.get("since"), "since") {
+        Ok(since) => since,
+        Err(e) => {
+            return e.into_response();
+        }
+    };
+    let after_id = match params.get("after_id").map(|v| v.parse::<i32>()) {
+        None => 0,
+        Some(Ok(after_id)) => after_id,
+        Some(Err(_)) => {
+            return error_response(StatusCode::BAD_REQUEST, "after_id must be an integer");
+        }
+    };
+    let domain = match params.get("domain").map(|domain| canonicalise_domain(domain)) {
+        None => None,
+        Some(Some(domain)) => Some(domain),
+        Some(None) => {
+            return error_response(StatusCode::BAD_REQUEST, "domain must be a valid domain name");
+        }
+    };
+    let include_deleted = match params.get("include_deleted").map(String::as_str) {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(_) => {
+            return error_response(StatusCode::BAD_REQUEST, "include_deleted must be true or false");
+        }
+    };
+
+    // The permit is held by the export task, so it is released once the stream ends or the
+    // client goes away
+    let permit = match state.export_permits.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            return error_response(StatusCode::TOO_MANY_REQUESTS, "An export is already running");
+        }
+    };
+
+    let filter = UrlExportFilter {
+        since,
+        domain,
+        after_id,
+        tombstones: state.tombstones(include_deleted),
+    };
+    let (sender, receiver) = mpsc::channel(CHUNK_BUFFER);
+    tokio::spawn(write_export(state.pool.clone(), filter, format, sender, permit));
+
+    // Fused because the compression layer polls the body again after it has ended
+    let chunks = futures::stream
+        ::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|chunk| (chunk, receiver))
+        })
+        .fuse();
+    ([(header::CONTENT_TYPE, format.content_type())], Body::from_stream(chunks)).into_response()
+}
+
+/// Reads the export query row by row and sends it to the client in chunks.
+///
+/// A database error is sent as a stream error, which aborts the response so the client can tell
+/// the export is incomplete rather than silently truncated.
+async fn write_export(
+    pool: PgPool,
+    filter: UrlExportFilter,
+    format: ExportFormat,
+    sender: mpsc::Sender<Result<Bytes, std::io::Error>>,
+    _permit: OwnedSemaphorePermit
+) {
+    let query = database::indexed_urls_query(&filter);
+    let mut rows = pin!(database::stream_indexed_urls(&pool, &filter, &query));
+    let mut chunk = format.header();
+    let mut exported = 0;
+
+    while let Some(row) = rows.next().await {
+        match row {
+            Ok(row) => {
+                format.write_row(&mut chunk, &row);
+                exported += 1;
+            }
+            Err(e) => {
+                eprintln!("URL export failed after {} rows: {}", exported, e);
+                sender.send(Err(std::io::Error::other(e.to_string()))).await.ok();
+                return;
+            }
+        }
+
+        if chunk.len() >= CHUNK_SIZE {
+            let full = std::mem::take(&mut chunk);
+            if sender.send(Ok(Bytes::from(full))).await.is_err() {
+                // The client disconnected
+                return;
+            }
+        }
+    }
+
+    if !chunk.is_empty() {
+        sender.send(Ok(Bytes::from(chunk))).await.ok();
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
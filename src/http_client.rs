@@ -0,0 +1,231 @@
+use reqwest::{ Client, Proxy, Response };
+use serde::Serialize;
+use serde_json::{ json, Value };
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+use url::Url;
+use crate::config::HttpClientConfig;
+
+const USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/yvanlok/search_engine_api)"
+);
+
+/// Request counters for a single outbound destination host
+#[derive(Debug, Default, Clone)]
+pub struct OutboundStats {
+    pub requests: u64,
+    pub errors: u64,
+    pub total_latency: Duration,
+}
+
+impl OutboundStats {
+    /// The mean latency of the requests sent, or zero before any are.
+    pub fn average_latency(&self) -> Duration {
+        if self.requests == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.total_latency.as_secs_f64() / (self.requests as f64))
+    }
+}
+
+/// Shared HTTP client for all outbound calls, recording per-destination metrics
+pub struct OutboundClient {
+    client: Client,
+    stats: Mutex<HashMap<String, OutboundStats>>,
+}
+
+impl OutboundClient {
+    /// Builds the underlying `reqwest::Client` from configuration.
+    pub fn from_config(config: &HttpClientConfig) -> reqwest::Result<Self> {
+        let mut builder = Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.request_timeout)
+            .pool_idle_timeout(config.pool_idle_timeout)
+            .pool_max_idle_per_host(config.pool_max_idle_per_host);
+
+        if let Some(proxy) = &config.https_proxy {
+            builder = builder.proxy(Proxy::https(proxy)?);
+        }
+
+        #[cfg(feature = "rustls-tls")]
+        {
+            builder = builder.use_rustls_tls();
+        }
+
+        Ok(OutboundClient {
+            client: builder.build()?,
+            stats: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Sends a form-encoded POST request, recording its outcome against the destination host.
+    pub async fn post_form<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        form: &T
+    ) -> reqwest::Result<Response> {
+        let start = Instant::now();
+        let result = self.client
+            .post(url)
+            .form(form)
+            .send().await
+            .and_then(|response| response.error_for_status());
+        self.record(url, start.elapsed(), result.as_ref().err());
+        result
+    }
+
+    /// Sends a JSON POST request, with a bearer token if one is given, recording its outcome
+    /// against the destination host.
+    pub async fn post_json<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        body: &T,
+        bearer_token: Option<&str>
+    ) -> reqwest::Result<Response> {
+        let start = Instant::now();
+        let mut request = self.client.post(url).json(body);
+        if let Some(token) = bearer_token {
+            request = request.bearer_auth(token);
+        }
+        let result = request.send().await.and_then(|response| response.error_for_status());
+        self.record(url, start.elapsed(), result.as_ref().err());
+        result
+    }
+
+    /// Request counters for each destination host since startup, for `/admin/metrics`.
+    pub fn stats(&self) -> Value {
+        let stats = self.stats.lock().unwrap();
+        let hosts: serde_json::Map<String, Value> = stats
+            .iter()
+            .map(|(host, entry)| {
+                let stats = json!({
+                    "requests": entry.requests,
+                    "errors": entry.errors,
+                    "average_latency_ms": entry.average_latency().as_secs_f64() * 1000.0,
+                });
+                (host.clone(), stats)
+            })
+            .collect();
+        Value::Object(hosts)
+    }
+
+    fn record(&self, url: &str, latency: Duration, error: Option<&reqwest::Error>) {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|parsed_url| parsed_url.host_str().map(String::from))
+            .unwrap_or_else(|| "unknown".to_string());
+
+        let mut stats = self.stats.lock().unwrap();
+        let entry = stats.entry(host.clone()).or_default();
+        entry.requests += 1;
+        entry.total_latency += latency;
+
+        if let Some(error) = error {
+            entry.errors += 1;
+            eprintln!(
+                "Outbound request to {} failed after {:?}: {} ({} of {} requests failed, avg latency {:?})",
+                host,
+                latency,
+                error,
+                entry.errors,
+                entry.requests,
+                entry.average_latency()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{ http::HeaderMap, routing::post, Router };
+
+    fn config(request_timeout: Duration) -> HttpClientConfig {
+        HttpClientConfig {
+            connect_timeout: Duration::from_secs(1),
+            request_timeout,
+            pool_idle_timeout: Duration::from_secs(90),
+            pool_max_idle_per_host: 8,
+            https_proxy: None,
+        }
+    }
+
+    /// Serves `router` on a free local port, returning its URL.
+    async fn spawn_mock(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        format!("http://{}/", address)
+    }
+
+    #[tokio::test]
+    async fn sends_a_descriptive_user_agent() {
+        let router = Router::new().route(
+            "/",
+            post(|headers: HeaderMap| async move {
+                headers[reqwest::header::USER_AGENT].to_str().unwrap().to_string()
+            })
+        );
+        let url = spawn_mock(router).await;
+        let client = OutboundClient::from_config(&config(Duration::from_secs(5))).unwrap();
+
+        let response = client.post_form(&url, &[("response", "token")]).await.unwrap();
+        let user_agent = response.text().await.unwrap();
+        assert_eq!(user_agent, USER_AGENT);
+        let name_and_version = format!("search_engine_api/{} ", env!("CARGO_PKG_VERSION"));
+        assert!(user_agent.starts_with(&name_and_version));
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_slow_destinations_and_counts_the_error() {
+        let router = Router::new().route(
+            "/",
+            post(|| async {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                "too late"
+            })
+        );
+        let url = spawn_mock(router).await;
+        let client = OutboundClient::from_config(&config(Duration::from_millis(200))).unwrap();
+
+        let started = Instant::now();
+        let error = client.post_json(&url, &json!({}), None).await.unwrap_err();
+        assert!(error.is_timeout());
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let stats = client.stats();
+        let host = &stats["127.0.0.1"];
+        assert_eq!(host["requests"], 1);
+        assert_eq!(host["errors"], 1);
+        let latency = host["average_latency_ms"].as_f64().unwrap();
+        assert!((200.0..1000.0).contains(&latency), "{}", latency);
+    }
+
+    #[test]
+    fn averages_latency_without_truncating_or_dividing_by_zero() {
+        assert_eq!(OutboundStats::default().average_latency(), Duration::ZERO);
+
+        let stats = OutboundStats {
+            requests: 3,
+            errors: 0,
+            total_latency: Duration::from_millis(10),
+        };
+        let average = stats.average_latency().as_secs_f64();
+        assert!((average - 0.010 / 3.0).abs() < 1e-9);
+
+        // More requests than fit in a u32 don't wrap around
+        let stats = OutboundStats {
+            requests: (1 << 32) + 1,
+            errors: 0,
+            total_latency: Duration::from_secs((1 << 32) + 1),
+        };
+        assert!((stats.average_latency().as_secs_f64() - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::watch;
+use crate::database::{ self, AppError, Webpage };
+use crate::state::AppState;
+
+/// How many documents contain each of the index's most common words, as of the last reload, so
+/// IDF doesn't need a lookup per search or a column on every fetched row
+#[derive(Debug, Default)]
+pub struct DocumentFrequencies {
+    counts: HashMap<String, i64>,
+}
+
+impl DocumentFrequencies {
+    /// How many words are held.
+    pub fn words(&self) -> usize {
+        self.counts.len()
+    }
+}
+
+/// Returns how many documents contain each of `words`, for those in the index, like
+/// `database::fetch_document_frequencies`. Words in the table are answered from it, and only
+/// the rest are looked up.
+pub async fn lookup(state: &AppState, words: &[String]) -> Result<HashMap<String, i64>, AppError> {
+    let table = state.document_frequencies.load();
+    let mut frequencies = HashMap::new();
+    let mut missing = vec![];
+    for word in words {
+        match table.counts.get(word) {
+            Some(&documents) => {
+                frequencies.insert(word.clone(), documents);
+            }
+            None => missing.push(word.clone()),
+        }
+    }
+    if !missing.is_empty() {
+        frequencies.extend(database::fetch_document_frequencies(&state.pool, &missing).await?);
+    }
+    Ok(frequencies)
+}
+
+/// Fills in the document frequency of every keyword of the fetched `webpages`, which the fetch
+/// leaves at 0.
+pub async fn fill(state: &AppState, webpages: &mut [Webpage]) -> Result<(), AppError> {
+    let mut words: Vec<String> = webpages
+        .iter()
+        .flat_map(|webpage| webpage.keywords.iter().map(|(keyword, _)| keyword.word.clone()))
+        .collect();
+    words.sort();
+    words.dedup();
+    let frequencies = lookup(state, &words).await?;
+    for webpage in webpages.iter_mut() {
+        for (keyword, _) in webpage.keywords.iter_mut() {
+            keyword.documents_containing_word = frequencies
+                .get(&keyword.word)
+                .copied()
+                .unwrap_or_default();
+        }
+    }
+    Ok(())
+}
+
+/// Reloads the `IDF_CACHE_WORDS` most common words' document frequencies every
+/// `IDF_REFRESH_SECS` until shutdown. Until the first load, and with `IDF_CACHE_WORDS=0`, every
+/// word is looked up.
+pub async fn run_refresh(state: Arc<AppState>, mut shutdown: watch::Receiver<bool>) {
+    let config = state.config.idf_cache.clone();
+    if config.words == 0 {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(config.refresh);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => {
+                break;
+            }
+        }
+
+        match database::fetch_common_document_frequencies(&state.pool, config.words).await {
+            Ok(counts) => {
+                state.document_frequencies.store(Arc::new(DocumentFrequencies { counts }));
+            }
+            Err(e) => eprintln!("Error loading document frequencies: {}", e),
+        }
+    }
+}
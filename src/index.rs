@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+use sqlx::PgPool;
+use tokio::sync::watch;
+use crate::database::{ self, AppError, IndexFingerprint, Tombstones };
+use crate::state::AppState;
+
+/// The index as it was last read, swapped out by `run_refresh` whenever it changes
+#[derive(Debug, Clone)]
+pub struct IndexStats {
+    /// Live pages only, when `soft_deletes` is on
+    pub website_count: i64,
+    /// The average word count of live pages, for BM25's length normalisation
+    pub average_word_count: f64,
+    /// When the index was first read, or last seen to have changed
+    pub indexed_at: SystemTime,
+    fingerprint: IndexFingerprint,
+}
+
+impl IndexStats {
+    pub async fn load(pool: &PgPool, tombstones: Tombstones) -> Result<Self, AppError> {
+        let fingerprint = database::fetch_index_fingerprint(pool, tombstones).await?;
+        let average_word_count = database::average_word_count(pool, tombstones).await?;
+        Ok(IndexStats {
+            website_count: fingerprint.live_pages,
+            average_word_count,
+            indexed_at: SystemTime::now(),
+            fingerprint,
+        })
+    }
+}
+
+/// Checks the index for changes every `INDEX_REFRESH_SECS` until shutdown. Once the crawler has
+/// added, recrawled or tombstoned pages, or changed links, the index statistics are reread,
+/// cached results ranked against the old index and pages formatted from it are dropped, and
+/// `state.index_generation` is bumped for the tasks that follow the index.
+pub async fn run_refresh(state: Arc<AppState>, mut shutdown: watch::Receiver<bool>) {
+    if state.config.index_refresh.is_zero() {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(state.config.index_refresh);
+    // The first tick completes immediately, and the index was only just read at startup
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => {
+                break;
+            }
+        }
+
+        if let Err(e) = refresh(&state).await {
+            eprintln!("Error checking the index for changes: {}", e);
+        }
+    }
+}
+
+/// Rereads the index statistics if the index has changed since they were last read.
+async fn refresh(state: &AppState) -> Result<(), AppError> {
+    let tombstones = state.tombstones(false);
+    let fingerprint = database::fetch_index_fingerprint(&state.pool, tombstones).await?;
+    if fingerprint == state.index.load().fingerprint {
+        return Ok(());
+    }
+
+    let stats = IndexStats::load(&state.pool, tombstones).await?;
+    println!("The index changed: {} websites.", stats.website_count);
+    state.index.store(Arc::new(stats));
+    state.result_cache.clear();
+    // A recrawled page may have a new title or description
+    state.fragment_cache.clear();
+    state.index_generation.send_modify(|generation| {
+        *generation += 1;
+    });
+    Ok(())
+}
@@ -4,17 +4,36 @@ use std::collections::HashMap;
 use std::path::Path;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::{ decompose_compatible, is_combining_mark };
+use crate::quantities;
+
+/// Words mapped to their lemmas
+pub type LemmaMap = HashMap<String, String>;
 
 // Global static for storing the lemma mappings.
-static LEMMA_MAP: Lazy<HashMap<String, String>> = Lazy::new(|| {
+static LEMMA_MAP: Lazy<LemmaMap> = Lazy::new(|| {
     load_lemma_map("lemmatised_words.txt").expect("Failed to load lemma map")
 });
 
-// Global static for the punctuation removal regex.
+// Global static for the punctuation removal regex. Letters and digits in any script are kept.
 static PUNCTUATION_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"[^a-zA-Z0-9\s]").expect("Failed to compile punctuation regex")
+    Regex::new(r"[^\p{L}\p{N}\s]").expect("Failed to compile punctuation regex")
 });
 
+/// Letters that compatibility decomposition leaves alone but that have a conventional
+/// unaccented spelling.
+const SPECIAL_FOLDS: &[(char, &str)] = &[
+    ('ß', "ss"),
+    ('đ', "d"),
+    ('ð', "d"),
+    ('ł', "l"),
+    ('ø', "o"),
+    ('æ', "ae"),
+    ('œ', "oe"),
+    ('þ', "th"),
+];
+
 /// Loads the lemma map from a file.
 ///
 /// # Arguments
@@ -24,7 +43,7 @@ static PUNCTUATION_REGEX: Lazy<Regex> = Lazy::new(|| {
 /// # Returns
 ///
 /// A `Result` containing the `HashMap` of lemma mappings or an error.
-fn load_lemma_map<P: AsRef<Path>>(filename: P) -> Result<HashMap<String, String>, std::io::Error> {
+pub fn load_lemma_map<P: AsRef<Path>>(filename: P) -> Result<LemmaMap, std::io::Error> {
     let file = File::open(filename)?;
     let reader = BufReader::new(file);
     let mut map = HashMap::new();
@@ -48,20 +67,185 @@ fn load_lemma_map<P: AsRef<Path>>(filename: P) -> Result<HashMap<String, String>
 /// # Arguments
 ///
 /// * `text` - The input string to lemmatize.
+/// * `folding_exceptions` - Letters to keep as they are instead of folding.
 ///
 /// # Returns
 ///
 /// A vector of lemmatized words.
-pub fn lemmatise_string(text: &str) -> Vec<String> {
-    let text = text.to_lowercase();
-    let text_without_punctuation = PUNCTUATION_REGEX.replace_all(&text, " ");
-    let result: Vec<String> = text_without_punctuation
-        .split_whitespace()
+pub fn lemmatise_string(text: &str, folding_exceptions: &[char]) -> Vec<String> {
+    lemmatise_with(text, folding_exceptions, &LEMMA_MAP)
+}
+
+/// Lemmatises a string like `lemmatise_string`, with another language's lemma map.
+///
+/// Numbers and units are normalised before lemma lookup, so "three hundred kilograms" and
+/// "300kg" both become "300 kilogram"; an indexer should lemmatise page text the same way.
+pub fn lemmatise_with(text: &str, folding_exceptions: &[char], lemmas: &LemmaMap) -> Vec<String> {
+    quantities::normalise(split_words(text, folding_exceptions))
+        .into_iter()
         .map(|word| {
-            LEMMA_MAP.get(word)
+            lemmas.get(&word)
                 .map(|s| s.to_string())
-                .unwrap_or_else(|| word.to_string())
+                .unwrap_or(word)
         })
-        .collect();
-    result
+        .collect()
+}
+
+/// Splits a string into lowercased, folded words without lemmatising them, so "POSTing" stays
+/// "posting".
+pub fn split_words(text: &str, folding_exceptions: &[char]) -> Vec<String> {
+    normalise_text(text, folding_exceptions)
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+/// Normalises text the way query words are before lemma lookup and keyword matching: NFKC, so
+/// ligatures and full-width letters become plain ones, then lowercasing, accent folding, and
+/// punctuation replaced by single spaces. "Ｃａｆé!" becomes "cafe".
+///
+/// Keywords only match if the index stores them normalised the same way, so an indexer should
+/// apply this (with the same folding exceptions) to page text before counting its words.
+pub fn normalise_text(text: &str, folding_exceptions: &[char]) -> String {
+    let text: String = text.nfkc().collect();
+    let text = fold_diacritics(&text.to_lowercase(), folding_exceptions);
+    PUNCTUATION_REGEX.replace_all(&text, " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits a string into folded words like `split_words`, but keeping their case, so "PgPool"
+/// stays "PgPool".
+pub fn split_cased_words(text: &str, folding_exceptions: &[char]) -> Vec<String> {
+    let text: String = text.nfkc().collect();
+    let text = fold_diacritics(&text, folding_exceptions);
+    PUNCTUATION_REGEX.replace_all(&text, " ")
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+/// How text is turned into the terms looked up in the index and compared with page text
+#[derive(Debug, Clone, Copy)]
+pub struct Normaliser<'a> {
+    /// Letters to keep as they are instead of folding
+    pub folding_exceptions: &'a [char],
+    /// Whether words are replaced by their lemmas; off for exact-match searches
+    pub lemmatise: bool,
+    /// The lemma map of the query's language, or `None` for the English one
+    pub lemmas: Option<&'a LemmaMap>,
+}
+
+impl Normaliser<'_> {
+    pub fn terms(self, text: &str) -> Vec<String> {
+        if self.lemmatise {
+            lemmatise_with(text, self.folding_exceptions, self.lemmas.unwrap_or(&LEMMA_MAP))
+        } else {
+            split_words(text, self.folding_exceptions)
+        }
+    }
+}
+
+/// Folds accented letters to their unaccented forms, so "café" and "cafe" become the same word.
+///
+/// Applies NFKD compatibility decomposition and drops combining marks, except for the letters
+/// in `exceptions`, which are kept as they are.
+///
+/// # Arguments
+///
+/// * `text` - The lowercased input string.
+/// * `exceptions` - Letters to leave unfolded, for languages where folding changes the meaning.
+///
+/// # Returns
+///
+/// The folded string.
+pub fn fold_diacritics(text: &str, exceptions: &[char]) -> String {
+    let mut folded = String::with_capacity(text.len());
+    // Compose first, so exceptions also match input typed with separate combining marks
+    for c in text.nfc() {
+        if exceptions.contains(&c) {
+            folded.push(c);
+        } else if let Some((_, replacement)) = SPECIAL_FOLDS.iter().find(|(from, _)| *from == c) {
+            folded.push_str(replacement);
+        } else {
+            decompose_compatible(c, |d| {
+                if !is_combining_mark(d) {
+                    folded.push(d);
+                }
+            });
+        }
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_accents_out_of_french_german_and_vietnamese() {
+        let cases = [
+            // French
+            ("Crème brûlée", "creme brulee"),
+            ("garçon naïf", "garcon naif"),
+            ("Œuvre", "oeuvre"),
+            ("L'été à Noël", "l ete a noel"),
+            // German, where ß and its capital become ss
+            ("Straße", "strasse"),
+            ("GROẞE Größe", "grosse grosse"),
+            ("Müller Käse", "muller kase"),
+            // Vietnamese, with stacked marks and đ
+            ("Tiếng Việt", "tieng viet"),
+            ("Đà Nẵng", "da nang"),
+            ("phở bò", "pho bo"),
+            // Already unaccented, and other scripts, are left alone
+            ("cafe", "cafe"),
+            ("москва 東京", "москва 東京"),
+        ];
+        for (text, folded) in cases {
+            assert_eq!(normalise_text(text, &[]), folded, "{}", text);
+        }
+    }
+
+    #[test]
+    fn folds_compatibility_forms_and_separate_marks() {
+        let cases = [
+            ("Ｃａｆé!", "cafe"),
+            ("ﬁlm", "film"),
+            // e followed by a combining acute accent
+            ("cafe\u{301}", "cafe"),
+            ("  CAFÉ -- au   lait ", "cafe au lait"),
+        ];
+        for (text, folded) in cases {
+            assert_eq!(normalise_text(text, &[]), folded, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn keeps_the_exception_letters() {
+        let cases: [(&str, &[char], &str); 5] = [
+            ("señor", &['ñ'], "señor"),
+            ("Käse Müller", &['ä'], "käse muller"),
+            ("smörgåsbord", &['ö', 'å'], "smörgåsbord"),
+            // Exceptions match however the letter was typed
+            ("cafe\u{301}", &['é'], "café"),
+            ("Straße", &['ß'], "straße"),
+        ];
+        for (text, exceptions, folded) in cases {
+            assert_eq!(normalise_text(text, exceptions), folded, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn folds_words_the_same_on_every_matching_path() {
+        let normaliser = Normaliser { folding_exceptions: &[], lemmatise: false, lemmas: None };
+        for text in ["café", "CAFÉ", "cafe\u{301}", "Ｃａｆé"] {
+            assert_eq!(normaliser.terms(text), ["cafe"], "{:?}", text);
+            assert_eq!(split_words(text, &[]), ["cafe"], "{:?}", text);
+        }
+        // The cased split keeps the case, but folds the same
+        assert_eq!(split_cased_words("Crème Brûlée", &[]), ["Creme", "Brulee"]);
+        assert_eq!(split_cased_words("Tiếng Việt", &['ế']), ["Tiếng", "Viet"]);
+    }
 }
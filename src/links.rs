@@ -0,0 +1,116 @@
+use axum::{
+    extract::{ Extension, Query },
+    http::StatusCode,
+    response::{ IntoResponse, Json, Response },
+};
+use serde_json::{ Value, json };
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::database::{ self, AppError };
+use crate::result_formatter::{ canonicalise_url, url_variants };
+use crate::state::AppState;
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+/// `GET /links?url=...`: the link graph around a single URL.
+pub async fn get_links(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(state): Extension<Arc<AppState>>
+) -> Response {
+    let url = match params.get("url").and_then(|url| canonicalise_url(url)) {
+        Some(url) => url,
+        None => {
+            return error_response(StatusCode::BAD_REQUEST, "Missing or invalid url parameter");
+        }
+    };
+    let limit = params
+        .get("limit")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_LIMIT)
+        .clamp(1, MAX_LIMIT);
+    let offset = params
+        .get("offset")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0_i64)
+        .max(0);
+
+    let cache_key = format!("{}|{}|{}", url, limit, offset);
+    if let Some(cached) = state.links_cache.get(&cache_key) {
+        return Json(cached).into_response();
+    }
+
+    let body = match state.database.run(|| lookup_links(&state, &url, limit, offset)).await {
+        Ok(body) => body,
+        Err(e) => {
+            eprintln!("Error fetching links for {}: {}", url, e);
+            return e.response("Failed to fetch links");
+        }
+    };
+
+    match body {
+        Some(body) => {
+            state.links_cache.insert(cache_key, body.clone());
+            Json(body).into_response()
+        }
+        None => not_found(&state, &url).await,
+    }
+}
+
+async fn lookup_links(
+    state: &AppState,
+    url: &str,
+    limit: i64,
+    offset: i64
+) -> Result<Option<Value>, AppError> {
+    let webpage = database::find_webpage_by_url(&state.pool, url).await?;
+    let links_to_count = match &webpage {
+        Some((id, _)) => Some(database::count_outbound_links(&state.pool, *id).await?),
+        None => None,
+    };
+    let (linked_from_count, links_from) = database::fetch_inbound_links(
+        &state.pool,
+        url,
+        limit,
+        offset
+    ).await?;
+
+    // A URL we have never seen, neither as a page nor as a link target
+    if webpage.is_none() && linked_from_count == 0 {
+        return Ok(None);
+    }
+
+    Ok(
+        Some(
+            json!({
+            "url": url,
+            "in_index": webpage.is_some(),
+            "title": webpage.map(|(_, title)| title),
+            "links_to_count": links_to_count,
+            "linked_from_count": linked_from_count,
+            "links_from": links_from.iter().map(|(link, count)| {
+                json!({ "link": link, "occurrences": count })
+            }).collect::<Vec<_>>(),
+            "limit": limit,
+            "offset": offset,
+        })
+        )
+    )
+}
+
+async fn not_found(state: &AppState, url: &str) -> Response {
+    let variants = url_variants(url);
+    let known = database::find_known_urls(&state.pool, &variants).await.unwrap_or_default();
+    // Suggest the closest spelling first, in the order the variants were generated
+    let suggestion = variants.into_iter().find(|variant| known.contains(variant));
+
+    let mut body = json!({ "error": "URL not found in the index" });
+    if let Some(suggestion) = suggestion {
+        body["did_you_mean"] = json!(suggestion);
+    }
+    (StatusCode::NOT_FOUND, Json(body)).into_response()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
@@ -0,0 +1,239 @@
+use axum::http::{ header, HeaderMap };
+use std::time::Duration;
+
+/// A language human-facing strings can be rendered in. Machine-readable values (RFC 3339 times,
+/// numbers) are the same in every locale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    Fr,
+    De,
+}
+
+/// The strings a locale renders relative times with
+struct Table {
+    just_now: &'static str,
+    /// `{n}` is replaced with the count and `{unit}` with the unit's singular or plural form
+    ago: &'static str,
+    days: (&'static str, &'static str),
+    hours: (&'static str, &'static str),
+    minutes: (&'static str, &'static str),
+}
+
+const EN: Table = Table {
+    just_now: "just now",
+    ago: "{n} {unit} ago",
+    days: ("day", "days"),
+    hours: ("hour", "hours"),
+    minutes: ("minute", "minutes"),
+};
+
+const FR: Table = Table {
+    just_now: "à l'instant",
+    ago: "il y a {n} {unit}",
+    days: ("jour", "jours"),
+    hours: ("heure", "heures"),
+    minutes: ("minute", "minutes"),
+};
+
+const DE: Table = Table {
+    just_now: "gerade eben",
+    ago: "vor {n} {unit}",
+    days: ("Tag", "Tagen"),
+    hours: ("Stunde", "Stunden"),
+    minutes: ("Minute", "Minuten"),
+};
+
+impl Locale {
+    /// Picks the locale from an explicit `ui_lang` parameter, then from the `Accept-Language`
+    /// header in order of preference, falling back to English. Region subtags are ignored, so
+    /// `fr-CA` is French.
+    pub fn negotiate(ui_lang: Option<&str>, headers: &HeaderMap) -> Self {
+        if let Some(locale) = ui_lang.and_then(Locale::from_tag) {
+            return locale;
+        }
+
+        let accept_language = headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let mut preferences: Vec<(f32, &str)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |quality| quality.parse().ok())?;
+                Some((quality, tag))
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+        // Stable, so equally preferred languages keep the client's order
+        preferences.sort_by(|a, b| b.0.total_cmp(&a.0));
+        preferences
+            .into_iter()
+            .find_map(|(_, tag)| Locale::from_tag(tag))
+            .unwrap_or_default()
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+        match language.as_str() {
+            "en" => Some(Locale::En),
+            "fr" => Some(Locale::Fr),
+            "de" => Some(Locale::De),
+            _ => None,
+        }
+    }
+
+    /// The language tag, as sent in `Content-Language`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Fr => "fr",
+            Locale::De => "de",
+        }
+    }
+
+    fn table(self) -> &'static Table {
+        match self {
+            Locale::En => &EN,
+            Locale::Fr => &FR,
+            Locale::De => &DE,
+        }
+    }
+
+    /// Whether `n` takes the singular form. French treats zero as singular.
+    fn is_singular(self, n: u64) -> bool {
+        match self {
+            Locale::Fr => n <= 1,
+            Locale::En | Locale::De => n == 1,
+        }
+    }
+
+    /// Renders how long ago something happened in the largest whole unit, e.g. "2 days ago".
+    pub fn relative_age(self, age: Duration) -> String {
+        let table = self.table();
+        let minutes = age.as_secs() / 60;
+        let (n, (singular, plural)) = if minutes >= 24 * 60 {
+            (minutes / (24 * 60), table.days)
+        } else if minutes >= 60 {
+            (minutes / 60, table.hours)
+        } else if minutes >= 1 {
+            (minutes, table.minutes)
+        } else {
+            return table.just_now.to_string();
+        };
+        let unit = if self.is_singular(n) { singular } else { plural };
+        table.ago.replace("{n}", &n.to_string()).replace("{unit}", unit)
+    }
+}
+
+/// The language of a page's content, as a lowercase two-letter ISO 639-1 code such as `en`.
+/// Unlike `Locale`, any language can be named, since it's only compared with what the crawler
+/// detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LanguageCode([u8; 2]);
+
+impl LanguageCode {
+    /// Parses a code case-insensitively. Region subtags are ignored, so `en-GB` is `en`.
+    pub fn parse(tag: &str) -> Option<Self> {
+        match tag.trim().split(['-', '_']).next()?.as_bytes() {
+            [a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => {
+                Some(LanguageCode([a.to_ascii_lowercase(), b.to_ascii_lowercase()]))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever holds ASCII letters
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+
+    fn accepting(accept_language: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_str(accept_language).unwrap());
+        headers
+    }
+
+    #[test]
+    fn renders_each_plural_form_in_each_locale() {
+        let cases = [
+            (0, ["just now", "à l'instant", "gerade eben"]),
+            (59, ["just now", "à l'instant", "gerade eben"]),
+            (MINUTE, ["1 minute ago", "il y a 1 minute", "vor 1 Minute"]),
+            (2 * MINUTE, ["2 minutes ago", "il y a 2 minutes", "vor 2 Minuten"]),
+            (HOUR - 1, ["59 minutes ago", "il y a 59 minutes", "vor 59 Minuten"]),
+            (HOUR, ["1 hour ago", "il y a 1 heure", "vor 1 Stunde"]),
+            (DAY - 1, ["23 hours ago", "il y a 23 heures", "vor 23 Stunden"]),
+            (DAY, ["1 day ago", "il y a 1 jour", "vor 1 Tag"]),
+            (2 * DAY - 1, ["1 day ago", "il y a 1 jour", "vor 1 Tag"]),
+            (2 * DAY, ["2 days ago", "il y a 2 jours", "vor 2 Tagen"]),
+            (1000 * DAY, ["1000 days ago", "il y a 1000 jours", "vor 1000 Tagen"]),
+        ];
+        let locales = [Locale::En, Locale::Fr, Locale::De];
+        for (secs, rendered) in cases {
+            for (locale, expected) in locales.into_iter().zip(rendered) {
+                assert_eq!(locale.relative_age(Duration::from_secs(secs)), expected, "{}s", secs);
+            }
+        }
+        // Huge ages still render
+        assert!(Locale::En.relative_age(Duration::MAX).ends_with(" days ago"));
+    }
+
+    #[test]
+    fn treats_zero_and_one_as_singular_only_in_french() {
+        for (n, english, french) in [(0, false, true), (1, true, true), (2, false, false)] {
+            assert_eq!(Locale::En.is_singular(n), english, "{}", n);
+            assert_eq!(Locale::De.is_singular(n), english, "{}", n);
+            assert_eq!(Locale::Fr.is_singular(n), french, "{}", n);
+        }
+    }
+
+    #[test]
+    fn prefers_ui_lang_then_accept_language_then_english() {
+        let german = accepting("de-DE");
+        assert_eq!(Locale::negotiate(Some("fr"), &german), Locale::Fr);
+        assert_eq!(Locale::negotiate(Some("FR_ca"), &german), Locale::Fr);
+        // An unsupported or malformed `ui_lang` falls through to the header
+        assert_eq!(Locale::negotiate(Some("es"), &german), Locale::De);
+        assert_eq!(Locale::negotiate(Some(""), &german), Locale::De);
+        assert_eq!(Locale::negotiate(None, &german), Locale::De);
+        assert_eq!(Locale::negotiate(None, &HeaderMap::new()), Locale::En);
+        assert_eq!(Locale::negotiate(Some("es"), &accepting("es, it")), Locale::En);
+    }
+
+    #[test]
+    fn follows_accept_language_preferences() {
+        let cases = [
+            ("fr;q=0.5, de;q=0.9, en;q=0.1", Locale::De),
+            // Unsupported languages are skipped, whatever their weight
+            ("es, pt-BR;q=0.9, fr;q=0.8", Locale::Fr),
+            // Equal weights keep the client's order, and no weight means 1
+            ("de;q=0.7, fr;q=0.7", Locale::De),
+            ("fr;q=0.9, de", Locale::De),
+            // A weight of zero means not acceptable, and a malformed one is ignored
+            ("de;q=0, fr;q=0.1", Locale::Fr),
+            ("de;q=high, fr;q=0.5", Locale::Fr),
+            ("*, de;q=0.5", Locale::De),
+            (" , ;q=1, ", Locale::En),
+        ];
+        for (accept_language, locale) in cases {
+            let negotiated = Locale::negotiate(None, &accepting(accept_language));
+            assert_eq!(negotiated, locale, "{}", accept_language);
+        }
+    }
+}
@@ -1,110 +1,515 @@
 use axum::{
-    routing::get,
+    routing::{ get, post },
     Router,
-    response::Json,
-    http::{ HeaderValue, Method },
-    extract::{ Query, Extension, ConnectInfo },
+    response::{ IntoResponse, Json, Response },
+    http::{ header, HeaderMap, HeaderName, HeaderValue, Method },
+    extract::{ ConnectInfo, Extension },
 };
 use std::collections::HashMap;
 use serde_json::{ Value, json };
-use sqlx::PgPool;
+use sqlx::postgres::PgPoolOptions;
 use dotenv::dotenv;
 use tokio::fs::File;
 use tokio::io::{ self, AsyncBufReadExt };
-use std::time::Instant;
+use std::time::{ Duration, Instant, UNIX_EPOCH };
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::CorsLayer;
-use reqwest::Client;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use std::net::SocketAddr;
-
+use std::sync::atomic::AtomicBool;
+use tokio::sync::{ watch, Semaphore };
+use std::net::{ IpAddr, SocketAddr };
+
+mod access_log;
+mod admin;
+mod api_keys;
+mod auth;
+mod background_writer;
+mod bench;
+mod blend;
+mod budget;
+mod build_info;
+mod capture;
+mod clicks;
+mod client_ip;
+mod conditional;
+mod config;
 mod lemmatise;
+mod pagerank;
+mod params;
+mod popularity;
+mod preferences;
+mod links;
+mod locale;
 mod database;
+mod diagnostics;
+mod diversity;
+mod duplicates;
+mod empty_state;
+mod endpoints;
+mod escape;
+mod explain;
+mod experiments;
+mod export;
+mod http_client;
+mod idf;
+mod index;
+mod random;
+mod query_parser;
 mod ranking;
+mod rate_limit;
+mod replay;
+mod state;
 mod token_cache;
 mod timing;
+mod trace;
+mod ttl_cache;
+mod units;
 mod turnstile;
+mod warnings;
+mod warming;
 mod result_formatter;
-
+mod retry;
+mod search;
+mod semantic;
+mod signals;
+mod shadow;
+mod site;
+mod spam;
+mod spelling;
+mod stopwords;
+mod query_language;
+mod quantities;
+mod saved;
+mod suggest;
+mod synonyms;
+mod trending;
+
+use api_keys::ApiKeys;
+use arc_swap::ArcSwap;
+use background_writer::{ BackgroundWriter, LogSink };
+use clicks::{ ClickLogSink, ClickRates };
+use conditional::ConditionalJson;
+use config::{ Config, CorsConfig };
+use database::{ AppError, Tombstones };
+use experiments::Experiment;
+use http_client::OutboundClient;
+use retry::DatabaseGuard;
+use idf::DocumentFrequencies;
+use index::IndexStats;
+use pagerank::AuthorityScores;
+use params::{ SearchParams, SearchRequest };
+use popularity::QueryPopularity;
+use rate_limit::RateLimiter;
+use search::{ SearchOptions, SearchOutcome };
+use shadow::ShadowRanker;
+use spam::SpamList;
+use spelling::Vocabulary;
+use stopwords::Stopwords;
+use synonyms::Synonyms;
+use state::AppState;
 use token_cache::TokenCache;
-use timing::RequestTiming;
-use turnstile::validate_turnstile_token;
+use trending::{ QueryLogSink, TrendingQueries };
+use ttl_cache::TtlCache;
+use timing::{ Phase, RequestTiming };
+use trace::{ CacheOutcome, PipelineTrace, Refinement, SearchVariant };
+use warnings::Warnings;
 use result_formatter::format_result;
+use signals::ResultSignals;
 
 #[tokio::main]
 async fn main() {
+    println!("{}", build_info::BUILD_INFO.banner());
+
+    // `bench-scoring` times relevance scoring on synthetic candidates, without a database
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("bench-scoring") {
+        if let Err(e) = bench::run(&args[1..]) {
+            eprintln!("Benchmark failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Load environment variables
     dotenv().ok();
+    let config = Config::from_env();
+    println!("Config: {}", config.units_summary());
 
     // Set up database connection
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let pool = PgPool::connect(&database_url).await.expect("Failed to connect to database");
-    let website_count = database::count_websites(&pool).await.expect("Failed to count websites");
-
-    println!("Connected to database. Found {} websites.", website_count);
+    let pool = PgPoolOptions::new()
+        .acquire_timeout(config.db_acquire_timeout)
+        .connect(&database_url).await
+        .expect("Failed to connect to database");
+    if let Err(e) = database::check_schema(&pool).await {
+        panic!("Database doesn't match schema.sql: {}", e);
+    }
+    let soft_deletes = database::has_deleted_at(&pool).await.expect("Failed to inspect schema");
+    let tombstones = if soft_deletes { Tombstones::Exclude } else { Tombstones::Include };
+    let term_positions = database::has_term_positions(&pool).await.expect("Failed to inspect schema");
+    let original_forms = database::has_original_forms(&pool).await.expect("Failed to inspect schema");
+    let link_text = database::has_link_text(&pool).await.expect("Failed to inspect schema");
+    let stored_pagerank = database::has_stored_pagerank(&pool).await.expect("Failed to inspect schema");
+    let index = IndexStats::load(&pool, tombstones).await.expect("Failed to read the index");
+    let website_count = index.website_count;
+
+    if website_count == 0 {
+        println!("Connected to database. The index is empty, so searches will find nothing.");
+    } else {
+        println!("Connected to database. Found {} websites.", website_count);
+    }
+    if !soft_deletes {
+        println!("No deleted_at column, so tombstoned pages can't be told apart and are served.");
+    }
+    if !term_positions {
+        println!("No website_keyword_positions table, so NEAR only checks titles and descriptions.");
+    }
+    if !original_forms {
+        println!("No original_forms column, so case_sensitive only checks titles and descriptions.");
+    }
+    if !link_text {
+        println!("No link_text column, so anchor text doesn't rank the pages linked to.");
+    }
 
     // Load top domains
     let top_domains = load_top_domains("top-1m.txt").await.expect("Failed to load top domains");
     // println!("Top domains: {:?}", top_domains);
-    // Get max results from environment variable
-    let max_results: usize = std::env
-        ::var("MAX_RESULTS")
-        .unwrap_or_else(|_| "100".to_string())
-        .parse()
-        .expect("MAX_RESULTS must be a valid number");
 
-    // Initialize token cache
-    let token_cache = Arc::new(Mutex::new(TokenCache::new()));
+    // Build the shared outbound HTTP client
+    let http_client = OutboundClient::from_config(&config.http_client).expect(
+        "Failed to build HTTP client"
+    );
+
+    // Load API keys
+    let api_keys = match &config.api_keys_file {
+        Some(filename) => ApiKeys::load(filename).expect("Failed to load API keys"),
+        None => ApiKeys::default(),
+    };
+    println!("Loaded {} API keys.", api_keys.len());
+
+    // Load spam terms
+    let spam_list = match &config.spam.terms_file {
+        Some(filename) =>
+            SpamList::load(filename, &config.folding_exceptions).expect("Failed to load spam terms"),
+        None => SpamList::default(),
+    };
+    println!("Loaded {} spam terms.", spam_list.len());
+
+    // Load synonyms
+    let synonyms = match &config.synonyms_file {
+        Some(filename) =>
+            Synonyms::load(filename, &config.folding_exceptions).expect("Failed to load synonyms"),
+        None => Synonyms::default(),
+    };
+    println!("Loaded synonyms for {} words.", synonyms.len());
+
+    let stopwords = Stopwords::new(&config.folding_exceptions);
+
+    // Load the lemma maps queries detected to be in other languages are lemmatised with
+    let mut lemma_maps = HashMap::new();
+    for (language, filename) in &config.lemma_files {
+        let lemmas = lemmatise::load_lemma_map(filename).expect("Failed to load lemma map");
+        println!("Loaded {} lemmas for {}.", lemmas.len(), language.as_str());
+        lemma_maps.insert(*language, lemmas);
+    }
+
+    // Load the words spelling corrections are drawn from
+    let vocabulary = Vocabulary::load(&pool, config.spelling.vocabulary_size).await.expect(
+        "Failed to load vocabulary"
+    );
+    println!("Loaded {} words for spelling suggestions.", vocabulary.len());
+
+    let port = config.port;
+    let rate_limiter = RateLimiter::new(config.rate_limit.window);
+    let links_cache_ttl = config.links_cache_ttl;
+    let top_links_cache_ttl = config.top_links_cache_ttl;
+    let site_cache_ttl = config.site_cache_ttl;
+    let suggest_cache_ttl = config.suggest_cache_ttl;
+    let result_cache_ttl = config.result_cache.ttl;
+    let result_cache_capacity = config.result_cache.capacity;
+    let fragment_cache_ttl = config.fragment_cache.ttl;
+    let fragment_cache_bytes = config.fragment_cache.max_bytes;
+    let max_refinements = config.two_tier.max_background;
+
+    // Writers are only stopped once the server has finished its last requests, so nothing
+    // those requests log is lost
+    let (writers_shutdown_tx, writers_shutdown_rx) = watch::channel(false);
+    let slow_query_log_file = config.slow_query_log_file.clone();
+    let (slow_query_log, slow_query_log_task) = BackgroundWriter::spawn(
+        "Slow query log",
+        &config.log_writer,
+        move || LogSink::new(slow_query_log_file.as_deref(), "Slow query: "),
+        writers_shutdown_rx.clone()
+    );
+    let experiment = Experiment::new(&config.experiment);
+    let shadow = ShadowRanker::new(&config.shadow);
+    let shadow_log_file = config.shadow.log_file.clone();
+    let (shadow_log, shadow_log_task) = BackgroundWriter::spawn(
+        "Shadow ranking log",
+        &config.log_writer,
+        move || LogSink::new(shadow_log_file.as_deref(), "Shadow ranking: "),
+        writers_shutdown_rx.clone()
+    );
+    let capture_file = config.capture_file.clone();
+    let (capture_log, capture_log_task) = BackgroundWriter::spawn(
+        "Capture log",
+        &config.log_writer,
+        move || LogSink::new(capture_file.as_deref(), "Capture: "),
+        writers_shutdown_rx.clone()
+    );
+    let access_log_file = config.access_log_file.clone();
+    let (access_log, access_log_task) = BackgroundWriter::spawn(
+        "Access log",
+        &config.log_writer,
+        move || LogSink::new(access_log_file.as_deref(), "Access: "),
+        writers_shutdown_rx.clone()
+    );
+    let query_log_pool = pool.clone();
+    let (query_log, query_log_task) = BackgroundWriter::spawn(
+        "Query log",
+        &config.log_writer,
+        move || QueryLogSink::new(query_log_pool.clone()),
+        writers_shutdown_rx.clone()
+    );
+    let click_log_pool = pool.clone();
+    let click_half_life = config.clicks.half_life;
+    let (click_log, click_log_task) = BackgroundWriter::spawn(
+        "Click log",
+        &config.log_writer,
+        move || ClickLogSink::new(click_log_pool.clone(), click_half_life),
+        writers_shutdown_rx
+    );
+    let query_log_salt = config.trending.salt.clone().unwrap_or_else(trending::random_salt);
+    let state = Arc::new(AppState {
+        pool,
+        database: DatabaseGuard::new(&config.db_retry),
+        soft_deletes,
+        index: ArcSwap::from_pointee(index),
+        index_generation: watch::Sender::new(0),
+        term_positions,
+        original_forms,
+        link_text,
+        top_domains,
+        config,
+        http_client,
+        token_cache: TokenCache::new(),
+        api_keys,
+        rate_limiter,
+        links_cache: TtlCache::new(links_cache_ttl, 1000),
+        top_links_cache: TtlCache::new(top_links_cache_ttl, 16),
+        site_cache: TtlCache::new(site_cache_ttl, 1000),
+        suggest_cache: TtlCache::new(suggest_cache_ttl, 10_000),
+        result_cache: TtlCache::new(result_cache_ttl, result_cache_capacity),
+        fragment_cache: TtlCache::weighted(
+            fragment_cache_ttl,
+            fragment_cache_bytes,
+            result_formatter::fragment_weight
+        ),
+        refinement_permits: Arc::new(Semaphore::new(max_refinements)),
+        export_permits: Arc::new(Semaphore::new(1)),
+        popularity: QueryPopularity::new(10_000),
+        authority: ArcSwap::from_pointee(AuthorityScores::default()),
+        authority_generation: watch::Sender::new(0),
+        stored_pagerank,
+        pagerank_permits: Arc::new(Semaphore::new(1)),
+        spam_list: ArcSwap::from_pointee(spam_list),
+        synonyms,
+        lemma_maps,
+        stopwords,
+        vocabulary,
+        slow_query_log,
+        capturing: AtomicBool::new(false),
+        capture_log,
+        access_log,
+        experiment,
+        shadow,
+        shadow_log,
+        query_log,
+        query_log_salt,
+        trending: ArcSwap::from_pointee(TrendingQueries::default()),
+        click_log,
+        click_rates: ArcSwap::from_pointee(ClickRates::default()),
+        document_frequencies: ArcSwap::from_pointee(DocumentFrequencies::default()),
+    });
+
+    // `replay <file>` re-runs captured searches against this build instead of serving
+    if args.first().map(String::as_str) == Some("replay") {
+        // Rank with authority, as the captured searches most likely were
+        pagerank::run_pagerank(state.clone()).await;
+        if let Err(e) = replay::run(&state, &args[1..]).await {
+            eprintln!("Replay failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Results are ranked without authority until the first computation finishes
+    tokio::spawn(pagerank::run_pagerank(state.clone()));
 
-    // Set up CORS
-    let cors = create_cors_layer();
+    // Keep popular queries warm in the result cache until shutdown
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(warming::run_cache_warming(state.clone(), shutdown_rx.clone()));
+    tokio::spawn(trending::run_aggregation(state.clone(), shutdown_rx.clone()));
+    tokio::spawn(clicks::run_refresh(state.clone(), shutdown_rx.clone()));
+    tokio::spawn(idf::run_refresh(state.clone(), shutdown_rx.clone()));
+    tokio::spawn(index::run_refresh(state.clone(), shutdown_rx.clone()));
+    tokio::spawn(pagerank::recompute_on_refresh(state.clone(), shutdown_rx));
 
     // Set up the Axum router
-    let app = create_router(pool, website_count, top_domains, max_results, token_cache, cors);
+    let app = create_router(state);
 
     // Start the server
-    let port: u16 = std::env
-        ::var("AXUM_PORT")
-        .unwrap_or_else(|_| "3000".to_string())
-        .parse()
-        .expect("AXUM_PORT must be a valid number");
-
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await.unwrap();
     println!("Listening on: http://{}", listener.local_addr().unwrap());
-    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+    axum
+        ::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal(shutdown_tx)).await
+        .unwrap();
+
+    // Flush the background writers before exiting
+    writers_shutdown_tx.send(true).ok();
+    slow_query_log_task.await.ok();
+    capture_log_task.await.ok();
+    access_log_task.await.ok();
+    shadow_log_task.await.ok();
+    query_log_task.await.ok();
+    click_log_task.await.ok();
 }
 
-fn create_cors_layer() -> CorsLayer {
+/// Waits for Ctrl+C or SIGTERM, then tells background tasks to stop.
+async fn shutdown_signal(shutdown: watch::Sender<bool>) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix
+            ::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv().await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
+    println!("Shutting down.");
+    shutdown.send(true).ok();
+}
+
+fn create_cors_layer(config: &CorsConfig) -> CorsLayer {
+    let origins: Vec<HeaderValue> = config.allowed_origins
+        .iter()
+        .map(|origin| origin.parse().expect("CORS_ALLOWED_ORIGINS must contain valid origins"))
+        .collect();
+    let methods: Vec<Method> = config.public_methods
+        .iter()
+        .map(|method| method.parse().expect("CORS_PUBLIC_METHODS must contain valid methods"))
+        .collect();
+
     CorsLayer::new()
-        .allow_origin(
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(
             vec![
-                "http://localhost:3000".parse::<HeaderValue>().unwrap(),
-                "http://localhost:3001".parse::<HeaderValue>().unwrap(),
-                "http://search.ylokhmotov.dev".parse::<HeaderValue>().unwrap(),
-                "https://search.ylokhmotov.dev".parse::<HeaderValue>().unwrap()
+                header::CONTENT_TYPE,
+                header::AUTHORIZATION,
+                api_keys::API_KEY_HEADER.clone(),
+                auth::TURNSTILE_TOKEN_HEADER.clone(),
+                preferences::PREFS_HEADER.clone(),
+                HeaderName::from_static("x-request-id")
             ]
         )
-        .allow_methods(vec![Method::GET])
-        .allow_headers(vec![axum::http::header::CONTENT_TYPE])
+        .expose_headers(
+            vec![
+                HeaderName::from_static("x-request-id"),
+                HeaderName::from_static("server-timing"),
+                build_info::VERSION_HEADER.clone(),
+                HeaderName::from_static("ratelimit-limit"),
+                HeaderName::from_static("ratelimit-remaining"),
+                HeaderName::from_static("ratelimit-reset")
+            ]
+        )
+        .max_age(config.max_age)
+        .allow_credentials(config.allow_credentials)
 }
 
-fn create_router(
-    pool: PgPool,
-    website_count: i64,
-    top_domains: HashMap<String, usize>,
-    max_results: usize,
-    token_cache: Arc<Mutex<TokenCache>>,
-    cors: CorsLayer
-) -> Router {
+fn create_router(state: Arc<AppState>) -> Router {
+    // Public routes are reachable from the configured browser origins.
+    // Admin routes must be merged outside this group so they never get CORS headers.
+    let public_routes = Router::new()
+        .route(
+            "/",
+            get(search)
+                .route_layer(axum::middleware::from_fn(auth::require_turnstile))
+                .layer(axum::middleware::map_response(build_info::add_version_header))
+        )
+        .route(
+            "/blend",
+            post(blend::blend)
+                .route_layer(axum::middleware::from_fn(auth::require_turnstile))
+                .layer(axum::middleware::map_response(build_info::add_version_header))
+        )
+        .route("/openapi.json", get(openapi))
+        .route("/stats", get(stats))
+        .route("/version", get(build_info::version))
+        .route(
+            "/links",
+            get(links::get_links).route_layer(axum::middleware::from_fn(auth::require_turnstile))
+        )
+        .route(
+            "/site/:domain",
+            get(site::site_pages).route_layer(axum::middleware::from_fn(auth::require_turnstile))
+        )
+        .route("/saved", post(saved::save))
+        .route(
+            "/saved/:name/run",
+            get(saved::run)
+                .route_layer(axum::middleware::from_fn(auth::require_turnstile))
+                .layer(axum::middleware::map_response(build_info::add_version_header))
+        )
+        .route(
+            "/trending",
+            get(trending::trending).route_layer(axum::middleware::from_fn(auth::require_turnstile))
+        )
+        .route(
+            "/click",
+            post(clicks::click).route_layer(axum::middleware::from_fn(auth::require_turnstile))
+        )
+        .route(
+            "/suggest",
+            get(suggest::suggest).route_layer(axum::middleware::from_fn(auth::require_turnstile))
+        )
+        .route(
+            "/random",
+            get(random::random_pages).route_layer(axum::middleware::from_fn(auth::require_turnstile))
+        )
+        .layer(axum::middleware::from_fn(rate_limit::limit_requests))
+        .layer(create_cors_layer(&state.config.cors));
+
+    // Admin routes are for operators only: no CORS and no public rate limits
+    let admin_routes = Router::new()
+        .route("/admin/links/top", get(admin::top_links))
+        .route("/admin/spam/reload", post(admin::reload_spam_terms))
+        .route("/admin/pagerank", post(pagerank::recompute_in_background))
+        .route("/admin/metrics", get(admin::metrics))
+        .route("/admin/capture", post(capture::set_capture))
+        .route(
+            "/admin/cache/results",
+            get(admin::cached_results).delete(admin::evict_results)
+        )
+        .route("/admin/export/urls", get(export::export_urls).layer(CompressionLayer::new()))
+        .route_layer(axum::middleware::from_fn(auth::require_admin));
+
     Router::new()
-        .route("/", get(search))
-        .layer(Extension(pool))
-        .layer(Extension(website_count))
-        .layer(Extension(top_domains))
-        .layer(Extension(max_results))
-        .layer(Extension(Client::new()))
-        .layer(Extension(token_cache))
-        .layer(cors)
+        .merge(public_routes)
+        .merge(admin_routes)
+        .layer(axum::middleware::from_fn(access_log::log_requests))
+        .layer(Extension(state))
         .layer(axum::middleware::map_request(timing_middleware))
 }
 
@@ -112,192 +517,353 @@ async fn timing_middleware(
     mut request: axum::http::Request<axum::body::Body>
 ) -> axum::http::Request<axum::body::Body> {
     // Add timing information to the request extensions
-    let timing = RequestTiming {
-        start: Some(Instant::now()),
-        ..Default::default()
-    };
-    request.extensions_mut().insert(timing);
+    request.extensions_mut().insert(RequestTiming::started());
     request
 }
 
+/// Invalid parameters are rejected with a `400` by the `SearchRequest` extractor before this runs.
 async fn search(
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Query(params): Query<HashMap<String, String>>,
-    Extension(pool): Extension<PgPool>,
-    Extension(website_count): Extension<i64>,
-    Extension(top_domains): Extension<HashMap<String, usize>>,
-    Extension(max_results): Extension<usize>,
-    Extension(client): Extension<Client>,
-    Extension(token_cache): Extension<Arc<Mutex<TokenCache>>>,
-    mut timing: Extension<RequestTiming>
-) -> Json<Value> {
-    let search_start = Instant::now();
+    Extension(state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+    timing: Option<Extension<RequestTiming>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: SearchRequest
+) -> Response {
+    let SearchRequest { params: search_params, warnings } = request;
+    // Without a query, describe the service instead of searching
+    let Some(search_params) = search_params else {
+        return Json(endpoints::service_descriptor(&state.config)).into_response();
+    };
+    let client = connect_info.map(|ConnectInfo(addr)| addr.ip());
+    run_search(&state, &headers, timing, client, search_params, warnings, "/").await
+}
 
-    // Extract query parameters
-    let (query, include_links, num_results) = extract_query_params(&params, max_results);
+/// Runs a search with resolved parameters and builds its response, for `GET /` and saved
+/// searches. `client` is who searched, for the query log, and `endpoint` names the route in the
+/// slow-query log.
+async fn run_search(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    timing: Option<Extension<RequestTiming>>,
+    client: Option<IpAddr>,
+    mut search_params: SearchParams,
+    mut warnings: Warnings,
+    endpoint: &str
+) -> Response {
+    // Timing is reported as zero rather than failing if the middleware didn't run
+    let mut timing = timing.map(|Extension(timing)| timing).unwrap_or_default();
 
-    // Validate Turnstile token
-    let turnstile_start = Instant::now();
-    let turnstile_token = params.get("token").expect("Missing Turnstile token");
-    let ip = addr.ip().to_string();
-    if !validate_token(&client, turnstile_token, &ip, &token_cache).await {
-        return Json(json!({ "error": "Invalid Turnstile token" }));
+    let search_start = Instant::now();
+    let query = search_params.query.clone();
+    if let (true, Some(mode)) = (query.is_empty(), search_params.empty) {
+        return empty_state::respond(state, &search_params, mode, warnings).await;
+    }
+
+    let is_admin = state.config.admin_token
+        .as_deref()
+        .is_some_and(|admin_token| auth::is_admin(headers, admin_token));
+    // Trusted callers may send queries that look up more keywords, and ask for signals
+    let privileged = is_admin || state.api_keys.find_in_headers(headers).is_some();
+    let keyword_budget = if privileged {
+        state.config.privileged_keyword_budget
+    } else {
+        state.config.keyword_budget
+    };
+    if search_params.signals && !privileged {
+        warnings.push(
+            warnings::INVALID_PARAMETER,
+            "signals requires an API key and was ignored".to_string(),
+            Some("signals")
+        );
+        search_params.signals = false;
+    }
+    search_params.preferences.add_header(headers, &mut warnings);
+    // Searches that don't name a profile are bucketed into the experiment's variants, by `bucket`
+    // or else by their client's address, and ranked with their variant's profile
+    let assignment = state.experiment
+        .as_ref()
+        .filter(|_| search_params.profile.is_none())
+        .and_then(|experiment| {
+            let bucket = search_params.bucket.or_else(|| {
+                client.map(|client| experiment.bucket_for(client, &state.config.client_identity))
+            })?;
+            Some(experiment.assign(bucket))
+        });
+    if let Some(assignment) = &assignment {
+        search_params.profile = assignment.profile.clone();
+    }
+    if search_params.include_deleted && !is_admin {
+        warnings.push(
+            warnings::INVALID_PARAMETER,
+            "include_deleted requires the admin token and was ignored".to_string(),
+            Some("include_deleted")
+        );
     }
-    timing.turnstile_validation = Some(turnstile_start.elapsed());
+    if search_params.url_regex.is_some() && !is_admin {
+        warnings.push(
+            warnings::INVALID_PARAMETER,
+            "url_regex requires the admin token and was ignored".to_string(),
+            Some("url_regex")
+        );
+    }
+    let search_options = search_params.search_options(keyword_budget, is_admin);
+
+    // The pipeline trace is always collected for the slow-query log, but only returned on request
+    let debug = search_params.debug || is_admin;
+    let mut trace = PipelineTrace::default();
+
+    // A follow-up to a two-tier search gets the completed ranking once it's ready
+    let refined = search_params.continuation
+        .as_deref()
+        .and_then(|token| state.result_cache.get(&search::continuation_cache_key(token)));
+    let mut execution_time = None;
+    let warnings_before_search = warnings.len();
 
     // Perform search
-    let search_result = perform_search(
-        &query,
-        &pool,
-        website_count,
-        &top_domains,
-        include_links,
-        num_results,
-        &mut timing
-    ).await;
+    let (mut outcome, mut continuation) = match refined {
+        Some(outcome) => {
+            trace.cache = CacheOutcome::ContinuationHit;
+            (outcome.as_ref().clone(), None)
+        }
+        None if search_params.two_tier => {
+            trace.variant = SearchVariant::TwoTier;
+            let outcome = search::perform_search(
+                &query,
+                state,
+                search_options.clone(),
+                Some(state.config.two_tier.candidate_limit),
+                &mut timing,
+                &mut trace,
+                &mut warnings
+            ).await;
+            let outcome = match outcome {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    return e.response("Failed to search");
+                }
+            };
+            // Nothing to refine if the quick fetch already saw every candidate
+            let continuation = if outcome.complete {
+                None
+            } else {
+                search::refine_in_background(state, &query, search_options.clone())
+            };
+            trace.refinement = match (&continuation, outcome.complete) {
+                (Some(_), _) => Refinement::Scheduled,
+                (None, true) => Refinement::NotNeeded,
+                (None, false) => Refinement::Saturated,
+            };
+            (outcome, continuation)
+        }
+        None => {
+            let searched = cached_search(
+                state,
+                &query,
+                &search_options,
+                &mut timing,
+                &mut trace,
+                &mut warnings
+            ).await;
+            let (outcome, executed) = match searched {
+                Ok(searched) => searched,
+                Err(e) => {
+                    return e.response("Failed to search");
+                }
+            };
+            execution_time = executed;
+            (outcome, None)
+        }
+    };
 
-    timing.total_search_function = Some(search_start.elapsed());
+    // Suggest a correction for misspelt terms when the query found little, and serve its
+    // results instead if asked to and it finds more. Exact terms are never corrected, since
+    // corrections are lemmas.
+    let did_you_mean = if
+        outcome.results.len() < state.config.spelling.suggest_below &&
+        !search_options.exact
+    {
+        spelling::suggest(state, &query, &outcome.keywords).await
+    } else {
+        None
+    };
+    let mut autocorrected = false;
+    if let (Some(corrected), true) = (&did_you_mean, search_params.autocorrect) {
+        // The warnings and trace are for whichever search is served
+        let mut corrected_trace = PipelineTrace::default();
+        let warnings_before_correction = warnings.len();
+        let corrected_search = cached_search(
+            state,
+            corrected,
+            &search_options,
+            &mut timing,
+            &mut corrected_trace,
+            &mut warnings
+        ).await;
+        // A correction that failed to search is no better than the original
+        match corrected_search {
+            Ok((corrected_outcome, _)) if
+                corrected_outcome.results.len() > outcome.results.len()
+            => {
+                warnings.remove(warnings_before_search..warnings_before_correction);
+                outcome = corrected_outcome;
+                trace = corrected_trace;
+                continuation = None;
+                autocorrected = true;
+            }
+            _ => {
+                warnings.remove(warnings_before_correction..warnings.len());
+            }
+        }
+    }
+    let search_result = &outcome.results;
+    let normalised_query = search::normalise_query(&query);
+    state.popularity.record(&normalised_query, execution_time);
+    trending::record(state, &normalised_query, client);
+    clicks::record_impressions(state, search_result);
+    trace.returned = search_result.len();
+
+    timing.record(Phase::TotalSearchFunction, search_start.elapsed());
+
+    let total_request_time = timing.elapsed();
+    if total_request_time >= state.config.slow_query_threshold {
+        search::log_slow_query(state, endpoint, json!(query), total_request_time, trace);
+    }
 
-    let total_request_time = timing.start.unwrap().elapsed();
+    let applied = search_params.applied(
+        &search_options,
+        trace.variant,
+        &outcome,
+        debug
+    );
+    let experiment = assignment.as_ref().map(|assignment| assignment.to_json());
+    // Captures are tagged with the variant on their own copy, so they can be compared by variant
+    // offline without changing the response's `applied_options`
+    let mut captured = applied.clone();
+    if let Some(experiment) = &experiment {
+        captured["experiment"] = experiment.clone();
+    }
+    capture::record(state, &captured, search_result);
 
     // Create the response JSON directly
-    Json(
+    let options = search_params.result_options();
+    let mut response =
         json!({
         "query": query,
-        "lemmatised_keywords": [], // Update this if you want to include lemmatized keywords
+        "lemmatised_keywords": outcome.keywords,
+        "parsed_query": outcome.parsed.to_json(),
         "matching_webpages": search_result.len(),
+        "filtered_below_threshold": outcome.below_threshold,
         "time_taken": timing::format_timing_info(&timing, total_request_time),
-        "website_count": website_count,
-        "results": search_result.iter().map(|(score, webpage)| 
-            format_result(score, webpage, &top_domains, include_links)).collect::<Vec<_>>(),
-    })
-    )
-}
-
-// Helper functions (implement these in separate modules)
-
-fn extract_query_params(
-    params: &HashMap<String, String>,
-    max_results: usize
-) -> (String, bool, usize) {
-    let query = params.get("q").expect("Missing query parameter").to_string();
-    let include_links = params
-        .get("links")
-        .map(|v| v == "true")
-        .unwrap_or(false);
-    let num_results = params
-        .get("results")
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(100)
-        .min(max_results);
-    (query, include_links, num_results)
-}
-
-async fn validate_token(
-    client: &Client,
-    token: &str,
-    ip: &str,
-    token_cache: &Arc<Mutex<TokenCache>>
-) -> bool {
-    let mut cache = token_cache.lock().await;
-    if !cache.is_valid(token, ip) {
-        if !validate_turnstile_token(client, token).await {
-            println!("Token validation failed for IP: {}", ip);
-            return false;
+        "website_count": state.index.load().website_count,
+        "complete": outcome.complete,
+        "continuation": continuation,
+        "applied_options": applied,
+        "related_searches": outcome.related_searches,
+    });
+    if let Some(experiment) = experiment {
+        response["experiment"] = experiment;
+    }
+    if let Some(did_you_mean) = &did_you_mean {
+        response["did_you_mean"] = json!(did_you_mean);
+        response["autocorrected"] = json!(autocorrected);
+    }
+    if debug {
+        response["debug"] = json!({ "pipeline": trace.to_json() });
+    }
+    if search_params.signals {
+        response["signals_version"] = json!(signals::SIGNALS_VERSION);
+    }
+    if search_result.is_empty() {
+        response["no_results"] = diagnostics::no_results(
+            state,
+            &outcome.keywords,
+            &trace,
+            search_options
+        ).await;
+    }
+    warnings.add_to(&mut response);
+    let now = chrono::Utc::now();
+    let results: Vec<Value> = search_result
+        .iter()
+        .map(|(score, webpage)| {
+            let mut result = format_result(score, webpage, &state.top_domains, &state.fragment_cache, options);
+            if search_params.signals {
+                result["signals"] = ResultSignals::compute(
+                    webpage,
+                    &outcome.keywords,
+                    state.index.load().website_count,
+                    &state.top_domains,
+                    now
+                ).to_json();
+            }
+            result
+        })
+        .collect();
+    match search_params.max_bytes {
+        Some(max_bytes) => {
+            response = budget::fit_to_budget(response, results, max_bytes);
+        }
+        None => {
+            response["results"] = json!(results);
         }
-        cache.add_token(token.to_string(), ip.to_string());
     }
-    cache.clean_old_tokens();
-    true
+    Json(response).into_response()
 }
 
-async fn perform_search(
+/// Runs a full search through the result cache, returning how long the pipeline took if it ran.
+/// A failed search is never cached.
+async fn cached_search(
+    state: &Arc<AppState>,
     query: &str,
-    pool: &PgPool,
-    website_count: i64,
-    top_domains: &HashMap<String, usize>,
-    include_links: bool,
-    num_results: usize,
-    timing: &mut RequestTiming
-) -> Vec<(f32, database::Webpage)> {
-    // Lemmatize the query
-    let lemmatise_time = Instant::now();
-    let keywords = lemmatise::lemmatise_string(query);
-    timing.lemmatisation = Some(lemmatise_time.elapsed());
-
-    // Fetch webpages from the database (without links initially)
-    let db_time = Instant::now();
-    let webpages = match database::fetch_webpages(pool, &keywords, false).await {
-        Ok(webpages) => webpages,
-        Err(e) => {
-            eprintln!("Error fetching webpages: {}", e);
-            return vec![];
-        }
-    };
-    timing.initial_database_query = Some(db_time.elapsed());
-
-    // Calculate TF-IDF scores and rank webpages
-    let tfidf_time = Instant::now();
-    let mut ranked_webpages = ranking::get_tf_idf_scores(website_count, &keywords, &webpages).await;
-
-    // Sort ranked_webpages by score in descending order
-    ranked_webpages.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-
-    // Count webpages with score >= 1.0
-    let high_score_count = ranked_webpages
-        .iter()
-        .take_while(|(score, _)| *score >= 1.0)
-        .count();
-
-    // Sort webpages with score >= 1.0 by score first, then by website rank
-    if high_score_count > 0 {
-        ranked_webpages[..high_score_count].sort_by(|a, b| {
-            b.0
-                .partial_cmp(&a.0)
-                .unwrap()
-                .then_with(|| {
-                    let domain_a = result_formatter::extract_domain_from_string(&a.1.url);
-                    let domain_b = result_formatter::extract_domain_from_string(&b.1.url);
-                    let rank_a = domain_a
-                        .and_then(|d| top_domains.get(&d).cloned())
-                        .unwrap_or(usize::MAX);
-                    let rank_b = domain_b
-                        .and_then(|d| top_domains.get(&d).cloned())
-                        .unwrap_or(usize::MAX);
-                    rank_a.cmp(&rank_b)
-                })
-        });
+    search_options: &SearchOptions,
+    timing: &mut RequestTiming,
+    trace: &mut PipelineTrace,
+    warnings: &mut Warnings
+) -> Result<(SearchOutcome, Option<Duration>), AppError> {
+    let cache_key = search::search_cache_key(query, search_options);
+    if let Some(outcome) = state.result_cache.get(&cache_key) {
+        trace.cache = CacheOutcome::ResultHit;
+        return Ok((outcome.as_ref().clone(), None));
     }
 
-    // Determine the number of results to return
-    let results_to_return = high_score_count.min(num_results);
-
-    // Limit the number of results
-    ranked_webpages.truncate(results_to_return);
-    timing.tf_idf_calculation = Some(tfidf_time.elapsed());
-
-    // Fetch links for top results if requested
-    if include_links {
-        let link_time = Instant::now();
-        let webpage_ids: Vec<i32> = ranked_webpages
-            .iter()
-            .map(|(_, webpage)| webpage.id)
-            .collect();
-
-        let links = database::fetch_links_for_ids(pool, &webpage_ids).await.unwrap_or_default();
-
-        for (_score, webpage) in &mut ranked_webpages {
-            if let Some((links_to_count, links_from)) = links.get(&webpage.id) {
-                webpage.links_to_count = Some(*links_to_count);
-                webpage.links_from = Some(links_from.clone());
-            }
-        }
-        timing.link_fetching = Some(link_time.elapsed());
+    let execution_start = Instant::now();
+    let warnings_before = warnings.len();
+    // Only full searches are shadowed, since they fetch every candidate
+    let options = SearchOptions { shadow: state.shadow.sample(), ..search_options.clone() };
+    let outcome =
+        search::perform_search(query, state, options, None, timing, trace, warnings).await?;
+    // Don't cache results degraded by a stage that had to be skipped partway through
+    if warnings.len() == warnings_before {
+        state.result_cache.insert(cache_key, Arc::new(outcome.clone()));
     }
+    Ok((outcome, Some(execution_start.elapsed())))
+}
+
+async fn openapi(Extension(state): Extension<Arc<AppState>>) -> Json<Value> {
+    Json(endpoints::openapi_document(&state.config))
+}
 
-    ranked_webpages
+async fn stats(Extension(state): Extension<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let index = state.index.load();
+    let indexed_at = index.indexed_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    // An empty index still serves every endpoint, it just can't find anything
+    let degraded_reasons: Vec<&str> = if index.website_count == 0 { vec!["empty_index"] } else { vec![] };
+    let body =
+        json!({
+        "status": if degraded_reasons.is_empty() { "ok" } else { "degraded" },
+        "degraded_reasons": degraded_reasons,
+        "website_count": index.website_count,
+        "top_domains": state.top_domains.len(),
+        "max_results": state.config.max_results,
+        "indexed_at": indexed_at,
+        "git_hash": build_info::BUILD_INFO.git_hash(),
+    });
+
+    ConditionalJson::new(&body, index.indexed_at, state.config.stats_max_age).respond(&headers)
 }
 
+// Helper functions (implement these in separate modules)
+
 async fn load_top_domains(filename: &str) -> io::Result<HashMap<String, usize>> {
     let file = File::open(filename).await?;
     let reader = io::BufReader::new(file);
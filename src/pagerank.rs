@@ -0,0 +1,460 @@
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    response::{ IntoResponse, Json, Response },
+};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::watch;
+use crate::config::PageRankConfig;
+use crate::database;
+use crate::state::AppState;
+
+/// A score for each indexed page, stored compactly as parallel id and score arrays
+#[derive(Debug, Clone, Default)]
+pub struct PageScores {
+    ids: Vec<i32>,
+    scores: Vec<f32>,
+    max_score: f32,
+}
+
+impl PageScores {
+    fn new(ids: Vec<i32>, scores: Vec<f32>) -> Self {
+        let max_score = scores.iter().cloned().fold(0.0, f32::max);
+        PageScores { ids, scores, max_score }
+    }
+
+    /// Returns the page's score relative to the highest, from 0 to 1. Pages without a score
+    /// get 0.
+    fn normalised(&self, webpage_id: i32) -> f32 {
+        if self.max_score <= 0.0 {
+            return 0.0;
+        }
+        match self.ids.binary_search(&webpage_id) {
+            Ok(index) => self.scores[index] / self.max_score,
+            Err(_) => 0.0,
+        }
+    }
+}
+
+/// Link-graph authority for each indexed page: its PageRank, and with `HITS_ENABLED` its HITS
+/// hub and authority scores
+#[derive(Debug, Default)]
+pub struct AuthorityScores {
+    pagerank: PageScores,
+    /// How well each page links to good authorities, as directories and link lists do
+    hubs: PageScores,
+    /// How much each page is linked to by good hubs
+    authorities: PageScores,
+}
+
+/// A page's HITS scores, each relative to the highest, from 0 to 1
+#[derive(Debug, Clone, Copy)]
+pub struct HitsScores {
+    pub hub: f32,
+    pub authority: f32,
+}
+
+impl AuthorityScores {
+    /// Whether scores have been computed yet.
+    pub fn is_published(&self) -> bool {
+        !self.pagerank.ids.is_empty()
+    }
+
+    /// Returns the page's authority relative to the most authoritative page, from 0 to 1.
+    /// Pages without a score (including all pages before scores are published) get 0.
+    pub fn normalised(&self, webpage_id: i32) -> f32 {
+        self.pagerank.normalised(webpage_id)
+    }
+
+    /// Whether HITS scores have been computed yet.
+    pub fn has_hits(&self) -> bool {
+        !self.hubs.ids.is_empty()
+    }
+
+    /// The page's HITS scores, or `None` before they're computed. Pages without links get 0.
+    pub fn hits(&self, webpage_id: i32) -> Option<HitsScores> {
+        self.has_hits().then(|| HitsScores {
+            hub: self.hubs.normalised(webpage_id),
+            authority: self.authorities.normalised(webpage_id),
+        })
+    }
+}
+
+/// Publishes authority scores at startup: those stored by the last computation if the schema
+/// has `website_pagerank` and it isn't empty, or freshly computed ones otherwise.
+pub async fn run_pagerank(state: Arc<AppState>) {
+    if !state.config.pagerank.enabled {
+        return;
+    }
+    let mut pagerank = true;
+    if state.stored_pagerank {
+        match database::fetch_pagerank_scores(&state.pool).await {
+            Ok((ids, scores)) if !ids.is_empty() => {
+                println!("Loaded {} stored authority scores.", ids.len());
+                publish(&state, Some(PageScores::new(ids, scores)), None);
+                pagerank = false;
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Error loading stored authority scores: {}", e),
+        }
+    }
+    // HITS scores aren't stored, so they're computed even when PageRank was loaded
+    if !pagerank && !state.config.pagerank.hits {
+        return;
+    }
+    let Ok(permit) = state.pagerank_permits.clone().try_acquire_owned() else {
+        return;
+    };
+    recompute(&state, pagerank).await;
+    drop(permit);
+}
+
+/// Recomputes authority each time the index changes, so scores follow the link graph as the
+/// crawler updates it, until shutdown. A change during a computation is picked up once it
+/// finishes.
+pub async fn recompute_on_refresh(state: Arc<AppState>, mut shutdown: watch::Receiver<bool>) {
+    if !state.config.pagerank.enabled {
+        return;
+    }
+    let mut generation = state.index_generation.subscribe();
+
+    loop {
+        tokio::select! {
+            changed = generation.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+            _ = shutdown.changed() => {
+                break;
+            }
+        }
+
+        let permit = tokio::select! {
+            permit = state.pagerank_permits.clone().acquire_owned() => permit,
+            _ = shutdown.changed() => {
+                break;
+            }
+        };
+        let Ok(permit) = permit else {
+            break;
+        };
+        recompute(&state, true).await;
+        drop(permit);
+    }
+}
+
+/// `POST /admin/pagerank`: recomputes authority from the current link graph in the background,
+/// replacing the stored scores. Searches keep the previous scores until it finishes.
+pub async fn recompute_in_background(Extension(state): Extension<Arc<AppState>>) -> Response {
+    if !state.config.pagerank.enabled {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "PageRank is off" })),
+        ).into_response();
+    }
+    let permit = match state.pagerank_permits.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(json!({ "error": "PageRank is already being computed" })),
+            ).into_response();
+        }
+    };
+    tokio::spawn(async move {
+        recompute(&state, true).await;
+        drop(permit);
+    });
+    (StatusCode::ACCEPTED, Json(json!({ "started": true }))).into_response()
+}
+
+/// Loads the link graph, computes PageRank over it if `pagerank`, and HITS if `HITS_ENABLED`,
+/// publishes the scores into `state.authority` and stores the PageRank scores, if the schema
+/// has `website_pagerank`.
+///
+/// Only page ids and edge indices are held in memory; URLs are resolved to ids in the database.
+async fn recompute(state: &Arc<AppState>, pagerank: bool) {
+    let config = state.config.pagerank.clone();
+    let start = Instant::now();
+    let (ids, edges) = match load_graph(state, &config).await.map_err(|e| e.to_string()) {
+        Ok(graph) => graph,
+        Err(e) => {
+            eprintln!("Error loading link graph for PageRank: {}", e);
+            return;
+        }
+    };
+    println!(
+        "Loaded link graph of {} pages and {} links in {:.1?}.",
+        ids.len(),
+        edges.len(),
+        start.elapsed()
+    );
+
+    // Power iteration is CPU-bound, so keep it off the async workers
+    let node_count = ids.len();
+    let computed = tokio::task::spawn_blocking(move || {
+        let scores = pagerank.then(|| {
+            compute_pagerank(node_count, &edges, config.iterations, config.damping)
+        });
+        let hits = config.hits.then(|| compute_hits(node_count, &edges, config.hits_iterations));
+        (scores, hits)
+    }).await;
+    let (scores, hits) = match computed {
+        Ok(computed) => computed,
+        Err(e) => {
+            eprintln!("PageRank computation failed: {}", e);
+            return;
+        }
+    };
+
+    if let (Some(scores), true) = (&scores, state.stored_pagerank) {
+        let stored = database::store_pagerank_scores(
+            &state.pool,
+            &ids,
+            scores,
+            config.batch_size as usize
+        ).await;
+        if let Err(e) = stored {
+            eprintln!("Error storing authority scores: {}", e);
+        }
+    }
+    let pagerank = scores.map(|scores| PageScores::new(ids.clone(), scores));
+    let hits = hits.map(|(hubs, authorities)| {
+        (PageScores::new(ids.clone(), hubs), PageScores::new(ids, authorities))
+    });
+    publish(state, pagerank, hits);
+    println!("Published authority scores in {:.1?}.", start.elapsed());
+}
+
+/// Publishes new PageRank scores, new HITS hub and authority scores, or both, keeping the
+/// published ones of whichever isn't given.
+fn publish(state: &AppState, pagerank: Option<PageScores>, hits: Option<(PageScores, PageScores)>) {
+    let current = state.authority.load();
+    let pagerank = pagerank.unwrap_or_else(|| current.pagerank.clone());
+    let (hubs, authorities) = hits.unwrap_or_else(|| {
+        (current.hubs.clone(), current.authorities.clone())
+    });
+    let replaced = state.authority.swap(Arc::new(AuthorityScores { pagerank, hubs, authorities }));
+    // Cached rankings were ordered with the scores replaced
+    if replaced.is_published() || replaced.has_hits() {
+        state.result_cache.clear();
+        state.authority_generation.send_modify(|generation| {
+            *generation += 1;
+        });
+    }
+}
+
+/// Reads every indexed page id and every link between indexed pages, as dense indices into
+/// the id list.
+async fn load_graph(
+    state: &AppState,
+    config: &PageRankConfig
+) -> Result<(Vec<i32>, Vec<(u32, u32)>), Box<dyn std::error::Error>> {
+    let ids = database::fetch_website_ids(&state.pool).await?;
+    let index_of = |id: i32| ids.binary_search(&id).ok().map(|index| index as u32);
+
+    let mut edges = Vec::new();
+    let mut after_id = 0;
+    loop {
+        let batch = database::fetch_link_edges(&state.pool, after_id, config.batch_size).await?;
+        let last_id = match batch.last() {
+            Some((link_id, _, _)) => *link_id,
+            None => {
+                break;
+            }
+        };
+
+        for (_, source, target) in batch {
+            let source = index_of(source);
+            let target = target.and_then(index_of);
+            if let (Some(source), Some(target)) = (source, target) {
+                // Pages don't vouch for themselves
+                if source != target {
+                    edges.push((source, target));
+                }
+            }
+        }
+        after_id = last_id;
+    }
+
+    edges.shrink_to_fit();
+    Ok((ids, edges))
+}
+
+/// Runs `iterations` rounds of PageRank power iteration over a graph of `node_count` nodes.
+///
+/// Rank held by pages without outbound links is spread evenly over every page, so scores
+/// always sum to 1.
+fn compute_pagerank(
+    node_count: usize,
+    edges: &[(u32, u32)],
+    iterations: usize,
+    damping: f32
+) -> Vec<f32> {
+    if node_count == 0 {
+        return vec![];
+    }
+
+    let mut out_degree = vec![0_u32; node_count];
+    for &(source, _) in edges {
+        out_degree[source as usize] += 1;
+    }
+
+    let uniform = 1.0 / (node_count as f32);
+    let mut ranks = vec![uniform; node_count];
+    let mut next = vec![0.0_f32; node_count];
+    let mut delta = 0.0;
+
+    for _ in 0..iterations {
+        let dangling: f32 = ranks
+            .iter()
+            .zip(&out_degree)
+            .filter(|(_, &degree)| degree == 0)
+            .map(|(rank, _)| rank)
+            .sum();
+        let base = ((1.0 - damping) + damping * dangling) * uniform;
+        next.iter_mut().for_each(|rank| {
+            *rank = base;
+        });
+
+        for &(source, target) in edges {
+            let source = source as usize;
+            next[target as usize] += (damping * ranks[source]) / (out_degree[source] as f32);
+        }
+
+        delta = ranks
+            .iter()
+            .zip(&next)
+            .map(|(old, new)| (old - new).abs())
+            .sum();
+        std::mem::swap(&mut ranks, &mut next);
+    }
+    println!("Ran {} rounds of PageRank, the last changing scores by {:.6}.", iterations, delta);
+
+    ranks
+}
+
+/// Runs `iterations` rounds of Kleinberg's HITS over a graph of `node_count` nodes, returning
+/// each node's hub and authority score. A page's authority is the sum of the hub scores of the
+/// pages linking to it, and its hub score the sum of the authorities it links to; both are
+/// scaled to unit length each round, so they converge instead of growing without bound.
+fn compute_hits(
+    node_count: usize,
+    edges: &[(u32, u32)],
+    iterations: usize
+) -> (Vec<f32>, Vec<f32>) {
+    let mut hubs = vec![1.0_f32; node_count];
+    let mut authorities = vec![0.0_f32; node_count];
+    let scale = |scores: &mut [f32]| {
+        let norm = scores
+            .iter()
+            .map(|score| score * score)
+            .sum::<f32>()
+            .sqrt();
+        if norm > 0.0 {
+            scores.iter_mut().for_each(|score| {
+                *score /= norm;
+            });
+        }
+    };
+
+    let mut delta = 0.0;
+
+    for _ in 0..iterations {
+        authorities.iter_mut().for_each(|score| {
+            *score = 0.0;
+        });
+        for &(source, target) in edges {
+            authorities[target as usize] += hubs[source as usize];
+        }
+        scale(&mut authorities);
+
+        let previous = std::mem::replace(&mut hubs, vec![0.0; node_count]);
+        for &(source, target) in edges {
+            hubs[source as usize] += authorities[target as usize];
+        }
+        scale(&mut hubs);
+
+        delta = previous
+            .iter()
+            .zip(&hubs)
+            .map(|(old, new)| (old - new).abs())
+            .sum();
+    }
+    println!("Ran {} rounds of HITS, the last changing hub scores by {:.6}.", iterations, delta);
+
+    (hubs, authorities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 1e-6;
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!((actual - expected).abs() < TOLERANCE, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn pagerank_matches_hand_computed_rounds() {
+        // 0 links to 1, which links nowhere, so its rank is spread over both pages each round
+        let edges = [(0, 1)];
+        assert_close(&compute_pagerank(2, &edges, 0, 0.85), &[0.5, 0.5]);
+        // base = (0.15 + 0.85 * 0.5) / 2 = 0.2875, and 1 also gets 0.85 * 0.5 from 0
+        assert_close(&compute_pagerank(2, &edges, 1, 0.85), &[0.2875, 0.7125]);
+        // base = (0.15 + 0.85 * 0.7125) / 2 = 0.3778125, and 1 also gets 0.85 * 0.2875
+        assert_close(&compute_pagerank(2, &edges, 2, 0.85), &[0.3778125, 0.6221875]);
+        // Converges to r0 = (0.15 + 0.85 * r1) / 2 and r1 = r0 + 0.85 * r0
+        assert_close(&compute_pagerank(2, &edges, 100, 0.85), &[1.0 / 2.85, 1.85 / 2.85]);
+    }
+
+    #[test]
+    fn pagerank_scores_sum_to_one() {
+        let edges = [(0, 1), (0, 2), (1, 2), (2, 0), (3, 2), (4, 3)];
+        let ranks = compute_pagerank(6, &edges, 20, 0.85);
+        assert!((ranks.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        // 2 is linked to most, and 5 by nothing at all
+        let best = ranks.iter().cloned().fold(0.0, f32::max);
+        assert_eq!(ranks[2], best);
+        assert!(ranks.iter().all(|&rank| rank >= ranks[5]));
+    }
+
+    #[test]
+    fn pagerank_of_graphs_without_links() {
+        assert!(compute_pagerank(0, &[], 20, 0.85).is_empty());
+        assert_close(&compute_pagerank(4, &[], 20, 0.85), &[0.25; 4]);
+    }
+
+    #[test]
+    fn hits_matches_a_hand_computed_round() {
+        // 0 and 1 both link to 2, and 1 also to 3
+        let edges = [(0, 2), (1, 2), (1, 3)];
+        let (hubs, authorities) = compute_hits(4, &edges, 1);
+        // Authorities [0, 0, 2, 1] from unit hubs, scaled by sqrt(5)
+        let root_5 = 5.0_f32.sqrt();
+        assert_close(&authorities, &[0.0, 0.0, 2.0 / root_5, 1.0 / root_5]);
+        // Hubs [2, 3, 0, 0] / sqrt(5), scaled to unit length
+        let root_13 = 13.0_f32.sqrt();
+        assert_close(&hubs, &[2.0 / root_13, 3.0 / root_13, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn scores_are_normalised_against_the_best() {
+        let scores = PageScores::new(vec![3, 7, 9], vec![0.1, 0.4, 0.2]);
+        assert_eq!(scores.normalised(7), 1.0);
+        assert_eq!(scores.normalised(3), 0.25);
+        assert_eq!(scores.normalised(8), 0.0);
+
+        let unpublished = AuthorityScores::default();
+        assert!(!unpublished.is_published());
+        assert_eq!(unpublished.normalised(7), 0.0);
+        assert!(unpublished.hits(7).is_none());
+    }
+}
@@ -0,0 +1,880 @@
+use axum::{
+    async_trait,
+    extract::{ Extension, FromRequestParts, Query },
+    http::{ request::Parts, StatusCode },
+    response::{ IntoResponse, Json, Response },
+};
+use chrono::{ DateTime, NaiveDate, SecondsFormat, Utc };
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::{ Value, json };
+use std::collections::{ BTreeMap, HashMap };
+use std::sync::Arc;
+use unicode_normalization::UnicodeNormalization;
+use crate::config::Config;
+use crate::empty_state::EmptyQuery;
+use crate::experiments;
+use crate::locale::LanguageCode;
+use crate::preferences::{ self, Preferences };
+use crate::query_parser;
+use crate::ranking::{ MatchMode, MinMatch, Ranker, Recency };
+use crate::result_formatter::ResultOptions;
+use crate::search::{ self, SearchOptions, SearchOutcome };
+use crate::state::AppState;
+use crate::trace::SearchVariant;
+use crate::warnings::{ self, Warnings };
+
+/// The query string couldn't be parsed into parameters
+pub const MALFORMED_QUERY_STRING: &str = "malformed_query_string";
+/// The query was empty, without an `empty` mode saying what to return instead
+pub const EMPTY_QUERY: &str = "empty_query";
+/// The query was longer than `MAX_QUERY_CHARS`
+pub const QUERY_TOO_LONG: &str = "query_too_long";
+/// The query had more words than `MAX_QUERY_TERMS`
+pub const TOO_MANY_TERMS: &str = "too_many_terms";
+/// The query's parentheses nested deeper than `MAX_QUERY_DEPTH`
+pub const QUERY_TOO_DEEP: &str = "query_too_deep";
+/// A parameter's value was outside what it accepts
+pub const INVALID_PARAMETER: &str = "invalid_parameter";
+
+/// Why a search's parameters were rejected, answered with a `400`, or a `422` for a query over
+/// one of the size limits
+#[derive(Debug, Clone)]
+pub struct ParamError {
+    pub code: &'static str,
+    pub message: String,
+    /// The parameter at fault, if it was a single one
+    pub field: Option<&'static str>,
+}
+
+impl ParamError {
+    fn invalid(field: &'static str, message: String) -> Self {
+        ParamError { code: INVALID_PARAMETER, message, field: Some(field) }
+    }
+
+    fn too_big(code: &'static str, message: String) -> Self {
+        ParamError { code, message, field: Some("q") }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self.code {
+            QUERY_TOO_LONG | TOO_MANY_TERMS | QUERY_TOO_DEEP => StatusCode::UNPROCESSABLE_ENTITY,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl IntoResponse for ParamError {
+    fn into_response(self) -> Response {
+        let body = json!({ "error": self.message, "code": self.code, "field": self.field });
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+/// A search request's resolved parameters, with any warnings raised resolving them. `params`
+/// is `None` without a `q` parameter, which asks for the service descriptor instead.
+pub struct SearchRequest {
+    pub params: Option<SearchParams>,
+    pub warnings: Warnings,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for SearchRequest {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<HashMap<String, String>>
+            ::from_request_parts(parts, state).await
+            .map_err(|e| {
+                (ParamError {
+                    code: MALFORMED_QUERY_STRING,
+                    message: e.body_text(),
+                    field: None,
+                }).into_response()
+            })?;
+        let mut warnings = Warnings::default();
+        if !params.contains_key("q") {
+            return Ok(SearchRequest { params: None, warnings });
+        }
+
+        let Extension(app_state) = Extension::<Arc<AppState>>
+            ::from_request_parts(parts, state).await
+            .map_err(IntoResponse::into_response)?;
+        let search_params = SearchParams::from_query(&params, &app_state.config, &mut warnings)
+            .map_err(IntoResponse::into_response)?;
+        Ok(SearchRequest { params: Some(search_params), warnings })
+    }
+}
+
+/// A named bag of default parameter values, overridden by any parameter sent explicitly
+pub type Preset = BTreeMap<String, String>;
+
+// Control characters, and characters that are invisible by default (zero-width spaces and
+// joiners, bidi overrides and marks, variation selectors), other than whitespace
+static INVISIBLE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"[[\p{Cc}\p{Default_Ignorable_Code_Point}]&&[^\s]]").expect(
+        "Failed to compile invisible character regex"
+    )
+});
+
+static WHITESPACE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\s+").expect("Failed to compile whitespace regex")
+});
+
+/// Parameters accepted by search besides those a preset may set
+const KNOWN_PARAMS: &[&str] = &[
+    "q",
+    "preset",
+    "continuation",
+    "token",
+    "include_deleted",
+    "signals",
+    "empty",
+    "after",
+    "before",
+    "lang",
+    "url_regex",
+    "prefer_domains",
+    "prefer_topics",
+    "bucket",
+];
+
+/// The parameters a preset may set
+const PRESET_KEYS: &[&str] = &[
+    "results",
+    "links",
+    "keywords",
+    "snippets",
+    "explain",
+    "debug",
+    "two_tier",
+    "verbatim_only",
+    "verbatim",
+    "case_sensitive",
+    "expand",
+    "autocorrect",
+    "stopwords",
+    "min_match",
+    "min_score",
+    "mode",
+    "ranker",
+    "recency",
+    "profile",
+    "semantic",
+    "collapse",
+    "max_per_domain",
+    "max_bytes",
+];
+
+/// Returns the built-in presets, overlaid with any defined in `file`.
+///
+/// Each line of the file is a preset name followed by `key=value` pairs; blank lines and lines
+/// starting with `#` are ignored. A custom preset with a built-in name replaces it.
+pub fn load_presets(file: Option<&str>, max_results: usize) -> Result<BTreeMap<String, Preset>, String> {
+    let max_results = max_results.to_string();
+    let mut presets = BTreeMap::new();
+    for (name, values) in [
+        ("minimal", vec![("results", "20"), ("links", "false"), ("keywords", "false"), ("snippets", "true")]),
+        ("standard", vec![]),
+        ("full", vec![("links", "true"), ("explain", "true")]),
+        (
+            "research",
+            vec![("results", max_results.as_str()), ("links", "true"), ("explain", "true"), ("debug", "true")],
+        ),
+    ] {
+        let preset = values
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        presets.insert(name.to_string(), preset);
+    }
+
+    let file = match file {
+        Some(file) => file,
+        None => {
+            return Ok(presets);
+        }
+    };
+    let contents = std::fs
+        ::read_to_string(file)
+        .map_err(|e| format!("Failed to read {}: {}", file, e))?;
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let name = fields.next().unwrap_or_default().to_lowercase();
+        let mut preset = Preset::new();
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .filter(|(key, _)| PRESET_KEYS.contains(key))
+                .ok_or_else(|| format!("{}:{}: invalid preset value {:?}", file, line_number + 1, field))?;
+            preset.insert(key.to_string(), value.to_string());
+        }
+        presets.insert(name, preset);
+    }
+    Ok(presets)
+}
+
+/// The validated parameters of a search request
+#[derive(Debug, Clone)]
+pub struct SearchParams {
+    pub query: String,
+    pub preset: Option<String>,
+    pub num_results: usize,
+    pub include_links: bool,
+    pub include_keywords: bool,
+    pub snippets: bool,
+    pub explain: bool,
+    pub debug: bool,
+    pub two_tier: bool,
+    pub verbatim_only: bool,
+    /// Look words up as typed, without lemmatising them
+    pub verbatim: bool,
+    /// Only match words in the case they were typed in
+    pub case_sensitive: bool,
+    /// Also search for synonyms of the query's terms
+    pub expand: bool,
+    /// Serve the results of a suggested spelling correction, if it finds more
+    pub autocorrect: bool,
+    /// Leave stopwords out of the lookup; `stopwords=off` keeps them
+    pub stopwords: bool,
+    pub min_match: Option<MinMatch>,
+    /// Least relevance score of the pages returned, in place of `MIN_SCORE`
+    pub min_score: Option<f64>,
+    /// Whether only pages containing every term are candidates
+    pub mode: MatchMode,
+    /// How relevance is scored, `RANKER` unless asked otherwise
+    pub ranker: Ranker,
+    /// How strongly newer pages are preferred
+    pub recency: Recency,
+    /// The ranking profile whose signal weights are used, if one was named
+    pub profile: Option<String>,
+    /// Reorder the best results by embedding similarity to the query
+    pub semantic: bool,
+    /// Collapse near-duplicates of better ranked results into them
+    pub collapse: bool,
+    /// Most results from one site, if capped
+    pub max_per_domain: Option<usize>,
+    /// The domains and topics the caller favours, from the parameters and then the
+    /// `X-Search-Prefs` header, which `run_search` adds
+    pub preferences: Preferences,
+    /// The experiment bucket to rank in, instead of the one the client's address falls in
+    pub bucket: Option<usize>,
+    /// Size budget for the response body, if any
+    pub max_bytes: Option<usize>,
+    pub continuation: Option<String>,
+    /// Asked for tombstoned pages too, which is only honoured for admins
+    pub include_deleted: bool,
+    /// Only pages whose URL matches this Postgres regex, which is only honoured for admins
+    pub url_regex: Option<String>,
+    /// Add each result's raw ranking signals, which only admin and API-key callers may do
+    pub signals: bool,
+    /// What to return if the query is empty; `None` rejects an empty query
+    pub empty: Option<EmptyQuery>,
+    /// Only return pages last crawled at or after this time
+    pub crawled_after: Option<DateTime<Utc>>,
+    /// Only return pages last crawled before this time
+    pub crawled_before: Option<DateTime<Utc>>,
+    /// Only return pages in this language
+    pub language: Option<LanguageCode>,
+}
+
+impl SearchParams {
+    /// Resolves the request's parameters on top of its preset, if it names one.
+    ///
+    /// Rejects an unknown preset, a query over the size limits, an empty one without a valid
+    /// `empty` mode, or an invalid date range, language, `min_match`, `min_score`, `mode`,
+    /// `ranker` or `recency`. Values that can't be used as they are add to `warnings`.
+    pub fn from_query(
+        params: &HashMap<String, String>,
+        config: &Config,
+        warnings: &mut Warnings
+    ) -> Result<Self, ParamError> {
+        let query = sanitise_query(params.get("q").map(String::as_str).unwrap_or_default());
+        check_query_size(&query, config)?;
+        let empty = match params.get("empty").map(|mode| mode.trim().to_lowercase()) {
+            Some(mode) =>
+                match EmptyQuery::parse(&mode) {
+                    Some(mode) => Some(mode),
+                    None => {
+                        return Err(
+                            ParamError::invalid(
+                                "empty",
+                                format!("Unknown empty mode {:?}; valid modes are: trending, recent, none", mode)
+                            )
+                        );
+                    }
+                }
+            None => None,
+        };
+        if query.is_empty() && empty.is_none() {
+            return Err(ParamError {
+                code: EMPTY_QUERY,
+                message: "Query must not be empty".to_string(),
+                field: Some("q"),
+            });
+        }
+        let crawled_after = parse_date(params.get("after"), "after")?;
+        let crawled_before = parse_date(params.get("before"), "before")?;
+        if let (Some(after), Some(before)) = (crawled_after, crawled_before) {
+            if after >= before {
+                return Err(
+                    ParamError::invalid("before", "after must be earlier than before".to_string())
+                );
+            }
+        }
+        let language = match params.get("lang") {
+            Some(lang) =>
+                match LanguageCode::parse(lang) {
+                    Some(language) => Some(language),
+                    None => {
+                        return Err(
+                            ParamError::invalid(
+                                "lang",
+                                format!("lang must be a two-letter language code such as en, not {:?}", lang)
+                            )
+                        );
+                    }
+                }
+            None => None,
+        };
+
+        let preset_name = params.get("preset").map(|name| name.trim().to_lowercase());
+        let preset = match &preset_name {
+            Some(name) =>
+                match config.presets.get(name) {
+                    Some(preset) => Some(preset),
+                    None => {
+                        let names: Vec<&str> = config.presets.keys().map(String::as_str).collect();
+                        return Err(
+                            ParamError::invalid(
+                                "preset",
+                                format!("Unknown preset {:?}; valid presets are: {}", name, names.join(", "))
+                            )
+                        );
+                    }
+                }
+            None => None,
+        };
+
+        // Explicit parameters take precedence over the preset's values
+        let get = |name: &str| {
+            params
+                .get(name)
+                .or_else(|| preset.and_then(|preset| preset.get(name)))
+                .map(String::as_str)
+        };
+        let mut flag = |name: &str, default: bool| parse_flag(get(name), name, default, warnings);
+
+        let include_links = flag("links", false);
+        let include_keywords = flag("keywords", true);
+        let snippets = flag("snippets", false);
+        let explain = flag("explain", false);
+        let debug = flag("debug", false);
+        let two_tier = flag("two_tier", false);
+        let verbatim_only = flag("verbatim_only", false);
+        let verbatim = flag("verbatim", false);
+        let case_sensitive = flag("case_sensitive", false);
+        let expand = flag("expand", true);
+        let autocorrect = flag("autocorrect", false);
+        let semantic = flag("semantic", false);
+        let collapse = flag("collapse", false);
+        let include_deleted = flag("include_deleted", false);
+        // Checked here so a typo is a 400 rather than a database error. Postgres regexes are
+        // close enough to this crate's for the patterns worth auditing URLs with.
+        let url_regex = match params.get("url_regex").map(|regex| regex.trim()) {
+            Some("") | None => None,
+            Some(regex) => {
+                if let Err(e) = Regex::new(regex) {
+                    return Err(
+                        ParamError::invalid("url_regex", format!("url_regex is not a valid regex: {}", e))
+                    );
+                }
+                Some(regex.to_string())
+            }
+        };
+        let signals = flag("signals", false);
+        let bucket = parse_number(params.get("bucket").map(String::as_str), "bucket", warnings)
+            .filter(|&bucket| {
+                let valid = bucket < experiments::BUCKETS;
+                if !valid {
+                    warnings.push(
+                        warnings::INVALID_PARAMETER,
+                        format!(
+                            "bucket must be below {}, not {}; it was ignored",
+                            experiments::BUCKETS,
+                            bucket
+                        ),
+                        Some("bucket")
+                    );
+                }
+                valid
+            });
+        let preferences = Preferences {
+            domains: params
+                .get("prefer_domains")
+                .map(|list| preferences::parse_domains(list, "prefer_domains", warnings))
+                .unwrap_or_default(),
+            topics: params
+                .get("prefer_topics")
+                .map(|list| preferences::parse_topics(list, "prefer_topics", warnings))
+                .unwrap_or_default(),
+        };
+        let stopwords = match get("stopwords") {
+            Some("on") | None => true,
+            Some("off") => false,
+            Some(value) => {
+                warnings.push(
+                    warnings::INVALID_PARAMETER,
+                    format!("stopwords must be on or off, not {:?}; using on", value),
+                    Some("stopwords")
+                );
+                true
+            }
+        };
+        let min_match = match get("min_match") {
+            Some(value) =>
+                match MinMatch::parse(value) {
+                    Some(min_match) => Some(min_match),
+                    None => {
+                        return Err(
+                            ParamError::invalid(
+                                "min_match",
+                                format!("min_match must be a percentage such as 75% or a number of terms, not {:?}", value)
+                            )
+                        );
+                    }
+                }
+            None => None,
+        };
+        let min_score = match get("min_score") {
+            Some(value) =>
+                match value.trim().parse::<f64>().ok().filter(|min_score| *min_score >= 0.0) {
+                    Some(min_score) if min_score.is_finite() => Some(min_score),
+                    _ => {
+                        return Err(
+                            ParamError::invalid(
+                                "min_score",
+                                format!("min_score must be a non-negative number such as 0.5, not {:?}", value)
+                            )
+                        );
+                    }
+                }
+            None => None,
+        };
+        let mode = match get("mode") {
+            Some(value) =>
+                match MatchMode::parse(value) {
+                    Some(mode) => mode,
+                    None => {
+                        return Err(
+                            ParamError::invalid(
+                                "mode",
+                                format!("mode must be strict or loose, not {:?}", value)
+                            )
+                        );
+                    }
+                }
+            None => MatchMode::Loose,
+        };
+        let ranker = match get("ranker") {
+            Some(value) =>
+                match Ranker::parse(value) {
+                    Some(ranker) => ranker,
+                    None => {
+                        return Err(
+                            ParamError::invalid(
+                                "ranker",
+                                format!("ranker must be tfidf or bm25, not {:?}", value)
+                            )
+                        );
+                    }
+                }
+            None => config.ranker,
+        };
+        let recency = match get("recency") {
+            Some(value) =>
+                match Recency::parse(value) {
+                    Some(recency) => recency,
+                    None => {
+                        return Err(
+                            ParamError::invalid(
+                                "recency",
+                                format!("recency must be normal, strong or off, not {:?}", value)
+                            )
+                        );
+                    }
+                }
+            None => Recency::Normal,
+        };
+        let profile = match get("profile").map(|name| name.trim().to_lowercase()) {
+            Some(name) if config.ranking_profiles.contains_key(&name) => Some(name),
+            Some(name) => {
+                let names: Vec<&str> = config.ranking_profiles.keys().map(String::as_str).collect();
+                return Err(
+                    ParamError::invalid(
+                        "profile",
+                        format!("Unknown profile {:?}; valid profiles are: {}", name, names.join(", "))
+                    )
+                );
+            }
+            None => None,
+        };
+        // Strict searches return every page with all the terms, so there's no fewer to allow
+        let min_match = match (min_match, mode) {
+            (Some(_), MatchMode::Strict) => {
+                warnings.push(
+                    warnings::INVALID_PARAMETER,
+                    "min_match doesn't apply to strict searches and was ignored".to_string(),
+                    Some("min_match")
+                );
+                None
+            }
+            (min_match, _) => min_match,
+        };
+
+        let mut unknown: Vec<&String> = params
+            .keys()
+            .filter(|name| !KNOWN_PARAMS.contains(&name.as_str()) && !PRESET_KEYS.contains(&name.as_str()))
+            .collect();
+        unknown.sort();
+        for name in unknown {
+            warnings.push(
+                warnings::UNKNOWN_PARAMETER,
+                format!("Unknown parameter {:?} was ignored", name),
+                Some(name)
+            );
+        }
+
+        Ok(SearchParams {
+            query,
+            preset: preset_name,
+            num_results: clamp_results(
+                parse_number(get("results"), "results", warnings),
+                config.max_results,
+                warnings
+            ),
+            include_links,
+            include_keywords,
+            snippets,
+            explain,
+            debug,
+            two_tier,
+            verbatim_only,
+            verbatim,
+            case_sensitive,
+            expand,
+            autocorrect,
+            stopwords,
+            min_match,
+            min_score,
+            mode,
+            ranker,
+            recency,
+            profile,
+            semantic,
+            collapse,
+            max_per_domain: parse_number(get("max_per_domain"), "max_per_domain", warnings)
+                .or(Some(config.max_per_domain))
+                .filter(|&max_per_domain| max_per_domain > 0),
+            preferences,
+            bucket,
+            max_bytes: parse_number(get("max_bytes"), "max_bytes", warnings)
+                .or(Some(config.max_response_bytes))
+                .filter(|&max_bytes| max_bytes > 0),
+            continuation: params.get("continuation").cloned(),
+            include_deleted,
+            url_regex,
+            signals,
+            empty,
+            crawled_after,
+            crawled_before,
+            language,
+        })
+    }
+
+    /// What the search pipeline should return, looking up at most `keyword_budget` keywords.
+    /// Tombstoned pages are only included, and the URL regex only applied, for admins.
+    pub fn search_options(&self, keyword_budget: usize, is_admin: bool) -> SearchOptions {
+        SearchOptions {
+            include_links: self.include_links,
+            num_results: self.num_results,
+            verbatim_only: self.verbatim_only,
+            keyword_budget,
+            include_deleted: self.include_deleted && is_admin,
+            url_regex: self.url_regex.clone().filter(|_| is_admin),
+            crawled_after: self.crawled_after,
+            crawled_before: self.crawled_before,
+            language: self.language,
+            exact: self.verbatim,
+            case_sensitive: self.case_sensitive,
+            expand: self.expand,
+            stopwords: self.stopwords,
+            min_match: self.min_match,
+            min_score: self.min_score,
+            mode: self.mode,
+            ranker: self.ranker,
+            recency: self.recency,
+            profile: self.profile.clone(),
+            semantic: self.semantic,
+            collapse: self.collapse,
+            max_per_domain: self.max_per_domain,
+            explain: self.explain,
+            preferences: self.preferences.clone(),
+            shadow: false,
+        }
+    }
+
+    /// How each result should be formatted.
+    pub fn result_options(&self) -> ResultOptions {
+        ResultOptions {
+            include_links: self.include_links,
+            include_keywords: self.include_keywords,
+            snippets: self.snippets,
+            explain: self.explain,
+        }
+    }
+
+    /// What the search ran with after preset resolution and clamping, echoed back so clients can
+    /// see what was actually applied. The lemmas looked up, the `site:` domain and the minimum
+    /// score come from the search's `outcome`; a `site:` search isn't capped per domain. The crawl
+    /// date range is echoed in UTC.
+    pub fn applied(
+        &self,
+        options: &SearchOptions,
+        variant: SearchVariant,
+        outcome: &SearchOutcome,
+        debug: bool
+    ) -> Value {
+        let site = outcome.parsed.site_filter.as_deref();
+        json!({
+            "query": self.query,
+            "lemmas": outcome.keywords,
+            "site": site,
+            "preset": self.preset,
+            "results": options.num_results,
+            "keyword_budget": options.keyword_budget,
+            "variant": variant.name(),
+            "verbatim_only": options.verbatim_only,
+            "verbatim": options.exact,
+            "case_sensitive": options.case_sensitive,
+            "expand": options.expand,
+            "autocorrect": self.autocorrect,
+            "stopwords": options.stopwords,
+            "min_match": options.min_match.map(|min_match| min_match.to_string()),
+            "min_score": outcome.min_score,
+            "mode": options.mode.name(),
+            "ranker": options.ranker.name(),
+            "recency": options.recency.name(),
+            "profile": options.profile,
+            "semantic": options.semantic,
+            "collapse": options.collapse,
+            "max_per_domain": options.max_per_domain.filter(|_| site.is_none()),
+            "preferences": options.preferences.to_json(),
+            "include_deleted": options.include_deleted,
+            "after": options.crawled_after.map(format_date),
+            "before": options.crawled_before.map(format_date),
+            "lang": options.language.as_ref().map(LanguageCode::as_str),
+            "url_regex": options.url_regex,
+            "max_bytes": self.max_bytes,
+            "features": {
+                "links": options.include_links,
+                "keywords": self.include_keywords,
+                "snippets": self.snippets,
+                "explain": self.explain,
+                "signals": self.signals,
+                "debug": debug,
+            },
+        })
+    }
+}
+
+/// Rejects a sanitised query longer than `MAX_QUERY_CHARS`, with more words than
+/// `MAX_QUERY_TERMS` or with parentheses nested deeper than `MAX_QUERY_DEPTH`, before it fans out
+/// into a lookup of every term and a scoring pass over every candidate.
+pub fn check_query_size(query: &str, config: &Config) -> Result<(), ParamError> {
+    if query.chars().count() > config.max_query_chars {
+        return Err(
+            ParamError::too_big(
+                QUERY_TOO_LONG,
+                format!("Query must be at most {} characters", config.max_query_chars)
+            )
+        );
+    }
+    let complexity = query_parser::complexity(query);
+    if complexity.terms > config.max_query_terms {
+        return Err(
+            ParamError::too_big(
+                TOO_MANY_TERMS,
+                format!(
+                    "Query has {} words, but at most {} are allowed",
+                    complexity.terms,
+                    config.max_query_terms
+                )
+            )
+        );
+    }
+    if complexity.depth > config.max_query_depth {
+        return Err(
+            ParamError::too_big(
+                QUERY_TOO_DEEP,
+                format!(
+                    "Query's parentheses nest {} deep, but at most {} levels are allowed",
+                    complexity.depth,
+                    config.max_query_depth
+                )
+            )
+        );
+    }
+    Ok(())
+}
+
+/// Cleans a query before it is searched for, logged, or echoed back: strips control and
+/// invisible characters, applies NFC normalisation, and collapses runs of whitespace to single
+/// spaces.
+pub fn sanitise_query(query: &str) -> String {
+    let visible = INVISIBLE_REGEX.replace_all(query, "");
+    let normalised: String = visible.nfc().collect();
+    WHITESPACE_REGEX.replace_all(normalised.trim(), " ").into_owned()
+}
+
+/// Caps a requested number of results at `max_results`, warning if it was more. Without a
+/// request, the default is capped without a warning, as nothing was asked for.
+pub fn clamp_results(
+    requested: Option<usize>,
+    max_results: usize,
+    warnings: &mut Warnings
+) -> usize {
+    let Some(requested) = requested else {
+        return search::DEFAULT_RESULTS.min(max_results);
+    };
+    if requested > max_results {
+        warnings.push(
+            warnings::RESULTS_CLAMPED,
+            format!("Requested {} results; at most {} are returned", requested, max_results),
+            Some("results")
+        );
+    }
+    requested.min(max_results)
+}
+
+/// Parses a boolean parameter, warning and using `default` if it isn't `true` or `false`.
+fn parse_flag(value: Option<&str>, name: &str, default: bool, warnings: &mut Warnings) -> bool {
+    match value {
+        Some("true") => true,
+        Some("false") => false,
+        Some(value) => {
+            warnings.push(
+                warnings::INVALID_PARAMETER,
+                format!("{} must be true or false, not {:?}; using {}", name, value, default),
+                Some(name)
+            );
+            default
+        }
+        None => default,
+    }
+}
+
+/// Parses a date parameter, either an RFC 3339 timestamp or a `YYYY-MM-DD` date meaning
+/// midnight UTC at its start, returning an error message if it's neither.
+pub fn parse_date(
+    value: Option<&String>,
+    name: &'static str
+) -> Result<Option<DateTime<Utc>>, ParamError> {
+    let value = match value.map(|value| value.trim()) {
+        Some(value) => value,
+        None => {
+            return Ok(None);
+        }
+    };
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(Some(time.with_timezone(&Utc)));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| Some(time.and_utc()))
+        .ok_or_else(|| {
+            ParamError::invalid(
+                name,
+                format!("{} must be a date such as 2024-05-01 or 2024-05-01T12:00:00Z, not {:?}", name, value)
+            )
+        })
+}
+
+/// Formats a date the way `after` and `before` are echoed and captured.
+pub fn format_date(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Parses a non-negative integer parameter, warning and returning `None` if it isn't one.
+fn parse_number(value: Option<&str>, name: &str, warnings: &mut Warnings) -> Option<usize> {
+    let value = value?;
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        warnings.push(
+            warnings::INVALID_PARAMETER,
+            format!("{} must be a non-negative integer, not {:?}; using the default", name, value),
+            Some(name)
+        );
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitises_an_adversarial_corpus() {
+        let corpus = [
+            // Controls, C0 and C1
+            ("rust\0 book", "rust book"),
+            ("rust\u{1}\u{7f}\u{9b}book", "rustbook"),
+            ("\u{1b}[31mrust\u{1b}[0m", "[31mrust[0m"),
+            // Bidi overrides, embeddings and isolates would reorder the echoed query
+            ("\u{202e}tsur\u{202c} book", "tsur book"),
+            ("\u{2067}rust\u{2069}\u{200f}\u{61c}", "rust"),
+            ("rust\u{202a}\u{202b}\u{202d}\u{2066}\u{2068}book", "rustbook"),
+            // Zero-width characters, including the joiners inside emoji sequences
+            ("ru\u{200b}st", "rust"),
+            ("\u{feff}rust\u{200c}\u{2060}", "rust"),
+            ("👩\u{200d}💻 rust", "👩💻 rust"),
+            ("soft\u{ad}ware", "software"),
+            ("\u{200b}\u{200d}\u{202e}\0", ""),
+            // Runs of any whitespace become one space, and the ends are trimmed
+            ("  rust \t\n\r book\u{85}\u{a0}\u{2003}async  ", "rust book async"),
+            (&format!("rust{}book", " ".repeat(10_000)), "rust book"),
+            ("\u{2028}\u{2029}", ""),
+            // NFC, including once an invisible character between the letters is gone
+            ("cafe\u{301}", "café"),
+            ("cafe\u{200b}\u{301}", "café"),
+            ("\u{212b}", "\u{c5}"),
+            // Visible text is left alone
+            ("\"rust\" -site:example.com (a OR b)", "\"rust\" -site:example.com (a OR b)"),
+            ("東京 ラーメン", "東京 ラーメン"),
+        ];
+        for (query, sanitised) in corpus {
+            assert_eq!(sanitise_query(query), sanitised, "{:?}", query);
+            // It's idempotent, so echoing a sanitised query back is safe
+            assert_eq!(sanitise_query(sanitised), sanitised, "{:?}", sanitised);
+        }
+    }
+
+    #[test]
+    fn leaves_nothing_invisible_in_any_character() {
+        let every_character: String = (0..=0x10ffff).filter_map(char::from_u32).collect();
+        let sanitised = sanitise_query(&every_character);
+        for character in sanitised.chars() {
+            assert!(
+                !character.is_control() && !INVISIBLE_REGEX.is_match(&character.to_string()),
+                "{:?} survived",
+                character
+            );
+        }
+        assert!(!sanitised.contains("  "));
+    }
+}
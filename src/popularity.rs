@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How often a query was searched for recently, and how long it last took to execute
+#[derive(Debug, Clone, Default)]
+pub struct QueryStats {
+    pub count: u64,
+    pub last_duration: Option<Duration>,
+}
+
+/// Rolling counter of the most frequently searched queries
+pub struct QueryPopularity {
+    capacity: usize,
+    queries: Mutex<HashMap<String, QueryStats>>,
+}
+
+impl QueryPopularity {
+    pub fn new(capacity: usize) -> Self {
+        QueryPopularity {
+            capacity,
+            queries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a search for `query`, along with how long it took if it was executed
+    /// rather than served from cache.
+    pub fn record(&self, query: &str, duration: Option<Duration>) {
+        let mut queries = self.queries.lock().unwrap();
+
+        // Make room by forgetting one-off queries first
+        if queries.len() >= self.capacity && !queries.contains_key(query) {
+            queries.retain(|_, stats| stats.count > 1);
+            if queries.len() >= self.capacity {
+                return;
+            }
+        }
+
+        let stats = queries.entry(query.to_string()).or_default();
+        stats.count += 1;
+        if duration.is_some() {
+            stats.last_duration = duration;
+        }
+    }
+
+    /// Returns the `n` most popular queries, most popular first.
+    pub fn top(&self, n: usize) -> Vec<(String, QueryStats)> {
+        let queries = self.queries.lock().unwrap();
+        let mut top: Vec<(String, QueryStats)> = queries
+            .iter()
+            .map(|(query, stats)| (query.clone(), stats.clone()))
+            .collect();
+        top.sort_by(|a, b| b.1.count.cmp(&a.1.count).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(n);
+        top
+    }
+
+    /// Returns up to `n` queries starting with `prefix` that were searched for at least
+    /// `min_count` times, most popular first.
+    pub fn with_prefix(&self, prefix: &str, min_count: u64, n: usize) -> Vec<String> {
+        let queries = self.queries.lock().unwrap();
+        let mut matching: Vec<(&String, u64)> = queries
+            .iter()
+            .filter(|(query, stats)| query.starts_with(prefix) && stats.count >= min_count)
+            .map(|(query, stats)| (query, stats.count))
+            .collect();
+        matching.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        matching
+            .into_iter()
+            .take(n)
+            .map(|(query, _)| query.clone())
+            .collect()
+    }
+
+    /// Halves every count so popularity reflects recent traffic, dropping queries that reach zero.
+    pub fn decay(&self) {
+        let mut queries = self.queries.lock().unwrap();
+        for stats in queries.values_mut() {
+            stats.count /= 2;
+        }
+        queries.retain(|_, stats| stats.count > 0);
+    }
+}
@@ -0,0 +1,182 @@
+use axum::http::{ HeaderMap, HeaderName };
+use serde_json::{ Value, json };
+use crate::config::PreferenceConfig;
+use crate::database::Webpage;
+use crate::lemmatise::Normaliser;
+use crate::result_formatter;
+use crate::warnings::{ self, Warnings };
+
+/// Header listing the domains and topics a caller favours, as
+/// `domains=example.com,docs.rs; topics=rust,async`
+pub static PREFS_HEADER: HeaderName = HeaderName::from_static("x-search-prefs");
+
+/// Most domains, and most topics, one request may favour
+const MAX_PREFERENCES: usize = 20;
+
+/// The domains and topics a caller favours, from `prefer_domains` and `prefer_topics` or the
+/// `X-Search-Prefs` header. Nothing is stored server-side: each request names its own.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Preferences {
+    /// Canonical hosts; their subdomains are favoured too
+    pub domains: Vec<String>,
+    /// Lowercased as sent; normalised like the query when pages are matched against them
+    pub topics: Vec<String>,
+}
+
+impl Preferences {
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty() && self.topics.is_empty()
+    }
+
+    /// Fills whichever of the domains and topics the parameters left empty from the
+    /// `X-Search-Prefs` header, so a parameter overrides the header's list of the same kind.
+    pub fn add_header(&mut self, headers: &HeaderMap, warnings: &mut Warnings) {
+        let Some(value) = headers.get(&PREFS_HEADER) else {
+            return;
+        };
+        let Ok(value) = value.to_str() else {
+            warnings.push(
+                warnings::INVALID_PARAMETER,
+                "X-Search-Prefs isn't readable text and was ignored".to_string(),
+                Some("X-Search-Prefs")
+            );
+            return;
+        };
+        for part in value.split(';').map(str::trim).filter(|part| !part.is_empty()) {
+            match part.split_once('=').map(|(kind, list)| (kind.trim().to_lowercase(), list)) {
+                Some((kind, list)) if kind == "domains" => {
+                    if self.domains.is_empty() {
+                        self.domains = parse_domains(list, "X-Search-Prefs", warnings);
+                    }
+                }
+                Some((kind, list)) if kind == "topics" => {
+                    if self.topics.is_empty() {
+                        self.topics = parse_topics(list, "X-Search-Prefs", warnings);
+                    }
+                }
+                _ => {
+                    warnings.push(
+                        warnings::INVALID_PARAMETER,
+                        format!(
+                            "X-Search-Prefs entries are domains= or topics= lists; {:?} was ignored",
+                            part
+                        ),
+                        Some("X-Search-Prefs")
+                    );
+                }
+            }
+        }
+    }
+
+    /// Each topic's words, normalised like the query's.
+    pub fn normalised_topics(&self, normaliser: Normaliser) -> Vec<Vec<String>> {
+        self.topics
+            .iter()
+            .map(|topic| normaliser.terms(topic))
+            .collect()
+    }
+
+    /// The preferences as one string for the result cache key, since they change the ranking.
+    pub fn cache_key(&self) -> String {
+        format!("{}/{}", self.domains.join(","), self.topics.join(","))
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({ "domains": self.domains, "topics": self.topics })
+    }
+
+    /// Reads back preferences echoed by `to_json`, or none for `null`.
+    pub fn from_json(value: &Value) -> Self {
+        let list = |key: &str| {
+            value[key]
+                .as_array()
+                .map(|items| {
+                    items
+                        .iter()
+                        .filter_map(|item| item.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Preferences { domains: list("domains"), topics: list("topics") }
+    }
+}
+
+/// Parses a comma-separated list of domains, canonicalising each and sorting them, so the same
+/// preferences in any order share cache entries. Invalid domains are dropped with a warning.
+pub fn parse_domains(list: &str, field: &str, warnings: &mut Warnings) -> Vec<String> {
+    let mut domains = vec![];
+    for domain in list.split(',').map(str::trim).filter(|domain| !domain.is_empty()) {
+        match result_formatter::canonicalise_domain(domain) {
+            Some(domain) => {
+                domains.push(domain.strip_prefix("www.").map(String::from).unwrap_or(domain));
+            }
+            None => {
+                warnings.push(
+                    warnings::INVALID_PARAMETER,
+                    format!("{:?} is not a valid domain and was ignored", domain),
+                    Some(field)
+                );
+            }
+        }
+    }
+    finish(domains, field, warnings)
+}
+
+/// Parses a comma-separated list of topics, each a word or a few, lowercased and sorted.
+pub fn parse_topics(list: &str, field: &str, warnings: &mut Warnings) -> Vec<String> {
+    let topics = list
+        .split(',')
+        .map(|topic| topic.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase())
+        .filter(|topic| !topic.is_empty())
+        .collect();
+    finish(topics, field, warnings)
+}
+
+fn finish(mut items: Vec<String>, field: &str, warnings: &mut Warnings) -> Vec<String> {
+    items.sort();
+    items.dedup();
+    if items.len() > MAX_PREFERENCES {
+        warnings.push(
+            warnings::INVALID_PARAMETER,
+            format!("Only the first {} preferences of a kind are used", MAX_PREFERENCES),
+            Some(field)
+        );
+        items.truncate(MAX_PREFERENCES);
+    }
+    items
+}
+
+/// The bonus a page gains for the caller's preferences: `PREFERRED_DOMAIN_WEIGHT` if it's on a
+/// favoured domain or one of its subdomains, plus `PREFERRED_TOPIC_WEIGHT` times the share of
+/// `topics` with every one of their words in its title, URL or description. `topics` are
+/// the preferences' topics as `normalised_topics` returns them.
+pub fn bonus(
+    preferences: &Preferences,
+    topics: &[Vec<String>],
+    webpage: &Webpage,
+    config: &PreferenceConfig,
+    normaliser: Normaliser
+) -> f64 {
+    let mut bonus = 0.0;
+    let host = result_formatter::extract_domain_from_string(&webpage.url);
+    let favoured = host.map(|host| host.to_lowercase()).is_some_and(|host| {
+        preferences.domains
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+    });
+    if favoured {
+        bonus += config.domain_weight;
+    }
+    if !topics.is_empty() && config.topic_weight > 0.0 {
+        let mut words = normaliser.terms(&webpage.title);
+        words.extend(normaliser.terms(&webpage.url));
+        words.extend(normaliser.terms(&webpage.description));
+        let matched = topics
+            .iter()
+            .filter(|topic| !topic.is_empty() && topic.iter().all(|word| words.contains(word)))
+            .count();
+        bonus += (config.topic_weight * (matched as f64)) / (topics.len() as f64);
+    }
+    bonus
+}
@@ -0,0 +1,219 @@
+/// English number words and the values they stand for
+const NUMBER_WORDS: &[(&str, u64)] = &[
+    ("zero", 0),
+    ("one", 1),
+    ("two", 2),
+    ("three", 3),
+    ("four", 4),
+    ("five", 5),
+    ("six", 6),
+    ("seven", 7),
+    ("eight", 8),
+    ("nine", 9),
+    ("ten", 10),
+    ("eleven", 11),
+    ("twelve", 12),
+    ("thirteen", 13),
+    ("fourteen", 14),
+    ("fifteen", 15),
+    ("sixteen", 16),
+    ("seventeen", 17),
+    ("eighteen", 18),
+    ("nineteen", 19),
+    ("twenty", 20),
+    ("thirty", 30),
+    ("forty", 40),
+    ("fifty", 50),
+    ("sixty", 60),
+    ("seventy", 70),
+    ("eighty", 80),
+    ("ninety", 90),
+];
+
+/// Words multiplying the number before them, largest last
+const SCALE_WORDS: &[(&str, u64)] = &[
+    ("hundred", 100),
+    ("thousand", 1_000),
+    ("million", 1_000_000),
+    ("billion", 1_000_000_000),
+];
+
+/// Each unit's name, which every other spelling of it is normalised to, with those spellings.
+/// Names are singular and British, and none is in the lemma map, so lemmatising keeps them.
+const UNITS: &[(&str, &[&str])] = &[
+    ("milligram", &["mg", "milligrams", "milligramme", "milligrammes"]),
+    ("gram", &["grams", "gramme", "grammes"]),
+    ("kilogram", &["kg", "kgs", "kilograms", "kilogramme", "kilogrammes", "kilo", "kilos"]),
+    ("pound", &["lb", "lbs"]),
+    ("ounce", &["oz", "ounces"]),
+    ("millimetre", &["mm", "millimetres", "millimeter", "millimeters"]),
+    ("centimetre", &["cm", "centimetres", "centimeter", "centimeters"]),
+    ("metre", &["metres", "meter", "meters"]),
+    ("kilometre", &["km", "kms", "kilometres", "kilometer", "kilometers"]),
+    ("mile", &["miles"]),
+    ("millilitre", &["ml", "millilitres", "milliliter", "milliliters"]),
+    ("litre", &["litres", "liter", "liters"]),
+    ("kilobyte", &["kb", "kilobytes"]),
+    ("megabyte", &["mb", "megabytes"]),
+    ("gigabyte", &["gb", "gigabytes"]),
+    ("terabyte", &["tb", "terabytes"]),
+    ("hertz", &["hz"]),
+    ("kilohertz", &["khz"]),
+    ("megahertz", &["mhz"]),
+    ("gigahertz", &["ghz"]),
+    ("watt", &["watts"]),
+    ("kilowatt", &["kw", "kilowatts"]),
+    ("volt", &["volts"]),
+    ("millisecond", &["ms", "milliseconds"]),
+];
+
+/// Unit symbols too ambiguous to be read as units on their own, so only read as one straight
+/// after a number, as in `5m`. (The lemma map even reads `m` as "am".)
+const ATTACHED_UNITS: &[(&str, &str)] = &[
+    ("g", "gram"),
+    ("m", "metre"),
+    ("l", "litre"),
+    ("w", "watt"),
+    ("v", "volt"),
+];
+
+/// What the last word of a spelled-out number was, to tell which words may follow it
+#[derive(Clone, Copy)]
+enum Last {
+    Start,
+    /// From zero to nineteen
+    Small,
+    /// Twenty, thirty and so on
+    Tens,
+    Hundred,
+    /// Thousand or larger
+    Scale(u64),
+}
+
+/// Normalises numbers and units in lowercased, folded words, so they match however they were
+/// written: spelled-out numbers become digits ("three hundred and five" becomes "305"), a number
+/// with a unit attached is split from it ("5kg" becomes "5 kilogram"), and every spelling of a
+/// unit becomes its name ("kg", "kgs" and "kilograms" become "kilogram").
+///
+/// Only English number words are read, but units are normalised whatever the text's language.
+pub fn normalise(words: Vec<String>) -> Vec<String> {
+    let mut normalised = Vec::with_capacity(words.len());
+    let mut rest = words.as_slice();
+    while let Some(word) = rest.first() {
+        if let Some((length, value)) = spelled_number(rest) {
+            normalised.push(value.to_string());
+            rest = &rest[length..];
+            continue;
+        }
+        match split_attached_unit(word) {
+            Some((number, unit)) => {
+                normalised.push(number.to_string());
+                normalised.push(unit.to_string());
+            }
+            None => normalised.push(unit_name(word).unwrap_or(word).to_string()),
+        }
+        rest = &rest[1..];
+    }
+    normalised
+}
+
+/// How many of `words`, from the start, spell out a number, with its value, or `None` if the
+/// first word isn't a number word. Reading stops at the first word that can't continue the
+/// number, so "three twenty" is two numbers.
+pub fn spelled_number<S: AsRef<str>>(words: &[S]) -> Option<(usize, u64)> {
+    let mut total: u64 = 0;
+    // The part below the last thousand or larger scale
+    let mut group: u64 = 0;
+    let mut last = Last::Start;
+    let mut length = 0;
+    while let Some(word) = words.get(length) {
+        let word = word.as_ref();
+        // "and" only joins a hundred or a scale to the number word after it
+        if word == "and" && matches!(last, Last::Hundred | Last::Scale(_)) {
+            let joins = words
+                .get(length + 1)
+                .is_some_and(|next| number_word(next.as_ref()).is_some());
+            if !joins {
+                break;
+            }
+            length += 1;
+            continue;
+        }
+        if let Some(value) = number_word(word) {
+            let follows = match last {
+                Last::Start | Last::Hundred | Last::Scale(_) => true,
+                // "twenty five", but not "twenty twelve"
+                Last::Tens => (1..10).contains(&value),
+                Last::Small => false,
+            };
+            if !follows {
+                break;
+            }
+            group += value;
+            last = if value >= 20 { Last::Tens } else { Last::Small };
+        } else if let Some(scale) = scale_word(word) {
+            if scale == 100 {
+                if !matches!(last, Last::Start | Last::Small | Last::Tens) || group >= 100 {
+                    break;
+                }
+                group = group.max(1) * 100;
+                last = Last::Hundred;
+            } else {
+                // "two million three thousand", but not "two thousand three million"
+                if let Last::Scale(previous) = last {
+                    if scale >= previous {
+                        break;
+                    }
+                }
+                let Some(value) = group.max(1).checked_mul(scale) else {
+                    break;
+                };
+                total += value;
+                group = 0;
+                last = Last::Scale(scale);
+            }
+        } else {
+            break;
+        }
+        length += 1;
+    }
+    (length > 0).then_some((length, total + group))
+}
+
+fn number_word(word: &str) -> Option<u64> {
+    NUMBER_WORDS
+        .iter()
+        .find(|(number, _)| *number == word)
+        .map(|(_, value)| *value)
+}
+
+fn scale_word(word: &str) -> Option<u64> {
+    SCALE_WORDS
+        .iter()
+        .find(|(scale, _)| *scale == word)
+        .map(|(_, value)| *value)
+}
+
+/// The name of the unit `word` is a spelling of, if it's one.
+fn unit_name(word: &str) -> Option<&'static str> {
+    UNITS.iter()
+        .find(|(name, spellings)| *name == word || spellings.contains(&word))
+        .map(|(name, _)| *name)
+}
+
+/// Splits a number from the unit written straight after it, as in `5kg` or `300m`, returning
+/// the number and the unit's name.
+fn split_attached_unit(word: &str) -> Option<(&str, &'static str)> {
+    let digits = word.find(|c: char| !c.is_ascii_digit())?;
+    if digits == 0 {
+        return None;
+    }
+    let (number, unit) = word.split_at(digits);
+    let name = unit_name(unit).or_else(|| {
+        ATTACHED_UNITS
+            .iter()
+            .find(|(symbol, _)| *symbol == unit)
+            .map(|(_, name)| *name)
+    })?;
+    Some((number, name))
+}
@@ -0,0 +1,49 @@
+use crate::lemmatise;
+use crate::locale::LanguageCode;
+use crate::stopwords::Stopwords;
+
+/// Letters that, before accents are folded, point to a language
+const LETTERS: &[(&str, &str)] = &[
+    ("de", "äöüß"),
+    ("fr", "àâçéèêëîïôùûœ"),
+];
+
+/// Least evidence a language needs to be detected, so a single word shared with another
+/// language, such as "die" in "die hard", doesn't switch the query to it
+const MIN_SCORE: usize = 2;
+
+/// Guesses the language a query is written in, from the words of each language's stopword list
+/// it uses and its words spelt with letters particular to a language. `None` if no language
+/// has clearly more evidence than the others, or too little, which short queries usually do.
+pub fn detect(
+    query: &str,
+    stopwords: &Stopwords,
+    folding_exceptions: &[char]
+) -> Option<LanguageCode> {
+    let words = lemmatise::split_words(query, folding_exceptions);
+    let mut scores = stopwords.count(&words);
+    for (code, letters) in LETTERS {
+        let Some(language) = LanguageCode::parse(code) else {
+            continue;
+        };
+        let spelt = query
+            .split_whitespace()
+            .filter(|word| word.to_lowercase().chars().any(|c| letters.contains(c)))
+            .count();
+        match scores.iter_mut().find(|(scored, _)| *scored == language) {
+            Some((_, score)) => {
+                *score += spelt;
+            }
+            None => scores.push((language, spelt)),
+        }
+    }
+
+    scores.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+    match scores.as_slice() {
+        [(language, best), rest @ ..] if *best >= MIN_SCORE => {
+            let runner_up = rest.first().map_or(0, |(_, score)| *score);
+            (*best > runner_up).then_some(*language)
+        }
+        _ => None,
+    }
+}
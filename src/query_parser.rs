@@ -0,0 +1,959 @@
+use serde_json::{ Value, json };
+use std::collections::HashMap;
+use crate::database::Webpage;
+use crate::lemmatise::{ self, Normaliser };
+use crate::locale::LanguageCode;
+use crate::quantities;
+use crate::result_formatter;
+
+/// Words that make a query boolean; only recognised in capitals
+const BOOLEAN_OPERATORS: &[&str] = &["AND", "OR", "NOT"];
+
+/// Shortest prefix a `*` wildcard may follow, so it can't expand to most of the vocabulary
+const MIN_WILDCARD_PREFIX: usize = 3;
+
+/// Largest distance `NEAR/n` accepts; further apart than this, terms aren't near in any useful
+/// sense
+pub const MAX_NEAR_DISTANCE: usize = 50;
+
+/// Most words a spelled-out number is read from, e.g. "nine hundred and ninety nine thousand"
+const MAX_SPELLED_NUMBER_WORDS: usize = 16;
+
+/// A search query split into its operators, with every term lemmatised, unless the search is
+/// exact
+#[derive(Debug, Clone, Default)]
+pub struct ParsedQuery {
+    /// Terms every result must contain, in query order, including those of phrases. For a
+    /// boolean query, every term outside a `NOT`, which results need only some of.
+    pub required_terms: Vec<String>,
+    /// Terms no result may contain, from `-word`. For a boolean query, every term inside a
+    /// `NOT` or `-word`, which only the `expression` decides about.
+    pub excluded_terms: Vec<String>,
+    /// Quoted phrases, as their terms in order
+    pub phrases: Vec<Vec<String>>,
+    /// Only pages on this domain or its subdomains match, from `site:`
+    pub site_filter: Option<String>,
+    /// What results must match, if the query used `AND`, `OR`, `NOT` or a `*` wildcard
+    pub expression: Option<QueryExpr>,
+    /// Terms every result must contain in a particular field, from `title:word` and
+    /// `desc:word`. They're also in `required_terms`, and like `site:` apply to the whole query,
+    /// even in a boolean one.
+    pub field_terms: Vec<(Field, String)>,
+    /// Text every result's URL must contain, from `url:text` or `inurl:text`, lowercased but not
+    /// lemmatised
+    pub url_terms: Vec<String>,
+    /// Text every result's title must contain, from `intitle:text`, lowercased but not
+    /// lemmatised. Unlike `title:` terms, they aren't searched for.
+    pub title_substrings: Vec<String>,
+    /// Pairs of terms that rank higher when close together, from `left NEAR/n right`
+    pub proximity: Vec<Proximity>,
+    /// Words also searched for in place of a term, as `(term, synonym)`. Filled in by the search
+    /// from the synonym list, not by parsing.
+    pub synonyms: Vec<(String, String)>,
+    /// Terms left out of the lookup as stopwords. Filled in by the search, not by parsing.
+    pub stopwords: Vec<String>,
+    /// Terms that may be matched by indexed words spelt similarly, from `word~`, as `(term,
+    /// typed)`: the term as looked up, and the word as typed, lowercased and folded but not
+    /// lemmatised, since lemmatising a misspelling can take it further from the word meant.
+    pub fuzzy_terms: Vec<(String, String)>,
+    /// Indexed words also searched for in place of a fuzzy term, as `(term, word)`. Filled in by
+    /// the search from the vocabulary, not by parsing.
+    pub fuzzy: Vec<(String, String)>,
+    /// Plain and phrase terms with the word they came from as typed, folded and split at
+    /// punctuation but keeping its case, as `(term, cased)`, for case-sensitive searches
+    pub cased_terms: Vec<(String, String)>,
+    /// Words from pages linking to the query's few matches that more pages were searched for
+    /// by. Filled in by the search, not by parsing.
+    pub link_expansion: Vec<String>,
+    /// The language the query was lemmatised and had stopwords removed in: `lang` if given,
+    /// otherwise the one detected, or `None` for the English defaults. Filled in by the search,
+    /// not by parsing.
+    pub language: Option<LanguageCode>,
+}
+
+/// Two terms that should appear within `distance` words of each other, in either order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proximity {
+    pub left: String,
+    pub right: String,
+    pub distance: usize,
+}
+
+/// A part of a page that query terms can be scoped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Description,
+}
+
+impl Field {
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Description => "desc",
+        }
+    }
+
+    pub fn text(self, webpage: &Webpage) -> &str {
+        match self {
+            Field::Title => &webpage.title,
+            Field::Description => &webpage.description,
+        }
+    }
+}
+
+/// A boolean query over lemmatised terms
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryExpr {
+    Term(String),
+    /// Every part must match; also how a phrase or several words in a row are read
+    And(Vec<QueryExpr>),
+    /// At least one part must match
+    Or(Vec<QueryExpr>),
+    Not(Box<QueryExpr>),
+    /// Any word starting with the prefix, from `prefix*`. Matches nothing until expanded into
+    /// the indexed words it stands for.
+    Prefix(String),
+}
+
+impl QueryExpr {
+    /// Whether a page matches, given whether it contains each term.
+    pub fn matches(&self, contains: &impl Fn(&str) -> bool) -> bool {
+        match self {
+            QueryExpr::Term(term) => contains(term),
+            QueryExpr::And(parts) => parts.iter().all(|part| part.matches(contains)),
+            QueryExpr::Or(parts) => parts.iter().any(|part| part.matches(contains)),
+            QueryExpr::Not(part) => !part.matches(contains),
+            QueryExpr::Prefix(_) => false,
+        }
+    }
+
+    fn collect_prefixes(&self, prefixes: &mut Vec<String>) {
+        match self {
+            QueryExpr::Prefix(prefix) => prefixes.push(prefix.clone()),
+            QueryExpr::And(parts) | QueryExpr::Or(parts) => {
+                for part in parts {
+                    part.collect_prefixes(prefixes);
+                }
+            }
+            QueryExpr::Not(part) => part.collect_prefixes(prefixes),
+            QueryExpr::Term(_) => {}
+        }
+    }
+
+    fn expand_prefixes(&mut self, expansions: &HashMap<String, Vec<String>>) {
+        match self {
+            QueryExpr::Prefix(prefix) => {
+                let words = expansions.get(prefix.as_str()).cloned().unwrap_or_default();
+                *self = QueryExpr::Or(words.into_iter().map(QueryExpr::Term).collect());
+            }
+            QueryExpr::And(parts) | QueryExpr::Or(parts) => {
+                for part in parts {
+                    part.expand_prefixes(expansions);
+                }
+            }
+            QueryExpr::Not(part) => part.expand_prefixes(expansions),
+            QueryExpr::Term(_) => {}
+        }
+    }
+
+    /// Adds every term to `positive` or `negated`, depending on whether it's inside an odd
+    /// number of `NOT`s, in query order.
+    fn collect_terms(
+        &self,
+        negated_context: bool,
+        positive: &mut Vec<String>,
+        negated: &mut Vec<String>
+    ) {
+        match self {
+            QueryExpr::Term(term) if negated_context => negated.push(term.clone()),
+            QueryExpr::Term(term) => positive.push(term.clone()),
+            QueryExpr::And(parts) | QueryExpr::Or(parts) => {
+                for part in parts {
+                    part.collect_terms(negated_context, positive, negated);
+                }
+            }
+            QueryExpr::Not(part) => part.collect_terms(!negated_context, positive, negated),
+            QueryExpr::Prefix(_) => {}
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let all = |parts: &[QueryExpr]| parts.iter().map(Self::to_json).collect::<Vec<_>>();
+        match self {
+            QueryExpr::Term(term) => json!(term),
+            QueryExpr::And(parts) => json!({ "and": all(parts) }),
+            QueryExpr::Or(parts) => json!({ "or": all(parts) }),
+            QueryExpr::Not(part) => json!({ "not": part.to_json() }),
+            QueryExpr::Prefix(prefix) => json!({ "prefix": prefix }),
+        }
+    }
+}
+
+impl ParsedQuery {
+    /// Whether the query used any operator besides plain terms.
+    pub fn has_operators(&self) -> bool {
+        !self.excluded_terms.is_empty() ||
+            !self.phrases.is_empty() ||
+            self.site_filter.is_some() ||
+            self.expression.is_some() ||
+            !self.field_terms.is_empty() ||
+            !self.url_terms.is_empty() ||
+            !self.title_substrings.is_empty() ||
+            !self.proximity.is_empty()
+    }
+
+    /// Whether `url` is on the `site:` domain or one of its subdomains, or there is no filter.
+    pub fn matches_site(&self, url: &str) -> bool {
+        let site = match &self.site_filter {
+            Some(site) => site,
+            None => {
+                return true;
+            }
+        };
+        result_formatter
+            ::extract_domain_from_string(url)
+            .is_some_and(|host| {
+                let host = host.to_lowercase();
+                host == *site || host.ends_with(&format!(".{}", site))
+            })
+    }
+
+    /// Whether every `title:` and `desc:` term is in its field of `webpage`, with the field's
+    /// words normalised like the query's.
+    pub fn matches_fields(&self, webpage: &Webpage, normaliser: Normaliser) -> bool {
+        [Field::Title, Field::Description].into_iter().all(|field| {
+            let mut terms = self.field_terms
+                .iter()
+                .filter(|(term_field, _)| *term_field == field)
+                .peekable();
+            if terms.peek().is_none() {
+                return true;
+            }
+            let words = normaliser.terms(field.text(webpage));
+            terms.all(|(_, term)| words.contains(term))
+        })
+    }
+
+    /// The prefixes of the query's `*` wildcards, in query order.
+    pub fn wildcard_prefixes(&self) -> Vec<String> {
+        let mut prefixes = vec![];
+        if let Some(expression) = &self.expression {
+            expression.collect_prefixes(&mut prefixes);
+        }
+        prefixes
+    }
+
+    /// Replaces each wildcard with any of the words `expansions` lists for its prefix, so one
+    /// without an entry matches nothing, and collects the terms again.
+    pub fn expand_wildcards(&mut self, expansions: &HashMap<String, Vec<String>>) {
+        let Some(expression) = &mut self.expression else {
+            return;
+        };
+        expression.expand_prefixes(expansions);
+        self.required_terms.clear();
+        self.excluded_terms.clear();
+        expression.collect_terms(false, &mut self.required_terms, &mut self.excluded_terms);
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "required_terms": self.required_terms,
+            "excluded_terms": self.excluded_terms,
+            "phrases": self.phrases,
+            "site": self.site_filter,
+            "expression": self.expression.as_ref().map(QueryExpr::to_json),
+            "fields": self.field_terms
+                .iter()
+                .map(|(field, term)| json!({ "field": field.name(), "term": term }))
+                .chain(self.url_terms.iter().map(|term| json!({ "field": "url", "term": term })))
+                .chain(
+                    self.title_substrings
+                        .iter()
+                        .map(|text| json!({ "field": "intitle", "term": text }))
+                )
+                .collect::<Vec<_>>(),
+            "near": self.proximity
+                .iter()
+                .map(|near| json!({ "terms": [near.left, near.right], "distance": near.distance }))
+                .collect::<Vec<_>>(),
+            "synonyms": self.synonyms
+                .iter()
+                .map(|(term, synonym)| json!({ "term": term, "synonym": synonym }))
+                .collect::<Vec<_>>(),
+            "stopwords": self.stopwords,
+            "link_expansion": self.link_expansion,
+            "language": self.language.as_ref().map(LanguageCode::as_str),
+            "fuzzy": self.fuzzy_terms
+                .iter()
+                .map(|(term, _)| {
+                    let words: Vec<&String> = self.fuzzy
+                        .iter()
+                        .filter(|(fuzzy_term, _)| fuzzy_term == term)
+                        .map(|(_, word)| word)
+                        .collect();
+                    json!({ "term": term, "words": words })
+                })
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Parses a query's operators: `"quoted phrases"`, `-excluded` words, a `site:` filter, terms
+/// scoped to a field with `title:`, `desc:` or `url:`, `NEAR/n` between two words, and `word~`
+/// fuzzy terms. Everything else is a plain term.
+///
+/// An unmatched quote is ignored, so the rest of the query is read as plain terms, and a
+/// `site:` that isn't followed by a valid domain is read as plain terms too. A later `site:`
+/// replaces an earlier one.
+///
+/// A query using `AND`, `OR`, `NOT` or a `prefix*` wildcard is parsed into an `expression`
+/// instead; see `parse_boolean`.
+pub fn parse(query: &str, normaliser: Normaliser) -> ParsedQuery {
+    let tokens = tokenise(query);
+    let is_boolean = tokens.iter().any(|token| {
+        matches!(token, Token::Word(word) if
+            BOOLEAN_OPERATORS.contains(word) ||
+            wildcard_prefix(word.trim_start_matches('-'), normaliser).is_some())
+    });
+    if is_boolean {
+        return parse_boolean(&tokens, normaliser);
+    }
+
+    let mut parsed = ParsedQuery::default();
+    let mut rest = query;
+
+    while let Some(start) = rest.find('"') {
+        let after_quote = &rest[start + 1..];
+        let end = match after_quote.find('"') {
+            Some(end) => end,
+            None => {
+                break;
+            }
+        };
+        parse_words(&rest[..start], normaliser, &mut parsed);
+
+        let phrase = normaliser.terms(&after_quote[..end]);
+        for word in after_quote[..end].split_whitespace() {
+            push_cased_terms(word, &normaliser.terms(word), normaliser, &mut parsed);
+        }
+        if !phrase.is_empty() {
+            parsed.required_terms.extend(phrase.iter().cloned());
+            parsed.phrases.push(phrase);
+        }
+        rest = &after_quote[end + 1..];
+    }
+    parse_words(rest, normaliser, &mut parsed);
+
+    parsed
+}
+
+/// Parses the unquoted part of a query, word by word so terms keep their order.
+///
+/// `NEAR/n` links the last term of the word before it with the first of the word after it. It
+/// only applies between plain words; next to an operator, or at either end, it's ignored.
+fn parse_words(text: &str, normaliser: Normaliser, parsed: &mut ParsedQuery) {
+    // The last term of the previous word, if it was a plain one
+    let mut previous: Option<String> = None;
+    // A `NEAR/n` waiting for the term after it
+    let mut near: Option<(String, usize)> = None;
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut position = 0;
+    while position < words.len() {
+        // A number spelled out over several words is read as one, so "three hundred" is 300
+        let length = spelled_number_words(&words[position..], normaliser).max(1);
+        let word = words[position..position + length].join(" ");
+        let word = word.as_str();
+        position += length;
+        if let Some(distance) = near_distance(word) {
+            near = previous.take().map(|left| (left, distance));
+            continue;
+        }
+        let pending = near.take();
+        previous = None;
+        if let Some(excluded) = word.strip_prefix('-').filter(|excluded| !excluded.is_empty()) {
+            parsed.excluded_terms.extend(normaliser.terms(excluded));
+            continue;
+        }
+        let site = word
+            .get(..5)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("site:"))
+            .and_then(|_| result_formatter::canonicalise_domain(&word[5..]));
+        if let Some(site) = site {
+            parsed.site_filter = Some(site);
+            continue;
+        }
+        if let Some(terms) = parse_field_word(word, normaliser, parsed) {
+            parsed.required_terms.extend(terms);
+            continue;
+        }
+        let fuzzy = word.strip_suffix('~').filter(|fuzzy| !fuzzy.is_empty());
+        let terms = normaliser.terms(fuzzy.unwrap_or(word));
+        if let Some(fuzzy) = fuzzy {
+            let typed = lemmatise::split_words(fuzzy, normaliser.folding_exceptions);
+            if let ([term], [typed]) = (terms.as_slice(), typed.as_slice()) {
+                parsed.fuzzy_terms.push((term.clone(), typed.clone()));
+            }
+        }
+        if fuzzy.is_none() {
+            push_cased_terms(word, &terms, normaliser, parsed);
+        }
+        if let (Some((left, distance)), Some(right)) = (pending, terms.first()) {
+            parsed.proximity.push(Proximity { left, right: right.clone(), distance });
+        }
+        previous = terms.last().cloned();
+        parsed.required_terms.extend(terms);
+    }
+}
+
+/// How many plain words from the start of `words` spell out a number together, or 0 if the first
+/// doesn't start one. Exact searches keep numbers as typed.
+fn spelled_number_words(words: &[&str], normaliser: Normaliser) -> usize {
+    if !normaliser.lemmatise {
+        return 0;
+    }
+    let folded: Vec<String> = words
+        .iter()
+        .take(MAX_SPELLED_NUMBER_WORDS)
+        .map_while(|word| {
+            if !word.chars().all(char::is_alphabetic) {
+                return None;
+            }
+            match <[String; 1]>::try_from(lemmatise::split_words(word, normaliser.folding_exceptions)) {
+                Ok([folded]) => Some(folded),
+                Err(_) => None,
+            }
+        })
+        .collect();
+    quantities::spelled_number(&folded).map_or(0, |(length, _)| length)
+}
+
+/// Pairs each of a word's terms with its part of the word as typed, keeping its case. A word
+/// that lemmatises to a different number of terms than it splits into can't be paired, so it's
+/// left out.
+fn push_cased_terms(word: &str, terms: &[String], normaliser: Normaliser, parsed: &mut ParsedQuery) {
+    let cased = lemmatise::split_cased_words(word, normaliser.folding_exceptions);
+    if cased.len() == terms.len() {
+        parsed.cased_terms.extend(terms.iter().cloned().zip(cased));
+    }
+}
+
+/// The distance of a `NEAR/n` operator, only recognised in capitals, or `None` if `word` isn't
+/// one or `n` isn't between 1 and `MAX_NEAR_DISTANCE`.
+fn near_distance(word: &str) -> Option<usize> {
+    word.strip_prefix("NEAR/")
+        .and_then(|distance| distance.parse().ok())
+        .filter(|distance| (1..=MAX_NEAR_DISTANCE).contains(distance))
+}
+
+/// How big a query is, measured before it's parsed, so oversized queries can be turned away
+/// before they cost anything
+#[derive(Debug, Clone, Copy)]
+pub struct QueryComplexity {
+    /// Words in the query, including those of phrases, but not operators like `OR` or `NEAR/n`
+    pub terms: usize,
+    /// How deeply its parentheses nest; 0 without any
+    pub depth: usize,
+}
+
+/// Measures `query`'s terms and parenthesis nesting. An unmatched `)` doesn't count.
+pub fn complexity(query: &str) -> QueryComplexity {
+    let mut complexity = QueryComplexity { terms: 0, depth: 0 };
+    let mut depth: usize = 0;
+    for token in tokenise(query) {
+        match token {
+            Token::Word(word) => {
+                if !BOOLEAN_OPERATORS.contains(&word) && near_distance(word).is_none() {
+                    complexity.terms += 1;
+                }
+            }
+            Token::Phrase(phrase) => {
+                complexity.terms += phrase.split_whitespace().count();
+            }
+            Token::Open => {
+                depth += 1;
+                complexity.depth = complexity.depth.max(depth);
+            }
+            Token::Close => {
+                depth = depth.saturating_sub(1);
+            }
+        }
+    }
+    complexity
+}
+
+/// A piece of a boolean query
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    /// The text between a pair of quotes
+    Phrase(&'a str),
+    Open,
+    Close,
+}
+
+/// Splits a query into words, quoted phrases and parentheses. An unmatched quote is dropped.
+fn tokenise(query: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut rest = query;
+    loop {
+        rest = rest.trim_start();
+        let Some(first) = rest.chars().next() else {
+            break;
+        };
+        let end = match first {
+            '(' | ')' => {
+                tokens.push(if first == '(' { Token::Open } else { Token::Close });
+                1
+            }
+            '"' =>
+                match rest[1..].find('"') {
+                    Some(end) => {
+                        tokens.push(Token::Phrase(&rest[1..end + 1]));
+                        end + 2
+                    }
+                    None => 1,
+                }
+            _ => {
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '"'))
+                    .unwrap_or(rest.len());
+                tokens.push(Token::Word(&rest[..end]));
+                end
+            }
+        };
+        rest = &rest[end..];
+    }
+    tokens
+}
+
+/// Parses a query with boolean operators. `NOT` binds tightest, then `AND`, then `OR`, and
+/// terms next to each other are joined by `AND`, so `rust tokio OR async` means
+/// `(rust AND tokio) OR async`. Parentheses group, `-word` is `NOT word`, phrases require all
+/// their terms, `prefix*` is a wildcard, and `site:`, `title:`, `desc:` and `url:` still filter
+/// the whole query. `NEAR/n` joins like `AND`, and links the terms either side of it when both
+/// are plain words.
+///
+/// Malformed queries are read leniently: an operator with nothing to apply to and an unmatched
+/// `)` are ignored, and an unclosed `(` closes at the end of the query.
+fn parse_boolean(tokens: &[Token], normaliser: Normaliser) -> ParsedQuery {
+    let mut parser = BooleanParser {
+        tokens,
+        position: 0,
+        normaliser,
+        parsed: ParsedQuery::default(),
+    };
+    let mut parts = vec![];
+    while parser.position < tokens.len() {
+        parts.extend(parser.parse_or());
+        // Only an unmatched `)` stops a top-level expression early
+        if parser.peek() == Some(&Token::Close) {
+            parser.position += 1;
+        }
+    }
+
+    let mut parsed = parser.parsed;
+    let expression = join(parts, QueryExpr::And);
+    if let Some(expression) = &expression {
+        expression.collect_terms(false, &mut parsed.required_terms, &mut parsed.excluded_terms);
+    }
+    parsed.expression = expression;
+    parsed
+}
+
+/// Joins the parts of an `AND` or `OR`, without wrapping a lone part.
+fn join(mut parts: Vec<QueryExpr>, combine: fn(Vec<QueryExpr>) -> QueryExpr) -> Option<QueryExpr> {
+    match parts.len() {
+        0 => None,
+        1 => parts.pop(),
+        _ => Some(combine(parts)),
+    }
+}
+
+struct BooleanParser<'a, 'b> {
+    tokens: &'b [Token<'a>],
+    position: usize,
+    normaliser: Normaliser<'b>,
+    /// Collects the phrases and `site:` filter
+    parsed: ParsedQuery,
+}
+
+impl<'a> BooleanParser<'a, '_> {
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.position)
+    }
+
+    fn parse_or(&mut self) -> Option<QueryExpr> {
+        let mut parts = vec![];
+        parts.extend(self.parse_and());
+        while self.peek() == Some(&Token::Word("OR")) {
+            self.position += 1;
+            parts.extend(self.parse_and());
+        }
+        join(parts, QueryExpr::Or)
+    }
+
+    fn parse_and(&mut self) -> Option<QueryExpr> {
+        let mut parts = vec![];
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) | Some(Token::Word("OR")) => {
+                    break;
+                }
+                Some(Token::Word("AND")) => {
+                    self.position += 1;
+                }
+                Some(Token::Word(word)) if near_distance(word).is_some() => {
+                    let distance = near_distance(word).unwrap_or_default();
+                    self.position += 1;
+                    let right = self.parse_unary();
+                    let left = parts.last().and_then(|part| edge_term(part, true));
+                    let first = right.as_ref().and_then(|part| edge_term(part, false));
+                    if let (Some(left), Some(right)) = (left, first) {
+                        self.parsed.proximity.push(Proximity {
+                            left: left.to_string(),
+                            right: right.to_string(),
+                            distance,
+                        });
+                    }
+                    parts.extend(right);
+                }
+                Some(_) => parts.extend(self.parse_unary()),
+            }
+        }
+        join(parts, QueryExpr::And)
+    }
+
+    /// Parses one operand, which may yield nothing, e.g. for a `site:` filter or punctuation.
+    fn parse_unary(&mut self) -> Option<QueryExpr> {
+        let token = self.peek()?.clone();
+        self.position += 1;
+        match token {
+            Token::Word("NOT") => {
+                // A `NOT` with nothing after it is ignored
+                match self.peek() {
+                    None | Some(Token::Close) | Some(Token::Word("AND" | "OR")) => None,
+                    Some(_) => self.parse_unary().map(|part| QueryExpr::Not(Box::new(part))),
+                }
+            }
+            Token::Open => {
+                let group = self.parse_or();
+                if self.peek() == Some(&Token::Close) {
+                    self.position += 1;
+                }
+                group
+            }
+            Token::Phrase(text) => {
+                let phrase = self.normaliser.terms(text);
+                let terms = self.terms(&phrase);
+                if !phrase.is_empty() {
+                    self.parsed.phrases.push(phrase);
+                }
+                terms
+            }
+            Token::Word(word) => {
+                let excluded = word.strip_prefix('-').filter(|excluded| !excluded.is_empty());
+                if let Some(excluded) = excluded {
+                    if let Some(prefix) = wildcard_prefix(excluded, self.normaliser) {
+                        return Some(QueryExpr::Not(Box::new(QueryExpr::Prefix(prefix))));
+                    }
+                    let terms = self.normaliser.terms(excluded);
+                    return self.terms(&terms).map(|part| QueryExpr::Not(Box::new(part)));
+                }
+                let site = word
+                    .get(..5)
+                    .filter(|prefix| prefix.eq_ignore_ascii_case("site:"))
+                    .and_then(|_| result_formatter::canonicalise_domain(&word[5..]));
+                if let Some(site) = site {
+                    self.parsed.site_filter = Some(site);
+                    return None;
+                }
+                // Like `site:`, scoped terms filter the whole query
+                let field_terms = parse_field_word(word, self.normaliser, &mut self.parsed);
+                if let Some(terms) = field_terms {
+                    self.parsed.required_terms.extend(terms);
+                    return None;
+                }
+                if let Some(prefix) = wildcard_prefix(word, self.normaliser) {
+                    return Some(QueryExpr::Prefix(prefix));
+                }
+                let terms = self.normaliser.terms(word);
+                self.terms(&terms)
+            }
+            // `parse_and` stops at a `)`, so it never gets here
+            Token::Close => None,
+        }
+    }
+
+    /// All of `terms`, which one word or phrase may lemmatise to.
+    fn terms(&self, terms: &[String]) -> Option<QueryExpr> {
+        join(terms.iter().cloned().map(QueryExpr::Term).collect(), QueryExpr::And)
+    }
+}
+
+/// The last (or first) term of a plain word or phrase, for linking with `NEAR/n`. `None` for
+/// anything else, such as an `OR` or a `NOT`.
+fn edge_term(part: &QueryExpr, last: bool) -> Option<&str> {
+    match part {
+        QueryExpr::Term(term) => Some(term),
+        QueryExpr::And(parts) if last => parts.last().and_then(|part| edge_term(part, last)),
+        QueryExpr::And(parts) => parts.first().and_then(|part| edge_term(part, last)),
+        _ => None,
+    }
+}
+
+/// The prefix of a `prefix*` wildcard, lowercased and folded like indexed words. Prefixes are
+/// left unlemmatised, since lemmatising part of a word means nothing. `None` unless the word
+/// ends in `*` after at least `MIN_WILDCARD_PREFIX` letters or digits, and nothing else.
+fn wildcard_prefix(word: &str, normaliser: Normaliser) -> Option<String> {
+    let prefix = word.strip_suffix('*')?.trim_end_matches('*');
+    let prefix = lemmatise::fold_diacritics(&prefix.to_lowercase(), normaliser.folding_exceptions);
+    let valid =
+        prefix.chars().count() >= MIN_WILDCARD_PREFIX && prefix.chars().all(char::is_alphanumeric);
+    valid.then_some(prefix)
+}
+
+/// Reads a `title:word`, `desc:word`, `url:text`, `inurl:text` or `intitle:text` scoped term
+/// into `parsed`. Returns the lemmas of a `title:` or `desc:` term, which are searched for like
+/// plain terms too; the others are substring filters applied in the fetch and return none.
+/// `None` if `word` isn't a scoped term.
+fn parse_field_word(
+    word: &str,
+    normaliser: Normaliser,
+    parsed: &mut ParsedQuery
+) -> Option<Vec<String>> {
+    let (name, text) = word.split_once(':')?;
+    if text.is_empty() {
+        return None;
+    }
+    let field = match name.to_lowercase().as_str() {
+        "title" => Field::Title,
+        "desc" => Field::Description,
+        "url" | "inurl" => {
+            parsed.url_terms.push(text.to_lowercase());
+            return Some(vec![]);
+        }
+        "intitle" => {
+            parsed.title_substrings.push(text.to_lowercase());
+            return Some(vec![]);
+        }
+        _ => {
+            return None;
+        }
+    };
+    let terms = normaliser.terms(text);
+    parsed.field_terms.extend(terms.iter().map(|term| (field, term.clone())));
+    Some(terms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEMMATISED: Normaliser<'static> = Normaliser {
+        folding_exceptions: &[],
+        lemmatise: true,
+        lemmas: None,
+    };
+    const EXACT: Normaliser<'static> = Normaliser {
+        folding_exceptions: &[],
+        lemmatise: false,
+        lemmas: None,
+    };
+
+    fn terms(terms: &[&str]) -> Vec<String> {
+        terms.iter().map(|term| term.to_string()).collect()
+    }
+
+    fn page(url: &str, title: &str, description: &str) -> Webpage {
+        Webpage {
+            url: url.to_string(),
+            title: title.to_string(),
+            description: description.to_string(),
+            ..Webpage::default()
+        }
+    }
+
+    #[test]
+    fn lemmatises_plain_terms_in_order() {
+        let parsed = parse("Rust  languages cafés", LEMMATISED);
+        assert_eq!(parsed.required_terms, terms(&["rust", "language", "cafe"]));
+        assert!(!parsed.has_operators());
+
+        let parsed = parse("Rust languages", EXACT);
+        assert_eq!(parsed.required_terms, terms(&["rust", "languages"]));
+    }
+
+    #[test]
+    fn reads_phrases_exclusions_and_sites() {
+        let parsed = parse("\"async languages\" tokio -python site:Example.COM", LEMMATISED);
+        assert_eq!(parsed.required_terms, terms(&["async", "language", "tokio"]));
+        assert_eq!(parsed.phrases, [terms(&["async", "language"])]);
+        assert_eq!(parsed.excluded_terms, terms(&["python"]));
+        assert_eq!(parsed.site_filter.as_deref(), Some("example.com"));
+        assert!(parsed.has_operators());
+        assert_eq!(parsed.expression, None);
+
+        // A later `site:` replaces an earlier one
+        let parsed = parse("rust site:a.example site:b.example", LEMMATISED);
+        assert_eq!(parsed.site_filter.as_deref(), Some("b.example"));
+    }
+
+    #[test]
+    fn leaves_nothing_to_search_for_with_only_exclusions_or_a_site() {
+        let parsed = parse("-rust -python", LEMMATISED);
+        assert!(parsed.required_terms.is_empty());
+        assert_eq!(parsed.excluded_terms, terms(&["rust", "python"]));
+        assert!(parsed.has_operators());
+
+        let parsed = parse("site:example.com", LEMMATISED);
+        assert!(parsed.required_terms.is_empty());
+        assert_eq!(parsed.site_filter.as_deref(), Some("example.com"));
+
+        let parsed = parse("", LEMMATISED);
+        assert!(parsed.required_terms.is_empty());
+        assert!(!parsed.has_operators());
+    }
+
+    #[test]
+    fn degrades_malformed_operators_to_plain_terms() {
+        // An unmatched quote is ignored
+        let parsed = parse("\"rust async", LEMMATISED);
+        assert_eq!(parsed.required_terms, terms(&["rust", "async"]));
+        assert!(parsed.phrases.is_empty());
+
+        let parsed = parse("\"rust\" \"async", LEMMATISED);
+        assert_eq!(parsed.phrases, [terms(&["rust"])]);
+        assert_eq!(parsed.required_terms, terms(&["rust", "async"]));
+
+        // An empty phrase, a bare `-` and a `site:` without a domain add nothing
+        let parsed = parse("\"\" - rust site:", LEMMATISED);
+        assert!(parsed.phrases.is_empty());
+        assert!(parsed.excluded_terms.is_empty());
+        assert_eq!(parsed.site_filter, None);
+        assert!(parsed.required_terms.contains(&"rust".to_string()));
+    }
+
+    #[test]
+    fn matches_sites_and_their_subdomains() {
+        let parsed = parse("rust site:example.com", LEMMATISED);
+        for url in [
+            "https://example.com/",
+            "https://docs.example.com/guide",
+            "http://a.b.EXAMPLE.com:8080/x",
+        ] {
+            assert!(parsed.matches_site(url), "{}", url);
+        }
+        for url in ["https://notexample.com/", "https://example.com.evil.net/", "not a url"] {
+            assert!(!parsed.matches_site(url), "{}", url);
+        }
+        // Without a filter, everything matches
+        assert!(parse("rust", LEMMATISED).matches_site("not a url"));
+    }
+
+    #[test]
+    fn matches_terms_scoped_to_a_field() {
+        let parsed = parse("title:languages desc:async rust", LEMMATISED);
+        assert_eq!(
+            parsed.field_terms,
+            [(Field::Title, "language".to_string()), (Field::Description, "async".to_string())]
+        );
+        assert_eq!(parsed.required_terms, terms(&["language", "async", "rust"]));
+
+        let both = page("https://a.example/", "Rust Languages", "Async code");
+        let title_only = page("https://b.example/", "Rust Languages", "Sync code");
+        let swapped = page("https://c.example/", "Async", "Languages");
+        assert!(parsed.matches_fields(&both, LEMMATISED));
+        assert!(!parsed.matches_fields(&title_only, LEMMATISED));
+        assert!(!parsed.matches_fields(&swapped, LEMMATISED));
+        // Without scoped terms, every page matches
+        assert!(parse("rust", LEMMATISED).matches_fields(&swapped, LEMMATISED));
+
+        let parsed = parse("inurl:Guide intitle:Rust", LEMMATISED);
+        assert_eq!(parsed.url_terms, terms(&["guide"]));
+        assert_eq!(parsed.title_substrings, terms(&["rust"]));
+        assert!(parsed.required_terms.is_empty());
+    }
+
+    #[test]
+    fn parses_boolean_queries_by_precedence() {
+        let term = |term: &str| QueryExpr::Term(term.to_string());
+        let parsed = parse("rust tokio OR async NOT python", LEMMATISED);
+        assert_eq!(
+            parsed.expression,
+            Some(
+                QueryExpr::Or(vec![
+                    QueryExpr::And(vec![term("rust"), term("tokio")]),
+                    QueryExpr::And(vec![term("async"), QueryExpr::Not(Box::new(term("python")))]),
+                ])
+            )
+        );
+        assert_eq!(parsed.required_terms, terms(&["rust", "tokio", "async"]));
+        assert_eq!(parsed.excluded_terms, terms(&["python"]));
+
+        let expression = parsed.expression.unwrap();
+        let containing = |words: &'static [&'static str]| move |word: &str| words.contains(&word);
+        assert!(expression.matches(&containing(&["rust", "tokio"])));
+        assert!(expression.matches(&containing(&["async"])));
+        assert!(!expression.matches(&containing(&["async", "python"])));
+        assert!(!expression.matches(&containing(&["rust"])));
+
+        // Lowercase operators are plain words
+        assert_eq!(parse("rust or async", LEMMATISED).expression, None);
+    }
+
+    #[test]
+    fn reads_malformed_boolean_queries_leniently() {
+        let term = |term: &str| QueryExpr::Term(term.to_string());
+        // An unclosed group closes at the end, and a stray `)` or dangling operator is ignored
+        assert_eq!(
+            parse("(rust OR async", LEMMATISED).expression,
+            Some(QueryExpr::Or(vec![term("rust"), term("async")]))
+        );
+        assert_eq!(parse("rust ) AND", LEMMATISED).expression, Some(term("rust")));
+        assert_eq!(parse("OR NOT", LEMMATISED).expression, None);
+        // A `site:` still filters a boolean query
+        let parsed = parse("rust OR async site:example.com", LEMMATISED);
+        assert_eq!(parsed.site_filter.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn expands_wildcards_into_the_words_given() {
+        let mut parsed = parse("asy* OR rust -pyth*", LEMMATISED);
+        assert_eq!(parsed.wildcard_prefixes(), terms(&["asy", "pyth"]));
+        // Too short a prefix isn't a wildcard
+        assert!(parse("as*", LEMMATISED).wildcard_prefixes().is_empty());
+
+        let expansions = HashMap::from([("asy".to_string(), terms(&["async", "asyncio"]))]);
+        parsed.expand_wildcards(&expansions);
+        assert_eq!(parsed.required_terms, terms(&["async", "asyncio", "rust"]));
+        assert!(parsed.excluded_terms.is_empty());
+        let expression = parsed.expression.unwrap();
+        assert!(expression.matches(&|word: &str| word == "asyncio"));
+        // A prefix without expansions matches nothing, so excluding it excludes nothing
+        assert!(expression.matches(&|word: &str| word == "rust" || word == "python"));
+    }
+
+    #[test]
+    fn links_terms_either_side_of_near() {
+        let parsed = parse("rust NEAR/5 async", LEMMATISED);
+        assert_eq!(
+            parsed.proximity,
+            [Proximity { left: "rust".to_string(), right: "async".to_string(), distance: 5 }]
+        );
+        assert_eq!(parsed.required_terms, terms(&["rust", "async"]));
+
+        // At either end, past the largest distance or in lowercase, it's not an operator
+        assert!(parse("NEAR/5 rust", LEMMATISED).proximity.is_empty());
+        assert!(parse("rust NEAR/51 async", LEMMATISED).proximity.is_empty());
+        assert!(parse("rust near/5 async", LEMMATISED).proximity.is_empty());
+    }
+
+    #[test]
+    fn measures_terms_and_nesting() {
+        let measured = complexity("rust OR (async AND (\"tokio runtime\" NEAR/3 io))) )");
+        assert_eq!(measured.terms, 5);
+        assert_eq!(measured.depth, 2);
+        assert_eq!(complexity("").terms, 0);
+    }
+}
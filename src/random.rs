@@ -0,0 +1,79 @@
+use axum::{
+    extract::{ Extension, Query },
+    http::StatusCode,
+    response::{ IntoResponse, Json, Response },
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::database::{ self, SampleFilter };
+use crate::result_formatter::{ canonicalise_domain, format_result, ResultOptions };
+use crate::state::AppState;
+
+const DEFAULT_COUNT: i64 = 5;
+const MAX_COUNT: i64 = 50;
+
+/// `GET /random?count=5&domain=&min_word_count=`: random indexed pages, for discovery.
+pub async fn random_pages(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(state): Extension<Arc<AppState>>
+) -> Response {
+    let count = params
+        .get("count")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_COUNT)
+        .clamp(1, MAX_COUNT);
+    let min_word_count = match params.get("min_word_count").map(|v| v.parse::<i32>()) {
+        None => None,
+        Some(Ok(min_word_count)) => Some(min_word_count),
+        Some(Err(_)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "min_word_count must be an integer" })),
+            ).into_response();
+        }
+    };
+    let domain = params
+        .get("domain")
+        .and_then(|domain| canonicalise_domain(domain));
+
+    let filter = SampleFilter { domain, min_word_count, tombstones: state.tombstones(false) };
+    let website_count = state.index.load().website_count;
+    let sampled = state.database.run(|| {
+        database::sample_webpages(&state.pool, count, website_count, &filter)
+    }).await;
+    let webpages = match sampled {
+        Ok(webpages) => webpages,
+        Err(e) => {
+            eprintln!("Error sampling random pages: {}", e);
+            return e.response("Failed to fetch random pages");
+        }
+    };
+
+    let options = ResultOptions {
+        include_links: false,
+        include_keywords: true,
+        snippets: false,
+        explain: false,
+    };
+    let results: Vec<_> = webpages
+        .iter()
+        .map(|webpage| {
+            // Random pages have no relevance score
+            let mut result = format_result(
+                &0.0,
+                webpage,
+                &state.top_domains,
+                &state.fragment_cache,
+                options
+            );
+            if let Some(result) = result.as_object_mut() {
+                result.remove("score");
+                result.remove("raw_score");
+            }
+            result
+        })
+        .collect();
+
+    Json(json!({ "count": results.len(), "results": results })).into_response()
+}
@@ -1,34 +1,616 @@
-use std::collections::HashMap;
+use chrono::{ DateTime, Utc };
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::{ BTreeMap, HashMap };
+use std::fmt;
+use std::time::Duration;
+use crate::config::{ Bm25Config, FieldBoosts, FieldWeights, QualityConfig };
 use crate::database::Webpage;
+use crate::lemmatise::Normaliser;
 
-pub async fn get_tf_idf_scores(
+/// How many of a query's distinct terms a page must contain, from `min_match`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MinMatch {
+    /// A percentage of the terms, rounded down but at least one, like `75%`
+    Percent(u8),
+    /// A number of terms, like `2`, or every term for queries with fewer
+    Terms(usize),
+}
+
+impl MinMatch {
+    /// Parses `75%` as a percentage from 0 to 100, or `2` as a number of terms from 1.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        match value.strip_suffix('%') {
+            Some(percent) => percent
+                .trim()
+                .parse()
+                .ok()
+                .filter(|&percent| percent <= 100)
+                .map(MinMatch::Percent),
+            None => value.parse().ok().filter(|&terms| terms > 0).map(MinMatch::Terms),
+        }
+    }
+
+    /// How many of `terms` distinct terms a page must contain.
+    pub fn required(self, terms: usize) -> usize {
+        match self {
+            MinMatch::Percent(percent) => ((terms * (percent as usize)) / 100).max(1),
+            MinMatch::Terms(required) => required.min(terms),
+        }
+    }
+}
+
+impl fmt::Display for MinMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MinMatch::Percent(percent) => write!(f, "{}%", percent),
+            MinMatch::Terms(terms) => write!(f, "{}", terms),
+        }
+    }
+}
+
+/// Whether a page must contain every one of the query's terms to be a candidate, from `mode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    /// Pages containing any term are candidates, and only those with a full score are returned
+    #[default]
+    Loose,
+    /// Only pages containing every term are candidates, and they're all returned
+    Strict,
+}
+
+impl MatchMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "loose" => Some(MatchMode::Loose),
+            "strict" => Some(MatchMode::Strict),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MatchMode::Loose => "loose",
+            MatchMode::Strict => "strict",
+        }
+    }
+}
+
+/// How strongly newer pages are preferred, from `recency`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Recency {
+    /// By `RECENCY_WEIGHT`
+    #[default]
+    Normal,
+    /// By `RECENCY_STRONG_WEIGHT`, for news-like queries
+    Strong,
+    Off,
+}
+
+impl Recency {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "normal" => Some(Recency::Normal),
+            "strong" => Some(Recency::Strong),
+            "off" => Some(Recency::Off),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Recency::Normal => "normal",
+            Recency::Strong => "strong",
+            Recency::Off => "off",
+        }
+    }
+}
+
+/// How a page's freshness falls as it ages. Both halve it at the half-life.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decay {
+    /// Halving again every half-life, so old pages keep a little
+    Exponential,
+    /// Falling steadily to nothing at twice the half-life
+    Linear,
+}
+
+impl Decay {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "exponential" => Some(Decay::Exponential),
+            "linear" => Some(Decay::Linear),
+            _ => None,
+        }
+    }
+}
+
+/// How fresh a page last crawled at `last_crawled` is at `now`, from 1 for a page crawled just
+/// now down to 0. Pages never crawled have no age to go by, so they get 0.
+pub fn freshness(
+    last_crawled: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    half_life: Duration,
+    decay: Decay
+) -> f64 {
+    let Some(last_crawled) = last_crawled else {
+        return 0.0;
+    };
+    let half_life = half_life.as_secs_f64();
+    if half_life <= 0.0 {
+        return 0.0;
+    }
+    // A crawl date in the future is a clock skew, not a fresher page
+    let age = ((now - last_crawled).num_seconds().max(0) as f64) / half_life;
+    match decay {
+        Decay::Exponential => 0.5_f64.powf(age),
+        Decay::Linear => (1.0 - age / 2.0).max(0.0),
+    }
+}
+
+/// Which relevance scorer ranks a search's candidates, from `ranker` or the `RANKER` default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Ranker {
+    /// Cosine similarity of TF-IDF vectors, which is 1 for a page matching the query in
+    /// proportion, so only those are returned
+    #[default]
+    TfIdf,
+    /// Okapi BM25, which saturates repeated terms and discounts long pages. Scores are relative,
+    /// so every candidate is returned, best first.
+    Bm25,
+}
+
+impl Ranker {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "tfidf" => Some(Ranker::TfIdf),
+            "bm25" => Some(Ranker::Bm25),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Ranker::TfIdf => "tfidf",
+            Ranker::Bm25 => "bm25",
+        }
+    }
+
+    /// The scorer that ranks with it, given the index's `average_length` in words and the BM25
+    /// settings.
+    pub fn scorer(self, average_length: f64, bm25: Bm25Config) -> Box<dyn Scorer> {
+        match self {
+            Ranker::TfIdf => Box::new(TfIdfScorer),
+            Ranker::Bm25 => Box::new(Bm25Scorer { average_length, config: bm25 }),
+        }
+    }
+}
+
+/// Fewest candidates scored across threads; below this, handing the work out costs more than it
+/// saves
+const PARALLEL_SCORING_MIN: usize = 2000;
+
+/// Whether `candidates` are worth scoring across threads: there are enough of them, and more
+/// than one thread to share them.
+fn score_in_parallel(candidates: usize) -> bool {
+    candidates >= PARALLEL_SCORING_MIN && rayon::current_num_threads() > 1
+}
+
+/// What candidates are scored against: the query's terms, each weighted by its share of the
+/// query, with the synonyms that may stand in for them as `(term, synonym)`, and the index
+/// they're scored within
+pub struct ScoringQuery<'a> {
+    pub term_tfs: HashMap<String, f64>,
+    pub synonyms: &'a [(String, String)],
+    pub document_count: i64,
+    /// How much extra occurrences in a page's title and description count, once they've been
+    /// counted
+    pub boosts: FieldBoosts,
+}
+
+impl<'a> ScoringQuery<'a> {
+    pub fn new(
+        document_count: i64,
+        boosts: FieldBoosts,
+        lemmatized_query: &[String],
+        synonyms: &'a [(String, String)]
+    ) -> Self {
+        ScoringQuery {
+            term_tfs: calculate_query_term_frequencies(lemmatized_query),
+            synonyms,
+            document_count,
+            boosts,
+        }
+    }
+}
+
+/// Scores a candidate's relevance to a query. A new scorer implements this and is returned by
+/// `Ranker::scorer` for a new `Ranker`, so ranking with it needs no change to the search
+/// pipeline.
+pub trait Scorer: Sync {
+    /// Scores `website` against `query`. `stand_ins` maps each synonym the page has in place of
+    /// a term it lacks to that term, so the synonym can be scored as the term.
+    fn score(
+        &self,
+        query: &ScoringQuery,
+        website: &Webpage,
+        stand_ins: &HashMap<&str, &str>
+    ) -> f64;
+
+    /// What every candidate's score is divided by, worked out from all of them before any is
+    /// scored, for scorers whose scores are relative; 1 by default. Candidates all score 0 if it
+    /// isn't positive.
+    fn scale(&self, _query: &ScoringQuery, _websites: &[Webpage]) -> f64 {
+        1.0
+    }
+}
+
+/// Cosine similarity of TF-IDF vectors, which is 1 for a page matching the query in proportion
+pub struct TfIdfScorer;
+
+impl Scorer for TfIdfScorer {
+    fn score(
+        &self,
+        query: &ScoringQuery,
+        website: &Webpage,
+        stand_ins: &HashMap<&str, &str>
+    ) -> f64 {
+        let document_count = query.document_count;
+        calculate_similarity(website, &query.term_tfs, stand_ins, document_count, query.boosts)
+    }
+}
+
+/// Okapi BM25, given the index's `average_length` in words
+pub struct Bm25Scorer {
+    pub average_length: f64,
+    pub config: Bm25Config,
+}
+
+impl Scorer for Bm25Scorer {
+    fn score(
+        &self,
+        query: &ScoringQuery,
+        website: &Webpage,
+        stand_ins: &HashMap<&str, &str>
+    ) -> f64 {
+        let config = self.config;
+        let length_ratio = (website.word_count.max(1) as f64) / self.average_length.max(1.0);
+        let length_norm = config.k1 * (1.0 - config.b + config.b * length_ratio);
+        let mut score = 0.0;
+        for (index, (word, occurrences)) in website.keywords.iter().enumerate() {
+            let term = stand_ins.get(word.word.as_str()).copied().unwrap_or(&word.word);
+            if let Some(&query_tf) = query.term_tfs.get(term) {
+                let tf = boosted_occurrences(website, index, *occurrences, query.boosts);
+                let idf = bm25_idf(query.document_count, word.documents_containing_word);
+                score += query_tf * idf * (tf * (config.k1 + 1.0)) / (tf + length_norm);
+            }
+        }
+        score
+    }
+
+    /// The most any page could score for the terms the candidates contain, so scores stay below
+    /// 1 and the bonuses added to relevance keep the same weight as with TF-IDF.
+    fn scale(&self, query: &ScoringQuery, websites: &[Webpage]) -> f64 {
+        // Each term's weight, or the largest of those of the synonyms standing in for it, summed
+        // in term order so the scale comes out the same in every run
+        let mut term_weights: BTreeMap<&str, f64> = BTreeMap::new();
+        for website in websites {
+            for (keyword, _) in &website.keywords {
+                let term = query.synonyms
+                    .iter()
+                    .find(|(_, synonym)| *synonym == keyword.word)
+                    .map_or(keyword.word.as_str(), |(term, _)| term.as_str());
+                if let Some(&query_tf) = query.term_tfs.get(term) {
+                    let idf = bm25_idf(query.document_count, keyword.documents_containing_word);
+                    let best = term_weights.entry(term).or_insert(query_tf * idf);
+                    *best = best.max(query_tf * idf);
+                }
+            }
+        }
+        term_weights.values().sum::<f64>() * (self.config.k1 + 1.0)
+    }
+}
+
+/// Orders scored webpages best first, and those scoring the same by id, so every stage ordering
+/// by score breaks ties the same way in every run.
+pub fn best_first(a: &(f64, Webpage), b: &(f64, Webpage)) -> Ordering {
+    b.0.total_cmp(&a.0).then_with(|| a.1.id.cmp(&b.1.id))
+}
+
+/// Scores websites against a query with `scorer`, in the order given: ranking them is left to
+/// the bounded selection of the results returned, so no stage sorts every candidate. A
+/// website lacking a term is scored as if its first synonym it contains were the term, and
+/// records the synonyms it used. Each website also records how many of the query's distinct
+/// terms it contains.
+pub fn score_candidates(
+    scorer: &dyn Scorer,
+    query: &ScoringQuery,
+    websites: &[Webpage]
+) -> Vec<(f64, Webpage)> {
+    score_with(scorer, query, websites, score_in_parallel(websites.len()))
+}
+
+/// `score_candidates`, scoring across rayon's threads if `parallel` and on this one otherwise.
+/// Both give the same scores in the same order.
+pub fn score_with(
+    scorer: &dyn Scorer,
+    query: &ScoringQuery,
+    websites: &[Webpage],
+    parallel: bool
+) -> Vec<(f64, Webpage)> {
+    let scale = scorer.scale(query, websites);
+    let score = |website: &Webpage| {
+        let stand_ins = synonym_stand_ins(website, query.synonyms);
+        let similarity = if scale > 0.0 {
+            scorer.score(query, website, &stand_ins) / scale
+        } else {
+            0.0
+        };
+        let mut website = website.clone();
+        website.relevance = similarity;
+        website.matched_terms = count_matched_terms(&website, &query.term_tfs, &stand_ins);
+        website.synonyms = stand_ins.into_keys().map(String::from).collect();
+        website.synonyms.sort();
+        (similarity, website)
+    };
+    if parallel {
+        websites.par_iter().map(score).collect()
+    } else {
+        websites.iter().map(score).collect()
+    }
+}
+
+/// Scores websites against several queries at once, each with a weight.
+///
+/// Returns each website's weighted contribution from every query, in query order, alongside
+/// their sum. Websites matching none of the queries are left out, and the rest keep the order
+/// given, to be ranked by the selection of the results returned.
+pub fn get_blended_scores(
     document_count: i64,
-    lemmatized_query: &[String],
+    weighted_queries: &[(Vec<String>, f64)],
     websites: &[Webpage]
-) -> Vec<(f32, Webpage)> {
-    // Calculate query term frequencies
-    let query_term_tfs = calculate_query_term_frequencies(lemmatized_query);
+) -> Vec<(f64, Vec<f64>, Webpage)> {
+    let query_term_tfs: Vec<(HashMap<String, f64>, f64)> = weighted_queries
+        .iter()
+        .map(|(query, weight)| (calculate_query_term_frequencies(query), *weight))
+        .collect();
 
-    // Calculate TF-IDF scores and similarities for each website
-    let mut website_similarities: Vec<(f32, Webpage)> = websites
+    websites
         .iter()
-        .map(|website| {
-            let similarity = calculate_similarity(website, &query_term_tfs, document_count);
-            (similarity, website.clone())
+        .filter_map(|website| {
+            let contributions: Vec<f64> = query_term_tfs
+                .iter()
+                .map(|(term_tfs, weight)| {
+                    // Blended candidates' fields aren't counted, so there's nothing to boost
+                    let similarity = calculate_similarity(
+                        website,
+                        term_tfs,
+                        &HashMap::new(),
+                        document_count,
+                        FieldBoosts::default()
+                    );
+                    weight * similarity
+                })
+                .collect();
+            // Summed in query order, so the same inputs always give the same score
+            let score: f64 = contributions.iter().sum();
+            (score > 0.0).then(|| (score, contributions, website.clone()))
         })
-        .collect();
+        .collect()
+}
+
+/// Added to the relevance score of pages containing every quoted phrase of the query in order,
+/// so they outrank pages that only contain the phrases' words. As relevance is at most 1 and
+/// authority adds at most `PAGERANK_WEIGHT`, this is enough with the default weight.
+pub const PHRASE_MATCH_BONUS: f64 = 1.0;
+
+/// How much of the query each of a page's fields contains, weighted: for each field, its weight
+/// times the share of the distinct `keywords` among the field's words, normalised like the query.
+pub fn field_bonus(
+    keywords: &[String],
+    webpage: &Webpage,
+    weights: &FieldWeights,
+    normaliser: Normaliser
+) -> f64 {
+    let mut distinct: Vec<&String> = keywords.iter().collect();
+    distinct.sort();
+    distinct.dedup();
+    if distinct.is_empty() {
+        return 0.0;
+    }
+
+    [
+        (&webpage.title, weights.title),
+        (&webpage.url, weights.url),
+        (&webpage.description, weights.description),
+    ]
+        .into_iter()
+        .filter(|(_, weight)| *weight > 0.0)
+        .map(|(text, weight)| {
+            let words = normaliser.terms(text);
+            let found = distinct
+                .iter()
+                .filter(|keyword| words.contains(*keyword))
+                .count();
+            (weight * (found as f64)) / (distinct.len() as f64)
+        })
+        .sum()
+}
+
+/// Added to the relevance score of pages where every `NEAR/n` pair of the query is within its
+/// distance. Half the phrase bonus, as terms merely near each other are weaker evidence than an
+/// exact phrase.
+pub const PROXIMITY_BONUS: f64 = 0.5;
+
+/// The fewest words between an occurrence of one term and one of the other, given each term's
+/// positions in ascending order, or `None` if either never occurs.
+pub fn min_distance(left: &[i32], right: &[i32]) -> Option<u32> {
+    let (mut i, mut j) = (0, 0);
+    let mut closest = None;
+    while i < left.len() && j < right.len() {
+        let distance = left[i].abs_diff(right[j]);
+        closest = Some(closest.map_or(distance, |closest: u32| closest.min(distance)));
+        if left[i] < right[j] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    closest
+}
+
+/// How close together a page has all of `terms`, given each one's positions in ascending
+/// order: 1 when they're next to each other, falling towards 0 as the fewest words spanning all
+/// of them grow. `None` if any term never occurs.
+pub fn term_closeness(positions: &HashMap<String, Vec<i32>>, terms: &[String]) -> Option<f64> {
+    if terms.len() < 2 {
+        return None;
+    }
+    let mut occurrences: Vec<(i32, usize)> = vec![];
+    for (index, term) in terms.iter().enumerate() {
+        let term_positions = positions.get(term).filter(|term_positions| !term_positions.is_empty())?;
+        occurrences.extend(term_positions.iter().map(|&position| (position, index)));
+    }
+    occurrences.sort_unstable();
+
+    // The narrowest window of occurrences containing every term, slid along them in order
+    let mut counts = vec![0; terms.len()];
+    let mut covered = 0;
+    let mut start = 0;
+    let mut narrowest = u32::MAX;
+    for &(position, index) in &occurrences {
+        counts[index] += 1;
+        if counts[index] == 1 {
+            covered += 1;
+        }
+        while covered == terms.len() {
+            let (first, first_index) = occurrences[start];
+            narrowest = narrowest.min(position.abs_diff(first));
+            counts[first_index] -= 1;
+            if counts[first_index] == 0 {
+                covered -= 1;
+            }
+            start += 1;
+        }
+    }
+    // Words between the terms beyond the fewest they could have
+    let gaps = narrowest.saturating_sub((terms.len() - 1) as u32);
+    Some(1.0 / (1.0 + (gaps as f64)))
+}
+
+/// Whether `phrase` occurs as consecutive words in order, given each word's positions in
+/// ascending order.
+pub fn contains_at_positions(positions: &HashMap<String, Vec<i32>>, phrase: &[String]) -> bool {
+    let Some((first, rest)) = phrase.split_first() else {
+        return false;
+    };
+    positions
+        .get(first)
+        .into_iter()
+        .flatten()
+        .any(|&start| {
+            rest.iter()
+                .zip(1..)
+                .all(|(word, offset)| {
+                    positions
+                        .get(word)
+                        .is_some_and(|after| after.binary_search(&(start + offset)).is_ok())
+                })
+        })
+}
+
+/// Added to the relevance of a page with `matched_terms` of a query's `distinct_terms`: all of
+/// `weight` for a page with every term, and that share of it for one with some.
+pub fn coverage_bonus(matched_terms: usize, distinct_terms: usize, weight: f64) -> f64 {
+    weight * (matched_terms.min(distinct_terms) as f64) / (distinct_terms.max(1) as f64)
+}
+
+/// Why a page was taken to be low quality, and the multiplier its score got for it
+#[derive(Debug, Clone)]
+pub struct QualityPenalty {
+    /// `thin`, `keyword_stuffing` or `empty_description`
+    pub reasons: Vec<&'static str>,
+    /// The product of the penalties for each reason
+    pub penalty: f64,
+}
 
-    // Sort websites by similarity score in descending order
-    website_similarities.sort_unstable_by(|a, b|
-        b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal)
+/// Checks a page against the quality thresholds: too few words, a query term making up too much
+/// of its text, or a near-empty description. `None` if it passes all of them.
+pub fn assess_quality(webpage: &Webpage, config: &QualityConfig) -> Option<QualityPenalty> {
+    let mut reasons = vec![];
+    let mut penalty = 1.0;
+    let mut check = |failed: bool, reason, reason_penalty: f64| {
+        if failed && reason_penalty < 1.0 {
+            reasons.push(reason);
+            penalty *= reason_penalty;
+        }
+    };
+    check(webpage.word_count < config.thin_words, "thin", config.thin_penalty);
+    let density = webpage.keywords
+        .iter()
+        .map(|(_, occurrences)| (*occurrences as f64) / (webpage.word_count.max(1) as f64))
+        .fold(0.0, f64::max);
+    check(density > config.max_density, "keyword_stuffing", config.stuffing_penalty);
+    let description_chars = webpage.description.trim().chars().count();
+    check(
+        description_chars < config.min_description_chars,
+        "empty_description",
+        config.description_penalty
     );
+    (!reasons.is_empty()).then_some(QualityPenalty { reasons, penalty })
+}
+
+/// Multiplies the relevance of pages that matched part of the query only through synonyms, so
+/// they rank below pages using the query's own words.
+pub const SYNONYM_WEIGHT: f64 = 0.8;
+
+/// Multiplies the relevance of pages that matched a fuzzy term only through a word spelt like
+/// it. Lower than `SYNONYM_WEIGHT`, since a similar spelling is weaker evidence than a synonym.
+pub const FUZZY_WEIGHT: f64 = 0.7;
 
-    website_similarities
+/// Multiplies the relevance of pages found only through words from pages linking to the query's
+/// matches. Lower than `FUZZY_WEIGHT`, since such pages needn't contain any of the query's words.
+pub const LINK_EXPANSION_WEIGHT: f64 = 0.5;
+
+/// How many of the query's distinct terms `website` contains, directly or through a synonym.
+fn count_matched_terms(
+    website: &Webpage,
+    query_term_tfs: &HashMap<String, f64>,
+    stand_ins: &HashMap<&str, &str>
+) -> usize {
+    query_term_tfs
+        .keys()
+        .filter(|term| {
+            website.keywords.iter().any(|(keyword, _)| keyword.word == **term) ||
+                stand_ins.values().any(|covered| covered == term)
+        })
+        .count()
 }
 
-fn calculate_query_term_frequencies(lemmatized_query: &[String]) -> HashMap<String, f32> {
+/// The synonyms standing in for query terms `website` lacks, mapped to the term they stand in
+/// for. Only the first synonym the website contains stands in for each term.
+fn synonym_stand_ins<'a>(website: &Webpage, synonyms: &'a [(String, String)]) -> HashMap<&'a str, &'a str> {
+    let contains = |word: &str| website.keywords.iter().any(|(keyword, _)| keyword.word == word);
+    let mut stand_ins: HashMap<&str, &str> = HashMap::new();
+    for (term, synonym) in synonyms {
+        let covered = contains(term) || stand_ins.values().any(|covered| covered == term);
+        if !covered && contains(synonym) {
+            stand_ins.insert(synonym, term);
+        }
+    }
+    stand_ins
+}
+
+/// Returns whether `phrase` appears in `words` as consecutive words in the same order. Repeated
+/// words must repeat in `words` too, so "new new york" doesn't match "new york".
+pub fn contains_consecutively(words: &[String], phrase: &[String]) -> bool {
+    !phrase.is_empty() && words.windows(phrase.len()).any(|window| window == phrase)
+}
+
+fn calculate_query_term_frequencies(lemmatized_query: &[String]) -> HashMap<String, f64> {
     let mut query_word_occurrences = HashMap::new();
-    let total_query_terms = lemmatized_query.len() as f32;
+    let total_query_terms = lemmatized_query.len() as f64;
 
     // Count occurrences of each query term
     for word in lemmatized_query {
@@ -38,25 +620,169 @@ fn calculate_query_term_frequencies(lemmatized_query: &[String]) -> HashMap<Stri
     // Calculate term frequencies
     query_word_occurrences
         .into_iter()
-        .map(|(word, count)| (word.to_string(), (count as f32) / total_query_terms))
+        .map(|(word, count)| (word.to_string(), (count as f64) / total_query_terms))
         .collect()
 }
 
+/// Inverse document frequency of a word found in `documents_containing_word` of
+/// `document_count` pages. Always finite and never negative: a stale frequency is clamped to
+/// the index size, and an empty index makes every word worthless.
+pub fn idf(document_count: i64, documents_containing_word: i64) -> f64 {
+    if document_count <= 0 {
+        return 0.0;
+    }
+    // A page containing the word was fetched, so at least one document has it
+    let documents_containing_word = documents_containing_word.clamp(1, document_count);
+    ((document_count as f64) / (documents_containing_word as f64)).ln()
+}
+
+/// BM25's inverse document frequency, which unlike `idf` stays positive for words in over half
+/// of the pages. Stale frequencies are clamped the same way.
+fn bm25_idf(document_count: i64, documents_containing_word: i64) -> f64 {
+    if document_count <= 0 {
+        return 0.0;
+    }
+    let documents_containing_word = documents_containing_word.clamp(1, document_count) as f64;
+    let document_count = document_count as f64;
+    let documents_without_word = (document_count - documents_containing_word).max(0.0);
+    (1.0 + (documents_without_word + 0.5) / (documents_containing_word + 0.5)).ln()
+}
+
+/// A keyword's occurrences on `website`, the `index`th of its keywords, with each in its title
+/// or description counting extra by `boosts`.
+fn boosted_occurrences(website: &Webpage, index: usize, occurrences: i32, boosts: FieldBoosts) -> f64 {
+    let fields = website.field_occurrences.get(index).copied().unwrap_or_default();
+    (occurrences as f64) +
+        boosts.title * (fields.title as f64) +
+        boosts.description * (fields.description as f64)
+}
+
+/// One query term's part in a page's relevance score, for `explain=true`
+#[derive(Debug, Clone)]
+pub struct TermScore {
+    pub term: String,
+    /// The page's word counted for the term: the term itself, or a synonym or similar spelling
+    /// standing in for it
+    pub word: String,
+    /// The word's occurrences on the page, with those in its title and description boosted
+    pub occurrences: f64,
+    /// TF-IDF's term frequency is the occurrences over the page's word count; BM25's is the
+    /// occurrences themselves
+    pub tf: f64,
+    pub idf: f64,
+    /// How much of the relevance score the term accounts for
+    pub contribution: f64,
+}
+
+/// The parts of a TF-IDF cosine similarity, for `explain=true`
+#[derive(Debug, Clone, Copy)]
+pub struct Cosine {
+    pub dot_product: f64,
+    pub query_norm: f64,
+    pub document_norm: f64,
+}
+
+/// Breaks `website`'s TF-IDF score down by term, the way `TfIdfScorer` computes it.
+/// Each term's contribution is its share of the dot product over the norms, so together they add
+/// up to the cosine similarity.
+pub fn explain_tf_idf(
+    document_count: i64,
+    boosts: FieldBoosts,
+    lemmatized_query: &[String],
+    synonyms: &[(String, String)],
+    website: &Webpage
+) -> (Vec<TermScore>, Cosine) {
+    let query_term_tfs = calculate_query_term_frequencies(lemmatized_query);
+    let stand_ins = synonym_stand_ins(website, synonyms);
+    let mut terms = vec![];
+    let mut products = vec![];
+    let mut cosine = Cosine { dot_product: 0.0, query_norm: 0.0, document_norm: 0.0 };
+    for (index, (word, occurrences)) in website.keywords.iter().enumerate() {
+        let term = stand_ins.get(word.word.as_str()).copied().unwrap_or(&word.word);
+        if let Some(&query_tf) = query_term_tfs.get(term) {
+            let occurrences = boosted_occurrences(website, index, *occurrences, boosts);
+            let tf = occurrences / (website.word_count as f64);
+            let idf = idf(document_count, word.documents_containing_word);
+            let (query_tf_idf, tf_idf) = (query_tf * idf, tf * idf);
+            cosine.query_norm += query_tf_idf.powi(2);
+            cosine.document_norm += tf_idf.powi(2);
+            cosine.dot_product += query_tf_idf * tf_idf;
+            products.push(query_tf_idf * tf_idf);
+            terms.push(TermScore {
+                term: term.to_string(),
+                word: word.word.clone(),
+                occurrences,
+                tf,
+                idf,
+                contribution: 0.0,
+            });
+        }
+    }
+    cosine.query_norm = cosine.query_norm.sqrt();
+    cosine.document_norm = cosine.document_norm.sqrt();
+    let norms = cosine.query_norm * cosine.document_norm;
+    if norms > 0.0 {
+        for (term, product) in terms.iter_mut().zip(products) {
+            term.contribution = product / norms;
+        }
+    }
+    (terms, cosine)
+}
+
+/// Breaks `website`'s BM25 score down by term, the way `Bm25Scorer` computes it before dividing
+/// by its scale, the best possible score. That depends on every candidate, so the contributions
+/// are left undivided.
+pub fn explain_bm25(
+    document_count: i64,
+    average_length: f64,
+    config: Bm25Config,
+    boosts: FieldBoosts,
+    lemmatized_query: &[String],
+    synonyms: &[(String, String)],
+    website: &Webpage
+) -> Vec<TermScore> {
+    let query_term_tfs = calculate_query_term_frequencies(lemmatized_query);
+    let stand_ins = synonym_stand_ins(website, synonyms);
+    let length_ratio = (website.word_count.max(1) as f64) / average_length.max(1.0);
+    let length_norm = config.k1 * (1.0 - config.b + config.b * length_ratio);
+    let mut terms = vec![];
+    for (index, (word, occurrences)) in website.keywords.iter().enumerate() {
+        let term = stand_ins.get(word.word.as_str()).copied().unwrap_or(&word.word);
+        if let Some(&query_tf) = query_term_tfs.get(term) {
+            let tf = boosted_occurrences(website, index, *occurrences, boosts);
+            let idf = bm25_idf(document_count, word.documents_containing_word);
+            terms.push(TermScore {
+                term: term.to_string(),
+                word: word.word.clone(),
+                occurrences: tf,
+                tf,
+                idf,
+                contribution: query_tf * idf * (tf * (config.k1 + 1.0)) / (tf + length_norm),
+            });
+        }
+    }
+    terms
+}
+
 fn calculate_similarity(
     website: &Webpage,
-    query_term_tfs: &HashMap<String, f32>,
-    document_count: i64
-) -> f32 {
+    query_term_tfs: &HashMap<String, f64>,
+    stand_ins: &HashMap<&str, &str>,
+    document_count: i64,
+    boosts: FieldBoosts
+) -> f64 {
     let mut query_vector_sum = 0.0;
     let mut document_vector_sum = 0.0;
     let mut dot_product = 0.0;
 
-    for (word, occurrences) in &website.keywords {
-        let tf = (*occurrences as f32) / (website.word_count as f32);
-        let idf = ((document_count as f32) / (word.documents_containing_word as f32)).ln().max(0.0);
+    // Keywords are in a fixed order and summed in f64, so the same page always gets the same score
+    for (index, (word, occurrences)) in website.keywords.iter().enumerate() {
+        let tf = boosted_occurrences(website, index, *occurrences, boosts) / (website.word_count as f64);
+        let idf = idf(document_count, word.documents_containing_word);
         let tf_idf = tf * idf;
 
-        if let Some(&query_tf) = query_term_tfs.get(&word.word) {
+        let term = stand_ins.get(word.word.as_str()).copied().unwrap_or(&word.word);
+        if let Some(&query_tf) = query_term_tfs.get(term) {
             let query_tf_idf = query_tf * idf;
             query_vector_sum += query_tf_idf.powi(2);
             document_vector_sum += tf_idf.powi(2);
@@ -74,3 +800,465 @@ fn calculate_similarity(
         0.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{ Value, json };
+    use std::path::PathBuf;
+    use crate::config::RankingConfig;
+    use crate::database::{ FieldOccurrences, Keyword };
+    use crate::pagerank::AuthorityScores;
+    use crate::search::{ self, RankingParams };
+
+    /// Pages, queries and blends the golden rankings are computed from
+    const CORPUS: &str = "tests/fixtures/golden/corpus.json";
+
+    /// The rankings the corpus gave when last blessed, with `BLESS=1 cargo test golden`
+    const EXPECTED: &str = "tests/fixtures/golden/expected.json";
+
+    /// Results recorded per query, after the blended ranking
+    const RANKED: usize = 5;
+
+    const GOLDEN_RANKING: RankingConfig = RankingConfig {
+        relevance: 1.0,
+        domain: 0.2,
+        freshness: 0.0,
+        links: 0.5,
+        clicks: 0.0,
+        hubs: 0.0,
+        authorities: 0.0,
+    };
+
+    const GOLDEN_BOOSTS: FieldBoosts = FieldBoosts { title: 3.0, description: 1.0 };
+
+    const GOLDEN_BM25: Bm25Config = Bm25Config { k1: 1.2, b: 0.75 };
+
+    fn fixture(path: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+    }
+
+    fn terms(value: &Value) -> Vec<String> {
+        value
+            .as_array()
+            .expect("terms are a list")
+            .iter()
+            .map(|term| term.as_str().expect("terms are strings").to_string())
+            .collect()
+    }
+
+    /// Every page of the corpus, as the database would return it for `terms`: only the pages
+    /// with one of them, holding only the keywords matched, with document frequencies and title
+    /// and description occurrences filled in over the whole corpus.
+    fn candidates(corpus: &Value, terms: &[String]) -> Vec<Webpage> {
+        let pages = corpus["pages"].as_array().expect("pages are a list");
+        let mut vocabulary: Vec<&str> = pages
+            .iter()
+            .flat_map(|page| page["keywords"].as_object().expect("keywords are an object").keys())
+            .map(String::as_str)
+            .collect();
+        vocabulary.sort();
+        vocabulary.dedup();
+        let documents = |word: &str| {
+            pages
+                .iter()
+                .filter(|page| page["keywords"].get(word).is_some())
+                .count() as i64
+        };
+        let occurrences_in = |text: &str, word: &str| {
+            text.to_lowercase()
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|token| *token == word)
+                .count() as u32
+        };
+
+        pages
+            .iter()
+            .filter_map(|page| {
+                let title = page["title"].as_str().unwrap_or_default();
+                let description = page["description"].as_str().unwrap_or_default();
+                let mut keywords: Vec<(Keyword, i32)> = page["keywords"]
+                    .as_object()?
+                    .iter()
+                    .filter(|(word, _)| terms.contains(word))
+                    .map(|(word, occurrences)| {
+                        let keyword = Keyword {
+                            id: vocabulary.binary_search(&word.as_str()).unwrap_or_default() as i32,
+                            word: word.clone(),
+                            documents_containing_word: documents(word),
+                        };
+                        (keyword, occurrences.as_i64().unwrap_or_default() as i32)
+                    })
+                    .collect();
+                if keywords.is_empty() {
+                    return None;
+                }
+                keywords.sort_by(|(a, _), (b, _)| a.word.cmp(&b.word));
+                let field_occurrences = keywords
+                    .iter()
+                    .map(|(keyword, _)| FieldOccurrences {
+                        title: occurrences_in(title, &keyword.word),
+                        description: occurrences_in(description, &keyword.word),
+                    })
+                    .collect();
+                Some(Webpage {
+                    id: page["id"].as_i64()? as i32,
+                    url: page["url"].as_str()?.to_string(),
+                    title: title.to_string(),
+                    description: description.to_string(),
+                    word_count: page["word_count"].as_i64()? as i32,
+                    keywords,
+                    field_occurrences,
+                    ..Webpage::default()
+                })
+            })
+            .collect()
+    }
+
+    fn top_domains(corpus: &Value) -> HashMap<String, usize> {
+        terms(&corpus["top_domains"])
+            .into_iter()
+            .enumerate()
+            .map(|(rank, domain)| (domain, rank + 1))
+            .collect()
+    }
+
+    /// The scores and order of the best ranked results, blended the way a search blends them.
+    fn ranked(corpus: &Value, mut scored: Vec<(f64, Webpage)>) -> Value {
+        let top_domains = top_domains(corpus);
+        let authority = AuthorityScores::default();
+        let params = RankingParams::new(&GOLDEN_RANKING);
+        search::select_ranked(&top_domains, &authority, &params, &mut scored, RANKED);
+        json!(
+            scored
+                .iter()
+                .map(|(score, webpage)| {
+                    json!({
+                        "id": webpage.id,
+                        "url": webpage.url,
+                        "relevance": score,
+                        "blended": params.blended_score(*score, webpage, &authority, &top_domains),
+                    })
+                })
+                .collect::<Vec<_>>()
+        )
+    }
+
+    /// Every query and blend of the corpus, scored and ranked.
+    fn golden_rankings() -> Value {
+        let corpus = std::fs::read_to_string(fixture(CORPUS)).expect("Failed to read the corpus");
+        let corpus: Value = serde_json::from_str(&corpus).expect("The corpus is valid JSON");
+        let pages = corpus["pages"].as_array().expect("pages are a list");
+        let document_count = pages.len() as i64;
+        let average_length = pages
+            .iter()
+            .map(|page| page["word_count"].as_f64().unwrap_or_default())
+            .sum::<f64>() / (document_count as f64);
+
+        let queries: Vec<Value> = corpus["queries"]
+            .as_array()
+            .expect("queries are a list")
+            .iter()
+            .map(|query| {
+                let ranker = query["ranker"].as_str().and_then(Ranker::parse).expect("a ranker");
+                let terms = terms(&query["terms"]);
+                let websites = candidates(&corpus, &terms);
+                let scorer = ranker.scorer(average_length, GOLDEN_BM25);
+                let scoring = ScoringQuery::new(document_count, GOLDEN_BOOSTS, &terms, &[]);
+                let scored = score_candidates(scorer.as_ref(), &scoring, &websites);
+                json!({
+                    "name": query["name"],
+                    "ranker": ranker.name(),
+                    "terms": terms,
+                    "candidates": scored.len(),
+                    "results": ranked(&corpus, scored),
+                })
+            })
+            .collect();
+
+        let blends: Vec<Value> = corpus["blends"]
+            .as_array()
+            .expect("blends are a list")
+            .iter()
+            .map(|blend| {
+                let weighted: Vec<(Vec<String>, f64)> = blend["queries"]
+                    .as_array()
+                    .expect("a blend's queries are a list")
+                    .iter()
+                    .map(|query| (terms(&query["terms"]), query["weight"].as_f64().unwrap_or(1.0)))
+                    .collect();
+                let mut all_terms: Vec<String> = weighted
+                    .iter()
+                    .flat_map(|(terms, _)| terms.clone())
+                    .collect();
+                all_terms.sort();
+                all_terms.dedup();
+                let websites = candidates(&corpus, &all_terms);
+                let scored: Vec<(f64, Webpage)> = get_blended_scores(
+                    document_count,
+                    &weighted,
+                    &websites
+                )
+                    .into_iter()
+                    .map(|(score, _, webpage)| (score, webpage))
+                    .collect();
+                json!({
+                    "name": blend["name"],
+                    "candidates": scored.len(),
+                    "results": ranked(&corpus, scored),
+                })
+            })
+            .collect();
+
+        json!({ "queries": queries, "blends": blends })
+    }
+
+    /// Fails when a ranking change moves any score or result of the golden corpus. If the change
+    /// was meant, re-bless the expected rankings with `BLESS=1 cargo test golden` and commit
+    /// `tests/fixtures/golden/expected.json` with it, so the diff shows what moved.
+    #[test]
+    fn golden_rankings_are_unchanged() {
+        let actual = serde_json::to_string_pretty(&golden_rankings()).unwrap() + "\n";
+        let path = fixture(EXPECTED);
+        if std::env::var_os("BLESS").is_some() {
+            std::fs::write(&path, &actual).expect("Failed to bless the golden rankings");
+            return;
+        }
+        let expected = std::fs
+            ::read_to_string(&path)
+            .expect("No golden rankings; create them with BLESS=1 cargo test golden");
+        if let Some((line, (actual, expected))) = actual
+            .lines()
+            .zip(expected.lines())
+            .enumerate()
+            .find(|(_, (actual, expected))| actual != expected)
+        {
+            panic!(
+                "Rankings differ from {} at line {}:\n  expected: {}\n  actual:   {}\nIf the change is intended, re-bless with BLESS=1 cargo test golden",
+                EXPECTED,
+                line + 1,
+                expected.trim(),
+                actual.trim()
+            );
+        }
+        assert_eq!(
+            actual.lines().count(),
+            expected.lines().count(),
+            "Rankings differ from {} in length; if intended, re-bless with BLESS=1 cargo test golden",
+            EXPECTED
+        );
+    }
+
+    #[test]
+    fn golden_rankings_are_deterministic() {
+        assert_eq!(golden_rankings(), golden_rankings());
+    }
+
+    #[test]
+    fn scoring_in_parallel_matches_scoring_serially() {
+        let corpus: Value = serde_json
+            ::from_str(&std::fs::read_to_string(fixture(CORPUS)).unwrap())
+            .unwrap();
+        let terms = vec!["rust".to_string(), "async".to_string()];
+        let websites = candidates(&corpus, &terms);
+        let query = ScoringQuery::new(10, GOLDEN_BOOSTS, &terms, &[]);
+        let serial = score_with(&TfIdfScorer, &query, &websites, false);
+        let parallel = score_with(&TfIdfScorer, &query, &websites, true);
+        let scores = |scored: &[(f64, Webpage)]| -> Vec<(i32, f64)> {
+            scored
+                .iter()
+                .map(|(score, webpage)| (webpage.id, *score))
+                .collect()
+        };
+        assert_eq!(scores(&serial), scores(&parallel));
+    }
+
+    #[test]
+    fn identical_pages_tie_break_by_id() {
+        let page = |id| (1.0, Webpage { id, ..Webpage::default() });
+        let mut pages = [page(7), page(3), (2.0, Webpage { id: 9, ..Webpage::default() })];
+        pages.sort_by(best_first);
+        let ids: Vec<i32> = pages
+            .iter()
+            .map(|(_, webpage)| webpage.id)
+            .collect();
+        assert_eq!(ids, vec![9, 3, 7]);
+    }
+
+    /// A page of `word_count` words with each `(word, occurrences)`, every word being on 1 of the
+    /// 10 pages of the index
+    fn blend_page(id: i32, word_count: i32, words: &[(&str, i32)]) -> Webpage {
+        let keywords = words
+            .iter()
+            .map(|&(word, occurrences)| {
+                let keyword = Keyword {
+                    id: 0,
+                    word: word.to_string(),
+                    documents_containing_word: 1,
+                };
+                (keyword, occurrences)
+            })
+            .collect();
+        Webpage { id, word_count, keywords, ..Webpage::default() }
+    }
+
+    fn query(terms: &[&str], weight: f64) -> (Vec<String>, f64) {
+        (
+            terms
+                .iter()
+                .map(|term| term.to_string())
+                .collect(),
+            weight,
+        )
+    }
+
+    /// The blended scores and contributions of each page, best first
+    fn blended(queries: &[(Vec<String>, f64)], pages: &[Webpage]) -> Vec<(i32, f64, Vec<f64>)> {
+        let mut scored: Vec<(i32, f64, Vec<f64>)> = get_blended_scores(10, queries, pages)
+            .into_iter()
+            .map(|(score, contributions, webpage)| (webpage.id, score, contributions))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scored
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} isn't {}", actual, expected);
+    }
+
+    #[test]
+    fn blends_by_the_weight_of_each_query() {
+        let pages = [
+            blend_page(1, 10, &[("rust", 2)]),
+            blend_page(2, 10, &[("jaguar", 2)]),
+            blend_page(3, 10, &[("rust", 1), ("jaguar", 1)]),
+        ];
+
+        // Each page is wholly relevant to each query it has the word of, so it scores the
+        // weights of those queries
+        let scores = blended(&[query(&["rust"], 0.75), query(&["jaguar"], 0.25)], &pages);
+        let ids: Vec<i32> = scores.iter().map(|(id, _, _)| *id).collect();
+        assert_eq!(ids, [3, 1, 2]);
+        assert_close(scores[0].1, 1.0);
+        assert_eq!(scores[0].2, [0.75, 0.25]);
+        assert_close(scores[1].1, 0.75);
+        assert_eq!(scores[1].2, [0.75, 0.0]);
+        assert_close(scores[2].1, 0.25);
+        assert_eq!(scores[2].2, [0.0, 0.25]);
+
+        // Swapping the weights swaps the single-word pages
+        let scores = blended(&[query(&["rust"], 0.25), query(&["jaguar"], 0.75)], &pages);
+        let ids: Vec<i32> = scores.iter().map(|(id, _, _)| *id).collect();
+        assert_eq!(ids, [3, 2, 1]);
+    }
+
+    #[test]
+    fn blends_each_querys_cosine_similarity() {
+        // Against "rust async", with both words equally rare, the page with 3 of one and 1 of
+        // the other is 4 / sqrt(2 * 10) similar, and the even one is wholly similar
+        let uneven = blend_page(1, 10, &[("async", 1), ("rust", 3)]);
+        let even = blend_page(2, 10, &[("async", 2), ("rust", 2)]);
+        let jaguar = blend_page(3, 10, &[("jaguar", 1)]);
+        let unrelated = blend_page(4, 10, &[("python", 5)]);
+        let pages = [uneven, even, jaguar, unrelated];
+
+        let scores = blended(&[query(&["rust", "async"], 0.5), query(&["jaguar"], 0.5)], &pages);
+        let ids: Vec<i32> = scores.iter().map(|(id, _, _)| *id).collect();
+        // The page matching neither query is left out
+        assert_eq!(ids, [2, 3, 1]);
+        assert_close(scores[0].1, 0.5);
+        assert_close(scores[1].1, 0.5);
+        assert_close(scores[2].1, (0.5 * 4.0) / (20.0_f64).sqrt());
+
+        // Weighted towards "jaguar", the uneven page falls further behind
+        let scores = blended(&[query(&["rust", "async"], 0.2), query(&["jaguar"], 0.8)], &pages);
+        let ids: Vec<i32> = scores.iter().map(|(id, _, _)| *id).collect();
+        assert_eq!(ids, [3, 2, 1]);
+        assert_close(scores[0].1, 0.8);
+        assert_close(scores[1].1, 0.2);
+        assert_close(scores[2].1, (0.2 * 4.0) / (20.0_f64).sqrt());
+    }
+
+    #[test]
+    fn a_repeated_word_weighs_more_within_its_query() {
+        // "rust rust async" leans 2:1 towards rust, so the page leaning 3:1 towards it is
+        // 7 / sqrt(50) similar and the even one only 3 / sqrt(10)
+        let pages = [
+            blend_page(1, 10, &[("async", 2), ("rust", 2)]),
+            blend_page(2, 10, &[("async", 1), ("rust", 3)]),
+        ];
+        let scores = blended(&[query(&["rust", "rust", "async"], 1.0)], &pages);
+        let ids: Vec<i32> = scores.iter().map(|(id, _, _)| *id).collect();
+        assert_eq!(ids, [2, 1]);
+        assert_close(scores[0].1, 7.0 / (50.0_f64).sqrt());
+        assert_close(scores[1].1, 3.0 / (10.0_f64).sqrt());
+    }
+
+    fn words(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn positions(entries: &[(&str, &[i32])]) -> HashMap<String, Vec<i32>> {
+        entries
+            .iter()
+            .map(|(word, positions)| (word.to_string(), positions.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_phrases_at_consecutive_positions() {
+        let text = positions(&[("new", &[3, 10, 11]), ("york", &[7, 12]), ("city", &[13])]);
+        assert!(contains_at_positions(&text, &words("new york")));
+        assert!(contains_at_positions(&text, &words("new york city")));
+        // Repeated words need a position each, in a row
+        assert!(contains_at_positions(&text, &words("new new york")));
+        assert!(!contains_at_positions(&text, &words("new new new york")));
+        assert!(!contains_at_positions(&text, &words("york new")));
+        assert!(!contains_at_positions(&text, &words("new city")));
+        assert!(contains_at_positions(&text, &words("york")));
+        assert!(!contains_at_positions(&text, &words("boston")));
+        assert!(!contains_at_positions(&text, &[]));
+
+        // A repeated word has to occur twice in a row, not just twice
+        let text = positions(&[("very", &[1, 5]), ("good", &[2, 7])]);
+        assert!(!contains_at_positions(&text, &words("very very good")));
+        let text = positions(&[("very", &[4, 5]), ("good", &[6])]);
+        assert!(contains_at_positions(&text, &words("very very good")));
+    }
+
+    #[test]
+    fn finds_phrases_as_consecutive_words() {
+        let title = words("the new york times in new york");
+        assert!(contains_consecutively(&title, &words("new york")));
+        assert!(contains_consecutively(&title, &words("york times")));
+        assert!(contains_consecutively(&title, &title));
+        assert!(!contains_consecutively(&title, &words("new times")));
+        assert!(!contains_consecutively(&title, &words("new new york")));
+        assert!(contains_consecutively(&words("a new new york"), &words("new new york")));
+        assert!(!contains_consecutively(&words("new york"), &words("new york times")));
+        assert!(!contains_consecutively(&title, &[]));
+        assert!(!contains_consecutively(&[], &words("new")));
+    }
+
+    #[test]
+    fn idf_is_finite_for_empty_indexes_and_stale_counts() {
+        for (document_count, documents_containing_word) in [(0, 0), (0, 5), (-1, 1)] {
+            assert_eq!(idf(document_count, documents_containing_word), 0.0);
+            assert_eq!(bm25_idf(document_count, documents_containing_word), 0.0);
+        }
+
+        // More pages with the word than the index holds counts as every page having it
+        assert_eq!(idf(3, 5), 0.0);
+        assert_eq!(idf(3, 3), 0.0);
+        let stale = bm25_idf(3, 5);
+        assert!(stale.is_finite() && stale > 0.0, "{}", stale);
+        assert_eq!(stale, bm25_idf(3, 3));
+
+        // A fetched page has the word, whatever the count says
+        assert_eq!(idf(4, 0), (4.0_f64).ln());
+        assert_eq!(idf(4, 0), idf(4, 1));
+        assert_eq!(bm25_idf(4, 0), bm25_idf(4, 1));
+        assert_eq!(idf(1, 1), 0.0);
+        assert!(bm25_idf(1, 1) > 0.0);
+    }
+}
@@ -0,0 +1,234 @@
+use axum::{
+    extract::{ ConnectInfo, Extension, Query, Request },
+    http::{ header, HeaderMap, HeaderName, HeaderValue, StatusCode },
+    middleware::Next,
+    response::{ IntoResponse, Json, Response },
+};
+use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{ Hash, Hasher };
+use std::net::SocketAddr;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+use crate::auth::extract_turnstile_token;
+use crate::client_ip::client_identity;
+use crate::state::AppState;
+
+const SHARD_COUNT: usize = 16;
+
+/// Number of tracked clients per shard above which expired windows are pruned
+const PRUNE_THRESHOLD: usize = 4096;
+
+static RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+static RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+static RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+/// Outcome of counting a request against a client's quota
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset: Duration,
+}
+
+/// Fixed-window request counter, sharded by client key to avoid a single global lock
+pub struct RateLimiter {
+    window: Duration,
+    shards: Vec<Mutex<HashMap<String, Window>>>,
+}
+
+impl RateLimiter {
+    pub fn new(window: Duration) -> Self {
+        RateLimiter {
+            window,
+            shards: (0..SHARD_COUNT).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    /// Counts a request from `key` against a quota of `limit` requests per window.
+    pub fn check(&self, key: &str, limit: u32) -> RateLimitDecision {
+        self.check_at(key, limit, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, limit: u32, now: Instant) -> RateLimitDecision {
+        let mut shard = self.shards[shard_index(key)].lock().unwrap();
+
+        if shard.len() > PRUNE_THRESHOLD {
+            shard.retain(|_, window| now.duration_since(window.started) < self.window);
+        }
+
+        let window = shard.entry(key.to_string()).or_insert(Window { started: now, count: 0 });
+        if now.duration_since(window.started) >= self.window {
+            window.started = now;
+            window.count = 0;
+        }
+
+        let allowed = window.count < limit;
+        if allowed {
+            window.count += 1;
+        }
+
+        RateLimitDecision {
+            allowed,
+            limit,
+            remaining: limit.saturating_sub(window.count),
+            reset: self.window.saturating_sub(now.duration_since(window.started)),
+        }
+    }
+}
+
+fn shard_index(key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() as usize) % SHARD_COUNT
+}
+
+/// Applies per-client quotas and adds `RateLimit-*` headers to every response.
+///
+/// Clients are identified by their API key if they send a valid one, otherwise by an
+/// already-validated Turnstile token, and otherwise by their IP address.
+pub async fn limit_requests(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request,
+    next: Next
+) -> Response {
+    let (key, limit) = client_key(&state, request.headers(), &params, addr);
+    let decision = state.rate_limiter.check(&key, limit);
+
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(json!({ "error": "Rate limit exceeded" })),
+        ).into_response();
+        response.headers_mut().insert(header::RETRY_AFTER, seconds_header(decision.reset));
+        response
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(RATELIMIT_LIMIT.clone(), HeaderValue::from(decision.limit));
+    headers.insert(RATELIMIT_REMAINING.clone(), HeaderValue::from(decision.remaining));
+    headers.insert(RATELIMIT_RESET.clone(), seconds_header(decision.reset));
+    response
+}
+
+fn client_key(
+    state: &AppState,
+    headers: &HeaderMap,
+    params: &HashMap<String, String>,
+    addr: SocketAddr
+) -> (String, u32) {
+    let config = &state.config.rate_limit;
+    let ip = client_identity(addr.ip(), &state.config.client_identity);
+
+    if let Some(api_key) = state.api_keys.find_in_headers(headers) {
+        let quota = state.api_keys.quota(api_key).unwrap_or(config.api_key_quota);
+        return (format!("key:{}", api_key), quota);
+    }
+
+    if
+        let Some(token) = extract_turnstile_token(
+            headers,
+            params,
+            state.config.turnstile.allow_query_token
+        )
+    {
+        if state.token_cache.is_valid(&token, &ip) {
+            return (format!("token:{}", token), config.token_quota);
+        }
+    }
+
+    (format!("ip:{}", ip), config.ip_quota)
+}
+
+/// Formats a duration as whole seconds, rounding up so clients never retry too early.
+fn seconds_header(duration: Duration) -> HeaderValue {
+    let seconds = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+    HeaderValue::from(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn counts_requests_down_to_the_limit() {
+        let limiter = RateLimiter::new(WINDOW);
+        let start = Instant::now();
+
+        for expected_remaining in [2, 1, 0] {
+            let decision = limiter.check_at("ip:203.0.113.7", 3, start);
+            assert!(decision.allowed);
+            assert_eq!(decision.limit, 3);
+            assert_eq!(decision.remaining, expected_remaining);
+            assert_eq!(decision.reset, WINDOW);
+        }
+
+        let decision = limiter.check_at("ip:203.0.113.7", 3, start + Duration::from_secs(15));
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.reset, Duration::from_secs(45));
+    }
+
+    #[test]
+    fn starts_a_new_window_once_the_last_one_ends() {
+        let limiter = RateLimiter::new(WINDOW);
+        let start = Instant::now();
+        limiter.check_at("key:abc", 1, start);
+        assert!(!limiter.check_at("key:abc", 1, start + WINDOW - Duration::from_millis(1)).allowed);
+
+        let decision = limiter.check_at("key:abc", 1, start + WINDOW);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.reset, WINDOW);
+    }
+
+    #[test]
+    fn counts_each_client_separately() {
+        let limiter = RateLimiter::new(WINDOW);
+        let now = Instant::now();
+        assert!(limiter.check_at("ip:203.0.113.7", 1, now).allowed);
+        assert!(!limiter.check_at("ip:203.0.113.7", 1, now).allowed);
+        assert!(limiter.check_at("ip:198.51.100.1", 1, now).allowed);
+        assert!(limiter.check_at("key:abc", 1, now).allowed);
+    }
+
+    #[test]
+    fn prunes_expired_windows() {
+        let limiter = RateLimiter::new(WINDOW);
+        let clients = SHARD_COUNT * (PRUNE_THRESHOLD + 1);
+        let start = Instant::now();
+        for i in 0..clients {
+            limiter.check_at(&format!("ip:old-{}", i), 1, start);
+        }
+
+        // Once they expire, the old clients are pruned as new ones fill their shards
+        let later = start + WINDOW;
+        for i in 0..clients {
+            assert!(limiter.check_at(&format!("ip:new-{}", i), 1, later).allowed);
+        }
+        let tracked: usize = limiter.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum();
+        assert!(tracked < clients + clients / 2);
+    }
+
+    #[test]
+    fn rounds_reset_seconds_up() {
+        assert_eq!(seconds_header(Duration::from_secs(5)), HeaderValue::from(5));
+        assert_eq!(seconds_header(Duration::from_millis(4001)), HeaderValue::from(5));
+        assert_eq!(seconds_header(Duration::ZERO), HeaderValue::from(0));
+    }
+}
@@ -0,0 +1,421 @@
+use chrono::{ DateTime, Utc };
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::locale::LanguageCode;
+use crate::preferences::Preferences;
+use crate::ranking::{ MatchMode, MinMatch, Ranker, Recency };
+use crate::search::{ self, SearchOptions };
+use crate::state::AppState;
+use crate::timing::RequestTiming;
+use crate::trace::PipelineTrace;
+use crate::warnings::Warnings;
+
+/// How many of the results that moved furthest are listed in the report
+const MOVERS_SHOWN: usize = 10;
+
+/// A search captured by `POST /admin/capture`, with the ordering it returned
+struct Captured {
+    query: String,
+    options: SearchOptions,
+    candidate_limit: Option<i64>,
+    results: Vec<String>,
+}
+
+/// A result whose rank changed between the captured search and the replay
+struct Mover {
+    query: String,
+    url: String,
+    /// Ranks start at 1; `None` if the result wasn't returned
+    captured_rank: Option<usize>,
+    replayed_rank: Option<usize>,
+}
+
+impl Mover {
+    /// How far the result moved, counting a result that appeared or disappeared as moving past
+    /// the end of the longer ordering.
+    fn distance(&self, captured_len: usize, replayed_len: usize) -> usize {
+        let past_end = captured_len.max(replayed_len) + 1;
+        let captured = self.captured_rank.unwrap_or(past_end);
+        let replayed = self.replayed_rank.unwrap_or(past_end);
+        captured.abs_diff(replayed)
+    }
+}
+
+/// `replay <file> [--limit N] [--seed S]`: re-runs captured searches against the current build
+/// and database and prints how much their orderings changed.
+///
+/// With `--seed`, the captures are shuffled deterministically before `--limit` picks the first
+/// of them; otherwise they're replayed in file order.
+pub async fn run(state: &Arc<AppState>, args: &[String]) -> Result<(), String> {
+    let mut file = None;
+    let mut limit = usize::MAX;
+    let mut seed = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--limit" => {
+                limit = parse_arg(args.next(), "--limit")?;
+            }
+            "--seed" => {
+                seed = Some(parse_arg(args.next(), "--seed")?);
+            }
+            _ if file.is_none() => {
+                file = Some(arg.clone());
+            }
+            _ => {
+                return Err(format!("Unexpected argument {:?}", arg));
+            }
+        }
+    }
+    let file = file.ok_or("Usage: replay <file> [--limit N] [--seed S]")?;
+
+    let contents = std::fs
+        ::read_to_string(&file)
+        .map_err(|e| format!("Failed to read {}: {}", file, e))?;
+    let mut captures = vec![];
+    let mut other_index_sizes = 0;
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Value = serde_json
+            ::from_str(line)
+            .map_err(|e| format!("{}:{}: {}", file, line_number + 1, e))?;
+        if record["website_count"].as_i64() != Some(state.index.load().website_count) {
+            other_index_sizes += 1;
+        }
+        captures.push(
+            parse_capture(&record, state).ok_or_else(|| {
+                format!("{}:{}: not a captured search", file, line_number + 1)
+            })?
+        );
+    }
+    if let Some(seed) = seed {
+        shuffle(&mut captures, seed);
+    }
+    captures.truncate(limit);
+
+    let mut taus = vec![];
+    let mut overlaps = vec![];
+    let mut movers = vec![];
+    for captured in &captures {
+        let outcome = search::perform_search(
+            &captured.query,
+            state,
+            captured.options.clone(),
+            captured.candidate_limit,
+            &mut RequestTiming::default(),
+            &mut PipelineTrace::default(),
+            &mut Warnings::default()
+        ).await
+            .map_err(|e| format!("Failed to replay {:?}: {}", captured.query, e))?;
+        let replayed: Vec<String> = outcome.results
+            .into_iter()
+            .map(|(_, webpage)| webpage.url)
+            .collect();
+
+        if let Some(tau) = kendall_tau(&captured.results, &replayed) {
+            taus.push(tau);
+        }
+        overlaps.push(overlap(&captured.results, &replayed));
+        movers.extend(find_movers(&captured.query, &captured.results, &replayed));
+    }
+
+    println!("Replayed {} captured searches from {}.", captures.len(), file);
+    if other_index_sizes > 0 {
+        println!(
+            "{} were captured against an index of a different size, so some movement is expected.",
+            other_index_sizes
+        );
+    }
+    match mean(&taus) {
+        Some(tau) => {
+            println!(
+                "Mean Kendall tau: {:.3} (over {} searches sharing at least 2 results)",
+                tau,
+                taus.len()
+            );
+        }
+        None => println!("Mean Kendall tau: n/a (no search shared at least 2 results)"),
+    }
+    if let Some(overlap) = mean(&overlaps) {
+        println!("Mean result overlap: {:.3}", overlap);
+    }
+
+    movers.sort_by_key(|(_, distance)| Reverse(*distance));
+    if !movers.is_empty() {
+        println!("Biggest movers:");
+    }
+    for (mover, distance) in movers.iter().take(MOVERS_SHOWN) {
+        let rank = |rank: Option<usize>| rank.map_or("-".to_string(), |rank| rank.to_string());
+        println!(
+            "  {:>4} -> {:<4} (moved {}) {} for {:?}",
+            rank(mover.captured_rank),
+            rank(mover.replayed_rank),
+            distance,
+            mover.url,
+            mover.query
+        );
+    }
+    Ok(())
+}
+
+pub fn parse_arg<T: std::str::FromStr>(value: Option<&String>, name: &str) -> Result<T, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("{} needs a non-negative integer", name))
+}
+
+/// Reads the search options back out of a capture's `applied_options`, kept as its `params`.
+fn parse_capture(record: &Value, state: &AppState) -> Option<Captured> {
+    let params = &record["params"];
+    let two_tier = params["variant"].as_str() == Some("two_tier");
+    Some(Captured {
+        query: params["query"].as_str()?.to_string(),
+        options: SearchOptions {
+            include_links: params["features"]["links"].as_bool().unwrap_or(false),
+            num_results: params["results"].as_u64()? as usize,
+            verbatim_only: params["verbatim_only"].as_bool().unwrap_or(false),
+            keyword_budget: params["keyword_budget"].as_u64()? as usize,
+            include_deleted: params["include_deleted"].as_bool().unwrap_or(false),
+            crawled_after: parse_date(&params["after"]),
+            crawled_before: parse_date(&params["before"]),
+            language: params["lang"].as_str().and_then(LanguageCode::parse),
+            exact: params["verbatim"].as_bool().unwrap_or(false),
+            case_sensitive: params["case_sensitive"].as_bool().unwrap_or(false),
+            expand: params["expand"].as_bool().unwrap_or(true),
+            stopwords: params["stopwords"].as_bool().unwrap_or(true),
+            min_match: params["min_match"].as_str().and_then(MinMatch::parse),
+            min_score: params["min_score"].as_f64(),
+            mode: params["mode"].as_str().and_then(MatchMode::parse).unwrap_or_default(),
+            // Captures from before `ranker` was echoed were all ranked with TF-IDF
+            ranker: params["ranker"].as_str().and_then(Ranker::parse).unwrap_or_default(),
+            recency: params["recency"].as_str().and_then(Recency::parse).unwrap_or_default(),
+            profile: params["profile"].as_str().map(String::from),
+            semantic: params["semantic"].as_bool().unwrap_or(false),
+            collapse: params["collapse"].as_bool().unwrap_or(false),
+            // Captures from before `max_per_domain` was echoed weren't capped
+            max_per_domain: params["max_per_domain"].as_u64().map(|max| max as usize),
+            explain: params["features"]["explain"].as_bool().unwrap_or(false),
+            preferences: Preferences::from_json(&params["preferences"]),
+            url_regex: params["url_regex"].as_str().map(String::from),
+            shadow: false,
+        },
+        candidate_limit: two_tier.then_some(state.config.two_tier.candidate_limit),
+        results: record["results"]
+            .as_array()?
+            .iter()
+            .filter_map(|url| url.as_str().map(String::from))
+            .collect(),
+    })
+}
+
+/// Reads back a date echoed in `applied`, or `None` for `null`.
+fn parse_date(value: &Value) -> Option<DateTime<Utc>> {
+    value
+        .as_str()
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Shuffles deterministically for a given seed, with a Fisher-Yates shuffle driven by
+/// xorshift64.
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    // Xorshift is stuck at zero, so mix the seed into a non-zero state first
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    for i in (1..items.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let j = (state % ((i as u64) + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+/// Kendall's tau-a between the two orderings of the results they share: 1 when every pair is
+/// in the same order, -1 when every pair is reversed. `None` with fewer than two shared results.
+fn kendall_tau(captured: &[String], replayed: &[String]) -> Option<f64> {
+    let replayed_ranks: HashMap<&String, usize> = replayed
+        .iter()
+        .enumerate()
+        .map(|(rank, url)| (url, rank))
+        .collect();
+    // Replayed ranks of the shared results, in captured order
+    let ranks: Vec<usize> = captured
+        .iter()
+        .filter_map(|url| replayed_ranks.get(url).copied())
+        .collect();
+    if ranks.len() < 2 {
+        return None;
+    }
+
+    let mut concordant = 0_i64;
+    let mut discordant = 0_i64;
+    for i in 0..ranks.len() {
+        for j in i + 1..ranks.len() {
+            if ranks[i] < ranks[j] {
+                concordant += 1;
+            } else {
+                discordant += 1;
+            }
+        }
+    }
+    Some(((concordant - discordant) as f64) / ((concordant + discordant) as f64))
+}
+
+/// The share of results in either ordering that are in both, or 1 if both are empty.
+fn overlap(captured: &[String], replayed: &[String]) -> f64 {
+    let shared = captured
+        .iter()
+        .filter(|url| replayed.contains(url))
+        .count();
+    let total = captured.len() + replayed.len() - shared;
+    if total == 0 {
+        1.0
+    } else {
+        (shared as f64) / (total as f64)
+    }
+}
+
+/// Every result that changed rank between the orderings for `query`, with how far it moved.
+fn find_movers(query: &str, captured: &[String], replayed: &[String]) -> Vec<(Mover, usize)> {
+    let rank_in = |urls: &[String], url: &String| {
+        urls.iter()
+            .position(|other| other == url)
+            .map(|position| position + 1)
+    };
+    let mut urls: Vec<&String> = captured.iter().collect();
+    urls.extend(replayed.iter().filter(|url| !captured.contains(url)));
+
+    urls.into_iter()
+        .map(|url| Mover {
+            query: query.to_string(),
+            url: url.clone(),
+            captured_rank: rank_in(captured, url),
+            replayed_rank: rank_in(replayed, url),
+        })
+        .map(|mover| {
+            let distance = mover.distance(captured.len(), replayed.len());
+            (mover, distance)
+        })
+        .filter(|(_, distance)| *distance > 0)
+        .collect()
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / (values.len() as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|url| url.to_string()).collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} isn't {}", actual, expected);
+    }
+
+    #[test]
+    fn measures_rank_correlation_over_shared_results() {
+        let abcd = urls(&["a", "b", "c", "d"]);
+        assert_eq!(kendall_tau(&abcd, &abcd), Some(1.0));
+        assert_eq!(kendall_tau(&abcd, &urls(&["d", "c", "b", "a"])), Some(-1.0));
+        // One adjacent swap leaves 5 of the 6 pairs in order
+        assert_close(kendall_tau(&abcd, &urls(&["b", "a", "c", "d"])).unwrap(), 4.0 / 6.0);
+        // Only the shared results count, wherever the others are
+        assert_eq!(kendall_tau(&abcd, &urls(&["x", "a", "y", "c"])), Some(1.0));
+        assert_eq!(kendall_tau(&abcd, &urls(&["c", "x", "a"])), Some(-1.0));
+        assert_eq!(kendall_tau(&abcd, &urls(&["a", "x"])), None);
+        assert_eq!(kendall_tau(&[], &[]), None);
+    }
+
+    #[test]
+    fn measures_overlap_as_the_share_of_results_in_both() {
+        let abcd = urls(&["a", "b", "c", "d"]);
+        assert_eq!(overlap(&abcd, &urls(&["d", "c", "b", "a"])), 1.0);
+        // Two shared of six distinct
+        assert_close(overlap(&abcd, &urls(&["c", "d", "e", "f"])), 2.0 / 6.0);
+        assert_eq!(overlap(&abcd, &[]), 0.0);
+        assert_eq!(overlap(&[], &[]), 1.0);
+    }
+
+    #[test]
+    fn ranks_movers_counting_new_and_lost_results_as_past_the_end() {
+        let moved = find_movers("rust", &urls(&["a", "b", "c"]), &urls(&["b", "a", "d"]));
+        let summary: Vec<(&str, Option<usize>, Option<usize>, usize)> = moved
+            .iter()
+            .map(|(mover, distance)| {
+                (mover.url.as_str(), mover.captured_rank, mover.replayed_rank, *distance)
+            })
+            .collect();
+        // Past the end of three results is rank 4
+        assert_eq!(
+            summary,
+            [
+                ("a", Some(1), Some(2), 1),
+                ("b", Some(2), Some(1), 1),
+                ("c", Some(3), None, 1),
+                ("d", None, Some(3), 1),
+            ]
+        );
+
+        let moved = find_movers("rust", &urls(&["a", "b"]), &urls(&["x", "y", "z", "a"]));
+        let distances: Vec<(&str, usize)> = moved
+            .iter()
+            .map(|(mover, distance)| (mover.url.as_str(), *distance))
+            .collect();
+        assert_eq!(distances, [("a", 3), ("b", 3), ("x", 4), ("y", 3), ("z", 2)]);
+
+        assert!(moved.iter().all(|(mover, _)| mover.query == "rust"));
+        assert!(find_movers("rust", &urls(&["a", "b"]), &urls(&["a", "b"])).is_empty());
+    }
+
+    #[test]
+    fn shuffles_the_same_way_for_the_same_seed() {
+        let shuffled = |seed| {
+            let mut items: Vec<u32> = (0..20).collect();
+            shuffle(&mut items, seed);
+            items
+        };
+        assert_eq!(shuffled(7), shuffled(7));
+        assert_ne!(shuffled(7), shuffled(8));
+        let mut sorted = shuffled(0);
+        assert_ne!(sorted, (0..20).collect::<Vec<_>>());
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+
+        let mut one = [1];
+        shuffle(&mut one, 3);
+        assert_eq!(one, [1]);
+    }
+
+    #[test]
+    fn averages_and_parses_arguments() {
+        assert_eq!(mean(&[]), None);
+        assert_eq!(mean(&[1.0, 0.0, -0.5]), Some(0.5 / 3.0));
+        assert_eq!(parse_arg::<usize>(Some(&"12".to_string()), "--limit"), Ok(12));
+        for value in [None, Some("-1".to_string()), Some("ten".to_string())] {
+            assert_eq!(
+                parse_arg::<usize>(value.as_ref(), "--limit"),
+                Err("--limit needs a non-negative integer".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn reads_back_echoed_dates_in_utc() {
+        let date = parse_date(&serde_json::json!("2024-03-01T12:00:00+02:00"));
+        assert_eq!(date.map(|time| time.to_rfc3339()), Some("2024-03-01T10:00:00+00:00".into()));
+        assert_eq!(parse_date(&Value::Null), None);
+        assert_eq!(parse_date(&serde_json::json!("yesterday")), None);
+    }
+}
@@ -1,52 +1,413 @@
+use chrono::SecondsFormat;
 use serde_json::{ Value, json };
 use std::collections::HashMap;
 use url::Url;
 use crate::database::Webpage;
+use crate::ttl_cache::TtlCache;
 
+/// Descriptions are cut to this many characters when snippets are requested
+const SNIPPET_CHARS: usize = 200;
+
+/// The raw score that normalises to 0.5: a full TF-IDF match, before any bonus or weight
+const SCORE_MIDPOINT: f64 = 1.0;
+
+/// Maps a raw score onto 0 to 1 as `raw / (raw + SCORE_MIDPOINT)`, a fixed curve rather than a
+/// share of the best result's, so the same page scores the same in any search and a client's
+/// threshold means the same thing whatever else was found. It keeps the raw scores' order.
+pub fn normalise_score(raw: f64) -> f64 {
+    match raw {
+        raw if raw.is_nan() || raw <= 0.0 => 0.0,
+        raw if raw.is_infinite() => 1.0,
+        raw => raw / (raw + SCORE_MIDPOINT),
+    }
+}
+
+/// Which optional fields a formatted result includes
+#[derive(Debug, Clone, Copy)]
+pub struct ResultOptions {
+    pub include_links: bool,
+    pub include_keywords: bool,
+    /// Shorten descriptions to a snippet
+    pub snippets: bool,
+    pub explain: bool,
+}
+
+/// Formats a result, reusing the page's query-independent fields from `fragments` when the page
+/// was formatted recently, since popular pages turn up in many different searches.
 pub fn format_result(
-    score: &f32,
+    score: &f64,
     webpage: &Webpage,
     top_domains: &HashMap<String, usize>,
-    include_links: bool
+    fragments: &TtlCache<Value>,
+    options: ResultOptions
 ) -> Value {
-    // Extract domain and get top website rank
-    let domain = extract_domain_from_string(&webpage.url);
-    let top_website_rank = domain.as_ref().and_then(|d| top_domains.get(d).cloned());
+    let key = format!("{}|{}", webpage.id, options.snippets);
+    let mut result = match fragments.get(&key) {
+        Some(fragment) => fragment,
+        None => {
+            let fragment = page_fragment(webpage, top_domains, options.snippets);
+            fragments.insert(key, fragment.clone());
+            fragment
+        }
+    };
 
-    // Create the base result JSON
-    let mut result =
-        json!({
-        "title": webpage.title,
-        "url": webpage.url,
-        "description": webpage.description,
-        "score": score,
-        "keywords": webpage.keywords.iter().map(|(keyword, &occurrences)| {
-            json!({ "keyword": keyword.word, "occurrences": occurrences })
-        }).collect::<Vec<_>>(),
-        "top_website_rank": top_website_rank,
-    });
+    // Scores are computed in f64 but reported at f32 precision
+    result["score"] = json!(normalise_score(*score) as f32);
+    result["raw_score"] = json!(*score as f32);
+
+    if let Some(verbatim) = webpage.verbatim {
+        result["verbatim"] = json!(verbatim);
+    }
+    if let Some(phrase_match) = webpage.phrase_match {
+        result["phrase_match"] = json!(phrase_match);
+    }
+    if let Some(proximity_match) = webpage.proximity_match {
+        result["proximity_match"] = json!(proximity_match);
+    }
+    if !webpage.synonyms.is_empty() {
+        result["synonyms"] = json!(webpage.synonyms);
+    }
+    if !webpage.fuzzy.is_empty() {
+        result["fuzzy"] = json!(webpage.fuzzy);
+    }
+    if !webpage.link_expansion.is_empty() {
+        result["link_expansion"] = json!(webpage.link_expansion);
+    }
+    if let Some(hits) = webpage.hits {
+        result["hits"] = json!({ "hub": hits.hub, "authority": hits.authority });
+    }
+    if !webpage.duplicates.is_empty() {
+        result["duplicates"] = json!(
+            webpage.duplicates
+                .iter()
+                .map(|duplicate| {
+                    json!({
+                        "url": duplicate.url,
+                        "title": duplicate.title,
+                        "score": normalise_score(duplicate.score) as f32,
+                        "raw_score": duplicate.score as f32,
+                    })
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+    if let Some(more) = &webpage.more_from_site {
+        result["more_from_site"] = json!({
+            "domain": more.domain,
+            "count": more.count,
+            "results": more.results
+                .iter()
+                .map(|(score, url, title)| {
+                    json!({
+                        "url": url,
+                        "title": title,
+                        "score": normalise_score(*score) as f32,
+                        "raw_score": *score as f32,
+                    })
+                })
+                .collect::<Vec<_>>(),
+            "query": more.query,
+        });
+    }
+
+    if options.include_keywords {
+        result["keywords"] = json!(
+            webpage.keywords
+                .iter()
+                .map(|(keyword, occurrences)| {
+                    json!({ "keyword": keyword.word, "occurrences": occurrences })
+                })
+                .collect::<Vec<_>>()
+        );
+    }
     
     // Add link information if requested
-    if include_links {
+    if options.include_links {
         if let Some(links_to_count) = webpage.links_to_count {
             result["links_to_count"] = json!(links_to_count);
         }
         if let Some(links_from) = &webpage.links_from {
+            // Most linked first, then by link, rather than in the map's order, which varies
+            let mut links: Vec<(&String, &i32)> = links_from.iter().collect();
+            links.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
             result["links_from"] = json!(
-                links_from
-                    .iter()
+                links
+                    .into_iter()
                     .map(|(link, &count)| { json!({ "link": link, "occurrences": count }) })
                     .collect::<Vec<_>>()
             );
         }
     }
 
+    // Explain how the result's ranking was adjusted if requested
+    if options.explain {
+        let mut explain = webpage.explanation
+            .as_ref()
+            .map_or_else(|| json!({}), |explanation| explanation.to_json());
+        explain["spam"] = json!(
+            webpage.spam.as_ref().map(|spam| {
+                json!({ "terms": spam.terms, "score": spam.score, "penalty": spam.penalty })
+            })
+        );
+        explain["quality"] = json!(
+            webpage.quality.as_ref().map(|quality| {
+                json!({ "reasons": quality.reasons, "penalty": quality.penalty })
+            })
+        );
+        result["explain"] = explain;
+    }
+
     result
 }
 
+/// The fields of a result that depend only on the page and whether snippets are on, not on the
+/// query it was found by.
+fn page_fragment(webpage: &Webpage, top_domains: &HashMap<String, usize>, snippets: bool) -> Value {
+    // Extract domain and get top website rank
+    let domain = extract_domain_from_string(&webpage.url);
+    let top_website_rank = domain.as_ref().and_then(|d| top_domains.get(d).cloned());
+
+    json!({
+        "title": webpage.title,
+        "url": webpage.url,
+        "description": if snippets {
+            snippet(&webpage.description, SNIPPET_CHARS)
+        } else {
+            webpage.description.clone()
+        },
+        "top_website_rank": top_website_rank,
+        "last_crawled": webpage.last_crawled.map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        "language": webpage.language,
+    })
+}
+
+/// How much a cached fragment counts against the fragment cache's byte budget.
+pub fn fragment_weight(fragment: &Value) -> usize {
+    fragment.to_string().len()
+}
+
 pub fn extract_domain_from_string(url: &str) -> Option<String> {
     // Parse the URL and extract the host (domain)
     Url::parse(url)
         .ok()
         .and_then(|parsed_url| parsed_url.host_str().map(String::from))
 }
+
+/// Normalises a URL the way the index stores it: lowercase scheme and host, no default port,
+/// no fragment. Returns `None` for anything that isn't an absolute http(s) URL.
+pub fn canonicalise_url(url: &str) -> Option<String> {
+    let mut parsed_url = Url::parse(url.trim()).ok()?;
+    if parsed_url.scheme() != "http" && parsed_url.scheme() != "https" {
+        return None;
+    }
+    parsed_url.set_fragment(None);
+    Some(parsed_url.to_string())
+}
+
+/// Normalises a domain name the way URL hosts are: lowercase, punycode for international names,
+/// no trailing dot. Returns `None` for anything that isn't a valid host name.
+pub fn canonicalise_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_end_matches('.');
+    if domain.is_empty() || domain.contains(['/', ':', '?', '#', '@']) {
+        return None;
+    }
+    let parsed_url = Url::parse(&format!("http://{}/", domain)).ok()?;
+    parsed_url.host_str().map(String::from)
+}
+
+/// Shortens text to at most `max_chars` characters, ending in an ellipsis if anything was cut.
+pub fn snippet(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text.to_string(),
+    }
+}
+
+/// Returns the plausible spellings of a canonical URL that differ only in scheme, a leading
+/// `www.`, or a trailing slash.
+pub fn url_variants(canonical_url: &str) -> Vec<String> {
+    let parsed_url = match Url::parse(canonical_url) {
+        Ok(parsed_url) => parsed_url,
+        Err(_) => {
+            return vec![];
+        }
+    };
+    let host = match parsed_url.host_str() {
+        Some(host) => host.to_string(),
+        None => {
+            return vec![];
+        }
+    };
+
+    let other_host = match host.strip_prefix("www.") {
+        Some(bare_host) => bare_host.to_string(),
+        None => format!("www.{}", host),
+    };
+    let path = parsed_url.path();
+    let other_path = match path.strip_suffix('/') {
+        Some(trimmed) if !trimmed.is_empty() => trimmed.to_string(),
+        Some(_) => path.to_string(),
+        None => format!("{}/", path),
+    };
+
+    let mut variants = vec![];
+    for scheme in ["https", "http"] {
+        for host in [&host, &other_host] {
+            for path in [path, other_path.as_str()] {
+                let mut variant = parsed_url.clone();
+                if variant.set_scheme(scheme).is_err() || variant.set_host(Some(host)).is_err() {
+                    continue;
+                }
+                variant.set_path(path);
+                let variant = variant.to_string();
+                if variant != canonical_url && !variants.contains(&variant) {
+                    variants.push(variant);
+                }
+            }
+        }
+    }
+    variants
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::database::Keyword;
+
+    const OPTIONS: ResultOptions = ResultOptions {
+        include_links: true,
+        include_keywords: true,
+        snippets: false,
+        explain: true,
+    };
+
+    fn fragments(max_bytes: usize) -> TtlCache<Value> {
+        TtlCache::weighted(Duration::from_secs(60), max_bytes, fragment_weight)
+    }
+
+    fn keyword(word: &str) -> Keyword {
+        Keyword { id: 0, word: word.to_string(), documents_containing_word: 1 }
+    }
+
+    fn page(id: i32) -> Webpage {
+        Webpage {
+            id,
+            url: format!("https://en.wikipedia.org/wiki/{}", id),
+            title: format!("Article {}", id),
+            description: "A description long enough to be cut. ".repeat(10),
+            word_count: 100,
+            last_crawled: Some("2024-03-01T12:00:00Z".parse().unwrap()),
+            language: Some("en".to_string()),
+            keywords: vec![(keyword("rust"), 4)],
+            links_to_count: Some(2),
+            links_from: Some(HashMap::from([("https://a.example/".to_string(), 1)])),
+            ..Webpage::default()
+        }
+    }
+
+    /// A result formatted with nothing cached, serialised
+    fn uncached(score: f64, webpage: &Webpage, options: ResultOptions) -> String {
+        let top_domains = HashMap::from([("en.wikipedia.org".to_string(), 2)]);
+        format_result(&score, webpage, &top_domains, &fragments(0), options).to_string()
+    }
+
+    #[test]
+    fn formats_the_same_bytes_from_a_cached_fragment() {
+        let top_domains = HashMap::from([("en.wikipedia.org".to_string(), 2)]);
+        for snippets in [false, true] {
+            let options = ResultOptions { snippets, ..OPTIONS };
+            let cache = fragments(1 << 20);
+            for (score, webpage) in [(0.5, page(1)), (2.0, page(2)), (0.25, page(1))] {
+                let cached = format_result(&score, &webpage, &top_domains, &cache, options);
+                assert_eq!(cached.to_string(), uncached(score, &webpage, options), "{}", snippets);
+            }
+            // The third result reused the first's fragment
+            assert_eq!(cache.stats()["hits"], 1);
+            assert_eq!(cache.stats()["entries"], 2);
+        }
+    }
+
+    #[test]
+    fn merges_what_depends_on_the_query_in_after_the_fragment() {
+        let top_domains = HashMap::new();
+        let cache = fragments(1 << 20);
+        let found_verbatim = Webpage { verbatim: Some(true), ..page(1) };
+        format_result(&3.0, &found_verbatim, &top_domains, &cache, OPTIONS);
+        let found_otherwise = Webpage {
+            keywords: vec![(keyword("jaguar"), 1)],
+            ..page(1)
+        };
+        let result = format_result(&1.0, &found_otherwise, &top_domains, &cache, OPTIONS);
+        assert_eq!(cache.stats()["hits"], 1);
+
+        assert_eq!(result["score"], 0.5);
+        assert_eq!(result["raw_score"], 1.0);
+        assert_eq!(result.get("verbatim"), None);
+        assert_eq!(result["keywords"], json!([{ "keyword": "jaguar", "occurrences": 1 }]));
+        // Snippets are part of the fragment, so they're cached apart
+        let snippets = ResultOptions { snippets: true, ..OPTIONS };
+        let result = format_result(&1.0, &page(1), &top_domains, &cache, snippets);
+        assert!(result["description"].as_str().unwrap().ends_with('…'));
+        assert_eq!(cache.stats()["entries"], 2);
+    }
+
+    #[test]
+    fn weighs_fragments_by_their_serialised_size() {
+        let fragment = page_fragment(&page(1), &HashMap::new(), false);
+        assert_eq!(fragment_weight(&fragment), fragment.to_string().len());
+
+        // Room for two fragments of about the same size, so a third evicts the oldest
+        let cache = fragments(2 * fragment_weight(&fragment) + 10);
+        for id in 1..=3 {
+            format_result(&1.0, &page(id), &HashMap::new(), &cache, OPTIONS);
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        let keys: Vec<String> = cache.entries("").into_iter().map(|entry| entry.key).collect();
+        assert_eq!(keys, ["2|false", "3|false"]);
+        let weight = cache.stats()["weight"].as_u64().unwrap() as usize;
+        assert!(weight <= 2 * fragment_weight(&fragment) + 10, "{}", weight);
+    }
+
+    #[test]
+    fn canonicalises_urls_like_the_search_path() {
+        assert_eq!(
+            canonicalise_url(" https://Example.COM/page#section ").as_deref(),
+            Some("https://example.com/page")
+        );
+        assert_eq!(canonicalise_url("http://example.com").as_deref(), Some("http://example.com/"));
+        assert_eq!(canonicalise_url("ftp://example.com/"), None);
+        assert_eq!(canonicalise_url("example.com/page"), None);
+    }
+
+    #[test]
+    fn canonicalises_domains_like_url_hosts() {
+        assert_eq!(canonicalise_domain(" Example.COM. ").as_deref(), Some("example.com"));
+        assert_eq!(canonicalise_domain("bücher.example").as_deref(), Some("xn--bcher-kva.example"));
+        for domain in ["", ".", "example.com/path", "example.com:80", "user@example.com", "a b"] {
+            assert_eq!(canonicalise_domain(domain), None, "{:?}", domain);
+        }
+    }
+
+    #[test]
+    fn lists_other_spellings_of_a_url() {
+        assert_eq!(
+            url_variants("https://example.com/page"),
+            [
+                "https://example.com/page/",
+                "https://www.example.com/page",
+                "https://www.example.com/page/",
+                "http://example.com/page",
+                "http://example.com/page/",
+                "http://www.example.com/page",
+                "http://www.example.com/page/",
+            ]
+        );
+        // The root has only the one path, and www. comes off as well as going on
+        assert_eq!(
+            url_variants("http://www.example.com/"),
+            ["https://www.example.com/", "https://example.com/", "http://example.com/"]
+        );
+        assert_eq!(url_variants("not a url"), Vec::<String>::new());
+    }
+}
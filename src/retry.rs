@@ -0,0 +1,263 @@
+use serde_json::{ json, Value };
+use std::future::Future;
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::Instant;
+use crate::config::DatabaseRetryConfig;
+use crate::database::AppError;
+
+/// Whether database calls are let through
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Circuit {
+    /// Calls go through; `failures` in a row have failed so far
+    Closed { failures: u32 },
+    /// Calls are rejected until `until`, when one is let through to try the database again
+    Open { until: Instant },
+}
+
+/// Retries database calls that failed in a way that may pass (see `AppError::is_retryable`),
+/// with a doubling backoff, and stops calling a database that keeps failing.
+///
+/// After `DB_BREAKER_THRESHOLD` calls in a row have failed, even once retried, the circuit opens
+/// and calls are rejected as `Unavailable` without waiting on the pool. Once
+/// `DB_BREAKER_COOLDOWN_SECS` has passed, one call at a time is let through: the first to
+/// succeed closes the circuit again. Failures that retrying can't fix, such as a schema mismatch,
+/// show the database answering, so they don't count.
+pub struct DatabaseGuard {
+    config: DatabaseRetryConfig,
+    circuit: Mutex<Circuit>,
+    retries: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl DatabaseGuard {
+    pub fn new(config: &DatabaseRetryConfig) -> Self {
+        DatabaseGuard {
+            config: config.clone(),
+            circuit: Mutex::new(Circuit::Closed { failures: 0 }),
+            retries: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs `operation`, trying it again after a retryable failure up to `DB_RETRY_ATTEMPTS`
+    /// times in all, unless the circuit is open.
+    pub async fn run<T, F, Fut>(&self, mut operation: F) -> Result<T, AppError>
+        where F: FnMut() -> Fut, Fut: Future<Output = Result<T, AppError>>
+    {
+        if !self.admit(Instant::now()) {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(
+                AppError::Unavailable(
+                    "the circuit breaker is open after repeated database failures".to_string()
+                )
+            );
+        }
+
+        let mut backoff = self.config.backoff;
+        let mut attempt = 1;
+        let result = loop {
+            match operation().await {
+                Err(e) if e.is_retryable() && attempt < self.config.attempts => {
+                    self.retries.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(backoff).await;
+                    backoff = backoff.saturating_mul(2);
+                    attempt += 1;
+                }
+                result => {
+                    break result;
+                }
+            }
+        };
+        let failed = matches!(&result, Err(e) if e.is_retryable());
+        self.record(failed, Instant::now());
+        result
+    }
+
+    /// The circuit's state and the calls retried and rejected since startup, for
+    /// `/admin/metrics`.
+    pub fn stats(&self) -> Value {
+        let circuit = *self.circuit.lock().unwrap();
+        let (state, failures) = match circuit {
+            Circuit::Closed { failures } => ("closed", failures),
+            Circuit::Open { .. } => ("open", self.config.breaker_threshold),
+        };
+        json!({
+            "circuit": state,
+            "consecutive_failures": failures,
+            "retries": self.retries.load(Ordering::Relaxed),
+            "rejected": self.rejected.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Whether a call may go through at `now`. A call let through an open circuit holds it open
+    /// for another cooldown, so the rest wait on its outcome.
+    fn admit(&self, now: Instant) -> bool {
+        let mut circuit = self.circuit.lock().unwrap();
+        match *circuit {
+            Circuit::Closed { .. } => true,
+            Circuit::Open { until } if now >= until => {
+                *circuit = Circuit::Open { until: now + self.config.breaker_cooldown };
+                true
+            }
+            Circuit::Open { .. } => false,
+        }
+    }
+
+    fn record(&self, failed: bool, now: Instant) {
+        let mut circuit = self.circuit.lock().unwrap();
+        let next = match (*circuit, failed) {
+            (Circuit::Open { .. }, false) => {
+                println!("The database answered again, so the circuit breaker closed.");
+                Circuit::Closed { failures: 0 }
+            }
+            (_, false) => Circuit::Closed { failures: 0 },
+            (Circuit::Open { .. }, true) => {
+                Circuit::Open { until: now + self.config.breaker_cooldown }
+            }
+            (Circuit::Closed { failures }, true) => {
+                let failures = failures.saturating_add(1);
+                let threshold = self.config.breaker_threshold;
+                if threshold > 0 && failures >= threshold {
+                    eprintln!(
+                        "{} database calls in a row failed; the circuit breaker opened for {:?}.",
+                        failures,
+                        self.config.breaker_cooldown
+                    );
+                    Circuit::Open { until: now + self.config.breaker_cooldown }
+                } else {
+                    Circuit::Closed { failures }
+                }
+            }
+        };
+        *circuit = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    /// An `AppError` variant, to fail with
+    type Failure = fn(String) -> AppError;
+
+    fn guard(attempts: usize, breaker_threshold: u32, cooldown: Duration) -> DatabaseGuard {
+        DatabaseGuard::new(
+            &(DatabaseRetryConfig {
+                attempts,
+                backoff: Duration::from_millis(1),
+                breaker_threshold,
+                breaker_cooldown: cooldown,
+            })
+        )
+    }
+
+    /// Runs an operation through `guard` that fails with `errors` in turn, then succeeds,
+    /// returning its result and how many times it ran.
+    async fn run(guard: &DatabaseGuard, errors: &[Failure]) -> (Result<(), AppError>, usize) {
+        let calls = AtomicUsize::new(0);
+        let result = guard.run(|| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match errors.get(call) {
+                    Some(error) => Err(error(format!("failure {}", call))),
+                    None => Ok(()),
+                }
+            }
+        }).await;
+        (result, calls.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn retries_retryable_failures_up_to_the_attempts() {
+        let guard = guard(3, 0, Duration::ZERO);
+
+        let (result, calls) = run(&guard, &[AppError::Unavailable, AppError::Timeout]).await;
+        assert!(result.is_ok());
+        assert_eq!(calls, 3);
+
+        let (result, calls) = run(&guard, &[AppError::Timeout as Failure; 4]).await;
+        assert!(matches!(result, Err(AppError::Timeout(detail)) if detail == "failure 2"));
+        assert_eq!(calls, 3);
+        assert_eq!(guard.stats()["retries"], 4);
+    }
+
+    #[tokio::test]
+    async fn never_retries_what_retrying_cant_fix() {
+        let guard = guard(3, 0, Duration::ZERO);
+        for error in [AppError::SchemaMismatch, AppError::Internal] {
+            let (result, calls) = run(&guard, &[error]).await;
+            assert!(result.is_err());
+            assert_eq!(calls, 1);
+        }
+        assert_eq!(guard.stats()["retries"], 0);
+    }
+
+    #[tokio::test]
+    async fn opens_after_consecutive_failures_and_closes_once_the_database_answers() {
+        let cooldown = Duration::from_millis(100);
+        let guard = guard(1, 2, cooldown);
+
+        // A success, or a failure retrying can't fix, starts the count again
+        let _ = run(&guard, &[AppError::Unavailable]).await;
+        let _ = run(&guard, &[]).await;
+        let _ = run(&guard, &[AppError::Unavailable]).await;
+        let _ = run(&guard, &[AppError::Internal]).await;
+        assert_eq!(guard.stats()["circuit"], "closed");
+
+        let _ = run(&guard, &[AppError::Unavailable]).await;
+        let (result, _) = run(&guard, &[AppError::Unavailable]).await;
+        assert!(matches!(result, Err(AppError::Unavailable(_))));
+        assert_eq!(guard.stats()["circuit"], "open");
+
+        // Open, calls are rejected without being run
+        let (result, calls) = run(&guard, &[]).await;
+        assert!(matches!(result, Err(AppError::Unavailable(_))));
+        assert_eq!(calls, 0);
+        assert_eq!(guard.stats()["rejected"], 1);
+
+        // After the cooldown one call is tried, and its failure keeps the circuit open
+        tokio::time::sleep(cooldown).await;
+        let (_, calls) = run(&guard, &[AppError::Unavailable]).await;
+        assert_eq!(calls, 1);
+        let (_, calls) = run(&guard, &[]).await;
+        assert_eq!(calls, 0);
+
+        // Its success closes it
+        tokio::time::sleep(cooldown).await;
+        let (result, calls) = run(&guard, &[]).await;
+        assert!(result.is_ok());
+        assert_eq!(calls, 1);
+        assert_eq!(guard.stats()["circuit"], "closed");
+        let (_, calls) = run(&guard, &[]).await;
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn lets_one_call_through_an_expired_circuit_at_a_time() {
+        let cooldown = Duration::from_secs(10);
+        let guard = guard(1, 1, cooldown);
+        let now = Instant::now();
+        guard.record(true, now);
+        assert!(!guard.admit(now));
+
+        let later = now + cooldown;
+        assert!(guard.admit(later));
+        assert!(!guard.admit(later));
+        assert!(!guard.admit(later + cooldown / 2));
+        // A call that never reports back only holds the circuit for one cooldown
+        assert!(guard.admit(later + cooldown));
+    }
+
+    #[tokio::test]
+    async fn a_zero_threshold_never_opens() {
+        let guard = guard(1, 0, Duration::from_secs(10));
+        for _ in 0..10 {
+            let _ = run(&guard, &[AppError::Unavailable]).await;
+        }
+        assert_eq!(guard.stats()["circuit"], "closed");
+        assert_eq!(guard.stats()["consecutive_failures"], 10);
+    }
+}
@@ -0,0 +1,184 @@
+use axum::{
+    extract::{ rejection::JsonRejection, ConnectInfo, Extension, Path, Query },
+    http::{ HeaderMap, StatusCode },
+    response::{ IntoResponse, Json, Response },
+};
+use serde::Deserialize;
+use serde_json::{ Value, json };
+use sha2::{ Digest, Sha256 };
+use std::collections::{ BTreeMap, HashMap };
+use std::net::SocketAddr;
+use std::sync::Arc;
+use crate::auth;
+use crate::database;
+use crate::params::{ self, SearchParams };
+use crate::state::AppState;
+use crate::timing::RequestTiming;
+use crate::warnings::Warnings;
+
+/// Longest name a saved search may have
+const MAX_NAME_CHARS: usize = 64;
+
+/// Parameters that only make sense for the request that sent them, so can't be saved
+const UNSAVEABLE_PARAMS: &[&str] = &["token", "continuation"];
+
+/// Body of a `POST /saved` request
+#[derive(Debug, Deserialize)]
+pub struct SaveRequest {
+    pub name: String,
+    pub q: String,
+    /// Any other search parameters, as they'd be sent to `GET /`
+    #[serde(default)]
+    pub params: HashMap<String, Value>,
+}
+
+/// `POST /saved`: stores a named search, its query and parameters, to be re-run with
+/// `GET /saved/{name}/run`. Saving under an existing name replaces it, if the same API key saved
+/// it; the admin may replace any. Needs an API key or the admin token.
+///
+/// The parameters are checked the way a search would check them, so a search that would be
+/// rejected can't be saved.
+pub async fn save(
+    Extension(state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+    request: Result<Json<SaveRequest>, JsonRejection>
+) -> Response {
+    let is_admin = state.config.admin_token
+        .as_deref()
+        .is_some_and(|admin_token| auth::is_admin(&headers, admin_token));
+    let owner = match state.api_keys.find_in_headers(&headers) {
+        _ if is_admin => "admin".to_string(),
+        Some(api_key) => key_owner(api_key),
+        None => {
+            return error_response(StatusCode::UNAUTHORIZED, "Saving searches requires an API key");
+        }
+    };
+    let request = match request {
+        Ok(Json(request)) => request,
+        Err(rejection) => {
+            return error_response(StatusCode::BAD_REQUEST, &rejection.body_text());
+        }
+    };
+    if !valid_name(&request.name) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "name must be 1 to {} letters, digits, hyphens or underscores",
+                MAX_NAME_CHARS
+            )
+        );
+    }
+
+    let mut parameters = BTreeMap::new();
+    for (name, value) in &request.params {
+        let value = match value {
+            Value::String(value) => value.clone(),
+            Value::Number(value) => value.to_string(),
+            Value::Bool(value) => value.to_string(),
+            _ => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!("{} must be a string, number or boolean", name)
+                );
+            }
+        };
+        if name == "q" || UNSAVEABLE_PARAMS.contains(&name.as_str()) {
+            return error_response(StatusCode::BAD_REQUEST, &format!("{} can't be saved", name));
+        }
+        parameters.insert(name.clone(), value);
+    }
+    parameters.insert("q".to_string(), params::sanitise_query(&request.q));
+
+    let mut warnings = Warnings::default();
+    let query: HashMap<String, String> = parameters.clone().into_iter().collect();
+    if let Err(e) = SearchParams::from_query(&query, &state.config, &mut warnings) {
+        return e.into_response();
+    }
+
+    let stored = serde_json::to_string(&parameters).unwrap_or_default();
+    match database::save_search(&state.pool, &request.name, &stored, &owner, is_admin).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return error_response(
+                StatusCode::FORBIDDEN,
+                &format!("{:?} was saved by someone else", request.name)
+            );
+        }
+        Err(e) => {
+            eprintln!("Error saving search {:?}: {}", request.name, e);
+            return e.response("Failed to save search");
+        }
+    }
+
+    let mut response =
+        json!({
+        "name": request.name,
+        "parameters": parameters,
+        "run": format!("/saved/{}/run", request.name),
+    });
+    warnings.add_to(&mut response);
+    Json(response).into_response()
+}
+
+/// `GET /saved/{name}/run`: runs a saved search, answering like `GET /`. Parameters sent with
+/// the request override the saved ones, so `?results=10` or a `continuation` can be added.
+pub async fn run(
+    Path(name): Path<String>,
+    Query(overrides): Query<HashMap<String, String>>,
+    Extension(state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+    timing: Option<Extension<RequestTiming>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>
+) -> Response {
+    let stored = match database::fetch_saved_search(&state.pool, &name).await {
+        Ok(Some(stored)) => stored,
+        Ok(None) => {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                &format!("No search is saved as {:?}", name)
+            );
+        }
+        Err(e) => {
+            eprintln!("Error fetching saved search {:?}: {}", name, e);
+            return e.response("Failed to fetch saved search");
+        }
+    };
+    let mut parameters: HashMap<String, String> = match serde_json::from_str(&stored) {
+        Ok(parameters) => parameters,
+        Err(e) => {
+            eprintln!("Saved search {:?} is unreadable: {}", name, e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Saved search is unreadable");
+        }
+    };
+    parameters.extend(overrides);
+
+    // Resolved again on every run, so a saved search follows changes to presets and limits
+    let mut warnings = Warnings::default();
+    let search_params = match SearchParams::from_query(&parameters, &state.config, &mut warnings) {
+        Ok(search_params) => search_params,
+        Err(e) => {
+            return e.into_response();
+        }
+    };
+    let client = connect_info.map(|ConnectInfo(addr)| addr.ip());
+    crate::run_search(&state, &headers, timing, client, search_params, warnings, "/saved").await
+}
+
+/// Who saved a search with `api_key`, without storing the key itself
+fn key_owner(api_key: &str) -> String {
+    let digest = Sha256::digest(api_key.as_bytes());
+    let hex: String = digest
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("key:{}", hex)
+}
+
+fn valid_name(name: &str) -> bool {
+    (1..=MAX_NAME_CHARS).contains(&name.chars().count()) &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
@@ -0,0 +1,1880 @@
+use chrono::{ DateTime, Utc };
+use std::collections::{ BinaryHeap, HashMap };
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+use crate::config::RankingConfig;
+use crate::database::{ self, AppError, CandidateFilter, FieldOccurrences };
+use crate::diversity;
+use crate::duplicates;
+use crate::explain;
+use crate::idf;
+use crate::lemmatise::{ self, Normaliser };
+use crate::locale::LanguageCode;
+use crate::pagerank::AuthorityScores;
+use crate::preferences::{ self, Preferences };
+use crate::query_language;
+use crate::query_parser::{ self, ParsedQuery };
+use crate::ranking::{ self, MatchMode, MinMatch, Ranker, Recency };
+use crate::result_formatter;
+use crate::semantic;
+use crate::shadow;
+use crate::state::AppState;
+use crate::timing::{ Phase, RequestTiming };
+use crate::trace::PipelineTrace;
+use crate::warnings::{ self, Warnings };
+
+/// Number of results returned when the request doesn't ask for a specific number
+pub const DEFAULT_RESULTS: usize = 100;
+
+/// Allowance for rounding when a score is compared with a minimum, so a full TF-IDF match, a
+/// cosine similarity of 1, meets a `min_score` of 1
+const SCORE_TOLERANCE: f64 = 1e-9;
+
+/// Most indexed words a `prefix*` wildcard is expanded into
+const MAX_WILDCARD_WORDS: usize = 10;
+
+/// Most similarly spelt indexed words a `word~` fuzzy term also matches
+const MAX_FUZZY_WORDS: usize = 3;
+
+/// What a search returns
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub include_links: bool,
+    pub num_results: usize,
+    /// Only return webpages containing the query verbatim
+    pub verbatim_only: bool,
+    /// Most distinct keywords a search may look up
+    pub keyword_budget: usize,
+    /// Also return pages the crawler has tombstoned, which only admins may ask for
+    pub include_deleted: bool,
+    /// Only return pages last crawled at or after this time
+    pub crawled_after: Option<DateTime<Utc>>,
+    /// Only return pages last crawled before this time
+    pub crawled_before: Option<DateTime<Utc>>,
+    /// Only return pages in this language
+    pub language: Option<LanguageCode>,
+    /// Look words up as typed instead of by their lemmas
+    pub exact: bool,
+    /// Only match the query's words in the case they were typed in
+    pub case_sensitive: bool,
+    /// Also search for synonyms of the query's terms, ranking pages that need them lower
+    pub expand: bool,
+    /// Leave stopwords in the search language out of the lookup
+    pub stopwords: bool,
+    /// Return pages containing at least this many of the query's terms, instead of only those
+    /// with a full score
+    pub min_match: Option<MinMatch>,
+    /// Only return pages with at least this relevance score, whatever the ranker and mode
+    pub min_score: Option<f64>,
+    /// Whether only pages containing every term are candidates
+    pub mode: MatchMode,
+    /// How relevance is scored
+    pub ranker: Ranker,
+    /// How strongly newer pages are preferred
+    pub recency: Recency,
+    /// The ranking profile whose signal weights are used; the configured weights without one
+    pub profile: Option<String>,
+    /// Reorder the best results by the similarity of their embeddings to the query's
+    pub semantic: bool,
+    /// Collapse near-duplicates of a better ranked result into it
+    pub collapse: bool,
+    /// Most results from one site returned, the rest grouped under its best one; `None` for no
+    /// cap
+    pub max_per_domain: Option<usize>,
+    /// Break each result's score down by term and signal
+    pub explain: bool,
+    /// The domains and topics the caller favours, boosted in the ranking
+    pub preferences: Preferences,
+    /// Only return pages whose URL matches this Postgres regex, which only admins may ask for
+    pub url_regex: Option<String>,
+    /// Also rank the candidates with the shadow configuration, in the background. Not part of
+    /// the cache key, since it never changes the results.
+    pub shadow: bool,
+}
+
+/// What the search pipeline produced for a query
+#[derive(Debug, Clone)]
+pub struct SearchOutcome {
+    pub results: Vec<(f64, database::Webpage)>,
+    /// Whether the results were drawn from the complete candidate set
+    pub complete: bool,
+    /// The lemmatised keywords that were looked up, after the keyword budget
+    pub keywords: Vec<String>,
+    /// The query's operators, as parsed
+    pub parsed: ParsedQuery,
+    /// The query with a word its top results often share added, most shared first
+    pub related_searches: Vec<String>,
+    /// Scored candidates dropped for scoring below the minimum or matching too few terms
+    pub below_threshold: usize,
+    /// The score results had to meet, the server's `MIN_SCORE` if the search needed one but
+    /// didn't ask
+    pub min_score: Option<f64>,
+    /// The index generation the search ran against
+    pub index_generation: u64,
+}
+
+/// Returns the result cache key for a search with the given options.
+pub fn search_cache_key(query: &str, options: &SearchOptions) -> String {
+    format!(
+        "{}{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}",
+        search_cache_prefix(query),
+        options.include_links,
+        options.num_results,
+        options.verbatim_only,
+        options.keyword_budget,
+        options.include_deleted,
+        options.crawled_after.map(|time| time.timestamp()).unwrap_or_default(),
+        options.crawled_before.map(|time| time.timestamp()).unwrap_or_default(),
+        options.language.as_ref().map_or("", LanguageCode::as_str),
+        options.exact,
+        options.expand,
+        options.stopwords,
+        options.min_match.map(|min_match| min_match.to_string()).unwrap_or_default(),
+        options.min_score.map(|min_score| min_score.to_string()).unwrap_or_default(),
+        options.mode.name(),
+        options.ranker.name(),
+        options.recency.name(),
+        options.profile.as_deref().unwrap_or_default(),
+        options.semantic,
+        options.collapse,
+        options.max_per_domain.unwrap_or_default(),
+        options.explain,
+        options.preferences.cache_key(),
+        options.url_regex.as_deref().unwrap_or_default(),
+        // The prefix is lowercased, so a case-sensitive search keys on the query as typed too
+        if options.case_sensitive {
+            query.split_whitespace().collect::<Vec<_>>().join(" ")
+        } else {
+            String::new()
+        }
+    )
+}
+
+/// Returns the start shared by the result cache keys for `query` with any options.
+pub fn search_cache_prefix(query: &str) -> String {
+    format!("search:{}|", normalise_query(query))
+}
+
+/// Returns the result cache key for a pending two-tier refinement.
+pub fn continuation_cache_key(token: &str) -> String {
+    format!("continuation:{}", token)
+}
+
+/// Lowercases a query and collapses its whitespace, so trivially different spellings of the
+/// same query share cache and popularity entries.
+pub fn normalise_query(query: &str) -> String {
+    query.to_lowercase().split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Runs the search pipeline for `query`.
+///
+/// With a `candidate_limit`, only the best candidates by SQL pre-ranking are scored. What each
+/// stage did is counted in `trace`, and anything it had to skip is reported in `warnings`. Fails
+/// only if the candidates themselves couldn't be fetched, since there's nothing to rank then.
+pub async fn perform_search(
+    query: &str,
+    state: &Arc<AppState>,
+    options: SearchOptions,
+    candidate_limit: Option<i64>,
+    timing: &mut RequestTiming,
+    trace: &mut PipelineTrace,
+    warnings: &mut Warnings
+) -> Result<SearchOutcome, AppError> {
+    let pool = &state.pool;
+    let website_count = state.index.load().website_count;
+    let index_generation = *state.index_generation.borrow();
+
+    // Parse the query's operators, lemmatising each term unless the search is exact. A query in
+    // another language is lemmatised with that language's lemma map, if there is one.
+    let lemmatise_time = Instant::now();
+    let folding_exceptions = &state.config.folding_exceptions;
+    let language = options.language.or_else(|| {
+        state.config.detect_query_language
+            .then(|| query_language::detect(query, &state.stopwords, folding_exceptions))
+            .flatten()
+    });
+    let normaliser = Normaliser {
+        folding_exceptions,
+        lemmatise: !options.exact,
+        lemmas: language.and_then(|language| state.lemma_maps.get(&language)),
+    };
+    let mut parsed = query_parser::parse(query, normaliser);
+    parsed.language = language;
+    timing.record(Phase::Lemmatisation, lemmatise_time.elapsed());
+
+    // Nothing can match in an empty index, so don't ask the database
+    if website_count == 0 {
+        warnings.push(
+            warnings::EMPTY_INDEX,
+            "The index has no pages yet, so no results can be returned".to_string(),
+            None
+        );
+        let keywords = parsed.required_terms.clone();
+        return Ok(SearchOutcome {
+            results: vec![],
+            complete: true,
+            keywords,
+            parsed,
+            related_searches: vec![],
+            below_threshold: 0,
+            min_score: options.min_score,
+            index_generation,
+        });
+    }
+
+    let had_wildcards = expand_wildcards(state, &mut parsed, warnings).await;
+    let mut keywords = parsed.required_terms.clone();
+
+    // Exclusions, site:, url: and intitle: narrow down pages matching something, so can't be
+    // searched alone. A wildcard matching no indexed word just finds nothing.
+    if keywords.is_empty() && parsed.has_operators() && !had_wildcards {
+        warnings.push(
+            warnings::NO_SEARCH_TERMS,
+            "The query has only exclusions or site:, url: and intitle: filters; add a term to search for"
+                .to_string(),
+            Some("q")
+        );
+        return Ok(SearchOutcome {
+            results: vec![],
+            complete: true,
+            keywords,
+            parsed,
+            related_searches: vec![],
+            below_threshold: 0,
+            min_score: options.min_score,
+            index_generation,
+        });
+    }
+
+    // Stopwords in the search language say little about a page but cost a lookup each, so
+    // they're left out unless nothing else is left. A boolean expression keeps them, since it
+    // decides which pages match.
+    if options.stopwords && parsed.expression.is_none() {
+        let language = parsed.language.or(state.config.stopwords_language);
+        parsed.stopwords = state.stopwords.remove(language, &mut keywords);
+        trace.stopwords_removed = parsed.stopwords.len();
+    }
+
+    // Keep the candidate fetch bounded for queries with very many distinct terms
+    let dropped = apply_keyword_budget(state, &mut keywords, options.keyword_budget).await;
+    if !dropped.is_empty() {
+        trace.keywords_dropped = dropped.len();
+        warnings.push(
+            warnings::KEYWORDS_DROPPED,
+            format!(
+                "The query has more than {} distinct terms; these were left out: {}",
+                options.keyword_budget,
+                dropped.join(", ")
+            ),
+            Some("q")
+        );
+    }
+    trace.query_terms = keywords.len();
+
+    // Synonyms stand in for terms a page lacks. Exact searches look words up as typed, and a
+    // boolean expression decides matches itself, so neither is expanded.
+    if options.expand && !options.exact && parsed.expression.is_none() {
+        parsed.synonyms = state.synonyms
+            .expand(&keywords)
+            .into_iter()
+            .filter(|(_, synonym)| !parsed.excluded_terms.contains(synonym))
+            .collect();
+    }
+    // Fuzzy terms also match indexed words spelt like them. A boolean expression decides matches
+    // itself, so its terms aren't matched fuzzily.
+    if parsed.expression.is_none() {
+        expand_fuzzy(state, &mut parsed, &keywords, warnings).await;
+    }
+    let mut lookup = keywords.clone();
+    lookup.extend(parsed.synonyms.iter().map(|(_, synonym)| synonym.clone()));
+    lookup.extend(parsed.fuzzy.iter().map(|(_, word)| word.clone()));
+
+    // Fetch webpages from the database (without links initially)
+    let db_time = Instant::now();
+    // The query's filters and the date and language parameters are applied in the fetch, so
+    // two-tier candidates all count. So is a strict search's requirement of every term, except
+    // fuzzy ones, which may be matched through a word spelt like them.
+    let required_terms = if options.mode == MatchMode::Strict && parsed.expression.is_none() {
+        let mut terms: Vec<String> = keywords
+            .iter()
+            .filter(|term| !parsed.fuzzy_terms.iter().any(|(fuzzy, _)| fuzzy == *term))
+            .cloned()
+            .collect();
+        terms.sort();
+        terms.dedup();
+        terms
+    } else {
+        vec![]
+    };
+    let filter = CandidateFilter {
+        crawled_after: options.crawled_after,
+        crawled_before: options.crawled_before,
+        language: options.language,
+        required_terms,
+        url_regex: options.url_regex.clone(),
+        ..CandidateFilter::for_query(&parsed, state.tombstones(options.include_deleted))
+    };
+    trace.filtered_in_fetch = filter.narrows();
+    let fetched = state.database.run(|| async {
+        match candidate_limit {
+            Some(limit) =>
+                database::fetch_top_webpages(pool, &lookup, website_count, limit, &filter).await,
+            None =>
+                database
+                    ::fetch_webpages(pool, &lookup, &filter).await
+                    .map(|webpages| (webpages, true)),
+        }
+    }).await;
+    let fetched = match fetched {
+        Ok((mut webpages, complete)) => {
+            idf::fill(state, &mut webpages).await.map(|_| (webpages, complete))
+        }
+        Err(e) => Err(e),
+    };
+    let (mut webpages, candidates_complete) = match fetched {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            eprintln!("Error fetching webpages: {}", e);
+            return Err(e);
+        }
+    };
+    timing.record(Phase::InitialDatabaseQuery, db_time.elapsed());
+    trace.candidates_fetched = webpages.len();
+    trace.candidates_complete = candidates_complete;
+
+    // Drop candidates off the site: domain, without a title: or desc: term in its field, or not
+    // matching the boolean expression before scoring. The fetch already filtered by host, so
+    // the site check only catches pages whose URL disagrees with their stored host.
+    let fetched = webpages.len();
+    webpages.retain(|webpage| parsed.matches_site(&webpage.url));
+    trace.off_site = fetched - webpages.len();
+    let in_site = webpages.len();
+    webpages.retain(|webpage| parsed.matches_fields(webpage, normaliser));
+    trace.not_in_field = in_site - webpages.len();
+    apply_expression(state, &parsed, &keywords, &mut webpages, trace, warnings).await;
+    if options.case_sensitive {
+        apply_case(state, &parsed, &mut webpages, trace, warnings).await;
+    }
+
+    // Score relevance and rank webpages
+    let tfidf_time = Instant::now();
+    count_field_occurrences(state, &mut webpages, normaliser);
+    // Synonyms are tried before similarly spelt words, so a term both could stand in for counts
+    // as matched through a synonym
+    let stand_ins: Vec<(String, String)> = parsed.synonyms
+        .iter()
+        .chain(&parsed.fuzzy)
+        .cloned()
+        .collect();
+    let mut ranked_webpages = score_relevance(
+        state,
+        options.ranker,
+        &keywords,
+        &stand_ins,
+        &webpages
+    );
+
+    // Only webpages scoring at least `MIN_SCORE`, a full score by default, or with `min_match` of
+    // the terms, are returned, unless a boolean expression or a strict search's candidate fetch
+    // already decided which match. BM25 has no full score, so it returns every webpage without
+    // `min_match`. A `min_score` the search asked for applies whatever else does.
+    let scored = ranked_webpages.len();
+    let mut min_score = options.min_score;
+    if parsed.expression.is_none() && options.mode == MatchMode::Loose {
+        match options.min_match {
+            Some(min_match) => {
+                let mut terms = keywords.clone();
+                terms.sort();
+                terms.dedup();
+                let required = min_match.required(terms.len());
+                ranked_webpages.retain(|(_, webpage)| webpage.matched_terms >= required);
+            }
+            None if options.ranker == Ranker::TfIdf => {
+                min_score = min_score.or(Some(state.config.min_score));
+            }
+            None => {}
+        }
+    }
+    if let Some(min_score) = min_score {
+        ranked_webpages.retain(|(score, _)| *score >= min_score - SCORE_TOLERANCE);
+    }
+    let below_threshold = scored - ranked_webpages.len();
+    trace.below_threshold = below_threshold;
+
+    // Pages that only match through synonyms or similar spellings rank below those with the
+    // query's own words
+    for (score, webpage) in ranked_webpages.iter_mut() {
+        let (fuzzy, synonyms) = webpage.synonyms
+            .drain(..)
+            .partition(|word| !parsed.synonyms.iter().any(|(_, synonym)| synonym == word));
+        webpage.synonyms = synonyms;
+        webpage.fuzzy = fuzzy;
+        if !webpage.synonyms.is_empty() {
+            *score *= ranking::SYNONYM_WEIGHT;
+            trace.synonym_matches += 1;
+        }
+        if !webpage.fuzzy.is_empty() {
+            *score *= ranking::FUZZY_WEIGHT;
+            trace.fuzzy_matches += 1;
+        }
+    }
+
+    // Pages with every term of a multi-word query outrank those stuffed with only some of them
+    let weight = state.config.coverage_weight;
+    if let Some(distinct_terms) = distinct_term_count(&keywords).filter(|_| weight > 0.0) {
+        for (score, webpage) in ranked_webpages.iter_mut() {
+            *score += ranking::coverage_bonus(webpage.matched_terms, distinct_terms, weight);
+        }
+    }
+
+    // A query matching very few pages also finds pages through the words of pages linking to
+    // its matches, ranked below them. A boolean expression decides matches itself, strict,
+    // exact and case-sensitive searches want the query's own words, and a search asking for a
+    // `min_score` wants only pages scoring it, so none of those are expanded.
+    let link_expansion = &state.config.link_expansion;
+    if
+        options.expand &&
+        options.mode == MatchMode::Loose &&
+        options.min_score.is_none() &&
+        !options.exact &&
+        !options.case_sensitive &&
+        parsed.expression.is_none() &&
+        !ranked_webpages.is_empty() &&
+        ranked_webpages.len() < link_expansion.below
+    {
+        trace.link_expansion_matches = expand_through_links(
+            state,
+            &mut parsed,
+            &filter,
+            normaliser,
+            &mut ranked_webpages,
+            &options,
+            warnings
+        ).await;
+    }
+
+    // Flag webpages containing the query verbatim, dropping the rest if only those are wanted
+    mark_verbatim(state, &keywords, &mut ranked_webpages, normaliser, warnings).await;
+    if options.verbatim_only {
+        let matching = ranked_webpages.len();
+        ranked_webpages.retain(|(_, webpage)| webpage.verbatim == Some(true));
+        trace.not_verbatim = matching - ranked_webpages.len();
+    }
+
+    // Pages containing the quoted phrases as written outrank those with their words scattered
+    trace.phrase_matches = mark_phrases(&parsed.phrases, &mut ranked_webpages, normaliser);
+
+    // Pages with the query in their title, URL or description gain any configured field weights
+    let field_weights = &state.config.field_weights;
+    if field_weights.any() {
+        for (score, webpage) in ranked_webpages.iter_mut() {
+            *score += ranking::field_bonus(&keywords, webpage, field_weights, normaliser);
+        }
+    }
+
+    // Newer pages gain up to the recency weight, so news-like queries can ask to prefer them
+    let ranking = state.config.ranking(options.profile.as_deref());
+    let recency = &state.config.recency;
+    let recency_weight = recency.weight(options.recency, &ranking);
+    let now = Utc::now();
+    if recency_weight > 0.0 {
+        for (score, webpage) in ranked_webpages.iter_mut() {
+            let freshness =
+                ranking::freshness(webpage.last_crawled, now, recency.half_life, recency.decay);
+            *score += recency_weight * freshness;
+        }
+    }
+
+    // Pages users click through to more often than others when shown gain up to the click weight
+    if state.config.clicks.enabled && ranking.clicks > 0.0 {
+        let click_rates = state.click_rates.load();
+        for (score, webpage) in ranked_webpages.iter_mut() {
+            *score += ranking.clicks * click_rates.rate(webpage.id);
+        }
+    }
+
+    // Pages on the caller's preferred domains, or about their preferred topics, gain the
+    // preference weights, so clients can personalise results without a server-side account
+    if !options.preferences.is_empty() {
+        let topics = options.preferences.normalised_topics(normaliser);
+        for (score, webpage) in ranked_webpages.iter_mut() {
+            let bonus = preferences::bonus(
+                &options.preferences,
+                &topics,
+                webpage,
+                &state.config.preferences,
+                normaliser
+            );
+            if bonus > 0.0 {
+                *score += bonus;
+                trace.preference_boosted += 1;
+            }
+        }
+    }
+
+    // Pages other pages link to with the query's words outrank those only describing themselves
+    // with them
+    trace.anchor_text_boosted = boost_anchor_text(
+        state,
+        &keywords,
+        &mut ranked_webpages,
+        normaliser,
+        warnings
+    ).await;
+
+    // Pages with each NEAR/n pair close together outrank those with the terms far apart
+    trace.proximity_matches = mark_proximity(
+        state,
+        &parsed,
+        &mut ranked_webpages,
+        normaliser,
+        warnings
+    ).await;
+
+    // Pages with a multi-word query's terms close together outrank those with them far apart
+    trace.term_closeness_boosted = boost_term_closeness(
+        state,
+        &keywords,
+        &mut ranked_webpages,
+        normaliser,
+        warnings
+    ).await;
+
+    // Pages with a multi-word query's words in order, as a phrase, outrank those with them
+    // jumbled, even if it wasn't quoted
+    let verbatim_multiplier = state.config.proximity.verbatim_multiplier;
+    if keywords.len() > 1 && verbatim_multiplier != 1.0 {
+        for (score, webpage) in ranked_webpages.iter_mut() {
+            if webpage.verbatim == Some(true) || webpage.verbatim_in_text == Some(true) {
+                *score *= verbatim_multiplier;
+                trace.verbatim_boosted += 1;
+            }
+        }
+    }
+
+    // Thin pages, pages stuffed with a query term and pages with a near-empty description rank
+    // below fuller ones
+    for (score, webpage) in ranked_webpages.iter_mut() {
+        webpage.quality = ranking::assess_quality(webpage, &state.config.quality);
+        if let Some(quality) = &webpage.quality {
+            *score *= quality.penalty;
+            trace.quality_penalised += 1;
+        }
+    }
+
+    // Downrank (or in strict mode, drop) webpages that lean heavily on spam terms
+    apply_spam_penalties(state, &mut ranked_webpages, trace, warnings).await;
+
+    // The shadow ranking gets the same candidates, before they're sorted and truncated
+    let shadow_candidates = options.shadow.then(|| ranked_webpages.clone());
+
+    // Sort by score blended with link authority and any spam penalty first, then by website rank.
+    // Only the results returned need ordering, unless a later step looks further down: semantic
+    // reranking reorders its top K, and collapsing and per-site caps fill the page from below.
+    let capped = options.max_per_domain.is_some() && parsed.site_filter.is_none();
+    let limit = if options.collapse || capped {
+        usize::MAX
+    } else if options.semantic {
+        options.num_results.max(state.config.semantic.top_k)
+    } else {
+        options.num_results
+    };
+    let unranked = sort_by_authority(state, &ranking, &mut ranked_webpages, limit, trace);
+    timing.record(Phase::TfIdfCalculation, tfidf_time.elapsed());
+
+    // An embedding model reorders the best results by meaning, if asked to
+    if options.semantic {
+        let semantic_time = Instant::now();
+        match semantic::rerank(state, query, &mut ranked_webpages).await {
+            Ok(reranked) => {
+                trace.semantic_reranked = reranked;
+            }
+            Err(e) => {
+                eprintln!("Error reranking semantically: {}", e);
+                warnings.push(
+                    warnings::SEMANTIC_UNAVAILABLE,
+                    format!("Results couldn't be reranked semantically: {}", e),
+                    Some("semantic")
+                );
+            }
+        }
+        timing.record(Phase::SemanticReranking, semantic_time.elapsed());
+    }
+
+    // Near-duplicates are folded into the best ranked of them, if asked to. Twice as many as are
+    // returned are scanned, so there are still enough left after collapsing.
+    if options.collapse {
+        let scanned = options.num_results.saturating_mul(2);
+        match duplicates::collapse(state, &mut ranked_webpages, scanned).await {
+            Ok(collapsed) => {
+                trace.duplicates_collapsed = collapsed;
+            }
+            Err(e) => {
+                eprintln!("Error collapsing near-duplicates: {}", e);
+                warnings.push(
+                    warnings::DUPLICATES_UNAVAILABLE,
+                    "Near-duplicate results couldn't be collapsed".to_string(),
+                    Some("collapse")
+                );
+            }
+        }
+    }
+
+    // No one site may fill the page: its results past `max_per_domain` are grouped under its best
+    // one. A `site:` search is all one site, so it isn't capped.
+    if let Some(max_per_domain) = options.max_per_domain.filter(|_| parsed.site_filter.is_none()) {
+        trace.domain_capped = diversity::cap_per_domain(query, &mut ranked_webpages, max_per_domain);
+    }
+
+    // Limit the number of results
+    trace.truncated = unranked + ranked_webpages.len().saturating_sub(options.num_results);
+    ranked_webpages.truncate(options.num_results);
+    trace.returned = ranked_webpages.len();
+
+    // Each returned result's score is broken down, if asked to, to debug its ranking by
+    if options.explain {
+        let context = explain::Context {
+            ranker: options.ranker,
+            keywords: &keywords,
+            stand_ins: &stand_ins,
+            link_words: &parsed.link_expansion,
+            ranking: &ranking,
+            recency_weight,
+            now,
+            normaliser,
+            preferences: &options.preferences,
+            blended_order: !options.semantic,
+        };
+        explain::explain(state, &context, &mut ranked_webpages);
+    }
+
+    if let Some(candidates) = shadow_candidates {
+        let served = ranked_webpages
+            .iter()
+            .map(|(_, webpage)| webpage.id)
+            .collect();
+        let primary = RankingParams::new(&ranking);
+        shadow::compare_in_background(state, query, primary, candidates, served);
+    }
+
+    // Fetch links for top results if requested
+    if options.include_links {
+        let link_time = Instant::now();
+        attach_links(state, &mut ranked_webpages, warnings).await;
+        timing.record(Phase::LinkFetching, link_time.elapsed());
+    }
+
+    let related_searches = related_searches(state, query, &parsed, &ranked_webpages, warnings).await;
+
+    Ok(SearchOutcome {
+        results: ranked_webpages,
+        complete: candidates_complete,
+        keywords,
+        parsed,
+        related_searches,
+        below_threshold,
+        min_score,
+        index_generation,
+    })
+}
+
+/// Suggests the query with a word added, for up to `RELATED_SEARCHES` of the words most of its
+/// top `RELATED_FROM_RESULTS` results share, so "rust" may lead to "rust async". Words the query
+/// already searches for or excludes, stopwords, and words with digits aren't suggested.
+async fn related_searches(
+    state: &AppState,
+    query: &str,
+    parsed: &ParsedQuery,
+    ranked_webpages: &[(f64, database::Webpage)],
+    warnings: &mut Warnings
+) -> Vec<String> {
+    let config = &state.config.related;
+    if config.count == 0 || ranked_webpages.is_empty() {
+        return vec![];
+    }
+
+    let ids: Vec<i32> = ranked_webpages
+        .iter()
+        .take(config.from_results)
+        .map(|(_, webpage)| webpage.id)
+        .collect();
+    let mut excluded: Vec<String> = parsed.required_terms
+        .iter()
+        .chain(&parsed.excluded_terms)
+        .chain(&parsed.stopwords)
+        .cloned()
+        .collect();
+    excluded.extend(parsed.synonyms.iter().map(|(_, synonym)| synonym.clone()));
+    // Twice as many as are needed, since stopwords are only filtered out afterwards
+    let limit = (config.count as i64) * 2;
+    let words = match database::fetch_cooccurring_words(&state.pool, &ids, &excluded, limit).await {
+        Ok(words) => words,
+        Err(e) => {
+            eprintln!("Error fetching related words: {}", e);
+            warnings.push(
+                warnings::RELATED_UNAVAILABLE,
+                "The words related searches are made of couldn't be looked up".to_string(),
+                None
+            );
+            return vec![];
+        }
+    };
+
+    let language = parsed.language.or(state.config.stopwords_language);
+    let query = normalise_query(query);
+    words
+        .into_iter()
+        .filter(|word| {
+            word.chars().all(char::is_alphabetic) && !state.stopwords.contains(language, word)
+        })
+        .take(config.count)
+        .map(|word| format!("{} {}", query, word))
+        .collect()
+}
+
+/// Counts how often each page's keywords occur in its title and description, normalised like
+/// the query, for field boosts. Nothing is counted with every boost off.
+fn count_field_occurrences(
+    state: &AppState,
+    webpages: &mut [database::Webpage],
+    normaliser: Normaliser<'_>
+) {
+    if !state.config.field_boosts.any() {
+        return;
+    }
+    for webpage in webpages.iter_mut() {
+        let title = normaliser.terms(&webpage.title);
+        let description = normaliser.terms(&webpage.description);
+        let count = |words: &[String], keyword: &str| {
+            words
+                .iter()
+                .filter(|word| *word == keyword)
+                .count() as u32
+        };
+        webpage.field_occurrences = webpage.keywords
+            .iter()
+            .map(|(keyword, _)| FieldOccurrences {
+                title: count(&title, &keyword.word),
+                description: count(&description, &keyword.word),
+            })
+            .collect();
+    }
+}
+
+/// Scores candidates' relevance to `keywords` with `ranker`'s scorer, in the order given.
+/// `stand_ins` pairs terms with words that may stand in for them, as for
+/// `ranking::ScoringQuery`.
+fn score_relevance(
+    state: &AppState,
+    ranker: Ranker,
+    keywords: &[String],
+    stand_ins: &[(String, String)],
+    webpages: &[database::Webpage]
+) -> Vec<(f64, database::Webpage)> {
+    let index = state.index.load();
+    let scorer = ranker.scorer(index.average_word_count, state.config.bm25);
+    let query = ranking::ScoringQuery::new(
+        index.website_count,
+        state.config.field_boosts,
+        keywords,
+        stand_ins
+    );
+    ranking::score_candidates(scorer.as_ref(), &query, webpages)
+}
+
+/// Adds up to `options.num_results` pages found through the `LINK_EXPANSION_TERMS` words most of the
+/// pages linking to the ranked ones share, recording the words in `parsed.link_expansion`.
+/// Returns how many pages were added.
+///
+/// The pages are scored against those words alone and weighted by `LINK_EXPANSION_WEIGHT`, so
+/// they rank below the query's own matches, and each lists the words it was found through. The
+/// query's filters apply to them as well. Words the query already searches for or excludes,
+/// stopwords, and words with digits aren't used.
+async fn expand_through_links(
+    state: &AppState,
+    parsed: &mut ParsedQuery,
+    filter: &CandidateFilter,
+    normaliser: Normaliser<'_>,
+    ranked_webpages: &mut Vec<(f64, database::Webpage)>,
+    options: &SearchOptions,
+    warnings: &mut Warnings
+) -> usize {
+    let num_results = options.num_results;
+    let count = state.config.link_expansion.terms;
+    let targets: Vec<String> = ranked_webpages
+        .iter()
+        .map(|(_, webpage)| webpage.url.clone())
+        .collect();
+    let mut excluded: Vec<String> = parsed.required_terms
+        .iter()
+        .chain(&parsed.excluded_terms)
+        .chain(&parsed.stopwords)
+        .cloned()
+        .collect();
+    excluded.extend(parsed.synonyms.iter().map(|(_, synonym)| synonym.clone()));
+    let tombstones = filter.tombstones;
+    // Twice as many as are needed, since stopwords are only filtered out afterwards
+    let limit = (count as i64) * 2;
+    let unavailable = |warnings: &mut Warnings, e: database::AppError| {
+        eprintln!("Error expanding the query through links: {}", e);
+        warnings.push(
+            warnings::LINK_EXPANSION_UNAVAILABLE,
+            "Pages linking to the results couldn't be looked up, so none were added through them"
+                .to_string(),
+            None
+        );
+        0
+    };
+    let words = match
+        database::fetch_linking_words(&state.pool, &targets, &excluded, limit, tombstones).await
+    {
+        Ok(words) => words,
+        Err(e) => {
+            return unavailable(warnings, e);
+        }
+    };
+    let language = parsed.language.or(state.config.stopwords_language);
+    let words: Vec<String> = words
+        .into_iter()
+        .filter(|word| {
+            word.chars().all(char::is_alphabetic) && !state.stopwords.contains(language, word)
+        })
+        .take(count)
+        .collect();
+    let fetched = database::fetch_top_webpages(
+        &state.pool,
+        &words,
+        state.index.load().website_count,
+        num_results as i64,
+        filter
+    ).await;
+    let mut webpages = match fetched {
+        Ok((webpages, _)) => webpages,
+        Err(e) => {
+            return unavailable(warnings, e);
+        }
+    };
+    if let Err(e) = idf::fill(state, &mut webpages).await {
+        return unavailable(warnings, e);
+    }
+
+    webpages.retain(|webpage| {
+        ranked_webpages.iter().all(|(_, ranked)| ranked.id != webpage.id) &&
+            parsed.matches_site(&webpage.url) &&
+            parsed.matches_fields(webpage, normaliser)
+    });
+    count_field_occurrences(state, &mut webpages, normaliser);
+    // At most `num_results` were fetched, and they're ranked with the rest
+    let mut expanded = score_relevance(state, options.ranker, &words, &[], &webpages);
+    for (score, webpage) in expanded.iter_mut() {
+        *score *= ranking::LINK_EXPANSION_WEIGHT;
+        webpage.link_expansion = webpage.keywords
+            .iter()
+            .map(|(keyword, _)| keyword.word.clone())
+            .filter(|word| words.contains(word))
+            .collect();
+    }
+    let added = expanded.len();
+    ranked_webpages.extend(expanded);
+    parsed.link_expansion = words;
+    added
+}
+
+/// Pairs each fuzzy term still looked up with up to `MAX_FUZZY_WORDS` vocabulary words spelt
+/// like it, as `parsed.fuzzy`: those near the word as typed, then those near its term. With
+/// `FUZZY_FALLBACK`, terms no page contains are made fuzzy first.
+async fn expand_fuzzy(
+    state: &AppState,
+    parsed: &mut ParsedQuery,
+    keywords: &[String],
+    warnings: &mut Warnings
+) {
+    if state.config.fuzzy_fallback && !keywords.is_empty() {
+        match idf::lookup(state, keywords).await {
+            Ok(frequencies) => {
+                for term in keywords {
+                    let unindexed = frequencies.get(term).is_none_or(|&documents| documents == 0);
+                    let fuzzy = parsed.fuzzy_terms.iter().any(|(fuzzy, _)| fuzzy == term);
+                    if unindexed && !fuzzy {
+                        parsed.fuzzy_terms.push((term.clone(), term.clone()));
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Error looking up document frequencies: {}", e);
+                warnings.push(
+                    warnings::FUZZY_UNAVAILABLE,
+                    "Which terms are indexed couldn't be looked up, so none was matched fuzzily"
+                        .to_string(),
+                    Some("q")
+                );
+            }
+        }
+    }
+
+    let vocabulary = &state.vocabulary;
+    let mut fuzzy = vec![];
+    for (term, typed) in &parsed.fuzzy_terms {
+        if !keywords.contains(term) {
+            continue;
+        }
+        let mut words: Vec<&str> = vocabulary.near(typed, MAX_FUZZY_WORDS);
+        for word in vocabulary.near(term, MAX_FUZZY_WORDS) {
+            if !words.contains(&word) {
+                words.push(word);
+            }
+        }
+        words.retain(|word| {
+            word != term && !parsed.excluded_terms.iter().any(|excluded| excluded == word)
+        });
+        words.truncate(MAX_FUZZY_WORDS);
+        fuzzy.extend(words.into_iter().map(|word| (term.clone(), word.to_string())));
+    }
+    parsed.fuzzy = fuzzy;
+}
+
+/// Replaces each of the query's `prefix*` wildcards with the most common indexed words
+/// starting with the prefix, at most `MAX_WILDCARD_WORDS` of them. Returns whether the query
+/// had any wildcards.
+async fn expand_wildcards(
+    state: &AppState,
+    parsed: &mut ParsedQuery,
+    warnings: &mut Warnings
+) -> bool {
+    let prefixes = parsed.wildcard_prefixes();
+    if prefixes.is_empty() {
+        return false;
+    }
+
+    let mut expansions = HashMap::new();
+    for prefix in prefixes {
+        if expansions.contains_key(&prefix) {
+            continue;
+        }
+        // One more than is kept, to tell whether any were left out
+        let fetched = database
+            ::fetch_words_with_prefix(&state.pool, &prefix, (MAX_WILDCARD_WORDS as i64) + 1).await
+            .map_err(|e| e.to_string());
+        let mut words = match fetched {
+            Ok(words) => words,
+            Err(e) => {
+                eprintln!("Error expanding wildcard {}*: {}", prefix, e);
+                warnings.push(
+                    warnings::WILDCARD_UNAVAILABLE,
+                    format!("The words matching {}* couldn't be looked up", prefix),
+                    Some("q")
+                );
+                vec![]
+            }
+        };
+        if words.len() > MAX_WILDCARD_WORDS {
+            words.truncate(MAX_WILDCARD_WORDS);
+            warnings.push(
+                warnings::WILDCARD_LIMITED,
+                format!(
+                    "{}* matches more than {} words; only the {} most common were searched for",
+                    prefix,
+                    MAX_WILDCARD_WORDS,
+                    MAX_WILDCARD_WORDS
+                ),
+                Some("q")
+            );
+        }
+        expansions.insert(prefix, words);
+    }
+    parsed.expand_wildcards(&expansions);
+    true
+}
+
+/// Drops webpages not matching the query's boolean expression, if it has one. Whether a page contains a term outside
+/// the fetched `keywords`, such as one inside a `NOT` or left out by the keyword budget, is
+/// looked up; if it can't be, such terms count as absent and a warning says so.
+async fn apply_expression(
+    state: &AppState,
+    parsed: &ParsedQuery,
+    keywords: &[String],
+    webpages: &mut Vec<database::Webpage>,
+    trace: &mut PipelineTrace,
+    warnings: &mut Warnings
+) {
+    let expression = match &parsed.expression {
+        Some(expression) if !webpages.is_empty() => expression,
+        _ => {
+            return;
+        }
+    };
+
+    let mut lookup: Vec<String> = parsed.required_terms
+        .iter()
+        .chain(&parsed.excluded_terms)
+        .filter(|term| !keywords.contains(term))
+        .cloned()
+        .collect();
+    lookup.sort();
+    lookup.dedup();
+    let mut occurrences = HashMap::new();
+    if !lookup.is_empty() {
+        let webpage_ids: Vec<i32> = webpages
+            .iter()
+            .map(|webpage| webpage.id)
+            .collect();
+        let fetched = database
+            ::fetch_keyword_occurrences(&state.pool, &webpage_ids, &lookup).await
+            .map_err(|e| e.to_string());
+        match fetched {
+            Ok(fetched) => {
+                occurrences = fetched;
+            }
+            Err(e) => {
+                eprintln!("Error fetching boolean term occurrences: {}", e);
+                warnings.push(
+                    warnings::EXCLUSIONS_UNAVAILABLE,
+                    "Results couldn't be checked for the query's NOT terms".to_string(),
+                    Some("q")
+                );
+            }
+        }
+    }
+
+    let candidates = webpages.len();
+    webpages.retain(|webpage| {
+        let looked_up = occurrences.get(&webpage.id);
+        expression.matches(
+            &(|term: &str| {
+                webpage.keywords.iter().any(|(keyword, _)| keyword.word == term) ||
+                    looked_up.is_some_and(|words| words.contains_key(term))
+            })
+        )
+    });
+    trace.not_matching = candidates - webpages.len();
+}
+
+/// Cuts `keywords` down to at most `budget` distinct words, keeping the most selective ones.
+/// Returns the words that were dropped.
+async fn apply_keyword_budget(
+    state: &AppState,
+    keywords: &mut Vec<String>,
+    budget: usize
+) -> Vec<String> {
+    let mut distinct: Vec<String> = keywords.clone();
+    distinct.sort();
+    distinct.dedup();
+    if distinct.len() <= budget {
+        return vec![];
+    }
+
+    // Without frequencies, the budget is still kept, just without regard to selectivity
+    let frequencies = idf::lookup(state, &distinct).await.unwrap_or_default();
+    let kept = select_keywords(&distinct, &frequencies, budget);
+    let dropped = distinct
+        .into_iter()
+        .filter(|word| !kept.contains(word))
+        .collect();
+    keywords.retain(|word| kept.contains(word));
+    dropped
+}
+
+/// Picks at most `budget` of the distinct `words`, rarest first by how many documents contain
+/// them. Words not in the index can't match anything, so they're picked last; ties keep the
+/// order of `words`.
+fn select_keywords(
+    words: &[String],
+    frequencies: &HashMap<String, i64>,
+    budget: usize
+) -> Vec<String> {
+    let mut ranked: Vec<&String> = words.iter().collect();
+    ranked.sort_by_key(|word| frequencies.get(*word).copied().unwrap_or(i64::MAX));
+    ranked.into_iter().take(budget).cloned().collect()
+}
+
+/// Flags each webpage containing the query's words consecutively and in order: in its text,
+/// where the crawler recorded word positions, or else in its title or description. A
+/// single-word query is verbatim wherever it matches.
+async fn mark_verbatim(
+    state: &AppState,
+    keywords: &[String],
+    ranked_webpages: &mut [(f64, database::Webpage)],
+    normaliser: Normaliser<'_>,
+    warnings: &mut Warnings
+) {
+    for (_score, webpage) in ranked_webpages.iter_mut() {
+        let verbatim =
+            keywords.len() <= 1 ||
+            [&webpage.title, &webpage.description].into_iter().any(|text| {
+                let words = normaliser.terms(text);
+                ranking::contains_consecutively(&words, keywords)
+            });
+        webpage.verbatim = Some(verbatim);
+    }
+
+    // Only the pages without the query in their title or description need their text checked
+    let unmatched: Vec<i32> = ranked_webpages
+        .iter()
+        .filter(|(_, webpage)| webpage.verbatim == Some(false))
+        .map(|(_, webpage)| webpage.id)
+        .collect();
+    if !state.term_positions || unmatched.is_empty() {
+        return;
+    }
+    let mut words = keywords.to_vec();
+    words.sort();
+    words.dedup();
+    match database::fetch_term_positions(&state.pool, &unmatched, &words).await {
+        Ok(positions) => {
+            for (_score, webpage) in ranked_webpages.iter_mut() {
+                if let Some(positions) = positions.get(&webpage.id) {
+                    let in_text = ranking::contains_at_positions(positions, keywords);
+                    webpage.verbatim_in_text = Some(in_text);
+                    webpage.verbatim = Some(webpage.verbatim == Some(true) || in_text);
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("Error fetching term positions: {}", e);
+            warnings.push(
+                warnings::PROXIMITY_UNAVAILABLE,
+                "Word positions couldn't be looked up, so verbatim matches only checked titles and descriptions"
+                    .to_string(),
+                Some("q")
+            );
+        }
+    }
+}
+
+/// Flags each webpage whose title or description contains every one of the query's `phrases`
+/// in order, raising its score by `ranking::PHRASE_MATCH_BONUS`. As with verbatim matches, page
+/// bodies can't be checked, and a one-word phrase matches wherever its word does. Returns how
+/// many webpages matched.
+fn mark_phrases(
+    phrases: &[Vec<String>],
+    ranked_webpages: &mut [(f64, database::Webpage)],
+    normaliser: Normaliser
+) -> usize {
+    if phrases.is_empty() {
+        return 0;
+    }
+
+    let mut matches = 0;
+    for (score, webpage) in ranked_webpages.iter_mut() {
+        let texts: Vec<Vec<String>> = [&webpage.title, &webpage.description]
+            .into_iter()
+            .map(|text| normaliser.terms(text))
+            .collect();
+        let phrase_match = phrases.iter().all(|phrase| {
+            phrase.len() <= 1 ||
+                texts.iter().any(|words| ranking::contains_consecutively(words, phrase))
+        });
+        webpage.phrase_match = Some(phrase_match);
+        if phrase_match {
+            *score += ranking::PHRASE_MATCH_BONUS;
+            matches += 1;
+        }
+    }
+    matches
+}
+
+/// Drops webpages that don't contain each of the query's words in the case it was typed in.
+/// Titles and descriptions are always checked; page text is too when the crawler records each
+/// word's original forms.
+async fn apply_case(
+    state: &AppState,
+    parsed: &ParsedQuery,
+    webpages: &mut Vec<database::Webpage>,
+    trace: &mut PipelineTrace,
+    warnings: &mut Warnings
+) {
+    if parsed.cased_terms.is_empty() || webpages.is_empty() {
+        return;
+    }
+
+    let mut body_forms = HashMap::new();
+    if state.original_forms {
+        let ids: Vec<i32> = webpages
+            .iter()
+            .map(|webpage| webpage.id)
+            .collect();
+        let mut words: Vec<String> = parsed.cased_terms
+            .iter()
+            .map(|(term, _)| term.clone())
+            .collect();
+        words.sort();
+        words.dedup();
+        match database::fetch_original_forms(&state.pool, &ids, &words).await {
+            Ok(forms) => {
+                body_forms = forms;
+            }
+            Err(e) => {
+                eprintln!("Error fetching original forms: {}", e);
+                warnings.push(
+                    warnings::CASE_UNAVAILABLE,
+                    "Case could only be checked in titles and descriptions"
+                        .to_string(),
+                    Some("case_sensitive")
+                );
+            }
+        }
+    }
+
+    let folding_exceptions = &state.config.folding_exceptions;
+    let fetched = webpages.len();
+    webpages.retain(|webpage| {
+        let mut forms = lemmatise::split_cased_words(&webpage.title, folding_exceptions);
+        forms.extend(lemmatise::split_cased_words(&webpage.description, folding_exceptions));
+        forms.extend(body_forms.remove(&webpage.id).into_iter().flatten());
+        parsed.cased_terms.iter().all(|(_, cased)| forms.contains(cased))
+    });
+    trace.wrong_case = fetched - webpages.len();
+}
+
+/// Flags each webpage where every `NEAR/n` pair of the query occurs within its distance, raising
+/// its score by `ranking::PROXIMITY_BONUS`. Titles and descriptions are always checked; page text
+/// is too when the crawler records word positions. Returns how many webpages matched.
+async fn mark_proximity(
+    state: &AppState,
+    parsed: &ParsedQuery,
+    ranked_webpages: &mut [(f64, database::Webpage)],
+    normaliser: Normaliser<'_>,
+    warnings: &mut Warnings
+) -> usize {
+    if parsed.proximity.is_empty() || ranked_webpages.is_empty() {
+        return 0;
+    }
+
+    let mut words: Vec<String> = parsed.proximity
+        .iter()
+        .flat_map(|near| [near.left.clone(), near.right.clone()])
+        .collect();
+    words.sort();
+    words.dedup();
+
+    let mut body_positions = HashMap::new();
+    if state.term_positions {
+        let ids: Vec<i32> = ranked_webpages
+            .iter()
+            .map(|(_, webpage)| webpage.id)
+            .collect();
+        match database::fetch_term_positions(&state.pool, &ids, &words).await {
+            Ok(positions) => {
+                body_positions = positions;
+            }
+            Err(e) => {
+                eprintln!("Error fetching term positions: {}", e);
+                warnings.push(
+                    warnings::PROXIMITY_UNAVAILABLE,
+                    "Word positions couldn't be looked up, so NEAR only checked titles and descriptions"
+                        .to_string(),
+                    Some("q")
+                );
+            }
+        }
+    }
+
+    let mut matches = 0;
+    for (score, webpage) in ranked_webpages.iter_mut() {
+        let mut texts = vec![
+            positions_in(&webpage.title, &words, normaliser),
+            positions_in(&webpage.description, &words, normaliser)
+        ];
+        texts.extend(body_positions.remove(&webpage.id));
+
+        let proximity_match = parsed.proximity.iter().all(|near| {
+            texts.iter().any(|positions| {
+                let distance = ranking::min_distance(
+                    positions.get(&near.left).map_or(&[], Vec::as_slice),
+                    positions.get(&near.right).map_or(&[], Vec::as_slice)
+                );
+                distance.is_some_and(|distance| (distance as usize) <= near.distance)
+            })
+        });
+        webpage.proximity_match = Some(proximity_match);
+        if proximity_match {
+            *score += ranking::PROXIMITY_BONUS;
+            matches += 1;
+        }
+    }
+    matches
+}
+
+/// How many distinct terms `keywords` has, if more than one.
+pub fn distinct_term_count(keywords: &[String]) -> Option<usize> {
+    let mut terms: Vec<&String> = keywords.iter().collect();
+    terms.sort();
+    terms.dedup();
+    (terms.len() > 1).then_some(terms.len())
+}
+
+/// Where `words` occur in a title or description, counted in lemmatised words.
+fn positions_in(text: &str, words: &[String], normaliser: Normaliser) -> HashMap<String, Vec<i32>> {
+    let mut positions: HashMap<String, Vec<i32>> = HashMap::new();
+    for (position, word) in normaliser.terms(text).into_iter().enumerate() {
+        if words.contains(&word) {
+            positions.entry(word).or_default().push(position as i32);
+        }
+    }
+    positions
+}
+
+/// Moves the best `count` scored of the ranked webpages to the front and returns them, in no
+/// particular order. They're only partitioned from the rest, so no more is sorted than the
+/// results returned.
+fn best_candidates(
+    ranked_webpages: &mut [(f64, database::Webpage)],
+    count: usize
+) -> &mut [(f64, database::Webpage)] {
+    if count > 0 && count < ranked_webpages.len() {
+        ranked_webpages.select_nth_unstable_by(count - 1, ranking::best_first);
+    }
+    let count = count.min(ranked_webpages.len());
+    &mut ranked_webpages[..count]
+}
+
+/// Raises each of the best `ANCHOR_TEXT_CANDIDATES` scored pages by `ANCHOR_TEXT_WEIGHT` times
+/// the share of the query's distinct terms in the anchor text of links to it, normalised like
+/// the query, since how other pages describe a page often sums it up better than its own words.
+/// Records each checked page's share, and returns how many were raised. Pages are left alone
+/// if the schema doesn't record anchor text.
+async fn boost_anchor_text(
+    state: &AppState,
+    keywords: &[String],
+    ranked_webpages: &mut [(f64, database::Webpage)],
+    normaliser: Normaliser<'_>,
+    warnings: &mut Warnings
+) -> usize {
+    let config = state.config.anchor_text;
+    let mut terms: Vec<String> = keywords.to_vec();
+    terms.sort();
+    terms.dedup();
+    if !state.link_text || terms.is_empty() || config.weight <= 0.0 {
+        return 0;
+    }
+    let ranked_webpages = best_candidates(ranked_webpages, config.candidates);
+    if ranked_webpages.is_empty() {
+        return 0;
+    }
+
+    let urls: Vec<String> = ranked_webpages
+        .iter()
+        .map(|(_, webpage)| webpage.url.clone())
+        .collect();
+    let anchor_texts = match database::fetch_anchor_texts(&state.pool, &urls).await {
+        Ok(anchor_texts) => anchor_texts,
+        Err(e) => {
+            eprintln!("Error fetching anchor text: {}", e);
+            warnings.push(
+                warnings::ANCHOR_TEXT_UNAVAILABLE,
+                "The anchor text of links to the results couldn't be looked up, so it didn't rank them"
+                    .to_string(),
+                Some("q")
+            );
+            return 0;
+        }
+    };
+
+    let mut boosted = 0;
+    for (score, webpage) in ranked_webpages.iter_mut() {
+        let words: Vec<String> = anchor_texts
+            .get(&webpage.url)
+            .into_iter()
+            .flatten()
+            .flat_map(|text| normaliser.terms(text))
+            .collect();
+        let matched = terms
+            .iter()
+            .filter(|term| words.contains(term))
+            .count();
+        let share = (matched as f64) / (terms.len() as f64);
+        webpage.anchor_text = Some(share);
+        if share > 0.0 {
+            *score += config.weight * share;
+            boosted += 1;
+        }
+    }
+    boosted
+}
+
+/// Raises the scores of the best `PROXIMITY_CANDIDATES` ranked webpages by up to
+/// `PROXIMITY_WEIGHT`, by how close together they have the query's distinct terms in their
+/// title, description or text (when the crawler records word positions): the full weight for
+/// terms next to each other, half for one word between them, and so on. Returns how many were
+/// raised. Queries with a single term are left alone.
+///
+/// Also records whether each checked page's text has the query's words in order.
+async fn boost_term_closeness(
+    state: &AppState,
+    keywords: &[String],
+    ranked_webpages: &mut [(f64, database::Webpage)],
+    normaliser: Normaliser<'_>,
+    warnings: &mut Warnings
+) -> usize {
+    let config = state.config.proximity;
+    let mut terms: Vec<String> = keywords.to_vec();
+    terms.sort();
+    terms.dedup();
+    if terms.len() < 2 || config.weight <= 0.0 {
+        return 0;
+    }
+    let ranked_webpages = best_candidates(ranked_webpages, config.candidates);
+
+    let mut body_positions = HashMap::new();
+    if state.term_positions && !ranked_webpages.is_empty() {
+        let ids: Vec<i32> = ranked_webpages
+            .iter()
+            .map(|(_, webpage)| webpage.id)
+            .collect();
+        match database::fetch_term_positions(&state.pool, &ids, &terms).await {
+            Ok(positions) => {
+                body_positions = positions;
+            }
+            Err(e) => {
+                eprintln!("Error fetching term positions: {}", e);
+                warnings.push(
+                    warnings::PROXIMITY_UNAVAILABLE,
+                    "Word positions couldn't be looked up, so term closeness only checked titles and descriptions"
+                        .to_string(),
+                    Some("q")
+                );
+            }
+        }
+    }
+
+    let mut boosted = 0;
+    for (score, webpage) in ranked_webpages.iter_mut() {
+        let mut texts = vec![
+            positions_in(&webpage.title, &terms, normaliser),
+            positions_in(&webpage.description, &terms, normaliser)
+        ];
+        let body = body_positions.remove(&webpage.id);
+        if state.term_positions {
+            let in_text = body
+                .as_ref()
+                .is_some_and(|positions| ranking::contains_at_positions(positions, keywords));
+            webpage.verbatim_in_text = Some(in_text);
+        }
+        texts.extend(body);
+        let closeness = texts
+            .iter()
+            .filter_map(|positions| ranking::term_closeness(positions, &terms))
+            .fold(0.0, f64::max);
+        webpage.term_closeness = Some(closeness);
+        if closeness > 0.0 {
+            *score += config.weight * closeness;
+            boosted += 1;
+        }
+    }
+    boosted
+}
+
+/// Assesses ranked webpages against the spam list, dropping penalised ones in strict mode.
+pub async fn apply_spam_penalties(
+    state: &AppState,
+    ranked_webpages: &mut Vec<(f64, database::Webpage)>,
+    trace: &mut PipelineTrace,
+    warnings: &mut Warnings
+) {
+    let spam_list = state.spam_list.load_full();
+    if spam_list.is_empty() || ranked_webpages.is_empty() {
+        return;
+    }
+
+    let webpage_ids: Vec<i32> = ranked_webpages
+        .iter()
+        .map(|(_, webpage)| webpage.id)
+        .collect();
+    let fetched = database
+        ::fetch_keyword_occurrences(&state.pool, &webpage_ids, &spam_list.words()).await
+        .map_err(|e| e.to_string());
+    let occurrences = match fetched {
+        Ok(occurrences) => occurrences,
+        Err(e) => {
+            eprintln!("Error fetching spam term occurrences: {}", e);
+            warnings.push(
+                warnings::SPAM_CHECK_UNAVAILABLE,
+                "Results couldn't be checked for spam".to_string(),
+                None
+            );
+            return;
+        }
+    };
+
+    for (_score, webpage) in ranked_webpages.iter_mut() {
+        if let Some(occurrences) = occurrences.get(&webpage.id) {
+            webpage.spam = spam_list.assess(occurrences, webpage.word_count, &state.config.spam);
+        }
+    }
+    trace.spam_penalised = ranked_webpages
+        .iter()
+        .filter(|(_, webpage)| webpage.spam.as_ref().is_some_and(|spam| spam.penalty < 1.0))
+        .count();
+    if state.config.spam.strict {
+        ranked_webpages.retain(|(_, webpage)| {
+            webpage.spam.as_ref().is_none_or(|spam| spam.penalty >= 1.0)
+        });
+        trace.spam_excluded = trace.spam_penalised;
+    }
+}
+
+/// How webpages that passed the relevance threshold are ordered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RankingParams {
+    /// Multiplier for the relevance score
+    pub relevance_weight: f64,
+    /// How strongly link authority reorders equally relevant results
+    pub authority_weight: f64,
+    /// How much a domain's popularity rank adds to the score, at most, for the most popular
+    pub domain_rank_weight: f64,
+    /// How much a HITS hub score adds, at most, for the best hub
+    pub hub_weight: f64,
+    /// How much a HITS authority score adds, at most, for the best authority
+    pub hits_authority_weight: f64,
+    /// Multiplier for pages over the spam threshold; `None` keeps the penalty they were assessed
+    /// with
+    pub spam_penalty: Option<f64>,
+}
+
+impl RankingParams {
+    /// The ranking served to clients searching with the signal weights of `ranking`.
+    pub fn new(ranking: &RankingConfig) -> Self {
+        RankingParams {
+            relevance_weight: ranking.relevance,
+            authority_weight: ranking.links,
+            domain_rank_weight: ranking.domain,
+            hub_weight: ranking.hubs,
+            hits_authority_weight: ranking.authorities,
+            spam_penalty: None,
+        }
+    }
+
+    /// A webpage's relevance `score` blended with its link authority, its HITS scores, its
+    /// domain's popularity and any spam penalty.
+    pub fn blended_score(
+        &self,
+        score: f64,
+        webpage: &database::Webpage,
+        authority: &AuthorityScores,
+        top_domains: &HashMap<String, usize>
+    ) -> f64 {
+        self.score_parts(score, webpage, authority, top_domains).score()
+    }
+
+    /// The weighted parts `blended_score` adds up.
+    pub fn score_parts(
+        &self,
+        score: f64,
+        webpage: &database::Webpage,
+        authority: &AuthorityScores,
+        top_domains: &HashMap<String, usize>
+    ) -> ScoreParts {
+        let penalty = match (&webpage.spam, self.spam_penalty) {
+            (Some(spam), Some(penalty)) if spam.penalty < 1.0 => penalty,
+            (Some(spam), _) => spam.penalty as f64,
+            (None, _) => 1.0,
+        };
+        let hits = authority.hits(webpage.id);
+        ScoreParts {
+            relevance: self.relevance_weight * score,
+            links: self.authority_weight * (authority.normalised(webpage.id) as f64),
+            hubs: self.hub_weight * hits.map_or(0.0, |hits| hits.hub as f64),
+            authorities: self.hits_authority_weight *
+            hits.map_or(0.0, |hits| hits.authority as f64),
+            domain: self.domain_rank_weight * domain_popularity(top_domains, &webpage.url),
+            penalty,
+        }
+    }
+}
+
+/// A webpage's blended score, by signal
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreParts {
+    /// The score, times the relevance weight
+    pub relevance: f64,
+    /// Link authority, times its weight
+    pub links: f64,
+    /// HITS hub score, times its weight
+    pub hubs: f64,
+    /// HITS authority score, times its weight
+    pub authorities: f64,
+    /// Domain popularity, times its weight
+    pub domain: f64,
+    /// Multiplies the sum of the others; 1 for pages that aren't spam
+    pub penalty: f64,
+}
+
+impl ScoreParts {
+    pub fn score(&self) -> f64 {
+        (self.relevance + self.links + self.hubs + self.authorities + self.domain) * self.penalty
+    }
+}
+
+/// How popular a URL's domain is, from 1 for the top-ranked domain down towards 0 for the last
+/// listed, on a log scale so the difference between ranks 10 and 100 counts as much as between
+/// 100,000 and 1,000,000. A host that isn't listed takes the rank of its closest listed parent
+/// domain, so `www.youtube.com` ranks as `youtube.com`; unlisted domains score 0.
+fn domain_popularity(top_domains: &HashMap<String, usize>, url: &str) -> f64 {
+    let Some(host) = result_formatter::extract_domain_from_string(url) else {
+        return 0.0;
+    };
+    let rank = host
+        .match_indices('.')
+        .map(|(dot, _)| &host[dot + 1..])
+        .filter(|parent| parent.contains('.'))
+        .fold(top_domains.get(host.as_str()).copied(), |rank, parent| {
+            rank.or_else(|| top_domains.get(parent).copied())
+        });
+    let Some(rank) = rank else {
+        return 0.0;
+    };
+    // One more than the list's length, so the last listed domain still scores above unlisted ones
+    let scale = ((top_domains.len() + 1) as f64).ln();
+    1.0 - (rank as f64).ln() / scale
+}
+
+/// Orders ranked webpages by score blended with link authority, domain popularity and any spam
+/// penalty, then by their domain's popularity rank, then by id, keeping only the best `limit`.
+/// Returns how many were dropped. Each page's HITS scores are recorded on it for its result.
+pub fn sort_by_authority(
+    state: &AppState,
+    ranking: &RankingConfig,
+    ranked_webpages: &mut Vec<(f64, database::Webpage)>,
+    limit: usize,
+    trace: &mut PipelineTrace
+) -> usize {
+    if ranked_webpages.is_empty() {
+        return 0;
+    }
+
+    let authority = state.authority.load();
+    trace.authority_applied = authority.is_published();
+    for (_, webpage) in ranked_webpages.iter_mut() {
+        webpage.hits = authority.hits(webpage.id);
+    }
+    let params = RankingParams::new(ranking);
+    select_ranked(&state.top_domains, &authority, &params, ranked_webpages, limit)
+}
+
+/// Where a ranked webpage places, worked out once per page rather than once per comparison.
+/// Better placed pages order first.
+#[derive(PartialEq)]
+struct Placing {
+    blended: f64,
+    domain_rank: usize,
+    id: i32,
+    /// Where the page was in the input, so pages tied on everything else keep their order
+    index: usize,
+}
+
+impl Eq for Placing {}
+
+impl Ord for Placing {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.blended
+            .total_cmp(&self.blended)
+            .then_with(|| self.domain_rank.cmp(&other.domain_rank))
+            .then_with(|| self.id.cmp(&other.id))
+            .then_with(|| self.index.cmp(&other.index))
+    }
+}
+
+impl PartialOrd for Placing {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Orders ranked webpages the way `sort_by_authority` does, with any ranking `params`, keeping
+/// only the best `limit`, and returns how many were dropped. Fewer than all of them are picked
+/// out with a heap holding `limit` at a time, so only those are sorted; the order is the same as
+/// a full sort's.
+pub fn select_ranked(
+    top_domains: &HashMap<String, usize>,
+    authority: &AuthorityScores,
+    params: &RankingParams,
+    ranked_webpages: &mut Vec<(f64, database::Webpage)>,
+    limit: usize
+) -> usize {
+    let placings = ranked_webpages
+        .iter()
+        .enumerate()
+        .map(|(index, (score, webpage))| Placing {
+            blended: params.blended_score(*score, webpage, authority, top_domains),
+            domain_rank: domain_rank(top_domains, &webpage.url),
+            id: webpage.id,
+            index,
+        });
+    let mut best: Vec<Placing> = if limit >= ranked_webpages.len() {
+        placings.collect()
+    } else {
+        // The worst placed of the best so far is on top, to be replaced by any better page
+        let mut heap = BinaryHeap::with_capacity(limit);
+        for placing in placings {
+            if heap.len() < limit {
+                heap.push(placing);
+            } else if let Some(mut worst) = heap.peek_mut().filter(|worst| placing < **worst) {
+                *worst = placing;
+            }
+        }
+        heap.into_vec()
+    };
+    best.sort_unstable();
+
+    let dropped = ranked_webpages.len() - best.len();
+    let mut pages: Vec<Option<(f64, database::Webpage)>> = ranked_webpages
+        .drain(..)
+        .map(Some)
+        .collect();
+    ranked_webpages.extend(best.iter().filter_map(|placing| pages[placing.index].take()));
+    dropped
+}
+
+/// The popularity rank of a URL's host, which breaks ties between equally scored pages, or
+/// `usize::MAX` if the host isn't listed.
+pub fn domain_rank(top_domains: &HashMap<String, usize>, url: &str) -> usize {
+    result_formatter
+        ::extract_domain_from_string(url)
+        .and_then(|domain| top_domains.get(&domain).cloned())
+        .unwrap_or(usize::MAX)
+}
+
+/// Fills in the link counts and linking pages of ranked webpages.
+pub async fn attach_links(
+    state: &AppState,
+    ranked_webpages: &mut [(f64, database::Webpage)],
+    warnings: &mut Warnings
+) {
+    let webpage_ids: Vec<i32> = ranked_webpages
+        .iter()
+        .map(|(_, webpage)| webpage.id)
+        .collect();
+
+    let fetched = database
+        ::fetch_links_for_ids(&state.pool, &webpage_ids).await
+        .map_err(|e| e.to_string());
+    let links = match fetched {
+        Ok(links) => links,
+        Err(e) => {
+            eprintln!("Error fetching links: {}", e);
+            warnings.push(
+                warnings::LINKS_UNAVAILABLE,
+                "Links couldn't be fetched, so results have none".to_string(),
+                Some("links")
+            );
+            return;
+        }
+    };
+
+    for (_score, webpage) in ranked_webpages.iter_mut() {
+        if let Some((links_to_count, links_from)) = links.get(&webpage.id) {
+            webpage.links_to_count = Some(*links_to_count);
+            webpage.links_from = Some(links_from.clone());
+        }
+    }
+}
+
+/// Finishes a two-tier search in the background, storing the complete ranking in the result
+/// cache. Returns the continuation token to fetch it with, or `None` if too many refinements
+/// are already running.
+pub fn refine_in_background(
+    state: &Arc<AppState>,
+    query: &str,
+    options: SearchOptions
+) -> Option<String> {
+    let permit = state.refinement_permits.clone().try_acquire_owned().ok()?;
+    let token = continuation_token(query);
+
+    let state = state.clone();
+    let query = query.to_string();
+    let key = continuation_cache_key(&token);
+    tokio::spawn(async move {
+        let outcome = perform_search(
+            &query,
+            &state,
+            options,
+            None,
+            &mut RequestTiming::default(),
+            &mut PipelineTrace::default(),
+            &mut Warnings::default()
+        ).await;
+        // A failed refinement leaves the follow-up to run the quick search again
+        if let Ok(outcome) = outcome {
+            state.result_cache.insert(key, Arc::new(outcome));
+        }
+        drop(permit);
+    });
+
+    Some(token)
+}
+
+/// Queues a search slower than the configured threshold for the slow-query log, with the
+/// pipeline trace that explains where the time went.
+pub fn log_slow_query(
+    state: &AppState,
+    endpoint: &str,
+    query: serde_json::Value,
+    duration: Duration,
+    trace: PipelineTrace
+) {
+    state.slow_query_log.send(
+        serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "endpoint": endpoint,
+            "query": query,
+            "duration_ms": duration.as_secs_f64() * 1000.0,
+            "trace": trace.to_json(),
+        })
+    );
+}
+
+/// Generates an unguessable-enough, unique key for a pending two-tier refinement.
+fn continuation_token(query: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = DefaultHasher::new();
+    query.hash(&mut hasher);
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().hash(&mut hasher);
+    COUNTER.fetch_add(1, Ordering::Relaxed).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn keeps_the_rarest_keywords_within_the_budget() {
+        let frequencies = HashMap::from([
+            ("common".to_string(), 900),
+            ("rare".to_string(), 3),
+            ("middling".to_string(), 40),
+            ("unique".to_string(), 1),
+        ]);
+        let all = words(&["common", "rare", "middling", "unique"]);
+
+        assert_eq!(select_keywords(&all, &frequencies, 2), words(&["unique", "rare"]));
+        assert_eq!(
+            select_keywords(&all, &frequencies, 10),
+            words(&["unique", "rare", "middling", "common"])
+        );
+        assert!(select_keywords(&all, &frequencies, 0).is_empty());
+    }
+
+    #[test]
+    fn picks_unindexed_keywords_last_and_keeps_the_order_of_ties() {
+        let frequencies = HashMap::from([
+            ("b".to_string(), 5),
+            ("d".to_string(), 5),
+            ("e".to_string(), 9),
+        ]);
+        let all = words(&["a", "b", "c", "d", "e"]);
+
+        assert_eq!(select_keywords(&all, &frequencies, 2), words(&["b", "d"]));
+        assert_eq!(select_keywords(&all, &frequencies, 4), words(&["b", "d", "e", "a"]));
+        // With no frequencies at all, the budget is taken in order
+        assert_eq!(select_keywords(&all, &HashMap::new(), 3), words(&["a", "b", "c"]));
+    }
+}
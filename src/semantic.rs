@@ -0,0 +1,123 @@
+use serde_json::{ Value, json };
+use crate::config::SemanticConfig;
+use crate::database::Webpage;
+use crate::state::AppState;
+
+/// Reorders the best `SEMANTIC_TOP_K` of `ranked_webpages` by the cosine similarity of their
+/// embeddings to the query's, returning how many were reordered. The rest keep their places
+/// after them.
+///
+/// Pages are embedded by their title and description. On an error the order is left as it was.
+pub async fn rerank(
+    state: &AppState,
+    query: &str,
+    ranked_webpages: &mut Vec<(f64, Webpage)>
+) -> Result<usize, String> {
+    let config = &state.config.semantic;
+    let Some(url) = &config.url else {
+        return Err("No embedding service is configured".to_string());
+    };
+    let top = ranked_webpages.len().min(config.top_k);
+    if top < 2 {
+        return Ok(0);
+    }
+
+    let mut inputs = vec![query.to_string()];
+    inputs.extend(ranked_webpages[..top].iter().map(|(_, webpage)| document_text(webpage)));
+    let embeddings = tokio::time
+        ::timeout(config.timeout, embed(state, config, url, &inputs)).await
+        .map_err(|_| "Embedding service timed out".to_string())??;
+    let Some((query_embedding, documents)) = embeddings.split_first() else {
+        return Err("Embedding service returned no embeddings".to_string());
+    };
+
+    let mut reranked: Vec<(f64, (f64, Webpage))> = documents
+        .iter()
+        .map(|embedding| cosine_similarity(query_embedding, embedding))
+        .zip(ranked_webpages.drain(..top))
+        .collect();
+    // Stable, so pages the embeddings can't tell apart keep their ranked order
+    reranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranked_webpages.splice(0..0, reranked.into_iter().map(|(_, ranked)| ranked));
+    Ok(top)
+}
+
+/// The text a page is embedded by.
+fn document_text(webpage: &Webpage) -> String {
+    if webpage.description.is_empty() {
+        webpage.title.clone()
+    } else {
+        format!("{}\n{}", webpage.title, webpage.description)
+    }
+}
+
+/// Embeds `inputs` with an OpenAI-compatible embeddings endpoint, returning one embedding per
+/// input, in order.
+async fn embed(
+    state: &AppState,
+    config: &SemanticConfig,
+    url: &str,
+    inputs: &[String]
+) -> Result<Vec<Vec<f64>>, String> {
+    let mut body = json!({ "input": inputs });
+    if let Some(model) = &config.model {
+        body["model"] = json!(model);
+    }
+    let response: Value = state.http_client
+        .post_json(url, &body, config.api_key.as_deref()).await
+        .map_err(|e| e.to_string())?
+        .json().await
+        .map_err(|e| e.to_string())?;
+
+    let mut data: Vec<&Value> = response["data"]
+        .as_array()
+        .ok_or("Embedding response has no data")?
+        .iter()
+        .collect();
+    // Services may answer out of order, so each embedding is placed by its index if it has one
+    data.sort_by_key(|item| item["index"].as_u64());
+    let embeddings: Vec<Vec<f64>> = data
+        .into_iter()
+        .map(|item| {
+            item["embedding"]
+                .as_array()
+                .map(|values| values.iter().filter_map(Value::as_f64).collect())
+                .ok_or("Embedding response has an item without an embedding")
+        })
+        .collect::<Result<_, _>>()?;
+    if embeddings.len() != inputs.len() {
+        return Err(
+            format!(
+                "Embedding service returned {} embeddings for {} inputs",
+                embeddings.len(),
+                inputs.len()
+            )
+        );
+    }
+    Ok(embeddings)
+}
+
+/// The cosine of the angle between two embeddings, or 0 if either is all zeros or they differ
+/// in length.
+fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f64 = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| x * y)
+        .sum();
+    let norm = |v: &[f64]| {
+        v.iter()
+            .map(|x| x * x)
+            .sum::<f64>()
+            .sqrt()
+    };
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
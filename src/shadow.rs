@@ -0,0 +1,292 @@
+use serde_json::{ Value, json };
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use tokio::sync::Semaphore;
+use crate::config::ShadowConfig;
+use crate::database::Webpage;
+use crate::search::{ self, RankingParams };
+use crate::state::AppState;
+
+/// How many results at the top of each ordering are compared
+const COMPARED_RESULTS: usize = 10;
+
+/// Picks searches to re-rank with a candidate ranking configuration, and counts how the
+/// comparisons went
+pub struct ShadowRanker {
+    sample_percent: f64,
+    params: RankingParams,
+    /// Bounds how many comparisons run in the background at once
+    permits: Arc<Semaphore>,
+    searches: AtomicU64,
+    compared: AtomicU64,
+    /// Sampled searches skipped because too many comparisons were already running
+    dropped: AtomicU64,
+    /// Comparisons where the shadow ranking put a different result first
+    top_changed: AtomicU64,
+    /// Summed overlap@10 in millionths, so the mean can be kept without a lock
+    overlap_micros: AtomicU64,
+}
+
+impl ShadowRanker {
+    pub fn new(config: &ShadowConfig) -> Self {
+        ShadowRanker {
+            sample_percent: config.sample_percent,
+            params: RankingParams {
+                relevance_weight: config.relevance_weight,
+                authority_weight: config.authority_weight,
+                domain_rank_weight: config.domain_rank_weight,
+                hub_weight: config.hub_weight,
+                hits_authority_weight: config.hits_authority_weight,
+                spam_penalty: Some(config.spam_penalty),
+            },
+            permits: Arc::new(Semaphore::new(config.max_concurrent)),
+            searches: AtomicU64::new(0),
+            compared: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            top_changed: AtomicU64::new(0),
+            overlap_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the next search should also be ranked in the shadow. Picks are evenly spaced
+    /// rather than random, so exactly `sample_percent` of searches are sampled.
+    pub fn sample(&self) -> bool {
+        if self.sample_percent <= 0.0 {
+            return false;
+        }
+        let n = self.searches.fetch_add(1, Ordering::Relaxed) as f64;
+        let picked = |n: f64| ((n * self.sample_percent) / 100.0).floor();
+        picked(n + 1.0) > picked(n)
+    }
+
+    pub fn stats(&self) -> Value {
+        let compared = self.compared.load(Ordering::Relaxed);
+        let mean = |total: u64| (compared > 0).then(|| (total as f64) / (compared as f64));
+        json!({
+            "sample_percent": self.sample_percent,
+            "authority_weight": self.params.authority_weight,
+            "spam_penalty": self.params.spam_penalty,
+            "compared": compared,
+            "dropped": self.dropped.load(Ordering::Relaxed),
+            "top_changed": self.top_changed.load(Ordering::Relaxed),
+            "mean_overlap_at_10": mean(self.overlap_micros.load(Ordering::Relaxed))
+                .map(|micros| micros / 1e6),
+        })
+    }
+}
+
+/// Re-ranks a search's candidates with the shadow configuration on a background task, and
+/// logs how its ordering compares with `served`, the ids of the results the client got.
+///
+/// The candidates are the ones the served ranking sorted, so the client's response is never
+/// held up or changed. The comparison is dropped, and counted, if too many are already running.
+pub fn compare_in_background(
+    state: &Arc<AppState>,
+    query: &str,
+    primary: RankingParams,
+    candidates: Vec<(f64, Webpage)>,
+    served: Vec<i32>
+) {
+    let shadow = &state.shadow;
+    let permit = match shadow.permits.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            shadow.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+
+    let state = state.clone();
+    let query = query.to_string();
+    tokio::spawn(async move {
+        let record = compare(&state, &query, &primary, candidates, &served);
+        state.shadow_log.send(record);
+        drop(permit);
+    });
+}
+
+fn compare(
+    state: &AppState,
+    query: &str,
+    primary: &RankingParams,
+    mut candidates: Vec<(f64, Webpage)>,
+    served: &[i32]
+) -> Value {
+    let shadow = &state.shadow;
+    let authority = state.authority.load_full();
+
+    let primary_scores: HashMap<i32, f64> = candidates
+        .iter()
+        .map(|(score, webpage)| {
+            let blended = primary.blended_score(*score, webpage, &authority, &state.top_domains);
+            (webpage.id, blended)
+        })
+        .collect();
+    let params = &shadow.params;
+    search::select_ranked(&state.top_domains, &authority, params, &mut candidates, usize::MAX);
+    let reranked: Vec<i32> = candidates
+        .iter()
+        .map(|(_, webpage)| webpage.id)
+        .collect();
+
+    let (overlap, top_result_shadow_rank) = compare_orderings(served, &reranked);
+    shadow.compared.fetch_add(1, Ordering::Relaxed);
+    shadow.overlap_micros.fetch_add((overlap * 1e6).round() as u64, Ordering::Relaxed);
+    if top_result_shadow_rank.is_some_and(|rank| rank != Some(1)) {
+        shadow.top_changed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let shadow_scores: Vec<f64> = candidates
+        .iter()
+        .take(COMPARED_RESULTS)
+        .map(|(score, webpage)| {
+            shadow.params.blended_score(*score, webpage, &authority, &state.top_domains)
+        })
+        .collect();
+    let served_scores: Vec<f64> = served
+        .iter()
+        .take(COMPARED_RESULTS)
+        .filter_map(|id| primary_scores.get(id).copied())
+        .collect();
+
+    json!({
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "query": query,
+        "candidates": candidates.len(),
+        "primary": params_json(primary),
+        "shadow": params_json(&shadow.params),
+        "overlap_at_10": overlap,
+        "top_result_shadow_rank": top_result_shadow_rank.flatten(),
+        "primary_scores": distribution(&served_scores),
+        "shadow_scores": distribution(&shadow_scores),
+    })
+}
+
+/// The share of the top results of the `served` and `reranked` orderings they have in common,
+/// and where the served top result ranks in `reranked`, counting from 1. The rank is `None`
+/// if nothing was served, and `Some(None)` if the shadow ranking didn't keep it.
+fn compare_orderings(served: &[i32], reranked: &[i32]) -> (f64, Option<Option<usize>>) {
+    let served_top = &served[..served.len().min(COMPARED_RESULTS)];
+    let shadow_top = &reranked[..reranked.len().min(COMPARED_RESULTS)];
+    let compared = served_top.len().max(shadow_top.len());
+    let overlap = if compared == 0 {
+        1.0
+    } else {
+        let shared = served_top
+            .iter()
+            .filter(|id| shadow_top.contains(id))
+            .count();
+        (shared as f64) / (compared as f64)
+    };
+    let top_result_shadow_rank = served
+        .first()
+        .map(|top| reranked.iter().position(|id| id == top).map(|position| position + 1));
+    (overlap, top_result_shadow_rank)
+}
+
+fn params_json(params: &RankingParams) -> Value {
+    json!({
+        "relevance_weight": params.relevance_weight,
+        "authority_weight": params.authority_weight,
+        "domain_rank_weight": params.domain_rank_weight,
+        "hub_weight": params.hub_weight,
+        "hits_authority_weight": params.hits_authority_weight,
+        "spam_penalty": params.spam_penalty,
+    })
+}
+
+/// The minimum, mean and maximum of the top results' blended scores, or `null` without any.
+fn distribution(scores: &[f64]) -> Value {
+    if scores.is_empty() {
+        return Value::Null;
+    }
+    json!({
+        "min": scores.iter().copied().fold(f64::INFINITY, f64::min),
+        "mean": scores.iter().sum::<f64>() / (scores.len() as f64),
+        "max": scores.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranker(sample_percent: f64) -> ShadowRanker {
+        ShadowRanker::new(&ShadowConfig {
+            sample_percent,
+            relevance_weight: 1.0,
+            authority_weight: 0.5,
+            domain_rank_weight: 0.0,
+            hub_weight: 0.0,
+            hits_authority_weight: 0.0,
+            spam_penalty: 0.1,
+            max_concurrent: 1,
+            log_file: None,
+        })
+    }
+
+    #[test]
+    fn samples_evenly_spaced_searches() {
+        let sampled = |percent: f64, searches: usize| {
+            let ranker = ranker(percent);
+            (0..searches).filter(|_| ranker.sample()).count()
+        };
+        assert_eq!(sampled(0.0, 100), 0);
+        assert_eq!(sampled(-5.0, 100), 0);
+        assert_eq!(sampled(100.0, 100), 100);
+        assert_eq!(sampled(25.0, 100), 25);
+        assert_eq!(sampled(12.5, 8), 1);
+        assert_eq!(sampled(12.5, 7), 0);
+
+        // Every other search, never two in a row
+        let ranker = ranker(50.0);
+        let picks: Vec<bool> = (0..6).map(|_| ranker.sample()).collect();
+        assert_eq!(picks, [false, true, false, true, false, true]);
+    }
+
+    #[test]
+    fn compares_the_top_ten_of_each_ordering() {
+        let ids = |range: std::ops::Range<i32>| range.collect::<Vec<i32>>();
+        assert_eq!(compare_orderings(&ids(1..6), &ids(1..6)), (1.0, Some(Some(1))));
+
+        // Reversed, the same results are shared but the top one is last
+        let reversed: Vec<i32> = ids(1..6).into_iter().rev().collect();
+        assert_eq!(compare_orderings(&ids(1..6), &reversed), (1.0, Some(Some(5))));
+
+        // Past ten results, order beyond the top ten doesn't count, but the top result's rank
+        // is still found
+        assert_eq!(compare_orderings(&ids(1..21), &ids(2..22)), (0.9, Some(None)));
+        let mut pushed_down = ids(2..21);
+        pushed_down.insert(14, 1);
+        assert_eq!(compare_orderings(&ids(1..21), &pushed_down), (0.9, Some(Some(15))));
+
+        // Orderings of different lengths are compared over the longer
+        assert_eq!(compare_orderings(&[1, 2], &[1, 2, 3, 4]), (0.5, Some(Some(1))));
+        assert_eq!(compare_orderings(&[], &[1]), (0.0, None));
+        assert_eq!(compare_orderings(&[], &[]), (1.0, None));
+    }
+
+    #[test]
+    fn summarises_score_distributions() {
+        assert_eq!(distribution(&[]), Value::Null);
+        assert_eq!(distribution(&[0.5]), json!({ "min": 0.5, "mean": 0.5, "max": 0.5 }));
+        assert_eq!(
+            distribution(&[2.0, 0.5, 3.5]),
+            json!({ "min": 0.5, "mean": 2.0, "max": 3.5 })
+        );
+    }
+
+    #[test]
+    fn reports_means_only_once_something_was_compared() {
+        let ranker = ranker(10.0);
+        let stats = ranker.stats();
+        assert_eq!(stats["compared"], 0);
+        assert_eq!(stats["mean_overlap_at_10"], Value::Null);
+
+        ranker.compared.store(4, Ordering::Relaxed);
+        ranker.overlap_micros.store(3_000_000, Ordering::Relaxed);
+        assert_eq!(ranker.stats()["mean_overlap_at_10"], 0.75);
+        assert_eq!(ranker.stats()["sample_percent"], 10.0);
+    }
+}
@@ -0,0 +1,240 @@
+use chrono::{ DateTime, Utc };
+use serde_json::{ Value, json };
+use std::collections::HashMap;
+use url::Url;
+use crate::database::Webpage;
+use crate::ranking;
+use crate::result_formatter::extract_domain_from_string;
+
+/// Bumped whenever a signal is added, removed, or computed differently, so re-rankers trained
+/// on one version can tell when their features change
+pub const SIGNALS_VERSION: u32 = 1;
+
+/// The raw ranking features of one result, for clients that re-rank results themselves
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultSignals {
+    /// One entry per distinct query term, in query order
+    pub terms: Vec<TermSignals>,
+    pub word_count: i32,
+    /// Share of the distinct query terms the page contains
+    pub coverage: f64,
+    /// The domain's position in the top domains list, if it's listed
+    pub domain_rank: Option<usize>,
+    /// Links to the page from indexed pages, if links were fetched
+    pub inbound_links: Option<usize>,
+    /// Number of non-empty path segments in the URL
+    pub url_depth: usize,
+    /// Whole days since the page was last crawled, if it has been
+    pub age_days: Option<i64>,
+}
+
+/// How a query term scores on one page
+#[derive(Debug, Clone, PartialEq)]
+pub struct TermSignals {
+    pub term: String,
+    pub occurrences: i32,
+    /// Occurrences over the page's word count
+    pub tf: f64,
+    /// As used for ranking, `None` if the page doesn't contain the term
+    pub idf: Option<f64>,
+}
+
+impl ResultSignals {
+    /// Collects the signals of `webpage` for a search that looked up `keywords` in an index of
+    /// `document_count` pages.
+    pub fn compute(
+        webpage: &Webpage,
+        keywords: &[String],
+        document_count: i64,
+        top_domains: &HashMap<String, usize>,
+        now: DateTime<Utc>
+    ) -> Self {
+        let mut distinct: Vec<&String> = vec![];
+        for keyword in keywords {
+            if !distinct.contains(&keyword) {
+                distinct.push(keyword);
+            }
+        }
+
+        let terms: Vec<TermSignals> = distinct
+            .iter()
+            .map(|&term| {
+                let found = webpage.keywords.iter().find(|(keyword, _)| keyword.word == *term);
+                let occurrences = found.map_or(0, |(_, occurrences)| *occurrences);
+                TermSignals {
+                    term: term.clone(),
+                    occurrences,
+                    tf: (occurrences as f64) / (webpage.word_count.max(1) as f64),
+                    idf: found.map(|(keyword, _)| {
+                        ranking::idf(document_count, keyword.documents_containing_word)
+                    }),
+                }
+            })
+            .collect();
+        let matched = terms
+            .iter()
+            .filter(|term| term.occurrences > 0)
+            .count();
+
+        ResultSignals {
+            coverage: if terms.is_empty() {
+                0.0
+            } else {
+                (matched as f64) / (terms.len() as f64)
+            },
+            terms,
+            word_count: webpage.word_count,
+            domain_rank: extract_domain_from_string(&webpage.url).and_then(|domain| {
+                top_domains.get(&domain).copied()
+            }),
+            inbound_links: webpage.links_from
+                .as_ref()
+                .map(|links_from| links_from.values().map(|&count| count as usize).sum()),
+            url_depth: url_depth(&webpage.url),
+            age_days: webpage.last_crawled.map(|time| (now - time).num_days().max(0)),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "terms": self.terms.iter().map(|term| {
+                json!({
+                    "term": term.term,
+                    "occurrences": term.occurrences,
+                    "tf": term.tf,
+                    "idf": term.idf,
+                })
+            }).collect::<Vec<_>>(),
+            "word_count": self.word_count,
+            "coverage": self.coverage,
+            "domain_rank": self.domain_rank,
+            "inbound_links": self.inbound_links,
+            "url_depth": self.url_depth,
+            "age_days": self.age_days,
+        })
+    }
+}
+
+fn url_depth(url: &str) -> usize {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.path_segments().map(|segments| segments.filter(|segment| !segment.is_empty()).count())
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Keyword;
+
+    fn keyword(word: &str, documents_containing_word: i64) -> Keyword {
+        Keyword { id: 0, word: word.to_string(), documents_containing_word }
+    }
+
+    fn date(date: &str) -> DateTime<Utc> {
+        format!("{}T00:00:00Z", date).parse().unwrap()
+    }
+
+    fn page() -> Webpage {
+        Webpage {
+            url: "https://docs.example.com/guide/async/".to_string(),
+            word_count: 200,
+            last_crawled: Some(date("2024-03-01")),
+            keywords: vec![(keyword("async", 2), 10), (keyword("rust", 5), 4)],
+            ..Webpage::default()
+        }
+    }
+
+    fn compute(webpage: &Webpage, keywords: &[&str]) -> ResultSignals {
+        let keywords: Vec<String> = keywords.iter().map(|keyword| keyword.to_string()).collect();
+        let top_domains = HashMap::from([("docs.example.com".to_string(), 7)]);
+        ResultSignals::compute(webpage, &keywords, 10, &top_domains, date("2024-03-11"))
+    }
+
+    #[test]
+    fn computes_term_frequencies_and_idf_for_each_distinct_term() {
+        let signals = compute(&page(), &["rust", "async", "rust", "tokio"]);
+        assert_eq!(
+            signals.terms,
+            [
+                TermSignals {
+                    term: "rust".to_string(),
+                    occurrences: 4,
+                    tf: 0.02,
+                    idf: Some((2.0_f64).ln()),
+                },
+                TermSignals {
+                    term: "async".to_string(),
+                    occurrences: 10,
+                    tf: 0.05,
+                    idf: Some((5.0_f64).ln()),
+                },
+                TermSignals { term: "tokio".to_string(), occurrences: 0, tf: 0.0, idf: None },
+            ]
+        );
+        // Two of the three distinct terms, however often "rust" was repeated
+        assert!((signals.coverage - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(compute(&page(), &["rust"]).coverage, 1.0);
+        assert_eq!(compute(&page(), &[]).coverage, 0.0);
+    }
+
+    #[test]
+    fn computes_the_page_signals() {
+        let signals = compute(&page(), &["rust"]);
+        assert_eq!(signals.word_count, 200);
+        assert_eq!(signals.domain_rank, Some(7));
+        assert_eq!(signals.url_depth, 2);
+        assert_eq!(signals.age_days, Some(10));
+        assert_eq!(signals.inbound_links, None);
+
+        // Links from each page are summed, and an empty page doesn't divide by zero
+        let webpage = Webpage {
+            url: "https://other.example.com".to_string(),
+            word_count: 0,
+            last_crawled: Some(date("2024-03-20")),
+            links_from: Some(HashMap::from([("a".to_string(), 2), ("b".to_string(), 3)])),
+            ..page()
+        };
+        let signals = compute(&webpage, &["rust"]);
+        assert_eq!(signals.inbound_links, Some(5));
+        assert_eq!(signals.terms[0].tf, 4.0);
+        assert_eq!(signals.domain_rank, None);
+        assert_eq!(signals.url_depth, 0);
+        // Crawled after "now", from a clock that's behind
+        assert_eq!(signals.age_days, Some(0));
+        let never_crawled = Webpage { last_crawled: None, ..page() };
+        assert_eq!(compute(&never_crawled, &["rust"]).age_days, None);
+    }
+
+    #[test]
+    fn counts_non_empty_path_segments() {
+        assert_eq!(url_depth("https://example.com/"), 0);
+        assert_eq!(url_depth("https://example.com/a"), 1);
+        assert_eq!(url_depth("https://example.com//a/b/?page=c/d#e/f"), 2);
+        assert_eq!(url_depth("not a url"), 0);
+    }
+
+    #[test]
+    fn serialises_every_signal() {
+        let mut webpage = page();
+        webpage.links_from = Some(HashMap::from([("a".to_string(), 1)]));
+        let json = compute(&webpage, &["rust", "tokio"]).to_json();
+        assert_eq!(
+            json,
+            json!({
+                "terms": [
+                    { "term": "rust", "occurrences": 4, "tf": 0.02, "idf": (2.0_f64).ln() },
+                    { "term": "tokio", "occurrences": 0, "tf": 0.0, "idf": null },
+                ],
+                "word_count": 200,
+                "coverage": 0.5,
+                "domain_rank": 7,
+                "inbound_links": 1,
+                "url_depth": 2,
+                "age_days": 10,
+            })
+        );
+    }
+}
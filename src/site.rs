@@ -0,0 +1,143 @@
+use axum::{
+    extract::{ Extension, Path, Query },
+    http::{ header, HeaderMap, StatusCode },
+    response::{ IntoResponse, Json, Response },
+};
+use chrono::{ DateTime, SecondsFormat, Utc };
+use serde_json::{ Value, json };
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::auth;
+use crate::database::{ self, SiteSort };
+use crate::locale::Locale;
+use crate::result_formatter::{ canonicalise_domain, snippet };
+use crate::state::AppState;
+
+const DEFAULT_PER_PAGE: i64 = 20;
+const MAX_PER_PAGE: i64 = 100;
+
+/// Descriptions are cut to this many characters in listings
+const SNIPPET_CHARS: usize = 200;
+
+/// `GET /site/{domain}?page=1&per_page=20&sort=recent|links|words`: the indexed pages on a
+/// domain and its subdomains. Admins may pass `include_deleted=true` to list tombstoned pages
+/// too.
+pub async fn site_pages(
+    Path(domain): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    Extension(state): Extension<Arc<AppState>>,
+    headers: HeaderMap
+) -> Response {
+    let domain = match canonicalise_domain(&domain) {
+        Some(domain) => domain,
+        None => {
+            return error_response(StatusCode::BAD_REQUEST, "Invalid domain");
+        }
+    };
+    let (sort, sort_name) = match params.get("sort").map(String::as_str) {
+        None | Some("recent") => (SiteSort::Recent, "recent"),
+        Some("links") => (SiteSort::Links, "links"),
+        Some("words") => (SiteSort::Words, "words"),
+        Some(_) => {
+            return error_response(StatusCode::BAD_REQUEST, "sort must be recent, links, or words");
+        }
+    };
+    let page = params
+        .get("page")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1_i64)
+        .max(1);
+    let per_page = params
+        .get("per_page")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+
+    let include_deleted = match params.get("include_deleted").map(String::as_str) {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(_) => {
+            return error_response(StatusCode::BAD_REQUEST, "include_deleted must be true or false");
+        }
+    };
+    let is_admin = state.config.admin_token
+        .as_deref()
+        .is_some_and(|admin_token| auth::is_admin(&headers, admin_token));
+    if include_deleted && !is_admin {
+        return error_response(StatusCode::FORBIDDEN, "include_deleted requires the admin token");
+    }
+
+    let locale = Locale::negotiate(params.get("ui_lang").map(String::as_str), &headers);
+
+    let cache_key = format!("{}|{}|{}|{}|{}", domain, sort_name, page, per_page, include_deleted);
+    if let Some(cached) = state.site_cache.get(&cache_key) {
+        return localised_response(cached, locale);
+    }
+
+    let offset = (page - 1).saturating_mul(per_page);
+    let tombstones = state.tombstones(include_deleted);
+    let fetched = state.database.run(|| {
+        database::fetch_site_pages(&state.pool, &domain, sort, per_page, offset, tombstones)
+    }).await;
+    let (total, pages) = match fetched {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error fetching pages for site {}: {}", domain, e);
+            return e.response("Failed to fetch site pages");
+        }
+    };
+
+    let body =
+        json!({
+        "domain": domain,
+        "sort": sort_name,
+        "total": total,
+        "page": page,
+        "per_page": per_page,
+        "total_pages": (total + per_page - 1) / per_page,
+        "has_more": offset + (pages.len() as i64) < total,
+        "pages": pages.iter().map(|page| {
+            json!({
+                "title": page.title,
+                "url": page.url,
+                "description": snippet(&page.description, SNIPPET_CHARS),
+                "word_count": page.word_count,
+                "last_crawled": page.last_crawled
+                    .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                "inbound_links": page.inbound_links,
+            })
+        }).collect::<Vec<_>>(),
+    });
+
+    state.site_cache.insert(cache_key, body.clone());
+    localised_response(body, locale)
+}
+
+/// Adds a human-readable `last_crawled_ago` to each page. It's added after caching so it stays
+/// current and the cache is shared between locales.
+fn localised_response(mut body: Value, locale: Locale) -> Response {
+    let now = Utc::now();
+    if let Some(pages) = body["pages"].as_array_mut() {
+        for page in pages {
+            let last_crawled = page["last_crawled"]
+                .as_str()
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+            page["last_crawled_ago"] = json!(
+                last_crawled.map(|time| {
+                    locale.relative_age((now - time.with_timezone(&Utc)).to_std().unwrap_or_default())
+                })
+            );
+        }
+    }
+    (
+        [
+            (header::CONTENT_LANGUAGE, locale.code()),
+            (header::VARY, "accept-language"),
+        ],
+        Json(body),
+    ).into_response()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
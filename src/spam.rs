@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ BufRead, BufReader };
+use std::path::Path;
+use crate::config::SpamConfig;
+use crate::lemmatise;
+
+/// Why a page was judged spammy, and the multiplier applied to its ranking
+#[derive(Debug, Clone)]
+pub struct SpamMatch {
+    pub terms: Vec<String>,
+    /// Occurrences of spam terms per word on the page
+    pub score: f32,
+    /// 1.0 when the page stayed under the threshold
+    pub penalty: f32,
+}
+
+/// Terms whose repeated use marks a page as spam
+#[derive(Debug, Default)]
+pub struct SpamList {
+    /// Each term as written in the file, with the lemmatised words that must all appear
+    terms: Vec<(String, Vec<String>)>,
+}
+
+impl SpamList {
+    /// Loads spam terms from a file, one term per line. A term of several words only matches
+    /// pages containing all of them. Lines starting with `#` are comments.
+    ///
+    /// Terms are lemmatised and folded the same way queries are, so they match stored keywords.
+    pub fn load<P: AsRef<Path>>(filename: P, folding_exceptions: &[char]) -> Result<Self, std::io::Error> {
+        let file = File::open(filename)?;
+        let reader = BufReader::new(file);
+        let mut terms = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let words = lemmatise::lemmatise_string(line, folding_exceptions);
+            if !words.is_empty() {
+                terms.push((line.to_string(), words));
+            }
+        }
+        Ok(SpamList { terms })
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Returns every distinct keyword that appears in a spam term.
+    pub fn words(&self) -> Vec<String> {
+        let mut words: Vec<String> = self.terms
+            .iter()
+            .flat_map(|(_, words)| words.iter().cloned())
+            .collect();
+        words.sort();
+        words.dedup();
+        words
+    }
+
+    /// Scores a page from how often it uses spam terms relative to its length.
+    ///
+    /// `occurrences` holds the page's stored occurrence count for each spam keyword it contains.
+    /// The penalty only applies once the page uses spam terms at least `min_occurrences` times
+    /// and at a density of at least `threshold`, so a legitimate page mentioning a term in
+    /// passing keeps its ranking. Returns `None` if no term matched at all.
+    pub fn assess(
+        &self,
+        occurrences: &HashMap<String, i32>,
+        word_count: i32,
+        config: &SpamConfig
+    ) -> Option<SpamMatch> {
+        let mut terms = Vec::new();
+        let mut hits: i64 = 0;
+
+        for (term, words) in &self.terms {
+            // A multi-word term occurs as often as its rarest word
+            let count = words
+                .iter()
+                .map(|word| occurrences.get(word).copied().unwrap_or(0))
+                .min()
+                .unwrap_or(0);
+            if count > 0 {
+                terms.push(term.clone());
+                hits += count as i64;
+            }
+        }
+
+        if terms.is_empty() {
+            return None;
+        }
+
+        let score = (hits as f32) / (word_count.max(1) as f32);
+        let penalised = hits >= config.min_occurrences && score >= config.threshold;
+        Some(SpamMatch {
+            terms,
+            score,
+            penalty: if penalised { config.penalty } else { 1.0 },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{ AtomicUsize, Ordering::SeqCst };
+
+    fn config(threshold: f32, min_occurrences: i64) -> SpamConfig {
+        SpamConfig { terms_file: None, threshold, min_occurrences, penalty: 0.2, strict: false }
+    }
+
+    fn list(terms: &str) -> SpamList {
+        // Tests run at once, so each list gets a file of its own
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let file = format!("spam_terms_{}_{}", std::process::id(), FILES.fetch_add(1, SeqCst));
+        let path = std::env::temp_dir().join(file);
+        std::fs::write(&path, terms).unwrap();
+        let list = SpamList::load(&path, &[]).unwrap();
+        std::fs::remove_file(path).ok();
+        list
+    }
+
+    fn occurrences(counts: &[(&str, i32)]) -> HashMap<String, i32> {
+        counts
+            .iter()
+            .map(|(word, count)| (word.to_string(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn loads_terms_folded_and_lemmatised_like_queries() {
+        let list = list("# gambling\ncasino bonus\n\n  Viagra  \nCRÈME  \n");
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.words(), ["bonus", "casino", "creme", "viagra"]);
+        assert!(list.assess(&occurrences(&[("creme", 1)]), 100, &config(0.0, 0)).is_some());
+    }
+
+    #[test]
+    fn penalises_from_the_threshold_and_minimum_occurrences_up() {
+        let list = list("casino\n");
+        let config = config(0.02, 3);
+        let penalty = |count, word_count| {
+            list.assess(&occurrences(&[("casino", count)]), word_count, &config).unwrap().penalty
+        };
+
+        // Exactly at the density threshold, then just under it
+        assert_eq!(penalty(4, 200), 0.2);
+        assert_eq!(penalty(4, 201), 1.0);
+        // Dense enough, but only past the minimum number of occurrences
+        assert_eq!(penalty(3, 100), 0.2);
+        assert_eq!(penalty(2, 10), 1.0);
+        // A long legitimate page mentioning the term in passing
+        assert_eq!(penalty(1, 1_000), 1.0);
+        assert_eq!(penalty(5, 1_000), 1.0);
+        // A page with no word count is scored as if it had one word
+        assert_eq!(penalty(3, 0), 0.2);
+    }
+
+    #[test]
+    fn reports_the_matched_terms_and_their_density() {
+        let list = list("casino bonus\ncasino\nviagra\n");
+        let page = occurrences(&[("casino", 10), ("bonus", 4), ("poker", 50)]);
+        let spam = list.assess(&page, 100, &config(0.02, 3)).unwrap();
+        // A multi-word term counts as often as its rarest word
+        assert_eq!(spam.terms, ["casino bonus", "casino"]);
+        assert_eq!(spam.score, 0.14);
+        assert_eq!(spam.penalty, 0.2);
+
+        // Half a term isn't a match
+        let page = occurrences(&[("bonus", 40)]);
+        assert!(list.assess(&page, 100, &config(0.02, 3)).is_none());
+        assert!(list.assess(&HashMap::new(), 100, &config(0.0, 0)).is_none());
+    }
+}
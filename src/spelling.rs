@@ -0,0 +1,146 @@
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::database::{ self, AppError };
+use crate::idf;
+use crate::lemmatise;
+use crate::state::AppState;
+
+/// Longest the lookup of which query terms are indexed may hold up a response
+const LOOKUP_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Words of at most this many letters may only be corrected by one edit; longer ones by two
+const SHORT_WORD_LETTERS: usize = 4;
+
+/// The indexed words a misspelt query term may be corrected to, or fuzzily matched with
+#[derive(Debug, Default)]
+pub struct Vocabulary {
+    /// Each word with its letters, those in the most documents first
+    words: Vec<(String, Vec<char>)>,
+    /// Positions in `words` by letter count, in the same order, so a term is only compared with
+    /// words of about its length
+    by_length: HashMap<usize, Vec<usize>>,
+}
+
+impl Vocabulary {
+    /// Loads the `size` indexed words in the most documents.
+    pub async fn load(pool: &PgPool, size: i64) -> Result<Self, AppError> {
+        let words: Vec<(String, Vec<char>)> = database
+            ::fetch_vocabulary(pool, size).await?
+            .into_iter()
+            .map(|word| {
+                let letters = word.chars().collect();
+                (word, letters)
+            })
+            .collect();
+        let mut by_length: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (position, (_, letters)) in words.iter().enumerate() {
+            by_length.entry(letters.len()).or_default().push(position);
+        }
+        Ok(Vocabulary { words, by_length })
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// The word closest to `term`, counting a swap of two adjacent letters as one edit. Ties go
+    /// to the word in more documents.
+    pub fn closest(&self, term: &str) -> Option<&str> {
+        self.near(term, 1).into_iter().next()
+    }
+
+    /// Up to `limit` words other than `term` itself that are close enough to be a misspelling
+    /// of it, closest first, then those in more documents. Close enough is one edit for words
+    /// of up to `SHORT_WORD_LETTERS` letters, and two for longer ones.
+    pub fn near(&self, term: &str, limit: usize) -> Vec<&str> {
+        let term: Vec<char> = term.chars().collect();
+        let max_distance = if term.len() <= SHORT_WORD_LETTERS { 1 } else { 2 };
+        let mut matches: Vec<(usize, usize)> = vec![];
+        for length in term.len().saturating_sub(max_distance)..=term.len() + max_distance {
+            for &position in self.by_length.get(&length).into_iter().flatten() {
+                let distance = edit_distance(&term, &self.words[position].1);
+                if distance > 0 && distance <= max_distance {
+                    matches.push((distance, position));
+                }
+            }
+        }
+        matches.sort();
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, position)| self.words[position].0.as_str())
+            .collect()
+    }
+}
+
+/// Corrects the query's unindexed terms to their closest vocabulary words, returning the
+/// corrected query, or `None` if there was nothing to correct.
+///
+/// Only plain words are corrected, and only those that are a single term once lemmatised;
+/// operators, quoted phrases and words with digits are kept as typed. Which terms are indexed is
+/// looked up best effort, so nothing is suggested if the lookup is slow.
+pub async fn suggest(state: &AppState, query: &str, keywords: &[String]) -> Option<String> {
+    if state.vocabulary.is_empty() || keywords.is_empty() {
+        return None;
+    }
+    let frequencies = tokio::time
+        ::timeout(LOOKUP_TIMEOUT, idf::lookup(state, keywords)).await
+        .ok()?
+        .ok()?;
+    let unindexed = |term: &String| frequencies.get(term).is_none_or(|&documents| documents == 0);
+
+    let mut corrected = false;
+    let words: Vec<&str> = query
+        .split_whitespace()
+        .map(|word| {
+            if !word.chars().all(char::is_alphabetic) {
+                return word;
+            }
+            let folding_exceptions = &state.config.folding_exceptions;
+            let terms = lemmatise::lemmatise_string(word, folding_exceptions);
+            let typed = lemmatise::split_words(word, folding_exceptions);
+            // The word as typed is tried first, since lemmatising a misspelling can take it
+            // further from the word meant
+            let correction = match (terms.as_slice(), typed.as_slice()) {
+                ([term], [typed]) if keywords.contains(term) && unindexed(term) =>
+                    state.vocabulary.closest(typed).or_else(|| state.vocabulary.closest(term)),
+                _ => None,
+            };
+            match correction {
+                Some(correction) => {
+                    corrected = true;
+                    correction
+                }
+                None => word,
+            }
+        })
+        .collect();
+    corrected.then(|| words.join(" "))
+}
+
+/// The optimal string alignment distance: insertions, deletions, substitutions and swaps of
+/// adjacent letters each count as one edit.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    // Three rows of the distance table: two rows back, the previous row and the current one
+    let mut before_previous: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current: Vec<usize> = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let substitution = previous[j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            current[j] = substitution.min(previous[j] + 1).min(current[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before_previous[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before_previous, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
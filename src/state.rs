@@ -0,0 +1,126 @@
+use arc_swap::ArcSwap;
+use serde_json::Value;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use tokio::sync::{ watch, Semaphore };
+use crate::api_keys::ApiKeys;
+use crate::background_writer::BackgroundWriter;
+use crate::config::Config;
+use crate::database::Tombstones;
+use crate::experiments::Experiment;
+use crate::clicks::{ ClickRates, Interaction };
+use crate::http_client::OutboundClient;
+use crate::retry::DatabaseGuard;
+use crate::idf::DocumentFrequencies;
+use crate::index::IndexStats;
+use crate::lemmatise::LemmaMap;
+use crate::locale::LanguageCode;
+use crate::pagerank::AuthorityScores;
+use crate::popularity::QueryPopularity;
+use crate::rate_limit::RateLimiter;
+use crate::search::SearchOutcome;
+use crate::shadow::ShadowRanker;
+use crate::spam::SpamList;
+use crate::spelling::Vocabulary;
+use crate::stopwords::Stopwords;
+use crate::synonyms::Synonyms;
+use crate::trending::{ LoggedQuery, TrendingQueries };
+use crate::token_cache::TokenCache;
+use crate::ttl_cache::TtlCache;
+
+/// Shared state available to every request handler
+pub struct AppState {
+    pub pool: PgPool,
+    /// Retries and circuit breaking for the pool's queries answering requests
+    pub database: DatabaseGuard,
+    /// Whether the schema has `deleted_at`, so tombstoned pages can be left out
+    pub soft_deletes: bool,
+    /// Page counts and lengths, swapped out by the background refresh when the index changes
+    pub index: ArcSwap<IndexStats>,
+    /// Bumped each time the index changes, for the tasks that follow it
+    pub index_generation: watch::Sender<u64>,
+    /// Whether the schema has `website_keyword_positions`, so `NEAR/n` can check page text
+    pub term_positions: bool,
+    /// Whether `website_keywords` has `original_forms`, so case-sensitive searches can check
+    /// page text
+    pub original_forms: bool,
+    /// Whether `website_links` has `link_text`, so anchor text can rank the pages linked to
+    pub link_text: bool,
+    pub top_domains: HashMap<String, usize>,
+    pub config: Config,
+    pub http_client: OutboundClient,
+    pub token_cache: TokenCache,
+    pub api_keys: ApiKeys,
+    pub rate_limiter: RateLimiter,
+    /// Recent `/links` responses, keyed by URL and page
+    pub links_cache: TtlCache<Value>,
+    /// First page of each `/admin/links/top` report
+    pub top_links_cache: TtlCache<Value>,
+    /// `/site` responses, keyed by domain, sort, and page
+    pub site_cache: TtlCache<Value>,
+    /// `/suggest` responses, keyed by normalised prefix and count
+    pub suggest_cache: TtlCache<Value>,
+    /// Ranked search results, keyed by query and options or by two-tier continuation token
+    pub result_cache: TtlCache<Arc<SearchOutcome>>,
+    /// Formatted query-independent result fields, keyed by webpage id and snippet setting, and
+    /// bounded by their serialised size
+    pub fragment_cache: TtlCache<Value>,
+    /// Bounds how many two-tier refinements run in the background at once
+    pub refinement_permits: Arc<Semaphore>,
+    /// Allows a single URL export to run at a time
+    pub export_permits: Arc<Semaphore>,
+    /// Rolling counts of recent queries, used to pick which ones to keep warm
+    pub popularity: QueryPopularity,
+    /// Link-graph authority scores, empty until the background computation publishes them
+    pub authority: ArcSwap<AuthorityScores>,
+    /// Bumped each time published scores are replaced, which drops the cached results
+    pub authority_generation: watch::Sender<u64>,
+    /// Whether the schema has `website_pagerank`, so authority scores are stored
+    pub stored_pagerank: bool,
+    /// Allows a single PageRank computation to run at a time
+    pub pagerank_permits: Arc<Semaphore>,
+    /// Spam terms, swapped out when the list is reloaded
+    pub spam_list: ArcSwap<SpamList>,
+    pub synonyms: Synonyms,
+    /// Lemma maps for languages other than English, from `LEMMA_FILES`
+    pub lemma_maps: HashMap<LanguageCode, LemmaMap>,
+    pub stopwords: Stopwords,
+    /// Words spelling corrections are drawn from, as of startup
+    pub vocabulary: Vocabulary,
+    /// Searches slower than `slow_query_threshold`, written out in the background
+    pub slow_query_log: BackgroundWriter<Value>,
+    /// Whether searches are being captured for offline replay, toggled by an admin
+    pub capturing: AtomicBool,
+    /// Captured searches, written out in the background
+    pub capture_log: BackgroundWriter<Value>,
+    /// Requests answered, when `ACCESS_LOG` is on, written out in the background
+    pub access_log: BackgroundWriter<Value>,
+    /// Splits searches between ranking variants, if an experiment is configured
+    pub experiment: Option<Experiment>,
+    /// Samples searches to re-rank with the candidate ranking configuration
+    pub shadow: ShadowRanker,
+    /// Comparisons of the served and shadow rankings, written out in the background
+    pub shadow_log: BackgroundWriter<Value>,
+    /// Searches for the query log, written to the database in the background
+    pub query_log: BackgroundWriter<LoggedQuery>,
+    /// Mixed into the hashes of who searched, so they can't be reversed
+    pub query_log_salt: String,
+    /// Trending queries for each window, swapped out by the background aggregation
+    pub trending: ArcSwap<TrendingQueries>,
+    /// Impressions and clicks of search results, counted in the database in the background
+    pub click_log: BackgroundWriter<Interaction>,
+    /// Click-through rates, swapped out by the background reload
+    pub click_rates: ArcSwap<ClickRates>,
+    /// The most common words' document frequencies, reloaded every `IDF_REFRESH_SECS`
+    pub document_frequencies: ArcSwap<DocumentFrequencies>,
+}
+
+impl AppState {
+    /// Which tombstone filter queries should use. Tombstoned pages are only included if an admin
+    /// asked for them, or if the schema can't tell them apart.
+    pub fn tombstones(&self, include_deleted: bool) -> Tombstones {
+        if self.soft_deletes && !include_deleted { Tombstones::Exclude } else { Tombstones::Include }
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::{ HashMap, HashSet };
+use crate::lemmatise;
+use crate::locale::LanguageCode;
+
+/// Words too common to say anything about what a page is about, for each language they're
+/// removed from queries in
+const LISTS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "a", "an", "and", "are", "as", "at", "be", "by", "do", "does", "for", "from", "how",
+            "i", "in", "is", "it", "its", "me", "my", "of", "on", "or", "that", "the", "this",
+            "to", "was", "what", "when", "where", "which", "who", "why", "with",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "au", "aux", "avec", "ce", "ces", "comment", "dans", "de", "des", "du", "elle", "en",
+            "est", "et", "il", "je", "la", "le", "les", "leur", "mon", "ne", "ou", "par", "pas",
+            "pour", "qu", "que", "qui", "quoi", "sa", "se", "son", "sur", "un", "une",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "auf", "aus", "bei", "das", "dem", "den", "der", "des", "die", "ein", "eine", "einer",
+            "es", "für", "ich", "im", "in", "ist", "mit", "nach", "nicht", "oder", "sich", "sie",
+            "und", "von", "was", "wie", "wo", "zu", "zum", "zur",
+        ],
+    ),
+];
+
+/// The built-in stopword lists, normalised the way query terms are
+#[derive(Debug)]
+pub struct Stopwords {
+    languages: HashMap<LanguageCode, HashSet<String>>,
+}
+
+impl Stopwords {
+    /// Builds the lists. Each word is kept both as typed and lemmatised, so it's recognised in
+    /// exact-terms searches too.
+    pub fn new(folding_exceptions: &[char]) -> Self {
+        let languages = LISTS.iter()
+            .filter_map(|(code, words)| {
+                let language = LanguageCode::parse(code)?;
+                let mut terms = HashSet::new();
+                for word in words.iter() {
+                    terms.extend(lemmatise::split_words(word, folding_exceptions));
+                    terms.extend(lemmatise::lemmatise_string(word, folding_exceptions));
+                }
+                Some((language, terms))
+            })
+            .collect();
+        Stopwords { languages }
+    }
+
+    /// Whether `term` is one of `language`'s stopwords.
+    pub fn contains(&self, language: Option<LanguageCode>, term: &str) -> bool {
+        language
+            .and_then(|language| self.languages.get(&language))
+            .is_some_and(|stopwords| stopwords.contains(term))
+    }
+
+    /// How many of `words` are stopwords, for each language with a list.
+    pub fn count(&self, words: &[String]) -> Vec<(LanguageCode, usize)> {
+        self.languages
+            .iter()
+            .map(|(language, stopwords)| {
+                (*language, words.iter().filter(|word| stopwords.contains(*word)).count())
+            })
+            .collect()
+    }
+
+    /// Removes `language`'s stopwords from `terms`, unless every term is one, returning those
+    /// removed. Languages without a list keep every term.
+    pub fn remove(&self, language: Option<LanguageCode>, terms: &mut Vec<String>) -> Vec<String> {
+        let Some(stopwords) = language.and_then(|language| self.languages.get(&language)) else {
+            return vec![];
+        };
+        if terms.iter().all(|term| stopwords.contains(term)) {
+            return vec![];
+        }
+        let mut removed: Vec<String> = vec![];
+        terms.retain(|term| {
+            if !stopwords.contains(term) {
+                return true;
+            }
+            if !removed.contains(term) {
+                removed.push(term.clone());
+            }
+            false
+        });
+        removed
+    }
+}
@@ -0,0 +1,112 @@
+use axum::{
+    extract::{ Extension, Query },
+    http::StatusCode,
+    response::{ IntoResponse, Json, Response },
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::database;
+use crate::empty_state::MIN_TRENDING_SEARCHES;
+use crate::lemmatise;
+use crate::search;
+use crate::state::AppState;
+
+const DEFAULT_COUNT: usize = 8;
+const MAX_COUNT: usize = 20;
+
+/// `GET /suggest?q=prefix&count=8`: completions for a partly typed query. Queries others have
+/// searched for come first, then the query with its last word completed from the index.
+///
+/// Responses are cached per prefix, so keystroke-rate traffic mostly doesn't reach the database.
+pub async fn suggest(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(state): Extension<Arc<AppState>>
+) -> Response {
+    let prefix = match params.get("q") {
+        Some(prefix) => search::normalise_query(prefix),
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "q is required" })),
+            ).into_response();
+        }
+    };
+    let count = params
+        .get("count")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_COUNT)
+        .clamp(1, MAX_COUNT);
+
+    let cache_key = format!("{}|{}", prefix, count);
+    if let Some(cached) = state.suggest_cache.get(&cache_key) {
+        return Json(cached).into_response();
+    }
+
+    // Only queries searched for often enough are suggested, so one person's query isn't shown
+    // to everyone
+    let mut suggestions: Vec<(String, &str)> = state.popularity
+        .with_prefix(&prefix, MIN_TRENDING_SEARCHES, count)
+        .into_iter()
+        .map(|query| (query, "query"))
+        .collect();
+
+    if suggestions.len() < count {
+        match complete_last_word(&state, &prefix, count).await {
+            Ok(completions) => {
+                for completion in completions {
+                    let seen = suggestions.iter().any(|(text, _)| *text == completion);
+                    if !seen && suggestions.len() < count {
+                        suggestions.push((completion, "index"));
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Error fetching completions for {:?}: {}", prefix, e);
+                return e.response("Failed to fetch suggestions");
+            }
+        }
+    }
+
+    let body =
+        json!({
+        "query": prefix,
+        "suggestions": suggestions
+            .iter()
+            .map(|(text, source)| json!({ "text": text, "source": source }))
+            .collect::<Vec<_>>(),
+    });
+    state.suggest_cache.insert(cache_key, body.clone());
+    Json(body).into_response()
+}
+
+/// The prefix with its last word replaced by each of the most common indexed words it starts.
+/// The word is accent-folded like the index, and only completed if it's letters and digits.
+async fn complete_last_word(
+    state: &AppState,
+    prefix: &str,
+    count: usize
+) -> Result<Vec<String>, database::AppError> {
+    let (head, last) = match prefix.rsplit_once(' ') {
+        Some((head, last)) => (Some(head), last),
+        None => (None, prefix),
+    };
+    let word = match lemmatise::split_words(last, &state.config.folding_exceptions).as_slice() {
+        [word] if word.chars().all(char::is_alphanumeric) => word.clone(),
+        _ => {
+            return Ok(vec![]);
+        }
+    };
+
+    let words = state.database
+        .run(|| database::fetch_words_with_prefix(&state.pool, &word, count as i64)).await?;
+    Ok(
+        words
+            .into_iter()
+            .map(|word| match head {
+                Some(head) => format!("{} {}", head, word),
+                None => word,
+            })
+            .collect()
+    )
+}
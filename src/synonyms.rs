@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ BufRead, BufReader };
+use std::path::Path;
+use crate::lemmatise;
+
+/// Most synonyms a single query term expands to, so a large group can't crowd out the query
+const MAX_SYNONYMS_PER_TERM: usize = 3;
+
+/// Groups of words that mean the same, searched for in place of each other
+#[derive(Debug, Default)]
+pub struct Synonyms {
+    /// Each lemmatised word with the other words of its groups, in file order
+    words: HashMap<String, Vec<String>>,
+}
+
+impl Synonyms {
+    /// Loads synonym groups from a file, one group of comma-separated words per line, so
+    /// `car, automobile, motorcar` makes each of them a synonym of the others. Lines starting
+    /// with `#` are comments.
+    ///
+    /// Words are lemmatised and folded the same way queries are, so they match stored keywords.
+    /// A word of several terms can't stand in for a single one, so those are skipped.
+    pub fn load<P: AsRef<Path>>(filename: P, folding_exceptions: &[char]) -> Result<Self, std::io::Error> {
+        let file = File::open(filename)?;
+        let reader = BufReader::new(file);
+        let mut words: HashMap<String, Vec<String>> = HashMap::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut group: Vec<String> = vec![];
+            for word in line.split(',') {
+                if let [lemma] = lemmatise::lemmatise_string(word, folding_exceptions).as_slice() {
+                    if !group.contains(lemma) {
+                        group.push(lemma.clone());
+                    }
+                }
+            }
+            for word in &group {
+                let synonyms = words.entry(word.clone()).or_default();
+                for other in &group {
+                    if other != word && !synonyms.contains(other) {
+                        synonyms.push(other.clone());
+                    }
+                }
+            }
+        }
+        Ok(Synonyms { words })
+    }
+
+    /// Number of words with at least one synonym.
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    /// Pairs each of `terms` with up to `MAX_SYNONYMS_PER_TERM` of its synonyms, as
+    /// `(term, synonym)`. Synonyms that are query terms themselves are left out, since the query
+    /// already searches for them.
+    pub fn expand(&self, terms: &[String]) -> Vec<(String, String)> {
+        let mut expansions = vec![];
+        for term in terms {
+            if expansions.iter().any(|(expanded, _)| expanded == term) {
+                continue;
+            }
+            let synonyms = self.words.get(term).into_iter().flatten();
+            for synonym in synonyms.filter(|synonym| !terms.contains(synonym)).take(MAX_SYNONYMS_PER_TERM) {
+                expansions.push((term.clone(), synonym.clone()));
+            }
+        }
+        expansions
+    }
+}
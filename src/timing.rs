@@ -1,28 +1,187 @@
 use std::time::{ Duration, Instant };
 use serde_json::json;
 
+/// A stage of handling a request whose duration is reported in `time_taken`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    TotalSearchFunction,
+    Lemmatisation,
+    InitialDatabaseQuery,
+    TfIdfCalculation,
+    SemanticReranking,
+    LinkFetching,
+    ResultsFormatting,
+    TurnstileValidation,
+}
+
+impl Phase {
+    /// Every phase, in the order they're reported
+    const ALL: [Phase; 8] = [
+        Phase::TotalSearchFunction,
+        Phase::Lemmatisation,
+        Phase::InitialDatabaseQuery,
+        Phase::TfIdfCalculation,
+        Phase::SemanticReranking,
+        Phase::LinkFetching,
+        Phase::ResultsFormatting,
+        Phase::TurnstileValidation,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Phase::TotalSearchFunction => "total_search_function",
+            Phase::Lemmatisation => "lemmatisation",
+            Phase::InitialDatabaseQuery => "initial_database_query",
+            Phase::TfIdfCalculation => "tf_idf_calculation",
+            Phase::SemanticReranking => "semantic_reranking",
+            Phase::LinkFetching => "link_fetching",
+            Phase::ResultsFormatting => "results_formatting",
+            Phase::TurnstileValidation => "turnstile_validation",
+        }
+    }
+}
+
+/// When a request started and how long each of its phases took
 #[derive(Default, Clone)]
 pub struct RequestTiming {
     pub start: Option<Instant>,
-    pub lemmatisation: Option<Duration>,
-    pub initial_database_query: Option<Duration>,
-    pub tf_idf_calculation: Option<Duration>,
-    pub link_fetching: Option<Duration>,
-    pub results_formatting: Option<Duration>,
-    pub total_search_function: Option<Duration>,
-    pub turnstile_validation: Option<Duration>,
+    phases: Vec<(Phase, Duration)>,
+}
+
+impl RequestTiming {
+    /// Starts timing a request now.
+    pub fn started() -> Self {
+        RequestTiming {
+            start: Some(Instant::now()),
+            phases: vec![],
+        }
+    }
+
+    /// Records how long a phase took, replacing any earlier duration recorded for it.
+    pub fn record(&mut self, phase: Phase, duration: Duration) {
+        match self.phases.iter_mut().find(|(recorded, _)| *recorded == phase) {
+            Some((_, recorded_duration)) => {
+                *recorded_duration = duration;
+            }
+            None => self.phases.push((phase, duration)),
+        }
+    }
+
+    /// Returns how long a phase took, or zero if it wasn't recorded.
+    pub fn phase(&self, phase: Phase) -> Duration {
+        self.phases
+            .iter()
+            .find(|(recorded, _)| *recorded == phase)
+            .map(|(_, duration)| *duration)
+            .unwrap_or_default()
+    }
+
+    /// Returns the time since the request started, or zero if its start wasn't recorded.
+    pub fn elapsed(&self) -> Duration {
+        self.start.map(|start| start.elapsed()).unwrap_or_default()
+    }
 }
 
 pub fn format_timing_info(timing: &RequestTiming, total_request_time: Duration) -> serde_json::Value {
-    json!({
-        "total_request": format!("{:?}", total_request_time),
-        "total_search_function": format!("{:?}", timing.total_search_function.unwrap_or_default()),
-        "lemmatisation": format!("{:?}", timing.lemmatisation.unwrap_or_default()),
-        "initial_database_query": format!("{:?}", timing.initial_database_query.unwrap_or_default()),
-        "tf_idf_calculation": format!("{:?}", timing.tf_idf_calculation.unwrap_or_default()),
-        "link_fetching": format!("{:?}", timing.link_fetching.unwrap_or_default()),
-        "results_formatting": format!("{:?}", timing.results_formatting.unwrap_or_default()),
-        "turnstile_validation": format!("{:?}", timing.turnstile_validation.unwrap_or_default()),
-        "other_operations": format!("{:?}", total_request_time - timing.total_search_function.unwrap_or_default()),
-    })
+    // The total is measured separately from the phases, so never report less than the search
+    // itself took
+    let total_search_function = timing.phase(Phase::TotalSearchFunction);
+    let total_request_time = total_request_time.max(total_search_function);
+
+    let mut info = json!({ "total_request": format!("{:?}", total_request_time) });
+    for phase in Phase::ALL {
+        info[phase.name()] = json!(format!("{:?}", timing.phase(phase)));
+    }
+    info["other_operations"] = json!(
+        format!("{:?}", total_request_time.saturating_sub(total_search_function))
+    );
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `time_taken` with every phase and the totals, so always well-formed
+    fn assert_well_formed(info: &serde_json::Value) {
+        let mut keys: Vec<&String> = info.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "initial_database_query",
+                "lemmatisation",
+                "link_fetching",
+                "other_operations",
+                "results_formatting",
+                "semantic_reranking",
+                "tf_idf_calculation",
+                "total_request",
+                "total_search_function",
+                "turnstile_validation",
+            ]
+        );
+        assert!(info.as_object().unwrap().values().all(serde_json::Value::is_string));
+    }
+
+    #[test]
+    fn reports_zero_for_a_request_never_timed() {
+        let timing = RequestTiming::default();
+        assert_eq!(timing.elapsed(), Duration::ZERO);
+        assert_eq!(timing.phase(Phase::LinkFetching), Duration::ZERO);
+
+        let info = format_timing_info(&timing, timing.elapsed());
+        assert_well_formed(&info);
+        assert_eq!(info["total_request"], "0ns");
+        assert_eq!(info["other_operations"], "0ns");
+    }
+
+    #[test]
+    fn never_reports_a_search_longer_than_the_request() {
+        // Measured in another layer, or with no start at all, the total can be the shorter
+        let mut timing = RequestTiming::default();
+        timing.record(Phase::TotalSearchFunction, Duration::from_millis(30));
+        timing.record(Phase::Lemmatisation, Duration::from_millis(45));
+
+        for total in [Duration::ZERO, Duration::from_millis(10), timing.elapsed()] {
+            let info = format_timing_info(&timing, total);
+            assert_well_formed(&info);
+            assert_eq!(info["total_request"], "30ms");
+            assert_eq!(info["total_search_function"], "30ms");
+            assert_eq!(info["other_operations"], "0ns");
+            // A phase is reported as recorded, even if it's longer than the totals
+            assert_eq!(info["lemmatisation"], "45ms");
+        }
+    }
+
+    #[test]
+    fn reports_the_rest_of_the_request_as_other_operations() {
+        let mut timing = RequestTiming::started();
+        timing.record(Phase::TotalSearchFunction, Duration::from_millis(20));
+        timing.record(Phase::TotalSearchFunction, Duration::from_millis(25));
+
+        let info = format_timing_info(&timing, Duration::from_millis(40));
+        assert_well_formed(&info);
+        assert_eq!(info["total_request"], "40ms");
+        // Recording a phase again replaces it
+        assert_eq!(info["total_search_function"], "25ms");
+        assert_eq!(info["other_operations"], "15ms");
+        assert_eq!(info["turnstile_validation"], "0ns");
+    }
+
+    #[test]
+    fn tolerates_extreme_durations() {
+        let mut timing = RequestTiming::default();
+        timing.record(Phase::TotalSearchFunction, Duration::MAX);
+        timing.record(Phase::InitialDatabaseQuery, Duration::MAX);
+
+        let info = format_timing_info(&timing, Duration::from_nanos(1));
+        assert_well_formed(&info);
+        assert_eq!(info["total_request"], info["total_search_function"]);
+        assert_eq!(info["other_operations"], "0ns");
+
+        let info = format_timing_info(&RequestTiming::default(), Duration::MAX);
+        assert_well_formed(&info);
+        assert_eq!(info["other_operations"], info["total_request"]);
+    }
 }
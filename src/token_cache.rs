@@ -1,34 +1,123 @@
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{ Hash, Hasher };
+use std::sync::Mutex;
 use std::time::{ SystemTime, UNIX_EPOCH };
 
+const SHARD_COUNT: usize = 16;
+
+/// How long a validated token is accepted without asking Turnstile again
+const TOKEN_LIFETIME_SECS: u64 = 120;
+
+/// Recently validated Turnstile tokens, bound to the client identity that presented them
+/// (see `client_ip::client_identity`).
+///
+/// Sharded by token, and locked only for the lookup or insert itself, so checking one client's
+/// token never waits on another's, and never on a Turnstile call.
 pub struct TokenCache {
-    tokens: HashMap<String, (u64, String)>,
+    shards: Vec<Mutex<HashMap<String, (u64, String)>>>,
 }
 
 impl TokenCache {
     pub fn new() -> Self {
         TokenCache {
-            tokens: HashMap::new(),
+            shards: (0..SHARD_COUNT).map(|_| Mutex::new(HashMap::new())).collect(),
         }
     }
 
-    pub fn is_valid(&mut self, token: &str, ip: &str) -> bool {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        if let Some((timestamp, stored_ip)) = self.tokens.get(token) {
-            if now - timestamp <= 120 && stored_ip == ip {
-                return true;
-            }
+    pub fn is_valid(&self, token: &str, client: &str) -> bool {
+        self.is_valid_at(token, client, now_secs())
+    }
+
+    /// Records `token` as validated for `client`, dropping the expired tokens that share its
+    /// shard.
+    pub fn add_token(&self, token: String, client: String) {
+        self.add_token_at(token, client, now_secs());
+    }
+
+    fn is_valid_at(&self, token: &str, client: &str, now: u64) -> bool {
+        let shard = self.shards[shard_index(token)].lock().unwrap();
+        match shard.get(token) {
+            Some((timestamp, stored_client)) =>
+                now.saturating_sub(*timestamp) <= TOKEN_LIFETIME_SECS && stored_client == client,
+            None => false,
         }
-        false
     }
 
-    pub fn add_token(&mut self, token: String, ip: String) {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        self.tokens.insert(token, (now, ip));
+    fn add_token_at(&self, token: String, client: String, now: u64) {
+        let mut shard = self.shards[shard_index(&token)].lock().unwrap();
+        shard.retain(|_, &mut (timestamp, _)| now.saturating_sub(timestamp) <= TOKEN_LIFETIME_SECS);
+        shard.insert(token, (now, client));
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn shard_index(token: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    token.hash(&mut hasher);
+    (hasher.finish() as usize) % SHARD_COUNT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_a_token_only_from_the_client_that_validated_it() {
+        let cache = TokenCache::new();
+        cache.add_token_at("token".to_string(), "203.0.113.7".to_string(), 1_000);
+
+        assert!(cache.is_valid_at("token", "203.0.113.7", 1_000));
+        assert!(!cache.is_valid_at("token", "198.51.100.1", 1_000));
+        assert!(!cache.is_valid_at("other", "203.0.113.7", 1_000));
+    }
+
+    #[test]
+    fn expires_tokens_after_their_lifetime() {
+        let cache = TokenCache::new();
+        cache.add_token_at("token".to_string(), "client".to_string(), 1_000);
+
+        assert!(cache.is_valid_at("token", "client", 1_000 + TOKEN_LIFETIME_SECS));
+        assert!(!cache.is_valid_at("token", "client", 1_001 + TOKEN_LIFETIME_SECS));
+        // A clock that went backwards doesn't make a token expire
+        assert!(cache.is_valid_at("token", "client", 900));
     }
 
-    pub fn clean_old_tokens(&mut self) {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        self.tokens.retain(|_, &mut (timestamp, _)| now - timestamp <= 120);
+    #[test]
+    fn adding_a_token_drops_expired_ones() {
+        let cache = TokenCache::new();
+        for i in 0..(SHARD_COUNT * 8) {
+            cache.add_token_at(format!("old-{}", i), "client".to_string(), 1_000);
+        }
+        assert_eq!(cache.len(), SHARD_COUNT * 8);
+
+        // Every shard gets a fresh token eventually, and drops its old ones when it does
+        let later = 1_001 + TOKEN_LIFETIME_SECS;
+        for i in 0..(SHARD_COUNT * 8) {
+            cache.add_token_at(format!("new-{}", i), "client".to_string(), later);
+        }
+        assert_eq!(cache.len(), SHARD_COUNT * 8);
+        assert!(cache.is_valid_at("new-0", "client", later));
+    }
+
+    #[test]
+    fn revalidating_a_token_rebinds_it() {
+        let cache = TokenCache::new();
+        cache.add_token_at("token".to_string(), "first".to_string(), 1_000);
+        cache.add_token_at("token".to_string(), "second".to_string(), 1_010);
+
+        assert!(!cache.is_valid_at("token", "first", 1_010));
+        assert!(cache.is_valid_at("token", "second", 1_010));
     }
 }
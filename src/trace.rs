@@ -0,0 +1,162 @@
+use serde_json::{ Value, json };
+
+/// How a search request was answered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchVariant {
+    /// Every candidate was fetched and scored
+    #[default]
+    Full,
+    /// Only the best pre-ranked candidates were scored, as the first page of a two-tier search
+    TwoTier,
+    /// Candidates for several weighted queries were scored together by `POST /blend`
+    Blended,
+}
+
+impl SearchVariant {
+    pub fn name(self) -> &'static str {
+        match self {
+            SearchVariant::Full => "full",
+            SearchVariant::TwoTier => "two_tier",
+            SearchVariant::Blended => "blended",
+        }
+    }
+}
+
+/// Which cache, if any, served the results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheOutcome {
+    #[default]
+    Miss,
+    /// Results for the same query and options were cached
+    ResultHit,
+    /// A finished two-tier refinement was picked up with its continuation token
+    ContinuationHit,
+}
+
+/// Whether a two-tier search scheduled a background refinement
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Refinement {
+    #[default]
+    NotNeeded,
+    Scheduled,
+    /// Too many refinements were already running
+    Saturated,
+}
+
+/// What the search pipeline did for one request, kept as plain counters so it costs nothing to
+/// collect on every search
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PipelineTrace {
+    pub variant: SearchVariant,
+    pub cache: CacheOutcome,
+    pub refinement: Refinement,
+    /// Query terms after lemmatisation and the keyword budget
+    pub query_terms: usize,
+    /// Distinct query terms left out by the keyword budget
+    pub keywords_dropped: usize,
+    pub candidates_fetched: usize,
+    /// Whether the candidates fetched were every page matching the query
+    pub candidates_complete: bool,
+    /// Whether the fetch left out pages off the `site:` domain or containing an excluded term
+    pub filtered_in_fetch: bool,
+    /// Candidates dropped for not being on the `site:` domain
+    pub off_site: usize,
+    /// Candidates dropped for not having a `title:` or `desc:` term in that field
+    pub not_in_field: usize,
+    /// Candidates dropped for not matching the boolean expression
+    pub not_matching: usize,
+    /// Candidates dropped for not containing the query's words in the case typed, when the
+    /// search was case-sensitive
+    pub wrong_case: usize,
+    /// Candidates dropped for not matching the query fully
+    pub below_threshold: usize,
+    /// Candidates dropped for not containing the query verbatim, when only those were wanted
+    pub not_verbatim: usize,
+    /// Ranked pages containing every quoted phrase in order, which were boosted
+    pub phrase_matches: usize,
+    /// Ranked pages with every `NEAR/n` pair close enough together, which were boosted
+    pub proximity_matches: usize,
+    /// Ranked pages raised for having the query's terms close together
+    pub term_closeness_boosted: usize,
+    /// Ranked pages multiplied for having the query's words in order
+    pub verbatim_boosted: usize,
+    /// Ranked pages downranked as thin, keyword-stuffed or without a description
+    pub quality_penalised: usize,
+    /// Ranked pages raised for matching the caller's preferred domains or topics
+    pub preference_boosted: usize,
+    /// Ranked pages raised for query terms in the anchor text of links to them
+    pub anchor_text_boosted: usize,
+    /// Ranked pages matching some query terms only through synonyms, which were downweighted
+    pub synonym_matches: usize,
+    /// Query terms left out of the lookup as stopwords
+    pub stopwords_removed: usize,
+    /// Ranked pages matching some fuzzy terms only through similarly spelt words, which were
+    /// downweighted
+    pub fuzzy_matches: usize,
+    /// Ranked pages found only through words from pages linking to the query's matches, which
+    /// were downweighted
+    pub link_expansion_matches: usize,
+    pub spam_penalised: usize,
+    pub spam_excluded: usize,
+    /// Whether published link-authority scores took part in ordering
+    pub authority_applied: bool,
+    /// Results reordered by the similarity of their embeddings to the query's
+    pub semantic_reranked: usize,
+    /// Results folded into a better ranked near-duplicate of them
+    pub duplicates_collapsed: usize,
+    /// Results held back by `max_per_domain`, grouped under their site's best result
+    pub domain_capped: usize,
+    /// Ranked pages left out by the requested number of results
+    pub truncated: usize,
+    pub returned: usize,
+}
+
+impl PipelineTrace {
+    pub fn to_json(self) -> Value {
+        json!({
+            "variant": self.variant.name(),
+            "cache": match self.cache {
+                CacheOutcome::Miss => "miss",
+                CacheOutcome::ResultHit => "result",
+                CacheOutcome::ContinuationHit => "continuation",
+            },
+            "refinement": match self.refinement {
+                Refinement::NotNeeded => "not_needed",
+                Refinement::Scheduled => "scheduled",
+                Refinement::Saturated => "saturated",
+            },
+            "query_terms": self.query_terms,
+            "keywords_dropped": self.keywords_dropped,
+            "candidates_fetched": self.candidates_fetched,
+            "candidates_complete": self.candidates_complete,
+            "filtered_in_fetch": self.filtered_in_fetch,
+            "dropped": {
+                "off_site": self.off_site,
+                "not_in_field": self.not_in_field,
+                "not_matching": self.not_matching,
+                "wrong_case": self.wrong_case,
+                "below_threshold": self.below_threshold,
+                "not_verbatim": self.not_verbatim,
+                "spam": self.spam_excluded,
+                "truncated": self.truncated,
+            },
+            "phrase_matches": self.phrase_matches,
+            "proximity_matches": self.proximity_matches,
+            "term_closeness_boosted": self.term_closeness_boosted,
+            "verbatim_boosted": self.verbatim_boosted,
+            "quality_penalised": self.quality_penalised,
+            "preference_boosted": self.preference_boosted,
+            "anchor_text_boosted": self.anchor_text_boosted,
+            "synonym_matches": self.synonym_matches,
+            "stopwords_removed": self.stopwords_removed,
+            "fuzzy_matches": self.fuzzy_matches,
+            "link_expansion_matches": self.link_expansion_matches,
+            "spam_penalised": self.spam_penalised,
+            "authority_applied": self.authority_applied,
+            "semantic_reranked": self.semantic_reranked,
+            "duplicates_collapsed": self.duplicates_collapsed,
+            "domain_capped": self.domain_capped,
+            "returned": self.returned,
+        })
+    }
+}
@@ -0,0 +1,262 @@
+use axum::{
+    extract::{ Extension, Query },
+    http::{ HeaderMap, StatusCode },
+    response::{ IntoResponse, Json, Response },
+};
+use chrono::{ DateTime, Utc };
+use serde_json::json;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::collections::hash_map::{ DefaultHasher, RandomState };
+use std::hash::{ BuildHasher, Hash, Hasher };
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use crate::background_writer::Sink;
+use crate::client_ip::client_identity;
+use crate::conditional::ConditionalJson;
+use crate::database;
+use crate::empty_state::MIN_TRENDING_SEARCHES;
+use crate::state::AppState;
+use crate::units;
+
+const DEFAULT_COUNT: usize = 10;
+
+/// A search kept in the query log. Who searched is only kept as a salted hash of their client
+/// identity, enough to count distinct searchers.
+pub struct LoggedQuery {
+    pub query: String,
+    pub ip_hash: String,
+    pub searched_at: DateTime<Utc>,
+}
+
+/// Writes logged searches to the `query_log` table
+pub struct QueryLogSink {
+    pool: PgPool,
+}
+
+impl QueryLogSink {
+    pub fn new(pool: PgPool) -> Self {
+        QueryLogSink { pool }
+    }
+}
+
+impl Sink<LoggedQuery> for QueryLogSink {
+    async fn write(&mut self, batch: &[LoggedQuery]) -> Result<(), String> {
+        database::insert_logged_queries(&self.pool, batch).await.map_err(|e| e.to_string())
+    }
+}
+
+/// A query's counts over one window
+#[derive(Debug, Clone)]
+pub struct TrendingQuery {
+    pub query: String,
+    pub searches: i64,
+    /// Distinct client identities that searched for it
+    pub searchers: i64,
+}
+
+/// The most searched queries over each configured window, as of the last aggregation
+#[derive(Debug, Default)]
+pub struct TrendingQueries {
+    /// `None` until the first aggregation finishes
+    pub computed_at: Option<DateTime<Utc>>,
+    pub windows: Vec<(Duration, Vec<TrendingQuery>)>,
+}
+
+/// A salt for hashing client identities that lasts as long as the process, for when
+/// `QUERY_LOG_SALT` isn't set.
+pub fn random_salt() -> String {
+    format!("{:016x}", RandomState::new().hash_one(0_u8))
+}
+
+/// Queues a search for the query log, if it's on. `query` should already be normalised, so
+/// searches differing only in spacing or case are counted together.
+pub fn record(state: &AppState, query: &str, client: Option<IpAddr>) {
+    let Some(client) = client else {
+        return;
+    };
+    if !state.config.trending.enabled || query.is_empty() {
+        return;
+    }
+    // Hashed with a secret salt, so the log can't be matched against a list of addresses
+    let mut hasher = DefaultHasher::new();
+    state.query_log_salt.hash(&mut hasher);
+    client_identity(client, &state.config.client_identity).hash(&mut hasher);
+    state.query_log.send(LoggedQuery {
+        query: query.to_string(),
+        ip_hash: format!("{:016x}", hasher.finish()),
+        searched_at: Utc::now(),
+    });
+}
+
+/// Recounts the trending queries of each window from the query log every
+/// `TRENDING_REFRESH_SECS` until shutdown, so `GET /trending` never has to.
+///
+/// Rows older than the longest window are deleted as they're no longer counted, which keeps the
+/// log, and so each recount, bounded by the traffic of that window.
+pub async fn run_aggregation(state: Arc<AppState>, mut shutdown: watch::Receiver<bool>) {
+    let config = state.config.trending.clone();
+    if !config.enabled || config.windows.is_empty() {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(config.refresh);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => {
+                break;
+            }
+        }
+
+        let now = Utc::now();
+        let mut windows = vec![];
+        for &window in &config.windows {
+            let since = window_start(now, window);
+            match
+                database::fetch_trending_queries(
+                    &state.pool,
+                    since,
+                    MIN_TRENDING_SEARCHES as i64,
+                    config.count as i64
+                ).await
+            {
+                Ok(queries) => windows.push((window, queries)),
+                Err(e) => {
+                    eprintln!("Error counting trending queries: {}", e);
+                    break;
+                }
+            }
+        }
+        // A partial recount would make some windows older than others, so it's not published
+        if windows.len() < config.windows.len() {
+            continue;
+        }
+        state.trending.store(Arc::new(TrendingQueries { computed_at: Some(now), windows }));
+
+        let longest = config.windows.iter().max().copied().unwrap_or_default();
+        let cutoff = window_start(now, longest);
+        if let Err(e) = database::delete_logged_queries_before(&state.pool, cutoff).await {
+            eprintln!("Error pruning the query log: {}", e);
+        }
+    }
+}
+
+/// When a window ending at `now` starts. One reaching back before the Unix epoch, long before
+/// anything was logged, starts at the epoch instead, so it can neither overflow nor fall outside
+/// the range of a Postgres timestamp.
+fn window_start(now: DateTime<Utc>, window: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(window)
+        .ok()
+        .and_then(|window| now.checked_sub_signed(window))
+        .unwrap_or(DateTime::UNIX_EPOCH)
+        .max(DateTime::UNIX_EPOCH)
+}
+
+/// `GET /trending?window=24h&count=10`: the queries searched for by the most people over one of
+/// the `TRENDING_WINDOWS`, the first of them by default. Only queries at least
+/// `MIN_TRENDING_SEARCHES` people searched for are listed, so one person's query isn't shown to
+/// everyone.
+///
+/// Once the first aggregation has finished, answered conditionally: last modified when the
+/// aggregation ran, and cacheable until the next is due.
+pub async fn trending(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(state): Extension<Arc<AppState>>,
+    headers: HeaderMap
+) -> Response {
+    let config = &state.config.trending;
+    if !config.enabled {
+        return error_response(StatusCode::NOT_FOUND, "Query logging is off");
+    }
+
+    let window = match params.get("window") {
+        Some(window) =>
+            match units::parse_duration(window, None) {
+                Ok(window) if config.windows.contains(&window) => window,
+                _ => {
+                    let windows: Vec<String> = config.windows
+                        .iter()
+                        .map(|&window| units::format_duration(window))
+                        .collect();
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        &format!("window must be one of {}", windows.join(", "))
+                    );
+                }
+            }
+        None =>
+            match config.windows.first() {
+                Some(&window) => window,
+                None => {
+                    return error_response(StatusCode::NOT_FOUND, "No trending windows are configured");
+                }
+            }
+    };
+    let count = params
+        .get("count")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_COUNT)
+        .clamp(1, config.count.max(1));
+
+    let snapshot = state.trending.load();
+    let queries = snapshot.windows
+        .iter()
+        .find(|(computed, _)| *computed == window)
+        .map(|(_, queries)| queries.as_slice())
+        .unwrap_or_default();
+    let body =
+        json!({
+        "window": units::format_duration(window),
+        "computed_at": snapshot.computed_at.map(|time| time.to_rfc3339()),
+        "queries": queries
+            .iter()
+            .take(count)
+            .map(|query| {
+                json!({
+                    "query": query.query,
+                    "searches": query.searches,
+                    "searchers": query.searchers,
+                })
+            })
+            .collect::<Vec<_>>(),
+    });
+    match snapshot.computed_at {
+        Some(computed_at) => {
+            ConditionalJson::new(&body, computed_at.into(), config.refresh).respond(&headers)
+        }
+        None => Json(body).into_response(),
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_start_their_length_before_now() {
+        let now = DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z").unwrap().to_utc();
+        let start = window_start(now, Duration::from_secs(24 * 60 * 60));
+        assert_eq!(start.to_rfc3339(), "2024-05-09T12:00:00+00:00");
+        assert_eq!(window_start(now, Duration::ZERO), now);
+    }
+
+    #[test]
+    fn windows_reaching_back_too_far_start_at_the_epoch() {
+        let now = DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z").unwrap().to_utc();
+        // Before the epoch, but within chrono's range
+        let century = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+        assert_eq!(window_start(now, century), DateTime::UNIX_EPOCH);
+        // Beyond what chrono can subtract
+        let ages = Duration::from_secs(1 << 50);
+        assert_eq!(window_start(now, ages), DateTime::UNIX_EPOCH);
+        // Beyond what chrono can represent as a duration at all
+        assert_eq!(window_start(now, Duration::MAX), DateTime::UNIX_EPOCH);
+    }
+}
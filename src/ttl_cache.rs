@@ -0,0 +1,336 @@
+use serde_json::{ Value, json };
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::{ Duration, Instant };
+
+/// A small keyed cache whose entries expire after a fixed time-to-live
+pub struct TtlCache<V> {
+    ttl: Duration,
+    /// Most total weight held at once; each entry weighs 1 unless the cache has a weigher
+    capacity: usize,
+    weigher: fn(&V) -> usize,
+    entries: Mutex<Entries<V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Entries<V> {
+    map: HashMap<String, (Instant, V, usize)>,
+    weight: usize,
+}
+
+/// A live cache entry, as returned for inspection
+pub struct CacheEntry<V> {
+    pub key: String,
+    pub age: Duration,
+    /// Time left before the entry expires
+    pub remaining: Duration,
+    /// What the entry counts for against the capacity
+    pub weight: usize,
+    pub value: V,
+}
+
+impl<V: Clone> TtlCache<V> {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        TtlCache::weighted(ttl, capacity, |_| 1)
+    }
+
+    /// A cache bounded by the total `weigher` weight of its entries rather than their number.
+    /// An entry heavier than the whole capacity isn't stored.
+    pub fn weighted(ttl: Duration, capacity: usize, weigher: fn(&V) -> usize) -> Self {
+        TtlCache {
+            ttl,
+            capacity,
+            weigher,
+            entries: Mutex::new(Entries { map: HashMap::new(), weight: 0 }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cached value for `key` if it hasn't expired.
+    pub fn get(&self, key: &str) -> Option<V> {
+        let entries = self.entries.lock().unwrap();
+        let value = entries.map
+            .get(key)
+            .filter(|(inserted, _, _)| inserted.elapsed() < self.ttl)
+            .map(|(_, value, _)| value.clone());
+        let counter = if value.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    /// Returns the unexpired entries whose keys start with `prefix`, sorted by key, without
+    /// counting as lookups.
+    pub fn entries(&self, prefix: &str) -> Vec<CacheEntry<V>> {
+        let entries = self.entries.lock().unwrap();
+        let mut matching: Vec<CacheEntry<V>> = entries.map
+            .iter()
+            .filter(|(key, (inserted, _, _))| {
+                key.starts_with(prefix) && inserted.elapsed() < self.ttl
+            })
+            .map(|(key, (inserted, value, weight))| {
+                CacheEntry {
+                    key: key.clone(),
+                    age: inserted.elapsed(),
+                    remaining: self.ttl.saturating_sub(inserted.elapsed()),
+                    weight: *weight,
+                    value: value.clone(),
+                }
+            })
+            .collect();
+        matching.sort_by(|a, b| a.key.cmp(&b.key));
+        matching
+    }
+
+    /// Drops the entry for `key`, returning whether there was one.
+    pub fn remove(&self, key: &str) -> bool {
+        self.entries.lock().unwrap().remove(key)
+    }
+
+    /// Drops every entry, e.g. after the data they were derived from has changed, and returns
+    /// how many there were. Hit and miss counts start over, since they described the old
+    /// entries.
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let removed = entries.map.len();
+        entries.map.clear();
+        entries.weight = 0;
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        removed
+    }
+
+    /// Entry count and total weight, and lookups that hit and missed since startup or the last
+    /// `clear`.
+    pub fn stats(&self) -> Value {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let entries = self.entries.lock().unwrap();
+        json!({
+            "entries": entries.map.len(),
+            "weight": entries.weight,
+            "capacity": self.capacity,
+            "hits": hits,
+            "misses": misses,
+            "hit_ratio": if hits + misses > 0 {
+                Some((hits as f64) / ((hits + misses) as f64))
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Stores a value, evicting expired entries (and then the oldest ones) when full.
+    pub fn insert(&self, key: String, value: V) {
+        let weight = (self.weigher)(&value);
+        if weight > self.capacity {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&key);
+
+        if entries.weight + weight > self.capacity {
+            let ttl = self.ttl;
+            let mut expired = 0;
+            entries.map.retain(|_, (inserted, _, weight)| {
+                let live = inserted.elapsed() < ttl;
+                if !live {
+                    expired += *weight;
+                }
+                live
+            });
+            entries.weight -= expired;
+        }
+        while entries.weight + weight > self.capacity {
+            let oldest = entries.map
+                .iter()
+                .min_by_key(|(_, (inserted, _, _))| *inserted)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => {
+                    entries.remove(&oldest);
+                }
+                None => {
+                    break;
+                }
+            }
+        }
+
+        entries.weight += weight;
+        entries.map.insert(key, (Instant::now(), value, weight));
+    }
+}
+
+impl<V> Entries<V> {
+    fn remove(&mut self, key: &str) -> bool {
+        match self.map.remove(key) {
+            Some((_, _, weight)) => {
+                self.weight -= weight;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_entries_after_the_ttl() {
+        let cache = TtlCache::new(Duration::from_millis(50), 10);
+        cache.insert("key".to_string(), 1);
+        assert_eq!(cache.get("key"), Some(1));
+        assert_eq!(cache.get("other"), None);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get("key"), None);
+    }
+
+    #[test]
+    fn evicts_the_oldest_entry_when_full() {
+        let cache = TtlCache::new(Duration::from_secs(60), 2);
+        cache.insert("first".to_string(), 1);
+        std::thread::sleep(Duration::from_millis(2));
+        cache.insert("second".to_string(), 2);
+        std::thread::sleep(Duration::from_millis(2));
+
+        // Replacing an entry doesn't make room
+        cache.insert("second".to_string(), 20);
+        assert_eq!(cache.get("first"), Some(1));
+        assert_eq!(cache.get("second"), Some(20));
+
+        cache.insert("third".to_string(), 3);
+        assert_eq!(cache.get("first"), None);
+        assert_eq!(cache.get("second"), Some(20));
+        assert_eq!(cache.get("third"), Some(3));
+    }
+
+    #[test]
+    fn evicts_expired_entries_before_live_ones() {
+        let cache = TtlCache::new(Duration::from_millis(50), 3);
+        cache.insert("old".to_string(), 1);
+        cache.insert("older".to_string(), 1);
+        std::thread::sleep(Duration::from_millis(60));
+        cache.insert("live".to_string(), 2);
+        cache.insert("new".to_string(), 3);
+        // Both expired entries went at once, leaving room to spare
+        assert_eq!(cache.stats()["entries"], 2);
+        assert_eq!(cache.get("live"), Some(2));
+        assert_eq!(cache.get("new"), Some(3));
+    }
+
+    #[test]
+    fn evicts_by_weight_until_a_heavier_entry_fits() {
+        let cache: TtlCache<String> = TtlCache::weighted(Duration::from_secs(60), 10, String::len);
+        for (key, value) in [("a", "xxxx"), ("b", "xxx"), ("c", "xx")] {
+            cache.insert(key.to_string(), value.to_string());
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert_eq!(cache.stats()["weight"], 9);
+
+        // Six more only fits once the two oldest are gone
+        cache.insert("d".to_string(), "xxxxxx".to_string());
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c").as_deref(), Some("xx"));
+        assert_eq!(cache.stats()["weight"], 8);
+        assert_eq!(cache.entries("d")[0].weight, 6);
+
+        // Replacing an entry swaps its weight for the new one's
+        cache.insert("c".to_string(), "x".to_string());
+        assert_eq!(cache.stats()["weight"], 7);
+        assert_eq!(cache.get("d").as_deref(), Some("xxxxxx"));
+    }
+
+    #[test]
+    fn skips_an_entry_heavier_than_the_capacity() {
+        let cache: TtlCache<String> = TtlCache::weighted(Duration::from_secs(60), 4, String::len);
+        cache.insert("small".to_string(), "xx".to_string());
+        cache.insert("huge".to_string(), "xxxxx".to_string());
+        assert_eq!(cache.get("huge"), None);
+        // Nothing was evicted to make room it couldn't have
+        assert_eq!(cache.get("small").as_deref(), Some("xx"));
+
+        // Exactly the capacity fits, once everything else is gone
+        cache.insert("full".to_string(), "xxxx".to_string());
+        assert_eq!(cache.get("small"), None);
+        assert_eq!(cache.stats()["weight"], 4);
+        assert_eq!(cache.stats()["capacity"], 4);
+
+        // With no capacity, nothing weighing anything is cached
+        let cache: TtlCache<String> = TtlCache::weighted(Duration::from_secs(60), 0, String::len);
+        cache.insert("a".to_string(), "x".to_string());
+        assert_eq!(cache.stats()["entries"], 0);
+    }
+
+    #[test]
+    fn lists_unexpired_entries_by_prefix_without_counting_lookups() {
+        let cache = TtlCache::new(Duration::from_millis(100), 10);
+        cache.insert("rust|b".to_string(), 2);
+        cache.insert("rust|a".to_string(), 1);
+        cache.insert("jaguar|a".to_string(), 3);
+
+        let entries = cache.entries("rust|");
+        let keys: Vec<&str> = entries.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(keys, ["rust|a", "rust|b"]);
+        assert_eq!(entries[0].value, 1);
+        assert_eq!(entries[0].weight, 1);
+        assert!(entries[0].age + entries[0].remaining <= Duration::from_millis(100));
+        assert!(entries[0].remaining > Duration::ZERO);
+        assert_eq!(cache.entries("").len(), 3);
+        assert!(cache.entries("python").is_empty());
+        assert_eq!(cache.stats()["hits"], 0);
+        assert_eq!(cache.stats()["misses"], 0);
+
+        std::thread::sleep(Duration::from_millis(110));
+        assert!(cache.entries("").is_empty());
+    }
+
+    #[test]
+    fn removes_one_entry_or_all_of_them() {
+        let cache = TtlCache::new(Duration::from_secs(60), 10);
+        for key in ["a", "b", "c"] {
+            cache.insert(key.to_string(), 1);
+        }
+
+        assert!(cache.remove("b"));
+        assert!(!cache.remove("b"));
+        assert!(!cache.remove("missing"));
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.stats()["entries"], 2);
+        assert_eq!(cache.stats()["weight"], 2);
+
+        assert_eq!(cache.clear(), 2);
+        assert_eq!(cache.clear(), 0);
+        assert!(cache.entries("").is_empty());
+    }
+
+    #[test]
+    fn starts_the_hit_ratio_over_when_cleared() {
+        let cache = TtlCache::new(Duration::from_secs(60), 10);
+        assert_eq!(cache.stats()["hit_ratio"], Value::Null);
+        cache.insert("a".to_string(), 1);
+        cache.get("a");
+        cache.get("a");
+        cache.get("a");
+        cache.get("b");
+        let stats = cache.stats();
+        assert_eq!((stats["hits"].clone(), stats["misses"].clone()), (json!(3), json!(1)));
+        assert_eq!(stats["hit_ratio"], 0.75);
+
+        // Removing one entry leaves the counts alone
+        cache.remove("a");
+        assert_eq!(cache.stats()["hits"], 3);
+
+        cache.clear();
+        let stats = cache.stats();
+        assert_eq!((stats["hits"].clone(), stats["misses"].clone()), (json!(0), json!(0)));
+        assert_eq!(stats["hit_ratio"], Value::Null);
+        cache.get("a");
+        assert_eq!(cache.stats()["hit_ratio"], 0.0);
+    }
+}
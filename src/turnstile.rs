@@ -1,16 +1,17 @@
-use reqwest::Client;
-
-pub async fn validate_turnstile_token(client: &Client, token: &str) -> bool {
-    let secret_key = std::env::var("CLOUDFLARE_TURNSTILE_SECRET_KEY")
-        .expect("CLOUDFLARE_TURNSTILE_SECRET_KEY must be set");
-    let url = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+use crate::config::TurnstileConfig;
+use crate::http_client::OutboundClient;
 
+pub async fn validate_turnstile_token(
+    client: &OutboundClient,
+    config: &TurnstileConfig,
+    token: &str
+) -> bool {
     let params = [
-        ("secret", secret_key),
-        ("response", token.to_string()),
+        ("secret", config.secret_key.as_str()),
+        ("response", token),
     ];
 
-    let response = client.post(url).form(&params).send().await;
+    let response = client.post_form(&config.verify_url, &params).await;
 
     match response {
         Ok(res) => {
@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+/// Duration suffixes and how many milliseconds each stands for
+const DURATION_UNITS: &[(&str, u64)] = &[
+    ("ms", 1),
+    ("s", 1000),
+    ("m", 60 * 1000),
+    ("h", 60 * 60 * 1000),
+];
+
+/// Size suffixes and how many bytes each stands for. Multiples are binary, so `1kb` is 1024
+/// bytes.
+const SIZE_UNITS: &[(&str, u64)] = &[
+    ("b", 1),
+    ("kb", 1 << 10),
+    ("mb", 1 << 20),
+    ("gb", 1 << 30),
+];
+
+/// Parses a whole-number duration such as `500ms`, `2s`, `5m` or `1h`.
+///
+/// A bare number is only accepted with a `bare_unit`, one of the suffixes, which it's read in;
+/// settings whose name doesn't state a unit pass `None` so `30` can't be misread.
+pub fn parse_duration(value: &str, bare_unit: Option<&str>) -> Result<Duration, String> {
+    let millis = parse_with_units(value, DURATION_UNITS, bare_unit, "500ms, 2s or 5m")?;
+    Ok(Duration::from_millis(millis))
+}
+
+/// Parses a whole-number size such as `512b`, `256kb` or `10mb`. A bare number is in bytes.
+pub fn parse_bytes(value: &str) -> Result<u64, String> {
+    parse_with_units(value, SIZE_UNITS, Some("b"), "256kb or 10mb")
+}
+
+/// Formats a duration in the largest unit that represents it exactly, e.g. `1500ms` or `5m`.
+pub fn format_duration(duration: Duration) -> String {
+    format_with_units(duration.as_millis().try_into().unwrap_or(u64::MAX), DURATION_UNITS)
+}
+
+/// Formats a size in the largest unit that represents it exactly, e.g. `256kb`.
+pub fn format_bytes(bytes: u64) -> String {
+    format_with_units(bytes, SIZE_UNITS)
+}
+
+fn parse_with_units(
+    value: &str,
+    units: &[(&str, u64)],
+    bare_unit: Option<&str>,
+    examples: &str
+) -> Result<u64, String> {
+    let value = value.trim().to_lowercase();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(digits);
+    if number.is_empty() {
+        return Err(format!("{:?} doesn't start with a whole number", value));
+    }
+    let number: u64 = number.parse().map_err(|_| format!("{:?} is too large", value))?;
+
+    let suffix = match suffix.trim() {
+        "" =>
+            bare_unit.ok_or_else(|| {
+                format!("{:?} needs a unit, such as {}", value, examples)
+            })?,
+        suffix => suffix,
+    };
+    let multiplier = units
+        .iter()
+        .find(|(unit, _)| *unit == suffix)
+        .map(|(_, multiplier)| *multiplier)
+        .ok_or_else(|| format!("{:?} has an unknown unit; use one such as {}", value, examples))?;
+    number.checked_mul(multiplier).ok_or_else(|| format!("{:?} is too large", value))
+}
+
+fn format_with_units(amount: u64, units: &[(&str, u64)]) -> String {
+    let (unit, multiplier) = units
+        .iter()
+        .rev()
+        .find(|(_, multiplier)| amount > 0 && amount.is_multiple_of(*multiplier))
+        .unwrap_or(&units[0]);
+    format!("{}{}", amount / multiplier, unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_duration_unit() {
+        for (value, millis) in [
+            ("500ms", 500),
+            ("2s", 2000),
+            ("5m", 5 * 60 * 1000),
+            ("1h", 60 * 60 * 1000),
+            ("0s", 0),
+            (" 2S ", 2000),
+            ("2 s", 2000),
+            ("007ms", 7),
+        ] {
+            assert_eq!(parse_duration(value, None), Ok(Duration::from_millis(millis)), "{}", value);
+        }
+    }
+
+    #[test]
+    fn reads_bare_durations_in_the_unit_given_or_not_at_all() {
+        assert_eq!(parse_duration("30", Some("s")), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30", Some("ms")), Ok(Duration::from_millis(30)));
+        // A unit given still wins over the bare one
+        assert_eq!(parse_duration("2m", Some("ms")), Ok(Duration::from_secs(120)));
+        assert_eq!(
+            parse_duration("30", None),
+            Err("\"30\" needs a unit, such as 500ms, 2s or 5m".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_binary_sizes() {
+        for (value, bytes) in [
+            ("512", 512),
+            ("512b", 512),
+            ("256kb", 256 * 1024),
+            ("10MB", 10 * 1024 * 1024),
+            ("1gb", 1 << 30),
+            ("0kb", 0),
+        ] {
+            assert_eq!(parse_bytes(value), Ok(bytes), "{}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_nonsense() {
+        for value in ["", "s", "ms", "-5s", "+5s", "1.5s", "2 sec", "2ss", "5d", "1e3ms", "2s5"] {
+            let error = parse_duration(value, Some("s")).unwrap_err();
+            assert!(
+                error.contains("whole number") || error.contains("unknown unit"),
+                "{}: {}",
+                value,
+                error
+            );
+        }
+        for value in ["kb", "10k", "10 mib", "10tb", "-1mb"] {
+            assert!(parse_bytes(value).is_err(), "{}", value);
+        }
+        assert_eq!(
+            parse_bytes("5d"),
+            Err("\"5d\" has an unknown unit; use one such as 256kb or 10mb".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_what_overflows() {
+        let max = u64::MAX.to_string();
+        let longest = parse_duration(&format!("{}ms", max), None);
+        assert_eq!(longest, Ok(Duration::from_millis(u64::MAX)));
+        // Too many seconds to count in milliseconds, or too many digits for any unit
+        for value in [format!("{}s", max), "18446744073709551616ms".to_string()] {
+            assert!(parse_duration(&value, None).unwrap_err().contains("too large"), "{}", value);
+        }
+        assert_eq!(parse_bytes(&max), Ok(u64::MAX));
+        assert!(parse_bytes("17179869184gb").unwrap_err().contains("too large"));
+        assert_eq!(parse_bytes("17179869183gb"), Ok(17179869183 << 30));
+    }
+
+    #[test]
+    fn formats_in_the_largest_exact_unit() {
+        for (millis, formatted) in [
+            (0, "0ms"),
+            (1500, "1500ms"),
+            (2000, "2s"),
+            (90_000, "90s"),
+            (5 * 60 * 1000, "5m"),
+            (7 * 24 * 60 * 60 * 1000, "168h"),
+        ] {
+            assert_eq!(format_duration(Duration::from_millis(millis)), formatted);
+        }
+        // Below a millisecond is lost, as no setting is that precise
+        assert_eq!(format_duration(Duration::from_micros(1500)), "1ms");
+        assert_eq!(format_bytes(0), "0b");
+        assert_eq!(format_bytes(1000), "1000b");
+        assert_eq!(format_bytes(2048), "2kb");
+        assert_eq!(format_bytes(16 << 20), "16mb");
+        assert_eq!(format_bytes(3 << 30), "3gb");
+    }
+
+    #[test]
+    fn formats_what_it_parses() {
+        for value in ["500ms", "2s", "5m", "1h", "256kb", "10mb", "1gb", "513b"] {
+            let formatted = match parse_duration(value, None) {
+                Ok(duration) => format_duration(duration),
+                Err(_) => format_bytes(parse_bytes(value).unwrap()),
+            };
+            assert_eq!(formatted, value);
+        }
+    }
+}
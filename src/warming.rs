@@ -0,0 +1,260 @@
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::{ watch, Semaphore };
+use tokio::task::JoinSet;
+use crate::config::WarmingConfig;
+use crate::popularity::QueryStats;
+use crate::preferences::Preferences;
+use crate::ranking::{ MatchMode, Recency };
+use crate::search::{ self, SearchOptions };
+use crate::state::AppState;
+use crate::timing::RequestTiming;
+use crate::trace::PipelineTrace;
+use crate::warnings::Warnings;
+
+/// Periodically re-executes the most popular queries so their results are cached before
+/// users ask for them, and again as soon as the index changes or new authority scores are
+/// published, since both drop the cached results. Stops when `shutdown` changes.
+pub async fn run_cache_warming(state: Arc<AppState>, mut shutdown: watch::Receiver<bool>) {
+    let config = state.config.warming.clone();
+    if config.top_n == 0 {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(config.interval);
+    // The first tick completes immediately, before anything has been searched for
+    interval.tick().await;
+    let mut index_generation = state.index_generation.subscribe();
+    let mut authority_generation = state.authority_generation.subscribe();
+
+    loop {
+        let refreshed = tokio::select! {
+            _ = interval.tick() => false,
+            changed = index_generation.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                true
+            }
+            changed = authority_generation.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                true
+            }
+            _ = shutdown.changed() => {
+                break;
+            }
+        };
+
+        let popular = state.popularity.top(config.top_n);
+        // Popularity fades with time, not with how often the index changes
+        if !refreshed {
+            state.popularity.decay();
+        }
+        match warm_popular_queries(&state, &config, popular, &mut shutdown).await {
+            Some((0, 0)) => {}
+            Some((warmed, skipped)) => {
+                println!("Warmed {} popular queries ({} skipped as too slow).", warmed, skipped);
+            }
+            None => {
+                break;
+            }
+        }
+    }
+}
+
+/// Re-executes `popular` into the result cache. Returns how many queries were warmed and how
+/// many were skipped, or `None` if interrupted.
+async fn warm_popular_queries(
+    state: &Arc<AppState>,
+    config: &WarmingConfig,
+    popular: Vec<(String, QueryStats)>,
+    shutdown: &mut watch::Receiver<bool>
+) -> Option<(usize, usize)> {
+    let num_results = search::DEFAULT_RESULTS.min(state.config.max_results);
+    let before = popular.len();
+    let queries: Vec<String> = popular
+        .into_iter()
+        // Don't let warming turn into a self-inflicted load spike
+        .filter(|(_, stats)| {
+            stats.last_duration.is_none_or(|duration| duration <= config.slow_threshold)
+        })
+        .map(|(query, _)| query)
+        .collect();
+    let skipped = before - queries.len();
+
+    let warmed = for_each_bounded(queries, config.concurrency, shutdown, |query| {
+        let state = state.clone();
+        async move {
+            let options = SearchOptions {
+                include_links: false,
+                num_results,
+                verbatim_only: false,
+                keyword_budget: state.config.keyword_budget,
+                include_deleted: false,
+                crawled_after: None,
+                crawled_before: None,
+                language: None,
+                exact: false,
+                case_sensitive: false,
+                expand: true,
+                stopwords: true,
+                min_match: None,
+                min_score: None,
+                mode: MatchMode::Loose,
+                ranker: state.config.ranker,
+                recency: Recency::Normal,
+                profile: None,
+                semantic: false,
+                collapse: false,
+                max_per_domain: Some(state.config.max_per_domain).filter(|&max| max > 0),
+                explain: false,
+                preferences: Preferences::default(),
+                url_regex: None,
+                shadow: false,
+            };
+            let mut warnings = Warnings::default();
+            let outcome = search::perform_search(
+                &query,
+                &state,
+                options.clone(),
+                None,
+                &mut RequestTiming::default(),
+                &mut PipelineTrace::default(),
+                &mut warnings
+            ).await;
+            // Failed or degraded results are better left for a real request to recompute
+            if let (Ok(outcome), true) = (outcome, warnings.is_empty()) {
+                let cache_key = search::search_cache_key(&query, &options);
+                state.result_cache.insert(cache_key, Arc::new(outcome));
+            }
+        }
+    }).await?;
+
+    Some((warmed, skipped))
+}
+
+/// Runs `task` for each of `items`, at most `concurrency` at a time. Returns how many ran to
+/// completion, or `None` if `shutdown` changed first, which aborts those still running.
+async fn for_each_bounded<T, F, Fut>(
+    items: Vec<T>,
+    concurrency: usize,
+    shutdown: &mut watch::Receiver<bool>,
+    task: F
+) -> Option<usize>
+    where F: Fn(T) -> Fut, Fut: Future<Output = ()> + Send + 'static
+{
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+
+    for item in items {
+        let permit = tokio::select! {
+            permit = permits.clone().acquire_owned() => permit.ok()?,
+            _ = shutdown.changed() => {
+                tasks.abort_all();
+                return None;
+            }
+        };
+        let running = task(item);
+        tasks.spawn(async move {
+            running.await;
+            drop(permit);
+        });
+    }
+
+    let mut finished = 0;
+    loop {
+        tokio::select! {
+            joined = tasks.join_next() => {
+                match joined {
+                    Some(Ok(())) => {
+                        finished += 1;
+                    }
+                    Some(Err(e)) => eprintln!("Cache warming task failed: {}", e),
+                    None => {
+                        break;
+                    }
+                }
+            }
+            _ = shutdown.changed() => {
+                tasks.abort_all();
+                return None;
+            }
+        }
+    }
+
+    Some(finished)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::time::Duration;
+
+    /// Counts the tasks running at once, and the most that were
+    #[derive(Default)]
+    struct Concurrency {
+        running: AtomicUsize,
+        most: AtomicUsize,
+    }
+
+    async fn run_counted(concurrency: usize, items: usize) -> (Option<usize>, usize) {
+        let counter = Arc::new(Concurrency::default());
+        let (_shutdown_tx, mut shutdown) = watch::channel(false);
+        let items = (0..items).collect();
+        let finished = for_each_bounded(items, concurrency, &mut shutdown, |_| {
+            let counter = counter.clone();
+            async move {
+                let running = counter.running.fetch_add(1, Ordering::SeqCst) + 1;
+                counter.most.fetch_max(running, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                counter.running.fetch_sub(1, Ordering::SeqCst);
+            }
+        }).await;
+        (finished, counter.most.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn runs_every_task_within_the_concurrency_limit() {
+        let (finished, most) = run_counted(3, 20).await;
+        assert_eq!(finished, Some(20));
+        assert_eq!(most, 3);
+    }
+
+    #[tokio::test]
+    async fn runs_one_at_a_time_without_a_concurrency_limit() {
+        let (finished, most) = run_counted(0, 5).await;
+        assert_eq!(finished, Some(5));
+        assert_eq!(most, 1);
+    }
+
+    #[tokio::test]
+    async fn finishes_nothing_without_tasks() {
+        assert_eq!(run_counted(2, 0).await, (Some(0), 0));
+    }
+
+    #[tokio::test]
+    async fn stops_when_shut_down() {
+        let (shutdown_tx, mut shutdown) = watch::channel(false);
+        let started = Arc::new(AtomicUsize::new(0));
+        let counter = started.clone();
+        let stop = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            shutdown_tx.send(true).ok();
+            shutdown_tx
+        });
+        let finished = for_each_bounded((0..100).collect(), 2, &mut shutdown, |_: usize| {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+        }).await;
+        stop.await.unwrap();
+
+        assert_eq!(finished, None);
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,158 @@
+use serde_json::{ Value, json };
+use std::ops::Range;
+
+/// A numeric parameter was above its limit and the limit was used instead
+pub const RESULTS_CLAMPED: &str = "results_clamped";
+/// A parameter's value couldn't be parsed and its default was used instead
+pub const INVALID_PARAMETER: &str = "invalid_parameter";
+/// A parameter isn't one the endpoint accepts, and was ignored
+pub const UNKNOWN_PARAMETER: &str = "unknown_parameter";
+/// The query had more distinct terms than its keyword budget, and the least selective were left
+/// out
+pub const KEYWORDS_DROPPED: &str = "keywords_dropped";
+/// Links were requested but couldn't be fetched, so results have none
+pub const LINKS_UNAVAILABLE: &str = "links_unavailable";
+/// Results couldn't be checked against the spam list, so none were penalised
+pub const SPAM_CHECK_UNAVAILABLE: &str = "spam_check_unavailable";
+/// The query had only exclusions or a `site:` filter, and nothing to search for
+pub const NO_SEARCH_TERMS: &str = "no_search_terms";
+/// Results of a boolean query couldn't be checked for the terms it didn't fetch, which counted as
+/// absent
+pub const EXCLUSIONS_UNAVAILABLE: &str = "exclusions_unavailable";
+/// A wildcard matched more indexed words than are searched for, and the rarest were left out
+pub const WILDCARD_LIMITED: &str = "wildcard_limited";
+/// A wildcard's words couldn't be looked up, so it matched nothing
+pub const WILDCARD_UNAVAILABLE: &str = "wildcard_unavailable";
+/// Word positions couldn't be looked up, so `NEAR/n` and term closeness only checked titles and
+/// descriptions
+pub const PROXIMITY_UNAVAILABLE: &str = "proximity_unavailable";
+/// The anchor text of links to the results couldn't be looked up, so it didn't rank them
+pub const ANCHOR_TEXT_UNAVAILABLE: &str = "anchor_text_unavailable";
+/// Which query terms are indexed couldn't be looked up, so none was matched fuzzily
+pub const FUZZY_UNAVAILABLE: &str = "fuzzy_unavailable";
+/// Words' original forms couldn't be looked up, so `case_sensitive` only checked titles and
+/// descriptions
+pub const CASE_UNAVAILABLE: &str = "case_unavailable";
+/// The words related searches are made of couldn't be looked up, so none were suggested
+pub const RELATED_UNAVAILABLE: &str = "related_unavailable";
+/// The words of pages linking to the query's matches couldn't be looked up, so no pages were
+/// added through them
+pub const LINK_EXPANSION_UNAVAILABLE: &str = "link_expansion_unavailable";
+/// The query and results couldn't be embedded, or no embedding service is configured, so
+/// `semantic=true` left the results in their ranked order
+pub const SEMANTIC_UNAVAILABLE: &str = "semantic_unavailable";
+/// Pages' words couldn't be looked up to fingerprint them, so `collapse=true` collapsed nothing
+pub const DUPLICATES_UNAVAILABLE: &str = "duplicates_unavailable";
+/// The pages matching a blend's queries couldn't be fetched, so it has no results
+pub const CANDIDATES_UNAVAILABLE: &str = "candidates_unavailable";
+/// The index has no pages yet, so nothing was searched for
+pub const EMPTY_INDEX: &str = "empty_index";
+
+/// A non-fatal problem with a request, reported alongside its results
+#[derive(Debug, Clone)]
+pub struct Warning {
+    /// One of the stable codes defined in this module
+    pub code: &'static str,
+    pub message: String,
+    /// The request parameter the warning is about, if any
+    pub field: Option<String>,
+}
+
+/// Warnings collected while handling a request
+#[derive(Debug, Default)]
+pub struct Warnings {
+    warnings: Vec<Warning>,
+}
+
+impl Warnings {
+    pub fn push(&mut self, code: &'static str, message: String, field: Option<&str>) {
+        self.warnings.push(Warning {
+            code,
+            message,
+            field: field.map(String::from),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.warnings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    /// Drops the warnings pushed in `range`, such as those about a search whose results weren't
+    /// served.
+    pub fn remove(&mut self, range: Range<usize>) {
+        self.warnings.drain(range);
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!(
+            self.warnings
+                .iter()
+                .map(|warning| {
+                    json!({ "code": warning.code, "message": warning.message, "field": warning.field })
+                })
+                .collect::<Vec<_>>()
+        )
+    }
+
+    /// Adds the warnings to a response as `warnings`, if there are any.
+    pub fn add_to(&self, response: &mut Value) {
+        if !self.is_empty() {
+            response["warnings"] = self.to_json();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialises_each_warning_in_order() {
+        let mut warnings = Warnings::default();
+        warnings.push(RESULTS_CLAMPED, "Too many".to_string(), Some("results"));
+        warnings.push(EMPTY_INDEX, "Nothing indexed".to_string(), None);
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(
+            warnings.to_json(),
+            json!([
+                { "code": "results_clamped", "message": "Too many", "field": "results" },
+                { "code": "empty_index", "message": "Nothing indexed", "field": null },
+            ])
+        );
+    }
+
+    #[test]
+    fn only_adds_the_array_when_there_are_warnings() {
+        let mut response = json!({ "results": [] });
+        let mut warnings = Warnings::default();
+        warnings.add_to(&mut response);
+        assert_eq!(response, json!({ "results": [] }));
+
+        warnings.push(LINKS_UNAVAILABLE, "No links".to_string(), Some("links"));
+        warnings.add_to(&mut response);
+        assert_eq!(response["warnings"][0]["code"], "links_unavailable");
+    }
+
+    #[test]
+    fn removes_the_warnings_of_a_range() {
+        let mut warnings = Warnings::default();
+        for code in [INVALID_PARAMETER, FUZZY_UNAVAILABLE, CASE_UNAVAILABLE, UNKNOWN_PARAMETER] {
+            warnings.push(code, String::new(), None);
+        }
+        warnings.remove(1..3);
+        let codes: Vec<Value> = warnings
+            .to_json()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|warning| warning["code"].clone())
+            .collect();
+        assert_eq!(codes, [json!("invalid_parameter"), json!("unknown_parameter")]);
+        warnings.remove(0..2);
+        assert!(warnings.is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::{ result_urls, TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+async fn search(server: &TestServer, params: &str) -> Value {
+    let (status, _, body) = server.get_json(&format!("/?q={}", params)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body
+}
+
+#[tokio::test]
+async fn echoes_the_server_limits_over_what_was_asked_for() {
+    let Some(db) = TestDb::create("applied_limits", &[INDEX]).await else {
+        return;
+    };
+    let env = [("MAX_RESULTS", "3"), ("MAX_PER_DOMAIN", "1"), ("KEYWORD_BUDGET", "8")];
+    let server = TestServer::start(&db, &env).await;
+
+    // Asked for more results than the server returns, and for admin-only options
+    let body = search(&server, "rust&results=10&include_deleted=true&url_regex=rust").await;
+    let applied = &body["applied_options"];
+    assert_eq!(applied["results"], 3);
+    assert_eq!(result_urls(&body).len(), 3);
+    assert_eq!(applied["include_deleted"], false);
+    assert_eq!(applied["url_regex"], Value::Null);
+    assert_eq!(applied["keyword_budget"], 8);
+    assert_eq!(applied["max_per_domain"], 1);
+    assert_eq!(applied["lemmas"], json!(["rust"]));
+
+    // Signals need an API key, so aren't reported as run without one
+    let body = search(&server, "rust&signals=true").await;
+    assert_eq!(body["applied_options"]["features"]["signals"], false);
+
+    // The server's cap per domain is replaced, or turned off, when asked
+    let body = search(&server, "rust&max_per_domain=2").await;
+    assert_eq!(body["applied_options"]["max_per_domain"], 2);
+    let body = search(&server, "rust&max_per_domain=0").await;
+    assert_eq!(body["applied_options"]["max_per_domain"], Value::Null);
+
+    // A `site:` search isn't capped per domain, and looks up only the words
+    let body = search(&server, "rust%20site:rust-lang.org").await;
+    let applied = &body["applied_options"];
+    assert_eq!(applied["max_per_domain"], Value::Null);
+    assert_eq!(applied["site"], "rust-lang.org");
+    assert_eq!(applied["lemmas"], json!(["rust"]));
+    assert_eq!(result_urls(&body).len(), 2);
+
+    // Admins get the privileged keyword budget
+    let (_, _, body) = server.get_admin("/?q=rust").await;
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["applied_options"]["keyword_budget"], 128);
+}
+
+#[tokio::test]
+async fn echoes_the_minimum_score_the_results_had_to_meet() {
+    let Some(db) = TestDb::create("applied_min_score", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("MIN_SCORE", "0.99")]).await;
+
+    // Without a `min_score`, TF-IDF searches need the server's
+    let body = search(&server, "rust%20language").await;
+    assert_eq!(body["applied_options"]["min_score"], 0.99);
+    let filtered = body["filtered_below_threshold"].as_u64().unwrap() as usize;
+    assert!(filtered > 0, "{}", body);
+    let kept = result_urls(&body).len();
+
+    // One asked for replaces it, letting more through
+    let body = search(&server, "rust%20language&min_score=0.2").await;
+    assert_eq!(body["applied_options"]["min_score"], 0.2);
+    assert_eq!(body["filtered_below_threshold"], 0);
+    assert_eq!(result_urls(&body).len(), kept + filtered);
+
+    // `min_match` decides instead, and BM25 has no full score to measure against
+    for params in ["rust%20language&min_match=50%25", "rust%20language&ranker=bm25"] {
+        let body = search(&server, params).await;
+        assert_eq!(body["applied_options"]["min_score"], Value::Null, "{}", params);
+    }
+    let body = search(&server, "rust%20language&ranker=bm25").await;
+    assert_eq!(body["applied_options"]["ranker"], "bm25");
+    assert_eq!(body["filtered_below_threshold"], 0);
+
+    // A cached result reports what the search it came from applied
+    let body = search(&server, "rust%20language").await;
+    assert_eq!(body["applied_options"]["min_score"], 0.99);
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use axum::{ routing::post, Json, Router };
+use common::{ json_response, result_urls, temp_file, TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+use std::time::{ Duration, Instant };
+
+async fn blend(server: &TestServer, body: Value) -> (StatusCode, Value) {
+    let request = server.client.post(server.url("/blend")).json(&body);
+    let (status, _, body) = json_response(request).await;
+    (status, body)
+}
+
+#[tokio::test]
+async fn merges_duplicates_and_normalises_the_weights() {
+    let Some(db) = TestDb::create("blend_weights", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let queries = json!([
+        { "query": "rust", "weight": 1 },
+        { "query": " RUST ", "weight": 2 },
+        { "query": "jaguar", "weight": 1 },
+    ]);
+    let (status, body) = blend(&server, json!({ "queries": queries, "explain": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["queries"],
+        json!([
+            { "query": "rust", "weight": 0.75 },
+            { "query": "jaguar", "weight": 0.25 },
+        ])
+    );
+
+    // Every page has one word or the other, so contributes that query's weight
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 7);
+    for result in results {
+        let contributions: Vec<f64> = result["explain"]["queries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|query| query["contribution"].as_f64().unwrap())
+            .collect();
+        let url = result["url"].as_str().unwrap();
+        if url.contains("aguar") {
+            assert_eq!(contributions, [0.0, 0.25], "{}", url);
+        } else {
+            assert_eq!(contributions, [0.75, 0.0], "{}", url);
+        }
+    }
+    // The heavier query's pages come first
+    let last_two: Vec<&str> = results[5..]
+        .iter()
+        .map(|result| result["url"].as_str().unwrap())
+        .collect();
+    assert!(last_two.iter().all(|url| url.contains("aguar")), "{:?}", last_two);
+
+    // Reweighted the other way round, the jaguar pages lead
+    let queries = json!([{ "query": "rust", "weight": 1 }, { "query": "jaguar", "weight": 9 }]);
+    let (_, body) = blend(&server, json!({ "queries": queries })).await;
+    let urls = result_urls(&body);
+    assert!(urls[..2].iter().all(|url| url.contains("aguar")), "{:?}", urls);
+    assert_eq!(body["results"][0].get("explain"), None);
+}
+
+#[tokio::test]
+async fn rejects_invalid_blends() {
+    let Some(db) = TestDb::create("blend_invalid", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("MAX_QUERY_CHARS", "10")]).await;
+    let one = |query: &str, weight: Value| {
+        json!({ "queries": [{ "query": query, "weight": weight }] })
+    };
+
+    for (body, error) in [
+        (json!({ "queries": [] }), "At least one query is required"),
+        (
+            json!({ "queries": vec![json!({ "query": "rust", "weight": 1 }); 6] }),
+            "At most 5 queries can be blended",
+        ),
+        (one("rust", json!(0)), "Weight for \"rust\" must be a positive number"),
+        (one("rust", json!(-1.5)), "Weight for \"rust\" must be a positive number"),
+        (one("\u{200b} \u{0}", json!(1)), "Queries must not be empty"),
+    ] {
+        let (status, response) = blend(&server, body.clone()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(response["error"], error);
+    }
+
+    let (status, _) = blend(&server, one("rust async programming", json!(1))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = blend(&server, json!({ "queries": "rust" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Five, even with duplicates among them, is allowed
+    let five = json!({ "queries": vec![json!({ "query": "rust", "weight": 1 }); 5] });
+    let (status, body) = blend(&server, five).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["queries"], json!([{ "query": "rust", "weight": 1.0 }]));
+}
+
+#[tokio::test]
+async fn enforces_the_search_limits_and_is_recorded_as_a_blend() {
+    let Some(db) = TestDb::create("blend_limits", &[INDEX]).await else {
+        return;
+    };
+    let log = temp_file("blend_slow.log", "");
+    let env = [
+        ("MAX_RESULTS", "2"),
+        ("SLOW_QUERY_MS", "0"),
+        ("SLOW_QUERY_LOG_FILE", log.to_str().unwrap()),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    let queries = json!([{ "query": "rust", "weight": 1 }, { "query": "jaguar", "weight": 1 }]);
+    let body = json!({ "queries": queries, "results": 50, "debug": true });
+    let (status, body) = blend(&server, body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result_urls(&body).len(), 2);
+    assert_eq!(body["warnings"][0]["code"], "results_clamped");
+    assert_eq!(body["debug"]["pipeline"]["variant"], "blended");
+    assert_eq!(body["debug"]["pipeline"]["candidates_fetched"], 7);
+    assert_eq!(body["debug"]["pipeline"]["returned"], 2);
+
+    let started = Instant::now();
+    let logged: Value = loop {
+        let written = std::fs::read_to_string(&log).unwrap_or_default();
+        if let Some(line) = written.lines().next() {
+            break serde_json::from_str(line).unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "Nothing logged:\n{}", server.log());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert_eq!(logged["endpoint"], "/blend");
+    assert_eq!(logged["query"], json!(["rust", "jaguar"]));
+    assert_eq!(logged["trace"]["variant"], "blended");
+
+    // Blends aren't counted as searches
+    let (_, _, metrics) = server.get_admin("/admin/metrics").await;
+    let metrics: Value = serde_json::from_str(&metrics).unwrap();
+    assert_eq!(metrics["result_cache"]["misses"], 0, "{}", metrics);
+}
+
+#[tokio::test]
+async fn requires_turnstile_like_search() {
+    let Some(db) = TestDb::create("blend_turnstile", &[INDEX]).await else {
+        return;
+    };
+    let router = Router::new().route(
+        "/",
+        post(|| async { Json(json!({ "success": true })) })
+    );
+    let verify_url = common::spawn_mock(router).await;
+    let env = [("TURNSTILE_ENABLED", "true"), ("TURNSTILE_VERIFY_URL", verify_url.as_str())];
+    let server = TestServer::start(&db, &env).await;
+    let body = json!({ "queries": [{ "query": "rust", "weight": 1 }] });
+
+    let (status, response) = blend(&server, body.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response["error"], "Missing Turnstile token");
+
+    let request = server.client
+        .post(server.url("/blend"))
+        .header("X-Turnstile-Token", "any")
+        .json(&body);
+    let (status, _, response) = json_response(request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result_urls(&response).len(), 5);
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::Value;
+
+/// Searches and returns the body's size and the body.
+async fn sized(server: &TestServer, path: &str) -> (usize, Value) {
+    let response = server.client.get(server.url(path)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.bytes().await.unwrap();
+    (body.len(), serde_json::from_slice(&body).expect("The body should still parse"))
+}
+
+/// How far down the shedding order a response went: 0 for nothing shed, 1 for keywords, then
+/// links and results. The fixture's descriptions are already shorter than they'd be cut to, so
+/// shedding them never removes anything.
+fn shed_stages(body: &Value) -> usize {
+    let truncated = &body["truncated"];
+    assert_ne!(truncated["descriptions"], true);
+    let stages = [
+        truncated["keywords"] == true,
+        truncated["links_from"] == true,
+        truncated["results_dropped"].as_u64().unwrap_or(0) > 0,
+    ];
+    let shed = stages.iter().take_while(|shed| **shed).count();
+    // Nothing later in the order is shed before everything earlier
+    assert!(stages[shed..].iter().all(|shed| !shed), "Shed out of order: {}", truncated);
+    shed
+}
+
+#[tokio::test]
+async fn sheds_payload_in_order_to_fit_the_budget() {
+    let Some(db) = TestDb::create("budget_shedding", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    let search = "/?q=rust&links=true";
+    let (full, body) = sized(&server, search).await;
+    assert_eq!(body.get("truncated"), None);
+    let results = body["results"].as_array().unwrap().len();
+    // Everything but the results, which no budget can shed
+    let (envelope, body) = sized(&server, &format!("{}&max_bytes=1", search)).await;
+    assert_eq!(body["results"].as_array().unwrap().len(), 0);
+    assert_eq!(body["truncated"]["results_dropped"], results);
+
+    let mut stages = vec![];
+    for max_bytes in [full + 20, full - 50, (full * 3) / 4, envelope + 300] {
+        let (len, body) = sized(&server, &format!("{}&max_bytes={}", search, max_bytes)).await;
+        assert!(len <= max_bytes, "{} bytes is over {}", len, max_bytes);
+        assert_eq!(body["query"], "rust");
+        assert_eq!(body["applied_options"]["max_bytes"], max_bytes);
+        let kept = body["results"].as_array().unwrap();
+        if body["truncated"]["keywords"] == true {
+            assert!(kept.iter().all(|result| result.get("keywords").is_none()));
+        }
+        if body["truncated"]["links_from"] == true {
+            assert!(kept.iter().all(|result| result.get("links_from").is_none()));
+        }
+        let dropped = body["truncated"]["results_dropped"].as_u64().unwrap_or(0) as usize;
+        assert_eq!(kept.len() + dropped, results);
+        stages.push(shed_stages(&body));
+    }
+    // Smaller budgets shed further down the order, to the results themselves
+    assert!(stages.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", stages);
+    assert_eq!(stages[..2], [0, 1]);
+    assert_eq!(stages.last(), Some(&3));
+}
+
+#[tokio::test]
+async fn defaults_to_the_configured_budget() {
+    let Some(db) = TestDb::create("budget_default", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("MAX_RESPONSE_BYTES", "2kb")]).await;
+
+    let (len, body) = sized(&server, "/?q=rust&links=true").await;
+    assert!(len <= 2048, "{} bytes", len);
+    assert!(shed_stages(&body) >= 1);
+
+    // A request can ask for more, or for no limit
+    let (len, body) = sized(&server, "/?q=rust&links=true&max_bytes=0").await;
+    assert!(len > 2048);
+    assert_eq!(body.get("truncated"), None);
+    let (_, body) = sized(&server, "/?q=rust&links=true&max_bytes=1000000").await;
+    assert_eq!(body.get("truncated"), None);
+
+    // A budget that isn't a number is reported, and the default used
+    let (len, body) = sized(&server, "/?q=rust&links=true&max_bytes=lots").await;
+    assert!(len <= 2048, "{} bytes", len);
+    assert_eq!(body["warnings"][0]["field"], "max_bytes");
+}
@@ -0,0 +1,284 @@
+// Shared by every integration test binary, each of which uses a different part of it
+#![allow(dead_code)]
+
+use reqwest::{ header::HeaderMap, Client, StatusCode };
+use serde_json::Value;
+use sqlx::{ Connection, Executor, PgConnection, PgPool };
+use std::fs;
+use std::net::TcpListener;
+use std::path::{ Path, PathBuf };
+use std::process::{ Child, Command, Stdio };
+use std::time::{ Duration, Instant };
+use url::Url;
+
+/// The admin token test servers are started with
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// How long a test server gets to start answering requests
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The index most tests search: see `tests/fixtures/index.sql`
+pub const INDEX: &str = "tests/fixtures/index.sql";
+
+/// A database of its own for one test, created from `schema.sql` in the database server at
+/// `TEST_DATABASE_URL`.
+pub struct TestDb {
+    pub url: String,
+    pub pool: PgPool,
+}
+
+impl TestDb {
+    /// Creates the database `search_test_<name>` afresh and loads `fixtures` into it, each a
+    /// path to a SQL file relative to the crate root. Returns `None`, so the test can skip,
+    /// when `TEST_DATABASE_URL` isn't set.
+    pub async fn create(name: &str, fixtures: &[&str]) -> Option<TestDb> {
+        let admin_url = match std::env::var("TEST_DATABASE_URL") {
+            Ok(admin_url) => admin_url,
+            Err(_) => {
+                eprintln!("Skipping {}: TEST_DATABASE_URL isn't set", name);
+                return None;
+            }
+        };
+        let database = format!("search_test_{}", name);
+        let mut admin = PgConnection::connect(&admin_url).await.expect(
+            "Failed to connect to TEST_DATABASE_URL"
+        );
+        admin
+            .execute(format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", database).as_str()).await
+            .expect("Failed to drop the test database");
+        admin
+            .execute(format!("CREATE DATABASE {}", database).as_str()).await
+            .expect("Failed to create the test database");
+        admin.close().await.ok();
+
+        let mut url = Url::parse(&admin_url).expect("TEST_DATABASE_URL isn't a URL");
+        url.set_path(&database);
+        let url = url.to_string();
+
+        let mut connection = PgConnection::connect(&url).await.expect(
+            "Failed to connect to the test database"
+        );
+        for path in std::iter::once("schema.sql").chain(fixtures.iter().copied()) {
+            let sql = fs::read_to_string(crate_path(path)).expect("Failed to read a fixture");
+            connection.execute(sql.as_str()).await.unwrap_or_else(|e| panic!("{}: {}", path, e));
+        }
+        connection.close().await.ok();
+
+        let pool = PgPool::connect(&url).await.expect("Failed to connect to the test database");
+        Some(TestDb { url, pool })
+    }
+}
+
+/// The search engine running as a child process against a test database, killed when dropped.
+pub struct TestServer {
+    pub base_url: String,
+    pub client: Client,
+    child: Child,
+    log: PathBuf,
+}
+
+impl TestServer {
+    /// Starts the server on a free port with Turnstile off and `ADMIN_TOKEN` as its admin token,
+    /// then `env` on top, and waits until it answers requests.
+    ///
+    /// It runs in a directory of its own, with `tests/fixtures/top-1m.txt` as its top domains.
+    pub async fn start(db: &TestDb, env: &[(&str, &str)]) -> TestServer {
+        let mut server = TestServer::spawn(db, env);
+        server.wait_until_ready().await;
+        server
+    }
+
+    /// Starts the server as `start` does, expecting it to exit rather than answer requests,
+    /// and returns what it logged.
+    pub async fn start_failing(db: &TestDb, env: &[(&str, &str)]) -> String {
+        let mut server = TestServer::spawn(db, env);
+        let started = Instant::now();
+        loop {
+            if let Ok(Some(_)) = server.child.try_wait() {
+                return server.log();
+            }
+            if server.client.get(server.url("/admin/metrics")).send().await.is_ok() {
+                panic!("The server started:\n{}", server.log());
+            }
+            if started.elapsed() > STARTUP_TIMEOUT {
+                panic!("The server didn't exit in time:\n{}", server.log());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    fn spawn(db: &TestDb, env: &[(&str, &str)]) -> TestServer {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("Failed to find a free port")
+            .port();
+        let dir = working_dir(&format!("server_{}", port));
+        let log = dir.join("server.log");
+        let log_file = fs::File::create(&log).expect("Failed to create the server log");
+
+        let mut command = command(db, &dir, env);
+        command
+            .env("AXUM_PORT", port.to_string())
+            .stdin(Stdio::null())
+            .stdout(log_file.try_clone().expect("Failed to open the server log"))
+            .stderr(log_file);
+        let child = command.spawn().expect("Failed to start the server");
+
+        TestServer {
+            base_url: format!("http://127.0.0.1:{}", port),
+            client: Client::new(),
+            child,
+            log,
+        }
+    }
+
+    async fn wait_until_ready(&mut self) {
+        let started = Instant::now();
+        loop {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                panic!("The server exited with {}:\n{}", status, self.log());
+            }
+            // Admin routes aren't rate limited, so waiting doesn't use up a test's quota
+            if self.client.get(self.url("/admin/metrics")).send().await.is_ok() {
+                return;
+            }
+            if started.elapsed() > STARTUP_TIMEOUT {
+                panic!("The server didn't start in time:\n{}", self.log());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Everything the server has logged so far
+    pub fn log(&self) -> String {
+        fs::read_to_string(&self.log).unwrap_or_default()
+    }
+
+    /// Sends `GET path` and returns the response's status, headers and JSON body, which is
+    /// `Value::Null` if it isn't JSON.
+    pub async fn get_json(&self, path: &str) -> (StatusCode, HeaderMap, Value) {
+        json_response(self.client.get(self.url(path))).await
+    }
+
+    /// Sends `GET path` with the admin token and returns the response's status, headers and
+    /// body as text.
+    pub async fn get_admin(&self, path: &str) -> (StatusCode, HeaderMap, String) {
+        let response = self.client
+            .get(self.url(path))
+            .header("X-Admin-Token", ADMIN_TOKEN)
+            .send().await
+            .expect("Request failed");
+        let status = response.status();
+        let headers = response.headers().clone();
+        (status, headers, response.text().await.expect("Failed to read the body"))
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// Runs the binary with `args`, e.g. a subcommand, against `db` with the same environment as a
+/// test server, and returns whether it succeeded and what it printed to standard output and
+/// standard error.
+pub fn run_command(
+    db: &TestDb,
+    name: &str,
+    args: &[&str],
+    env: &[(&str, &str)]
+) -> (bool, String, String) {
+    let dir = working_dir(&format!("command_{}", name));
+    let output = command(db, &dir, env)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to run the command");
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+/// A directory for the binary to run in, with `tests/fixtures/top-1m.txt` as its top domains
+/// and the crate's lemma map.
+fn working_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("search_test_{}", name));
+    fs::create_dir_all(&dir).expect("Failed to create the working directory");
+    fs::copy(crate_path("tests/fixtures/top-1m.txt"), dir.join("top-1m.txt")).expect(
+        "Failed to copy the top domains"
+    );
+    let lemmas = dir.join("lemmatised_words.txt");
+    if !lemmas.exists() {
+        std::os::unix::fs
+            ::symlink(crate_path("lemmatised_words.txt"), &lemmas)
+            .expect("Failed to link the lemma map");
+    }
+    dir
+}
+
+/// The binary, to run in `dir` against `db` with Turnstile off, `ADMIN_TOKEN` as its admin
+/// token and `env` on top.
+fn command(db: &TestDb, dir: &Path, env: &[(&str, &str)]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_search_engine_api"));
+    command
+        .current_dir(dir)
+        .env("DATABASE_URL", &db.url)
+        .env("TURNSTILE_ENABLED", "false")
+        .env("CLOUDFLARE_TURNSTILE_SECRET_KEY", "test-secret")
+        .env("ADMIN_TOKEN", ADMIN_TOKEN)
+        .envs(env.iter().copied());
+    command
+}
+
+/// Sends `request` and returns the response's status, headers and JSON body, which is
+/// `Value::Null` if it isn't JSON.
+pub async fn json_response(request: reqwest::RequestBuilder) -> (StatusCode, HeaderMap, Value) {
+    let response = request.send().await.expect("Request failed");
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.bytes().await.expect("Failed to read the body");
+    (status, headers, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+/// The URLs of a search response's results, in order
+pub fn result_urls(body: &Value) -> Vec<String> {
+    body["results"]
+        .as_array()
+        .map(|results| {
+            results
+                .iter()
+                .filter_map(|result| result["url"].as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Serves `router` on a free port in the background, standing in for an outside service, and
+/// returns its base URL.
+pub async fn spawn_mock(router: axum::Router) -> String {
+    let listener = tokio::net::TcpListener
+        ::bind("127.0.0.1:0").await
+        .expect("Failed to bind a mock server");
+    let address = listener.local_addr().expect("Failed to read the mock server's address");
+    tokio::spawn(async move { axum::serve(listener, router).await.ok() });
+    format!("http://{}", address)
+}
+
+/// Writes `contents` to a file of its own in the temporary directory and returns its path.
+pub fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("search_test_{}", name));
+    fs::write(&path, contents).expect("Failed to write a temporary file");
+    path
+}
+
+fn crate_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::{ header::HeaderMap, StatusCode };
+use serde_json::Value;
+use std::time::{ Duration, Instant };
+
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(20);
+
+fn header(headers: &HeaderMap, name: &str) -> String {
+    headers[name].to_str().unwrap().to_string()
+}
+
+/// Sends `GET path` revalidating a copy cached with `etag` or `last_modified`
+async fn revalidate(
+    server: &TestServer,
+    path: &str,
+    validator: (&str, &str)
+) -> (StatusCode, HeaderMap, Value) {
+    let request = server.client.get(server.url(path)).header(validator.0, validator.1);
+    common::json_response(request).await
+}
+
+/// Polls `path` until `changed` holds for its body, and returns its response.
+async fn wait_for(
+    server: &TestServer,
+    path: &str,
+    changed: impl Fn(&Value) -> bool
+) -> (StatusCode, HeaderMap, Value) {
+    let started = Instant::now();
+    loop {
+        let response = server.get_json(path).await;
+        if changed(&response.2) {
+            return response;
+        }
+        assert!(started.elapsed() < REFRESH_TIMEOUT, "{} didn't change:\n{}", path, server.log());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[tokio::test]
+async fn stats_are_revalidated_until_the_index_changes() {
+    let Some(db) = TestDb::create("conditional_stats", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("INDEX_REFRESH_SECS", "1s")]).await;
+
+    let (status, headers, body) = server.get_json("/stats").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["website_count"], 9);
+    assert_eq!(header(&headers, "cache-control"), "public, max-age=30");
+    let etag = header(&headers, "etag");
+    let last_modified = header(&headers, "last-modified");
+
+    // Nothing changed, so the cached copies are still current, and the index is checked
+    // meanwhile without changing that
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let (status, headers, _) = revalidate(&server, "/stats", ("If-None-Match", &etag)).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(header(&headers, "etag"), etag);
+    let (status, _, _) = revalidate(&server, "/stats", ("If-Modified-Since", &last_modified)).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+    // The crawler tombstones a page
+    sqlx::query("UPDATE websites SET deleted_at = now() WHERE id = 9")
+        .execute(&db.pool).await
+        .unwrap();
+    wait_for(&server, "/stats", |body| body["website_count"] == 8).await;
+
+    let (status, headers, body) = revalidate(&server, "/stats", ("If-None-Match", &etag)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["website_count"], 8);
+    assert_ne!(header(&headers, "etag"), etag);
+    let modified = httpdate::parse_http_date(&header(&headers, "last-modified")).unwrap();
+    assert!(modified > httpdate::parse_http_date(&last_modified).unwrap());
+    let (status, _, _) = revalidate(&server, "/stats", ("If-Modified-Since", &last_modified)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn trending_is_revalidated_until_the_next_aggregation() {
+    let Some(db) = TestDb::create("conditional_trending", &[INDEX]).await else {
+        return;
+    };
+    let env = [
+        ("QUERY_LOG", "true"),
+        ("TRENDING_REFRESH_SECS", "3s"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    // Answered unconditionally until there's an aggregation to date it
+    let (_, _, first) = wait_for(&server, "/trending", |body| {
+        body["computed_at"].is_string()
+    }).await;
+    let (status, headers, body) = wait_for(&server, "/trending", |body| {
+        body["computed_at"] != first["computed_at"]
+    }).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["queries"], serde_json::json!([]));
+    assert_eq!(header(&headers, "cache-control"), "public, max-age=3");
+    let etag = header(&headers, "etag");
+    let last_modified = header(&headers, "last-modified");
+    let if_modified_since = ("If-Modified-Since", last_modified.as_str());
+
+    let (status, _, _) = revalidate(&server, "/trending", ("If-None-Match", &etag)).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    let (status, _, _) = revalidate(&server, "/trending", if_modified_since).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+    // Enough people search for the same thing
+    sqlx::query(
+        r#"
+        INSERT INTO query_log (query, ip_hash)
+        SELECT 'rust', 'searcher-' || n FROM generate_series(1, 3) n
+    "#
+    )
+        .execute(&db.pool).await
+        .unwrap();
+    wait_for(&server, "/trending", |body| body["queries"][0]["query"] == "rust").await;
+
+    let (status, headers, body) = revalidate(&server, "/trending", ("If-None-Match", &etag)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["queries"][0]["searchers"], 3);
+    assert_ne!(header(&headers, "etag"), etag);
+    let (status, _, _) = revalidate(&server, "/trending", if_modified_since).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+
+/// The startup printout's value for the setting `name`
+fn printed<'a>(log: &'a str, name: &str) -> &'a str {
+    let config = log
+        .lines()
+        .find_map(|line| line.strip_prefix("Config: "))
+        .expect("No config printed");
+    config
+        .split(' ')
+        .find_map(|setting| setting.strip_prefix(&format!("{}=", name)))
+        .unwrap_or_else(|| panic!("{} isn't printed in {:?}", name, config))
+}
+
+/// The settings `src/config.rs` reads as a duration or a size
+fn unit_settings() -> Vec<&'static str> {
+    let source = include_str!("../src/config.rs");
+    ["env_secs(\"", "env_millis(\"", "env_duration(\"", "env_bytes(\""]
+        .iter()
+        .flat_map(|call| source.split(call).skip(1))
+        .filter_map(|rest| rest.split('"').next())
+        .collect()
+}
+
+#[tokio::test]
+async fn prints_every_duration_and_size_with_its_unit() {
+    let Some(db) = TestDb::create("config_units_printed", &[INDEX]).await else {
+        return;
+    };
+    let env = [
+        ("DB_ACQUIRE_TIMEOUT", "90s"),
+        ("SLOW_QUERY_MS", "1500"),
+        ("RESULT_CACHE_TTL_SECS", "120"),
+        ("WARM_INTERVAL_SECS", "2h"),
+        ("CLICK_HALF_LIFE", "5m"),
+        ("RECENCY_HALF_LIFE", "48h"),
+        ("TRENDING_WINDOWS", "30m,24h"),
+        ("TRENDING_REFRESH_SECS", "90"),
+        ("MAX_RESPONSE_BYTES", "1024kb"),
+        ("FRAGMENT_CACHE_BYTES", "1000"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+    let log = server.log();
+
+    // Bare numbers count in the name's unit, and each is printed in the largest exact unit
+    for (name, value) in [
+        ("DB_ACQUIRE_TIMEOUT", "90s"),
+        ("SLOW_QUERY_MS", "1500ms"),
+        ("RESULT_CACHE_TTL_SECS", "2m"),
+        ("WARM_INTERVAL_SECS", "2h"),
+        ("CLICK_HALF_LIFE", "5m"),
+        ("RECENCY_HALF_LIFE", "48h"),
+        ("TRENDING_WINDOWS", "30m,24h"),
+        ("TRENDING_REFRESH_SECS", "90s"),
+        ("MAX_RESPONSE_BYTES", "1mb"),
+        ("FRAGMENT_CACHE_BYTES", "1000b"),
+    ] {
+        assert_eq!(printed(&log, name), value, "{}", name);
+    }
+    // Defaults are printed too
+    assert_eq!(printed(&log, "HTTP_CONNECT_TIMEOUT_MS"), "2s");
+    assert_eq!(printed(&log, "FRAGMENT_CACHE_TTL_SECS"), "5m");
+    assert_eq!(printed(&log, "IDF_REFRESH_SECS"), "1h");
+    // So is every other setting with a unit
+    let settings = unit_settings();
+    assert!(settings.len() > 20, "{:?}", settings);
+    for name in settings {
+        printed(&log, name);
+    }
+}
+
+#[tokio::test]
+async fn refuses_to_start_with_an_ambiguous_or_invalid_value() {
+    let Some(db) = TestDb::create("config_units_invalid", &[INDEX]).await else {
+        return;
+    };
+
+    for (name, value, error) in [
+        (
+            "DB_ACQUIRE_TIMEOUT",
+            "30",
+            "DB_ACQUIRE_TIMEOUT must be a duration: \"30\" needs a unit, such as 500ms, 2s or 5m",
+        ),
+        ("CLICK_HALF_LIFE", "1w", "CLICK_HALF_LIFE must be a duration: \"1w\" has an unknown unit"),
+        ("SLOW_QUERY_MS", "1.5s", "SLOW_QUERY_MS must be a duration"),
+        ("RESULT_CACHE_TTL_SECS", "-1", "doesn't start with a whole number"),
+        ("WARM_INTERVAL_SECS", "99999999999999999999h", "is too large"),
+        ("MAX_RESPONSE_BYTES", "10tb", "MAX_RESPONSE_BYTES must be a size such as 256kb or 10mb"),
+        ("FRAGMENT_CACHE_BYTES", "lots", "FRAGMENT_CACHE_BYTES must be a size"),
+    ] {
+        let log = TestServer::start_failing(&db, &[(name, value)]).await;
+        assert!(log.contains(error), "{}={}: {}", name, value, log);
+    }
+}
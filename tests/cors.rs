@@ -0,0 +1,154 @@
+mod common;
+
+use common::{ TestDb, TestServer, ADMIN_TOKEN, INDEX };
+use reqwest::{ header::HeaderMap, Method, StatusCode };
+
+const ORIGIN: &str = "https://search.ylokhmotov.dev";
+
+/// Sends a preflight for `method` on `path` from `origin`, asking to send `headers`.
+async fn preflight(
+    server: &TestServer,
+    path: &str,
+    origin: &str,
+    method: &str,
+    headers: &str
+) -> (StatusCode, HeaderMap) {
+    let mut request = server.client
+        .request(Method::OPTIONS, server.url(path))
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", method);
+    if !headers.is_empty() {
+        request = request.header("Access-Control-Request-Headers", headers);
+    }
+    let response = request.send().await.unwrap();
+    (response.status(), response.headers().clone())
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).map(|value| value.to_str().unwrap())
+}
+
+/// The `access-control-*` headers a response has
+fn cors_headers(headers: &HeaderMap) -> Vec<String> {
+    let mut names: Vec<String> = headers
+        .keys()
+        .map(|name| name.to_string())
+        .filter(|name| name.starts_with("access-control-"))
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn answers_preflights_from_allowed_origins() {
+    let Some(db) = TestDb::create("cors_preflight", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    for (path, method) in [("/", "GET"), ("/blend", "POST"), ("/saved", "POST")] {
+        let (status, headers) = preflight(
+            &server,
+            path,
+            ORIGIN,
+            method,
+            "x-turnstile-token,x-api-key,content-type"
+        ).await;
+        assert_eq!(status, StatusCode::OK, "{}", path);
+        assert_eq!(header(&headers, "access-control-allow-origin"), Some(ORIGIN));
+        assert_eq!(header(&headers, "access-control-allow-methods"), Some("GET,POST"));
+        assert_eq!(
+            header(&headers, "access-control-allow-headers"),
+            Some(
+                "content-type,authorization,x-api-key,x-turnstile-token,x-search-prefs,\
+                 x-request-id"
+            )
+        );
+        assert_eq!(header(&headers, "access-control-max-age"), Some("600"));
+        assert_eq!(header(&headers, "access-control-allow-credentials"), None);
+    }
+
+    // Other origins get no grant at all
+    let (_, headers) = preflight(&server, "/", "https://evil.example", "GET", "").await;
+    assert_eq!(header(&headers, "access-control-allow-origin"), None);
+}
+
+#[tokio::test]
+async fn exposes_the_custom_response_headers() {
+    let Some(db) = TestDb::create("cors_expose", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let response = server.client
+        .get(server.url("/?q=rust"))
+        .header("Origin", ORIGIN)
+        .send().await
+        .unwrap();
+    let headers = response.headers();
+    assert_eq!(header(headers, "access-control-allow-origin"), Some(ORIGIN));
+    assert_eq!(
+        header(headers, "access-control-expose-headers"),
+        Some(
+            "x-request-id,server-timing,x-search-version,ratelimit-limit,ratelimit-remaining,\
+             ratelimit-reset"
+        )
+    );
+    assert!(headers.contains_key("x-search-version"));
+    assert!(headers.contains_key("ratelimit-limit"));
+}
+
+#[tokio::test]
+async fn never_grants_admin_routes_to_any_origin() {
+    let Some(db) = TestDb::create("cors_admin", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("CORS_ALLOW_CREDENTIALS", "true")]).await;
+
+    for path in ["/admin/metrics", "/admin/cache/results", "/admin/pagerank"] {
+        let (_, headers) = preflight(&server, path, ORIGIN, "GET", "x-admin-token").await;
+        assert_eq!(cors_headers(&headers), Vec::<String>::new(), "{}", path);
+    }
+    let response = server.client
+        .get(server.url("/admin/metrics"))
+        .header("Origin", ORIGIN)
+        .header("X-Admin-Token", ADMIN_TOKEN)
+        .send().await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(cors_headers(response.headers()), Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn follows_the_configured_origins_methods_and_credentials() {
+    let Some(db) = TestDb::create("cors_configured", &[INDEX]).await else {
+        return;
+    };
+    let env = [
+        ("CORS_ALLOWED_ORIGINS", "https://app.example.com"),
+        ("CORS_PUBLIC_METHODS", "GET"),
+        ("CORS_MAX_AGE_SECS", "60"),
+        ("CORS_ALLOW_CREDENTIALS", "true"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    let (_, headers) = preflight(&server, "/", "https://app.example.com", "GET", "").await;
+    assert_eq!(
+        cors_headers(&headers),
+        [
+            "access-control-allow-credentials",
+            "access-control-allow-headers",
+            "access-control-allow-methods",
+            "access-control-allow-origin",
+            "access-control-max-age",
+        ]
+    );
+    assert_eq!(header(&headers, "access-control-allow-origin"), Some("https://app.example.com"));
+    assert_eq!(header(&headers, "access-control-allow-methods"), Some("GET"));
+    assert_eq!(header(&headers, "access-control-max-age"), Some("60"));
+    assert_eq!(header(&headers, "access-control-allow-credentials"), Some("true"));
+
+    // The default origins are no longer allowed
+    let (_, headers) = preflight(&server, "/", ORIGIN, "GET", "").await;
+    assert_eq!(header(&headers, "access-control-allow-origin"), None);
+}
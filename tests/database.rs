@@ -0,0 +1,132 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::time::{ Duration, Instant };
+
+async fn database_metrics(server: &TestServer) -> Value {
+    let (_, _, body) = server.get_admin("/admin/metrics").await;
+    let metrics: Value = serde_json::from_str(&body).unwrap();
+    metrics["database"].clone()
+}
+
+#[tokio::test]
+async fn refuses_to_start_against_a_mismatched_schema() {
+    let Some(db) = TestDb::create("database_schema", &[
+        INDEX,
+        "tests/fixtures/broken_schema.sql",
+    ]).await else {
+        return;
+    };
+
+    let log = TestServer::start_failing(&db, &[]).await;
+    assert!(log.contains("Database doesn't match schema.sql"), "{}", log);
+    assert!(log.contains("database schema mismatch: SQLSTATE 42703"), "{}", log);
+}
+
+#[tokio::test]
+async fn stops_querying_a_database_that_keeps_timing_out() {
+    let Some(db) = TestDb::create("database_breaker", &[INDEX]).await else {
+        return;
+    };
+    let env = [
+        ("DB_RETRY_ATTEMPTS", "2"),
+        ("DB_RETRY_BACKOFF_MS", "10"),
+        ("DB_BREAKER_THRESHOLD", "2"),
+        ("DB_BREAKER_COOLDOWN_SECS", "2s"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+    let links = "/links?url=https://tokio.rs/";
+    let (status, _, _) = server.get_json(links).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Queries give up waiting on locks quickly, from the server's next connections on
+    sqlx::query("ALTER DATABASE search_test_database_breaker SET lock_timeout = '200ms'")
+        .execute(&db.pool).await
+        .unwrap();
+    sqlx::query(
+        r#"
+        SELECT pg_terminate_backend(pid) FROM pg_stat_activity
+        WHERE datname = current_database() AND pid <> pg_backend_pid()
+    "#
+    )
+        .execute(&db.pool).await
+        .unwrap();
+    // A migration holds a lock on the pages
+    let mut migration = db.pool.begin().await.unwrap();
+    sqlx::query("LOCK TABLE websites IN ACCESS EXCLUSIVE MODE")
+        .execute(&mut *migration).await
+        .unwrap();
+
+    // Each request is retried once before it times out
+    for _ in 0..2 {
+        let (status, _, body) = server.get_json("/links?url=https://tokio.rs/&limit=5").await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body["retryable"], true);
+    }
+    let metrics = database_metrics(&server).await;
+    assert_eq!(metrics["retries"], 2);
+    assert_eq!(metrics["circuit"], "open");
+
+    // With the circuit open, requests fail at once without waiting on the database
+    let started = Instant::now();
+    let (status, _, body) = server.get_json("/links?url=https://tokio.rs/&limit=6").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["retryable"], true);
+    assert!(started.elapsed() < Duration::from_millis(150), "{:?}", started.elapsed());
+    assert_eq!(database_metrics(&server).await["rejected"], 1);
+
+    // Once the lock is released and the cooldown has passed, the next request closes it
+    migration.rollback().await.unwrap();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let (status, _, body) = server.get_json("/links?url=https://tokio.rs/&limit=7").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(database_metrics(&server).await["circuit"], "closed");
+    assert!(server.log().contains("so the circuit breaker closed"));
+}
+
+#[tokio::test]
+async fn fails_a_search_whose_candidates_cant_be_fetched() {
+    let Some(db) = TestDb::create("database_search", &[INDEX]).await else {
+        return;
+    };
+    let env = [
+        ("DB_RETRY_ATTEMPTS", "1"),
+        ("DB_BREAKER_THRESHOLD", "1"),
+        ("DB_BREAKER_COOLDOWN_SECS", "2s"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    sqlx::query("ALTER DATABASE search_test_database_search SET lock_timeout = '200ms'")
+        .execute(&db.pool).await
+        .unwrap();
+    sqlx::query(
+        r#"
+        SELECT pg_terminate_backend(pid) FROM pg_stat_activity
+        WHERE datname = current_database() AND pid <> pg_backend_pid()
+    "#
+    )
+        .execute(&db.pool).await
+        .unwrap();
+    let mut migration = db.pool.begin().await.unwrap();
+    sqlx::query("LOCK TABLE websites IN ACCESS EXCLUSIVE MODE")
+        .execute(&mut *migration).await
+        .unwrap();
+
+    // The failure is reported rather than answered with an empty page of results
+    let (status, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT, "{}", body);
+    assert_eq!(body["retryable"], true);
+    let (status, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+    assert_eq!(body["retryable"], true);
+    assert!(body.get("results").is_none(), "{}", body);
+
+    // Nothing was cached, so the same search finds results once the database is back
+    migration.rollback().await.unwrap();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let (status, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["matching_webpages"].as_u64().unwrap() > 0, "{}", body);
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::{ json_response, result_urls, TestDb, TestServer };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+use std::time::{ Duration, Instant };
+
+/// A single page whose keyword counts say more pages have its words than the index holds
+const ONE_PAGE: &str = "tests/fixtures/one_page.sql";
+
+fn codes(body: &Value) -> Vec<&str> {
+    body["warnings"]
+        .as_array()
+        .map(|warnings| warnings.iter().filter_map(|warning| warning["code"].as_str()).collect())
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn answers_every_search_of_an_empty_index_without_asking_the_database() {
+    let Some(db) = TestDb::create("empty_index_searches", &[]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    assert!(server.log().contains("The index is empty, so searches will find nothing."));
+    assert!(!server.log().contains("Found 0 websites"));
+
+    for search in [
+        "rust",
+        "rust%20async&ranker=bm25",
+        "rust%20async&mode=strict",
+        "%22rust%20async%22%20-python%20site:rust-lang.org",
+        "rust*",
+    ] {
+        let (status, _, body) = server.get_json(&format!("/?q={}&debug=true", search)).await;
+        assert_eq!(status, StatusCode::OK, "{}: {}", search, body);
+        assert_eq!(result_urls(&body), Vec::<String>::new(), "{}", search);
+        assert_eq!(body["website_count"], 0);
+        assert_eq!(codes(&body), ["empty_index"], "{}", search);
+        assert_eq!(body["debug"]["pipeline"]["candidates_fetched"], 0, "{}", search);
+    }
+
+    let blend = json!({ "queries": [{ "query": "rust", "weight": 1 }] });
+    let (status, _, body) = json_response(server.client.post(server.url("/blend")).json(&blend))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(result_urls(&body), Vec::<String>::new());
+    assert_eq!(codes(&body), ["empty_index"]);
+    let (status, _, body) = server.get_json("/random").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["count"], 0);
+}
+
+#[tokio::test]
+async fn reports_an_empty_index_as_degraded_until_pages_arrive() {
+    let Some(db) = TestDb::create("empty_index_stats", &[]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("INDEX_REFRESH_SECS", "1")]).await;
+
+    // Degraded, but serving
+    let (status, _, stats) = server.get_json("/stats").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["status"], "degraded");
+    assert_eq!(stats["degraded_reasons"], json!(["empty_index"]));
+    assert_eq!(stats["website_count"], 0);
+
+    let sql = std::fs::read_to_string(ONE_PAGE).unwrap();
+    sqlx::raw_sql(&sql).execute(&db.pool).await.unwrap();
+    let started = Instant::now();
+    loop {
+        let (_, _, stats) = server.get_json("/stats").await;
+        if stats["website_count"] == 1 {
+            assert_eq!(stats["status"], "ok");
+            assert_eq!(stats["degraded_reasons"], json!([]));
+            break;
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "Not refreshed:\n{}", server.log());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let (_, _, body) = server.get_json("/?q=rust&ranker=bm25").await;
+    assert_eq!(result_urls(&body), ["https://doc.rust-lang.org/book/"]);
+    assert_eq!(body.get("warnings"), None);
+}
+
+#[tokio::test]
+async fn scores_a_one_page_index_with_finite_numbers() {
+    let Some(db) = TestDb::create("empty_index_one_page", &[ONE_PAGE]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    assert!(server.log().contains("Found 1 websites."), "{}", server.log());
+
+    // A word on every page is worth nothing to TF-IDF, so its scores fall below the default
+    // minimum; BM25 still tells the page apart
+    for search in [
+        "rust&min_score=0",
+        "rust%20book&min_score=0",
+        "rust%20book&ranker=bm25",
+        "rust&ranker=bm25&mode=strict",
+    ] {
+        let (status, _, body) = server.get_json(&format!("/?q={}&explain=true", search)).await;
+        assert_eq!(status, StatusCode::OK, "{}: {}", search, body);
+        assert_eq!(result_urls(&body), ["https://doc.rust-lang.org/book/"], "{}", search);
+        let result = &body["results"][0];
+        // A NaN or infinity wouldn't even serialise as a number
+        assert!(result["score"].as_f64().unwrap().is_finite(), "{}: {}", search, result);
+        for term in result["explain"]["terms"].as_array().unwrap() {
+            let idf = term["idf"].as_f64().unwrap();
+            assert!(idf.is_finite() && idf >= 0.0, "{}: {}", search, term);
+        }
+    }
+
+    // Every word is on every page, and more pages than that is clamped to the index size
+    let (_, _, body) = server.get_json("/?q=rust&explain=true&min_score=0").await;
+    assert_eq!(body["results"][0]["explain"]["terms"][0]["idf"], 0.0);
+    let (_, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(result_urls(&body), Vec::<String>::new());
+    assert_eq!(body.get("warnings"), None);
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+async fn empty_state(server: &TestServer, search: &str) -> Value {
+    let (status, _, body) = server.get_json(search).await;
+    assert_eq!(status, StatusCode::OK, "{}: {}", search, body);
+    assert_eq!(body["query"], "");
+    assert_eq!(body["count"], body["results"].as_array().unwrap().len());
+    body
+}
+
+#[tokio::test]
+async fn rejects_an_empty_query_by_default() {
+    let Some(db) = TestDb::create("empty_query_rejected", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    // Without any `q`, `/` describes the API instead
+    for search in ["/?q=", "/?q=%20%20%09", "/?q=&results=5"] {
+        let (status, _, body) = server.get_json(search).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", search);
+        assert_eq!(body["code"], "empty_query", "{}", search);
+        assert_eq!(body["field"], "q");
+    }
+
+    // An unknown mode is rejected whether or not the query is empty
+    for search in ["/?q=&empty=popular", "/?q=rust&empty=popular"] {
+        let (status, _, body) = server.get_json(search).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", search);
+        assert_eq!(body["field"], "empty");
+        assert_eq!(
+            body["error"],
+            "Unknown empty mode \"popular\"; valid modes are: trending, recent, none"
+        );
+    }
+
+    // A query that isn't empty is searched as usual
+    let (status, _, body) = server.get_json("/?q=rust&empty=trending").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.get("result_source"), None);
+    assert_eq!(body["results"].as_array().unwrap().len(), 5);
+}
+
+#[tokio::test]
+async fn returns_queries_searched_more_than_once_when_trending() {
+    let Some(db) = TestDb::create("empty_query_trending", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let body = empty_state(&server, "/?q=&empty=trending").await;
+    assert_eq!(body["result_source"], "trending");
+    assert_eq!(body["results"], json!([]));
+
+    for search in ["rust", "Rust", "rust", "jaguar", "jaguar", "python"] {
+        server.get_json(&format!("/?q={}", search)).await;
+    }
+    // However the query was spelled, and even when answered from the result cache
+    let body = empty_state(&server, "/?q=%20&empty=Trending").await;
+    assert_eq!(body["result_source"], "trending");
+    assert_eq!(
+        body["results"],
+        json!([{ "query": "rust", "searches": 3 }, { "query": "jaguar", "searches": 2 }])
+    );
+    let body = empty_state(&server, "/?q=&empty=trending&results=1").await;
+    assert_eq!(body["results"], json!([{ "query": "rust", "searches": 3 }]));
+}
+
+#[tokio::test]
+async fn returns_the_newest_pages_on_top_domains_when_recent() {
+    let Some(db) = TestDb::create("empty_query_recent", &[INDEX]).await else {
+        return;
+    };
+    sqlx::query(
+        r#"
+        INSERT INTO websites (title, description, url, word_count, last_crawled, deleted_at)
+        VALUES
+            ('Python news', '', 'https://python.org/news', 10, '2024-06-01T00:00:00Z', NULL),
+            ('Example', '', 'https://example.com/old', 10, '2023-01-01T00:00:00Z', NULL),
+            ('Off the list', '', 'https://new.example.net/', 10, '2024-07-01T00:00:00Z', NULL),
+            ('Gone', '', 'https://example.com/gone', 10, '2024-08-01T00:00:00Z', now()),
+            ('Never crawled', '', 'https://wikipedia.org/new', 10, NULL, NULL)
+        "#
+    )
+        .execute(&db.pool).await
+        .unwrap();
+    let server = TestServer::start(&db, &[]).await;
+
+    let body = empty_state(&server, "/?q=&empty=recent").await;
+    assert_eq!(body["result_source"], "recent");
+    let urls: Vec<&str> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["url"].as_str().unwrap())
+        .collect();
+    assert_eq!(urls, ["https://python.org/news", "https://tokio.rs/", "https://example.com/old"]);
+    let newest = &body["results"][0];
+    assert_eq!(newest["title"], "Python news");
+    assert_eq!(newest["last_crawled"], "2024-06-01T00:00:00Z");
+    assert_eq!(newest["top_website_rank"], 5);
+    assert_eq!(newest.get("score"), None);
+    assert_eq!(newest.get("raw_score"), None);
+
+    let body = empty_state(&server, "/?q=&empty=recent&results=2").await;
+    assert_eq!(body["count"], 2);
+    assert_eq!(body["results"][1]["url"], "https://tokio.rs/");
+}
+
+#[tokio::test]
+async fn returns_nothing_when_asked_for_none() {
+    let Some(db) = TestDb::create("empty_query_none", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    server.get_json("/?q=rust").await;
+    server.get_json("/?q=rust&results=3").await;
+
+    let body = empty_state(&server, "/?q=%E2%80%8B&empty=none").await;
+    assert_eq!(body["result_source"], "none");
+    assert_eq!(body["results"], json!([]));
+    assert_eq!(body["website_count"], 9);
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use common::{ TestDb, TestServer };
+use reqwest::StatusCode;
+use serde_json::Value;
+
+const HOSTILE: &str = "tests/fixtures/hostile.sql";
+
+/// Every page's id, title, description and URL, as stored
+async fn stored(db: &TestDb) -> Vec<(i32, String, String, String)> {
+    sqlx::query_as("SELECT id, title, description, url FROM websites ORDER BY id")
+        .fetch_all(&db.pool).await
+        .unwrap()
+}
+
+/// Splits CSV text into rows of fields the way a spreadsheet reads it.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut characters = text.chars().peekable();
+    while let Some(character) = characters.next() {
+        match (character, quoted) {
+            ('"', true) if characters.peek() == Some(&'"') => {
+                characters.next();
+                field.push('"');
+            }
+            ('"', _) => {
+                quoted = !quoted;
+            }
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (character, _) => field.push(character),
+        }
+    }
+    assert!(!quoted && field.is_empty(), "The CSV is cut off: {:?}", text);
+    rows
+}
+
+#[tokio::test]
+async fn search_results_round_trip_hostile_strings() {
+    let Some(db) = TestDb::create("escaping_search", &[HOSTILE]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let query = "hostile%20%3C%2Fscript%3E%5D%5D%3E";
+    let (status, headers, body) = server.get_json(&format!("/?q={}", query)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "application/json");
+    assert_eq!(body["query"], "hostile </script>]]>");
+
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    // Each hostile page comes back exactly as stored
+    for (id, title, description, url) in stored(&db).await.into_iter().take(3) {
+        let result = results
+            .iter()
+            .find(|result| result["url"] == url.as_str())
+            .unwrap_or_else(|| panic!("Page {} is missing: {}", id, body));
+        assert_eq!(result["title"], title.as_str());
+        assert_eq!(result["description"], description.as_str());
+    }
+}
+
+#[tokio::test]
+async fn exports_round_trip_hostile_urls_and_neutralise_formulas() {
+    let Some(db) = TestDb::create("escaping_export", &[HOSTILE]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    let urls: Vec<String> = stored(&db).await
+        .into_iter()
+        .map(|(_, _, _, url)| url)
+        .collect();
+
+    let (status, _, body) = server.get_admin("/admin/export/urls").await;
+    assert_eq!(status, StatusCode::OK);
+    let exported: Vec<Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let exported: Vec<&str> = exported
+        .iter()
+        .map(|row| row["url"].as_str().unwrap())
+        .collect();
+    assert_eq!(exported, urls);
+
+    let (status, _, body) = server.get_admin("/admin/export/urls?format=csv").await;
+    assert_eq!(status, StatusCode::OK);
+    let rows = parse_csv(&body);
+    assert_eq!(rows[0], ["id", "url", "last_crawled", "word_count"]);
+    assert_eq!(rows.len(), 5);
+    for (row, url) in rows[1..].iter().zip(&urls) {
+        assert_eq!(row.len(), 4, "{:?}", row);
+        // Only a formula gains a leading quote
+        match url.starts_with('=') {
+            true => assert_eq!(row[1], format!("'{}", url)),
+            false => assert_eq!(&row[1], url),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::Value;
+
+/// Pages added on top of the fixture index, enough for an export several chunks long
+const GENERATED_PAGES: i32 = 5000;
+
+fn jsonl_rows(body: &str) -> Vec<Value> {
+    body.lines()
+        .map(|line| serde_json::from_str(line).expect("Each line should be a JSON object"))
+        .collect()
+}
+
+fn ids(rows: &[Value]) -> Vec<i64> {
+    rows.iter()
+        .map(|row| row["id"].as_i64().expect("Each row should have an id"))
+        .collect()
+}
+
+async fn add_generated_pages(db: &TestDb) {
+    sqlx::query(
+        r#"
+        INSERT INTO websites (title, description, url, word_count, last_crawled)
+        SELECT 'Page ' || n, 'Generated page ' || n, 'https://generated.example.org/' || n, n,
+            '2024-06-01T00:00:00Z'
+        FROM generate_series(1, $1) n
+    "#
+    )
+        .bind(GENERATED_PAGES)
+        .execute(&db.pool).await
+        .expect("Failed to add the generated pages");
+}
+
+#[tokio::test]
+async fn exports_every_url_once_in_id_order() {
+    let Some(db) = TestDb::create("export_complete", &[INDEX]).await else {
+        return;
+    };
+    add_generated_pages(&db).await;
+    let server = TestServer::start(&db, &[]).await;
+
+    let (status, headers, body) = server.get_admin("/admin/export/urls").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "application/x-ndjson");
+    let exported = ids(&jsonl_rows(&body));
+
+    // Everything but the tombstoned page 8, each once and in order
+    let live: i64 = 9 + (GENERATED_PAGES as i64);
+    assert_eq!(exported.len() as i64, live);
+    assert!(exported.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(!exported.contains(&8));
+    assert_eq!(exported.last(), Some(&(10 + (GENERATED_PAGES as i64))));
+
+    let (_, _, body) = server.get_admin("/admin/export/urls?include_deleted=true").await;
+    assert_eq!(jsonl_rows(&body).len() as i64, live + 1);
+
+    let (status, headers, body) = server.get_admin("/admin/export/urls?format=csv").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "text/csv; charset=utf-8");
+    let mut lines = body.lines();
+    assert_eq!(lines.next(), Some("id,url,last_crawled,word_count"));
+    assert_eq!(lines.next(), Some("1,https://doc.rust-lang.org/book/,2024-03-01T00:00:00Z,1200"));
+    assert_eq!(lines.count() as i64, live - 1);
+}
+
+#[tokio::test]
+async fn resumes_after_the_last_id_received() {
+    let Some(db) = TestDb::create("export_resume", &[INDEX]).await else {
+        return;
+    };
+    add_generated_pages(&db).await;
+    let server = TestServer::start(&db, &[]).await;
+
+    let (_, _, body) = server.get_admin("/admin/export/urls").await;
+    let full = jsonl_rows(&body);
+
+    // As if the first export broke off part way through
+    let received = &full[..full.len() / 3];
+    let last_id = received.last().unwrap()["id"].as_i64().unwrap();
+    let (status, _, body) = server.get_admin(
+        &format!("/admin/export/urls?after_id={}", last_id)
+    ).await;
+    assert_eq!(status, StatusCode::OK);
+    let resumed = jsonl_rows(&body);
+    assert!(ids(&resumed).iter().all(|&id| id > last_id));
+
+    let stitched: Vec<Value> = received.iter().chain(resumed.iter()).cloned().collect();
+    assert_eq!(stitched, full);
+
+    // Resuming after the last row exports nothing
+    let last_id = full.last().unwrap()["id"].as_i64().unwrap();
+    let (status, _, body) = server.get_admin(
+        &format!("/admin/export/urls?after_id={}", last_id)
+    ).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.is_empty());
+}
+
+#[tokio::test]
+async fn filters_and_rejects_invalid_filters() {
+    let Some(db) = TestDb::create("export_filters", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (status, _, body) = server.get_admin("/admin/export/urls?domain=wikipedia.org").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ids(&jsonl_rows(&body)), vec![4, 5]);
+
+    let (_, _, body) = server.get_admin("/admin/export/urls?domain=EXAMPLE.com.").await;
+    assert_eq!(ids(&jsonl_rows(&body)), vec![6]);
+
+    let (_, _, body) = server.get_admin("/admin/export/urls?since=2024-03-01").await;
+    assert_eq!(ids(&jsonl_rows(&body)), vec![1, 6, 7, 9]);
+
+    let (_, _, body) = server.get_admin(
+        "/admin/export/urls?since=2024-04-15T12:00:00%2B02:00&domain=example.fr"
+    ).await;
+    assert_eq!(ids(&jsonl_rows(&body)), vec![7]);
+
+    for query in [
+        "domain=",
+        "domain=not%20a%20domain",
+        "domain=example.com/path",
+        "since=yesterday",
+        "since=2024-13-01",
+        "after_id=last",
+        "format=xml",
+        "include_deleted=yes",
+    ] {
+        let (status, _, body) = server.get_admin(&format!("/admin/export/urls?{}", query)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert!(body["error"].is_string(), "{}", query);
+    }
+
+    let unauthorised = server.client.get(server.url("/admin/export/urls")).send().await.unwrap();
+    assert_eq!(unauthorised.status(), StatusCode::UNAUTHORIZED);
+}
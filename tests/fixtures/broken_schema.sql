@@ -0,0 +1,3 @@
+-- A database the crawler migrated only halfway: `websites` has lost the `host` column the
+-- queries rely on
+ALTER TABLE websites DROP COLUMN host CASCADE;
@@ -0,0 +1,20 @@
+-- Eleven pages and three families of words: "fana0" to "fana10", and likewise with "b" and
+-- "c". The word ending in n is on the first n + 1 pages, so the higher the number, the more
+-- common the word, and "fana0" is the rarest.
+INSERT INTO websites (id, title, description, url, word_count, last_crawled, language)
+SELECT page, 'Page ' || page, 'Words', 'https://fan-out.example.com/' || page, 100,
+    '2024-03-01T00:00:00Z', 'en'
+FROM generate_series(1, 11) AS page;
+
+SELECT setval('websites_id_seq', 11);
+
+INSERT INTO keywords (id, word, documents_containing_word)
+SELECT family * 11 + n + 1, 'fan' || chr(97 + family) || n, n + 1
+FROM generate_series(0, 2) AS family, generate_series(0, 10) AS n;
+
+SELECT setval('keywords_id_seq', 33);
+
+INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences)
+SELECT family * 11 + n + 1, page, 1
+FROM generate_series(0, 2) AS family, generate_series(0, 10) AS n, generate_series(1, 11) AS page
+WHERE page <= n + 1;
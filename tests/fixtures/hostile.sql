@@ -0,0 +1,22 @@
+-- Pages whose titles, descriptions and URLs try to break out of each output format: script tags
+-- for HTML, a CDATA terminator for XML, formulas and delimiters for CSV, and line breaks for SSE.
+INSERT INTO websites (id, title, description, url, word_count, last_crawled, language)
+VALUES
+    (1, '<script>alert("x")</script>', 'Ends ]]> the CDATA & <b>then</b>',
+        'https://evil.example/=cmd|'' /C calc''!A0', 100, '2024-03-01T00:00:00Z', 'en'),
+    (2, '=HYPERLINK("https://evil.example","click")', E'data: injected\n\nevent: fake',
+        '=HYPERLINK("https://evil.example")', 100, '2024-03-01T00:00:00Z', 'en'),
+    (3, E'Line\r\nbreak, "quoted"', '+1+1', 'https://evil.example/a,"b"', 100,
+        '2024-03-01T00:00:00Z', 'en'),
+    (4, 'A plain page', 'Nothing to see', 'https://plain.example/', 100, '2024-03-01T00:00:00Z',
+        'en');
+
+SELECT setval('websites_id_seq', 4);
+
+INSERT INTO keywords (id, word, documents_containing_word)
+VALUES (1, 'hostile', 3);
+
+SELECT setval('keywords_id_seq', 1);
+
+INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences)
+VALUES (1, 1, 5), (1, 2, 5), (1, 3, 5);
@@ -0,0 +1,62 @@
+-- A small index shared by the integration tests: a few domains and subdomains, links with and
+-- without anchor text, and one tombstoned page.
+INSERT INTO websites (id, title, description, url, word_count, last_crawled, language, deleted_at)
+VALUES
+    (1, 'The Rust Programming Language', 'An introductory book about Rust',
+        'https://doc.rust-lang.org/book/', 1200, '2024-03-01T00:00:00Z', 'en', NULL),
+    (2, 'Rust Programming Language', 'A language empowering everyone to build reliable software',
+        'https://www.rust-lang.org/', 400, '2024-02-01T00:00:00Z', 'en', NULL),
+    (3, 'Tokio, an async runtime for Rust', 'Build reliable network applications with async Rust',
+        'https://tokio.rs/', 600, '2024-01-15T00:00:00Z', 'en', NULL),
+    (4, 'Rust', 'Iron oxide formed by the reaction of iron and oxygen',
+        'https://en.wikipedia.org/wiki/Rust', 2500, '2023-12-01T00:00:00Z', 'en', NULL),
+    (5, 'Jaguar', 'The jaguar is a large cat native to the Americas',
+        'https://en.wikipedia.org/wiki/Jaguar', 1800, '2023-11-01T00:00:00Z', 'en', NULL),
+    (6, 'Jaguar cars', 'Luxury car models from Jaguar',
+        'https://jaguar.example.com/cars', 300, '2024-04-01T00:00:00Z', 'en', NULL),
+    (7, 'Le café', 'Un café au coin de la rue',
+        'https://www.example.fr/cafe', 200, '2024-05-01T00:00:00Z', 'fr', NULL),
+    (8, 'Gone rust page', 'A page about rust the crawler no longer finds',
+        'https://old.example.com/gone', 100, '2024-02-10T00:00:00Z', 'en', '2024-03-10T00:00:00Z'),
+    (9, 'Async Rust in practice', 'Notes on async programming in Rust',
+        'https://blog.example.net/async-rust', 450, '2024-05-10T00:00:00Z', 'en', NULL),
+    (10, 'Python documentation', 'The official Python programming language documentation',
+        'https://docs.python.org/3/', 3000, '2024-01-01T00:00:00Z', 'en', NULL);
+
+SELECT setval('websites_id_seq', 10);
+
+INSERT INTO keywords (id, word)
+VALUES
+    (1, 'rust'), (2, 'programming'), (3, 'language'), (4, 'book'), (5, 'async'),
+    (6, 'runtime'), (7, 'iron'), (8, 'jaguar'), (9, 'cat'), (10, 'car'), (11, 'cafe'),
+    (12, 'python'), (13, 'gone');
+
+SELECT setval('keywords_id_seq', 13);
+
+INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences)
+VALUES
+    (1, 1, 40), (2, 1, 12), (3, 1, 15), (4, 1, 8),
+    (1, 2, 20), (2, 2, 6), (3, 2, 9),
+    (1, 3, 10), (5, 3, 25), (6, 3, 14),
+    (1, 4, 60), (7, 4, 45),
+    (8, 5, 50), (9, 5, 30),
+    (8, 6, 12), (10, 6, 18),
+    (11, 7, 6),
+    (1, 8, 5), (13, 8, 3),
+    (1, 9, 9), (5, 9, 9), (2, 9, 3),
+    (12, 10, 40), (2, 10, 20), (3, 10, 18);
+
+UPDATE keywords k
+SET documents_containing_word = (
+    SELECT COUNT(*) FROM website_keywords wk WHERE wk.keyword_id = k.id
+);
+
+INSERT INTO website_links (source_website_id, target_website, link_text)
+VALUES
+    (2, 'https://doc.rust-lang.org/book/', 'the book'),
+    (3, 'https://doc.rust-lang.org/book/', NULL),
+    (9, 'https://doc.rust-lang.org/book/', 'learn rust'),
+    (9, 'https://tokio.rs/', 'asynchronous runtime'),
+    (1, 'https://www.rust-lang.org/', NULL),
+    (6, 'https://en.wikipedia.org/wiki/Jaguar', 'the big cat'),
+    (10, 'https://www.rust-lang.org/', NULL);
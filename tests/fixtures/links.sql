@@ -0,0 +1,12 @@
+-- Extra links on top of `index.sql`: a richly linked page, and a dangling URL that no crawled
+-- page has but several link to.
+INSERT INTO website_links (source_website_id, target_website, link_text)
+VALUES
+    (4, 'https://doc.rust-lang.org/book/', NULL),
+    (7, 'https://doc.rust-lang.org/book/', 'un livre'),
+    (1, 'https://tokio.rs/', NULL),
+    (1, 'https://crates.io/', 'crates.io'),
+    (1, 'https://en.wikipedia.org/wiki/Rust', NULL),
+    (1, 'https://www.rust-lang.org/tools', NULL),
+    (2, 'https://crates.io/', 'packages'),
+    (3, 'https://crates.io/', NULL);
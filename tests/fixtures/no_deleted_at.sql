@@ -0,0 +1,3 @@
+-- An older schema from before the crawler tombstoned pages: `websites` has no `deleted_at`, so
+-- the formerly tombstoned page is as live as any other
+ALTER TABLE websites DROP COLUMN deleted_at;
@@ -0,0 +1,16 @@
+-- An index of a single page, whose keyword counts are stale: the crawler still counts pages it
+-- has since dropped
+INSERT INTO websites (id, title, description, url, word_count, last_crawled)
+VALUES
+    (1, 'The Rust Programming Language', 'An introductory book about Rust',
+        'https://doc.rust-lang.org/book/', 1200, '2024-03-01T00:00:00Z');
+
+SELECT setval('websites_id_seq', 1);
+
+INSERT INTO keywords (id, word, documents_containing_word)
+VALUES (1, 'rust', 5), (2, 'book', 1);
+
+SELECT setval('keywords_id_seq', 2);
+
+INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences)
+VALUES (1, 1, 40), (2, 1, 8);
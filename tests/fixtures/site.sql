@@ -0,0 +1,31 @@
+-- Pages on example.org and its subdomains, some spelt unusually, plus look-alike hosts that
+-- aren't on it and a tombstoned page that is.
+INSERT INTO websites (id, title, description, url, word_count, last_crawled, deleted_at)
+VALUES
+    (1, 'Example', 'The apex page', 'https://example.org/', 100,
+        '2024-01-01T00:00:00Z', NULL),
+    (2, 'About', 'About the example', 'https://www.example.org/about', 800,
+        '2024-05-01T00:00:00Z', NULL),
+    (3, 'A post', 'A blog post', 'https://blog.example.org/post', 300,
+        '2024-03-01T00:00:00Z', NULL),
+    (4, 'Nested', 'Deep down, on another port', 'https://deep.blog.example.org:8443/nested', 50,
+        '2024-04-01T00:00:00Z', NULL),
+    (5, 'Shouting', 'An upper-case host', 'https://Example.ORG/Upper?x=1', 400,
+        '2024-02-01T00:00:00Z', NULL),
+    (6, 'Decoy', 'Ends like the domain', 'https://notexample.org/decoy', 1000,
+        '2024-06-01T00:00:00Z', NULL),
+    (7, 'Decoy', 'Starts like the domain', 'https://example.org.evil.com/decoy', 1000,
+        '2024-06-01T00:00:00Z', NULL),
+    (8, 'Gone', 'No longer there', 'https://old.example.org/gone', 900,
+        '2024-06-01T00:00:00Z', '2024-07-01T00:00:00Z');
+
+SELECT setval('websites_id_seq', 8);
+
+INSERT INTO website_links (source_website_id, target_website)
+VALUES
+    (1, 'https://blog.example.org/post'),
+    (2, 'https://blog.example.org/post'),
+    (6, 'https://blog.example.org/post'),
+    (1, 'https://www.example.org/about'),
+    (2, 'https://example.org/'),
+    (3, 'https://example.org/');
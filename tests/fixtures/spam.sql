@@ -0,0 +1,20 @@
+-- Poker pages: a long guide mentioning a casino in passing, and a short page stuffed with
+-- casino bonus spam that would otherwise outrank it.
+INSERT INTO websites (id, title, description, url, word_count, last_crawled, language)
+VALUES
+    (1, 'Poker strategy', 'A guide to playing poker well', 'https://guide.example.com/poker',
+        1000, '2024-03-01T00:00:00Z', 'en'),
+    (2, 'Poker casino bonus', 'Best casino bonus poker bonus casino', 'https://bonus.example.com/',
+        200, '2024-03-01T00:00:00Z', 'en'),
+    (3, 'Card games', 'Rules for card games', 'https://cards.example.com/', 500,
+        '2024-03-01T00:00:00Z', 'en');
+
+SELECT setval('websites_id_seq', 3);
+
+INSERT INTO keywords (id, word, documents_containing_word)
+VALUES (1, 'poker', 2), (2, 'casino', 2), (3, 'bonus', 1), (4, 'card', 1);
+
+SELECT setval('keywords_id_seq', 4);
+
+INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences)
+VALUES (1, 1, 30), (2, 1, 1), (1, 2, 20), (2, 2, 20), (3, 2, 15), (4, 3, 10);
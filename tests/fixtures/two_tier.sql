@@ -0,0 +1,23 @@
+-- Pages whose quick SQL pre-ranking disagrees with the full ranking: the page that mentions
+-- `ferris` most densely is a scrap of notes, while the page titled and named for it mentions
+-- it less often.
+INSERT INTO websites (id, title, description, url, word_count, last_crawled, language)
+VALUES
+    (1, 'Crab notes', 'Scraps about crabs', 'https://notes.example.com/crabs', 100,
+        '2024-03-01T00:00:00Z', 'en'),
+    (2, 'Ferris', 'Ferris, the unofficial mascot of Rust', 'https://ferris.example.com/', 1000,
+        '2024-03-01T00:00:00Z', 'en'),
+    (3, 'Miscellany', 'Odds and ends', 'https://misc.example.com/', 1000,
+        '2024-03-01T00:00:00Z', 'en'),
+    (4, 'Elsewhere', 'Nothing about crabs', 'https://elsewhere.example.com/', 500,
+        '2024-03-01T00:00:00Z', 'en');
+
+SELECT setval('websites_id_seq', 4);
+
+INSERT INTO keywords (id, word, documents_containing_word)
+VALUES (1, 'ferris', 3), (2, 'elsewhere', 1);
+
+SELECT setval('keywords_id_seq', 2);
+
+INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences)
+VALUES (1, 1, 20), (1, 2, 30), (1, 3, 10), (2, 4, 5);
@@ -0,0 +1,28 @@
+-- Pages with "async" and "rust" in various orders. The first two have the positions of their
+-- words recorded, in order and reversed; the other two only have their titles to go on.
+INSERT INTO websites (id, title, description, url, word_count, last_crawled, language)
+VALUES
+    (1, 'Ferris the crab', 'A mascot', 'https://positions.example.com/in-order', 100,
+        '2024-03-01T00:00:00Z', 'en'),
+    (2, 'Crabs', 'More crabs', 'https://positions.example.com/reversed', 100,
+        '2024-03-01T00:00:00Z', 'en'),
+    (3, 'Async Rust guide', 'A guide', 'https://titles.example.com/in-order', 100,
+        '2024-03-01T00:00:00Z', 'en'),
+    (4, 'Rust with async', 'Another guide', 'https://titles.example.com/apart', 100,
+        '2024-03-01T00:00:00Z', 'en'),
+    (5, 'Gardening', 'Plants', 'https://garden.example.com/', 100, '2024-03-01T00:00:00Z', 'en');
+
+SELECT setval('websites_id_seq', 5);
+
+INSERT INTO keywords (id, word, documents_containing_word)
+VALUES (1, 'async', 4), (2, 'rust', 4), (3, 'plant', 1);
+
+SELECT setval('keywords_id_seq', 3);
+
+INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences)
+VALUES (1, 1, 2), (2, 1, 2), (1, 2, 2), (2, 2, 2), (1, 3, 2), (2, 3, 2), (1, 4, 2), (2, 4, 2),
+    (3, 5, 2);
+
+-- Page 1 has "async rust" at words 10 and 11; page 2 only "rust async" and the words apart
+INSERT INTO website_keyword_positions (website_id, keyword_id, positions)
+VALUES (1, 1, '{10, 40}'), (1, 2, '{11, 30}'), (2, 1, '{21, 50}'), (2, 2, '{20, 60}');
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{ result_urls, TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+
+/// The fixture's French page, titled "Le café", whose keyword is stored folded as `cafe`
+const CAFE: &str = "https://www.example.fr/cafe";
+
+async fn search(server: &TestServer, query: &str) -> Vec<String> {
+    let request = server.client.get(server.url("/")).query(&[("q", query)]);
+    let (status, _, body) = common::json_response(request).await;
+    assert_eq!(status, StatusCode::OK, "{}: {}", query, body);
+    result_urls(&body)
+}
+
+#[tokio::test]
+async fn accented_and_unaccented_forms_match_on_every_path() {
+    let Some(db) = TestDb::create("folding_paths", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    for query in [
+        // Keyword lookup, however the accent was typed
+        "cafe",
+        "café",
+        "CAFÉ",
+        "cafe\u{301}",
+        "ｃａｆé",
+        // Field scopes, phrases and fuzzy terms compare folded page text
+        "title:café",
+        "title:CAFE",
+        "desc:café",
+        "\"café au coin\"",
+        "\"CAFE AU COIN\"",
+        "café~",
+    ] {
+        assert_eq!(search(&server, query).await, [CAFE], "{}", query);
+    }
+
+    // So does the verbatim filter, checking the page's own accented title
+    for query in ["le cafe", "LE CAFÉ"] {
+        let request = server.client
+            .get(server.url("/"))
+            .query(&[("q", query), ("verbatim_only", "true")]);
+        let (_, _, body) = common::json_response(request).await;
+        assert_eq!(result_urls(&body), [CAFE], "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn exception_letters_are_searched_as_typed() {
+    let Some(db) = TestDb::create("folding_exceptions", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("DIACRITIC_FOLDING_EXCEPTIONS", "é")]).await;
+
+    // The index stores `cafe`, which an unfolded `café` is no longer the same word as
+    assert_eq!(search(&server, "cafe").await, [CAFE]);
+    assert_eq!(search(&server, "café").await, Vec::<String>::new());
+    assert_eq!(search(&server, "cafe\u{301}").await, Vec::<String>::new());
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::time::{ Duration, Instant };
+
+/// Searches sharing pages, so later ones reuse the fragments earlier ones formatted
+const SEARCHES: &[&str] = &[
+    "rust",
+    "rust%20programming&links=true",
+    "rust%20language&explain=true",
+    "programming&min_score=0",
+];
+
+/// The results of a search, serialised
+async fn results(server: &TestServer, search: &str) -> String {
+    let (status, _, body) = server.get_json(&format!("/?q={}", search)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["results"].to_string()
+}
+
+async fn fragment_stats(server: &TestServer) -> Value {
+    let (_, _, metrics) = server.get_admin("/admin/metrics").await;
+    let metrics: Value = serde_json::from_str(&metrics).unwrap();
+    metrics["fragment_cache"].clone()
+}
+
+#[tokio::test]
+async fn formats_the_same_results_as_without_the_cache() {
+    let Some(db) = TestDb::create("fragment_cache_identical", &[INDEX]).await else {
+        return;
+    };
+    let cached = TestServer::start(&db, &[]).await;
+    let uncached = TestServer::start(&db, &[("FRAGMENT_CACHE_BYTES", "0")]).await;
+
+    for snippets in ["false", "true"] {
+        for search in SEARCHES {
+            let search = format!("{}&snippets={}", search, snippets);
+            let expected = results(&uncached, &search).await;
+            assert_eq!(results(&cached, &search).await, expected, "{}", search);
+        }
+    }
+
+    let stats = fragment_stats(&cached).await;
+    assert!(stats["hits"].as_u64().unwrap() > 0, "{}", stats);
+    // A fragment per page and snippet setting, each weighed in bytes
+    assert!(stats["entries"].as_u64().unwrap() > 0, "{}", stats);
+    assert!(stats["weight"].as_u64().unwrap() > stats["entries"].as_u64().unwrap() * 100);
+    let stats = fragment_stats(&uncached).await;
+    assert_eq!(stats["entries"], 0, "{}", stats);
+    assert_eq!(stats["hits"], 0, "{}", stats);
+}
+
+#[tokio::test]
+async fn drops_fragments_when_the_index_changes() {
+    let Some(db) = TestDb::create("fragment_cache_stale", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("INDEX_REFRESH_SECS", "1")]).await;
+    let (_, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(body["results"][0]["title"], "The Rust Programming Language");
+    assert!(fragment_stats(&server).await["entries"].as_u64().unwrap() > 0);
+
+    // The crawler recrawls the book under a new title
+    sqlx::query("UPDATE websites SET title = 'The Book', last_crawled = now() WHERE id = 1")
+        .execute(&db.pool).await
+        .unwrap();
+    let started = Instant::now();
+    while !server.log().contains("The index changed") {
+        assert!(started.elapsed() < Duration::from_secs(10), "Not refreshed:\n{}", server.log());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let stats = fragment_stats(&server).await;
+    assert_eq!(stats["entries"], 0, "{}", stats);
+    let (_, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(body["results"][0]["url"], "https://doc.rust-lang.org/book/");
+    assert_eq!(body["results"][0]["title"], "The Book");
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::{ TestDb, TestServer };
+use reqwest::{ RequestBuilder, StatusCode };
+use serde_json::{ json, Value };
+
+const FAN_OUT: &str = "tests/fixtures/fan_out.sql";
+
+/// Three wildcards, each matching the ten most common of eleven words
+const WILDCARDS: &str = "/?q=fana*%20fanb*%20fanc*&debug=true";
+
+async fn search(request: RequestBuilder) -> Value {
+    let (status, _, body) = common::json_response(request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body
+}
+
+fn warning_codes(body: &Value) -> Vec<&str> {
+    body["warnings"]
+        .as_array()
+        .map(|warnings| warnings.iter().map(|warning| warning["code"].as_str().unwrap()).collect())
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn keeps_the_rarest_terms_of_a_wildcard_fan_out() {
+    let Some(db) = TestDb::create("keyword_budget_fan_out", &[FAN_OUT]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("KEYWORD_BUDGET", "8")]).await;
+
+    let body = search(server.client.get(server.url(WILDCARDS))).await;
+    let mut kept: Vec<&str> = body["lemmatised_keywords"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|word| word.as_str().unwrap())
+        .collect();
+    kept.sort();
+    // The two rarest of each family, and the first two families' third rarest
+    assert_eq!(kept, ["fana1", "fana2", "fana3", "fanb1", "fanb2", "fanb3", "fanc1", "fanc2"]);
+    assert_eq!(body["debug"]["pipeline"]["query_terms"], 8);
+    assert_eq!(body["debug"]["pipeline"]["keywords_dropped"], 22);
+    assert!(!body["results"].as_array().unwrap().is_empty());
+
+    let dropped = body["warnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|warning| warning["code"] == "keywords_dropped")
+        .expect("No warning about the dropped terms");
+    assert_eq!(dropped["field"], "q");
+    let message = dropped["message"].as_str().unwrap();
+    assert!(message.starts_with("The query has more than 8 distinct terms"), "{}", message);
+    let listed: Vec<&str> = message.rsplit(": ").next().unwrap().split(", ").collect();
+    assert_eq!(listed.len(), 22);
+    assert!(listed.contains(&"fana10") && listed.contains(&"fanc3"), "{}", message);
+    assert!(kept.iter().all(|word| !listed.contains(word)));
+}
+
+#[tokio::test]
+async fn leaves_queries_within_the_budget_alone() {
+    let Some(db) = TestDb::create("keyword_budget_normal", &[FAN_OUT]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("KEYWORD_BUDGET", "8")]).await;
+
+    let (status, _, body) = server.get_json("/?q=fana1%20fanb2%20fanc10&debug=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["lemmatised_keywords"], json!(["fana1", "fanb2", "fanc10"]));
+    assert_eq!(body["debug"]["pipeline"]["query_terms"], 3);
+    assert_eq!(body["debug"]["pipeline"]["keywords_dropped"], 0);
+    assert_eq!(body.get("warnings"), None);
+
+    // Exactly the budget is still within it
+    let query = "fana1%20fana2%20fana3%20fana4%20fana5%20fana6%20fana7%20fana8";
+    let (_, _, body) = server.get_json(&format!("/?q={}&debug=true", query)).await;
+    assert_eq!(body["debug"]["pipeline"]["query_terms"], 8);
+    assert_eq!(body.get("warnings"), None);
+}
+
+#[tokio::test]
+async fn allows_admins_and_api_keys_more_terms() {
+    let Some(db) = TestDb::create("keyword_budget_privileged", &[FAN_OUT]).await else {
+        return;
+    };
+    let keys = common::temp_file("keyword_budget_keys.txt", "key-a\n");
+    let env = [
+        ("KEYWORD_BUDGET", "8"),
+        ("KEYWORD_BUDGET_PRIVILEGED", "64"),
+        ("API_KEYS_FILE", keys.to_str().unwrap()),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    for request in [
+        server.client.get(server.url(WILDCARDS)).header("X-Admin-Token", common::ADMIN_TOKEN),
+        server.client.get(server.url(WILDCARDS)).header("X-Api-Key", "key-a"),
+    ] {
+        let body = search(request).await;
+        assert_eq!(body["debug"]["pipeline"]["query_terms"], 30);
+        assert_eq!(body["debug"]["pipeline"]["keywords_dropped"], 0);
+        assert!(!warning_codes(&body).contains(&"keywords_dropped"), "{}", body["warnings"]);
+    }
+
+    // An unknown key gets the public budget
+    let request = server.client.get(server.url(WILDCARDS)).header("X-Api-Key", "key-unknown");
+    let body = search(request).await;
+    assert_eq!(body["debug"]["pipeline"]["query_terms"], 8);
+    assert!(warning_codes(&body).contains(&"keywords_dropped"));
+}
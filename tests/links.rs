@@ -0,0 +1,138 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+const LINKS: &str = "tests/fixtures/links.sql";
+
+/// The sources listed in a `/links` body, checking each links to the URL once
+fn sources(body: &Value) -> Vec<&str> {
+    body["links_from"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|source| {
+            assert_eq!(source["occurrences"], 1);
+            source["link"].as_str().unwrap()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn describes_a_richly_linked_page() {
+    let Some(db) = TestDb::create("links_rich", &[INDEX, LINKS]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    // The fragment is dropped, as search results do
+    let (status, _, body) = server.get_json(
+        "/links?url=https%3A%2F%2Fdoc.rust-lang.org%2Fbook%2F%23intro"
+    ).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["url"], "https://doc.rust-lang.org/book/");
+    assert_eq!(body["in_index"], true);
+    assert_eq!(body["title"], "The Rust Programming Language");
+    assert_eq!(body["links_to_count"], 5);
+    assert_eq!(body["linked_from_count"], 5);
+    assert_eq!(
+        sources(&body),
+        [
+            "https://blog.example.net/async-rust",
+            "https://en.wikipedia.org/wiki/Rust",
+            "https://tokio.rs/",
+            "https://www.example.fr/cafe",
+            "https://www.rust-lang.org/",
+        ]
+    );
+
+    // Pages of sources, in the same order, with the total still counting them all
+    let (_, _, page) = server.get_json(
+        "/links?url=https://doc.rust-lang.org/book/&limit=2&offset=1"
+    ).await;
+    assert_eq!(page["linked_from_count"], 5);
+    assert_eq!((page["limit"].clone(), page["offset"].clone()), (json!(2), json!(1)));
+    assert_eq!(sources(&page), sources(&body)[1..3]);
+    let (_, _, past_the_end) = server.get_json(
+        "/links?url=https://doc.rust-lang.org/book/&offset=10"
+    ).await;
+    assert_eq!(sources(&past_the_end), Vec::<&str>::new());
+
+    // Limits are bounded
+    let (_, _, clamped) = server.get_json(
+        "/links?url=https://doc.rust-lang.org/book/&limit=100000&offset=-3"
+    ).await;
+    assert_eq!((clamped["limit"].clone(), clamped["offset"].clone()), (json!(200), json!(0)));
+}
+
+#[tokio::test]
+async fn describes_a_url_only_seen_as_a_link_target() {
+    let Some(db) = TestDb::create("links_dangling", &[INDEX, LINKS]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (status, _, body) = server.get_json("/links?url=https://crates.io/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["in_index"], false);
+    assert_eq!(body["title"], Value::Null);
+    assert_eq!(body["links_to_count"], Value::Null);
+    assert_eq!(body["linked_from_count"], 3);
+    assert_eq!(
+        sources(&body),
+        ["https://doc.rust-lang.org/book/", "https://tokio.rs/", "https://www.rust-lang.org/"]
+    );
+}
+
+#[tokio::test]
+async fn answers_unknown_urls_with_a_hint() {
+    let Some(db) = TestDb::create("links_unknown", &[INDEX, LINKS]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    // Another spelling of an indexed page, and of a URL only linked to
+    for (url, suggestion) in [
+        ("http://tokio.rs", "https://tokio.rs/"),
+        ("https://www.tokio.rs/", "https://tokio.rs/"),
+        ("http://www.crates.io", "https://crates.io/"),
+        ("https://rust-lang.org/tools/", "https://www.rust-lang.org/tools"),
+    ] {
+        let (status, _, body) = server.get_json(&format!("/links?url={}", url)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", url);
+        assert_eq!(body["error"], "URL not found in the index");
+        assert_eq!(body["did_you_mean"], suggestion, "{}", url);
+    }
+
+    let (status, _, body) = server.get_json("/links?url=https://nowhere.example/").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body.get("did_you_mean").is_none());
+
+    for query in ["/links", "/links?url=ftp://tokio.rs/", "/links?url=not%20a%20url"] {
+        let (status, _, body) = server.get_json(query).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+        assert_eq!(body["error"], "Missing or invalid url parameter");
+    }
+}
+
+#[tokio::test]
+async fn caches_each_url_briefly() {
+    let Some(db) = TestDb::create("links_cached", &[INDEX, LINKS]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("LINKS_CACHE_TTL_SECS", "1s")]).await;
+
+    let (_, _, before) = server.get_json("/links?url=https://tokio.rs/").await;
+    assert_eq!(before["linked_from_count"], 2);
+    sqlx::query("INSERT INTO website_links (source_website_id, target_website) VALUES (5, $1)")
+        .bind("https://tokio.rs/")
+        .execute(&db.pool).await
+        .unwrap();
+
+    let (_, _, cached) = server.get_json("/links?url=https://tokio.rs/").await;
+    assert_eq!(cached, before);
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let (_, _, refreshed) = server.get_json("/links?url=https://tokio.rs/").await;
+    assert_eq!(refreshed["linked_from_count"], 3);
+}
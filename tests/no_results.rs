@@ -0,0 +1,114 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+/// The `no_results` object of a search that should find nothing
+async fn no_results(server: &TestServer, params: &str) -> Value {
+    let (status, _, body) = server.get_json(&format!("/?q={}", params)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["results"], json!([]), "{}", params);
+    assert!(body["no_results"].is_object(), "No diagnostics for {}: {}", params, body);
+    body["no_results"].clone()
+}
+
+fn action(no_results: &Value) -> &str {
+    no_results["suggestion"]["action"].as_str().unwrap_or_default()
+}
+
+#[tokio::test]
+async fn reports_terms_missing_from_the_index() {
+    let Some(db) = TestDb::create("no_results_unknown", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let diagnosed = no_results(&server, "zzzqqq").await;
+    assert_eq!(
+        diagnosed["terms"],
+        json!([{ "term": "zzzqqq", "indexed": false, "documents": 0 }])
+    );
+    assert_eq!(action(&diagnosed), "drop_terms");
+    assert_eq!(diagnosed["suggestion"]["terms"], json!(["zzzqqq"]));
+    assert_eq!(diagnosed["candidates"]["fetched"], 0);
+
+    // Looked up as typed, a plural isn't indexed, though its lemma is
+    let diagnosed = no_results(&server, "languages&verbatim=true").await;
+    assert_eq!(diagnosed["terms"][0]["indexed"], false);
+    assert_eq!(action(&diagnosed), "disable_verbatim");
+
+    // Nothing is left out when there are results
+    let (_, _, body) = server.get_json("/?q=rust%20zzzqqq").await;
+    assert!(!body["results"].as_array().unwrap().is_empty());
+    assert_eq!(body.get("no_results"), None);
+}
+
+#[tokio::test]
+async fn reports_known_terms_filtered_out() {
+    let Some(db) = TestDb::create("no_results_filtered", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    for params in ["rust%20-rust", "rust%20site:nowhere.example", "rust&lang=fr"] {
+        let diagnosed = no_results(&server, params).await;
+        assert_eq!(
+            diagnosed["terms"],
+            json!([{ "term": "rust", "indexed": true, "documents": 6 }]),
+            "{}",
+            params
+        );
+        assert_eq!(action(&diagnosed), "drop_filters", "{}", params);
+        assert_eq!(diagnosed["candidates"]["filtered_in_fetch"], true, "{}", params);
+    }
+
+    let diagnosed = no_results(&server, "RUST&case_sensitive=true").await;
+    assert_eq!(action(&diagnosed), "disable_case_sensitive");
+    assert_eq!(diagnosed["candidates"]["fetched"], 5);
+    assert_eq!(diagnosed["candidates"]["wrong_case"], 5);
+
+    let diagnosed = no_results(&server, "rust%20language&verbatim_only=true").await;
+    assert_eq!(action(&diagnosed), "disable_verbatim_only");
+    assert_eq!(diagnosed["candidates"]["not_verbatim"], 4);
+
+    // Answered from the result cache, there are no counts to go on
+    let diagnosed = no_results(&server, "rust%20-rust").await;
+    assert_eq!(diagnosed["candidates"], Value::Null);
+    assert_eq!(diagnosed["terms"][0]["indexed"], true);
+}
+
+#[tokio::test]
+async fn reports_known_terms_below_the_threshold() {
+    let Some(db) = TestDb::create("no_results_threshold", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    // Both words are indexed, but no page has both
+    let diagnosed = no_results(&server, "rust%20jaguar&mode=strict").await;
+    assert!(diagnosed["terms"].as_array().unwrap().iter().all(|term| term["indexed"] == true));
+    assert_eq!(action(&diagnosed), "loose_mode");
+
+    let diagnosed = no_results(&server, "rust%20jaguar&min_match=100%25").await;
+    assert_eq!(action(&diagnosed), "fewer_terms");
+    let fetched = &diagnosed["candidates"]["fetched"];
+    assert!(fetched.as_u64().unwrap() > 0);
+    assert_eq!(&diagnosed["candidates"]["below_threshold"], fetched);
+
+    let diagnosed = no_results(&server, "rust%20language&min_score=2").await;
+    assert_eq!(action(&diagnosed), "lower_min_score");
+    assert_eq!(diagnosed["candidates"]["below_threshold"], 6);
+}
+
+#[tokio::test]
+async fn reports_an_empty_index() {
+    let Some(db) = TestDb::create("no_results_empty", &[]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let diagnosed = no_results(&server, "rust").await;
+    assert_eq!(action(&diagnosed), "wait_for_index");
+    assert_eq!(diagnosed["terms"], json!([{ "term": "rust", "indexed": false, "documents": 0 }]));
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::{ result_urls, TestDb, TestServer, INDEX };
+use std::time::{ Duration, Instant };
+
+/// How long the index refresh and the recomputation it starts get to finish
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Waits until authority scores are published and no computation is running.
+async fn wait_for_scores(server: &TestServer) {
+    let started = Instant::now();
+    loop {
+        let (_, _, body) = server.get_admin("/admin/metrics").await;
+        let metrics: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let pagerank = &metrics["pagerank"];
+        if pagerank["published"] == true && pagerank["computing"] == false {
+            return;
+        }
+        assert!(started.elapsed() < REFRESH_TIMEOUT, "Scores weren't published:\n{}", server.log());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[tokio::test]
+async fn rankings_follow_links_added_to_the_index() {
+    let Some(db) = TestDb::create("pagerank_refresh", &[INDEX]).await else {
+        return;
+    };
+    // Without links every page has the same authority, so relevance alone decides
+    sqlx::query("DELETE FROM website_links").execute(&db.pool).await.unwrap();
+    let env = [
+        ("INDEX_REFRESH_SECS", "1s"),
+        ("PAGERANK_WEIGHT", "5"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+    wait_for_scores(&server).await;
+
+    let (_, _, body) = server.get_json("/?q=rust").await;
+    let before = result_urls(&body);
+    let target = "https://blog.example.net/async-rust";
+    assert!(before.len() > 1);
+    assert!(before.contains(&target.to_string()));
+    assert_ne!(before[0], target);
+
+    // The crawler finds every other page linking to the target
+    sqlx::query(
+        r#"
+        INSERT INTO website_links (source_website_id, target_website)
+        SELECT id, $1 FROM websites WHERE url <> $1
+    "#
+    )
+        .bind(target)
+        .execute(&db.pool).await
+        .unwrap();
+
+    let started = Instant::now();
+    let after = loop {
+        let (_, _, body) = server.get_json("/?q=rust").await;
+        let after = result_urls(&body);
+        if after.first().map(String::as_str) == Some(target) {
+            break after;
+        }
+        assert!(
+            started.elapsed() < REFRESH_TIMEOUT,
+            "Rankings didn't change after the index did: {:?}\n{}",
+            after,
+            server.log()
+        );
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    };
+    assert_eq!(after.len(), before.len());
+    assert!(server.log().contains("The index changed"));
+
+    // Scores recomputed on refresh are stored, so they outlast a restart
+    drop(server);
+    let server = TestServer::start(&db, &[("PAGERANK_WEIGHT", "5")]).await;
+    wait_for_scores(&server).await;
+    assert!(server.log().contains("Loaded 10 stored authority scores."));
+    let (_, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(result_urls(&body), after);
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use common::{ result_urls, temp_file, TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::time::{ Duration, Instant };
+
+/// Searches with `debug=true` and returns the response and its pipeline trace.
+async fn traced(server: &TestServer, query: &str) -> (Value, Value) {
+    let (status, _, body) = server.get_json(&format!("/?q={}&debug=true", query)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let trace = body["debug"]["pipeline"].clone();
+    assert!(trace.is_object(), "No trace for {}: {}", query, body);
+    (body, trace)
+}
+
+#[tokio::test]
+async fn counts_what_each_stage_did() {
+    let Some(db) = TestDb::create("pipeline_stages", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (body, trace) = traced(&server, "rust").await;
+    assert_eq!(result_urls(&body).len(), 5);
+    assert_eq!(trace["candidates_fetched"], 5);
+    assert_eq!(trace["candidates_complete"], true);
+    assert_eq!(trace["query_terms"], 1);
+    assert_eq!(trace["returned"], 5);
+    assert_eq!(trace["cache"], "miss");
+    assert_eq!(trace["variant"], "full");
+    assert_eq!(trace["refinement"], "not_needed");
+    assert_eq!(trace["filtered_in_fetch"], false);
+    // Only the Rust book is linked to with "rust" in the anchor text
+    assert_eq!(trace["anchor_text_boosted"], 1);
+
+    // Asked again, it's answered from the result cache without fetching anything
+    let (_, trace) = traced(&server, "rust").await;
+    assert_eq!(trace["cache"], "result");
+    assert_eq!(trace["candidates_fetched"], 0);
+
+    // Without `debug` there's no trace
+    let (_, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(body["debug"], Value::Null);
+}
+
+#[tokio::test]
+async fn counts_the_results_each_filter_dropped() {
+    let Some(db) = TestDb::create("pipeline_dropped", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    // Six pages have one word or the other, and only two have both, neither as a phrase
+    let (body, trace) = traced(&server, "rust%20language&verbatim_only=true").await;
+    assert_eq!(result_urls(&body), Vec::<String>::new());
+    assert_eq!(trace["candidates_fetched"], 6);
+    assert_eq!(trace["dropped"]["below_threshold"], 2);
+    assert_eq!(trace["dropped"]["not_verbatim"], 4);
+    assert_eq!(trace["returned"], 0);
+
+    // No page spells it in capitals
+    let (_, trace) = traced(&server, "RUST&case_sensitive=true").await;
+    assert_eq!(trace["candidates_fetched"], 5);
+    assert_eq!(trace["dropped"]["wrong_case"], 5);
+
+    // No page has all four words
+    let (_, trace) = traced(&server, "rust%20programming%20language%20book&min_match=100%25").await;
+    assert_eq!(trace["query_terms"], 4);
+    assert_eq!(trace["dropped"]["below_threshold"], 6);
+    assert_eq!(trace["returned"], 0);
+}
+
+#[tokio::test]
+async fn counts_filters_applied_in_the_fetch() {
+    let Some(db) = TestDb::create("pipeline_fetch", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    // `site:` narrows the fetch, so nothing is dropped as off-site afterwards
+    let (body, trace) = traced(&server, "rust%20site:rust-lang.org").await;
+    assert_eq!(result_urls(&body).len(), 2);
+    assert_eq!(trace["filtered_in_fetch"], true);
+    assert_eq!(trace["candidates_fetched"], 2);
+    assert_eq!(trace["dropped"]["off_site"], 0);
+
+    let (_, trace) = traced(&server, "rust&lang=fr").await;
+    assert_eq!(trace["filtered_in_fetch"], true);
+    assert_eq!(trace["candidates_fetched"], 0);
+
+    // Strict searches only fetch the pages with every term
+    let (body, trace) = traced(&server, "rust%20async&mode=strict").await;
+    assert_eq!(
+        result_urls(&body),
+        ["https://tokio.rs/", "https://blog.example.net/async-rust"]
+    );
+    assert_eq!(trace["query_terms"], 2);
+    assert_eq!(trace["candidates_fetched"], 2);
+    assert_eq!(trace["term_closeness_boosted"], 2);
+}
+
+#[tokio::test]
+async fn counts_how_the_query_was_interpreted() {
+    let Some(db) = TestDb::create("pipeline_query", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (_, trace) = traced(&server, "the%20rust").await;
+    assert_eq!(trace["stopwords_removed"], 1);
+    assert_eq!(trace["query_terms"], 1);
+
+    let (_, trace) = traced(&server, "the%20rust&stopwords=off").await;
+    assert_eq!(trace["stopwords_removed"], 0);
+    assert_eq!(trace["query_terms"], 2);
+
+    // Every page with "rust" matches "rustt~" through it
+    let (_, trace) = traced(&server, "rustt~").await;
+    assert_eq!(trace["fuzzy_matches"], 5);
+    assert_eq!(trace["candidates_fetched"], 5);
+
+    // The book and rust-lang.org have the words next to each other
+    let (_, trace) = traced(&server, "%22rust%20programming%22").await;
+    assert_eq!(trace["phrase_matches"], 2);
+    assert_eq!(trace["verbatim_boosted"], 2);
+}
+
+#[tokio::test]
+async fn logs_the_trace_of_slow_queries_without_debug() {
+    let Some(db) = TestDb::create("pipeline_slow_log", &[INDEX]).await else {
+        return;
+    };
+    let log = temp_file("pipeline_slow.log", "");
+    let log_path = log.to_str().unwrap();
+    let env = [("SLOW_QUERY_MS", "0"), ("SLOW_QUERY_LOG_FILE", log_path)];
+    let server = TestServer::start(&db, &env).await;
+
+    let (status, _, body) = server.get_json("/?q=rust%20site:rust-lang.org").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["debug"], Value::Null);
+
+    let started = Instant::now();
+    let entry: Value = loop {
+        let written = std::fs::read_to_string(&log).unwrap_or_default();
+        if let Some(line) = written.lines().find(|line| line.contains("\"trace\"")) {
+            break serde_json::from_str(line).unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "Nothing logged:\n{}", server.log());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert_eq!(entry["endpoint"], "/");
+    assert_eq!(entry["query"], "rust site:rust-lang.org");
+    assert!(entry["duration_ms"].as_f64().unwrap() >= 0.0);
+    let trace = &entry["trace"];
+    assert_eq!(trace["cache"], "miss");
+    assert_eq!(trace["filtered_in_fetch"], true);
+    assert_eq!(trace["candidates_fetched"], 2);
+    assert_eq!(trace["returned"], 2);
+}
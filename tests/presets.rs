@@ -0,0 +1,96 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+/// The `applied_options` of a search for rust with `params` on top
+async fn applied_options(server: &TestServer, params: &str) -> Value {
+    let (status, _, body) = server.get_json(&format!("/?q=rust{}", params)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["applied_options"].clone()
+}
+
+fn features(links: bool, keywords: bool, snippets: bool, explain: bool, debug: bool) -> Value {
+    json!({
+        "links": links,
+        "keywords": keywords,
+        "snippets": snippets,
+        "explain": explain,
+        "signals": false,
+        "debug": debug,
+    })
+}
+
+#[tokio::test]
+async fn built_in_presets_fill_in_their_parameters() {
+    let Some(db) = TestDb::create("presets_built_in", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("MAX_RESULTS", "50")]).await;
+
+    let defaults = applied_options(&server, "").await;
+    assert_eq!(defaults["preset"], Value::Null);
+    assert_eq!(defaults["results"], 50);
+    assert_eq!(defaults["features"], features(false, true, false, false, false));
+
+    // Each preset's effective settings, with everything else as the defaults left it
+    let presets = [
+        ("minimal", 20, features(false, false, true, false, false)),
+        ("standard", 50, features(false, true, false, false, false)),
+        ("full", 50, features(true, true, false, true, false)),
+        ("research", 50, features(true, true, false, true, true)),
+    ];
+    for (preset, results, features) in presets {
+        let mut applied = applied_options(&server, &format!("&preset={}", preset)).await;
+        assert_eq!(applied["preset"], preset);
+        assert_eq!(applied["results"], results, "{}", preset);
+        assert_eq!(applied["features"], features, "{}", preset);
+        for key in ["preset", "results", "features"] {
+            applied[key] = defaults[key].clone();
+        }
+        assert_eq!(applied, defaults, "{}", preset);
+    }
+
+    // What the presets turn on is in the response too
+    let (_, _, body) = server.get_json("/?q=rust&preset=research").await;
+    assert!(body["debug"].is_object());
+    assert!(body["results"][0]["explain"].is_object());
+    assert!(body["results"][0]["links_to_count"].is_number());
+    let (_, _, body) = server.get_json("/?q=rust&preset=minimal").await;
+    assert!(body["results"][0].get("keywords").is_none());
+}
+
+#[tokio::test]
+async fn explicit_parameters_override_presets() {
+    let Some(db) = TestDb::create("presets_overrides", &[INDEX]).await else {
+        return;
+    };
+    let presets = common::temp_file(
+        "presets.txt",
+        "# Custom presets\nmobile results=2 snippets=true keywords=false\nminimal results=4\n"
+    );
+    let env = [("SEARCH_PRESETS_FILE", presets.to_str().unwrap())];
+    let server = TestServer::start(&db, &env).await;
+
+    let applied = applied_options(&server, "&preset=full&links=false&results=3").await;
+    assert_eq!(applied["results"], 3);
+    assert_eq!(applied["features"], features(false, true, false, true, false));
+
+    // Custom presets work like the built-in ones, and replace one they're named after
+    let applied = applied_options(&server, "&preset=mobile&keywords=true").await;
+    assert_eq!(applied["preset"], "mobile");
+    assert_eq!(applied["results"], 2);
+    assert_eq!(applied["features"], features(false, true, true, false, false));
+    let applied = applied_options(&server, "&preset=MINIMAL").await;
+    assert_eq!(applied["results"], 4);
+    assert_eq!(applied["features"], features(false, true, false, false, false));
+
+    let (status, _, body) = server.get_json("/?q=rust&preset=tablet").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["field"], "preset");
+    assert_eq!(
+        body["error"],
+        "Unknown preset \"tablet\"; valid presets are: full, minimal, mobile, research, standard"
+    );
+}
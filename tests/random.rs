@@ -0,0 +1,116 @@
+mod common;
+
+use common::{ result_urls, TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use std::collections::HashSet;
+use std::time::{ Duration, Instant };
+
+#[tokio::test]
+async fn returns_varying_live_pages_in_the_result_shape() {
+    let Some(db) = TestDb::create("random_pages", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (status, _, body) = server.get_json("/random?count=3").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["count"], 3);
+    let result = &body["results"][0];
+    assert!(result["url"].is_string() && result["title"].is_string());
+    assert!(result.get("score").is_none() && result.get("raw_score").is_none());
+
+    // Over repeated calls most of the live pages turn up, and never the tombstoned one
+    let mut seen = HashSet::new();
+    let mut orders = HashSet::new();
+    for _ in 0..30 {
+        let (_, _, body) = server.get_json("/random?count=3").await;
+        let urls = result_urls(&body);
+        assert_eq!(urls.iter().collect::<HashSet<_>>().len(), 3, "{:?}", urls);
+        orders.insert(urls.clone());
+        seen.extend(urls);
+    }
+    assert!(!seen.contains("https://old.example.com/gone"));
+    assert!(seen.len() >= 6, "{:?}", seen);
+    assert!(orders.len() > 1);
+
+    // Asking for more than there are returns them all once each
+    let (_, _, body) = server.get_json("/random?count=1000").await;
+    assert_eq!(body["count"], 9);
+    let (_, _, body) = server.get_json("/random?count=0").await;
+    assert_eq!(body["count"], 1);
+}
+
+#[tokio::test]
+async fn applies_the_filters() {
+    let Some(db) = TestDb::create("random_filters", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    // A domain covers its subdomains, however it's spelt
+    for _ in 0..5 {
+        let (_, _, body) = server.get_json("/random?count=5&domain=Example.COM.").await;
+        assert_eq!(result_urls(&body), ["https://jaguar.example.com/cars"]);
+        let (_, _, body) = server.get_json("/random?count=5&domain=rust-lang.org").await;
+        let urls: HashSet<String> = result_urls(&body).into_iter().collect();
+        assert_eq!(
+            urls,
+            HashSet::from(
+                ["https://doc.rust-lang.org/book/", "https://www.rust-lang.org/"].map(String::from)
+            )
+        );
+    }
+
+    let (_, _, body) = server.get_json("/random?count=50&min_word_count=1500").await;
+    let urls: HashSet<String> = result_urls(&body).into_iter().collect();
+    assert_eq!(
+        urls,
+        HashSet::from(
+            [
+                "https://en.wikipedia.org/wiki/Rust",
+                "https://en.wikipedia.org/wiki/Jaguar",
+                "https://docs.python.org/3/",
+            ].map(String::from)
+        )
+    );
+
+    let (status, _, body) = server.get_json("/random?min_word_count=lots").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "min_word_count must be an integer");
+}
+
+#[tokio::test]
+async fn gives_up_on_filters_matching_nothing() {
+    let Some(db) = TestDb::create("random_nothing", &[INDEX]).await else {
+        return;
+    };
+    // Enough pages that sampling starts with a small fraction of them
+    sqlx::query(
+        r#"
+        INSERT INTO websites (title, description, url, word_count, last_crawled)
+        SELECT 'Filler ' || n, '', 'https://filler.example.org/' || n, 10, now()
+        FROM generate_series(1, 20000) n
+    "#
+    )
+        .execute(&db.pool).await
+        .unwrap();
+    let server = TestServer::start(&db, &[]).await;
+
+    for query in [
+        "/random?count=50&domain=nowhere.example",
+        "/random?count=50&min_word_count=1000000",
+        "/random?count=5&domain=example.com&min_word_count=1000",
+    ] {
+        let started = Instant::now();
+        let (status, _, body) = server.get_json(query).await;
+        assert_eq!(status, StatusCode::OK, "{}", query);
+        assert_eq!(body["count"], 0, "{}", query);
+        assert_eq!(body["results"], serde_json::json!([]));
+        let elapsed = started.elapsed();
+        assert!(elapsed < Duration::from_secs(5), "{} took {:?}", query, elapsed);
+    }
+
+    // Sampling a fraction of the table still fills the page when plenty of pages match
+    let (_, _, body) = server.get_json("/random?count=5&domain=filler.example.org").await;
+    assert_eq!(body["count"], 5);
+}
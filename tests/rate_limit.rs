@@ -0,0 +1,215 @@
+mod common;
+
+use axum::{ routing::post, Form, Json, Router };
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::{ header::HeaderMap, StatusCode };
+use serde_json::{ json, Value };
+use std::collections::HashMap;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+
+fn header(headers: &HeaderMap, name: &str) -> u64 {
+    headers[name].to_str().unwrap().parse().unwrap()
+}
+
+/// Stands in for Turnstile's siteverify endpoint, accepting tokens that start with `good` after
+/// `delay`, and counting the calls it gets.
+async fn spawn_turnstile(delay: Duration) -> (String, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let router = Router::new().route(
+        "/",
+        post(move |Form(form): Form<HashMap<String, String>>| {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(delay).await;
+                let success =
+                    form.get("secret").map(String::as_str) == Some("test-secret") &&
+                    form.get("response").is_some_and(|token| token.starts_with("good"));
+                Json(json!({ "success": success }))
+            }
+        })
+    );
+    (common::spawn_mock(router).await, calls)
+}
+
+#[tokio::test]
+async fn limits_by_ip_and_resets_with_the_window() {
+    let Some(db) = TestDb::create("rate_limit_ip", &[INDEX]).await else {
+        return;
+    };
+    let env = [
+        ("RATE_LIMIT_IP_QUOTA", "3"),
+        ("RATE_LIMIT_WINDOW_SECS", "2s"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    for remaining in [2, 1, 0] {
+        let (status, headers, _) = server.get_json("/version").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(header(&headers, "ratelimit-limit"), 3);
+        assert_eq!(header(&headers, "ratelimit-remaining"), remaining);
+        assert!((1..=2).contains(&header(&headers, "ratelimit-reset")));
+    }
+
+    let (status, headers, body) = server.get_json("/stats").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["error"], "Rate limit exceeded");
+    assert_eq!(header(&headers, "ratelimit-remaining"), 0);
+    let retry_after = header(&headers, "retry-after");
+    assert!((1..=2).contains(&retry_after));
+    assert_eq!(retry_after, header(&headers, "ratelimit-reset"));
+
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    let (status, headers, _) = server.get_json("/version").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(header(&headers, "ratelimit-limit"), 3);
+    assert_eq!(header(&headers, "ratelimit-remaining"), 2);
+    assert_eq!(header(&headers, "ratelimit-reset"), 2);
+}
+
+#[tokio::test]
+async fn gives_each_api_key_its_own_quota() {
+    let Some(db) = TestDb::create("rate_limit_keys", &[INDEX]).await else {
+        return;
+    };
+    let keys = common::temp_file("rate_limit_keys.txt", "# test keys\nkey-a 2\nkey-b\n");
+    let env = [
+        ("API_KEYS_FILE", keys.to_str().unwrap()),
+        ("RATE_LIMIT_IP_QUOTA", "1"),
+        ("RATE_LIMIT_API_KEY_QUOTA", "5"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+    let get = |key: &'static str| {
+        common::json_response(server.client.get(server.url("/version")).header("X-Api-Key", key))
+    };
+
+    // A key's own quota, and otherwise the default one
+    let (_, headers, _) = get("key-a").await;
+    assert_eq!(header(&headers, "ratelimit-limit"), 2);
+    assert_eq!(header(&headers, "ratelimit-remaining"), 1);
+    let (_, headers, _) = get("key-b").await;
+    assert_eq!(header(&headers, "ratelimit-limit"), 5);
+    assert_eq!(header(&headers, "ratelimit-remaining"), 4);
+
+    // An unknown key counts against the client's IP address
+    let (status, headers, _) = get("key-unknown").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(header(&headers, "ratelimit-limit"), 1);
+    let (status, _, _) = server.get_json("/version").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Exhausting the IP's quota leaves the keys' quotas alone
+    let (status, headers, _) = get("key-a").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(header(&headers, "ratelimit-remaining"), 0);
+    let (status, _, _) = get("key-a").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let (status, _, _) = get("key-b").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn validated_turnstile_tokens_get_the_token_quota() {
+    let Some(db) = TestDb::create("rate_limit_tokens", &[INDEX]).await else {
+        return;
+    };
+    let (verify_url, calls) = spawn_turnstile(Duration::ZERO).await;
+    let env = [
+        ("TURNSTILE_ENABLED", "true"),
+        ("TURNSTILE_VERIFY_URL", verify_url.as_str()),
+        ("RATE_LIMIT_IP_QUOTA", "10"),
+        ("RATE_LIMIT_TOKEN_QUOTA", "4"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+    let search = |token: &'static str| {
+        common::json_response(
+            server.client.get(server.url("/?q=rust")).header("X-Turnstile-Token", token)
+        )
+    };
+
+    // Until Turnstile has accepted it, a token counts against the client's IP address
+    let (status, headers, _) = search("good-1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(header(&headers, "ratelimit-limit"), 10);
+
+    let (status, headers, _) = search("good-1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(header(&headers, "ratelimit-limit"), 4);
+    assert_eq!(header(&headers, "ratelimit-remaining"), 3);
+    assert_eq!(calls.load(Ordering::SeqCst), 1, "A validated token should be cached");
+
+    let (status, headers, body) = search("bad-1").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "Invalid Turnstile token");
+    assert_eq!(header(&headers, "ratelimit-limit"), 10);
+    assert_eq!(header(&headers, "ratelimit-remaining"), 8);
+
+    let (status, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "Missing Turnstile token");
+}
+
+#[tokio::test]
+async fn turnstile_calls_do_not_wait_on_each_other() {
+    let Some(db) = TestDb::create("rate_limit_concurrent", &[INDEX]).await else {
+        return;
+    };
+    let delay = Duration::from_millis(500);
+    let (verify_url, calls) = spawn_turnstile(delay).await;
+    let env = [
+        ("TURNSTILE_ENABLED", "true"),
+        ("TURNSTILE_VERIFY_URL", verify_url.as_str()),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    let clients = 6;
+    let started = Instant::now();
+    let requests = (0..clients).map(|i| {
+        common::json_response(
+            server.client
+                .get(server.url("/?q=rust"))
+                .header("X-Turnstile-Token", format!("good-{}", i))
+        )
+    });
+    let responses: Vec<(StatusCode, HeaderMap, Value)> = futures::future::join_all(requests).await;
+    let elapsed = started.elapsed();
+
+    assert!(responses.iter().all(|(status, _, _)| *status == StatusCode::OK));
+    assert_eq!(calls.load(Ordering::SeqCst), clients);
+    // One after another they would take `clients * delay`
+    assert!(elapsed < delay * 4, "Validating {} tokens took {:?}", clients, elapsed);
+}
+
+#[tokio::test]
+async fn shares_a_quota_across_the_identity_prefix() {
+    let Some(db) = TestDb::create("rate_limit_prefix", &[INDEX]).await else {
+        return;
+    };
+    let env = [
+        ("RATE_LIMIT_IP_QUOTA", "2"),
+        ("RATE_LIMIT_WINDOW_SECS", "60s"),
+        ("IPV4_IDENTITY_PREFIX", "24"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+    // Clients connecting from different loopback addresses
+    let from = |address: &str| {
+        reqwest::Client::builder()
+            .local_address(address.parse::<std::net::IpAddr>().unwrap())
+            .build()
+            .unwrap()
+    };
+    let status = |client: reqwest::Client| {
+        let url = server.url("/version");
+        async move { client.get(url).send().await.unwrap().status() }
+    };
+
+    // 127.0.0.1 and 127.0.0.2 are one /24, so they share its quota
+    assert_eq!(status(from("127.0.0.1")).await, StatusCode::OK);
+    assert_eq!(status(from("127.0.0.2")).await, StatusCode::OK);
+    assert_eq!(status(from("127.0.0.3")).await, StatusCode::TOO_MANY_REQUESTS);
+    // Another /24 has its own
+    assert_eq!(status(from("127.0.1.1")).await, StatusCode::OK);
+}
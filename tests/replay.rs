@@ -0,0 +1,137 @@
+mod common;
+
+use common::{ run_command, temp_file, TestDb, TestServer, ADMIN_TOKEN, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+use std::path::Path;
+use std::time::{ Duration, Instant };
+
+/// Captures a search for each of `queries` into `file`
+async fn capture(server: &TestServer, file: &Path, queries: &[&str]) -> Vec<Value> {
+    let request = server.client
+        .post(server.url("/admin/capture?enabled=true"))
+        .header("X-Admin-Token", ADMIN_TOKEN);
+    let (status, _, _) = common::json_response(request).await;
+    assert_eq!(status, StatusCode::OK);
+    for query in queries {
+        let (status, _, body) = server.get_json(&format!("/?q={}", query)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    let started = Instant::now();
+    loop {
+        let written = std::fs::read_to_string(file).unwrap_or_default();
+        if written.lines().count() == queries.len() {
+            return written.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "Not captured:\n{}", server.log());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+fn replay(db: &TestDb, name: &str, args: &[&str]) -> String {
+    let (succeeded, stdout, stderr) = run_command(db, name, &[&["replay"], args].concat(), &[]);
+    assert!(succeeded, "Replay failed:\n{}\n{}", stdout, stderr);
+    stdout
+}
+
+#[tokio::test]
+async fn replays_captured_searches_and_reports_what_moved() {
+    let Some(db) = TestDb::create("replay_captured", &[INDEX]).await else {
+        return;
+    };
+    let file = temp_file("replay_captures.jsonl", "");
+    let server = TestServer::start(&db, &[("CAPTURE_FILE", file.to_str().unwrap())]).await;
+    let captured = capture(&server, &file, &["rust%20async", "jaguar", "rust&results=3"]).await;
+    drop(server);
+
+    // Each capture has what the search applied and the order it returned
+    assert_eq!(captured[0]["params"]["query"], "rust async");
+    assert_eq!(captured[2]["params"]["results"], 3);
+    assert_eq!(captured[2]["results"].as_array().unwrap().len(), 3);
+    assert!(captured.iter().all(|capture| capture["website_count"] == 9));
+    let path = file.to_str().unwrap();
+
+    // Against the same database, nothing moves
+    let report = replay(&db, "replay_same", &[path]);
+    assert!(report.contains("Replayed 3 captured searches from"), "{}", report);
+    assert!(report.contains("Mean Kendall tau: 1.000 (over 3 searches"), "{}", report);
+    assert!(report.contains("Mean result overlap: 1.000"), "{}", report);
+    assert!(!report.contains("Biggest movers"), "{}", report);
+    assert!(!report.contains("different size"), "{}", report);
+
+    // The top rust page loses its words, so drops out of both rust searches
+    let top = captured[2]["results"][0].as_str().unwrap();
+    sqlx::query(
+        "DELETE FROM website_keywords WHERE website_id = (SELECT id FROM websites WHERE url = $1)"
+    )
+        .bind(top)
+        .execute(&db.pool).await
+        .unwrap();
+    let report = replay(&db, "replay_changed", &[path]);
+    assert!(report.contains("Replayed 3 captured searches"), "{}", report);
+    assert!(report.contains("Biggest movers:"), "{}", report);
+    // Past the end of the longer ordering: 4 results for "rust async", 3 for "rust"
+    for (distance, query) in [(4, "rust async"), (3, "rust")] {
+        let lost = format!("     1 -> -    (moved {}) {} for {:?}", distance, top, query);
+        assert!(report.contains(&lost), "No {:?} in:\n{}", lost, report);
+    }
+    assert!(!report.contains("for \"jaguar\""), "{}", report);
+
+    // A limit replays the first captures, or with a seed a reproducible sample
+    let report = replay(&db, "replay_limit", &[path, "--limit", "1"]);
+    assert!(report.contains("Replayed 1 captured searches"), "{}", report);
+    let sampled = replay(&db, "replay_seed", &[path, "--limit", "2", "--seed", "5"]);
+    assert!(sampled.contains("Replayed 2 captured searches"), "{}", sampled);
+    assert_eq!(replay(&db, "replay_seed_again", &[path, "--limit", "2", "--seed", "5"]), sampled);
+}
+
+#[tokio::test]
+async fn rejects_bad_arguments_and_files() {
+    let Some(db) = TestDb::create("replay_invalid", &[INDEX]).await else {
+        return;
+    };
+    let not_captures = temp_file("replay_not_captures.jsonl", "{\"params\": {}}\n");
+    let not_json = temp_file("replay_not_json.jsonl", "\n{\"params\":\n");
+
+    for (args, error) in [
+        (vec!["replay"], "Usage: replay <file> [--limit N] [--seed S]"),
+        (vec!["replay", "missing.jsonl"], "Failed to read missing.jsonl"),
+        (vec!["replay", "a.jsonl", "--limit", "some"], "--limit needs a non-negative integer"),
+        (vec!["replay", "a.jsonl", "b.jsonl"], "Unexpected argument \"b.jsonl\""),
+        (vec!["replay", not_captures.to_str().unwrap()], ":1: not a captured search"),
+        (vec!["replay", not_json.to_str().unwrap()], ":2: "),
+    ] {
+        let (succeeded, _, stderr) = run_command(&db, "replay_invalid", &args, &[]);
+        assert!(!succeeded, "{:?}", args);
+        assert!(stderr.contains("Replay failed: "), "{:?}: {}", args, stderr);
+        assert!(stderr.contains(error), "{:?}: {}", args, stderr);
+    }
+}
+
+#[tokio::test]
+async fn tags_captures_with_the_experiment_without_changing_applied_options() {
+    let Some(db) = TestDb::create("replay_experiment", &[INDEX]).await else {
+        return;
+    };
+    let file = temp_file("replay_experiment.jsonl", "");
+    let env = [
+        ("CAPTURE_FILE", file.to_str().unwrap()),
+        ("EXPERIMENT_NAME", "ranking"),
+        ("EXPERIMENT_VARIANTS", "control"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+    let (status, _, body) = server.get_json("/?q=rust&bucket=7").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let tag = json!({ "name": "ranking", "variant": "control", "bucket": 7 });
+    assert_eq!(body["experiment"], tag);
+    assert!(body["applied_options"].get("experiment").is_none(), "{}", body["applied_options"]);
+
+    let captured = capture(&server, &file, &["rust&bucket=7"]).await;
+    assert_eq!(captured[0]["params"]["experiment"], tag);
+    let mut params = captured[0]["params"].clone();
+    params.as_object_mut().unwrap().remove("experiment");
+    assert_eq!(params, body["applied_options"]);
+    let report = replay(&db, "replay_experiment", &[file.to_str().unwrap()]);
+    assert!(report.contains("Replayed 1 captured searches"), "{}", report);
+}
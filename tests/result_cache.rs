@@ -0,0 +1,145 @@
+mod common;
+
+use common::{ TestDb, TestServer, ADMIN_TOKEN, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+async fn admin(server: &TestServer, path: &str) -> (StatusCode, Value) {
+    let (status, _, body) = server.get_admin(path).await;
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+async fn evict(server: &TestServer, params: &str) -> (StatusCode, Value) {
+    let request = server.client
+        .delete(server.url(&format!("/admin/cache/results?{}", params)))
+        .header("X-Admin-Token", ADMIN_TOKEN);
+    let (status, _, body) = common::json_response(request).await;
+    (status, body)
+}
+
+/// Percent-encodes every byte, so a cache key can be sent as a parameter
+fn urlencode(text: &str) -> String {
+    text.bytes().map(|byte| format!("%{:02X}", byte)).collect()
+}
+
+async fn search(server: &TestServer, params: &str) {
+    let (status, _, body) = server.get_json(&format!("/?q={}", params)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[tokio::test]
+async fn inspects_cached_rankings_by_query_and_key() {
+    let Some(db) = TestDb::create("result_cache_inspect", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    // Nothing is cached before the first search
+    let (status, body) = admin(&server, "/admin/cache/results?query=rust").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "lookup": { "query": "rust" }, "cached": false, "entries": [] }));
+
+    search(&server, "rust").await;
+    search(&server, "rust&links=true").await;
+    search(&server, "jaguar").await;
+
+    // Looked up as searched: sanitised and normalised
+    let (_, body) = admin(&server, "/admin/cache/results?query=%20RUST%E2%80%8B%20").await;
+    assert_eq!(body["lookup"]["query"], "rust");
+    assert_eq!(body["cached"], true);
+    let entries = body["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    for entry in entries {
+        assert!(entry["key"].as_str().unwrap().starts_with("search:rust|"), "{}", entry);
+        assert!(entry["age_secs"].as_u64().unwrap() <= 1);
+        assert!(entry["ttl_remaining_secs"].as_u64().unwrap() > 0);
+        assert_eq!(entry["weight"], 1);
+        assert_eq!(entry["index_generation"], 0);
+        assert_eq!(entry["results"], 5);
+        assert_eq!(entry["complete"], true);
+    }
+
+    // One exact key, and a key that isn't cached
+    let key = entries[0]["key"].as_str().unwrap();
+    let (_, body) = admin(&server, &format!("/admin/cache/results?key={}", urlencode(key))).await;
+    assert_eq!(body["lookup"], json!({ "key": key }));
+    assert_eq!(body["entries"].as_array().unwrap().len(), 1);
+    assert_eq!(body["entries"][0]["key"], key);
+    let (_, body) = admin(&server, "/admin/cache/results?key=rust").await;
+    assert_eq!(body["cached"], false);
+
+    let (status, body) = admin(&server, "/admin/cache/results").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "query or key is required");
+}
+
+#[tokio::test]
+async fn evicts_one_ranking_or_purges_them_all() {
+    let Some(db) = TestDb::create("result_cache_evict", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    for params in ["rust", "rust", "rust&links=true", "jaguar", "jaguar"] {
+        search(&server, params).await;
+    }
+    let (_, metrics) = admin(&server, "/admin/metrics").await;
+    assert_eq!(metrics["result_cache"]["entries"], 3);
+    assert_eq!(metrics["result_cache"]["hits"], 2);
+    assert_eq!(metrics["result_cache"]["misses"], 3);
+
+    // Evicting one key leaves the query's other options cached. Keys start with the links flag,
+    // so the one without links sorts first.
+    let (_, body) = admin(&server, "/admin/cache/results?query=rust").await;
+    let key = body["entries"][0]["key"].as_str().unwrap().to_string();
+    assert!(key.starts_with("search:rust|false|"), "{}", key);
+    let by_key = format!("key={}", urlencode(&key));
+    assert_eq!(evict(&server, &by_key).await.1, json!({ "removed": 1 }));
+    assert_eq!(evict(&server, &by_key).await.1, json!({ "removed": 0 }));
+    let (_, body) = admin(&server, "/admin/cache/results?query=rust").await;
+    assert_eq!(body["entries"].as_array().unwrap().len(), 1);
+    assert_ne!(body["entries"][0]["key"], key.as_str());
+    let (_, metrics) = admin(&server, "/admin/metrics").await;
+    assert_eq!(metrics["result_cache"]["hits"], 2);
+
+    // The evicted search runs again
+    let (_, _, body) = server.get_json("/?q=rust&debug=true").await;
+    assert_eq!(body["debug"]["pipeline"]["cache"], "miss");
+
+    // A purge empties the cache and starts the hit ratio over
+    let (status, body) = evict(&server, "all=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "removed": 3 }));
+    let (_, metrics) = admin(&server, "/admin/metrics").await;
+    let stats = &metrics["result_cache"];
+    assert_eq!(stats["entries"], 0);
+    assert_eq!(stats["hits"], 0);
+    assert_eq!(stats["misses"], 0);
+    assert_eq!(stats["hit_ratio"], Value::Null);
+    search(&server, "jaguar").await;
+    search(&server, "jaguar").await;
+    let (_, metrics) = admin(&server, "/admin/metrics").await;
+    assert_eq!(metrics["result_cache"]["hit_ratio"], 0.5);
+
+    for params in ["", "all=false", "all=yes"] {
+        let (status, body) = evict(&server, params).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", params);
+        assert_eq!(body["error"], "key or all=true is required");
+    }
+}
+
+#[tokio::test]
+async fn is_only_for_admins() {
+    let Some(db) = TestDb::create("result_cache_auth", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    search(&server, "rust").await;
+
+    let (status, _, _) = server.get_json("/admin/cache/results?query=rust").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let request = server.client.delete(server.url("/admin/cache/results?all=true"));
+    let (status, _, _) = common::json_response(request).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (_, body) = admin(&server, "/admin/cache/results?query=rust").await;
+    assert_eq!(body["cached"], true);
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::Value;
+
+/// What `GET /` describes with Turnstile on and the default limits, with the version elided
+const SNAPSHOT: &str = include_str!("snapshots/root_descriptor.json");
+
+#[tokio::test]
+async fn describes_the_service_without_leaking_configuration() {
+    let Some(db) = TestDb::create("root_descriptor", &[INDEX]).await else {
+        return;
+    };
+    let env = [
+        ("TURNSTILE_ENABLED", "true"),
+        ("TURNSTILE_VERIFY_URL", "http://siteverify.internal.example:9/verify"),
+        ("CLOUDFLARE_TURNSTILE_SECRET_KEY", "descriptor-turnstile-secret"),
+        ("ADMIN_TOKEN", "descriptor-admin-token"),
+        ("CORS_ALLOWED_ORIGINS", "https://staging.internal.example"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    // Without a token: the bare root isn't behind Turnstile
+    let response = server.client.get(server.url("/")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let text = response.text().await.unwrap();
+    for secret in [
+        "descriptor-turnstile-secret",
+        "descriptor-admin-token",
+        "internal.example",
+        "127.0.0.1",
+        "localhost",
+        "postgres",
+        "search_test_root_descriptor",
+        "/admin",
+    ] {
+        assert!(!text.contains(secret), "The descriptor mentions {}: {}", secret, text);
+    }
+
+    let mut descriptor: Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(descriptor["version"], env!("CARGO_PKG_VERSION"));
+    descriptor["version"] = "[version]".into();
+    let snapshot: Value = serde_json::from_str(SNAPSHOT).unwrap();
+    assert_eq!(
+        descriptor,
+        snapshot,
+        "The descriptor changed; if that's intended, update tests/snapshots/root_descriptor.json \
+         to:\n{}",
+        serde_json::to_string_pretty(&descriptor).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn follows_the_configured_limits_and_the_openapi_definitions() {
+    let Some(db) = TestDb::create("root_configured", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("MAX_RESULTS", "25")]).await;
+
+    let (status, _, descriptor) = server.get_json("/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(descriptor["max_results"], 25);
+    assert_eq!(descriptor["turnstile_enforced"], false);
+    assert_eq!(descriptor["openapi"], "/openapi.json");
+
+    // Every endpoint and parameter described is the one OpenAPI describes
+    let (_, _, openapi) = server.get_json(descriptor["openapi"].as_str().unwrap()).await;
+    let endpoints = descriptor["endpoints"].as_array().unwrap();
+    let operations: usize = openapi["paths"]
+        .as_object()
+        .unwrap()
+        .values()
+        .map(|path| path.as_object().unwrap().len())
+        .sum();
+    assert_eq!(endpoints.len(), operations);
+    for endpoint in endpoints {
+        // Nothing needs a Turnstile token while Turnstile is off
+        assert_eq!(endpoint["authenticated"], false);
+        let path = endpoint["path"].as_str().unwrap();
+        let method = endpoint["method"].as_str().unwrap().to_lowercase();
+        let operation = &openapi["paths"][path][&method];
+        assert_eq!(operation["summary"], endpoint["summary"], "{} {}", method, path);
+        let names = |parameters: &Value| -> Vec<Value> {
+            parameters
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|parameter| parameter["name"].clone())
+                .collect()
+        };
+        assert_eq!(names(&operation["parameters"]), names(&endpoint["parameters"]));
+    }
+    let search = &openapi["paths"]["/"]["get"]["parameters"];
+    let results = search
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|parameter| parameter["name"] == "results")
+        .unwrap();
+    assert_eq!(results["schema"]["maximum"], 25);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{ result_urls, temp_file, TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::time::{ Duration, Instant };
+
+/// "rust async" behind a bidi override, with a null, a tab, a run of spaces and zero-width
+/// characters
+const ADVERSARIAL: &str = "%E2%80%AErust%00%20%20%20%09%E2%80%8Basync%E2%80%8D%E2%81%A9";
+
+#[tokio::test]
+async fn searches_for_logs_and_echoes_the_sanitised_query() {
+    let Some(db) = TestDb::create("sanitise_echo", &[INDEX]).await else {
+        return;
+    };
+    let log = temp_file("sanitise_slow.log", "");
+    let env = [
+        ("SLOW_QUERY_MS", "0"),
+        ("SLOW_QUERY_LOG_FILE", log.to_str().unwrap()),
+        ("QUERY_LOG", "true"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    let (status, _, body) = server.get_json(&format!("/?q={}", ADVERSARIAL)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["query"], "rust async");
+    assert_eq!(body["applied_options"]["query"], "rust async");
+    assert_eq!(body["lemmatised_keywords"], serde_json::json!(["rust", "async"]));
+    let (_, _, plain) = server.get_json("/?q=rust%20async").await;
+    assert_eq!(result_urls(&body), result_urls(&plain));
+
+    // The slow-query log and the query log have the same form
+    let started = Instant::now();
+    let logged: Value = loop {
+        let written = std::fs::read_to_string(&log).unwrap_or_default();
+        if let Some(line) = written.lines().next() {
+            break serde_json::from_str(line).unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "Nothing logged:\n{}", server.log());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert_eq!(logged["query"], "rust async");
+    let started = Instant::now();
+    let queries: Vec<String> = loop {
+        let queries = sqlx::query_scalar("SELECT query FROM query_log")
+            .fetch_all(&db.pool).await
+            .unwrap();
+        if queries.len() == 2 {
+            break queries;
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "Not recorded:\n{}", server.log());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert_eq!(queries, ["rust async", "rust async"]);
+    assert!(!server.log().contains('\u{202e}'));
+}
+
+#[tokio::test]
+async fn limits_the_length_once_sanitised() {
+    let Some(db) = TestDb::create("sanitise_length", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("MAX_QUERY_CHARS", "10")]).await;
+
+    // Far over the limit as sent, but "rust async" once the padding is gone
+    let padded = format!("rust{}async%E2%80%8B%00", "%20".repeat(1000));
+    let (status, _, body) = server.get_json(&format!("/?q={}", padded)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["query"], "rust async");
+
+    let (status, _, body) = server.get_json("/?q=rust%20asyncs").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "query_too_long");
+
+    // Nothing but invisible characters is an empty query
+    let (status, _, body) = server.get_json("/?q=%E2%80%8B%00%E2%80%AE%20%09").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["code"], "empty_query");
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{ json_response, temp_file, TestDb, TestServer, ADMIN_TOKEN, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+/// Saves `q` as `name`, sending `api_key`, or the admin token for `"admin"`
+async fn save(
+    server: &TestServer,
+    api_key: Option<&str>,
+    name: &str,
+    q: &str
+) -> (StatusCode, Value) {
+    let mut request = server.client
+        .post(server.url("/saved"))
+        .json(&json!({ "name": name, "q": q, "params": { "results": 2 } }));
+    request = match api_key {
+        Some("admin") => request.header("X-Admin-Token", ADMIN_TOKEN),
+        Some(api_key) => request.header("X-Api-Key", api_key),
+        None => request,
+    };
+    let (status, _, body) = json_response(request).await;
+    (status, body)
+}
+
+/// The query of the search saved as `name`
+async fn saved_query(server: &TestServer, name: &str) -> Value {
+    let (status, _, body) = server.get_json(&format!("/saved/{}/run", name)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["query"].clone()
+}
+
+/// Starts a server accepting the API keys `key-a` and `key-b`
+async fn start(db: &TestDb, name: &str) -> TestServer {
+    let keys = temp_file(&format!("{}_keys.txt", name), "key-a\nkey-b\n");
+    TestServer::start(db, &[("API_KEYS_FILE", keys.to_str().unwrap())]).await
+}
+
+#[tokio::test]
+async fn only_lets_the_key_that_saved_a_search_replace_it() {
+    let Some(db) = TestDb::create("saved_owners", &[INDEX]).await else {
+        return;
+    };
+    let server = start(&db, "saved_owners").await;
+
+    let (status, body) = save(&server, Some("key-a"), "rust", "rust").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["run"], "/saved/rust/run");
+
+    let (status, body) = save(&server, Some("key-b"), "rust", "jaguar").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"], "\"rust\" was saved by someone else");
+    assert_eq!(saved_query(&server, "rust").await, "rust");
+
+    let (status, _) = save(&server, Some("key-a"), "rust", "rust async").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(saved_query(&server, "rust").await, "rust async");
+
+    // The admin may replace it, and it stays the key's to replace
+    let (status, _) = save(&server, Some("admin"), "rust", "rust book").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(saved_query(&server, "rust").await, "rust book");
+    let (status, _) = save(&server, Some("key-a"), "rust", "rust").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = save(&server, Some("key-b"), "rust", "rust").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // What the admin saves, no key can replace
+    let (status, _) = save(&server, Some("admin"), "cats", "jaguar").await;
+    assert_eq!(status, StatusCode::OK);
+    for api_key in ["key-a", "key-b"] {
+        let (status, _) = save(&server, Some(api_key), "cats", "python").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+    assert_eq!(saved_query(&server, "cats").await, "jaguar");
+
+    // Keys are only stored hashed
+    let owners: Vec<String> = sqlx::query_scalar("SELECT owner FROM saved_searches ORDER BY name")
+        .fetch_all(&db.pool).await
+        .unwrap();
+    assert_eq!(owners[0], "admin");
+    assert!(owners[1].starts_with("key:") && owners[1].len() == 68, "{}", owners[1]);
+    assert!(!owners[1].contains("key-a"));
+}
+
+#[tokio::test]
+async fn leaves_searches_saved_without_an_owner_to_the_admin() {
+    let Some(db) = TestDb::create("saved_unowned", &[INDEX]).await else {
+        return;
+    };
+    sqlx::query("INSERT INTO saved_searches (name, parameters) VALUES ('old', '{\"q\":\"rust\"}')")
+        .execute(&db.pool).await
+        .unwrap();
+    let server = start(&db, "saved_unowned").await;
+
+    let (status, _) = save(&server, Some("key-a"), "old", "jaguar").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(saved_query(&server, "old").await, "rust");
+    let (status, _) = save(&server, Some("admin"), "old", "jaguar").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(saved_query(&server, "old").await, "jaguar");
+}
+
+#[tokio::test]
+async fn requires_a_known_api_key_or_the_admin_token() {
+    let Some(db) = TestDb::create("saved_keys", &[INDEX]).await else {
+        return;
+    };
+    let server = start(&db, "saved_keys").await;
+
+    for api_key in [None, Some("key-unknown")] {
+        let (status, body) = save(&server, api_key, "rust", "rust").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "Saving searches requires an API key");
+    }
+    let (status, _, _) = server.get_json("/saved/rust/run").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
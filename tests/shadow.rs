@@ -0,0 +1,129 @@
+mod common;
+
+use common::{ temp_file, TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::path::Path;
+use std::time::{ Duration, Instant };
+
+/// A shadow ranking that orders results by domain popularity above all
+const BY_DOMAIN: [(&str, &str); 2] = [
+    ("SHADOW_RELEVANCE_WEIGHT", "0"),
+    ("SHADOW_DOMAIN_RANK_WEIGHT", "10"),
+];
+
+async fn results(server: &TestServer, search: &str) -> String {
+    let (status, _, body) = server.get_json(&format!("/?q={}", search)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["results"].to_string()
+}
+
+async fn shadow_stats(server: &TestServer) -> Value {
+    let (_, _, metrics) = server.get_admin("/admin/metrics").await;
+    let metrics: Value = serde_json::from_str(&metrics).unwrap();
+    metrics["shadow"].clone()
+}
+
+/// Waits for `count` comparisons in `log`
+async fn logged(server: &TestServer, log: &Path, count: usize) -> Vec<Value> {
+    let started = Instant::now();
+    loop {
+        let written = std::fs::read_to_string(log).unwrap_or_default();
+        if written.lines().count() >= count {
+            return written.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "Not logged:\n{}", server.log());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+async fn compares_a_shadow_ranking_without_changing_the_response() {
+    let Some(db) = TestDb::create("shadow_compared", &[INDEX]).await else {
+        return;
+    };
+    let log = temp_file("shadow_compared.log", "");
+    let mut env = vec![
+        ("SHADOW_SAMPLE_PERCENT", "100"),
+        ("SHADOW_LOG_FILE", log.to_str().unwrap()),
+    ];
+    env.extend(BY_DOMAIN);
+    let shadowed = TestServer::start(&db, &env).await;
+    let plain = TestServer::start(&db, &[]).await;
+
+    assert_eq!(results(&shadowed, "rust").await, results(&plain, "rust").await);
+    let record = logged(&shadowed, &log, 1).await.remove(0);
+    assert_eq!(record["query"], "rust");
+    assert_eq!(record["candidates"], 5);
+    assert_eq!(record["primary"]["relevance_weight"], 1.0);
+    assert_eq!(record["shadow"]["relevance_weight"], 0.0);
+    assert_eq!(record["shadow"]["domain_rank_weight"], 10.0);
+    // The same five results, but tokio.rs, the only one on a listed domain, comes first
+    assert_eq!(record["overlap_at_10"], 1.0);
+    assert_ne!(record["top_result_shadow_rank"], 1, "{}", record);
+    for scores in [&record["primary_scores"], &record["shadow_scores"]] {
+        let [min, mean, max] = ["min", "mean", "max"].map(|stat| scores[stat].as_f64().unwrap());
+        assert!(min <= mean && mean <= max, "{}", scores);
+    }
+    let max = |scores: &Value| scores["max"].as_f64().unwrap();
+    assert!(max(&record["shadow_scores"]) > max(&record["primary_scores"]), "{}", record);
+
+    let stats = shadow_stats(&shadowed).await;
+    assert_eq!(stats["compared"], 1, "{}", stats);
+    assert_eq!(stats["top_changed"], 1);
+    assert_eq!(stats["mean_overlap_at_10"], 1.0);
+    assert_eq!(stats["dropped"], 0);
+
+    // An answer from the result cache has no candidates to re-rank, so isn't sampled
+    assert_eq!(results(&shadowed, "rust").await, results(&plain, "rust").await);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(shadow_stats(&shadowed).await["compared"], 1);
+}
+
+#[tokio::test]
+async fn samples_the_configured_share_of_searches() {
+    let Some(db) = TestDb::create("shadow_sampled", &[INDEX]).await else {
+        return;
+    };
+    let log = temp_file("shadow_sampled.log", "");
+    let env = [("SHADOW_SAMPLE_PERCENT", "50"), ("SHADOW_LOG_FILE", log.to_str().unwrap())];
+    let server = TestServer::start(&db, &env).await;
+
+    for search in ["rust", "jaguar", "async", "python"] {
+        results(&server, search).await;
+    }
+    let queries: Vec<Value> = logged(&server, &log, 2).await
+        .into_iter()
+        .map(|record| record["query"].clone())
+        .collect();
+    assert_eq!(queries, ["jaguar", "python"]);
+    // With the served configuration, the shadow changes nothing
+    let stats = shadow_stats(&server).await;
+    assert_eq!(stats["compared"], 2, "{}", stats);
+    assert_eq!(stats["top_changed"], 0);
+    assert_eq!(stats["mean_overlap_at_10"], 1.0);
+}
+
+#[tokio::test]
+async fn drops_comparisons_beyond_the_concurrency_limit() {
+    let Some(db) = TestDb::create("shadow_dropped", &[INDEX]).await else {
+        return;
+    };
+    let log = temp_file("shadow_dropped.log", "");
+    let env = [
+        ("SHADOW_SAMPLE_PERCENT", "100"),
+        ("SHADOW_MAX_CONCURRENT", "0"),
+        ("SHADOW_LOG_FILE", log.to_str().unwrap()),
+    ];
+    let server = TestServer::start(&db, &env).await;
+    let plain = TestServer::start(&db, &[]).await;
+
+    for search in ["rust", "jaguar", "async"] {
+        assert_eq!(results(&server, search).await, results(&plain, search).await);
+    }
+    let stats = shadow_stats(&server).await;
+    assert_eq!(stats["dropped"], 3, "{}", stats);
+    assert_eq!(stats["compared"], 0);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), "");
+}
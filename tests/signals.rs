@@ -0,0 +1,106 @@
+mod common;
+
+use chrono::{ DateTime, Utc };
+use common::{ temp_file, TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+async fn search(server: &TestServer, path: &str, api_key: Option<&str>) -> Value {
+    let mut request = server.client.get(server.url(path));
+    if let Some(api_key) = api_key {
+        request = request.header("X-Api-Key", api_key);
+    }
+    let (status, _, body) = common::json_response(request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body
+}
+
+fn days_since(date: &str) -> i64 {
+    (Utc::now() - date.parse::<DateTime<Utc>>().unwrap()).num_days()
+}
+
+#[tokio::test]
+async fn reports_each_results_signals_to_api_key_holders() {
+    let Some(db) = TestDb::create("signals_values", &[INDEX]).await else {
+        return;
+    };
+    let keys = temp_file("signals_keys.txt", "key-a\n");
+    let server = TestServer::start(&db, &[("API_KEYS_FILE", keys.to_str().unwrap())]).await;
+
+    let body = search(&server, "/?q=rust&signals=true&links=true", Some("key-a")).await;
+    assert_eq!(body["signals_version"], 1);
+    assert_eq!(body.get("warnings"), None);
+    let results = body["results"].as_array().unwrap();
+    assert!(results.iter().all(|result| result["signals"].is_object()));
+
+    // The 9 live pages over the 6 with "rust", a count kept by the crawler that still includes
+    // the tombstoned page
+    let idf = (9.0_f64 / 6.0).ln();
+    let book = results
+        .iter()
+        .find(|result| result["url"] == "https://doc.rust-lang.org/book/")
+        .unwrap();
+    assert_eq!(
+        book["signals"],
+        json!({
+            "terms": [{ "term": "rust", "occurrences": 40, "tf": 40.0 / 1200.0, "idf": idf }],
+            "word_count": 1200,
+            "coverage": 1.0,
+            "domain_rank": null,
+            "inbound_links": 3,
+            "url_depth": 1,
+            "age_days": days_since("2024-03-01T00:00:00Z"),
+        })
+    );
+    let tokio = results
+        .iter()
+        .find(|result| result["url"] == "https://tokio.rs/")
+        .unwrap();
+    assert_eq!(tokio["signals"]["domain_rank"], 6);
+    assert_eq!(tokio["signals"]["url_depth"], 0);
+    assert_eq!(tokio["signals"]["inbound_links"], 1);
+
+    // Without links, the inbound count isn't known; terms the page lacks have no IDF
+    let body = search(&server, "/?q=rust%20tokio&signals=true", Some("key-a")).await;
+    let wikipedia = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|result| result["url"] == "https://en.wikipedia.org/wiki/Rust")
+        .unwrap();
+    let signals = &wikipedia["signals"];
+    assert_eq!(signals["inbound_links"], Value::Null);
+    assert_eq!(signals["coverage"], 0.5);
+    assert_eq!(
+        signals["terms"][1],
+        json!({ "term": "tokio", "occurrences": 0, "tf": 0.0, "idf": null })
+    );
+}
+
+#[tokio::test]
+async fn requires_an_api_key() {
+    let Some(db) = TestDb::create("signals_gated", &[INDEX]).await else {
+        return;
+    };
+    let keys = temp_file("signals_gated_keys.txt", "key-a\n");
+    let server = TestServer::start(&db, &[("API_KEYS_FILE", keys.to_str().unwrap())]).await;
+
+    for api_key in [None, Some("key-unknown")] {
+        let body = search(&server, "/?q=rust&signals=true", api_key).await;
+        assert_eq!(body.get("signals_version"), None);
+        let results = body["results"].as_array().unwrap();
+        assert!(results.iter().all(|result| result.get("signals").is_none()));
+        assert_eq!(body["warnings"][0]["field"], "signals", "{}", body);
+        assert_eq!(body["warnings"][0]["message"], "signals requires an API key and was ignored");
+    }
+
+    // Admins hold every privilege an API key does
+    let (_, _, body) = server.get_admin("/?q=rust&signals=true").await;
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["signals_version"], 1);
+
+    // Not asked for, there's nothing to warn about
+    let body = search(&server, "/?q=rust", None).await;
+    assert_eq!(body.get("warnings"), None);
+    assert_eq!(body.get("signals_version"), None);
+}
@@ -0,0 +1,219 @@
+mod common;
+
+use common::{ TestDb, TestServer };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+const SITE: &str = "tests/fixtures/site.sql";
+
+/// The URLs of a listing's pages
+fn urls(body: &Value) -> Vec<&str> {
+    body["pages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|page| page["url"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn lists_a_domain_and_its_subdomains_in_each_order() {
+    let Some(db) = TestDb::create("site_sorts", &[SITE]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (status, _, recent) = server.get_json("/site/example.org").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(recent["domain"], "example.org");
+    assert_eq!(recent["sort"], "recent");
+    assert_eq!(recent["total"], 5);
+    // Look-alike hosts and the tombstoned page are left out
+    assert_eq!(
+        urls(&recent),
+        [
+            "https://www.example.org/about",
+            "https://deep.blog.example.org:8443/nested",
+            "https://blog.example.org/post",
+            "https://Example.ORG/Upper?x=1",
+            "https://example.org/",
+        ]
+    );
+    let about = &recent["pages"][0];
+    assert_eq!(about["title"], "About");
+    assert_eq!(about["description"], "About the example");
+    assert_eq!(about["word_count"], 800);
+    assert_eq!(about["last_crawled"], "2024-05-01T00:00:00Z");
+    assert_eq!(about["inbound_links"], 1);
+
+    let (_, _, words) = server.get_json("/site/example.org?sort=words").await;
+    assert_eq!(
+        urls(&words),
+        [
+            "https://www.example.org/about",
+            "https://Example.ORG/Upper?x=1",
+            "https://blog.example.org/post",
+            "https://example.org/",
+            "https://deep.blog.example.org:8443/nested",
+        ]
+    );
+
+    // Ties in link count keep the order pages were indexed in
+    let (_, _, links) = server.get_json("/site/example.org?sort=links").await;
+    assert_eq!(
+        urls(&links),
+        [
+            "https://blog.example.org/post",
+            "https://example.org/",
+            "https://www.example.org/about",
+            "https://deep.blog.example.org:8443/nested",
+            "https://Example.ORG/Upper?x=1",
+        ]
+    );
+    let counts: Vec<&Value> = links["pages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|page| &page["inbound_links"])
+        .collect();
+    assert_eq!(counts, [3, 2, 1, 0, 0]);
+
+    // A subdomain lists only itself and what's below it, however it's spelt
+    for domain in ["blog.example.org", "BLOG.Example.org."] {
+        let (_, _, body) = server.get_json(&format!("/site/{}", domain)).await;
+        assert_eq!(body["domain"], "blog.example.org");
+        assert_eq!(
+            urls(&body),
+            ["https://deep.blog.example.org:8443/nested", "https://blog.example.org/post"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn pages_through_a_site() {
+    let Some(db) = TestDb::create("site_pages", &[SITE]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    let (_, _, everything) = server.get_json("/site/example.org?sort=words").await;
+
+    let mut paged = vec![];
+    for (page, count, has_more) in [(1, 2, true), (2, 2, true), (3, 1, false), (4, 0, false)] {
+        let path = format!("/site/example.org?sort=words&per_page=2&page={}", page);
+        let (_, _, body) = server.get_json(&path).await;
+        assert_eq!(body["total"], 5);
+        assert_eq!(body["total_pages"], 3);
+        assert_eq!((body["page"].clone(), body["per_page"].clone()), (json!(page), json!(2)));
+        assert_eq!(body["has_more"], has_more, "page {}", page);
+        assert_eq!(urls(&body).len(), count, "page {}", page);
+        paged.extend(urls(&body).into_iter().map(String::from));
+    }
+    assert_eq!(paged, urls(&everything));
+
+    // Out-of-range pagination is brought into range
+    let (_, _, body) = server.get_json("/site/example.org?page=0&per_page=0").await;
+    assert_eq!((body["page"].clone(), body["per_page"].clone()), (json!(1), json!(1)));
+    assert_eq!(body["total_pages"], 5);
+    let (_, _, body) = server.get_json("/site/example.org?per_page=100000").await;
+    assert_eq!(body["per_page"], 100);
+    assert_eq!(body["total_pages"], 1);
+}
+
+#[tokio::test]
+async fn unknown_and_invalid_domains() {
+    let Some(db) = TestDb::create("site_unknown", &[SITE]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (status, _, body) = server.get_json("/site/nowhere.example").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 0);
+    assert_eq!(body["total_pages"], 0);
+    assert_eq!(body["has_more"], false);
+    assert_eq!(body["pages"], json!([]));
+
+    for (path, error) in [
+        ("/site/exa:mple.org", "Invalid domain"),
+        ("/site/example.org?sort=popular", "sort must be recent, links, or words"),
+        ("/site/example.org?include_deleted=yes", "include_deleted must be true or false"),
+    ] {
+        let (status, _, body) = server.get_json(path).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", path);
+        assert_eq!(body["error"], error);
+    }
+}
+
+#[tokio::test]
+async fn admins_may_list_tombstoned_pages() {
+    let Some(db) = TestDb::create("site_deleted", &[SITE]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (status, _, body) = server.get_json("/site/old.example.org?include_deleted=true").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"], "include_deleted requires the admin token");
+
+    let (_, _, body) = server.get_admin("/site/old.example.org?include_deleted=true").await;
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(urls(&body), ["https://old.example.org/gone"]);
+}
+
+#[tokio::test]
+async fn caches_each_domain_listing() {
+    let Some(db) = TestDb::create("site_cache", &[SITE]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (_, _, before) = server.get_json("/site/blog.example.org").await;
+    sqlx::query(
+        "INSERT INTO websites (title, description, url, word_count) VALUES ('New', '', $1, 10)"
+    )
+        .bind("https://new.blog.example.org/")
+        .execute(&db.pool).await
+        .unwrap();
+
+    let (_, _, cached) = server.get_json("/site/blog.example.org").await;
+    assert_eq!(cached["total"], before["total"]);
+    assert_eq!(urls(&cached), urls(&before));
+    // Another domain, or another page of this one, isn't in the cache yet
+    let (_, _, parent) = server.get_json("/site/example.org").await;
+    assert_eq!(parent["total"], 6);
+    let (_, _, other_page) = server.get_json("/site/blog.example.org?per_page=5").await;
+    assert_eq!(other_page["total"], 3);
+}
+
+#[tokio::test]
+async fn renders_crawl_ages_in_the_negotiated_language() {
+    let Some(db) = TestDb::create("site_locale", &[SITE]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    let listing = |path: &str, accept_language: Option<&str>| {
+        let request = server.client.get(server.url(path));
+        let request = match accept_language {
+            Some(accept_language) => request.header("Accept-Language", accept_language),
+            None => request,
+        };
+        common::json_response(request)
+    };
+
+    for (path, accept_language, code, prefix, suffix) in [
+        ("/site/example.org", None, "en", "", " days ago"),
+        ("/site/example.org", Some("de-AT, fr;q=0.5"), "de", "vor ", " Tagen"),
+        ("/site/example.org?ui_lang=fr", Some("de"), "fr", "il y a ", " jours"),
+        ("/site/example.org?ui_lang=es", Some("es, fr;q=0.1"), "fr", "il y a ", " jours"),
+        ("/site/example.org?ui_lang=es", Some("es"), "en", "", " days ago"),
+    ] {
+        let (status, headers, body) = listing(path, accept_language).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-language"], code, "{} {:?}", path, accept_language);
+        assert_eq!(headers["vary"], "accept-language");
+        let ago = body["pages"][0]["last_crawled_ago"].as_str().unwrap();
+        assert!(ago.starts_with(prefix) && ago.ends_with(suffix), "{} for {}", ago, code);
+        // The machine-readable time is the same in every language
+        assert_eq!(body["pages"][0]["last_crawled"], "2024-05-01T00:00:00Z");
+    }
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{ json_response, result_urls, TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+/// The index's one tombstoned page
+const GONE: &str = "https://old.example.com/gone";
+/// A search only the tombstoned page matches
+const ON_GONE_SITE: &str = "/?q=rust%20site:old.example.com";
+
+/// The IDF the explanation of a search for "rust" reports
+async fn rust_idf(server: &TestServer) -> f64 {
+    let (_, _, body) = server.get_json("/?q=rust&explain=true").await;
+    body["results"][0]["explain"]["terms"][0]["idf"].as_f64().expect("No IDF explained")
+}
+
+#[tokio::test]
+async fn leaves_tombstoned_pages_out_of_every_public_response() {
+    let Some(db) = TestDb::create("soft_deletes_public", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (_, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(result_urls(&body).len(), 5);
+    assert!(!result_urls(&body).contains(&GONE.to_string()));
+    let (_, _, body) = server.get_json(ON_GONE_SITE).await;
+    assert_eq!(result_urls(&body), Vec::<String>::new());
+
+    // Asking for them without the admin token is reported and ignored
+    let (_, _, body) = server.get_json(&format!("{}&include_deleted=true", ON_GONE_SITE)).await;
+    assert_eq!(result_urls(&body), Vec::<String>::new());
+    assert_eq!(body["applied_options"]["include_deleted"], false);
+    assert!(
+        body["warnings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|warning| warning["field"] == "include_deleted"),
+        "{}",
+        body
+    );
+
+    let (_, _, body) = server.get_json("/site/old.example.com").await;
+    assert_eq!(body["pages"], json!([]), "{}", body);
+    let (_, _, body) = server.get_json("/random?count=50&domain=old.example.com").await;
+    assert_eq!(body["count"], 0, "{}", body);
+    let blend = json!({ "queries": [{ "query": "rust", "weight": 1 }] });
+    let (_, _, body) = json_response(server.client.post(server.url("/blend")).json(&blend)).await;
+    assert_eq!(result_urls(&body).len(), 5);
+    assert!(!result_urls(&body).contains(&GONE.to_string()));
+
+    // Nor are they counted in the index size IDF is worked out from
+    let (_, _, stats) = server.get_json("/stats").await;
+    assert_eq!(stats["website_count"], 9);
+    let (_, _, body) = server.get_json("/?q=jaguar").await;
+    assert_eq!(body["website_count"], 9);
+}
+
+#[tokio::test]
+async fn admins_may_search_tombstoned_pages() {
+    let Some(db) = TestDb::create("soft_deletes_admin", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let search = format!("{}&include_deleted=true", ON_GONE_SITE);
+    let (status, _, body) = server.get_admin(&search).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(result_urls(&body), [GONE]);
+    assert_eq!(body["applied_options"]["include_deleted"], true);
+    let (_, _, body) = server.get_admin("/?q=rust&include_deleted=true").await;
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(result_urls(&body).len(), 6);
+    assert!(result_urls(&body).contains(&GONE.to_string()));
+
+    // The flag is the admin's escape hatch, not the default for admins
+    let (_, _, body) = server.get_admin(ON_GONE_SITE).await;
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(result_urls(&body), Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn serves_every_page_on_a_schema_without_tombstones() {
+    let Some(live) = TestDb::create("soft_deletes_live", &[INDEX]).await else {
+        return;
+    };
+    let Some(db) = TestDb::create("soft_deletes_old", &[INDEX, "tests/fixtures/no_deleted_at.sql"])
+        .await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    assert!(server.log().contains("No deleted_at column"), "{}", server.log());
+
+    let (status, _, body) = server.get_json(ON_GONE_SITE).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(result_urls(&body), [GONE]);
+    let (_, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(result_urls(&body).len(), 6);
+    let (_, _, body) = server.get_json("/site/old.example.com").await;
+    assert_eq!(body["pages"][0]["url"], GONE, "{}", body);
+    let (_, _, stats) = server.get_json("/stats").await;
+    assert_eq!(stats["website_count"], 10);
+    let (status, _, body) = server.get_admin("/admin/export/urls").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.lines().count(), 10);
+
+    // Counting the tenth page makes "rust" a little rarer than among the nine live ones
+    let live = TestServer::start(&live, &[]).await;
+    assert!(rust_idf(&server).await > rust_idf(&live).await);
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use common::{ result_urls, TestDb, TestServer };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+const SPAM: &str = "tests/fixtures/spam.sql";
+const GUIDE: &str = "https://guide.example.com/poker";
+const BONUS: &str = "https://bonus.example.com/";
+
+async fn reload(server: &TestServer) -> (StatusCode, Value) {
+    let response = server.client
+        .post(server.url("/admin/spam/reload"))
+        .header("X-Admin-Token", common::ADMIN_TOKEN)
+        .send().await
+        .unwrap();
+    (response.status(), response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn downranks_stuffed_pages_but_not_passing_mentions() {
+    let Some(db) = TestDb::create("spam_downrank", &[SPAM]).await else {
+        return;
+    };
+    let terms = common::temp_file("spam_downrank.txt", "# gambling\ncasino bonus\ncasino\n");
+    // BM25 favours the short stuffed page, as long as nothing penalises it
+    let unpenalised = TestServer::start(&db, &[]).await;
+    let (_, _, body) = unpenalised.get_json("/?q=poker&ranker=bm25").await;
+    assert_eq!(result_urls(&body), [BONUS, GUIDE]);
+    drop(unpenalised);
+
+    let server = TestServer::start(&db, &[("SPAM_TERMS_FILE", terms.to_str().unwrap())]).await;
+    let (status, _, body) = server.get_json("/?q=poker&ranker=bm25&explain=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result_urls(&body), [GUIDE, BONUS]);
+
+    // One mention in a thousand words matches, but isn't penalised
+    let guide = &body["results"][0]["explain"]["spam"];
+    assert_eq!(guide["terms"], json!(["casino"]));
+    assert_eq!(guide["penalty"], 1.0);
+    // Both terms, 35 times in 200 words
+    let bonus = &body["results"][1]["explain"]["spam"];
+    assert_eq!(bonus["terms"], json!(["casino bonus", "casino"]));
+    assert!((bonus["score"].as_f64().unwrap() - 0.175).abs() < 1e-6);
+    assert!((bonus["penalty"].as_f64().unwrap() - 0.2).abs() < 1e-6);
+
+    // Pages using no spam terms have nothing to explain
+    let (_, _, body) = server.get_json("/?q=card&explain=true").await;
+    assert_eq!(body["results"][0]["explain"]["spam"], Value::Null);
+}
+
+#[tokio::test]
+async fn strict_mode_drops_penalised_pages() {
+    let Some(db) = TestDb::create("spam_strict", &[SPAM]).await else {
+        return;
+    };
+    let terms = common::temp_file("spam_strict.txt", "casino\n");
+    let env = [("SPAM_TERMS_FILE", terms.to_str().unwrap()), ("SPAM_STRICT", "true")];
+    let server = TestServer::start(&db, &env).await;
+
+    let (_, _, body) = server.get_json("/?q=poker&ranker=bm25&debug=true").await;
+    assert_eq!(result_urls(&body), [GUIDE]);
+    assert_eq!(body["debug"]["pipeline"]["dropped"]["spam"], 1);
+}
+
+#[tokio::test]
+async fn reloads_the_list_without_a_restart() {
+    let Some(db) = TestDb::create("spam_reload", &[SPAM]).await else {
+        return;
+    };
+    let terms = common::temp_file("spam_reload.txt", "viagra\n");
+    let server = TestServer::start(&db, &[("SPAM_TERMS_FILE", terms.to_str().unwrap())]).await;
+
+    let (_, _, body) = server.get_json("/?q=poker&ranker=bm25").await;
+    assert_eq!(result_urls(&body), [BONUS, GUIDE]);
+
+    // The cached ranking is dropped along with the old list
+    std::fs::write(&terms, "viagra\ncasino\n").unwrap();
+    let (status, body) = reload(&server).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "terms": 2 }));
+    let (_, _, body) = server.get_json("/?q=poker&ranker=bm25").await;
+    assert_eq!(result_urls(&body), [GUIDE, BONUS]);
+
+    // A list that can't be read keeps the old one
+    std::fs::remove_file(&terms).unwrap();
+    let (status, body) = reload(&server).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["error"], "Failed to reload spam terms");
+    let (_, _, body) = server.get_json("/?q=poker&ranker=bm25").await;
+    assert_eq!(result_urls(&body), [GUIDE, BONUS]);
+}
+
+#[tokio::test]
+async fn reloading_needs_a_configured_file() {
+    let Some(db) = TestDb::create("spam_unconfigured", &[SPAM]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (status, body) = reload(&server).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"], "No spam terms file is configured");
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+const LINKS: &str = "tests/fixtures/links.sql";
+
+async fn top_links(server: &TestServer, query: &str) -> (StatusCode, Value) {
+    let (status, _, body) = server.get_admin(&format!("/admin/links/top{}", query)).await;
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+/// The `(url, link_count)` of each page in a report
+fn counts(body: &Value) -> Vec<(&str, i64)> {
+    body["pages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|page| (page["url"].as_str().unwrap(), page["link_count"].as_i64().unwrap()))
+        .collect()
+}
+
+#[tokio::test]
+async fn orders_pages_by_inbound_and_outbound_links() {
+    let Some(db) = TestDb::create("top_links_order", &[INDEX, LINKS]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    // Ties are broken by URL; URLs only ever linked to have no title
+    let (status, inbound) = top_links(&server, "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(inbound["by"], "inbound");
+    assert_eq!(inbound["total"], 7);
+    assert_eq!(
+        counts(&inbound),
+        [
+            ("https://doc.rust-lang.org/book/", 5),
+            ("https://crates.io/", 3),
+            ("https://tokio.rs/", 2),
+            ("https://www.rust-lang.org/", 2),
+            ("https://en.wikipedia.org/wiki/Jaguar", 1),
+            ("https://en.wikipedia.org/wiki/Rust", 1),
+            ("https://www.rust-lang.org/tools", 1),
+        ]
+    );
+    assert_eq!(inbound["pages"][0]["title"], "The Rust Programming Language");
+    assert_eq!(inbound["pages"][1]["title"], Value::Null);
+    assert_eq!(inbound["has_more"], false);
+
+    // Ties are broken by page id
+    let (_, outbound) = top_links(&server, "?by=outbound").await;
+    assert_eq!(outbound["by"], "outbound");
+    assert_eq!(outbound["total"], 8);
+    assert_eq!(
+        counts(&outbound),
+        [
+            ("https://doc.rust-lang.org/book/", 5),
+            ("https://www.rust-lang.org/", 2),
+            ("https://tokio.rs/", 2),
+            ("https://blog.example.net/async-rust", 2),
+            ("https://en.wikipedia.org/wiki/Rust", 1),
+            ("https://jaguar.example.com/cars", 1),
+            ("https://www.example.fr/cafe", 1),
+            ("https://docs.python.org/3/", 1),
+        ]
+    );
+    assert_eq!(outbound["pages"][3]["title"], "Async Rust in practice");
+}
+
+#[tokio::test]
+async fn pages_through_the_report() {
+    let Some(db) = TestDb::create("top_links_pages", &[INDEX, LINKS]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    let (_, everything) = top_links(&server, "?by=outbound").await;
+    let everything = counts(&everything);
+
+    // Pages of three cover every row once, in order
+    let mut paged = vec![];
+    for (offset, has_more) in [(0, true), (3, true), (6, false)] {
+        let query = format!("?by=outbound&limit=3&offset={}", offset);
+        let (_, page) = top_links(&server, &query).await;
+        assert_eq!(page["total"], 8);
+        assert_eq!((page["limit"].clone(), page["offset"].clone()), (json!(3), json!(offset)));
+        assert_eq!(page["has_more"], has_more, "offset {}", offset);
+        paged.extend(counts(&page).into_iter().map(|(url, count)| (url.to_string(), count)));
+    }
+    let everything: Vec<(String, i64)> = everything
+        .into_iter()
+        .map(|(url, count)| (url.to_string(), count))
+        .collect();
+    assert_eq!(paged, everything);
+
+    // Past the end
+    let (_, past_the_end) = top_links(&server, "?by=outbound&limit=3&offset=8").await;
+    assert_eq!(counts(&past_the_end), []);
+    assert_eq!(past_the_end["has_more"], false);
+
+    // The limit has a hard cap, and nonsense is brought into range
+    let (_, capped) = top_links(&server, "?limit=100000&offset=-5").await;
+    assert_eq!((capped["limit"].clone(), capped["offset"].clone()), (json!(500), json!(0)));
+    let (_, smallest) = top_links(&server, "?limit=0").await;
+    assert_eq!(smallest["limit"], 1);
+    assert_eq!(counts(&smallest), [("https://doc.rust-lang.org/book/", 5)]);
+
+    let (status, body) = top_links(&server, "?by=sideways").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "by must be inbound or outbound");
+}
+
+#[tokio::test]
+async fn caches_only_the_first_page() {
+    let Some(db) = TestDb::create("top_links_cache", &[INDEX, LINKS]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (_, first) = top_links(&server, "?limit=2").await;
+    let (_, second) = top_links(&server, "?limit=2&offset=2").await;
+    // Python docs become the most linked page, with 6 links
+    sqlx::query(
+        r#"
+        INSERT INTO website_links (source_website_id, target_website)
+        SELECT id, 'https://docs.python.org/3/' FROM websites WHERE id <= 6
+    "#
+    )
+        .execute(&db.pool).await
+        .unwrap();
+
+    let (_, cached) = top_links(&server, "?limit=2").await;
+    assert_eq!(cached, first);
+    let (_, uncached) = top_links(&server, "?limit=2&offset=2").await;
+    assert_ne!(uncached, second);
+    assert_eq!(uncached["total"], 8);
+    // Another limit is another first page
+    let (_, other_limit) = top_links(&server, "?limit=1").await;
+    assert_eq!(counts(&other_limit), [("https://docs.python.org/3/", 6)]);
+}
+
+#[tokio::test]
+async fn is_only_for_operators() {
+    let Some(db) = TestDb::create("top_links_admin", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (status, _, _) = server.get_json("/admin/links/top").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
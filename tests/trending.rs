@@ -0,0 +1,69 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::time::{ Duration, Instant };
+
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Polls `/trending?window=...` until it has been recounted, and returns its body.
+async fn counted(server: &TestServer, window: &str) -> Value {
+    let started = Instant::now();
+    loop {
+        let (status, _, body) = server.get_json(&format!("/trending?window={}", window)).await;
+        assert_eq!(status, StatusCode::OK);
+        if body["computed_at"].is_string() {
+            return body;
+        }
+        assert!(started.elapsed() < REFRESH_TIMEOUT, "Nothing was counted:\n{}", server.log());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[tokio::test]
+async fn counts_windows_reaching_back_beyond_any_timestamp() {
+    let Some(db) = TestDb::create("trending_windows", &[INDEX]).await else {
+        return;
+    };
+    // Searches from an hour ago and from years back, by enough people to be shown
+    sqlx::query(
+        r#"
+        INSERT INTO query_log (query, ip_hash, searched_at)
+        SELECT 'rust', 'searcher-' || n, now() - interval '10 minutes' FROM generate_series(1, 3) n
+        UNION ALL
+        SELECT 'jaguar', 'searcher-' || n, now() - interval '20 years' FROM generate_series(1, 2) n
+    "#
+    )
+        .execute(&db.pool).await
+        .unwrap();
+    let env = [
+        ("QUERY_LOG", "true"),
+        // Longer than chrono or Postgres can reach back
+        ("TRENDING_WINDOWS", "1h,10000000000h"),
+        ("TRENDING_REFRESH_SECS", "1s"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    let recent = counted(&server, "1h").await;
+    let queries = recent["queries"].as_array().unwrap();
+    assert_eq!(queries.len(), 1);
+    assert_eq!(queries[0]["query"], "rust");
+
+    let all_time = counted(&server, "10000000000h").await;
+    let queries: Vec<&Value> = all_time["queries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|query| &query["query"])
+        .collect();
+    assert_eq!(queries, ["rust", "jaguar"]);
+
+    // Pruning up to the longest window keeps every row, rather than failing
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let rows: i64 = sqlx::query_scalar("SELECT count(*) FROM query_log")
+        .fetch_one(&db.pool).await
+        .unwrap();
+    assert_eq!(rows, 5);
+    assert!(!server.log().contains("Error"), "{}", server.log());
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use axum::{ routing::post, Form, Json, Router };
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+/// Stands in for Turnstile's siteverify endpoint, accepting tokens that start with `good`, and
+/// recording the tokens it was asked about.
+async fn spawn_turnstile() -> (String, Arc<Mutex<Vec<String>>>) {
+    let verified = Arc::new(Mutex::new(Vec::new()));
+    let recorded = verified.clone();
+    let router = Router::new().route(
+        "/",
+        post(move |Form(form): Form<HashMap<String, String>>| {
+            let recorded = recorded.clone();
+            async move {
+                let token = form.get("response").cloned().unwrap_or_default();
+                let success = token.starts_with("good");
+                recorded.lock().unwrap().push(token);
+                Json(json!({ "success": success }))
+            }
+        })
+    );
+    (common::spawn_mock(router).await, verified)
+}
+
+/// The access log rows for requests to `path_prefix`, once there are `count` of them.
+async fn access_log(server: &TestServer, path_prefix: &str, count: usize) -> Vec<Value> {
+    let started = Instant::now();
+    loop {
+        let log = server.log();
+        let rows: Vec<Value> = log
+            .lines()
+            .filter_map(|line| line.strip_prefix("Access: "))
+            .map(|row| serde_json::from_str(row).unwrap())
+            .filter(|row: &Value| row["uri"].as_str().unwrap().starts_with(path_prefix))
+            .collect();
+        if rows.len() >= count {
+            return rows;
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "Requests weren't logged:\n{}", log);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[tokio::test]
+async fn accepts_the_token_from_headers_or_the_query_and_keeps_it_out_of_the_log() {
+    let Some(db) = TestDb::create("turnstile_carriers", &[INDEX]).await else {
+        return;
+    };
+    let (verify_url, verified) = spawn_turnstile().await;
+    let env = [
+        ("TURNSTILE_ENABLED", "true"),
+        ("TURNSTILE_VERIFY_URL", verify_url.as_str()),
+        ("ACCESS_LOG", "true"),
+        ("LOG_FLUSH_MS", "100"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+    let search = |query: &str| server.client.get(server.url(&format!("/?q={}", query)));
+
+    // Header only, in either header
+    let request = search("rust").header("X-Turnstile-Token", "good-header");
+    let (status, _, body) = common::json_response(request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"].as_array().unwrap().len(), 5);
+    let request = search("tokio").header("Authorization", "Turnstile good-authorization");
+    let (status, _, _) = common::json_response(request).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Query parameter only
+    let (status, _, _) = common::json_response(search("jaguar&token=good-param")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Both, where the header wins over a token Turnstile would reject
+    let request = search("python&token=bad-param").header("X-Turnstile-Token", "good-both");
+    let (status, _, _) = common::json_response(request).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Neither
+    let (status, _, body) = common::json_response(search("wikipedia")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "Missing Turnstile token");
+
+    assert_eq!(
+        *verified.lock().unwrap(),
+        ["good-header", "good-authorization", "good-param", "good-both"]
+    );
+
+    // Every request is logged, but none of the tokens are
+    let rows = access_log(&server, "/?q=", 5).await;
+    let log = server.log();
+    for token in ["good-header", "good-authorization", "good-param", "bad-param", "good-both"] {
+        assert!(!log.contains(token), "{} was logged:\n{}", token, log);
+    }
+    let row = |query: &str| {
+        rows.iter()
+            .find(|row| row["uri"].as_str().unwrap().starts_with(&format!("/?q={}", query)))
+            .unwrap_or_else(|| panic!("No request for {} was logged: {:?}", query, rows))
+    };
+    assert_eq!(row("rust")["headers"]["x-turnstile-token"], "[redacted]");
+    assert_eq!(row("rust")["status"], 200);
+    assert_eq!(row("tokio")["headers"]["authorization"], "Turnstile [redacted]");
+    assert_eq!(row("jaguar")["uri"], "/?q=jaguar&token=[redacted]");
+    assert_eq!(row("python")["uri"], "/?q=python&token=[redacted]");
+    assert_eq!(row("python")["headers"]["x-turnstile-token"], "[redacted]");
+    assert_eq!(row("wikipedia")["status"], 401);
+}
+
+#[tokio::test]
+async fn query_tokens_can_be_turned_off() {
+    let Some(db) = TestDb::create("turnstile_query_off", &[INDEX]).await else {
+        return;
+    };
+    let (verify_url, verified) = spawn_turnstile().await;
+    let env = [
+        ("TURNSTILE_ENABLED", "true"),
+        ("TURNSTILE_VERIFY_URL", verify_url.as_str()),
+        ("TURNSTILE_ALLOW_QUERY_TOKEN", "false"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    let (status, _, body) = server.get_json("/?q=rust&token=good-param").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "Missing Turnstile token");
+    assert!(verified.lock().unwrap().is_empty());
+
+    let request = server.client.get(server.url("/?q=rust")).header("X-Turnstile-Token", "good-1");
+    let (status, _, _) = common::json_response(request).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn a_validated_token_follows_its_client_within_the_identity_prefix() {
+    let Some(db) = TestDb::create("turnstile_prefix", &[INDEX]).await else {
+        return;
+    };
+    let (verify_url, verified) = spawn_turnstile().await;
+    let env = [
+        ("TURNSTILE_ENABLED", "true"),
+        ("TURNSTILE_VERIFY_URL", verify_url.as_str()),
+        ("IPV4_IDENTITY_PREFIX", "24"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+    // A search with the same token from a loopback address of its own
+    let search_from = |address: &str| {
+        let client = reqwest::Client::builder()
+            .local_address(address.parse::<std::net::IpAddr>().unwrap())
+            .build()
+            .unwrap();
+        client.get(server.url("/?q=rust")).header("X-Turnstile-Token", "good-moving")
+    };
+
+    for address in ["127.0.0.1", "127.0.0.2", "127.0.0.200"] {
+        let (status, _, _) = common::json_response(search_from(address)).await;
+        assert_eq!(status, StatusCode::OK, "{}", address);
+    }
+    // Only the first was verified: the others were the same /24, so the cached token held
+    assert_eq!(verified.lock().unwrap().len(), 1);
+
+    // Another /24 is a different client, so Turnstile is asked again
+    let (status, _, _) = common::json_response(search_from("127.0.1.1")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(verified.lock().unwrap().len(), 2);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::{ result_urls, TestDb, TestServer };
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::time::{ Duration, Instant };
+
+const TWO_TIER: &str = "tests/fixtures/two_tier.sql";
+
+/// The full ranking of `ferris`, which the quick pre-ranking disagrees with
+const FULL_RANKING: [&str; 3] = [
+    "https://ferris.example.com/",
+    "https://notes.example.com/crabs",
+    "https://misc.example.com/",
+];
+
+/// Follows up a two-tier search until its continuation has the complete ranking.
+async fn refined(server: &TestServer, continuation: &str) -> Value {
+    let started = Instant::now();
+    loop {
+        let path = format!("/?q=ferris&two_tier=true&debug=true&continuation={}", continuation);
+        let (status, _, body) = server.get_json(&path).await;
+        assert_eq!(status, StatusCode::OK);
+        if body["complete"] == true {
+            return body;
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "Never refined:\n{}", server.log());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+async fn refines_the_quick_first_page_in_the_background() {
+    let Some(db) = TestDb::create("two_tier_refined", &[TWO_TIER]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("TWO_TIER_CANDIDATES", "1")]).await;
+
+    // Tier one scores only the densest page, so misses the best one
+    let (status, _, quick) = server.get_json("/?q=ferris&two_tier=true&debug=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result_urls(&quick), ["https://notes.example.com/crabs"]);
+    assert_eq!(quick["complete"], false);
+    assert_eq!(quick["applied_options"]["variant"], "two_tier");
+    assert_eq!(quick["debug"]["pipeline"]["refinement"], "scheduled");
+    assert_eq!(quick["debug"]["pipeline"]["candidates_fetched"], 1);
+    let continuation = quick["continuation"].as_str().unwrap();
+
+    // Tier two is the full ranking, which moves tier one's top result down
+    let full = refined(&server, continuation).await;
+    assert_eq!(result_urls(&full), FULL_RANKING);
+    assert_eq!(full["continuation"], Value::Null);
+    assert_eq!(full["debug"]["pipeline"]["cache"], "continuation");
+
+    // The same as an ordinary search
+    let (_, _, ordinary) = server.get_json("/?q=ferris").await;
+    assert_eq!(result_urls(&ordinary), FULL_RANKING);
+    assert_eq!(ordinary["complete"], true);
+    assert_eq!(ordinary["continuation"], Value::Null);
+}
+
+#[tokio::test]
+async fn skips_refining_when_tier_one_saw_every_candidate() {
+    let Some(db) = TestDb::create("two_tier_complete", &[TWO_TIER]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("TWO_TIER_CANDIDATES", "10")]).await;
+
+    let (_, _, body) = server.get_json("/?q=ferris&two_tier=true&debug=true").await;
+    assert_eq!(result_urls(&body), FULL_RANKING);
+    assert_eq!(body["complete"], true);
+    assert_eq!(body["continuation"], Value::Null);
+    assert_eq!(body["debug"]["pipeline"]["refinement"], "not_needed");
+    assert_eq!(body["debug"]["pipeline"]["candidates_complete"], true);
+}
+
+#[tokio::test]
+async fn bounds_the_background_refinements() {
+    let Some(db) = TestDb::create("two_tier_saturated", &[TWO_TIER]).await else {
+        return;
+    };
+    let env = [
+        ("TWO_TIER_CANDIDATES", "1"),
+        ("TWO_TIER_MAX_BACKGROUND", "0"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    // With no permits to spare the quick page is all there is
+    let (_, _, body) = server.get_json("/?q=ferris&two_tier=true&debug=true").await;
+    assert_eq!(result_urls(&body), ["https://notes.example.com/crabs"]);
+    assert_eq!(body["complete"], false);
+    assert_eq!(body["continuation"], Value::Null);
+    assert_eq!(body["debug"]["pipeline"]["refinement"], "saturated");
+
+    // A continuation that was never issued is just another search
+    let (_, _, body) = server.get_json("/?q=ferris&continuation=0123456789abcdef").await;
+    assert_eq!(result_urls(&body), FULL_RANKING);
+    assert_eq!(body["complete"], true);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::{ result_urls, TestDb, TestServer };
+use reqwest::StatusCode;
+use serde_json::Value;
+
+const VERBATIM: &str = "tests/fixtures/verbatim.sql";
+
+/// Each result's URL and `verbatim` flag, sorted by URL
+async fn verbatim_flags(server: &TestServer, query: &str) -> Vec<(String, Value)> {
+    let (status, _, body) = server.get_json(&format!("/?q={}", query)).await;
+    assert_eq!(status, StatusCode::OK);
+    let mut flags: Vec<(String, Value)> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| (result["url"].as_str().unwrap().to_string(), result["verbatim"].clone()))
+        .collect();
+    flags.sort_by(|a, b| a.0.cmp(&b.0));
+    flags
+}
+
+fn flags(expected: &[(&str, bool)]) -> Vec<(String, Value)> {
+    expected
+        .iter()
+        .map(|(url, verbatim)| (url.to_string(), Value::Bool(*verbatim)))
+        .collect()
+}
+
+#[tokio::test]
+async fn checks_word_positions_and_falls_back_to_titles() {
+    let Some(db) = TestDb::create("verbatim_positions", &[VERBATIM]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    assert_eq!(
+        verbatim_flags(&server, "async%20rust").await,
+        flags(
+            &[
+                ("https://positions.example.com/in-order", true),
+                ("https://positions.example.com/reversed", false),
+                ("https://titles.example.com/apart", false),
+                ("https://titles.example.com/in-order", true),
+            ]
+        )
+    );
+    // In the other order only the reversed page's text has it
+    assert_eq!(
+        verbatim_flags(&server, "rust%20async").await,
+        flags(
+            &[
+                ("https://positions.example.com/in-order", false),
+                ("https://positions.example.com/reversed", true),
+                ("https://titles.example.com/apart", false),
+                ("https://titles.example.com/in-order", false),
+            ]
+        )
+    );
+    // A repeated word has to be repeated in the page
+    let repeated = verbatim_flags(&server, "async%20async%20rust").await;
+    assert!(repeated.iter().all(|(_, verbatim)| verbatim == false), "{:?}", repeated);
+    // A single word is verbatim wherever it's found
+    let single = verbatim_flags(&server, "rust").await;
+    assert_eq!(single.len(), 4);
+    assert!(single.iter().all(|(_, verbatim)| verbatim == true), "{:?}", single);
+
+    let (_, _, body) = server.get_json("/?q=async%20rust&verbatim_only=true").await;
+    let mut urls = result_urls(&body);
+    urls.sort();
+    assert_eq!(
+        urls,
+        ["https://positions.example.com/in-order", "https://titles.example.com/in-order"]
+    );
+}
+
+#[tokio::test]
+async fn only_checks_titles_and_descriptions_without_positions() {
+    let Some(db) = TestDb::create("verbatim_no_positions", &[VERBATIM]).await else {
+        return;
+    };
+    sqlx::query("DROP TABLE website_keyword_positions").execute(&db.pool).await.unwrap();
+    let server = TestServer::start(&db, &[]).await;
+
+    assert_eq!(
+        verbatim_flags(&server, "async%20rust").await,
+        flags(
+            &[
+                ("https://positions.example.com/in-order", false),
+                ("https://positions.example.com/reversed", false),
+                ("https://titles.example.com/apart", false),
+                ("https://titles.example.com/in-order", true),
+            ]
+        )
+    );
+    // The title check is folded and case-insensitive
+    let (_, _, body) = server.get_json("/?q=ASYNC%20R%C3%BCst&verbatim_only=true").await;
+    assert_eq!(result_urls(&body), ["https://titles.example.com/in-order"]);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn describes_the_running_build() {
+    let Some(db) = TestDb::create("version_endpoint", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    let (status, headers, body) = server.get_json("/version").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["cache-control"], "public, max-age=3600");
+    let mut keys: Vec<&String> = body.as_object().unwrap().keys().collect();
+    keys.sort();
+    assert_eq!(keys, ["built_at", "features", "git_dirty", "git_hash", "service", "version"]);
+    assert_eq!(body["service"], env!("CARGO_PKG_NAME"));
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert!(body["git_dirty"].is_boolean());
+    assert!(body["features"].is_array());
+    let built_at = body["built_at"].as_str().unwrap();
+    assert!(chrono::DateTime::parse_from_rfc3339(built_at).is_ok(), "{}", built_at);
+    // Only absent from builds outside a git checkout
+    let git_hash = body["git_hash"].as_str();
+    assert!(
+        git_hash.is_none_or(|hash| hash.len() >= 4 && hash.chars().all(|c| c.is_ascii_hexdigit())),
+        "{:?}",
+        git_hash
+    );
+
+    // It's cacheable
+    let response = server.client
+        .get(server.url("/version"))
+        .header("If-None-Match", headers["etag"].to_str().unwrap())
+        .send().await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // The banner printed first names the same build
+    let version = match (git_hash, body["git_dirty"] == true) {
+        (None, _) => env!("CARGO_PKG_VERSION").to_string(),
+        (Some(hash), false) => format!("{}+{}", env!("CARGO_PKG_VERSION"), hash),
+        (Some(hash), true) => format!("{}+{}.dirty", env!("CARGO_PKG_VERSION"), hash),
+    };
+    let log = server.log();
+    let banner = log.lines().next().unwrap();
+    let named = format!("{} {} (built {}, features: ", env!("CARGO_PKG_NAME"), version, built_at);
+    assert!(banner.starts_with(&named), "{}", banner);
+
+    // Search responses and `/stats` carry the build too
+    let (_, headers, _) = server.get_json("/?q=rust").await;
+    assert_eq!(headers["x-search-version"], version.as_str());
+    let request = server.client
+        .post(server.url("/blend"))
+        .json(&json!({ "queries": [{ "query": "rust", "weight": 1 }] }));
+    let (_, headers, _) = common::json_response(request).await;
+    assert_eq!(headers["x-search-version"], version.as_str());
+    let (_, headers, stats) = server.get_json("/stats").await;
+    assert_eq!(stats["git_hash"], body["git_hash"]);
+    assert!(!headers.contains_key("x-search-version"));
+}
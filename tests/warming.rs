@@ -0,0 +1,82 @@
+mod common;
+
+use common::{ TestDb, TestServer, INDEX };
+use serde_json::Value;
+use std::time::{ Duration, Instant };
+
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The cached rankings of `query`, whatever options they were searched with
+async fn cached(server: &TestServer, query: &str) -> Vec<Value> {
+    let (_, _, body) = server.get_admin(&format!("/admin/cache/results?query={}", query)).await;
+    let body: Value = serde_json::from_str(&body).unwrap();
+    body["entries"].as_array().cloned().unwrap_or_default()
+}
+
+#[tokio::test]
+async fn rewarms_popular_queries_when_the_index_changes() {
+    let Some(db) = TestDb::create("warming_refresh", &[INDEX]).await else {
+        return;
+    };
+    let env = [
+        ("INDEX_REFRESH_SECS", "1s"),
+        // Only the index changing can warm anything during the test
+        ("WARM_INTERVAL_SECS", "1h"),
+        ("WARM_CONCURRENCY", "1"),
+    ];
+    let server = TestServer::start(&db, &env).await;
+
+    for query in ["rust", "jaguar", "rust"] {
+        let (status, _, _) = server.get_json(&format!("/?q={}", query)).await;
+        assert!(status.is_success());
+    }
+    let before = cached(&server, "rust").await;
+    assert_eq!(before.len(), 1);
+    assert_eq!(before[0]["results"], 5);
+    assert_eq!(before[0]["index_generation"], 0);
+
+    // The crawler adds a page about rust
+    sqlx::query(
+        r#"
+        WITH page AS (
+            INSERT INTO websites (title, description, url, word_count, last_crawled, language)
+            VALUES ('Rust by Example', 'Learn Rust with examples',
+                'https://doc.rust-lang.org/rust-by-example/', 800, now(), 'en')
+            RETURNING id
+        )
+        INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences)
+        SELECT 1, id, 30 FROM page
+    "#
+    )
+        .execute(&db.pool).await
+        .unwrap();
+    sqlx::query("UPDATE keywords SET documents_containing_word = 6 WHERE word = 'rust'")
+        .execute(&db.pool).await
+        .unwrap();
+
+    // The refresh drops the stale rankings, and warming searches both queries again
+    let started = Instant::now();
+    loop {
+        let rust = cached(&server, "rust").await;
+        let jaguar = cached(&server, "jaguar").await;
+        if rust.first().is_some_and(|entry| entry["results"] == 6) && jaguar.len() == 1 {
+            break;
+        }
+        assert!(
+            started.elapsed() < REFRESH_TIMEOUT,
+            "The cache wasn't rewarmed: {:?} {:?}\n{}",
+            rust,
+            jaguar,
+            server.log()
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert!(server.log().contains("Warmed 2 popular queries (0 skipped as too slow)."));
+    assert_eq!(cached(&server, "rust").await[0]["index_generation"], 1);
+
+    // Searches are answered from the rewarmed cache
+    let (status, _, body) = server.get_json("/?q=rust&debug=true").await;
+    assert!(status.is_success());
+    assert_eq!(body["results"].as_array().unwrap().len(), 6);
+    assert_eq!(body["debug"]["pipeline"]["cache"], "result");
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{ json_response, TestDb, TestServer, INDEX };
+use reqwest::StatusCode;
+use serde_json::{ json, Value };
+
+/// The `(code, field)` of each warning a search returned
+async fn warnings(server: &TestServer, query: &str) -> Vec<(String, Value)> {
+    let (status, _, body) = server.get_json(&format!("/?q={}", query)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body.get("warnings")
+        .map(|warnings| {
+            warnings
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|warning| {
+                    assert!(warning["message"].as_str().is_some_and(|message| !message.is_empty()));
+                    (warning["code"].as_str().unwrap().to_string(), warning["field"].clone())
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn expected(warnings: &[(&str, Option<&str>)]) -> Vec<(String, Value)> {
+    warnings
+        .iter()
+        .map(|(code, field)| (code.to_string(), json!(field)))
+        .collect()
+}
+
+#[tokio::test]
+async fn pins_the_codes_of_parameter_warnings() {
+    let Some(db) = TestDb::create("warnings_parameters", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[("MAX_RESULTS", "3")]).await;
+
+    // Nothing to warn about leaves the field out, even with the default over the maximum
+    let (_, _, body) = server.get_json("/?q=rust").await;
+    assert_eq!(body.get("warnings"), None);
+    assert_eq!(body["results"].as_array().unwrap().len(), 3);
+
+    assert_eq!(
+        warnings(&server, "rust&results=10").await,
+        expected(&[("results_clamped", Some("results"))])
+    );
+    assert_eq!(
+        warnings(&server, "rust&results=many&stopwords=maybe").await,
+        expected(&[
+            ("invalid_parameter", Some("stopwords")),
+            ("invalid_parameter", Some("results")),
+        ])
+    );
+    assert_eq!(
+        warnings(&server, "rust&colour=blue").await,
+        expected(&[("unknown_parameter", Some("colour"))])
+    );
+    assert_eq!(warnings(&server, "-rust").await, expected(&[("no_search_terms", Some("q"))]));
+}
+
+#[tokio::test]
+async fn warns_when_links_cant_be_fetched() {
+    let Some(db) = TestDb::create("warnings_links", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    sqlx::query("DROP TABLE website_links CASCADE").execute(&db.pool).await.unwrap();
+
+    let (status, _, body) = server.get_json("/?q=jaguar&links=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!body["results"].as_array().unwrap().is_empty());
+    let codes: Vec<&Value> = body["warnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|warning| &warning["code"])
+        .collect();
+    assert!(codes.contains(&&json!("links_unavailable")), "{}", body["warnings"]);
+}
+
+#[tokio::test]
+async fn warns_about_an_empty_index() {
+    let Some(db) = TestDb::create("warnings_empty", &[]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+
+    assert_eq!(warnings(&server, "rust").await, expected(&[("empty_index", None)]));
+}
+
+#[tokio::test]
+async fn warns_when_a_blends_pages_cant_be_fetched() {
+    let Some(db) = TestDb::create("warnings_blend", &[INDEX]).await else {
+        return;
+    };
+    let server = TestServer::start(&db, &[]).await;
+    sqlx::query("DROP TABLE website_keywords CASCADE").execute(&db.pool).await.unwrap();
+
+    let queries = json!([{ "query": "rust", "weight": 1 }, { "query": "jaguar", "weight": 1 }]);
+    let request = server.client.post(server.url("/blend")).json(&json!({ "queries": queries }));
+    let (status, _, body) = json_response(request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["results"].as_array().unwrap().is_empty(), "{}", body);
+    let warnings: Vec<(&Value, &Value)> = body["warnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|warning| (&warning["code"], &warning["field"]))
+        .collect();
+    assert_eq!(warnings, [(&json!("candidates_unavailable"), &Value::Null)]);
+}